@@ -0,0 +1,47 @@
+#ifndef RRADIO_CLIENT_H
+#define RRADIO_CLIENT_H
+
+#include <stdint.h>
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+/* A connection to rradio */
+typedef struct RRadioClient RRadioClient;
+
+/* Connect to rradio at the given host and port, e.g. rradio_client_connect("localhost", 8002).
+ * Returns NULL on failure, in which case the reason is given by rradio_client_last_error.
+ * The returned client must be freed with rradio_client_disconnect. */
+RRadioClient *rradio_client_connect(const char *host, uint16_t port);
+
+/* Wait up to timeout_ms milliseconds for the next event.
+ * Returns 1 and sets *event_json to the event encoded as JSON if an event is received, which must be freed with rradio_client_free_string.
+ * Returns 0 if no event is received before the timeout.
+ * Returns -1 if the connection has failed or closed, in which case the reason is given by rradio_client_last_error. */
+int rradio_client_poll_event(RRadioClient *client, uint32_t timeout_ms, char **event_json);
+
+/* Send a command encoded as JSON, e.g. "\"PlayPause\"" or "{\"SetVolume\":70}".
+ * Returns 0 on success, or -1 on failure, in which case the reason is given by rradio_client_last_error. */
+int rradio_client_send_command(RRadioClient *client, const char *command_json);
+
+/* How many milliseconds rradio suggests waiting before reconnecting if the connection is lost.
+ * Clients should wait for a random duration of up to this long before reconnecting, and double the delay after each failed attempt.
+ * Returns 0 if client is NULL. */
+uint32_t rradio_client_backoff_hint_ms(const RRadioClient *client);
+
+/* The reason for the most recent failure on this thread, or NULL if there hasn't been a failure.
+ * The returned string is owned by the library, and is valid until the next failure on this thread. */
+const char *rradio_client_last_error(void);
+
+/* Free a string returned by rradio_client_poll_event */
+void rradio_client_free_string(char *s);
+
+/* Close the connection and free the client */
+void rradio_client_disconnect(RRadioClient *client);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif /* RRADIO_CLIENT_H */
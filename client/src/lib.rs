@@ -0,0 +1,353 @@
+//! A client for the rradio binary port with a C ABI, for display projects written in other languages.
+//!
+//! Commands and events are passed as JSON strings, using the same representation as the web port.
+//! See `rradio_client.h` for the C declarations.
+
+#![warn(clippy::pedantic)]
+
+use std::{
+    cell::RefCell,
+    convert::TryFrom,
+    ffi::{CStr, CString},
+    os::raw::{c_char, c_int},
+    pin::Pin,
+    time::Duration,
+};
+
+use futures_util::{Sink, SinkExt, Stream, StreamExt};
+use rradio_messages::{Command, CommandStreamEncodeError, Event, EventStreamDecodeError};
+
+type Events = Pin<Box<dyn Stream<Item = Result<Event, EventStreamDecodeError>> + Send>>;
+type Commands = Pin<Box<dyn Sink<Command, Error = CommandStreamEncodeError> + Send>>;
+
+/// A connection to rradio
+pub struct RRadioClient {
+    runtime: tokio::runtime::Runtime,
+    /// How long rradio suggests waiting before reconnecting if the connection is lost
+    backoff_hint: Duration,
+    events: Events,
+    commands: Commands,
+}
+
+thread_local! {
+    static LAST_ERROR: RefCell<Option<CString>> = const { RefCell::new(None) };
+}
+
+fn set_last_error(error: impl std::fmt::Display) {
+    let message = CString::new(error.to_string().replace('\0', "\\0"))
+        .expect("Error message contains no nul bytes");
+
+    LAST_ERROR.with(|last_error| *last_error.borrow_mut() = Some(message));
+}
+
+/// Read a UTF-8 string from a C string, recording an error if it's not valid
+///
+/// # Safety
+///
+/// `s` must be null or a valid nul terminated string
+unsafe fn str_from_c<'a>(s: *const c_char, name: &str) -> Option<&'a str> {
+    if s.is_null() {
+        set_last_error(format_args!("{name} is null"));
+        return None;
+    }
+
+    match CStr::from_ptr(s).to_str() {
+        Ok(s) => Some(s),
+        Err(err) => {
+            set_last_error(format_args!("{name} is not valid UTF-8: {err}"));
+            None
+        }
+    }
+}
+
+impl RRadioClient {
+    fn connect(host: &str, port: u16) -> Result<Self, String> {
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .map_err(|err| format!("Failed to create runtime: {err}"))?;
+
+        let (backoff_hint, events, commands) = runtime.block_on(async {
+            let (connection_rx, connection_tx) = tokio::net::TcpStream::connect((host, port))
+                .await
+                .map_err(|err| format!("Failed to connect to {host}:{port}: {err}"))?
+                .into_split();
+
+            let (backoff_hint, events) = Event::decode_from_stream_with_backoff_hint(
+                tokio::io::BufReader::new(connection_rx),
+            )
+            .await
+            .map_err(|err| err.to_string())?;
+
+            Ok::<_, String>((
+                backoff_hint,
+                Box::pin(events) as Events,
+                Box::pin(Command::encode_to_stream(connection_tx)) as Commands,
+            ))
+        })?;
+
+        Ok(Self {
+            runtime,
+            backoff_hint,
+            events,
+            commands,
+        })
+    }
+
+    fn poll_event(&mut self, timeout: Duration) -> Result<Option<String>, String> {
+        let events = &mut self.events;
+
+        match self
+            .runtime
+            .block_on(async { tokio::time::timeout(timeout, events.next()).await })
+        {
+            Ok(Some(Ok(event))) => serde_json::to_string(&event)
+                .map(Some)
+                .map_err(|err| format!("Failed to encode event as JSON: {err}")),
+            Ok(Some(Err(err))) => Err(err.to_string()),
+            Ok(None) => Err(String::from("Disconnected")),
+            Err(_) => Ok(None),
+        }
+    }
+
+    fn send_command(&mut self, command: &str) -> Result<(), String> {
+        let command = serde_json::from_str::<Command>(command)
+            .map_err(|err| format!("Failed to parse command: {err}"))?;
+
+        let commands = &mut self.commands;
+
+        self.runtime
+            .block_on(commands.send(command))
+            .map_err(|err| err.to_string())
+    }
+}
+
+/// Connect to rradio at the given host and port, e.g. `rradio_client_connect("localhost", 8002)`.
+///
+/// Returns null on failure, in which case the reason is given by `rradio_client_last_error`.
+/// The returned client must be freed with `rradio_client_disconnect`.
+///
+/// # Safety
+///
+/// `host` must be a valid nul terminated string
+#[no_mangle]
+pub unsafe extern "C" fn rradio_client_connect(
+    host: *const c_char,
+    port: u16,
+) -> *mut RRadioClient {
+    let Some(host) = str_from_c(host, "host") else {
+        return std::ptr::null_mut();
+    };
+
+    match RRadioClient::connect(host, port) {
+        Ok(client) => Box::into_raw(Box::new(client)),
+        Err(err) => {
+            set_last_error(err);
+            std::ptr::null_mut()
+        }
+    }
+}
+
+/// Wait up to `timeout_ms` milliseconds for the next event.
+///
+/// Returns `1` and sets `*event_json` to the event encoded as JSON if an event is received, which must be freed with `rradio_client_free_string`.
+/// Returns `0` if no event is received before the timeout.
+/// Returns `-1` if the connection has failed or closed, in which case the reason is given by `rradio_client_last_error`.
+///
+/// # Safety
+///
+/// `client` must have been returned by `rradio_client_connect` and not yet disconnected, and `event_json` must be a valid pointer
+#[no_mangle]
+pub unsafe extern "C" fn rradio_client_poll_event(
+    client: *mut RRadioClient,
+    timeout_ms: u32,
+    event_json: *mut *mut c_char,
+) -> c_int {
+    let Some(client) = client.as_mut() else {
+        set_last_error("client is null");
+        return -1;
+    };
+
+    if event_json.is_null() {
+        set_last_error("event_json is null");
+        return -1;
+    }
+
+    match client.poll_event(Duration::from_millis(timeout_ms.into())) {
+        Ok(Some(event)) => match CString::new(event) {
+            Ok(event) => {
+                *event_json = event.into_raw();
+                1
+            }
+            Err(err) => {
+                set_last_error(err);
+                -1
+            }
+        },
+        Ok(None) => 0,
+        Err(err) => {
+            set_last_error(err);
+            -1
+        }
+    }
+}
+
+/// Send a command encoded as JSON, e.g. `"PlayPause"` or `{"SetVolume":70}`.
+///
+/// Returns `0` on success, or `-1` on failure, in which case the reason is given by `rradio_client_last_error`.
+///
+/// # Safety
+///
+/// `client` must have been returned by `rradio_client_connect` and not yet disconnected, and `command_json` must be a valid nul terminated string
+#[no_mangle]
+pub unsafe extern "C" fn rradio_client_send_command(
+    client: *mut RRadioClient,
+    command_json: *const c_char,
+) -> c_int {
+    let Some(client) = client.as_mut() else {
+        set_last_error("client is null");
+        return -1;
+    };
+
+    let Some(command) = str_from_c(command_json, "command_json") else {
+        return -1;
+    };
+
+    match client.send_command(command) {
+        Ok(()) => 0,
+        Err(err) => {
+            set_last_error(err);
+            -1
+        }
+    }
+}
+
+/// How many milliseconds rradio suggests waiting before reconnecting if the connection is lost.
+///
+/// Clients should wait for a random duration of up to this long before reconnecting, and double the delay after each failed attempt.
+/// Returns `0` if `client` is null.
+///
+/// # Safety
+///
+/// `client` must be null or have been returned by `rradio_client_connect` and not yet disconnected
+#[no_mangle]
+pub unsafe extern "C" fn rradio_client_backoff_hint_ms(client: *const RRadioClient) -> u32 {
+    client.as_ref().map_or(0, |client| {
+        u32::try_from(client.backoff_hint.as_millis()).unwrap_or(u32::MAX)
+    })
+}
+
+/// The reason for the most recent failure on this thread, or null if there hasn't been a failure.
+///
+/// The returned string is owned by the library, and is valid until the next failure on this thread.
+#[no_mangle]
+pub extern "C" fn rradio_client_last_error() -> *const c_char {
+    LAST_ERROR.with(|last_error| {
+        last_error
+            .borrow()
+            .as_ref()
+            .map_or(std::ptr::null(), |last_error| last_error.as_ptr())
+    })
+}
+
+/// Free a string returned by `rradio_client_poll_event`.
+///
+/// # Safety
+///
+/// `s` must be null or have been returned by `rradio_client_poll_event`, and not already freed
+#[no_mangle]
+pub unsafe extern "C" fn rradio_client_free_string(s: *mut c_char) {
+    if !s.is_null() {
+        drop(CString::from_raw(s));
+    }
+}
+
+/// Close the connection and free the client.
+///
+/// # Safety
+///
+/// `client` must be null or have been returned by `rradio_client_connect`, and not already disconnected
+#[no_mangle]
+pub unsafe extern "C" fn rradio_client_disconnect(client: *mut RRadioClient) {
+    if !client.is_null() {
+        drop(Box::from_raw(client));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        ffi::{CStr, CString},
+        io::{BufRead, Write},
+    };
+
+    #[test]
+    fn receive_event_and_send_command() {
+        let listener = std::net::TcpListener::bind(("127.0.0.1", 0)).unwrap();
+        let port = listener.local_addr().unwrap().port();
+
+        let server = std::thread::spawn(move || {
+            let (mut connection, _) = listener.accept().unwrap();
+
+            connection
+                .write_all(rradio_messages::API_VERSION_HEADER.as_bytes())
+                .unwrap();
+
+            connection
+                .write_all(
+                    rradio_messages::encode_backoff_hint(std::time::Duration::from_millis(1500))
+                        .as_bytes(),
+                )
+                .unwrap();
+
+            let mut buffer = Vec::new();
+            let event = serde_json::from_str::<rradio_messages::Event>(
+                r#"{"PlayerStateChanged":{"volume":42}}"#,
+            )
+            .unwrap();
+            connection
+                .write_all(event.encode(&mut buffer).unwrap())
+                .unwrap();
+
+            let mut command = Vec::new();
+            std::io::BufReader::new(connection)
+                .read_until(0, &mut command)
+                .unwrap();
+
+            rradio_messages::Command::decode(&mut command).unwrap()
+        });
+
+        let host = CString::new("127.0.0.1").unwrap();
+
+        unsafe {
+            let client = super::rradio_client_connect(host.as_ptr(), port);
+            assert!(!client.is_null());
+
+            assert_eq!(super::rradio_client_backoff_hint_ms(client), 1500);
+
+            let mut event_json = std::ptr::null_mut();
+            assert_eq!(
+                super::rradio_client_poll_event(client, 5000, std::ptr::addr_of_mut!(event_json)),
+                1
+            );
+            assert!(CStr::from_ptr(event_json)
+                .to_str()
+                .unwrap()
+                .contains(r#""volume":42"#));
+            super::rradio_client_free_string(event_json);
+
+            let command = CString::new(r#"{"SetVolume":70}"#).unwrap();
+            assert_eq!(
+                super::rradio_client_send_command(client, command.as_ptr()),
+                0
+            );
+
+            super::rradio_client_disconnect(client);
+        }
+
+        assert!(matches!(
+            server.join().unwrap(),
+            rradio_messages::Command::SetVolume(70)
+        ));
+    }
+}
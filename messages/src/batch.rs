@@ -0,0 +1,211 @@
+//! Decoding the commands of a [`Command::Batch`](crate::Command::Batch) without allowing nested batches.
+//! Otherwise decoding recurses once per level of nesting, so a client could overflow the stack with a small, deeply nested frame
+
+use alloc::{string::String, vec::Vec};
+use core::{convert::TryFrom, fmt};
+
+use serde::de::{
+    self, value::StringDeserializer, value::U64Deserializer, DeserializeSeed, Deserializer,
+    EnumAccess, IntoDeserializer, Visitor,
+};
+
+use crate::Command;
+
+use serde::Deserialize;
+
+const BATCH_VARIANT: &str = "Batch";
+
+/// Deserialize the commands of a batch, rejecting any command which is itself a batch before its commands are decoded
+pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<Command>, D::Error> {
+    struct UnbatchedCommand(Command);
+
+    impl<'de> Deserialize<'de> for UnbatchedCommand {
+        fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+            Command::deserialize(RejectBatch(deserializer)).map(Self)
+        }
+    }
+
+    Ok(Vec::<UnbatchedCommand>::deserialize(deserializer)?
+        .into_iter()
+        .map(|UnbatchedCommand(command)| command)
+        .collect())
+}
+
+/// The name or index of an enum variant, as chosen by the format
+enum VariantIdentifier {
+    Index(u64),
+    Name(String),
+}
+
+impl VariantIdentifier {
+    fn is_batch(&self, variants: &[&str]) -> bool {
+        match self {
+            Self::Index(index) => usize::try_from(*index)
+                .ok()
+                .and_then(|index| variants.get(index))
+                .is_some_and(|&variant| variant == BATCH_VARIANT),
+            Self::Name(name) => name == BATCH_VARIANT,
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for VariantIdentifier {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct IdentifierVisitor;
+
+        impl Visitor<'_> for IdentifierVisitor {
+            type Value = VariantIdentifier;
+
+            fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                f.write_str("a variant identifier")
+            }
+
+            fn visit_u64<E: de::Error>(self, index: u64) -> Result<Self::Value, E> {
+                Ok(VariantIdentifier::Index(index))
+            }
+
+            fn visit_str<E: de::Error>(self, name: &str) -> Result<Self::Value, E> {
+                Ok(VariantIdentifier::Name(name.into()))
+            }
+
+            fn visit_bytes<E: de::Error>(self, name: &[u8]) -> Result<Self::Value, E> {
+                Ok(VariantIdentifier::Name(
+                    String::from_utf8_lossy(name).into(),
+                ))
+            }
+        }
+
+        deserializer.deserialize_identifier(IdentifierVisitor)
+    }
+}
+
+/// Wraps the deserializer of a command, so that the variant is checked before the rest of the command is decoded
+struct RejectBatch<D>(D);
+
+/// Forward to the wrapped deserializer
+macro_rules! forward {
+    ($($method:ident($($arg:ident: $ty:ty),*)),* $(,)?) => {
+        $(
+            fn $method<V: Visitor<'de>>(self, $($arg: $ty,)* visitor: V) -> Result<V::Value, D::Error> {
+                self.0.$method($($arg,)* visitor)
+            }
+        )*
+    };
+}
+
+impl<'de, D: Deserializer<'de>> Deserializer<'de> for RejectBatch<D> {
+    type Error = D::Error;
+
+    forward! {
+        deserialize_any(), deserialize_bool(), deserialize_i8(), deserialize_i16(), deserialize_i32(), deserialize_i64(),
+        deserialize_u8(), deserialize_u16(), deserialize_u32(), deserialize_u64(), deserialize_f32(), deserialize_f64(),
+        deserialize_char(), deserialize_str(), deserialize_string(), deserialize_bytes(), deserialize_byte_buf(),
+        deserialize_option(), deserialize_unit(), deserialize_unit_struct(name: &'static str),
+        deserialize_newtype_struct(name: &'static str), deserialize_seq(), deserialize_tuple(len: usize),
+        deserialize_tuple_struct(name: &'static str, len: usize), deserialize_map(),
+        deserialize_struct(name: &'static str, fields: &'static [&'static str]),
+        deserialize_identifier(), deserialize_ignored_any(),
+    }
+
+    fn deserialize_enum<V: Visitor<'de>>(
+        self,
+        name: &'static str,
+        variants: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, D::Error> {
+        self.0
+            .deserialize_enum(name, variants, RejectBatch((visitor, variants)))
+    }
+
+    fn is_human_readable(&self) -> bool {
+        self.0.is_human_readable()
+    }
+}
+
+impl<'de, V: Visitor<'de>> Visitor<'de> for RejectBatch<(V, &'static [&'static str])> {
+    type Value = V::Value;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.0 .0.expecting(f)
+    }
+
+    fn visit_enum<A: EnumAccess<'de>>(self, data: A) -> Result<Self::Value, A::Error> {
+        let (visitor, variants) = self.0;
+        visitor.visit_enum(RejectBatch((data, variants)))
+    }
+}
+
+impl<'de, A: EnumAccess<'de>> EnumAccess<'de> for RejectBatch<(A, &'static [&'static str])> {
+    type Error = A::Error;
+    type Variant = A::Variant;
+
+    fn variant_seed<S: DeserializeSeed<'de>>(
+        self,
+        seed: S,
+    ) -> Result<(S::Value, Self::Variant), Self::Error> {
+        let (data, variants) = self.0;
+
+        let (identifier, variant) = data.variant::<VariantIdentifier>()?;
+
+        if identifier.is_batch(variants) {
+            return Err(de::Error::custom("Batches can't be nested"));
+        }
+
+        let value = match identifier {
+            VariantIdentifier::Index(index) => {
+                seed.deserialize::<U64Deserializer<A::Error>>(index.into_deserializer())?
+            }
+            VariantIdentifier::Name(name) => {
+                seed.deserialize::<StringDeserializer<A::Error>>(name.into_deserializer())?
+            }
+        };
+
+        Ok((value, variant))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::{vec, vec::Vec};
+
+    use crate::Command;
+
+    fn nested_batch(depth: usize) -> Command {
+        (0..depth).fold(Command::PlayPause, |command, _| {
+            Command::Batch(vec![command])
+        })
+    }
+
+    #[test]
+    fn batches_are_decoded() {
+        let mut buffer = Vec::new();
+        let mut frame = nested_batch(1).encode(&mut buffer).unwrap().to_vec();
+
+        let Command::Batch(commands) = Command::decode(&mut frame).unwrap() else {
+            panic!("Not a batch");
+        };
+
+        assert!(matches!(commands.as_slice(), [Command::PlayPause]));
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn nested_batches_are_rejected() {
+        for depth in [2, 100_000] {
+            // Encoding and dropping recurse too, so the frame is encoded on a thread with a large stack
+            let mut frame = std::thread::Builder::new()
+                .stack_size(256 * 1024 * 1024)
+                .spawn(move || {
+                    nested_batch(depth)
+                        .encode(&mut Vec::new())
+                        .unwrap()
+                        .to_vec()
+                })
+                .unwrap()
+                .join()
+                .unwrap();
+
+            assert!(Command::decode(&mut frame).is_err());
+        }
+    }
+}
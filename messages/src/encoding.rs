@@ -1,3 +1,5 @@
+use alloc::vec::Vec;
+
 struct PostcardFlavour<'a> {
     buffer: &'a mut Vec<u8>,
 }
@@ -20,7 +22,7 @@ impl<'a> postcard::ser_flavors::Flavor for PostcardFlavour<'a> {
     }
 }
 
-impl<'a> std::ops::Index<usize> for PostcardFlavour<'a> {
+impl<'a> core::ops::Index<usize> for PostcardFlavour<'a> {
     type Output = u8;
 
     fn index(&self, index: usize) -> &Self::Output {
@@ -28,7 +30,7 @@ impl<'a> std::ops::Index<usize> for PostcardFlavour<'a> {
     }
 }
 
-impl<'a> std::ops::IndexMut<usize> for PostcardFlavour<'a> {
+impl<'a> core::ops::IndexMut<usize> for PostcardFlavour<'a> {
     fn index_mut(&mut self, index: usize) -> &mut Self::Output {
         &mut self.buffer[index]
     }
@@ -50,6 +52,7 @@ pub fn decode_value<'de, T: serde::Deserialize<'de>>(buffer: &'de mut [u8]) -> p
     postcard::from_bytes_cobs(buffer)
 }
 
+/// Decode frames of at most `maximum_frame_length` bytes, including the terminating zero, so that a peer can't use up all memory by never ending a frame
 #[cfg(feature = "async")]
 pub fn decode_from_stream<
     S: tokio::io::AsyncBufRead + Unpin,
@@ -57,19 +60,31 @@ pub fn decode_from_stream<
     E: From<std::io::Error> + From<postcard::Error>,
 >(
     stream: S,
+    maximum_frame_length: u64,
 ) -> impl futures_util::Stream<Item = Result<T, E>> {
-    use tokio::io::AsyncBufReadExt;
+    use tokio::io::{AsyncBufReadExt, AsyncReadExt};
 
     futures_util::stream::try_unfold(
         (stream, Vec::new()),
-        |(mut stream, mut buffer)| async move {
+        move |(mut stream, mut buffer)| async move {
             buffer.clear();
-            let read_size = stream.read_until(0, &mut buffer).await?;
+            let read_size = (&mut stream)
+                .take(maximum_frame_length)
+                .read_until(0, &mut buffer)
+                .await?;
 
             if read_size == 0 {
                 return Ok(None);
             }
 
+            if buffer.last() != Some(&0) && read_size as u64 == maximum_frame_length {
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::InvalidData,
+                    alloc::format!("Frame is longer than {maximum_frame_length} bytes"),
+                )
+                .into());
+            }
+
             let message = decode_value(&mut buffer)?;
 
             Ok(Some((message, (stream, buffer))))
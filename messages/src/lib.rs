@@ -1,13 +1,23 @@
 #![warn(clippy::pedantic)]
+#![cfg_attr(not(feature = "std"), no_std)]
 
-use std::{fmt, sync::Arc, time::Duration};
+extern crate alloc;
+
+use alloc::{boxed::Box, string::String, sync::Arc, vec::Vec};
+use core::{fmt, time::Duration};
 
 use serde::{Deserialize, Serialize};
 
 pub use arcstr;
 pub use arcstr::ArcStr;
 
+mod batch;
 mod encoding;
+mod message_catalogue;
+mod track_list_patch;
+
+pub use message_catalogue::{Message, MessageId};
+pub use track_list_patch::{TrackListOperation, TrackListPatch, TrackListPatchError};
 
 pub const VERSION: &str = env!("CARGO_PKG_VERSION");
 /// When connecting over TCP, `RRadio` will begin by immediately sending the following header
@@ -17,21 +27,34 @@ pub const API_VERSION_HEADER: &str =
 
 pub const API_VERSION_HEADER_LENGTH: usize = API_VERSION_HEADER.as_bytes().len();
 
+/// Immediately after the version header, `RRadio` sends a hint of how long TCP clients should wait before reconnecting if the connection is lost, as a decimal number of milliseconds followed by `'\n'`.
+/// Clients SHOULD wait for a random duration of up to this long before reconnecting, and double the delay after each failed attempt, so that reconnections after a restart are spread out
+#[must_use]
+pub fn encode_backoff_hint(backoff: Duration) -> String {
+    alloc::format!("{}\n", backoff.as_millis())
+}
+
 /// The port to connect to for sending commands and receiving events
 pub const API_PORT: u16 = 8002;
 
+/// The longest encoded [`Command`] which is decoded from a stream, including the terminating zero
+pub const MAXIMUM_COMMAND_FRAME_LENGTH: u64 = 1024 * 1024;
+
+/// The longest encoded [`Event`] which is decoded from a stream, including the terminating zero. Events can contain images, so can be much larger than commands
+pub const MAXIMUM_EVENT_FRAME_LENGTH: u64 = 64 * 1024 * 1024;
+
 pub const VOLUME_ZERO_DB: i32 = 100;
 pub const VOLUME_MIN: i32 = 0;
 pub const VOLUME_MAX: i32 = 120;
 
-#[derive(Debug, Deserialize, Serialize)]
+#[derive(Clone, Debug, Deserialize, Serialize)]
 pub struct SetPlaylistTrack {
     pub title: String,
     pub url: String,
 }
 
 /// Commands from the user
-#[derive(Debug, Deserialize, Serialize)]
+#[derive(Clone, Debug, Deserialize, Serialize)]
 pub enum Command {
     SetChannel(StationIndex),
     PlayPause,
@@ -53,15 +76,248 @@ pub enum Command {
     },
     Eject,
     DebugPipeline,
+    /// Reload the persisted settings and publish them
+    GetSettings,
+    /// Change and persist a single setting
+    SetSetting(Setting),
+    /// Clear the latest error, e.g. when the user acknowledges it
+    ClearLatestError,
+    /// Choose how events are sent over this connection. Clients should send this immediately after receiving the version header.
+    /// Only supported by the binary port. Clients of the text port choose the text format using text commands
+    SetEventFormat(EventFormat),
+    /// Preview seeking to the given position without changing the current position, e.g. while the user drags a seek bar.
+    /// The result is sent as an [`Event::SeekPreview`] to the client which sent the command.
+    /// If several previews are requested in quick succession, only the most recent is made
+    SeekPreview(Duration),
+    /// Export the tracks of the current station as a playlist document, which is sent as an [`Event::PlaylistDocument`]
+    ExportCurrentPlaylist {
+        format: PlaylistFormat,
+    },
+    /// Play a music file, or the music files in a directory, on the configured USB device or network share, without creating a station file.
+    /// `path` is relative to the music directory of `source`. If `recursive` is true, the music files in subdirectories are also played
+    PlayPath {
+        #[serde(default)]
+        source: PathSource,
+        path: String,
+        recursive: bool,
+    },
+    /// Fade out and stop playing after the given duration, or cancel the sleep timer if `None`
+    SleepTimer(Option<Duration>),
+    /// List the containers and items in a container on a `UPnP` media server, so that clients can browse the server and play items with [`Command::SetPlaylist`].
+    /// `container_id` is `None` for the root container. The result is sent as an [`Event::UPnPBrowseResult`]
+    BrowseUPnP {
+        root_description_url: String,
+        container_id: Option<String>,
+    },
+    /// Request the most recent entries of the audit log, which records each received command. The entries are sent as an [`Event::AuditLog`]
+    RequestAuditLog {
+        count: usize,
+    },
+    /// Stop playing, save state, and power off the device. Ignored unless `token` matches the configured token
+    SystemPowerOff {
+        token: SystemCommandToken,
+    },
+    /// Stop playing, save state, and reboot the device. Ignored unless `token` matches the configured token
+    SystemReboot {
+        token: SystemCommandToken,
+    },
+    /// Check the hardware and configuration, e.g. on the production line or after hardware changes. A short tone is played. The result is sent as an [`Event::SelfTestResult`]
+    RunSelfTest,
+    /// Request the play counts of the most recently played tracks of USB and `UPnP` stations. The statistics are sent as an [`Event::PlayStatistics`]
+    RequestPlayStatistics {
+        count: usize,
+    },
+    /// Check the configured release manifest for a newer version of rradio. The result is sent as an [`Event::UpdateProgress`].
+    /// Ignored unless `token` matches the configured token
+    CheckForUpdate {
+        token: SystemCommandToken,
+    },
+    /// Download, verify and install a newer version of rradio, if there is one, and restart. Progress is sent as [`Event::UpdateProgress`] events.
+    /// Ignored unless `token` matches the configured token
+    ApplyUpdate {
+        token: SystemCommandToken,
+    },
+    /// Add tracks to the current station without interrupting the current track, or play them as a new playlist if nothing is playing.
+    /// Unlike [`Command::SetPlaylist`], which replaces the current station immediately
+    EnqueueTracks {
+        tracks: Vec<SetPlaylistTrack>,
+        position: EnqueuePosition,
+    },
+    /// Select one of the configured audio outputs, such as the speakers or the headphones, by name
+    SetAudioOutput(ArcStr),
+    /// Run several commands in order, without commands from other clients running between them, e.g. to set the station, volume and position after reconnecting.
+    /// If a command fails, the remaining commands aren't run. The result is sent as an [`Event::BatchResult`]. Batches can't be nested
+    Batch(#[serde(deserialize_with = "batch::deserialize")] Vec<Command>),
+    /// Start or stop logging every gstreamer bus message, to diagnose odd stream behaviour without restarting rradio. Initially set by the `bus_trace` config option
+    SetBusTrace(bool),
+    /// Search the configured sources, such as `UPnP` media servers and radio-browser.info, concurrently.
+    /// The results of each source are sent as an [`Event::SearchResults`] to the client which sent the command, as soon as that source has been searched
+    Search {
+        query: String,
+    },
+    /// Remove the track at the given index from the current station, e.g. to edit a queue built with [`Command::EnqueueTracks`].
+    /// The current track is playing, so can't be removed
+    RemoveTrack(usize),
+    /// Move the track at index `from` of the current station to index `to`, e.g. to play a queued track next. The current track keeps playing
+    MoveTrack {
+        from: usize,
+        to: usize,
+    },
+    /// Play the first `seconds` of each track of the current station in turn, starting with the current track, to help find a track in a large library.
+    /// Scanning continues until [`Command::StopIntroScan`], or until [`Command::PlayPause`] chooses the current track, which then keeps playing
+    StartIntroScan {
+        seconds: u32,
+    },
+    /// Stop scanning started by [`Command::StartIntroScan`]. The current track keeps playing
+    StopIntroScan,
+    /// Add an alarm, replacing the alarm at the same time, if there is one. Alarms are saved in the settings, so persist between restarts
+    SetAlarm(Alarm),
+    /// Remove the alarm at the given time
+    RemoveAlarm {
+        hour: u8,
+        minute: u8,
+    },
+    /// Forget the loudness learned for a station by loudness normalisation, or for all stations if `station` is `None`
+    ResetStationLoudness {
+        station: Option<StationIndex>,
+    },
+    /// Lock or unlock the current station. While locked, [`Command::SetChannel`], [`Command::SetPlaylist`] and [`Command::Eject`] are rejected unless they come from a privileged client.
+    /// Only privileged clients can lock or unlock the station. The station stays locked until it's unlocked or rradio restarts
+    LockStation(bool),
+    /// As [`Command::SetEventFormat`], with the options of [`EventFormatV2`]
+    SetEventFormatV2(EventFormatV2),
+    /// Fetch a podcast feed, and play its episodes as with [`Command::SetPlaylist`].
+    /// Podcast search results are played with this command, so that the episodes of every podcast found aren't sent with the results
+    PlayPodcastFeed {
+        title: String,
+        feed_url: String,
+    },
+}
+
+/// Where [`Command::EnqueueTracks`] adds tracks
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize, Serialize)]
+pub enum EnqueuePosition {
+    /// After the current track
+    Next,
+    /// After the last track, but before the playlist suffix notification, if there is one
+    End,
+}
+
+/// A shared secret which authorises [`Command::SystemPowerOff`], [`Command::SystemReboot`], [`Command::CheckForUpdate`] and [`Command::ApplyUpdate`].
+/// It's hidden when debug formatted, so that it isn't logged
+#[derive(Clone, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(transparent)]
+pub struct SystemCommandToken(pub String);
+
+impl fmt::Debug for SystemCommandToken {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("SystemCommandToken(..)")
+    }
+}
+
+/// Where the path of a [`Command::PlayPath`] is found
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize, Serialize)]
+pub enum PathSource {
+    /// The configured USB device
+    #[default]
+    Usb,
+    /// The configured network share
+    Share,
+}
+
+/// The format of a playlist document
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize, Serialize)]
+pub enum PlaylistFormat {
+    /// [Extended M3U](https://en.wikipedia.org/wiki/M3U), encoded as UTF-8
+    M3u8,
+    /// [PLS](https://en.wikipedia.org/wiki/PLS_(file_format))
+    Pls,
+}
+
+/// How events are encoded by the binary port
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize, Serialize)]
+pub enum EventEncoding {
+    /// [COBS](https://en.wikipedia.org/wiki/Consistent_Overhead_Byte_Stuffing) encoded postcard, suffixed with a value of `0`
+    #[default]
+    Postcard,
+    /// JSON, suffixed with a newline
+    Json,
+}
+
+/// How the text port displays durations
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize, Serialize)]
+pub enum DurationFormat {
+    /// Minutes and seconds, such as `3:07`, or hours, minutes and seconds, such as `1:03:07`
+    #[default]
+    Clock,
+    /// Seconds, such as `187.5s`
+    Seconds,
+}
+
+/// How the text port displays times of day
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize, Serialize)]
+pub enum ClockFormat {
+    /// Such as `18:05:09`
+    #[default]
+    TwentyFourHour,
+    /// Such as `6:05:09 PM`
+    TwelveHour,
+}
+
+/// How the text port displays values
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize, Serialize)]
+pub struct TextFormat {
+    pub duration_format: DurationFormat,
+    pub clock_format: ClockFormat,
+}
+
+/// The largest track image which a connection can use. Larger images are downscaled if rradio supports it, and otherwise aren't sent
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize, Serialize)]
+pub struct ImageLimits {
+    /// The maximum width and height, in pixels
+    pub maximum_dimension: Option<u32>,
+    /// The maximum size of the encoded image, in bytes
+    pub maximum_bytes: Option<u32>,
+}
+
+/// How a connection to the binary port receives events
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize, Serialize)]
+pub struct EventFormat {
+    pub encoding: EventEncoding,
+    /// If true, each event contains the entire player state, rather than only the fields which have changed
+    pub full_state: bool,
+    /// Only used by the text port
+    pub text_format: TextFormat,
+    /// If set, track images are downscaled or removed to fit within the limits, e.g. for displays which can't show large images
+    #[serde(default)]
+    pub image_limits: Option<ImageLimits>,
+}
+
+/// [`EventFormat`] with the options added since, set with [`Command::SetEventFormatV2`].
+/// Postcard isn't self-describing, so a field added to `EventFormat` would break clients which encode the old `EventFormat`, and new options are added in a new version instead
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize, Serialize)]
+pub struct EventFormatV2 {
+    pub format: EventFormat,
+    /// If true, each event is sent as a [`SequencedEvent`], so that clients can order and age events even if the wall clock of the server is corrected
+    pub sequenced: bool,
+}
+
+impl From<EventFormat> for EventFormatV2 {
+    fn from(format: EventFormat) -> Self {
+        Self {
+            format,
+            sequenced: false,
+        }
+    }
 }
 
 #[derive(Debug, thiserror::Error)]
 #[error("Failed to encode Command: {0}")]
-pub struct CommandEncodeError(#[source] postcard::Error);
+pub struct CommandEncodeError(#[cfg_attr(feature = "std", source)] postcard::Error);
 
 #[derive(Debug, thiserror::Error)]
 #[error("Failed to decode Command: {0}")]
-pub struct CommandDecodeError(#[source] postcard::Error);
+pub struct CommandDecodeError(#[cfg_attr(feature = "std", source)] postcard::Error);
 
 impl Command {
     /// Clear the buffer and encode the `Command` into it
@@ -122,7 +378,7 @@ impl Command {
     pub fn decode_from_stream<S: tokio::io::AsyncBufRead + Unpin>(
         stream: S,
     ) -> impl futures_util::Stream<Item = Result<Self, CommandStreamDecodeError>> {
-        encoding::decode_from_stream(stream)
+        encoding::decode_from_stream(stream, MAXIMUM_COMMAND_FRAME_LENGTH)
     }
 
     pub fn encode_to_stream<S: tokio::io::AsyncWrite + Unpin>(
@@ -157,13 +413,53 @@ impl fmt::Display for PipelineState {
     }
 }
 
+/// Preferences which persist between restarts of rradio
+#[derive(Clone, Debug, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(default)]
+pub struct Settings {
+    /// The volume to use on startup. If `None`, the configured initial volume is used
+    pub volume: Option<i32>,
+    pub is_muted: bool,
+    /// The alarms set with [`Command::SetAlarm`]
+    pub alarms: Vec<Alarm>,
+}
+
+/// Playing a station every day at a time of day, fading in the volume
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+pub struct Alarm {
+    /// From 0 to 23, in the timezone configured by `timezone`
+    pub hour: u8,
+    /// From 0 to 59
+    pub minute: u8,
+    pub station: StationIndex,
+    /// If set, the sleep timer is set when the alarm goes off, so that playback fades out and stops unless the sleep timer is cancelled
+    #[serde(default)]
+    pub stop_after: Option<Duration>,
+}
+
+/// A single setting, as changed by [`Command::SetSetting`]
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub enum Setting {
+    Volume(i32),
+    IsMuted(bool),
+}
+
 #[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
 pub struct Track {
     pub title: Option<ArcStr>,
     pub album: Option<ArcStr>,
     pub artist: Option<ArcStr>,
+    pub track_number: Option<u32>,
+    pub disc_number: Option<u32>,
     pub url: ArcStr,
     pub is_notification: bool,
+    /// Where the track starts in its file, for tracks which share a file, such as the tracks of an album rip which is split by a cue sheet.
+    /// Such a track ends where the next track in the same file starts
+    #[serde(default)]
+    pub start_offset: Option<Duration>,
+    /// Other urls of the same audio, such as the relays of a broadcaster, which are tried in order if `url` fails to play
+    #[serde(default)]
+    pub mirrors: Vec<ArcStr>,
 }
 
 impl Track {
@@ -173,8 +469,12 @@ impl Track {
             title: None,
             album: None,
             artist: None,
+            track_number: None,
+            disc_number: None,
             url,
             is_notification: false,
+            start_offset: None,
+            mirrors: Vec::new(),
         }
     }
 
@@ -184,8 +484,12 @@ impl Track {
             title: None,
             album: None,
             artist: None,
+            track_number: None,
+            disc_number: None,
             url,
             is_notification: true,
+            start_offset: None,
+            mirrors: Vec::new(),
         }
     }
 }
@@ -196,8 +500,12 @@ impl From<SetPlaylistTrack> for Track {
             title: Some(title.into()),
             album: None,
             artist: None,
+            track_number: None,
+            disc_number: None,
             url: url.into(),
             is_notification: false,
+            start_offset: None,
+            mirrors: Vec::new(),
         }
     }
 }
@@ -223,7 +531,7 @@ impl AsRef<str> for StationIndex {
     }
 }
 
-impl std::borrow::Borrow<str> for StationIndex {
+impl core::borrow::Borrow<str> for StationIndex {
     fn borrow(&self) -> &str {
         &self.0
     }
@@ -269,6 +577,10 @@ pub enum CdError {
 pub enum MountError {
     #[error("USB support is not enabled")]
     UsbNotEnabled,
+    #[error("Network share support is not enabled")]
+    ShareNotEnabled,
+    #[error("No network share is configured")]
+    ShareNotConfigured,
     #[error("Not found")]
     NotFound,
     #[error("Failed to create temporary directory: {0}")]
@@ -279,6 +591,23 @@ pub enum MountError {
     ErrorFindingTracks(ArcStr),
     #[error("Tracks not found")]
     TracksNotFound,
+    #[error("Invalid path: {0}")]
+    InvalidPath(ArcStr),
+}
+
+/// The reason why the first track of a station could not be reached
+#[derive(Clone, Debug, Deserialize, Serialize, thiserror::Error)]
+pub enum ReachabilityError {
+    #[error("Bad url: {0}")]
+    BadUrl(ArcStr),
+    #[error("DNS lookup failed: {0}")]
+    Dns(ArcStr),
+    #[error("No addresses found")]
+    NoAddresses,
+    #[error("Failed to connect: {0}")]
+    Connect(ArcStr),
+    #[error("Timeout")]
+    Timeout,
 }
 
 #[derive(Clone, Debug, Deserialize, Serialize, thiserror::Error)]
@@ -298,6 +627,35 @@ pub enum StationError {
     },
     #[error("Bad Station File: {0}")]
     BadStationFile(ArcStr),
+    #[error("{url} is unreachable: {err}")]
+    Unreachable {
+        url: ArcStr,
+        err: ReachabilityError,
+    },
+    #[error("{alias:?} matches more than one station: {}", StationIndices(.indices))]
+    AmbiguousStationAlias {
+        alias: ArcStr,
+        indices: Vec<StationIndex>,
+    },
+    #[error("SDR Error: {0}")]
+    SdrError(ArcStr),
+}
+
+/// Displays station indices as a comma separated list
+struct StationIndices<'a>(&'a [StationIndex]);
+
+impl fmt::Display for StationIndices<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (position, index) in self.0.iter().enumerate() {
+            if position > 0 {
+                f.write_str(", ")?;
+            }
+
+            index.fmt(f)?;
+        }
+
+        Ok(())
+    }
 }
 
 #[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize, Serialize)]
@@ -306,6 +664,10 @@ pub enum StationType {
     UPnP,
     CD,
     Usb,
+    /// FM or DAB, received with an RTL-SDR dongle
+    Sdr,
+    /// A network share, such as a NAS
+    Share,
 }
 
 impl fmt::Display for StationType {
@@ -315,6 +677,8 @@ impl fmt::Display for StationType {
             Self::UPnP => "UPnP",
             Self::CD => "CD",
             Self::Usb => "USB",
+            Self::Sdr => "SDR",
+            Self::Share => "Network Share",
         })
     }
 }
@@ -326,6 +690,8 @@ pub enum CurrentStation {
     NoStation,
     FailedToPlayStation {
         error: StationError,
+        /// `error` as a [`Message`], so that clients can show it in the user's language
+        message: Message,
     },
     PlayingStation {
         index: Option<StationIndex>,
@@ -346,7 +712,7 @@ impl Image {
     pub fn new(mime_type: &str, image_data: &[u8]) -> Self {
         use base64::Engine;
         Self(
-            format!(
+            alloc::format!(
                 "data:{};base64,{}",
                 mime_type,
                 base64::engine::general_purpose::URL_SAFE.encode(image_data)
@@ -368,7 +734,7 @@ impl AsRef<str> for Image {
     }
 }
 
-impl std::ops::Deref for Image {
+impl core::ops::Deref for Image {
     type Target = str;
 
     fn deref(&self) -> &Self::Target {
@@ -377,6 +743,7 @@ impl std::ops::Deref for Image {
 }
 
 impl fmt::Debug for Image {
+    #[cfg(feature = "std")]
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         use std::hash::{Hash, Hasher};
         let mut hasher = std::collections::hash_map::DefaultHasher::new();
@@ -384,6 +751,11 @@ impl fmt::Debug for Image {
 
         write!(f, "Image {{ hash: {:016X} }}", hasher.finish())
     }
+
+    #[cfg(not(feature = "std"))]
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Image {{ length: {} }}", self.0.len())
+    }
 }
 
 impl fmt::Display for Image {
@@ -398,6 +770,8 @@ pub struct TrackTags {
     pub organisation: Option<ArcStr>,
     pub artist: Option<ArcStr>,
     pub album: Option<ArcStr>,
+    pub track_number: Option<u32>,
+    pub disc_number: Option<u32>,
     pub genre: Option<ArcStr>,
     pub image: Option<Image>,
     pub comment: Option<ArcStr>,
@@ -420,6 +794,9 @@ pub enum PingError {
     /// Ping response reported as "Destination Unreachable"
     #[error("Destination Unreachable")]
     DestinationUnreachable,
+    /// A TCP connect or HTTP HEAD probe failed to connect, or an HTTP HEAD probe received no response
+    #[error("Failed to connect")]
+    FailedToConnect,
 }
 
 #[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize, Serialize)]
@@ -449,14 +826,91 @@ impl Default for PingTimes {
     }
 }
 
+/// What kind of error occurred, so that clients can react to errors, e.g. by showing "stream offline" rather than "no internet"
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize, Serialize)]
+pub enum ErrorKind {
+    /// A server couldn't be reached, or the connection failed
+    Network,
+    /// A host name couldn't be resolved
+    Dns,
+    /// A stream or file couldn't be decoded, e.g. because it's corrupt or there isn't a suitable decoder
+    Codec,
+    /// A device, such as the audio output or the CD drive, couldn't be used
+    Device,
+    /// Access was denied, e.g. to a file or by a server
+    Permission,
+    /// A file, stream, or station doesn't exist
+    NotFound,
+    Other,
+}
+
 #[derive(Clone, Debug, Deserialize, Serialize)]
 pub struct LatestError {
+    /// The wall clock time, for showing to users
     pub timestamp: chrono::DateTime<chrono::Utc>,
+    /// A description of the error in English, for clients which don't have a translation of `message`
     pub error: ArcStr,
+    pub kind: ErrorKind,
+    pub message: Message,
+    /// How long after rradio started the error occurred, by a monotonic clock which doesn't jump when the wall clock is corrected, e.g. by NTP
+    pub since_start: Duration,
+}
+
+/// The selected audio output, and the outputs which can be selected using [`Command::SetAudioOutput`]
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+pub struct AudioOutputs {
+    pub selected: ArcStr,
+    pub available: Vec<ArcStr>,
+    /// Whether headphones are plugged in, or `None` if headphone detection isn't configured
+    pub headphones_connected: Option<bool>,
+}
+
+/// The format of the current track, so that clients can show e.g. a "lossless" badge
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+pub struct AudioFormat {
+    /// The codec, as described by gstreamer, e.g. "Free Lossless Audio Codec (FLAC)", or `None` if the stream doesn't say
+    pub codec: Option<ArcStr>,
+    /// Whether the codec is lossless, such as FLAC, ALAC, or uncompressed PCM as found in WAV files and on CDs
+    pub is_lossless: bool,
+    /// The bit depth of the decoded audio, e.g. 16 or 24. For lossy codecs, this is chosen by the decoder rather than by the source
+    pub bit_depth: u32,
+    /// The sample rate of the decoded audio, in Hz
+    pub sample_rate: u32,
+    /// Whether the decoded audio reaches the audio sink without being converted to a different sample format or resampled
+    pub is_passthrough: bool,
+}
+
+/// How rradio was quietened when another application, such as a voice call, asked the audio server to quieten it
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize, Serialize)]
+pub enum AudioInterruption {
+    Paused,
+    Ducked,
+}
+
+/// What display clients show while rradio is idle, i.e. when nothing has played for a while
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+pub enum IdleScreen {
+    /// The time, shown using the client's clock
+    Clock,
+    /// The IP addresses of the device, so that users can find e.g. the web interface
+    IpAddresses(Vec<ArcStr>),
+    /// Text provided by the user, such as the weather written to a file by a script
+    Text(ArcStr),
+}
+
+/// Whether the player is playing, paused, or has been idle for a while, so that displays can e.g. dim their backlight without timing the pipeline state themselves
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize, Serialize)]
+pub enum Activity {
+    Playing,
+    Paused,
+    /// Nothing has been playing or paused since `since`, which only changes when the player becomes idle, so that an idle player doesn't send an event every second
+    Idle {
+        since: chrono::DateTime<chrono::Utc>,
+    },
 }
 
 /// `PlayerStateDiff` records what fields have changed since the last diff was sent. If a field is `Some(_)`, then it has changed
-#[derive(Debug, Deserialize, Serialize)]
+#[derive(Clone, Debug, Deserialize, Serialize)]
 pub struct PlayerStateDiff {
     pub pipeline_state: Option<PipelineState>,
     pub current_station: Option<CurrentStation>,
@@ -470,21 +924,280 @@ pub struct PlayerStateDiff {
     pub track_position: Option<Option<Duration>>,
     pub ping_times: Option<PingTimes>,
     pub latest_error: Option<Option<LatestError>>,
+    pub settings: Option<Settings>,
+    /// The urls of tracks which have failed too many times, and are skipped
+    pub quarantined_tracks: Option<Vec<ArcStr>>,
+    /// The revision of the tracks of the current station, which changes whenever the current station changes
+    pub tracks_revision: Option<u64>,
+    /// If only some of the tracks of the current station have changed, `current_station` is `None`, and the changes are sent as a patch
+    pub track_list_patch: Option<TrackListPatch>,
+    /// Why rradio isn't using its config file after failing to start, or `None` if it is
+    pub safe_mode: Option<Option<ArcStr>>,
+    /// What display clients should show while rradio is idle, or `None` if rradio is active
+    pub idle_screen: Option<Option<IdleScreen>>,
+    /// The maximum volume while quiet hours are active, or `None` if they aren't
+    pub quiet_hours_maximum_volume: Option<Option<i32>>,
+    /// `None` if there are no configured audio outputs
+    pub audio_outputs: Option<Option<AudioOutputs>>,
+    pub activity: Option<Activity>,
+    /// Set while another application has asked the audio server to quieten rradio, so that clients can show why the radio went quiet
+    pub audio_interruption: Option<Option<AudioInterruption>>,
+    pub audio_format: Option<Option<AudioFormat>>,
+    /// How much of each track is played while scanning, as started by [`Command::StartIntroScan`], or `None` if not scanning
+    pub intro_scan: Option<Option<Duration>>,
+    /// Set by [`Command::LockStation`]
+    pub station_locked: Option<bool>,
+}
+
+/// The result of a [`Command::SeekPreview`]
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+pub struct SeekPreview {
+    pub requested_position: Duration,
+    /// The position of the keyframe nearest to the requested position, which is where seeking would actually go to
+    pub keyframe_position: Duration,
+    /// For local files, the peak amplitude of the audio around the requested position, from 0 (silent) to 255 (full scale)
+    pub peaks: Option<Vec<u8>>,
+}
+
+/// The result of a [`Command::ExportCurrentPlaylist`]
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+pub struct PlaylistDocument {
+    pub format: PlaylistFormat,
+    /// The text of the playlist. Local files are relative to the mounted device, so the playlist can be saved onto the device
+    pub document: String,
+}
+
+/// Progress of loading the tracks of a station, for stations which take a long time to load, such as large `UPnP` containers,
+/// or whose server is asleep and is being woken up
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+pub struct StationLoadProgress {
+    pub index: Option<StationIndex>,
+    /// The host of the server which is being woken up with Wake-on-LAN, before the tracks are loaded
+    #[serde(default)]
+    pub waking_server: Option<ArcStr>,
+    /// The number of folders or containers which have been scanned so far
+    pub containers_scanned: usize,
+    /// The number of tracks which have been found so far
+    pub tracks_found: usize,
+    /// The time since the station started loading
+    pub elapsed: Duration,
+}
+
+/// A container within a `UPnP` container, which can be browsed using its id
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+pub struct UPnPContainerReference {
+    pub id: ArcStr,
+    pub title: ArcStr,
+}
+
+/// The contents of a `UPnP` container
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+pub struct UPnPContainer {
+    pub containers: Vec<UPnPContainerReference>,
+    pub items: Vec<Track>,
+}
+
+/// The result of a [`Command::BrowseUPnP`]
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+pub struct UPnPBrowseResult {
+    pub root_description_url: ArcStr,
+    pub container_id: ArcStr,
+    /// The contents of the container, or a description of why it couldn't be browsed
+    pub result: Result<UPnPContainer, ArcStr>,
+}
+
+/// Identifies a device, so that clients which are connected to several devices can label them
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+pub struct DeviceIdentity {
+    /// Generated when the device first starts, and unchanged after that
+    pub id: ArcStr,
+    /// The configured device name, or the hostname if no name is configured
+    pub name: ArcStr,
+}
+
+/// A command which was received, and whether it succeeded
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+pub struct AuditLogEntry {
+    pub timestamp: chrono::DateTime<chrono::Utc>,
+    /// Where the command came from, e.g. the port and the address of the client
+    pub source: ArcStr,
+    /// The command, formatted for debugging
+    pub command: ArcStr,
+    /// Why the command failed, or `None` if it succeeded
+    pub error: Option<ArcStr>,
+}
+
+/// How often and how recently a track of a USB or `UPnP` station has been played
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+pub struct TrackPlayStatistics {
+    /// The url of the track, or for tracks on a USB device, which is mounted in a different directory each time, `usb:` followed by the path of the track on the device
+    pub track: ArcStr,
+    pub play_count: u64,
+    pub last_played: chrono::DateTime<chrono::Utc>,
+}
+
+/// Progress of checking for or applying an update
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+pub enum UpdateProgress {
+    UpToDate {
+        current_version: ArcStr,
+    },
+    Available {
+        current_version: ArcStr,
+        version: ArcStr,
+    },
+    Downloading {
+        downloaded: u64,
+        /// `None` if the server didn't report the size of the release
+        total: Option<u64>,
+    },
+    Verifying,
+    /// The new version has been installed, and rradio is restarting
+    Installed {
+        version: ArcStr,
+    },
+    Failed(ArcStr),
+}
+
+/// A stage of startup, so that users of devices without screens can tell how far startup got if something is wrong
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize, Serialize)]
+pub enum BootMilestone {
+    /// The config file has been parsed. Not reached if the default config is used because the config file couldn't be loaded
+    ConfigLoaded,
+    /// There is a route to the internet
+    NetworkUp,
+    /// All ports are accepting connections
+    PortsListening,
+    /// Every station in the stations directory has loaded successfully
+    StationsValidated,
+}
+
+/// The outcome of one self-test check
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+pub enum SelfTestOutcome {
+    Passed,
+    Failed(ArcStr),
+    /// The check doesn't apply, e.g. because the feature isn't enabled
+    Skipped(ArcStr),
+}
+
+impl SelfTestOutcome {
+    #[must_use]
+    pub fn is_failed(&self) -> bool {
+        matches!(self, Self::Failed(_))
+    }
+}
+
+/// The result of a [`Command::Batch`]
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+pub struct BatchResult {
+    /// The number of commands which succeeded
+    pub completed: usize,
+    /// The number of commands in the batch
+    pub total: usize,
+    /// Why the command after the completed commands failed, or `None` if every command succeeded
+    pub error: Option<ArcStr>,
+    /// `error` as a [`Message`], so that clients can show it in the user's language
+    pub error_message: Option<Message>,
+}
+
+/// The station files which were added, changed, or removed when the stations directory was synced
+#[derive(Clone, Debug, Default, PartialEq, Eq, Deserialize, Serialize)]
+pub struct StationSyncChanges {
+    pub added: Vec<ArcStr>,
+    pub changed: Vec<ArcStr>,
+    pub removed: Vec<ArcStr>,
+}
+
+/// A station, album, track or podcast found by a [`Command::Search`]
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct SearchResult {
+    pub title: ArcStr,
+    /// Further details, such as the artist of an album or the country of a station
+    pub subtitle: Option<ArcStr>,
+    /// Send this command to play the result
+    pub play: Command,
+}
+
+/// The results of a [`Command::Search`] from one of the configured sources
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct SearchResults {
+    pub query: ArcStr,
+    /// The name of the source, such as `radio-browser`
+    pub source: ArcStr,
+    /// The results, best match first, or a description of why the source couldn't be searched
+    pub result: Result<Vec<SearchResult>, ArcStr>,
+}
+
+/// The result of a [`Command::RunSelfTest`]
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+pub struct SelfTestResult {
+    /// A short tone was played without error. Whether it was heard must be checked by a person
+    pub audio_output: SelfTestOutcome,
+    pub cd_drive: SelfTestOutcome,
+    pub usb_device: SelfTestOutcome,
+    /// There is a route to the internet
+    pub network: SelfTestOutcome,
+    /// The gateway responds to pings
+    pub gateway: SelfTestOutcome,
+    /// Every station in the stations directory loads successfully
+    pub stations_directory: SelfTestOutcome,
+}
+
+impl SelfTestResult {
+    /// True if no check failed
+    #[must_use]
+    pub fn passed(&self) -> bool {
+        ![
+            &self.audio_output,
+            &self.cd_drive,
+            &self.usb_device,
+            &self.network,
+            &self.gateway,
+            &self.stations_directory,
+        ]
+        .iter()
+        .any(|outcome| outcome.is_failed())
+    }
 }
 
 #[allow(clippy::large_enum_variant)]
-#[derive(Debug, Deserialize, Serialize)]
+#[derive(Clone, Debug, Deserialize, Serialize)]
 pub enum Event {
     PlayerStateChanged(PlayerStateDiff),
+    SeekPreview(SeekPreview),
+    PlaylistDocument(PlaylistDocument),
+    StationLoadProgress(StationLoadProgress),
+    BootProgress(BootMilestone),
+    UPnPBrowseResult(UPnPBrowseResult),
+    /// The first event sent on each connection
+    Hello(DeviceIdentity),
+    /// Audit log entries, oldest first, in response to [`Command::RequestAuditLog`]
+    AuditLog(Vec<AuditLogEntry>),
+    SelfTestResult(SelfTestResult),
+    /// Play statistics, most recently played first, in response to [`Command::RequestPlayStatistics`]
+    PlayStatistics(Vec<TrackPlayStatistics>),
+    UpdateProgress(UpdateProgress),
+    BatchResult(BatchResult),
+    /// The stations directory was synced from the configured station manifest, and some stations changed, or syncing failed
+    StationsSynced(Result<StationSyncChanges, ArcStr>),
+    /// rradio is shutting down, but the named tasks didn't stop within their grace periods, and are being aborted
+    ShutdownTimedOut(Vec<ArcStr>),
+    SearchResults(SearchResults),
+    /// A command was rejected before it was processed, e.g. because too many commands are waiting to be processed. Only sent to the connection which sent the command
+    CommandRejected(Message),
+    /// A volume command was ignored because the volume was already at its minimum or maximum, so that clients without a screen can give feedback, e.g. by vibrating.
+    /// Sent each time, rather than being part of the player state, so that repeated presses are each reported
+    VolumeAtLimit,
 }
 
 #[derive(Debug, thiserror::Error)]
 #[error("Failed to encode Event: {0}")]
-pub struct EventEncodeError(#[source] postcard::Error);
+pub struct EventEncodeError(#[cfg_attr(feature = "std", source)] postcard::Error);
 
 #[derive(Debug, thiserror::Error)]
 #[error("Failed to decode Event: {0}")]
-pub struct EventDecodeError(#[source] postcard::Error);
+pub struct EventDecodeError(#[cfg_attr(feature = "std", source)] postcard::Error);
 
 impl Event {
     /// Clear the buffer and encode the `Event` into it
@@ -536,7 +1249,39 @@ impl Event {
     }
 }
 
-impl std::convert::From<PlayerStateDiff> for Event {
+/// An [`Event`], along with its sequence number and when it was created. Sent instead of events to connections which set [`EventFormatV2::sequenced`]
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct SequencedEvent {
+    /// Increases with each event which rradio creates. There are gaps where a connection skips events, e.g. intermediate player states of slow connections
+    pub sequence: u64,
+    /// How long after rradio started the event was created, by a monotonic clock which doesn't jump when the wall clock is corrected, e.g. by NTP
+    pub since_start: Duration,
+    /// The wall clock time, for showing to users
+    pub timestamp: chrono::DateTime<chrono::Utc>,
+    pub event: Event,
+}
+
+impl SequencedEvent {
+    /// Clear the buffer and encode the `SequencedEvent` into it
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if the event cannot be encoded.
+    pub fn encode<'a>(&self, buffer: &'a mut Vec<u8>) -> Result<&'a [u8], EventEncodeError> {
+        encoding::encode_value(self, buffer).map_err(EventEncodeError)
+    }
+
+    /// Decode a `SequencedEvent` from the buffer, which is encoded in the same way as an [`Event`]
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if the event cannot be decoded.
+    pub fn decode(buffer: &mut [u8]) -> Result<Self, EventDecodeError> {
+        encoding::decode_value(buffer).map_err(EventDecodeError)
+    }
+}
+
+impl core::convert::From<PlayerStateDiff> for Event {
     fn from(diff: PlayerStateDiff) -> Self {
         Self::PlayerStateChanged(diff)
     }
@@ -575,6 +1320,8 @@ mod event_async {
             expected: &'static [u8],
             actual: [u8; super::API_VERSION_HEADER_LENGTH],
         },
+        #[error("Bad reconnection backoff hint: {0:?}")]
+        BadBackoffHint(String),
     }
 
     pub(crate) async fn verify_rradio_header<S: tokio::io::AsyncRead + Unpin>(
@@ -596,6 +1343,27 @@ mod event_async {
         }
     }
 
+    pub(crate) async fn read_backoff_hint<S: tokio::io::AsyncBufRead + Unpin>(
+        stream: &mut S,
+    ) -> Result<core::time::Duration, BadRRadioHeader> {
+        use tokio::io::{AsyncBufReadExt, AsyncReadExt};
+
+        /// The length of `u64::MAX` and the newline
+        const MAXIMUM_HINT_LENGTH: u64 = 21;
+
+        let mut hint = String::new();
+
+        stream
+            .take(MAXIMUM_HINT_LENGTH)
+            .read_line(&mut hint)
+            .await?;
+
+        hint.strip_suffix('\n')
+            .and_then(|milliseconds| milliseconds.parse().ok())
+            .map(core::time::Duration::from_millis)
+            .ok_or(BadRRadioHeader::BadBackoffHint(hint))
+    }
+
     #[derive(Debug, thiserror::Error)]
     pub enum EventStreamDecodeError {
         #[error("Failed to read Event")]
@@ -644,6 +1412,36 @@ mod event_async {
 
             println!("{err}");
         }
+
+        #[tokio::test]
+        async fn read_backoff_hint() {
+            let backoff = core::time::Duration::from_millis(2500);
+            let hint = super::super::encode_backoff_hint(backoff);
+
+            assert_eq!(
+                super::read_backoff_hint(&mut hint.as_bytes()).await.unwrap(),
+                backoff
+            );
+
+            assert!(super::read_backoff_hint(&mut &b"soon\n"[..]).await.is_err());
+        }
+
+        #[tokio::test]
+        async fn frames_which_are_too_long_are_rejected() {
+            use core::convert::TryFrom;
+            use futures_util::StreamExt;
+
+            let frame =
+                alloc::vec![1; usize::try_from(crate::MAXIMUM_COMMAND_FRAME_LENGTH).unwrap() + 1];
+
+            let commands = crate::Command::decode_from_stream(frame.as_slice());
+            futures_util::pin_mut!(commands);
+
+            assert!(matches!(
+                commands.next().await,
+                Some(Err(crate::CommandStreamDecodeError::IoError(_)))
+            ));
+        }
     }
 }
 
@@ -663,9 +1461,32 @@ impl Event {
         impl futures_util::Stream<Item = Result<Self, EventStreamDecodeError>>,
         BadRRadioHeader,
     > {
-        verify_rradio_header(stream)
+        Self::decode_from_stream_with_backoff_hint(stream)
             .await
-            .map(encoding::decode_from_stream)
+            .map(|(_backoff_hint, events)| events)
+    }
+
+    /// Decode an event stream from a buffered reader, also returning the suggested delay before reconnecting if the connection is lost. See [`encode_backoff_hint`]
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if there's an IO error, if the `RRadio` header does not match the expected version, or if the backoff hint is malformed
+    pub async fn decode_from_stream_with_backoff_hint<S: tokio::io::AsyncBufRead + Unpin>(
+        stream: S,
+    ) -> Result<
+        (
+            Duration,
+            impl futures_util::Stream<Item = Result<Self, EventStreamDecodeError>>,
+        ),
+        BadRRadioHeader,
+    > {
+        let mut stream = verify_rradio_header(stream).await?;
+        let backoff_hint = read_backoff_hint(&mut stream).await?;
+
+        Ok((
+            backoff_hint,
+            encoding::decode_from_stream(stream, MAXIMUM_EVENT_FRAME_LENGTH),
+        ))
     }
 
     pub fn encode_to_stream<S: tokio::io::AsyncWrite + Unpin>(
@@ -0,0 +1,186 @@
+use alloc::{string::String, vec::Vec};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{ArcStr, StationError};
+
+/// Defines [`MessageId`], along with the English text and the names of the parameters of each message
+macro_rules! message_ids {
+    ($($id:ident => $template:literal $([$($parameter:literal),*])?,)*) => {
+        /// Identifies a message which is shown to users, so that clients can show the message in the user's language rather than matching against the English text.
+        /// Parameters of the message, such as the index of a station, are written in braces, e.g. `{index}`
+        #[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Deserialize, Serialize)]
+        pub enum MessageId {
+            $($id,)*
+        }
+
+        impl MessageId {
+            /// Every message, i.e. the message catalogue
+            pub const ALL: &'static [Self] = &[$(Self::$id,)*];
+
+            /// The text of the message in English, with the parameters in braces
+            #[must_use]
+            pub fn english_template(self) -> &'static str {
+                match self {
+                    $(Self::$id => $template,)*
+                }
+            }
+
+            /// The names of the parameters of the message, in the order that [`Message::parameters`] are given
+            #[must_use]
+            pub fn parameter_names(self) -> &'static [&'static str] {
+                match self {
+                    $(Self::$id => &[$($($parameter),*)?],)*
+                }
+            }
+        }
+    };
+}
+
+message_ids! {
+    CdNotEnabled => "CD support is not enabled",
+    CdError => "CD Error: {error}" ["error"],
+    UsbNotEnabled => "USB support is not enabled",
+    MountError => "Mount Error: {error}" ["error"],
+    UPnPError => "UPnP Error: {error}" ["error"],
+    StationsDirectoryUnreadable => "Failed to read from stations directory \"{directory}\": {error}" ["directory", "error"],
+    StationNotFound => "Station {index} not found in {directory}" ["index", "directory"],
+    BadStationFile => "Bad Station File: {error}" ["error"],
+    StationUnreachable => "{url} is unreachable: {error}" ["url", "error"],
+    AmbiguousStationAlias => "\"{alias}\" matches more than one station: {indices}" ["alias", "indices"],
+    PipelineError => "Pipeline error",
+    PlaybackError => "gstreamer error: {error}" ["error"],
+    TrackResolutionFailed => "Failed to resolve track: {error}" ["error"],
+    CdEjectFailed => "Failed to eject CD: {error}" ["error"],
+    SafeMode => "Started in safe mode: {reason}" ["reason"],
+    NoAudioOutputNamed => "No audio output named \"{name}\"" ["name"],
+    NoAudioOutputs => "No audio outputs are configured",
+    UpdatesDisabled => "Updates are disabled",
+    NoTracksToEnqueue => "No tracks to enqueue",
+    SystemCommandsDisabled => "System commands are disabled",
+    BadSystemCommandToken => "Bad system command token",
+    NestedBatch => "Batches can't be nested",
+    TitleLoopDetected => "The stream has played \"{title}\" {count} times" ["title", "count"],
+    SearchDisabled => "No search sources are configured",
+    NoTrackAtIndex => "There is no track {index}" ["index"],
+    CannotRemoveCurrentTrack => "The current track can't be removed",
+    NotEnoughTracksToScan => "The current station doesn't have enough tracks to scan",
+    IntroScanTooShort => "Intro scans must play at least one second of each track",
+    BadAlarmTime => "{time} isn't a time of day" ["time"],
+    NoAlarmAt => "There is no alarm at {time}" ["time"],
+    StationLocked => "The station is locked",
+    NotPrivileged => "Only privileged clients can lock the station",
+    SdrError => "SDR Error: {error}" ["error"],
+    CommandQueueFull => "Too many commands are waiting to be processed",
+}
+
+impl MessageId {
+    /// The text of the message in English, with the parameters filled in
+    #[must_use]
+    pub fn format_english(self, parameters: &[ArcStr]) -> String {
+        let mut text = String::from(self.english_template());
+
+        for (name, value) in self.parameter_names().iter().zip(parameters) {
+            text = text.replace(&alloc::format!("{{{name}}}"), value);
+        }
+
+        text
+    }
+}
+
+/// A message shown to users, such as an error
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+pub struct Message {
+    pub id: MessageId,
+    /// The values of the parameters of the message, in the order given by [`MessageId::parameter_names`]
+    pub parameters: Vec<ArcStr>,
+    /// The message in English, for clients which don't have a translation of the message
+    pub text: ArcStr,
+}
+
+impl Message {
+    #[must_use]
+    pub fn new(id: MessageId, parameters: Vec<ArcStr>) -> Self {
+        let text = id.format_english(&parameters).into();
+
+        Self {
+            id,
+            parameters,
+            text,
+        }
+    }
+}
+
+impl From<MessageId> for Message {
+    fn from(id: MessageId) -> Self {
+        Self::new(id, Vec::new())
+    }
+}
+
+impl StationError {
+    /// The error as a [`Message`], so that clients can show it in the user's language
+    #[must_use]
+    pub fn message(&self) -> Message {
+        use alloc::vec;
+
+        let display = |value: &dyn core::fmt::Display| ArcStr::from(alloc::format!("{value}"));
+
+        match self {
+            Self::CdError(crate::CdError::CdNotEnabled) => MessageId::CdNotEnabled.into(),
+            Self::CdError(error) => Message::new(MessageId::CdError, vec![display(error)]),
+            Self::MountError(crate::MountError::UsbNotEnabled) => MessageId::UsbNotEnabled.into(),
+            Self::MountError(error) => Message::new(MessageId::MountError, vec![display(error)]),
+            Self::UPnPError(error) => Message::new(MessageId::UPnPError, vec![error.clone()]),
+            Self::StationsDirectoryIoError { directory, err } => Message::new(
+                MessageId::StationsDirectoryUnreadable,
+                vec![directory.clone(), err.clone()],
+            ),
+            Self::StationNotFound { index, directory } => Message::new(
+                MessageId::StationNotFound,
+                vec![display(index), directory.clone()],
+            ),
+            Self::BadStationFile(error) => {
+                Message::new(MessageId::BadStationFile, vec![error.clone()])
+            }
+            Self::Unreachable { url, err } => Message::new(
+                MessageId::StationUnreachable,
+                vec![url.clone(), display(err)],
+            ),
+            Self::AmbiguousStationAlias { alias, indices } => Message::new(
+                MessageId::AmbiguousStationAlias,
+                vec![alias.clone(), display(&crate::StationIndices(indices))],
+            ),
+            Self::SdrError(error) => Message::new(MessageId::SdrError, vec![error.clone()]),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Message, MessageId};
+
+    #[test]
+    fn every_parameter_appears_in_its_template() {
+        for &id in MessageId::ALL {
+            for name in id.parameter_names() {
+                assert!(
+                    id.english_template()
+                        .contains(&alloc::format!("{{{name}}}")),
+                    "{:?} is missing {}",
+                    id,
+                    name
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn parameters_are_substituted_into_the_english_text() {
+        let message = Message::new(
+            MessageId::StationNotFound,
+            alloc::vec!["05".into(), "stations".into()],
+        );
+
+        assert_eq!(message.text, "Station 05 not found in stations");
+    }
+}
@@ -0,0 +1,178 @@
+use alloc::vec::Vec;
+
+use serde::{Deserialize, Serialize};
+
+use crate::Track;
+
+/// A change to a list of tracks
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+pub enum TrackListOperation {
+    /// Insert `tracks` before the track at `index`, or at the end if `index` is the length of the list
+    Insert { index: usize, tracks: Vec<Track> },
+    /// Remove `count` tracks, starting at `index`
+    Remove { index: usize, count: usize },
+    /// Replace the tracks starting at `index` with `tracks`
+    Replace { index: usize, tracks: Vec<Track> },
+}
+
+/// Changes to the tracks of the current station, sent instead of the entire list of tracks when only some of the tracks have changed
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+pub struct TrackListPatch {
+    /// The revision of the tracks which the patch applies to
+    pub base_revision: u64,
+    /// Applied in order
+    pub operations: Vec<TrackListOperation>,
+}
+
+#[derive(Debug, thiserror::Error)]
+#[error("Track list operation is out of bounds")]
+pub struct TrackListPatchError;
+
+impl TrackListPatch {
+    /// The patch which changes `old` into `new`, or `None` if the patch would contain every track of `new`, in which case it's smaller to send `new`
+    #[must_use]
+    pub fn new(base_revision: u64, old: &[Track], new: &[Track]) -> Option<Self> {
+        let prefix_length = old
+            .iter()
+            .zip(new)
+            .take_while(|(old, new)| old == new)
+            .count();
+
+        let (old_middle, new_middle) = (&old[prefix_length..], &new[prefix_length..]);
+
+        let suffix_length = old_middle
+            .iter()
+            .rev()
+            .zip(new_middle.iter().rev())
+            .take_while(|(old, new)| old == new)
+            .count();
+
+        let old_middle = &old_middle[..old_middle.len() - suffix_length];
+        let new_middle = &new_middle[..new_middle.len() - suffix_length];
+
+        if !new.is_empty() && new_middle.len() == new.len() {
+            return None;
+        }
+
+        let replaced_count = old_middle.len().min(new_middle.len());
+
+        let mut operations = Vec::new();
+
+        if replaced_count > 0 {
+            operations.push(TrackListOperation::Replace {
+                index: prefix_length,
+                tracks: new_middle[..replaced_count].to_vec(),
+            });
+        }
+
+        let index = prefix_length + replaced_count;
+
+        if new_middle.len() > replaced_count {
+            operations.push(TrackListOperation::Insert {
+                index,
+                tracks: new_middle[replaced_count..].to_vec(),
+            });
+        }
+
+        if old_middle.len() > replaced_count {
+            operations.push(TrackListOperation::Remove {
+                index,
+                count: old_middle.len() - replaced_count,
+            });
+        }
+
+        Some(Self {
+            base_revision,
+            operations,
+        })
+    }
+
+    /// Apply the patch to the tracks which have revision `base_revision`
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if an operation refers to tracks which don't exist, in which case `tracks` might be partially patched
+    pub fn apply(&self, tracks: &mut Vec<Track>) -> Result<(), TrackListPatchError> {
+        for operation in &self.operations {
+            match operation {
+                TrackListOperation::Insert {
+                    index,
+                    tracks: inserted,
+                } => {
+                    if *index > tracks.len() {
+                        return Err(TrackListPatchError);
+                    }
+
+                    tracks.splice(*index..*index, inserted.iter().cloned());
+                }
+                TrackListOperation::Remove { index, count } => {
+                    let end = index.checked_add(*count).ok_or(TrackListPatchError)?;
+
+                    if end > tracks.len() {
+                        return Err(TrackListPatchError);
+                    }
+
+                    tracks.drain(*index..end);
+                }
+                TrackListOperation::Replace {
+                    index,
+                    tracks: replacements,
+                } => {
+                    tracks
+                        .get_mut(*index..)
+                        .and_then(|tracks| tracks.get_mut(..replacements.len()))
+                        .ok_or(TrackListPatchError)?
+                        .clone_from_slice(replacements);
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::vec::Vec;
+
+    use super::{TrackListOperation, TrackListPatch};
+    use crate::Track;
+
+    fn tracks(urls: &[&str]) -> Vec<Track> {
+        urls.iter().map(|&url| Track::url(url.into())).collect()
+    }
+
+    #[test]
+    fn patches_change_old_tracks_into_new_tracks() {
+        let old = tracks(&["a", "b", "c", "d", "e"]);
+
+        for new in [
+            tracks(&["a", "b", "x", "c", "d", "e"]),
+            tracks(&["a", "b", "e"]),
+            tracks(&["a", "x", "y", "z", "e"]),
+            tracks(&["a", "x", "y", "z", "w", "e"]),
+            tracks(&["a", "x", "e"]),
+            tracks(&["a", "b", "c", "d", "e", "f"]),
+            tracks(&["a"]),
+        ] {
+            let patch = TrackListPatch::new(3, &old, &new).unwrap();
+
+            let mut patched = old.clone();
+            patch.apply(&mut patched).unwrap();
+
+            assert_eq!(patched, new);
+        }
+
+        assert_eq!(
+            TrackListPatch::new(3, &old, &tracks(&["a", "b", "x", "c", "d", "e"]))
+                .unwrap()
+                .operations,
+            [TrackListOperation::Insert {
+                index: 2,
+                tracks: tracks(&["x"])
+            }]
+        );
+
+        assert!(TrackListPatch::new(3, &old, &tracks(&["x", "y"])).is_none());
+    }
+}
@@ -0,0 +1,170 @@
+//! Measure how much a busy task, such as loading a large `UPnP` container, delays the pipeline controller and the events it sends to clients,
+//! with each of the runtime configurations described in the README.
+//!
+//! Run on the target device with `cargo run --release --example runtime-latency`
+
+use std::time::{Duration, Instant};
+
+use tokio::sync::broadcast;
+
+/// How often the simulated controller handles a message and sends an event
+const TICK_INTERVAL: Duration = Duration::from_millis(10);
+
+/// How long the simulated station load blocks its thread before yielding, as when parsing a large `UPnP` response
+const BUSY_DURATION: Duration = Duration::from_millis(50);
+
+/// How long each runtime configuration is measured for
+const RUN_DURATION: Duration = Duration::from_secs(5);
+
+#[derive(Clone, Copy, Debug)]
+enum Setup {
+    /// `flavor = "current_thread"`
+    CurrentThread,
+    /// `flavor = "current_thread"` and `dedicated_pipeline_thread = true`
+    DedicatedPipelineThread,
+    /// `flavor = "multi_thread"`
+    MultiThread,
+}
+
+/// Ticks every `TICK_INTERVAL`, sending the time of each tick as an event. Returns how late each tick was
+async fn controller(events_tx: broadcast::Sender<Instant>) -> Vec<Duration> {
+    let mut interval = tokio::time::interval(TICK_INTERVAL);
+    interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+
+    let start = Instant::now();
+    let mut lateness = Vec::new();
+
+    while start.elapsed() < RUN_DURATION {
+        let scheduled = interval.tick().await;
+        lateness.push(scheduled.elapsed());
+
+        events_tx.send(Instant::now()).ok();
+    }
+
+    lateness
+}
+
+/// Receives events, as a port does. Returns how long each event took to be received
+async fn port(mut events_rx: broadcast::Receiver<Instant>) -> Vec<Duration> {
+    let mut latencies = Vec::new();
+
+    loop {
+        match events_rx.recv().await {
+            Ok(sent) => latencies.push(sent.elapsed()),
+            Err(broadcast::error::RecvError::Lagged(_)) => (),
+            Err(broadcast::error::RecvError::Closed) => return latencies,
+        }
+    }
+}
+
+/// Repeatedly blocks its thread for `BUSY_DURATION`, as a slow station load does
+async fn station_load() {
+    loop {
+        let start = Instant::now();
+        while start.elapsed() < BUSY_DURATION {
+            std::hint::spin_loop();
+        }
+
+        tokio::task::yield_now().await;
+    }
+}
+
+/// Run the controller on its own thread, as `spawn_pipeline_thread` does
+fn spawn_pipeline_thread(
+    events_tx: broadcast::Sender<Instant>,
+) -> std::thread::JoinHandle<Vec<Duration>> {
+    std::thread::spawn(move || {
+        tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .unwrap()
+            .block_on(controller(events_tx))
+    })
+}
+
+/// Returns how late the controller's ticks were, and how long events took to be received
+fn measure(setup: Setup) -> (Vec<Duration>, Vec<Duration>) {
+    let runtime = match setup {
+        Setup::CurrentThread | Setup::DedicatedPipelineThread => {
+            tokio::runtime::Builder::new_current_thread()
+        }
+        Setup::MultiThread => tokio::runtime::Builder::new_multi_thread(),
+    }
+    .enable_all()
+    .build()
+    .unwrap();
+
+    let (events_tx, events_rx) = broadcast::channel(1024);
+
+    let (controller_lateness, event_latencies) = runtime.block_on(async move {
+        tokio::spawn(station_load());
+
+        let port = tokio::spawn(port(events_rx));
+
+        let controller_lateness = match setup {
+            Setup::CurrentThread => tokio::spawn(controller(events_tx)).await.unwrap(),
+            Setup::DedicatedPipelineThread | Setup::MultiThread => {
+                let pipeline_thread = spawn_pipeline_thread(events_tx);
+                tokio::task::spawn_blocking(move || pipeline_thread.join().unwrap())
+                    .await
+                    .unwrap()
+            }
+        };
+
+        (controller_lateness, port.await.unwrap())
+    });
+
+    // The station load never finishes, so is cancelled
+    runtime.shutdown_background();
+
+    (controller_lateness, event_latencies)
+}
+
+fn milliseconds(duration: Duration) -> f64 {
+    duration.as_secs_f64() * 1000.0
+}
+
+/// The median, 99th percentile, and maximum, in milliseconds
+fn summarise(mut durations: Vec<Duration>) -> String {
+    durations.sort_unstable();
+
+    let percentile = |percent: usize| durations[(durations.len() - 1) * percent / 100];
+
+    format!(
+        "{:>7.1} {:>7.1} {:>7.1}",
+        milliseconds(percentile(50)),
+        milliseconds(percentile(99)),
+        milliseconds(durations[durations.len() - 1]),
+    )
+}
+
+fn main() {
+    println!(
+        "{} CPU cores. A task blocks its thread for {BUSY_DURATION:?} at a time, the controller ticks every {TICK_INTERVAL:?}. Times in ms",
+        std::thread::available_parallelism().map_or(1, std::num::NonZeroUsize::get)
+    );
+    println!();
+    println!(
+        "{:<26} {:^23} {:^23}",
+        "", "Controller tick delay", "Event delivery delay"
+    );
+    println!(
+        "{:<26} {:>7} {:>7} {:>7} {:>7} {:>7} {:>7}",
+        "", "p50", "p99", "max", "p50", "p99", "max"
+    );
+
+    for setup in [
+        Setup::CurrentThread,
+        Setup::DedicatedPipelineThread,
+        Setup::MultiThread,
+    ] {
+        let (controller_lateness, event_latencies) = measure(setup);
+
+        println!(
+            "{:<26} {} {}",
+            format!("{setup:?}"),
+            summarise(controller_lateness),
+            summarise(event_latencies)
+        );
+    }
+}
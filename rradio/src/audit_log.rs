@@ -0,0 +1,312 @@
+//! A record of received commands, stored as JSON lines in the state directory.
+//! Entries are appended to the file by a writer on a blocking thread, and the file is rewritten with only the most recent entries once it grows to twice the maximum length
+
+use std::{
+    collections::VecDeque,
+    io::{BufWriter, Write},
+    path::{Path, PathBuf},
+};
+
+use anyhow::{Context, Result};
+use tokio::sync::mpsc;
+
+use rradio_messages::AuditLogEntry;
+
+const AUDIT_LOG_FILE_NAME: &str = "audit_log.jsonl";
+
+/// How many entries can wait to be written before new entries are only kept in memory
+const QUEUE_LENGTH: usize = 64;
+
+pub struct AuditLog {
+    maximum_length: usize,
+    entries: VecDeque<AuditLogEntry>,
+    writer: Writer,
+}
+
+enum Writer {
+    /// The audit log is loaded before the runtime starts, so the writer is started when the first entry is recorded
+    Idle(LogFile),
+    Running {
+        entries_tx: mpsc::Sender<AuditLogEntry>,
+        handle: tokio::task::JoinHandle<()>,
+    },
+    /// The audit log is disabled, or has been closed
+    Stopped,
+}
+
+impl AuditLog {
+    /// Load the most recent entries from the given state directory. If `maximum_length` is zero, the audit log is disabled
+    pub fn load(state_directory: impl AsRef<Path>, maximum_length: usize) -> Self {
+        if maximum_length == 0 {
+            return Self {
+                maximum_length,
+                entries: VecDeque::new(),
+                writer: Writer::Stopped,
+            };
+        }
+
+        let path = state_directory.as_ref().join(AUDIT_LOG_FILE_NAME);
+
+        let (entries, file_length) = read_entries(&path, maximum_length).unwrap_or_else(|err| {
+            tracing::error!("{err:#}");
+            (VecDeque::new(), 0)
+        });
+
+        Self {
+            maximum_length,
+            entries: entries.clone(),
+            writer: Writer::Idle(LogFile {
+                path,
+                maximum_length,
+                entries,
+                file_length,
+                file: None,
+            }),
+        }
+    }
+
+    pub fn record(&mut self, mut entry: AuditLogEntry) {
+        if self.maximum_length == 0 {
+            return;
+        }
+
+        crate::redact::audit_log_entry(&mut entry);
+
+        if self.entries.len() == self.maximum_length {
+            self.entries.pop_front();
+        }
+
+        self.entries.push_back(entry.clone());
+
+        self.writer = match std::mem::replace(&mut self.writer, Writer::Stopped) {
+            Writer::Idle(log_file) => {
+                let (entries_tx, entries_rx) = mpsc::channel(QUEUE_LENGTH);
+
+                Writer::Running {
+                    entries_tx,
+                    handle: tokio::task::spawn_blocking(move || log_file.write_all(entries_rx)),
+                }
+            }
+            writer => writer,
+        };
+
+        if let Writer::Running { entries_tx, .. } = &self.writer {
+            if entries_tx.try_send(entry).is_err() {
+                tracing::warn!("Audit log writer is falling behind, so an entry won't be written");
+            }
+        }
+    }
+
+    /// The most recent `count` entries, oldest first
+    pub fn recent(&self, count: usize) -> Vec<AuditLogEntry> {
+        self.entries
+            .iter()
+            .skip(self.entries.len().saturating_sub(count))
+            .cloned()
+            .collect()
+    }
+
+    /// Wait for the recorded entries to be written. Entries recorded afterwards are only kept in memory. Called on shutdown
+    pub async fn close(&mut self) {
+        if let Writer::Running { entries_tx, handle } =
+            std::mem::replace(&mut self.writer, Writer::Stopped)
+        {
+            // The writer finishes once the queue is empty and the sender has been dropped
+            drop(entries_tx);
+
+            if let Err(err) = handle.await {
+                tracing::error!("Audit log writer failed: {err}");
+            }
+        }
+    }
+}
+
+/// The file which entries are appended to, owned by the writer
+struct LogFile {
+    path: PathBuf,
+    maximum_length: usize,
+    /// The most recent entries, which are kept when the file is rewritten
+    entries: VecDeque<AuditLogEntry>,
+    /// The number of lines in the file, which is allowed to exceed `maximum_length` so that the file isn't rewritten after every command
+    file_length: usize,
+    file: Option<BufWriter<std::fs::File>>,
+}
+
+impl LogFile {
+    /// Write entries until the sender is dropped. Entries are flushed once the queue is empty, so that a burst of commands, such as a batch, is written at once
+    fn write_all(mut self, mut entries_rx: mpsc::Receiver<AuditLogEntry>) {
+        while let Some(mut entry) = entries_rx.blocking_recv() {
+            loop {
+                if let Err(err) = self.write(entry) {
+                    tracing::error!("{err:#}");
+                }
+
+                match entries_rx.try_recv() {
+                    Ok(next_entry) => entry = next_entry,
+                    Err(_) => break,
+                }
+            }
+
+            if let Err(err) = self.flush() {
+                tracing::error!("{err:#}");
+            }
+        }
+    }
+
+    fn write(&mut self, entry: AuditLogEntry) -> Result<()> {
+        if self.entries.len() == self.maximum_length {
+            self.entries.pop_front();
+        }
+
+        if self.file_length + 1 >= 2 * self.maximum_length {
+            self.entries.push_back(entry);
+            self.rewrite()
+        } else {
+            let result = self.append(&entry);
+            self.entries.push_back(entry);
+            result
+        }
+    }
+
+    fn create_parent_directory(&self) -> Result<()> {
+        if let Some(parent) = self.path.parent() {
+            std::fs::create_dir_all(parent)
+                .with_context(|| format!("Failed to create {}", parent.display()))?;
+        }
+
+        Ok(())
+    }
+
+    fn append(&mut self, entry: &AuditLogEntry) -> Result<()> {
+        let mut line = serde_json::to_vec(entry).context("Failed to serialize audit log entry")?;
+        line.push(b'\n');
+
+        let file = if let Some(file) = &mut self.file {
+            file
+        } else {
+            self.create_parent_directory()?;
+
+            let file = std::fs::OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(&self.path)
+                .with_context(|| format!("Failed to open {}", self.path.display()))?;
+
+            self.file.insert(BufWriter::new(file))
+        };
+
+        file.write_all(&line)
+            .with_context(|| format!("Failed to append to {}", self.path.display()))?;
+
+        self.file_length += 1;
+
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        match &mut self.file {
+            Some(file) => file
+                .flush()
+                .with_context(|| format!("Failed to append to {}", self.path.display())),
+            None => Ok(()),
+        }
+    }
+
+    fn rewrite(&mut self) -> Result<()> {
+        // Appended entries which haven't been written yet are also in `entries`, so the file is closed before it's replaced
+        self.flush()?;
+        self.file = None;
+
+        let mut contents = Vec::new();
+
+        for entry in &self.entries {
+            serde_json::to_writer(&mut contents, entry)
+                .context("Failed to serialize audit log entry")?;
+            contents.push(b'\n');
+        }
+
+        crate::state_file::write(&self.path, contents)?;
+
+        self.file_length = self.entries.len();
+
+        Ok(())
+    }
+}
+
+/// Read the last `maximum_length` entries, and the number of lines in the file
+fn read_entries(path: &Path, maximum_length: usize) -> Result<(VecDeque<AuditLogEntry>, usize)> {
+    let contents = match std::fs::read_to_string(path) {
+        Ok(contents) => contents,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => {
+            tracing::debug!("{} not found, starting a new audit log", path.display());
+            return Ok((VecDeque::new(), 0));
+        }
+        Err(err) => {
+            return Err(err).with_context(|| format!("Failed to read {}", path.display()));
+        }
+    };
+
+    let mut entries = VecDeque::with_capacity(maximum_length);
+    let mut file_length = 0;
+
+    for line in contents.lines() {
+        file_length += 1;
+
+        // A line might be partially written if rradio stopped while appending to the log, so skip lines which can't be parsed
+        match serde_json::from_str(line) {
+            Ok(entry) => {
+                if entries.len() == maximum_length {
+                    entries.pop_front();
+                }
+                entries.push_back(entry);
+            }
+            Err(err) => tracing::warn!("Bad audit log entry in {}: {err}", path.display()),
+        }
+    }
+
+    Ok((entries, file_length))
+}
+
+#[cfg(test)]
+mod tests {
+    use rradio_messages::{arcstr, AuditLogEntry};
+
+    use super::{AuditLog, AUDIT_LOG_FILE_NAME};
+
+    fn entry(index: usize) -> AuditLogEntry {
+        AuditLogEntry {
+            timestamp: chrono::Utc::now(),
+            source: arcstr::literal!("test"),
+            command: arcstr::format!("Command {index}"),
+            error: (index % 2 == 1).then(|| arcstr::literal!("Failed")),
+        }
+    }
+
+    #[tokio::test]
+    async fn keeps_most_recent_entries_across_restarts() {
+        let state_directory = tempfile::tempdir().unwrap();
+
+        let mut audit_log = AuditLog::load(state_directory.path(), 3);
+
+        let entries = (0..10).map(entry).collect::<Vec<_>>();
+
+        for entry in &entries {
+            audit_log.record(entry.clone());
+        }
+
+        assert_eq!(audit_log.recent(2), entries[8..]);
+
+        audit_log.close().await;
+
+        let file_length = std::fs::read_to_string(state_directory.path().join(AUDIT_LOG_FILE_NAME))
+            .unwrap()
+            .lines()
+            .count();
+
+        assert!(file_length < 6);
+
+        let audit_log = AuditLog::load(state_directory.path(), 3);
+
+        assert_eq!(audit_log.recent(10), entries[7..]);
+    }
+}
@@ -0,0 +1,137 @@
+//! Milestones of startup, which are sent to clients as events and can have notification sounds, so that users of devices without screens can tell how far startup got
+
+use std::{
+    net::{Ipv4Addr, SocketAddr, UdpSocket},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+use rradio_messages::BootMilestone;
+use tokio::sync::mpsc;
+
+use crate::{config::Config, ports::PortChannels, safe_mode::BootRecord};
+
+/// How often to check whether the network is up
+const NETWORK_POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+/// The network is considered to be up once there is a route to this address. No packets are sent to it
+const NETWORK_PROBE_ADDRESS: (Ipv4Addr, u16) = (Ipv4Addr::new(8, 8, 8, 8), 53);
+
+/// Reports milestones to the controller
+#[derive(Debug, Clone)]
+pub struct Milestones {
+    milestones_tx: mpsc::UnboundedSender<BootMilestone>,
+    ports_not_listening: Arc<AtomicUsize>,
+}
+
+impl Milestones {
+    /// `port_count` is the number of ports which must be listening for [`BootMilestone::PortsListening`] to be reached
+    pub fn new(port_count: usize) -> (Self, mpsc::UnboundedReceiver<BootMilestone>) {
+        let (milestones_tx, milestones_rx) = mpsc::unbounded_channel();
+
+        (
+            Self {
+                milestones_tx,
+                ports_not_listening: Arc::new(AtomicUsize::new(port_count)),
+            },
+            milestones_rx,
+        )
+    }
+
+    pub fn reached(&self, milestone: BootMilestone) {
+        tracing::info!(?milestone, "Boot milestone reached");
+
+        // The controller might have stopped, which is not an error
+        self.milestones_tx.send(milestone).ok();
+    }
+
+    /// Record that a port is listening. Once all ports are listening, [`BootMilestone::PortsListening`] is reached
+    pub fn port_listening(&self) {
+        if self.ports_not_listening.fetch_sub(1, Ordering::AcqRel) == 1 {
+            self.reached(BootMilestone::PortsListening);
+        }
+    }
+}
+
+pub fn has_route_to_internet() -> bool {
+    UdpSocket::bind((Ipv4Addr::UNSPECIFIED, 0))
+        .and_then(|socket| socket.connect(SocketAddr::from(NETWORK_PROBE_ADDRESS)))
+        .is_ok()
+}
+
+/// Wait for the network to come up and validate the stations, reporting milestones as they are reached, and record that startup succeeded once rradio has been running for a while.
+/// `boot_record` is `None` if rradio is embedded in another program, which is responsible for recovering from bad configs
+pub async fn run(
+    port_channels: PortChannels,
+    config: Config,
+    boot_record: Option<BootRecord>,
+) -> anyhow::Result<()> {
+    let milestones = &port_channels.boot_milestones;
+
+    let stations_config = config.clone();
+
+    let validate_stations = async {
+        match tokio::task::spawn_blocking(move || crate::station::validate_all(&stations_config))
+            .await
+        {
+            Ok(Ok(0)) => milestones.reached(BootMilestone::StationsValidated),
+            Ok(Ok(failure_count)) => tracing::warn!("{failure_count} stations failed to load"),
+            Ok(Err(err)) => tracing::error!("Failed to validate stations: {err}"),
+            Err(err) => tracing::error!("Failed to validate stations: {err}"),
+        }
+    };
+
+    let wait_for_network = async {
+        while !has_route_to_internet() {
+            tokio::time::sleep(NETWORK_POLL_INTERVAL).await;
+        }
+
+        milestones.reached(BootMilestone::NetworkUp);
+    };
+
+    let record_startup_success = async {
+        let Some(boot_record) = boot_record else {
+            return;
+        };
+
+        tokio::time::sleep(crate::safe_mode::STABLE_DURATION).await;
+
+        match tokio::task::spawn_blocking(move || boot_record.startup_succeeded(&config)).await {
+            Ok(Ok(())) => (),
+            Ok(Err(err)) => tracing::error!("Failed to record startup success: {err:#}"),
+            Err(err) => tracing::error!("Failed to record startup success: {err}"),
+        }
+    };
+
+    tokio::select! {
+        () = port_channels.shutdown_signal.clone() => (),
+        ((), (), ()) = futures_util::future::join3(validate_stations, wait_for_network, record_startup_success) => (),
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use rradio_messages::BootMilestone;
+
+    use super::Milestones;
+
+    #[test]
+    fn ports_listening_is_reached_once_every_port_is_listening() {
+        let (milestones, mut milestones_rx) = Milestones::new(2);
+
+        milestones.port_listening();
+        assert!(milestones_rx.try_recv().is_err());
+
+        milestones.clone().port_listening();
+        assert_eq!(
+            milestones_rx.try_recv().ok(),
+            Some(BootMilestone::PortsListening)
+        );
+        assert!(milestones_rx.try_recv().is_err());
+    }
+}
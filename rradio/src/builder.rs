@@ -0,0 +1,436 @@
+//! Starting rradio from within another program, choosing which ports run and supplying the config programmatically, rather than running the `rradio` binary
+
+use std::{future::Future, pin::Pin};
+
+use futures_util::{Stream, StreamExt};
+use rradio_messages::{ArcStr, Event};
+use tokio::sync::{mpsc, oneshot};
+
+use crate::{
+    config::Config,
+    ports::{CommandSender, PortChannels},
+    safe_mode::{BootRecord, Startup},
+    system_action::SystemAction,
+    task,
+};
+
+/// A port or background task, which is spawned by [`Rradio::run`]
+type Task = Pin<Box<dyn Future<Output = anyhow::Result<()>> + Send>>;
+
+/// A task, along with its name, which is used to look up its shutdown grace period, and its span
+type NamedTask = (&'static str, tracing::Span, Task);
+
+/// The task which runs the pipeline, and signals that the pipeline has finished, e.g. after saving the playback position
+type PipelineTask = Pin<Box<dyn Future<Output = ()> + Send>>;
+
+/// Which ports and integrations run. The background tasks, such as station sync, always run, and do nothing unless they're configured
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[allow(clippy::struct_excessive_bools)]
+pub struct Ports {
+    /// The text port, as configured by `tcp`
+    pub tcp_text: bool,
+    /// The binary port, as configured by `tcp`
+    pub tcp_binary: bool,
+    /// The MPD port, if `mpd` is configured
+    pub mpd: bool,
+    /// The web app and API, as configured by `web`
+    #[cfg(feature = "web")]
+    pub web: bool,
+    /// The configured hooks
+    pub hooks: bool,
+    /// The configured Lua plugins
+    #[cfg(feature = "lua-plugins")]
+    pub plugins: bool,
+    /// Commands sent by the configured unix signals
+    pub signal_commands: bool,
+}
+
+impl Ports {
+    /// Every port, as run by the `rradio` binary
+    #[must_use]
+    pub fn all() -> Self {
+        Self {
+            tcp_text: true,
+            tcp_binary: true,
+            mpd: true,
+            #[cfg(feature = "web")]
+            web: true,
+            hooks: true,
+            #[cfg(feature = "lua-plugins")]
+            plugins: true,
+            signal_commands: true,
+        }
+    }
+
+    /// No ports, e.g. if the program embedding rradio only uses [`Rradio::commands`] and [`Rradio::events`]
+    #[must_use]
+    pub fn none() -> Self {
+        Self {
+            tcp_text: false,
+            tcp_binary: false,
+            mpd: false,
+            #[cfg(feature = "web")]
+            web: false,
+            hooks: false,
+            #[cfg(feature = "lua-plugins")]
+            plugins: false,
+            signal_commands: false,
+        }
+    }
+
+    /// The number of ports which report that they're listening, all of which must be listening for [`rradio_messages::BootMilestone::PortsListening`] to be reached
+    fn listening_count(self) -> usize {
+        [
+            self.tcp_text,
+            self.tcp_binary,
+            #[cfg(feature = "web")]
+            self.web,
+        ]
+        .iter()
+        .filter(|&&is_running| is_running)
+        .count()
+    }
+}
+
+impl Default for Ports {
+    fn default() -> Self {
+        Self::all()
+    }
+}
+
+/// Configures rradio before it starts
+pub struct Builder {
+    config: Config,
+    ports: Ports,
+    safe_mode: Option<ArcStr>,
+    config_loaded: bool,
+    boot_record: Option<BootRecord>,
+}
+
+impl Builder {
+    /// rradio with the given config, e.g. loaded with [`Config::from_file`], or constructed programmatically starting with [`Config::default`]
+    #[must_use]
+    pub fn new(config: Config) -> Self {
+        Self {
+            config,
+            ports: Ports::all(),
+            safe_mode: None,
+            config_loaded: true,
+            boot_record: None,
+        }
+    }
+
+    /// rradio as started by the binary, which falls back to safe mode if the config file is unusable, and records when startup succeeds
+    pub(crate) fn from_startup(
+        Startup {
+            config,
+            config_loaded,
+            safe_mode,
+        }: Startup,
+        boot_record: BootRecord,
+    ) -> Self {
+        Self {
+            safe_mode,
+            config_loaded,
+            boot_record: Some(boot_record),
+            ..Self::new(config)
+        }
+    }
+
+    /// Choose which ports run. Defaults to [`Ports::all`]
+    #[must_use]
+    pub fn ports(self, ports: Ports) -> Self {
+        Self { ports, ..self }
+    }
+
+    /// Create the pipeline and the ports. Nothing runs until [`Rradio::run`] is called
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if gstreamer fails to initialise, or the pipeline can't be created
+    pub fn build(self) -> anyhow::Result<Rradio> {
+        let Self {
+            config,
+            ports,
+            safe_mode,
+            config_loaded,
+            boot_record,
+        } = self;
+
+        let (shutdown_handle, shutdown_signal) = task::ShutdownSignal::new();
+        let shutdown_signal = futures_util::FutureExt::shared(shutdown_signal);
+
+        // The ports shut down after the other tasks, so that connected clients can be told which tasks didn't stop in time
+        let (ports_shutdown_handle, ports_shutdown_signal) = task::ShutdownSignal::new();
+        let ports_shutdown_signal = futures_util::FutureExt::shared(ports_shutdown_signal);
+
+        // Powering off and rebooting are requested by the pipeline, and performed once rradio has shut down
+        let (system_actions_tx, system_actions_rx) = mpsc::channel(1);
+
+        let (pipeline_task, port_channels) = crate::pipeline::run(
+            config.clone(),
+            safe_mode,
+            ports.listening_count(),
+            shutdown_signal.clone(),
+            system_actions_tx,
+        )?;
+
+        // The pipeline task saves the playback position when shutting down, which `Rradio::run` waits for
+        let (pipeline_finished_tx, pipeline_finished_rx) = oneshot::channel::<()>();
+        let pipeline_task = Box::pin(async move {
+            pipeline_task.await;
+            pipeline_finished_tx.send(()).ok();
+        });
+
+        let task_channels = port_channels.clone().with_shutdown_signal(shutdown_signal);
+        let port_channels = port_channels.with_shutdown_signal(ports_shutdown_signal);
+
+        if config_loaded {
+            port_channels
+                .boot_milestones
+                .reached(rradio_messages::BootMilestone::ConfigLoaded);
+        }
+
+        let mut port_tasks: Vec<NamedTask> = Vec::new();
+        let mut tasks: Vec<NamedTask> = Vec::new();
+
+        if ports.tcp_text {
+            port_tasks.push((
+                "tcp_text",
+                tracing::error_span!("tcp_text"),
+                Box::pin(crate::ports::tcp_text::run(
+                    port_channels.clone(),
+                    config.tcp_config.clone(),
+                )),
+            ));
+        }
+
+        if ports.tcp_binary {
+            port_tasks.push((
+                "tcp_binary",
+                tracing::error_span!("tcp_binary"),
+                Box::pin(crate::ports::tcp_binary::run(
+                    port_channels.clone(),
+                    config.tcp_config.clone(),
+                )),
+            ));
+        }
+
+        if ports.mpd {
+            port_tasks.push((
+                "mpd",
+                tracing::error_span!("mpd"),
+                Box::pin(crate::ports::mpd::run(
+                    port_channels.clone(),
+                    config.clone(),
+                )),
+            ));
+        }
+
+        if ports.hooks {
+            port_tasks.push((
+                "hooks",
+                tracing::error_span!("hooks"),
+                Box::pin(crate::ports::hooks::run(
+                    port_channels.clone(),
+                    config.hooks.clone(),
+                )),
+            ));
+        }
+
+        tasks.push((
+            "boot",
+            tracing::error_span!("boot"),
+            Box::pin(crate::boot::run(
+                task_channels.clone(),
+                config.clone(),
+                boot_record,
+            )),
+        ));
+
+        tasks.push((
+            "station_check",
+            tracing::error_span!("station_check"),
+            Box::pin(crate::station_check::run(
+                task_channels.clone(),
+                config.clone(),
+            )),
+        ));
+
+        tasks.push((
+            "station_sync",
+            tracing::error_span!("station_sync"),
+            Box::pin(crate::station_sync::run(
+                task_channels.clone(),
+                config.clone(),
+            )),
+        ));
+
+        tasks.push((
+            "podcast_sync",
+            tracing::error_span!("podcast_sync"),
+            Box::pin(crate::podcast_sync::run(
+                task_channels.clone(),
+                config.clone(),
+            )),
+        ));
+
+        if ports.signal_commands {
+            port_tasks.push((
+                "signal_commands",
+                tracing::error_span!("signal_commands"),
+                Box::pin(crate::signal_commands::run(
+                    port_channels.clone(),
+                    config.signal_commands_config.clone(),
+                )),
+            ));
+        }
+
+        #[cfg(feature = "lua-plugins")]
+        if ports.plugins {
+            port_tasks.push((
+                "plugins",
+                tracing::error_span!("plugins"),
+                Box::pin(crate::ports::plugins::run(
+                    port_channels.clone(),
+                    config.clone(),
+                )),
+            ));
+        }
+
+        #[cfg(feature = "web")]
+        if ports.web {
+            port_tasks.push((
+                "web",
+                tracing::error_span!("web"),
+                Box::pin(crate::ports::web::run(
+                    port_channels.clone(),
+                    config.clone(),
+                )),
+            ));
+        }
+
+        Ok(Rradio {
+            config,
+            port_channels,
+            shutdown_handle,
+            ports_shutdown_handle,
+            pipeline_task: Some(pipeline_task),
+            pipeline_finished_rx,
+            system_actions_rx,
+            port_tasks,
+            tasks,
+        })
+    }
+}
+
+/// rradio, which has been built but runs once [`Rradio::run`] is called
+pub struct Rradio {
+    config: Config,
+    port_channels: PortChannels,
+    shutdown_handle: task::ShutdownHandle,
+    ports_shutdown_handle: task::ShutdownHandle,
+    pipeline_task: Option<PipelineTask>,
+    pipeline_finished_rx: oneshot::Receiver<()>,
+    system_actions_rx: mpsc::Receiver<SystemAction>,
+    port_tasks: Vec<NamedTask>,
+    tasks: Vec<NamedTask>,
+}
+
+impl Rradio {
+    /// Sends commands to rradio. `source` labels the commands in the audit log, e.g. the name of the integration.
+    /// The commands aren't privileged unless [`CommandSender::with_privilege`] is used
+    #[must_use]
+    pub fn commands(&self, source: impl Into<ArcStr>) -> CommandSender {
+        self.port_channels.commands_tx.with_source(source)
+    }
+
+    /// The events which clients are sent, starting with [`Event::Hello`] and the entire player state, followed by changes to the player state and other events.
+    /// Slow consumers skip events rather than holding up rradio, and the stream ends when rradio shuts down
+    #[must_use]
+    pub fn events(&self) -> impl Stream<Item = Event> {
+        self.port_channels
+            .event_stream(None)
+            .map(|event| event.event().clone())
+    }
+
+    /// The pipeline task, so that the binary can run it on a dedicated thread. If it isn't taken, [`Rradio::run`] spawns it
+    pub(crate) fn take_pipeline_task(&mut self) -> Option<PipelineTask> {
+        self.pipeline_task.take()
+    }
+
+    /// Run rradio until `stop` resolves or a system action, such as rebooting, is requested. rradio then shuts down,
+    /// giving the pipeline and the background tasks, and then the ports, the configured grace periods to finish, e.g. so that the playback position is saved.
+    /// Must be called within a tokio runtime with timers and IO enabled.
+    ///
+    /// Returns the requested system action, if any, which the caller can perform with [`SystemAction::perform`]
+    pub async fn run(mut self, stop: impl Future<Output = ()>) -> Option<SystemAction> {
+        if let Some(pipeline_task) = self.pipeline_task.take() {
+            tokio::spawn(pipeline_task);
+        }
+
+        let wait_group = task::WaitGroup::new();
+
+        for (name, span, task) in self.tasks {
+            wait_group.spawn_task(name, span, task);
+        }
+
+        // The pipeline task saves the playback position and unmounts stations when it finishes
+        let pipeline_finished_rx = self.pipeline_finished_rx;
+        wait_group.spawn_task("pipeline", tracing::error_span!("pipeline"), async move {
+            pipeline_finished_rx.await.ok();
+            Ok(())
+        });
+
+        let ports_wait_group = task::WaitGroup::new();
+
+        for (name, span, task) in self.port_tasks {
+            ports_wait_group.spawn_task(name, span, task);
+        }
+
+        let system_action = tokio::select! {
+            () = stop => None,
+            Some(system_action) = self.system_actions_rx.recv() => Some(system_action),
+        };
+
+        // Signal that tasks other than the ports should shut down
+        self.shutdown_handle.signal_shutdown();
+
+        // Wait (with grace periods) for tasks to shut down, and for the pipeline to save the playback position
+        let shutdown_config = self.config.shutdown_config;
+
+        let timed_out_tasks = wait_group
+            .wait_with_grace_periods(|task| shutdown_config.grace_period(task))
+            .await;
+
+        if !timed_out_tasks.is_empty() {
+            tracing::warn!(
+                tasks = ?timed_out_tasks,
+                "Not all tasks shutdown within time limit"
+            );
+
+            // The ports are still running, so connected clients are told which tasks are being aborted
+            self.port_channels
+                .events_tx
+                .send(crate::ports::SharedEvent::new(Event::ShutdownTimedOut(
+                    timed_out_tasks.into_iter().map(Into::into).collect(),
+                )))
+                .ok();
+        }
+
+        self.ports_shutdown_handle.signal_shutdown();
+
+        let timed_out_ports = ports_wait_group
+            .wait_with_grace_periods(|task| shutdown_config.grace_period(task))
+            .await;
+
+        if !timed_out_ports.is_empty() {
+            tracing::warn!(
+                ports = ?timed_out_ports,
+                "Not all ports shutdown within time limit"
+            );
+        }
+
+        system_action
+    }
+}
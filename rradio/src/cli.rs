@@ -0,0 +1,268 @@
+//! The `rradio` binary: parsing command line arguments, setting up logging, falling back to safe mode if the config file is unusable, and running rradio until it's terminated
+
+use anyhow::{Context, Result};
+use tracing_subscriber::prelude::*;
+
+use crate::{clock, config, keyboard_commands, logging, redact, safe_mode, self_test, Builder};
+
+#[cfg(feature = "drop-privileges")]
+use crate::privileges;
+#[cfg(feature = "thread-priority")]
+use crate::thread_priority;
+
+/// Run rradio as configured by the command line arguments
+///
+/// # Errors
+///
+/// Will return `Err` if the arguments are invalid, rradio fails to start, or a requested power off or reboot fails
+pub fn main() -> Result<()> {
+    clock::start();
+
+    let (log_filter_reload_handle, log_outputs_reload_handle) = setup_logging();
+
+    let mut config_path = String::from(option_env!("RRADIO_CONFIG_PATH").unwrap_or("config.toml"));
+
+    let mut self_test = false;
+
+    let mut args = std::env::args().skip(1);
+
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "-c" | "--config" => {
+                config_path = args.next().context("No config specified")?;
+            }
+            "--self-test" => self_test = true,
+            "-V" | "--version" => {
+                println!("{} v{}", env!("CARGO_PKG_NAME"), env!("CARGO_PKG_VERSION"));
+                println!("rradio-messages v{}", rradio_messages::VERSION);
+                return Ok(());
+            }
+            _ => return Err(anyhow::Error::msg(format!("Unhandled argument {arg:?}"))),
+        }
+    }
+
+    let mut boot_record = safe_mode::BootRecord::new(&config_path);
+
+    // The self-test doesn't count as a startup
+    let safe_mode::Startup {
+        config,
+        config_loaded,
+        safe_mode,
+    } = if self_test {
+        safe_mode::Startup::from_file(&config_path)
+    } else {
+        boot_record.start()
+    };
+
+    // Redact logs before they're sent to the configured outputs
+    redact::set_rules(config.url_redactions.clone());
+
+    log_filter_reload_handle
+        .reload(config.log_level.clone().filter) // Filter logs as specified by config
+        .context("Failed to reload logger filter")?;
+
+    log_outputs_reload_handle
+        .reload(logging::outputs(&config.log_targets)) // Send logs to the outputs specified by config
+        .context("Failed to reload log outputs")?;
+
+    tracing::debug!(target: concat!(module_path!(), "::config"), "{config:?}");
+
+    if self_test {
+        return run_self_test(config);
+    }
+
+    let runtime_config = config.runtime_config.clone();
+
+    let mut rradio = Builder::from_startup(
+        safe_mode::Startup {
+            config: config.clone(),
+            config_loaded,
+            safe_mode,
+        },
+        boot_record.clone(),
+    )
+    .build()?;
+
+    // Privileged resources such as raw sockets have now been acquired
+    #[cfg(feature = "drop-privileges")]
+    privileges::drop_privileges(&config.privileges_config).context("Failed to drop privileges")?;
+
+    let keyboard_commands_task = keyboard_commands::run(rradio.commands("keyboard"), config);
+
+    let pipeline_task = rradio
+        .take_pipeline_task()
+        .context("Pipeline task already taken")?;
+
+    // Setup the async runtime
+    let runtime = match runtime_config.flavor {
+        config::runtime::Flavor::CurrentThread => {
+            let runtime = tokio::runtime::Builder::new_current_thread()
+                .enable_all()
+                .build()?;
+
+            // The pipeline task is spawned outside of the wait group, as it signals that it has finished, which the wait group waits for
+            if runtime_config.has_dedicated_pipeline_thread() {
+                spawn_pipeline_thread(pipeline_task, &runtime_config)?;
+            } else {
+                runtime.spawn(pipeline_task);
+            }
+
+            runtime
+        }
+        config::runtime::Flavor::MultiThread => {
+            // As above, the pipeline task signals that it has finished
+            spawn_pipeline_thread(pipeline_task, &runtime_config)?;
+
+            let mut builder = tokio::runtime::Builder::new_multi_thread();
+
+            if let Some(worker_threads) = runtime_config.worker_threads {
+                builder.worker_threads(worker_threads);
+            }
+
+            builder.enable_all().build()?
+        }
+    };
+
+    // Run until the keyboard task finishes, i.e. when "Q" is pressed, until rradio is terminated, or until a system action is requested
+    let system_action = runtime.block_on(rradio.run(async {
+        tokio::select! {
+            () = keyboard_commands_task => (),
+            () = terminate_signal() => tracing::info!("Terminated"),
+        }
+    }));
+
+    // rradio shut down cleanly, so this wasn't a failed startup
+    boot_record.reset_boot_attempts();
+
+    // The pipeline has stopped, mounted stations have been unmounted, and the playback position has been saved
+    if let Some(system_action) = system_action {
+        system_action.perform()?;
+    }
+
+    Ok(())
+}
+
+/// Run the pipeline task on a dedicated thread so that commands and gstreamer messages are handled promptly even if other tasks are busy
+#[cfg_attr(not(feature = "thread-priority"), allow(unused_variables))]
+fn spawn_pipeline_thread(
+    pipeline_task: impl std::future::Future<Output = ()> + Send + 'static,
+    runtime_config: &config::runtime::Config,
+) -> Result<()> {
+    let mut pipeline_runtime = tokio::runtime::Builder::new_current_thread();
+    pipeline_runtime.enable_all();
+
+    #[cfg(feature = "thread-priority")]
+    let priority = runtime_config.pipeline_thread_priority;
+
+    // Stations are loaded on the runtime's blocking threads, which are spawned by the pipeline thread, so would otherwise inherit its real-time priority,
+    // and a real-time thread which is busy loading a large station can starve every other thread and process on a single core device
+    #[cfg(feature = "thread-priority")]
+    if let Some(config::runtime::Priority::Fifo(_)) = priority {
+        pipeline_runtime.on_thread_start(thread_priority::reset_current_thread);
+    }
+
+    let pipeline_runtime = pipeline_runtime.build()?;
+
+    std::thread::Builder::new()
+        .name(String::from("pipeline"))
+        .spawn(move || {
+            #[cfg(feature = "thread-priority")]
+            if let Some(priority) = priority {
+                match thread_priority::set_current_thread(priority) {
+                    Ok(()) => tracing::info!(?priority, "Set pipeline thread priority"),
+                    Err(err) => {
+                        tracing::error!(?priority, "Failed to set pipeline thread priority: {err}")
+                    }
+                }
+            }
+
+            pipeline_runtime.block_on(pipeline_task);
+        })
+        .context("Failed to spawn pipeline thread")?;
+
+    Ok(())
+}
+
+/// Run the self-test without starting the player or ports, print the result as JSON, and fail if any check failed
+fn run_self_test(config: config::Config) -> Result<()> {
+    gstreamer::init()?;
+
+    let result = tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()?
+        .block_on(self_test::run(config));
+
+    println!("{}", serde_json::to_string_pretty(&result)?);
+
+    if result.passed() {
+        Ok(())
+    } else {
+        Err(anyhow::Error::msg("Self-test failed"))
+    }
+}
+
+/// Resolves when rradio receives SIGTERM, e.g. when the system is shutting down
+#[cfg(unix)]
+async fn terminate_signal() {
+    match tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate()) {
+        Ok(mut signal) => {
+            signal.recv().await;
+        }
+        Err(err) => {
+            tracing::error!("Failed to listen for SIGTERM: {err}");
+            std::future::pending().await
+        }
+    }
+}
+
+#[cfg(not(unix))]
+async fn terminate_signal() {
+    std::future::pending().await
+}
+
+fn setup_logging() -> (
+    tracing_subscriber::reload::Handle<
+        tracing_subscriber::filter::Targets,
+        tracing_subscriber::Registry,
+    >,
+    tracing_subscriber::reload::Handle<logging::Outputs, logging::FilteredRegistry>,
+) {
+    let (log_filter, filter_reload_handle) =
+        tracing_subscriber::reload::Layer::new(config::LogLevelFilter::default().filter);
+
+    let (log_outputs, outputs_reload_handle) =
+        tracing_subscriber::reload::Layer::new(logging::Outputs::new());
+
+    tracing_subscriber::registry() // Register logging
+        .with(log_filter) // Only output some of the logs
+        .with(log_outputs) // Send logs to the outputs specified by config ...
+        .with(
+            tracing_subscriber::fmt::Layer::default() // Write formatted logs ...
+                .with_writer(std::sync::Mutex::new(ForceCR(std::io::stderr()))), // .. and to stderr
+        )
+        .init();
+
+    (filter_reload_handle, outputs_reload_handle)
+}
+
+/// `ForceCR` is a wrapper around a [`std::io::Write`] which explicitly sends a "\r\n" as a newline, even if only a "\n" is written.
+/// This is useful because `stdout` is in "Raw" Mode.
+/// Track urls are also redacted, as configured by `url_redaction`
+struct ForceCR<W: std::io::Write>(W);
+
+impl<W: std::io::Write> std::io::Write for ForceCR<W> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        for segment in redact::log_message(buf).split(|&b| b == b'\n') {
+            if !segment.is_empty() {
+                self.0.write_all(segment)?;
+                self.0.write_all(b"\r\n")?;
+            }
+        }
+
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.0.flush()
+    }
+}
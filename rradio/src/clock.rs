@@ -0,0 +1,18 @@
+//! A monotonic clock, which unlike the wall clock doesn't jump when the system time is corrected, e.g. by NTP, so that events can be ordered and aged correctly
+
+use std::{
+    sync::OnceLock,
+    time::{Duration, Instant},
+};
+
+static START: OnceLock<Instant> = OnceLock::new();
+
+/// Start the clock. Called when rradio starts
+pub fn start() {
+    START.get_or_init(Instant::now);
+}
+
+/// How long ago rradio started
+pub fn since_start() -> Duration {
+    START.get_or_init(Instant::now).elapsed()
+}
@@ -4,7 +4,7 @@ use std::{collections::BTreeMap, fmt};
 
 use tokio::time::Duration;
 
-use rradio_messages::{arcstr, ArcStr};
+use rradio_messages::{arcstr, ArcStr, StationIndex};
 use tracing_subscriber::filter::Targets;
 
 #[derive(Clone)]
@@ -65,6 +65,1050 @@ impl Default for LogLevelFilter {
     }
 }
 
+/// A password or other secret in the config file. It's hidden when debug formatted, so that it isn't logged when the config is logged
+#[derive(Clone, serde::Deserialize)]
+#[serde(transparent)]
+pub struct Secret(pub ArcStr);
+
+impl fmt::Debug for Secret {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("Secret(..)")
+    }
+}
+
+/// The token which authorises system commands. It can't be empty, as an empty token would authorise clients which send an empty token by mistake
+#[derive(Clone, Debug, serde::Deserialize)]
+#[serde(try_from = "String")]
+pub struct SystemCommandToken(Secret);
+
+impl std::convert::TryFrom<String> for SystemCommandToken {
+    type Error = &'static str;
+
+    fn try_from(token: String) -> Result<Self, Self::Error> {
+        if token.trim().is_empty() {
+            Err("The system command token can't be empty")
+        } else {
+            Ok(Self(Secret(token.into())))
+        }
+    }
+}
+
+impl SystemCommandToken {
+    #[must_use]
+    pub fn as_str(&self) -> &str {
+        &self.0 .0
+    }
+}
+
+/// A timezone, either `"local"`, `"utc"`, or a fixed offset such as `"+01:00"`
+#[derive(Clone, Copy, Debug, Default, serde::Deserialize)]
+#[serde(try_from = "String")]
+pub enum Timezone {
+    #[default]
+    Local,
+    Utc,
+    Fixed(chrono::FixedOffset),
+}
+
+impl std::convert::TryFrom<String> for Timezone {
+    type Error = String;
+
+    fn try_from(timezone: String) -> Result<Self, Self::Error> {
+        Ok(match timezone.as_str() {
+            "local" => Self::Local,
+            "utc" | "UTC" => Self::Utc,
+            offset => Self::Fixed(
+                offset
+                    .parse()
+                    .map_err(|err| format!("Bad timezone {timezone:?}: {err}"))?,
+            ),
+        })
+    }
+}
+
+impl Timezone {
+    #[must_use]
+    pub fn now(self) -> chrono::DateTime<chrono::FixedOffset> {
+        match self {
+            Self::Local => chrono::Local::now().fixed_offset(),
+            Self::Utc => chrono::Utc::now().fixed_offset(),
+            Self::Fixed(offset) => chrono::Utc::now().with_timezone(&offset),
+        }
+    }
+}
+
+pub mod log_target {
+    /// Where logs are sent, in addition to stderr
+    #[derive(Clone, Debug, serde::Deserialize)]
+    #[serde(tag = "type", rename_all = "snake_case")]
+    pub enum Output {
+        #[cfg(feature = "journald")]
+        Journald,
+        /// A syslog server, which receives RFC 5424 messages over UDP
+        Syslog { address: String },
+    }
+
+    #[derive(Clone, Debug, serde::Deserialize)]
+    pub struct Target {
+        #[serde(flatten)]
+        pub output: Output,
+        /// Which logs are sent to this target. Logs are first filtered by `log_level`
+        #[serde(default)]
+        pub level: super::LogLevelFilter,
+    }
+}
+
+pub mod pipeline {
+    use std::collections::BTreeMap;
+
+    use rradio_messages::{arcstr, ArcStr};
+
+    /// The gstreamer element which plays tracks
+    #[derive(Clone, Copy, Debug, Default, serde::Deserialize)]
+    #[serde(rename_all = "snake_case")]
+    pub enum Backend {
+        #[default]
+        Playbin,
+        Playbin3,
+    }
+
+    /// Fixed caps for audio output, for sound cards which only accept specific formats, or which click when the format changes
+    #[derive(Clone, Debug, Default, serde::Deserialize)]
+    #[serde(default)]
+    pub struct OutputCaps {
+        /// The sample rate in Hz, e.g. 48000
+        pub rate: Option<u32>,
+        /// The sample format, e.g. "S32LE"
+        pub format: Option<ArcStr>,
+        pub channels: Option<u32>,
+    }
+
+    impl OutputCaps {
+        /// The caps as a gstreamer caps description, e.g. "audio/x-raw,rate=48000,format=S32LE", or `None` if no caps are fixed
+        fn description(&self) -> Option<String> {
+            let fields = vec![
+                self.rate.map(|rate| format!("rate={rate}")),
+                self.format
+                    .as_ref()
+                    .map(|format| format!("format={format}")),
+                self.channels.map(|channels| format!("channels={channels}")),
+            ]
+            .into_iter()
+            .flatten()
+            .collect::<Vec<_>>();
+
+            if fields.is_empty() {
+                None
+            } else {
+                Some(format!("audio/x-raw,{}", fields.join(",")))
+            }
+        }
+    }
+
+    /// Playbin flags to set and unset, by nick, e.g. "video" or "vis"
+    #[derive(Clone, Debug, Default, serde::Deserialize)]
+    #[serde(default)]
+    pub struct FlagOverrides {
+        pub set: Vec<ArcStr>,
+        pub unset: Vec<ArcStr>,
+    }
+
+    #[derive(Clone, Debug, serde::Deserialize)]
+    #[serde(default)]
+    pub struct Config {
+        pub backend: Backend,
+        /// A gstreamer pipeline description of the audio sink, e.g. "audioconvert ! alsasink device=hw:1"
+        pub audio_sink: Option<ArcStr>,
+        pub output_caps: OutputCaps,
+        /// Changes to the playbin's default flags
+        pub flags: FlagOverrides,
+        /// Further changes to the flags when a station is selected, by station index, e.g. to keep video for TV streams on HDMI builds
+        pub station_flags: BTreeMap<ArcStr, FlagOverrides>,
+        /// A fixed gain in dB, applied after the user volume, so that different speakers and amplifiers can be calibrated to the same loudness at the same volume
+        pub output_trim: f64,
+        /// If true, the next track is queued while the current track is still playing, so that there's no gap between tracks
+        pub gapless: bool,
+        /// Stations, by station index, which pause between tracks even if `gapless` is true
+        pub gapless_disabled_stations: Vec<ArcStr>,
+    }
+
+    impl Default for Config {
+        fn default() -> Self {
+            Self {
+                backend: Backend::default(),
+                audio_sink: None,
+                output_caps: OutputCaps::default(),
+                flags: FlagOverrides {
+                    set: Vec::new(),
+                    unset: vec![arcstr::literal!("text"), arcstr::literal!("video")],
+                },
+                station_flags: BTreeMap::new(),
+                output_trim: 0.0,
+                gapless: true,
+                gapless_disabled_stations: Vec::new(),
+            }
+        }
+    }
+
+    impl Config {
+        /// Whether tracks of the station with the given index are played without a gap
+        #[must_use]
+        pub fn is_gapless(&self, station_index: Option<&str>) -> bool {
+            self.gapless
+                && !station_index.is_some_and(|station_index| {
+                    self.gapless_disabled_stations
+                        .iter()
+                        .any(|disabled_station| disabled_station.as_str() == station_index)
+                })
+        }
+
+        /// A gstreamer pipeline description of the audio sink, which applies the output trim, and converts and resamples audio to the output caps if any are fixed
+        #[must_use]
+        pub fn audio_sink_description(&self) -> String {
+            self.output_sink_description(
+                self.audio_sink.as_deref().unwrap_or("autoaudiosink"),
+                None,
+            )
+        }
+
+        /// As [`Self::audio_sink_description`], but with the audio sink and output trim of a selected audio output.
+        /// If `output_trim` is `None`, the output trim of the pipeline is used
+        #[must_use]
+        pub fn output_sink_description(
+            &self,
+            audio_sink: &str,
+            output_trim: Option<f64>,
+        ) -> String {
+            let output_trim = output_trim.unwrap_or(self.output_trim);
+
+            // The trim is a fixed gain, so the user volume of the playbin is unchanged
+            let trim = if output_trim == 0.0 {
+                String::new()
+            } else {
+                format!(
+                    "audioconvert ! volume volume={} ! ",
+                    10_f64.powf(output_trim / 20.0)
+                )
+            };
+
+            match self.output_caps.description() {
+                Some(caps) => {
+                    format!("{trim}audioconvert ! audioresample ! {caps} ! {audio_sink}")
+                }
+                None => format!("{trim}{audio_sink}"),
+            }
+        }
+    }
+}
+
+pub mod runtime {
+    /// How the async runtime schedules tasks
+    #[derive(Clone, Copy, Debug, Default, serde::Deserialize)]
+    #[serde(rename_all = "snake_case")]
+    pub enum Flavor {
+        /// All tasks run on the main thread
+        #[default]
+        CurrentThread,
+        /// The pipeline controller runs on a dedicated thread, and all other tasks run on a pool of worker threads
+        MultiThread,
+    }
+
+    /// The scheduling priority of the pipeline thread
+    #[cfg(feature = "thread-priority")]
+    #[derive(Clone, Copy, Debug, serde::Deserialize)]
+    #[serde(rename_all = "snake_case")]
+    pub enum Priority {
+        /// Real-time first-in first-out scheduling, with a priority from 1 (lowest) to 99 (highest)
+        Fifo(i32),
+        /// Normal scheduling, with a nice value from -20 (highest priority) to 19 (lowest priority)
+        Nice(i32),
+    }
+
+    #[derive(Clone, Debug, Default, serde::Deserialize)]
+    #[serde(default)]
+    pub struct Config {
+        pub flavor: Flavor,
+        /// The number of worker threads used by a multi-threaded runtime. If not set, there is one per CPU core
+        pub worker_threads: Option<usize>,
+        /// If true, the pipeline controller runs on a dedicated thread even if the runtime is single-threaded. A multi-threaded runtime always uses a dedicated thread
+        pub dedicated_pipeline_thread: bool,
+        /// The scheduling priority of the dedicated pipeline thread
+        #[cfg(feature = "thread-priority")]
+        pub pipeline_thread_priority: Option<Priority>,
+    }
+
+    impl Config {
+        #[must_use]
+        pub fn has_dedicated_pipeline_thread(&self) -> bool {
+            matches!(self.flavor, Flavor::MultiThread) || self.dedicated_pipeline_thread
+        }
+    }
+}
+
+pub mod shutdown {
+    use std::{collections::BTreeMap, time::Duration};
+
+    use rradio_messages::ArcStr;
+
+    /// How long tasks are given to stop when rradio shuts down
+    #[derive(Clone, Debug, serde::Deserialize)]
+    #[serde(default)]
+    pub struct Config {
+        /// How long each task is given to stop, unless it has a grace period
+        #[serde(with = "humantime_serde")]
+        pub timeout: Duration,
+        /// How long particular tasks are given to stop, keyed by task name, e.g. `pipeline`, which unmounts USB drives when it stops
+        pub grace_periods: BTreeMap<ArcStr, humantime_serde::Serde<Duration>>,
+    }
+
+    impl Config {
+        #[must_use]
+        pub fn grace_period(&self, task: &str) -> Duration {
+            self.grace_periods
+                .get(task)
+                .map_or(self.timeout, |grace_period| **grace_period)
+        }
+    }
+
+    impl Default for Config {
+        fn default() -> Self {
+            Self {
+                timeout: Duration::from_secs(5),
+                grace_periods: BTreeMap::new(),
+            }
+        }
+    }
+}
+
+pub mod fade {
+    use std::time::Duration;
+
+    #[derive(Clone, Debug, serde::Deserialize)]
+    #[serde(default)]
+    pub struct Config {
+        /// How long the volume takes to fade out when the sleep timer expires
+        #[serde(with = "humantime_serde")]
+        pub sleep_fade_out: Duration,
+        /// How long the volume takes to fade in when playback starts automatically, such as for an alarm
+        #[serde(with = "humantime_serde")]
+        pub wake_fade_in: Duration,
+    }
+
+    impl Default for Config {
+        fn default() -> Self {
+            Self {
+                sleep_fade_out: Duration::from_secs(30),
+                wake_fade_in: Duration::from_secs(30),
+            }
+        }
+    }
+}
+
+pub mod tcp {
+    use std::{net::IpAddr, time::Duration};
+
+    /// Which IP versions the TCP ports and the web server accept connections over
+    #[derive(Clone, Copy, Debug, Default, PartialEq, Eq, serde::Deserialize)]
+    #[serde(rename_all = "snake_case")]
+    pub enum AddressFamily {
+        Ipv4,
+        Ipv6,
+        /// Both IPv4 and IPv6, falling back to IPv4 if IPv6 is disabled
+        #[default]
+        DualStack,
+    }
+
+    /// How connections to the TCP ports and the web port are accepted. The rate is limited so that clients reconnecting after a restart don't overwhelm the server
+    #[derive(Clone, Debug, serde::Deserialize)]
+    #[serde(default)]
+    pub struct Config {
+        /// Also used by the web server
+        pub address_family: AddressFamily,
+        /// The minimum time between accepting connections on each port, including the web port
+        #[serde(with = "humantime_serde")]
+        pub accept_interval: Duration,
+        /// The suggested delay before reconnecting which is sent to clients when connections are infrequent
+        #[serde(with = "humantime_serde")]
+        pub minimum_backoff: Duration,
+        /// The largest suggested delay before reconnecting
+        #[serde(with = "humantime_serde")]
+        pub maximum_backoff: Duration,
+        /// The minimum time between events sent to each connection to the text port, e.g. for displays on slow serial links. Player state changes in between are combined
+        #[serde(with = "humantime_serde")]
+        pub text_port_event_interval: Option<Duration>,
+        /// As `text_port_event_interval`, but for the binary port
+        #[serde(with = "humantime_serde")]
+        pub binary_port_event_interval: Option<Duration>,
+        /// Clients connecting to the TCP ports, the MPD port or the web server from these addresses are privileged, so can lock the station
+        pub privileged_addresses: Vec<IpAddr>,
+    }
+
+    impl Config {
+        /// Whether a client connecting from `address` is privileged
+        #[must_use]
+        pub fn is_privileged(&self, address: IpAddr) -> bool {
+            self.privileged_addresses.contains(&address)
+        }
+    }
+
+    impl Default for Config {
+        fn default() -> Self {
+            Self {
+                address_family: AddressFamily::default(),
+                accept_interval: Duration::from_millis(100),
+                minimum_backoff: Duration::from_secs(1),
+                maximum_backoff: Duration::from_secs(30),
+                text_port_event_interval: None,
+                binary_port_event_interval: None,
+                privileged_addresses: Vec::new(),
+            }
+        }
+    }
+}
+
+pub mod mpd {
+    /// A port which speaks a subset of the Music Player Daemon protocol, so that MPD clients can control rradio
+    #[derive(Clone, Debug, serde::Deserialize)]
+    #[serde(default)]
+    pub struct Config {
+        pub port: u16,
+        /// The maximum number of bytes of the commands in a command list. Clients which send longer command lists are disconnected
+        pub max_command_list_size: usize,
+    }
+
+    impl Default for Config {
+        fn default() -> Self {
+            Self {
+                port: 6600,
+                max_command_list_size: 2048 * 1024,
+            }
+        }
+    }
+}
+
+pub mod dns {
+    use std::time::Duration;
+
+    /// How DNS lookups, used by the pinger and reachability checks, are cached
+    #[derive(Clone, Debug, serde::Deserialize)]
+    #[serde(default)]
+    pub struct Config {
+        /// The number of lookups which are cached. Lookups expire according to their TTL
+        pub cache_size: usize,
+        /// How long to wait before retrying a failed lookup. This doubles with each consecutive failure
+        #[serde(with = "humantime_serde")]
+        pub minimum_backoff: Duration,
+        /// The largest wait before retrying a failed lookup
+        #[serde(with = "humantime_serde")]
+        pub maximum_backoff: Duration,
+    }
+
+    impl Default for Config {
+        fn default() -> Self {
+            Self {
+                cache_size: 32,
+                minimum_backoff: Duration::from_secs(1),
+                maximum_backoff: Duration::from_secs(60),
+            }
+        }
+    }
+}
+
+pub mod yt_dlp {
+    use std::time::Duration;
+
+    use rradio_messages::{arcstr, ArcStr};
+
+    /// Resolving pages such as `YouTube` videos into audio streams using yt-dlp
+    #[derive(Clone, Debug, serde::Deserialize)]
+    #[serde(default)]
+    pub struct Config {
+        /// The path of yt-dlp. If not set, yt-dlp isn't used
+        pub path: Option<ArcStr>,
+        /// Tracks on these hosts, or their subdomains, are resolved
+        pub hosts: Vec<ArcStr>,
+        /// The yt-dlp format, which should select a single stream containing audio
+        pub format: ArcStr,
+        /// How long resolved urls are reused for. Stream urls expire, e.g. after a few hours for `YouTube`
+        #[serde(with = "humantime_serde")]
+        pub cache_duration: Duration,
+        /// How long to wait for yt-dlp before giving up
+        #[serde(with = "humantime_serde")]
+        pub timeout: Duration,
+    }
+
+    impl Default for Config {
+        fn default() -> Self {
+            Self {
+                path: None,
+                hosts: vec![
+                    arcstr::literal!("youtube.com"),
+                    arcstr::literal!("youtu.be"),
+                    arcstr::literal!("bandcamp.com"),
+                    arcstr::literal!("soundcloud.com"),
+                ],
+                format: arcstr::literal!("bestaudio/best"),
+                cache_duration: Duration::from_secs(60 * 60),
+                timeout: Duration::from_secs(30),
+            }
+        }
+    }
+}
+
+pub mod update {
+    use rradio_messages::ArcStr;
+
+    /// Where releases are found, and how they are verified
+    #[derive(Clone, Debug, Default, serde::Deserialize)]
+    #[serde(default)]
+    pub struct Config {
+        /// The url of the JSON release manifest. If not set, updates are disabled
+        pub manifest_url: Option<ArcStr>,
+        /// The base64 encoded Ed25519 public key which release manifests must be signed with
+        pub public_key: Option<ArcStr>,
+    }
+}
+
+pub mod audio_output {
+    use std::time::Duration;
+
+    use rradio_messages::ArcStr;
+
+    /// An audio output which can be selected, such as the speakers or the headphones
+    #[derive(Clone, Debug, serde::Deserialize)]
+    pub struct Output {
+        pub name: ArcStr,
+        /// A gstreamer pipeline description of the audio sink, e.g. "alsasink device=hw:1"
+        pub audio_sink: ArcStr,
+        /// If set, the volume is reduced to this when this output is selected, e.g. so that headphones aren't too loud
+        pub maximum_volume: Option<i32>,
+        /// If set, replaces `pipeline.output_trim` while this output is selected
+        pub output_trim: Option<f64>,
+    }
+
+    /// Selects an audio output when headphones are plugged in or unplugged, using e.g. the value of a headphone detect GPIO
+    #[derive(Clone, Debug, serde::Deserialize)]
+    pub struct HeadphoneDetect {
+        /// A file which contains "1" while headphones are plugged in, e.g. "/sys/class/gpio/gpio17/value"
+        pub path: ArcStr,
+        /// If true, the file contains "0" while headphones are plugged in
+        #[serde(default)]
+        pub active_low: bool,
+        /// The output which is selected when headphones are plugged in
+        pub headphones: ArcStr,
+        /// The output which is selected when headphones are unplugged
+        pub speakers: ArcStr,
+        /// How often the file is read
+        #[serde(default = "default_poll_interval", with = "humantime_serde")]
+        pub poll_interval: Duration,
+    }
+
+    fn default_poll_interval() -> Duration {
+        Duration::from_millis(250)
+    }
+
+    /// The audio outputs which can be selected. If there are none, `pipeline.audio_sink` is used
+    #[derive(Clone, Debug, Default, serde::Deserialize)]
+    #[serde(default)]
+    pub struct Config {
+        /// The first output is selected on startup
+        pub outputs: Vec<Output>,
+        pub headphone_detect: Option<HeadphoneDetect>,
+    }
+}
+
+pub mod quiet_hours {
+    use std::convert::TryFrom;
+
+    /// A time of day, such as `"22:30"`
+    #[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, serde::Deserialize)]
+    #[serde(try_from = "String")]
+    pub struct TimeOfDay(pub chrono::NaiveTime);
+
+    impl TryFrom<String> for TimeOfDay {
+        type Error = String;
+
+        fn try_from(time: String) -> Result<Self, Self::Error> {
+            time.split_once(':')
+                .and_then(|(hours, minutes)| {
+                    chrono::NaiveTime::from_hms_opt(hours.parse().ok()?, minutes.parse().ok()?, 0)
+                })
+                .map(Self)
+                .ok_or_else(|| format!("Bad time of day {time:?}, expected e.g. \"22:30\""))
+        }
+    }
+
+    /// A time of day during which the volume is limited, such as at night
+    #[derive(Clone, Debug, serde::Deserialize)]
+    pub struct Config {
+        pub start: TimeOfDay,
+        /// If `end` is before `start`, quiet hours continue past midnight
+        pub end: TimeOfDay,
+        pub maximum_volume: i32,
+        /// If true, the volume is reduced to `maximum_volume` when quiet hours start. Otherwise, only increases in volume are limited
+        #[serde(default)]
+        pub reduce_volume: bool,
+    }
+
+    impl Config {
+        /// True if `time` is during quiet hours
+        #[must_use]
+        pub fn contains(&self, time: chrono::NaiveTime) -> bool {
+            let Self { start, end, .. } = *self;
+
+            if start.0 <= end.0 {
+                start.0 <= time && time < end.0
+            } else {
+                start.0 <= time || time < end.0
+            }
+        }
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use std::convert::TryFrom;
+
+        use chrono::NaiveTime;
+
+        use super::{Config, TimeOfDay};
+
+        fn quiet_hours(start: &str, end: &str) -> Config {
+            Config {
+                start: TimeOfDay::try_from(String::from(start)).unwrap(),
+                end: TimeOfDay::try_from(String::from(end)).unwrap(),
+                maximum_volume: 50,
+                reduce_volume: false,
+            }
+        }
+
+        fn time(hour: u32, minute: u32) -> NaiveTime {
+            NaiveTime::from_hms_opt(hour, minute, 0).unwrap()
+        }
+
+        #[test]
+        fn quiet_hours_can_cross_midnight() {
+            let quiet_hours = quiet_hours("22:30", "07:00");
+
+            assert!(!quiet_hours.contains(time(22, 29)));
+            assert!(quiet_hours.contains(time(22, 30)));
+            assert!(quiet_hours.contains(time(23, 59)));
+            assert!(quiet_hours.contains(time(0, 0)));
+            assert!(quiet_hours.contains(time(6, 59)));
+            assert!(!quiet_hours.contains(time(7, 0)));
+            assert!(!quiet_hours.contains(time(12, 0)));
+        }
+
+        #[test]
+        fn quiet_hours_can_be_within_a_day() {
+            let quiet_hours = quiet_hours("13:00", "15:00");
+
+            assert!(!quiet_hours.contains(time(12, 59)));
+            assert!(quiet_hours.contains(time(13, 0)));
+            assert!(quiet_hours.contains(time(14, 59)));
+            assert!(!quiet_hours.contains(time(15, 0)));
+            assert!(!quiet_hours.contains(time(0, 0)));
+        }
+
+        #[test]
+        fn bad_times_of_day_are_rejected() {
+            for time in ["24:00", "12:60", "12", "noon"] {
+                assert!(TimeOfDay::try_from(String::from(time)).is_err(), "{}", time);
+            }
+        }
+    }
+}
+
+pub mod cork {
+    /// What rradio does when the audio server asks it to be quiet
+    #[derive(Clone, Copy, Debug, Default, serde::Deserialize)]
+    #[serde(rename_all = "snake_case")]
+    pub enum Action {
+        #[default]
+        Pause,
+        /// Reduce the volume to `duck_volume`
+        Duck,
+    }
+
+    /// Responding to the audio server, e.g. `PulseAudio` or `PipeWire` with `module-role-cork`, asking rradio to be quiet while another application, such as a voice call, plays audio
+    #[derive(Clone, Debug, serde::Deserialize)]
+    #[serde(default)]
+    pub struct Config {
+        pub action: Action,
+        /// The volume while ducked. If the volume is already lower, it isn't changed
+        pub duck_volume: i32,
+    }
+
+    impl Default for Config {
+        fn default() -> Self {
+            Self {
+                action: Action::default(),
+                duck_volume: 20,
+            }
+        }
+    }
+}
+
+pub mod adaptive_buffering {
+    use std::{num::NonZeroUsize, time::Duration};
+
+    /// Increasing the buffer duration of stations which frequently run out of buffered audio during playback
+    #[derive(Clone, Debug, serde::Deserialize)]
+    pub struct Config {
+        /// The buffer duration is increased when a station rebuffers this many times within `period`
+        #[serde(default = "default_rebuffer_count")]
+        pub rebuffer_count: NonZeroUsize,
+        #[serde(default = "default_period", with = "humantime_serde")]
+        pub period: Duration,
+        /// How much the buffer duration is increased by each time
+        #[serde(default = "default_increment", with = "humantime_serde")]
+        pub increment: Duration,
+        /// Buffer durations are never increased beyond this
+        #[serde(with = "humantime_serde")]
+        pub maximum_duration: Duration,
+    }
+
+    fn default_rebuffer_count() -> NonZeroUsize {
+        NonZeroUsize::new(3).unwrap()
+    }
+
+    fn default_period() -> Duration {
+        Duration::from_secs(10 * 60)
+    }
+
+    fn default_increment() -> Duration {
+        Duration::from_secs(2)
+    }
+}
+
+pub mod loudness_normalisation {
+    use std::time::Duration;
+
+    /// Learning the average loudness of each station, and applying a gain to each station to bring it towards a target loudness
+    #[derive(Clone, Debug, serde::Deserialize)]
+    pub struct Config {
+        /// The average RMS level which stations are brought towards, in dB relative to full scale
+        #[serde(default = "default_target_loudness")]
+        pub target_loudness: f64,
+        /// The largest gain, in dB, which is applied to make a station louder or quieter
+        #[serde(default = "default_maximum_gain")]
+        pub maximum_gain: f64,
+        /// Once a station has been measured for this long, older measurements gradually count for less
+        #[serde(default = "default_learning_period", with = "humantime_serde")]
+        pub learning_period: Duration,
+        /// No gain is applied to a station until it has been measured for at least this long
+        #[serde(default = "default_minimum_duration", with = "humantime_serde")]
+        pub minimum_duration: Duration,
+    }
+
+    fn default_target_loudness() -> f64 {
+        -20.0
+    }
+
+    fn default_maximum_gain() -> f64 {
+        6.0
+    }
+
+    fn default_learning_period() -> Duration {
+        Duration::from_secs(60 * 60)
+    }
+
+    fn default_minimum_duration() -> Duration {
+        Duration::from_secs(60)
+    }
+}
+
+pub mod title_loop {
+    use std::{num::NonZeroUsize, time::Duration};
+
+    use rradio_messages::StationIndex;
+
+    /// What to do when a stream plays the same title over and over
+    #[derive(Clone, Debug, Default, PartialEq, Eq, serde::Deserialize)]
+    #[serde(rename_all = "snake_case")]
+    pub enum Action {
+        #[default]
+        Log,
+        /// Show an error to users
+        Notify,
+        /// Play another station instead
+        SwitchTo(StationIndex),
+    }
+
+    /// Detecting streams which loop the same title, such as free streams which loop an advert break when region-blocked
+    #[derive(Clone, Debug, serde::Deserialize)]
+    pub struct Config {
+        /// A loop is detected when the stream changes to the same title this many times within `window`
+        #[serde(default = "default_repeat_count")]
+        pub repeat_count: NonZeroUsize,
+        #[serde(default = "default_window", with = "humantime_serde")]
+        pub window: Duration,
+        #[serde(default)]
+        pub action: Action,
+    }
+
+    fn default_repeat_count() -> NonZeroUsize {
+        NonZeroUsize::new(5).unwrap()
+    }
+
+    fn default_window() -> Duration {
+        Duration::from_secs(5 * 60)
+    }
+}
+
+pub mod station_check {
+    use std::time::Duration;
+
+    use super::quiet_hours::TimeOfDay;
+
+    /// Checking every night that the first track of each URL list station is reachable, so that users can see which stations are dead
+    #[derive(Clone, Debug, serde::Deserialize)]
+    pub struct Config {
+        /// When stations are checked each day, in the configured timezone
+        pub time: TimeOfDay,
+        /// How long to wait for each station to respond
+        #[serde(default = "default_timeout", with = "humantime_serde")]
+        pub timeout: Duration,
+    }
+
+    fn default_timeout() -> Duration {
+        Duration::from_secs(10)
+    }
+}
+
+pub mod station_sync {
+    use std::time::Duration;
+
+    use rradio_messages::ArcStr;
+
+    /// Periodically replacing the stations directory with a centrally managed list of stations, so that several radios can share the same stations
+    #[derive(Clone, Debug, serde::Deserialize)]
+    pub struct Config {
+        /// The url of the JSON station manifest
+        pub manifest_url: ArcStr,
+        /// The base64 encoded Ed25519 public key which the manifest must be signed with. If not set, the manifest isn't signed
+        #[serde(default)]
+        pub public_key: Option<ArcStr>,
+        /// How often the manifest is fetched
+        #[serde(default = "default_interval", with = "humantime_serde")]
+        pub interval: Duration,
+    }
+
+    fn default_interval() -> Duration {
+        Duration::from_secs(60 * 60)
+    }
+}
+
+pub mod podcast_sync {
+    use std::time::Duration;
+
+    use rradio_messages::{arcstr, ArcStr};
+
+    /// The API which subscriptions and listening progress are synced with
+    #[derive(Clone, Copy, Debug, serde::Deserialize)]
+    #[serde(rename_all = "snake_case")]
+    pub enum Service {
+        /// [gpodder.net](https://gpodder.net/), or another server with the same API
+        Gpodder,
+        /// A Nextcloud server with the [GPodder Sync](https://apps.nextcloud.com/apps/gpoddersync) app
+        Nextcloud,
+    }
+
+    /// Syncing podcast subscriptions and listening progress with a podcast sync server, so that the podcasts followed on a phone are stations on the radio
+    #[derive(Clone, Debug, serde::Deserialize)]
+    pub struct Config {
+        pub service: Service,
+        /// The url of the server, e.g. `https://gpodder.net` or the url of the Nextcloud server
+        pub server_url: url::Url,
+        pub username: ArcStr,
+        pub password: super::Secret,
+        /// The name of the radio in the episode actions which it uploads
+        #[serde(default = "default_device_id")]
+        pub device_id: ArcStr,
+        /// How often subscriptions and progress are synced
+        #[serde(default = "default_interval", with = "humantime_serde")]
+        pub interval: Duration,
+        /// The number of episodes of each podcast, newest first, which are the tracks of its station
+        #[serde(default = "default_episode_limit")]
+        pub episode_limit: usize,
+    }
+
+    fn default_device_id() -> ArcStr {
+        arcstr::literal!("rradio")
+    }
+
+    fn default_interval() -> Duration {
+        Duration::from_secs(15 * 60)
+    }
+
+    fn default_episode_limit() -> usize {
+        20
+    }
+}
+
+pub mod search {
+    use std::{num::NonZeroUsize, time::Duration};
+
+    use rradio_messages::{arcstr, ArcStr};
+
+    /// A source which is searched by `Search` commands
+    #[derive(Clone, Debug, serde::Deserialize)]
+    #[serde(tag = "type", rename_all = "snake_case")]
+    pub enum Source {
+        /// A `UPnP` media server which supports the `ContentDirectory` `Search` action. Audio items whose title, artist or album match are found
+        #[serde(rename = "upnp")]
+        UPnP { root_description_url: url::Url },
+        /// The index of the albums on the USB stick, if `usb.index` is true. Albums whose artist or album directory match are found
+        #[cfg(feature = "usb")]
+        UsbIndex,
+        /// A [radio-browser.info](https://www.radio-browser.info/) server. Stations whose name matches are found
+        RadioBrowser {
+            #[serde(default = "default_radio_browser_url")]
+            server_url: ArcStr,
+        },
+        /// A podcast directory with an iTunes compatible search API. Podcasts are played as a playlist of their episodes
+        PodcastDirectory {
+            #[serde(default = "default_podcast_directory_url")]
+            search_url: ArcStr,
+        },
+    }
+
+    fn default_radio_browser_url() -> ArcStr {
+        arcstr::literal!("https://all.api.radio-browser.info")
+    }
+
+    fn default_podcast_directory_url() -> ArcStr {
+        arcstr::literal!("https://itunes.apple.com/search")
+    }
+
+    /// Searching several sources at once, so that users can find something to play which isn't one of the stations
+    #[derive(Clone, Debug, serde::Deserialize)]
+    pub struct Config {
+        #[serde(rename = "source")]
+        pub sources: Vec<Source>,
+        /// The maximum number of results from each source
+        #[serde(default = "default_result_limit")]
+        pub result_limit: NonZeroUsize,
+        /// How long each source is given to respond
+        #[serde(default = "default_timeout", with = "humantime_serde")]
+        pub timeout: Duration,
+    }
+
+    fn default_result_limit() -> NonZeroUsize {
+        NonZeroUsize::new(10).unwrap()
+    }
+
+    fn default_timeout() -> Duration {
+        Duration::from_secs(10)
+    }
+}
+
+pub mod signal_commands {
+    use rradio_messages::Command;
+
+    /// Commands which are sent when rradio receives a signal, e.g. `kill -USR1`, so that scripts can control playback without connecting to a port
+    #[derive(Clone, Debug, Default, serde::Deserialize)]
+    #[serde(default, rename_all = "SCREAMING_SNAKE_CASE")]
+    pub struct Config {
+        pub sigusr1: Option<Command>,
+        pub sigusr2: Option<Command>,
+    }
+}
+
+pub mod temporary_stations {
+    /// Playlists pushed by clients with `SetPlaylist`, which are kept as stations so that they can be listed and selected again
+    #[derive(Clone, Debug, serde::Deserialize)]
+    #[serde(default)]
+    pub struct Config {
+        /// If true, temporary stations are saved in the state directory, so that they're kept after a restart
+        pub persist: bool,
+        /// When a new temporary station would exceed this, the oldest temporary station is removed
+        pub maximum_count: usize,
+    }
+
+    impl Default for Config {
+        fn default() -> Self {
+            Self {
+                persist: false,
+                maximum_count: 10,
+            }
+        }
+    }
+}
+
+pub mod startup_rotation {
+    use rradio_messages::StationIndex;
+
+    /// Playing a different station each time rradio starts, rather than waiting for the user to choose one
+    #[derive(Clone, Debug, serde::Deserialize)]
+    pub struct Config {
+        /// Played in order, starting again from the first station after the last
+        pub stations: Vec<StationIndex>,
+        /// If true, alarms also play the next station of the rotation, rather than their own station
+        #[serde(default = "default_alarms")]
+        pub alarms: bool,
+    }
+
+    fn default_alarms() -> bool {
+        true
+    }
+}
+
+pub mod alarm {
+    use std::time::Duration;
+
+    use rradio_messages::StationIndex;
+
+    /// Playing a station every day at a time of day, fading in the volume over `fade.wake_fade_in`
+    #[derive(Clone, Debug, serde::Deserialize)]
+    pub struct Config {
+        /// In the timezone configured by `timezone`
+        pub time: super::quiet_hours::TimeOfDay,
+        pub station: StationIndex,
+        /// If set, the sleep timer is set when the alarm goes off, so that playback fades out and stops unless the sleep timer is cancelled
+        #[serde(default, with = "humantime_serde")]
+        pub stop_after: Option<Duration>,
+    }
+}
+
+pub mod idle_screen {
+    use std::time::Duration;
+
+    use rradio_messages::ArcStr;
+
+    /// Something which display clients show while rradio is idle
+    #[derive(Clone, Debug, PartialEq, Eq, serde::Deserialize)]
+    #[serde(rename_all = "snake_case")]
+    pub enum Page {
+        Clock,
+        IpAddress,
+        /// The contents of a file, such as the weather written by a script. Skipped if the file is missing or empty
+        File(ArcStr),
+    }
+
+    /// What display clients show when nothing has played for a while
+    #[derive(Clone, Debug, serde::Deserialize)]
+    #[serde(default)]
+    pub struct Config {
+        /// rradio is idle once nothing has played and no commands have been received for this long. If not set, rradio is never idle
+        #[serde(with = "humantime_serde")]
+        pub idle_time: Option<Duration>,
+        /// How long each page is shown before showing the next page
+        #[serde(with = "humantime_serde")]
+        pub page_duration: Duration,
+        pub pages: Vec<Page>,
+    }
+
+    impl Default for Config {
+        fn default() -> Self {
+            Self {
+                idle_time: Some(Duration::from_secs(5 * 60)),
+                page_duration: Duration::from_secs(10),
+                pages: vec![Page::Clock, Page::IpAddress],
+            }
+        }
+    }
+}
+
 #[cfg(feature = "cd")]
 pub mod cd {
     use rradio_messages::{arcstr, ArcStr};
@@ -86,6 +1130,26 @@ pub mod cd {
     }
 }
 
+#[cfg(feature = "mount")]
+pub mod mount {
+    /// How devices are mounted
+    #[derive(Clone, Copy, Debug, Default, PartialEq, Eq, serde::Deserialize)]
+    #[serde(rename_all = "snake_case")]
+    pub enum Backend {
+        /// Call `mount` directly, which requires root or `CAP_SYS_ADMIN`
+        #[default]
+        Direct,
+        /// Ask udisks2 to mount the device, which doesn't require root
+        Udisks2,
+    }
+
+    #[derive(Clone, Debug, Default, serde::Deserialize)]
+    #[serde(default)]
+    pub struct Config {
+        pub backend: Backend,
+    }
+}
+
 #[cfg(feature = "usb")]
 pub mod usb {
     use std::path::PathBuf;
@@ -98,6 +1162,10 @@ pub mod usb {
         pub station: ArcStr,
         pub device: ArcStr,
         pub path: PathBuf,
+        /// If true, the album directories on each stick are indexed the first time it's played, and saved in the state directory, so that later random album choices don't walk the stick
+        pub index: bool,
+        /// If true, albums chosen from the index favour albums which haven't been played recently
+        pub favour_least_recently_played: bool,
     }
 
     impl Default for Config {
@@ -106,6 +1174,65 @@ pub mod usb {
                 station: arcstr::literal!("01"),
                 device: arcstr::literal!("/dev/sda1"),
                 path: PathBuf::new(),
+                index: false,
+                favour_least_recently_played: false,
+            }
+        }
+    }
+}
+
+#[cfg(feature = "share")]
+pub mod share {
+    use std::path::PathBuf;
+
+    use rradio_messages::ArcStr;
+
+    /// A network share, such as a NAS, whose music can be played with `PlayPath`
+    #[derive(Clone, Debug, serde::Deserialize)]
+    pub struct Config {
+        /// The share, e.g. "//nas.local/music"
+        pub device: ArcStr,
+        /// The music directory within the share
+        #[serde(default)]
+        pub path: PathBuf,
+        pub username: ArcStr,
+        pub password: super::Secret,
+    }
+}
+
+#[cfg(feature = "sdr")]
+pub mod sdr {
+    use rradio_messages::{arcstr, ArcStr};
+
+    /// How FM and DAB stations are received with an RTL-SDR dongle. Each command is run without a shell, and its standard output is played
+    #[derive(Clone, Debug, serde::Deserialize)]
+    #[serde(default)]
+    pub struct Config {
+        /// The program and arguments which receive an FM station. `{frequency}` is replaced with the frequency in Hz
+        pub fm_command: Vec<ArcStr>,
+        /// The program and arguments which receive a DAB station. `{channel}` and `{service}` are replaced with the channel of the multiplex and the name of the service
+        pub dab_command: Vec<ArcStr>,
+    }
+
+    impl Default for Config {
+        fn default() -> Self {
+            // The station's values are passed to the shell as arguments, rather than substituted into the script, so that they aren't interpreted by the shell
+            Self {
+                fm_command: vec![
+                    arcstr::literal!("sh"),
+                    arcstr::literal!("-c"),
+                    arcstr::literal!("rtl_fm -f \"$1\" -M wbfm -s 200000 -r 48000 - | sox -t raw -r 48000 -e signed -b 16 -c 1 - -t wav -"),
+                    arcstr::literal!("sdr"),
+                    arcstr::literal!("{frequency}"),
+                ],
+                dab_command: vec![
+                    arcstr::literal!("sh"),
+                    arcstr::literal!("-c"),
+                    arcstr::literal!("eti-cmdline-rtlsdr -C \"$1\" | dablin -p -l \"$2\" | sox -t raw -r 48000 -e signed -b 16 -c 2 - -t wav -"),
+                    arcstr::literal!("sdr"),
+                    arcstr::literal!("{channel}"),
+                    arcstr::literal!("{service}"),
+                ],
             }
         }
     }
@@ -117,19 +1244,37 @@ pub mod ping {
 
     use rradio_messages::{arcstr, ArcStr};
 
+    /// How latency is measured
+    #[derive(Clone, Copy, Debug, Default, PartialEq, Eq, serde::Deserialize)]
+    #[serde(rename_all = "snake_case")]
+    pub enum Probe {
+        /// ICMP echo requests, using raw sockets or the `ping` command
+        #[default]
+        Icmp,
+        /// The time taken to open a TCP connection to the port of the stream
+        TcpConnect,
+        /// The time taken to receive the response to an HTTP HEAD request for the stream
+        HttpHead,
+    }
+
     #[derive(Clone, Debug, serde::Deserialize)]
     #[serde(default)]
     pub struct Config {
+        pub probe: Probe,
         pub remote_ping_count: usize,
         pub gateway_address: Ipv4Addr,
+        /// If `probe` isn't `icmp`, the gateway is probed by opening a TCP connection to this port
+        pub gateway_port: u16,
         pub initial_ping_address: ArcStr,
     }
 
     impl Default for Config {
         fn default() -> Self {
             Self {
+                probe: Probe::default(),
                 remote_ping_count: 30,
                 gateway_address: default_gateway(),
+                gateway_port: 53,
                 initial_ping_address: arcstr::literal!("8.8.8.8"),
             }
         }
@@ -160,22 +1305,409 @@ pub mod ping {
     }
 }
 
+#[cfg(feature = "drop-privileges")]
+pub mod privileges {
+    use rradio_messages::ArcStr;
+
+    #[derive(Clone, Debug, Default, serde::Deserialize)]
+    #[serde(default)]
+    pub struct Config {
+        /// The user to switch to after startup. If `None`, privileges are not dropped
+        pub user: Option<ArcStr>,
+    }
+}
+
+#[cfg(feature = "watchdog")]
+pub mod watchdog {
+    use std::time::Duration;
+
+    use rradio_messages::{arcstr, ArcStr};
+
+    /// Feeding a hardware watchdog while the pipeline is responsive
+    #[derive(Clone, Debug, serde::Deserialize)]
+    #[serde(default)]
+    pub struct Config {
+        /// The watchdog device, which is opened on startup
+        pub device: ArcStr,
+        /// How often the watchdog is fed. Must be shorter than the watchdog's own timeout, which is 15 seconds on a Raspberry Pi
+        #[serde(with = "humantime_serde")]
+        pub feed_interval: Duration,
+        /// How long the controller and the gstreamer bus may be unresponsive before the watchdog is no longer fed
+        #[serde(with = "humantime_serde")]
+        pub unresponsive_timeout: Duration,
+    }
+
+    impl Default for Config {
+        fn default() -> Self {
+            Self {
+                device: arcstr::literal!("/dev/watchdog"),
+                feed_interval: Duration::from_secs(5),
+                unresponsive_timeout: Duration::from_secs(60),
+            }
+        }
+    }
+}
+
 #[cfg(feature = "web")]
 pub mod web {
+    use std::time::Duration;
+
     use rradio_messages::{arcstr, ArcStr};
 
+    /// A field of the player state which the public status page shows
+    #[derive(Clone, Copy, Debug, PartialEq, Eq, serde::Deserialize)]
+    #[serde(rename_all = "snake_case")]
+    pub enum StatusField {
+        Title,
+        Artist,
+        Station,
+        Volume,
+    }
+
     #[derive(Clone, Debug, serde::Deserialize)]
     #[serde(default)]
     pub struct Config {
         pub web_app_path: ArcStr,
+        /// The minimum time between events sent to each websocket connection. Player state changes in between are combined
+        #[serde(with = "humantime_serde")]
+        pub event_interval: Option<Duration>,
+        /// The fields shown by the public status page. If empty, there is no public status page
+        pub public_status_fields: Vec<StatusField>,
+        /// If set, the web interface and the websocket API are served over HTTPS rather than HTTP, on the same port
+        pub tls: Option<Tls>,
+    }
+
+    /// The certificate and private key used to serve the web interface over HTTPS. Requires the `web-tls` feature
+    #[derive(Clone, Debug, serde::Deserialize)]
+    pub struct Tls {
+        /// A PEM file containing the certificate chain, starting with the server's certificate
+        pub certificate_path: ArcStr,
+        /// A PEM file containing the private key, in PKCS#8, PKCS#1 or SEC1 format
+        pub private_key_path: ArcStr,
     }
 
     impl Default for Config {
         fn default() -> Self {
             Self {
                 web_app_path: arcstr::literal!("web_app"),
+                event_interval: None,
+                public_status_fields: Vec::new(),
+                tls: None,
+            }
+        }
+    }
+}
+
+pub mod tag_filter {
+    use std::convert::TryFrom;
+
+    use rradio_messages::ArcStr;
+
+    /// A regular expression, using the syntax of the [`regex`](https://docs.rs/regex) crate
+    #[derive(Clone, Debug, serde::Deserialize)]
+    #[serde(try_from = "String")]
+    pub struct Pattern(pub regex::Regex);
+
+    impl TryFrom<String> for Pattern {
+        type Error = regex::Error;
+
+        fn try_from(pattern: String) -> Result<Self, Self::Error> {
+            regex::Regex::new(&pattern).map(Self)
+        }
+    }
+
+    /// A field of the track tags which can be filtered
+    #[derive(Clone, Copy, Debug, PartialEq, Eq, serde::Deserialize)]
+    #[serde(rename_all = "snake_case")]
+    pub enum Field {
+        Title,
+        Organisation,
+        Artist,
+        Album,
+        Genre,
+        Comment,
+    }
+
+    /// Replaces matches of a pattern in track tags, e.g. to remove adverts from titles
+    #[derive(Clone, Debug, serde::Deserialize)]
+    pub struct Filter {
+        pub fields: Vec<Field>,
+        pub pattern: Pattern,
+        /// What matches are replaced with, which may refer to capture groups, e.g. `"$1"`
+        #[serde(default)]
+        pub replacement: String,
+        /// The stations which the filter applies to. If empty, the filter applies to all stations
+        #[serde(default)]
+        pub stations: Vec<ArcStr>,
+    }
+}
+
+pub mod url_redaction {
+    fn default_replacement() -> String {
+        String::from("***")
+    }
+
+    /// Replaces matches of a pattern in track urls which are sent to clients or written to logs, e.g. to hide passwords or temporary mount paths
+    #[derive(Clone, Debug, serde::Deserialize)]
+    pub struct Rule {
+        pub pattern: super::tag_filter::Pattern,
+        /// What matches are replaced with, which may refer to capture groups, e.g. `"$1"`
+        #[serde(default = "default_replacement")]
+        pub replacement: String,
+    }
+}
+
+pub mod tag_encoding {
+    use std::{collections::BTreeMap, convert::TryFrom};
+
+    use rradio_messages::ArcStr;
+
+    /// A character encoding, such as `"windows-1251"` or `"gbk"`, named by one of its [labels](https://encoding.spec.whatwg.org/#names-and-labels)
+    #[derive(Clone, Copy, Debug, serde::Deserialize)]
+    #[serde(try_from = "String")]
+    pub struct Encoding(pub &'static encoding_rs::Encoding);
+
+    impl TryFrom<String> for Encoding {
+        type Error = String;
+
+        fn try_from(label: String) -> Result<Self, Self::Error> {
+            encoding_rs::Encoding::for_label(label.as_bytes())
+                .map(Self)
+                .ok_or_else(|| format!("Unknown encoding {label:?}"))
+        }
+    }
+
+    /// How tags which aren't UTF-8, such as the ID3 tags of old MP3s, are decoded
+    #[derive(Clone, Debug, Default, serde::Deserialize)]
+    #[serde(default)]
+    pub struct Config {
+        /// Detect the encoding of tags which aren't UTF-8
+        pub detect: bool,
+        /// The encoding of tags which aren't UTF-8, used rather than detecting the encoding
+        pub encoding: Option<Encoding>,
+        /// The encoding of tags of particular stations, keyed by station index, overriding `encoding`
+        pub stations: BTreeMap<ArcStr, Encoding>,
+    }
+}
+
+pub mod hook {
+    use std::time::Duration;
+
+    /// A change in the player state which triggers a hook
+    #[derive(Clone, Copy, Debug, PartialEq, Eq, serde::Deserialize)]
+    #[serde(rename_all = "snake_case")]
+    pub enum Event {
+        StationChanged,
+        TrackChanged,
+        Error,
+        VolumeChanged,
+    }
+
+    /// What a hook does when triggered
+    #[derive(Clone, Debug, serde::Deserialize)]
+    #[serde(untagged)]
+    pub enum Action {
+        /// Run a program. The event is passed in `RRADIO_EVENT` and the player state, as JSON, in `RRADIO_STATE`
+        Command {
+            command: String,
+            #[serde(default)]
+            args: Vec<String>,
+        },
+        /// POST the event and the player state, as JSON, to a url
+        Webhook { url: url::Url },
+    }
+
+    /// Runs a command or calls a webhook when the player state changes, e.g. to update an external display
+    #[derive(Clone, Debug, serde::Deserialize)]
+    pub struct Hook {
+        pub events: Vec<Event>,
+        #[serde(flatten)]
+        pub action: Action,
+        /// Commands and webhooks which take longer than this are abandoned
+        #[serde(default = "default_timeout", with = "humantime_serde")]
+        pub timeout: Duration,
+        /// The hook is triggered at most once per interval. Changes during the interval are combined into a single trigger per event at the end of the interval
+        #[serde(default = "default_minimum_interval", with = "humantime_serde")]
+        pub minimum_interval: Duration,
+    }
+
+    fn default_timeout() -> Duration {
+        Duration::from_secs(10)
+    }
+
+    fn default_minimum_interval() -> Duration {
+        Duration::from_secs(1)
+    }
+}
+
+#[cfg(feature = "lua-plugins")]
+pub mod plugins {
+    use rradio_messages::{arcstr, ArcStr};
+
+    #[derive(Clone, Debug, serde::Deserialize)]
+    #[serde(default)]
+    pub struct Config {
+        /// The directory containing `.lua` plugins
+        pub directory: ArcStr,
+        /// The maximum number of bytes of memory used by each plugin
+        pub memory_limit: usize,
+        /// The maximum number of Lua instructions run while loading each plugin, and by each call of a plugin function
+        pub instruction_limit: u64,
+    }
+
+    impl Default for Config {
+        fn default() -> Self {
+            Self {
+                directory: arcstr::literal!("plugins"),
+                memory_limit: 16 * 1024 * 1024,
+                instruction_limit: 10_000_000,
+            }
+        }
+    }
+}
+
+pub mod wake_on_lan {
+    use std::{
+        convert::TryFrom,
+        fmt,
+        net::{Ipv4Addr, SocketAddr},
+        time::Duration,
+    };
+
+    use rradio_messages::ArcStr;
+
+    #[derive(Clone, Copy, Debug, PartialEq, Eq, serde::Deserialize)]
+    #[serde(try_from = "String")]
+    pub struct MacAddress(pub [u8; 6]);
+
+    impl TryFrom<String> for MacAddress {
+        type Error = String;
+
+        fn try_from(address: String) -> Result<Self, Self::Error> {
+            let mut bytes = [0; 6];
+            let mut sections = address.split([':', '-']);
+
+            for byte in &mut bytes {
+                *byte = sections
+                    .next()
+                    .and_then(|section| u8::from_str_radix(section, 16).ok())
+                    .ok_or_else(|| format!("Bad MAC address {address:?}"))?;
+            }
+
+            if sections.next().is_some() {
+                return Err(format!("Bad MAC address {address:?}"));
+            }
+
+            Ok(Self(bytes))
+        }
+    }
+
+    impl fmt::Display for MacAddress {
+        fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            for (index, byte) in self.0.iter().enumerate() {
+                if index > 0 {
+                    f.write_str(":")?;
+                }
+
+                write!(f, "{byte:02x}")?;
             }
+
+            Ok(())
+        }
+    }
+
+    /// A server which hosts stations, and which might be asleep
+    #[derive(Clone, Debug, serde::Deserialize)]
+    pub struct Target {
+        /// The host name or address of the server, as it appears in station urls
+        pub host: ArcStr,
+        pub mac_address: MacAddress,
+        /// Where to send the Wake-on-LAN packet
+        #[serde(default = "default_broadcast_address")]
+        pub broadcast_address: SocketAddr,
+        /// How long to wait for the server to wake up
+        #[serde(default = "default_wake_timeout", with = "humantime_serde")]
+        pub wake_timeout: Duration,
+    }
+
+    fn default_broadcast_address() -> SocketAddr {
+        (Ipv4Addr::BROADCAST, 9).into()
+    }
+
+    fn default_wake_timeout() -> Duration {
+        Duration::from_secs(90)
+    }
+}
+
+/// The volume a notification is played at
+#[derive(Clone, Copy, Debug, Default)]
+pub enum NotificationVolume {
+    /// The current volume
+    #[default]
+    Current,
+    Fixed(i32),
+    /// An offset from the current volume
+    Relative(i32),
+}
+
+impl NotificationVolume {
+    #[must_use]
+    pub fn apply(self, current_volume: i32) -> i32 {
+        match self {
+            Self::Current => current_volume,
+            Self::Fixed(volume) => volume,
+            Self::Relative(offset) => current_volume.saturating_add(offset),
         }
+        .clamp(rradio_messages::VOLUME_MIN, rradio_messages::VOLUME_MAX)
+    }
+}
+
+/// A notification sound. Either a url, or a table with a `url` and optionally either a fixed `volume` or a `volume_offset` from the current volume
+#[derive(Clone, Debug, serde::Deserialize)]
+#[serde(try_from = "NotificationDescription")]
+pub struct Notification {
+    pub url: ArcStr,
+    pub volume: NotificationVolume,
+}
+
+#[derive(serde::Deserialize)]
+#[serde(untagged)]
+enum NotificationDescription {
+    Url(ArcStr),
+    WithVolume {
+        url: ArcStr,
+        volume: Option<i32>,
+        volume_offset: Option<i32>,
+    },
+}
+
+impl std::convert::TryFrom<NotificationDescription> for Notification {
+    type Error = String;
+
+    fn try_from(description: NotificationDescription) -> Result<Self, Self::Error> {
+        Ok(match description {
+            NotificationDescription::Url(url) => Self {
+                url,
+                volume: NotificationVolume::Current,
+            },
+            NotificationDescription::WithVolume {
+                url,
+                volume,
+                volume_offset,
+            } => Self {
+                volume: match (volume, volume_offset) {
+                    (None, None) => NotificationVolume::Current,
+                    (Some(volume), None) => NotificationVolume::Fixed(volume),
+                    (None, Some(offset)) => NotificationVolume::Relative(offset),
+                    (Some(_), Some(_)) => {
+                        return Err(format!(
+                            "Notification {url:?} has both a volume and a volume_offset"
+                        ))
+                    }
+                },
+                url,
+            },
+        })
     }
 }
 
@@ -183,10 +1715,34 @@ pub mod web {
 #[derive(Clone, Debug, Default, serde::Deserialize)]
 #[serde(default)]
 pub struct Notifications {
-    pub ready: Option<ArcStr>,
-    pub playlist_prefix: Option<ArcStr>,
-    pub playlist_suffix: Option<ArcStr>,
-    pub error: Option<ArcStr>,
+    pub ready: Option<Notification>,
+    pub playlist_prefix: Option<Notification>,
+    pub playlist_suffix: Option<Notification>,
+    pub error: Option<Notification>,
+    /// Played when the config file has been loaded
+    pub config_loaded: Option<Notification>,
+    /// Played when there is a route to the internet
+    pub network_up: Option<Notification>,
+    /// Played when all ports are accepting connections
+    pub ports_listening: Option<Notification>,
+    /// Played when every station has loaded successfully
+    pub stations_validated: Option<Notification>,
+    /// Played over the current track when a volume command is ignored because the volume is at its minimum or maximum, so is always preloaded
+    pub volume_limit: Option<Notification>,
+    /// If true, the `ready`, `error` and boot milestone notifications are loaded into memory at startup, and played using a separate pipeline
+    pub preload: bool,
+}
+
+/// An announcement or jingle played between the tracks of a station which isn't a stream, e.g. to recreate the feel of a real radio station, or to announce the time
+#[derive(Clone, Debug, serde::Deserialize)]
+pub struct StationIdent {
+    /// Played using the notification pipeline, so must be a `file://` url, which is loaded into memory at startup
+    pub notification: Notification,
+    /// Play the ident once this many tracks have finished
+    pub every_tracks: Option<std::num::NonZeroUsize>,
+    /// Play the ident when a track finishes once this long has passed since the station started or the ident was last played
+    #[serde(default, with = "humantime_serde")]
+    pub every: Option<Duration>,
 }
 
 /// A description of the rradio configuration file
@@ -196,6 +1752,18 @@ pub struct Config {
     /// Where to find stations
     pub stations_directory: ArcStr,
 
+    /// Where to store state which persists between restarts, such as settings
+    pub state_directory: ArcStr,
+
+    /// The name which clients use to label this device. Defaults to the hostname
+    pub device_name: Option<ArcStr>,
+
+    /// How many received commands are kept in the audit log. If zero, commands are not recorded
+    pub audit_log_length: usize,
+
+    /// If set, `SystemPowerOff`, `SystemReboot`, `CheckForUpdate` and `ApplyUpdate` commands with this token are allowed
+    pub system_command_token: Option<SystemCommandToken>,
+
     /// The timeout when entering two digit station indices
     #[serde(with = "humantime_serde")]
     pub input_timeout: Duration,
@@ -209,6 +1777,19 @@ pub struct Config {
     #[serde(with = "humantime_serde")]
     pub buffering_duration: Option<Duration>,
 
+    /// If set, stations which frequently rebuffer are given longer buffers than `buffering_duration`
+    pub adaptive_buffering: Option<adaptive_buffering::Config>,
+
+    /// If set, a gain learned from the average loudness of each station is applied to it
+    pub loudness_normalisation: Option<loudness_normalisation::Config>,
+
+    /// If set, streams which play the same title over and over are detected
+    pub title_loop: Option<title_loop::Config>,
+
+    /// If set, check that the first track of a URL list station is reachable before playing it, failing if the check takes longer than this
+    #[serde(with = "humantime_serde")]
+    pub reachability_check_timeout: Option<Duration>,
+
     #[serde(with = "humantime_serde")]
     pub pause_before_playing_increment: Duration,
 
@@ -218,36 +1799,174 @@ pub struct Config {
     #[serde(with = "humantime_serde")]
     pub smart_goto_previous_track_duration: Duration,
 
+    /// Rapid `NextItem` and `PreviousItem` commands are coalesced, so only the final track is loaded once no track change has been requested for this long
+    #[serde(with = "humantime_serde")]
+    pub track_change_debounce: Duration,
+
     pub maximum_error_recovery_attempts: usize,
 
     #[serde(with = "humantime_serde")]
     pub error_recovery_attempt_count_reset_time: Option<Duration>,
 
+    /// The timezone used when substituting the date and time into station urls, for quiet hours, and for scheduling station checks
+    pub timezone: Timezone,
+
+    /// If set, the latest error is cleared after this duration
+    #[serde(with = "humantime_serde")]
+    pub latest_error_clear_time: Option<Duration>,
+
+    /// If set, tracks which fail more than this many times are skipped for the rest of the session
+    pub maximum_track_failures: Option<usize>,
+
+    /// If true, the station and track position are saved on shutdown, and playback resumes from the same place on startup
+    pub resume_on_start: bool,
+
     pub log_level: LogLevelFilter,
 
+    /// Where logs are sent, in addition to stderr
+    #[serde(rename = "log_target")]
+    pub log_targets: Vec<log_target::Target>,
+
+    /// If true, every gstreamer bus message is logged at debug level, with the target `rradio_core::pipeline::controller::bus_trace`
+    pub bus_trace: bool,
+
     /// Notification sounds
     #[serde(rename = "Notifications")]
     pub notifications: Notifications,
 
+    /// Idents played between tracks, keyed by station index
+    pub station_idents: BTreeMap<ArcStr, StationIdent>,
+
+    /// Aliases which can be used instead of station indices, e.g. `jazz = "12"`. Station titles can also be used
+    pub station_aliases: BTreeMap<ArcStr, StationIndex>,
+
+    #[serde(rename = "pipeline")]
+    pub pipeline_config: pipeline::Config,
+
+    #[serde(rename = "runtime")]
+    pub runtime_config: runtime::Config,
+
+    #[serde(rename = "fade")]
+    pub fade_config: fade::Config,
+
+    #[serde(rename = "tcp")]
+    pub tcp_config: tcp::Config,
+
+    /// If set, rradio listens for MPD clients
+    pub mpd: Option<mpd::Config>,
+
+    #[serde(rename = "dns")]
+    pub dns_config: dns::Config,
+
+    #[serde(rename = "yt_dlp")]
+    pub yt_dlp_config: yt_dlp::Config,
+
+    #[serde(rename = "update")]
+    pub update_config: update::Config,
+
+    #[serde(rename = "idle_screen")]
+    pub idle_screen_config: idle_screen::Config,
+
+    #[serde(rename = "audio_output")]
+    pub audio_output_config: audio_output::Config,
+
+    /// If set, the volume is limited during quiet hours
+    pub quiet_hours: Option<quiet_hours::Config>,
+
+    /// If set, rradio pauses or ducks when the audio server asks it to be quiet
+    pub cork: Option<cork::Config>,
+
+    /// If set, stations are checked every day, and the results are shown in the station list
+    pub station_check: Option<station_check::Config>,
+
+    /// If set, the next station of the rotation is played on startup, unless a saved position is resumed
+    pub startup_rotation: Option<startup_rotation::Config>,
+
+    /// If set, the stations directory is periodically synced from a station manifest
+    pub station_sync: Option<station_sync::Config>,
+
+    /// If set, podcast subscriptions and listening progress are synced with gpodder.net or a Nextcloud server
+    pub podcast_sync: Option<podcast_sync::Config>,
+
+    /// If set, clients can search these sources using `Search` commands
+    pub search: Option<search::Config>,
+
+    #[serde(rename = "signal_commands")]
+    pub signal_commands_config: signal_commands::Config,
+
+    #[serde(rename = "temporary_stations")]
+    pub temporary_stations_config: temporary_stations::Config,
+
+    #[serde(rename = "shutdown")]
+    pub shutdown_config: shutdown::Config,
+
+    /// Filters applied to track tags before they are sent to clients
+    #[serde(rename = "tag_filter")]
+    pub tag_filters: Vec<tag_filter::Filter>,
+
+    /// Rules which redact track urls in events and logs. The pipeline still plays the real urls
+    #[serde(rename = "url_redaction")]
+    pub url_redactions: Vec<url_redaction::Rule>,
+
+    #[serde(rename = "tag_encoding")]
+    pub tag_encoding_config: tag_encoding::Config,
+
+    /// Commands and webhooks which are triggered by changes to the player state
+    #[serde(rename = "hook")]
+    pub hooks: Vec<hook::Hook>,
+
+    /// Servers which are woken up before loading stations which they host
+    #[serde(rename = "wake_on_lan")]
+    pub wake_on_lan_targets: Vec<wake_on_lan::Target>,
+
+    /// Alarms which are always set, in addition to those set by `SetAlarm` commands
+    #[serde(rename = "alarm")]
+    pub alarms: Vec<alarm::Config>,
+
     #[cfg(feature = "cd")]
     #[serde(rename = "CD")]
     pub cd_config: cd::Config,
 
+    #[cfg(feature = "mount")]
+    #[serde(rename = "mount")]
+    pub mount_config: mount::Config,
+
     #[cfg(feature = "usb")]
     #[serde(rename = "USB")]
     pub usb_config: usb::Config,
 
+    #[cfg(feature = "share")]
+    #[serde(rename = "share")]
+    pub share_config: Option<share::Config>,
+
+    #[cfg(feature = "sdr")]
+    #[serde(rename = "sdr")]
+    pub sdr_config: sdr::Config,
+
     #[cfg(feature = "ping")]
     #[serde(rename = "ping")]
     pub ping_config: ping::Config,
 
+    #[cfg(feature = "lua-plugins")]
+    #[serde(rename = "plugins")]
+    pub plugins_config: plugins::Config,
+
+    #[cfg(feature = "drop-privileges")]
+    #[serde(rename = "privileges")]
+    pub privileges_config: privileges::Config,
+
+    /// If set, a hardware watchdog is fed while the pipeline is responsive, so that the device reboots if it stops responding
+    #[cfg(feature = "watchdog")]
+    pub watchdog: Option<watchdog::Config>,
+
     #[cfg(feature = "web")]
     #[serde(rename = "web")]
     pub web_config: web::Config,
 }
 
 impl Config {
-    pub fn from_file(path: impl AsRef<std::path::Path> + Copy) -> Self {
+    /// Load the config file, returning None if it can't be read or parsed, in which case the error is logged
+    pub fn from_file(path: impl AsRef<std::path::Path> + Copy) -> Option<Self> {
         std::fs::read_to_string(path)
             .map_err(|err| {
                 tracing::error!(
@@ -265,7 +1984,7 @@ impl Config {
                     );
                 })
             })
-            .unwrap_or_default()
+            .ok()
     }
 }
 
@@ -273,23 +1992,78 @@ impl Default for Config {
     fn default() -> Self {
         Self {
             stations_directory: arcstr::literal!("stations"),
+            state_directory: arcstr::literal!("state"),
+            device_name: None,
+            audit_log_length: 1000,
+            system_command_token: None,
             input_timeout: Duration::from_millis(2000),
             initial_volume: 70,
             volume_offset: 5,
             buffering_duration: None,
+            adaptive_buffering: None,
+            loudness_normalisation: None,
+            title_loop: None,
+            reachability_check_timeout: None,
             pause_before_playing_increment: Duration::from_secs(1),
             max_pause_before_playing: Duration::from_secs(5),
             smart_goto_previous_track_duration: Duration::from_secs(2),
+            track_change_debounce: Duration::from_millis(300),
             maximum_error_recovery_attempts: 5,
             error_recovery_attempt_count_reset_time: Some(Duration::from_secs(30)),
+            timezone: Timezone::default(),
+            latest_error_clear_time: None,
+            maximum_track_failures: None,
+            resume_on_start: false,
             log_level: LogLevelFilter::default(),
+            log_targets: Vec::new(),
+            bus_trace: false,
             notifications: Notifications::default(),
+            station_idents: BTreeMap::new(),
+            station_aliases: BTreeMap::new(),
+            pipeline_config: pipeline::Config::default(),
+            runtime_config: runtime::Config::default(),
+            fade_config: fade::Config::default(),
+            tcp_config: tcp::Config::default(),
+            mpd: None,
+            dns_config: dns::Config::default(),
+            yt_dlp_config: yt_dlp::Config::default(),
+            update_config: update::Config::default(),
+            idle_screen_config: idle_screen::Config::default(),
+            audio_output_config: audio_output::Config::default(),
+            quiet_hours: None,
+            cork: None,
+            station_check: None,
+            startup_rotation: None,
+            station_sync: None,
+            podcast_sync: None,
+            search: None,
+            signal_commands_config: signal_commands::Config::default(),
+            temporary_stations_config: temporary_stations::Config::default(),
+            shutdown_config: shutdown::Config::default(),
+            tag_filters: Vec::new(),
+            url_redactions: Vec::new(),
+            tag_encoding_config: tag_encoding::Config::default(),
+            hooks: Vec::new(),
+            wake_on_lan_targets: Vec::new(),
+            alarms: Vec::new(),
             #[cfg(feature = "cd")]
             cd_config: cd::Config::default(),
+            #[cfg(feature = "mount")]
+            mount_config: mount::Config::default(),
             #[cfg(feature = "usb")]
             usb_config: usb::Config::default(),
+            #[cfg(feature = "share")]
+            share_config: None,
+            #[cfg(feature = "sdr")]
+            sdr_config: sdr::Config::default(),
             #[cfg(feature = "ping")]
             ping_config: ping::Config::default(),
+            #[cfg(feature = "lua-plugins")]
+            plugins_config: plugins::Config::default(),
+            #[cfg(feature = "drop-privileges")]
+            privileges_config: privileges::Config::default(),
+            #[cfg(feature = "watchdog")]
+            watchdog: None,
             #[cfg(feature = "web")]
             web_config: web::Config::default(),
         }
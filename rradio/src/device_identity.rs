@@ -0,0 +1,78 @@
+//! A persistent identity for the device, which is sent to clients when they connect, so that clients connected to several devices can tell them apart
+
+use std::path::Path;
+
+use anyhow::{Context, Result};
+
+use rradio_messages::{ArcStr, DeviceIdentity};
+
+const DEVICE_ID_FILE_NAME: &str = "device_id";
+
+/// Load the device id from the state directory, or generate and save a new id if there isn't one
+fn load_or_create_id(state_directory: impl AsRef<Path>) -> Result<ArcStr> {
+    let path = state_directory.as_ref().join(DEVICE_ID_FILE_NAME);
+
+    match std::fs::read_to_string(&path) {
+        Ok(id) if !id.trim().is_empty() => return Ok(id.trim().into()),
+        Ok(_) => tracing::warn!("{} is empty, generating a new device id", path.display()),
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => {
+            tracing::info!("Generating a new device id");
+        }
+        Err(err) => {
+            return Err(err).with_context(|| format!("Failed to read {}", path.display()));
+        }
+    }
+
+    let id = format!("{:032x}", rand::random::<u128>());
+
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent)
+            .with_context(|| format!("Failed to create {}", parent.display()))?;
+    }
+
+    std::fs::write(&path, &id).with_context(|| format!("Failed to write {}", path.display()))?;
+
+    Ok(id.into())
+}
+
+/// The configured device name, or the hostname if there isn't one
+fn device_name(config: &crate::config::Config) -> ArcStr {
+    if let Some(name) = &config.device_name {
+        return name.clone();
+    }
+
+    std::fs::read_to_string("/etc/hostname")
+        .ok()
+        .map(|hostname| hostname.trim().to_owned())
+        .filter(|hostname| !hostname.is_empty())
+        .map_or(rradio_messages::arcstr::literal!("rradio"), ArcStr::from)
+}
+
+pub fn load(config: &crate::config::Config) -> DeviceIdentity {
+    let id = load_or_create_id(config.state_directory.as_str()).unwrap_or_else(|err| {
+        // Clients can still use the name to identify the device
+        tracing::error!("Failed to load device id: {err:#}");
+        ArcStr::new()
+    });
+
+    DeviceIdentity {
+        id,
+        name: device_name(config),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn id_persists() {
+        let state_directory = tempfile::tempdir().unwrap();
+
+        let id = super::load_or_create_id(state_directory.path()).unwrap();
+
+        assert_eq!(id.len(), 32);
+        assert_eq!(
+            super::load_or_create_id(state_directory.path()).unwrap(),
+            id
+        );
+    }
+}
@@ -0,0 +1,179 @@
+//! An asynchronous DNS resolver which is shared by the pinger and station reachability checks.
+//!
+//! Successful lookups are cached until their TTL expires. Failed lookups aren't retried until a backoff has passed,
+//! which doubles with each consecutive failure, so that flaky DNS doesn't cause a lookup on every ping sequence.
+//! If the resolver fails, the host is looked up using the system's resolver, so that hosts in `/etc/hosts` or found using mDNS still resolve.
+
+use std::{
+    collections::HashMap,
+    net::IpAddr,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use hickory_resolver::{
+    config::{ResolverConfig, ResolverOpts},
+    TokioAsyncResolver,
+};
+use rradio_messages::{arcstr, ArcStr};
+
+/// The maximum number of hosts whose failures are remembered, so that looking up many hosts which don't resolve doesn't use unbounded memory
+const MAXIMUM_FAILURE_COUNT: usize = 256;
+
+#[derive(Debug, Clone, thiserror::Error)]
+pub enum LookupError {
+    #[error("{0}")]
+    Failed(ArcStr),
+    #[error("{error} (not retrying for {}s)", retry_in.as_secs())]
+    BackingOff { error: ArcStr, retry_in: Duration },
+}
+
+struct Failure {
+    error: ArcStr,
+    backoff: Duration,
+    retry_at: Instant,
+}
+
+#[derive(Clone)]
+pub struct Resolver {
+    resolver: TokioAsyncResolver,
+    config: crate::config::dns::Config,
+    failures: Arc<Mutex<HashMap<String, Failure>>>,
+}
+
+impl Resolver {
+    /// Use the system's DNS configuration, or fall back to the default configuration if it can't be read
+    pub fn new(config: crate::config::dns::Config) -> Self {
+        let (resolver_config, mut options) = hickory_resolver::system_conf::read_system_conf()
+            .unwrap_or_else(|err| {
+                tracing::warn!("Failed to read system DNS configuration, using defaults: {err}");
+                (ResolverConfig::default(), ResolverOpts::default())
+            });
+
+        options.cache_size = config.cache_size;
+
+        Self {
+            resolver: TokioAsyncResolver::tokio(resolver_config, options),
+            config,
+            failures: Arc::default(),
+        }
+    }
+
+    fn failures(&self) -> std::sync::MutexGuard<HashMap<String, Failure>> {
+        // The map is only modified by simple insertions and removals, so is valid even if another thread panicked
+        self.failures
+            .lock()
+            .unwrap_or_else(std::sync::PoisonError::into_inner)
+    }
+
+    /// The addresses of `host`, which may be a domain name, or an IP address, as found in the host of a url, e.g. `[::1]`
+    pub async fn lookup(&self, host: &str) -> Result<Vec<IpAddr>, LookupError> {
+        if let Ok(address) = host.trim_start_matches('[').trim_end_matches(']').parse() {
+            return Ok(vec![address]);
+        }
+
+        if let Some(failure) = self.failures().get(host) {
+            let now = Instant::now();
+
+            if now < failure.retry_at {
+                return Err(LookupError::BackingOff {
+                    error: failure.error.clone(),
+                    retry_in: failure.retry_at - now,
+                });
+            }
+        }
+
+        let lookup = match self.resolver.lookup_ip(host).await {
+            Ok(lookup) => Ok(lookup.iter().collect::<Vec<_>>()),
+            Err(err) => {
+                tracing::debug!(host, "Resolver failed, using system resolver: {err}");
+
+                match tokio::net::lookup_host((host, 0)).await {
+                    Ok(addresses) => Ok(addresses.map(|address| address.ip()).collect()),
+                    Err(system_err) => Err(arcstr::format!("{err}; {system_err}")),
+                }
+            }
+        };
+
+        match lookup {
+            Ok(addresses) => {
+                self.failures().remove(host);
+
+                tracing::trace!(host, ?addresses, "Resolved");
+
+                Ok(addresses)
+            }
+            Err(error) => {
+                let mut failures = self.failures();
+
+                let backoff = failures
+                    .get(host)
+                    .map_or(self.config.minimum_backoff, |failure| {
+                        (2 * failure.backoff).min(self.config.maximum_backoff)
+                    });
+
+                tracing::debug!(host, ?backoff, "Failed to resolve: {error}");
+
+                if failures.len() >= MAXIMUM_FAILURE_COUNT && !failures.contains_key(host) {
+                    forget_oldest_failure(&mut failures);
+                }
+
+                failures.insert(
+                    host.into(),
+                    Failure {
+                        error: error.clone(),
+                        backoff,
+                        retry_at: Instant::now() + backoff,
+                    },
+                );
+
+                Err(LookupError::Failed(error))
+            }
+        }
+    }
+}
+
+/// Forget the failure which can be retried soonest, which is usually the least recent
+fn forget_oldest_failure(failures: &mut HashMap<String, Failure>) {
+    if let Some(host) = failures
+        .iter()
+        .min_by_key(|(_, failure)| failure.retry_at)
+        .map(|(host, _)| host.clone())
+    {
+        failures.remove(&host);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        collections::HashMap,
+        time::{Duration, Instant},
+    };
+
+    use super::Failure;
+
+    #[test]
+    fn the_failure_retried_soonest_is_forgotten() {
+        let now = Instant::now();
+
+        let mut failures = [("later", 20), ("soonest", 5), ("latest", 60)]
+            .iter()
+            .map(|&(host, seconds)| {
+                (
+                    String::from(host),
+                    Failure {
+                        error: rradio_messages::arcstr::literal!("Failed"),
+                        backoff: Duration::from_secs(seconds),
+                        retry_at: now + Duration::from_secs(seconds),
+                    },
+                )
+            })
+            .collect::<HashMap<_, _>>();
+
+        super::forget_oldest_failure(&mut failures);
+
+        assert!(!failures.contains_key("soonest"));
+        assert_eq!(failures.len(), 2);
+    }
+}
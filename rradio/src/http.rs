@@ -0,0 +1,40 @@
+//! Fetching documents over HTTP, such as station manifests, search results and podcast feeds, without reading more than a given number of bytes
+
+use anyhow::{Context, Result};
+
+/// Fetch `url`, failing if the response is longer than `maximum_length` bytes, so that a misbehaving server can't exhaust memory
+pub async fn fetch(client: &reqwest::Client, url: &str, maximum_length: usize) -> Result<Vec<u8>> {
+    send(client.get(url), maximum_length)
+        .await
+        .with_context(|| format!("Failed to fetch {url}"))
+}
+
+/// Send `request`, such as an authenticated request to a sync server, failing if the response is longer than `maximum_length` bytes
+pub async fn send(request: reqwest::RequestBuilder, maximum_length: usize) -> Result<Vec<u8>> {
+    let mut response = request
+        .send()
+        .await
+        .and_then(reqwest::Response::error_for_status)?;
+
+    let too_long = || anyhow::anyhow!("The response is longer than {maximum_length} bytes");
+
+    // The content length is only a hint, and isn't always sent, so the body is also checked as it's read
+    if response
+        .content_length()
+        .is_some_and(|content_length| content_length > maximum_length as u64)
+    {
+        return Err(too_long());
+    }
+
+    let mut body = Vec::new();
+
+    while let Some(chunk) = response.chunk().await? {
+        if body.len() + chunk.len() > maximum_length {
+            return Err(too_long());
+        }
+
+        body.extend_from_slice(&chunk);
+    }
+
+    Ok(body)
+}
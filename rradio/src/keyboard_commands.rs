@@ -4,11 +4,14 @@
 use anyhow::Result;
 use crossterm::event::{Event, EventStream, KeyCode, KeyEvent};
 use futures_util::StreamExt;
-use tokio::{sync::mpsc, time};
+use tokio::time;
 
 use rradio_messages::{Command, StationIndex};
 
-use crate::task::FailableFuture;
+use crate::{
+    ports::{CommandSendError, CommandSender},
+    task::FailableFuture,
+};
 
 /// `RawMode` is an RAII guard for the raw mode of stdin (and stdout).
 ///
@@ -50,7 +53,7 @@ impl std::ops::Drop for RawMode {
 }
 
 /// Process keyboard input and send parsed commands through channel `commands`
-pub async fn run(commands_tx: mpsc::UnboundedSender<Command>, config: crate::config::Config) {
+pub async fn run(commands_tx: CommandSender, config: crate::config::Config) {
     async move {
         let mut raw_mode = RawMode::new()?;
 
@@ -128,7 +131,10 @@ pub async fn run(commands_tx: mpsc::UnboundedSender<Command>, config: crate::con
                 }
             };
 
-            commands_tx.send(command)?;
+            match commands_tx.send(command) {
+                Ok(()) | Err(CommandSendError::QueueFull) => (),
+                Err(err @ CommandSendError::Closed) => return Err(err.into()),
+            }
         }
 
         tracing::debug!("Shutting down");
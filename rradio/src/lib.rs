@@ -0,0 +1,48 @@
+//! rradio, an internet radio with configurable two digit entry of stations.
+//!
+//! The `rradio` binary is a thin wrapper around [`cli::main`]. Other programs can embed rradio using [`Builder`], choosing which [`Ports`] to run,
+//! supplying the [`Config`](config::Config) programmatically, and sending commands and receiving events directly
+
+#![warn(clippy::pedantic)]
+
+mod audit_log;
+mod boot;
+mod builder;
+pub mod cli;
+mod clock;
+pub mod config;
+mod device_identity;
+mod dns;
+mod http;
+mod keyboard_commands;
+mod logging;
+mod pipeline;
+mod play_statistics;
+mod podcast_sync;
+mod ports;
+#[cfg(feature = "drop-privileges")]
+mod privileges;
+mod redact;
+mod safe_mode;
+mod saved_position;
+mod search;
+mod self_test;
+mod settings;
+mod signal_commands;
+mod startup_rotation;
+mod state_file;
+mod station;
+mod station_check;
+mod station_sync;
+mod stream_select;
+mod system_action;
+mod tag;
+mod task;
+#[cfg(feature = "thread-priority")]
+mod thread_priority;
+mod updater;
+
+pub use builder::{Builder, Ports, Rradio};
+pub use ports::{CommandSendError, CommandSender};
+pub use rradio_messages;
+pub use system_action::{SystemAction, RESTART_EXIT_STATUS};
@@ -0,0 +1,184 @@
+//! Log outputs which are configured in the config file, such as journald or a remote syslog server
+
+use std::net::UdpSocket;
+
+use anyhow::{Context, Result};
+use tracing::{span, Event, Level, Metadata, Subscriber};
+use tracing_subscriber::{
+    filter::Targets,
+    fmt::MakeWriter,
+    layer::{Context as LayerContext, Layered},
+    registry::LookupSpan,
+    reload, Layer, Registry,
+};
+
+use crate::config::log_target::{Output, Target};
+
+/// The subscriber which log outputs are added to, i.e. after logs have been filtered by `log_level`
+pub type FilteredRegistry = Layered<reload::Layer<Targets, Registry>, Registry>;
+
+pub type Outputs = Vec<Box<dyn Layer<FilteredRegistry> + Send + Sync>>;
+
+/// Only passes on events which are enabled by `filter`.
+/// Per-layer filters can't be used, as they don't work with reloadable layers
+struct TargetFiltered<L> {
+    filter: Targets,
+    layer: L,
+}
+
+impl<S, L> Layer<S> for TargetFiltered<L>
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+    L: Layer<S>,
+{
+    fn on_new_span(&self, attrs: &span::Attributes<'_>, id: &span::Id, ctx: LayerContext<'_, S>) {
+        self.layer.on_new_span(attrs, id, ctx);
+    }
+
+    fn on_record(&self, span: &span::Id, values: &span::Record<'_>, ctx: LayerContext<'_, S>) {
+        self.layer.on_record(span, values, ctx);
+    }
+
+    fn on_follows_from(&self, span: &span::Id, follows: &span::Id, ctx: LayerContext<'_, S>) {
+        self.layer.on_follows_from(span, follows, ctx);
+    }
+
+    fn on_event(&self, event: &Event<'_>, ctx: LayerContext<'_, S>) {
+        let metadata = event.metadata();
+
+        if self
+            .filter
+            .would_enable(metadata.target(), metadata.level())
+        {
+            self.layer.on_event(event, ctx);
+        }
+    }
+
+    fn on_enter(&self, id: &span::Id, ctx: LayerContext<'_, S>) {
+        self.layer.on_enter(id, ctx);
+    }
+
+    fn on_exit(&self, id: &span::Id, ctx: LayerContext<'_, S>) {
+        self.layer.on_exit(id, ctx);
+    }
+
+    fn on_close(&self, id: span::Id, ctx: LayerContext<'_, S>) {
+        self.layer.on_close(id, ctx);
+    }
+
+    fn on_id_change(&self, old: &span::Id, new: &span::Id, ctx: LayerContext<'_, S>) {
+        self.layer.on_id_change(old, new, ctx);
+    }
+}
+
+/// Sends each log message to a syslog server as a UDP datagram
+struct Syslog {
+    socket: UdpSocket,
+}
+
+impl Syslog {
+    fn connect(address: &str) -> Result<Self> {
+        let socket = UdpSocket::bind(("::", 0))
+            .or_else(|_| UdpSocket::bind(("0.0.0.0", 0)))
+            .context("Failed to bind syslog socket")?;
+
+        socket
+            .connect(address)
+            .with_context(|| format!("Failed to connect to syslog server {address}"))?;
+
+        Ok(Self { socket })
+    }
+}
+
+/// A single syslog message
+struct SyslogMessage<'a> {
+    socket: &'a UdpSocket,
+    severity: u8,
+}
+
+impl std::io::Write for SyslogMessage<'_> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        /// The "system daemons" facility
+        const FACILITY: u8 = 3;
+
+        let mut message = format!(
+            "<{}>1 {} - {} {} - - ",
+            FACILITY * 8 + self.severity,
+            chrono::Utc::now().to_rfc3339_opts(chrono::SecondsFormat::Millis, true),
+            env!("CARGO_PKG_NAME"),
+            std::process::id(),
+        )
+        .into_bytes();
+
+        let redacted_buf = crate::redact::log_message(buf);
+
+        message.extend_from_slice(redacted_buf.strip_suffix(b"\n").unwrap_or(&redacted_buf));
+
+        self.socket.send(&message)?;
+
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+impl<'a> MakeWriter<'a> for Syslog {
+    type Writer = SyslogMessage<'a>;
+
+    fn make_writer(&'a self) -> Self::Writer {
+        SyslogMessage {
+            socket: &self.socket,
+            severity: 6,
+        }
+    }
+
+    fn make_writer_for(&'a self, meta: &Metadata<'_>) -> Self::Writer {
+        let severity = match *meta.level() {
+            Level::ERROR => 3,
+            Level::WARN => 4,
+            Level::INFO => 6,
+            Level::DEBUG | Level::TRACE => 7,
+        };
+
+        SyslogMessage {
+            socket: &self.socket,
+            severity,
+        }
+    }
+}
+
+fn output_layer(output: &Output) -> Result<Box<dyn Layer<FilteredRegistry> + Send + Sync>> {
+    Ok(match output {
+        #[cfg(feature = "journald")]
+        Output::Journald => {
+            Box::new(tracing_journald::layer().context("Failed to connect to journald")?)
+        }
+        Output::Syslog { address } => Box::new(
+            tracing_subscriber::fmt::layer()
+                .with_ansi(false)
+                .without_time() // The time and level are part of the syslog header
+                .with_level(false)
+                .with_writer(Syslog::connect(address)?),
+        ),
+    })
+}
+
+/// Create the configured log outputs. Outputs which can't be created are logged and skipped
+pub fn outputs(targets: &[Target]) -> Outputs {
+    targets
+        .iter()
+        .filter_map(|target| match output_layer(&target.output) {
+            Ok(layer) => Some(Box::new(TargetFiltered {
+                filter: target.level.filter.clone(),
+                layer,
+            })
+                as Box<dyn Layer<FilteredRegistry> + Send + Sync>),
+            Err(err) => {
+                tracing::error!("Failed to create log output: {err:#}");
+                None
+            }
+        })
+        .collect()
+}
@@ -0,0 +1,240 @@
+//! Counting how often each station rebuffers, i.e. runs out of buffered audio during playback, and if `adaptive_buffering` is configured,
+//! increasing the buffer duration of stations which rebuffer frequently. The counts and buffer durations are stored as a JSON file in the state directory
+
+use std::{
+    collections::{BTreeMap, VecDeque},
+    path::{Path, PathBuf},
+    time::{Duration, Instant},
+};
+
+use anyhow::Result;
+
+use rradio_messages::StationIndex;
+
+use crate::config::adaptive_buffering::Config;
+
+const STATION_BUFFERING_FILE_NAME: &str = "station_buffering.json";
+
+/// The buffer duration used by gstreamer if `buffering_duration` isn't set
+const DEFAULT_BUFFER_DURATION: Duration = Duration::from_secs(2);
+
+/// How often a station has rebuffered, and the buffer duration it has been given
+#[derive(Clone, Debug, Default, PartialEq, Eq, serde::Deserialize, serde::Serialize)]
+pub struct StationBuffering {
+    pub rebuffer_count: u64,
+    /// `None` unless the station has rebuffered frequently enough to be given a longer buffer
+    #[serde(default, with = "humantime_serde")]
+    pub buffer_duration: Option<Duration>,
+}
+
+pub struct AdaptiveBuffering {
+    config: Option<Config>,
+    default_buffer_duration: Option<Duration>,
+    path: PathBuf,
+    stations: BTreeMap<StationIndex, StationBuffering>,
+    current_station: Option<StationIndex>,
+    /// When the current station rebuffered since its buffer duration was last changed
+    recent_rebuffers: VecDeque<Instant>,
+    /// Whether the current track has finished buffering, after which buffering is a rebuffer
+    is_buffered: bool,
+}
+
+impl AdaptiveBuffering {
+    pub fn load(config: &crate::config::Config) -> Self {
+        Self::new(
+            config.adaptive_buffering.clone(),
+            config.buffering_duration,
+            config.state_directory.as_str(),
+        )
+    }
+
+    /// The statistics are only loaded and saved if `config` is set
+    fn new(
+        config: Option<Config>,
+        default_buffer_duration: Option<Duration>,
+        state_directory: impl AsRef<Path>,
+    ) -> Self {
+        let path = state_directory.as_ref().join(STATION_BUFFERING_FILE_NAME);
+
+        let stations = if config.is_some() {
+            crate::state_file::read_json(&path).unwrap_or_else(|err| {
+                tracing::error!("{err:#}");
+                BTreeMap::new()
+            })
+        } else {
+            BTreeMap::new()
+        };
+
+        Self {
+            config,
+            default_buffer_duration,
+            path,
+            stations,
+            current_station: None,
+            recent_rebuffers: VecDeque::new(),
+            is_buffered: false,
+        }
+    }
+
+    pub fn stations(&self) -> &BTreeMap<StationIndex, StationBuffering> {
+        &self.stations
+    }
+
+    /// The buffer duration of the current station, or `None` if gstreamer's default is used
+    pub fn buffer_duration(&self) -> Option<Duration> {
+        self.current_station
+            .as_ref()
+            .and_then(|index| self.stations.get(index))
+            .and_then(|station| station.buffer_duration)
+            .or(self.default_buffer_duration)
+    }
+
+    /// Start counting the rebuffers of a new station
+    pub fn start_station(&mut self, index: Option<&StationIndex>) {
+        self.current_station = index.cloned();
+        self.recent_rebuffers.clear();
+        self.is_buffered = false;
+    }
+
+    pub fn start_track(&mut self) {
+        self.is_buffered = false;
+    }
+
+    /// Record the buffering level of the current track. Returns true if the buffering statistics have changed
+    pub fn record_buffering(&mut self, percent: u8) -> bool {
+        if percent >= 100 {
+            self.is_buffered = true;
+            return false;
+        }
+
+        if !std::mem::take(&mut self.is_buffered) {
+            return false;
+        }
+
+        let Some(index) = self.current_station.clone() else {
+            return false;
+        };
+
+        tracing::debug!(%index, "Station rebuffered");
+
+        self.stations
+            .entry(index.clone())
+            .or_default()
+            .rebuffer_count += 1;
+
+        if let Some(buffer_duration) = self.increased_buffer_duration(&index) {
+            tracing::info!(%index, ?buffer_duration, "Increasing buffer duration");
+
+            if let Some(station) = self.stations.get_mut(&index) {
+                station.buffer_duration = Some(buffer_duration);
+            }
+
+            self.recent_rebuffers.clear();
+        }
+
+        if self.config.is_some() {
+            if let Err(err) = self.save() {
+                tracing::error!("{err:#}");
+            }
+        }
+
+        true
+    }
+
+    /// The longer buffer duration which the station should be given, if it has rebuffered frequently and isn't at the maximum buffer duration
+    fn increased_buffer_duration(&mut self, index: &StationIndex) -> Option<Duration> {
+        let config = self.config.as_ref()?;
+
+        let now = Instant::now();
+
+        self.recent_rebuffers.push_back(now);
+
+        while self
+            .recent_rebuffers
+            .front()
+            .is_some_and(|&rebuffered_at| now.duration_since(rebuffered_at) > config.period)
+        {
+            self.recent_rebuffers.pop_front();
+        }
+
+        if self.recent_rebuffers.len() < config.rebuffer_count.get() {
+            return None;
+        }
+
+        let buffer_duration = self
+            .stations
+            .get(index)
+            .and_then(|station| station.buffer_duration)
+            .or(self.default_buffer_duration)
+            .unwrap_or(DEFAULT_BUFFER_DURATION);
+
+        let increased_buffer_duration = buffer_duration
+            .saturating_add(config.increment)
+            .min(config.maximum_duration);
+
+        (increased_buffer_duration > buffer_duration).then_some(increased_buffer_duration)
+    }
+
+    fn save(&self) -> Result<()> {
+        crate::state_file::write_json(&self.path, &self.stations)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use rradio_messages::StationIndex;
+
+    use super::AdaptiveBuffering;
+
+    fn rebuffer(adaptive_buffering: &mut AdaptiveBuffering) {
+        adaptive_buffering.record_buffering(100);
+        adaptive_buffering.record_buffering(40);
+    }
+
+    #[test]
+    fn frequently_rebuffering_stations_are_given_longer_buffers() {
+        let state_directory = tempfile::tempdir().unwrap();
+
+        let config = crate::config::adaptive_buffering::Config {
+            rebuffer_count: std::num::NonZeroUsize::new(2).unwrap(),
+            period: Duration::from_secs(10 * 60),
+            increment: Duration::from_secs(3),
+            maximum_duration: Duration::from_secs(7),
+        };
+
+        let index = StationIndex::new("05".into());
+
+        let mut adaptive_buffering =
+            AdaptiveBuffering::new(Some(config.clone()), None, state_directory.path());
+        adaptive_buffering.start_station(Some(&index));
+
+        // Buffering before the track has started playing isn't a rebuffer
+        adaptive_buffering.record_buffering(10);
+
+        rebuffer(&mut adaptive_buffering);
+        assert_eq!(adaptive_buffering.buffer_duration(), None);
+
+        rebuffer(&mut adaptive_buffering);
+        assert_eq!(
+            adaptive_buffering.buffer_duration(),
+            Some(Duration::from_secs(5))
+        );
+
+        rebuffer(&mut adaptive_buffering);
+        rebuffer(&mut adaptive_buffering);
+        rebuffer(&mut adaptive_buffering);
+        rebuffer(&mut adaptive_buffering);
+        assert_eq!(
+            adaptive_buffering.buffer_duration(),
+            Some(Duration::from_secs(7))
+        );
+
+        let mut reloaded = AdaptiveBuffering::new(Some(config), None, state_directory.path());
+        reloaded.start_station(Some(&index));
+
+        assert_eq!(reloaded.buffer_duration(), Some(Duration::from_secs(7)));
+        assert_eq!(reloaded.stations()[&index].rebuffer_count, 6);
+    }
+}
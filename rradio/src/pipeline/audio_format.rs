@@ -0,0 +1,75 @@
+//! Describing the format of the current track from its `audio-codec` tag and the caps of its decoded audio, so that clients can show a "lossless" badge
+
+use rradio_messages::{ArcStr, AudioFormat};
+
+/// Substrings of the `audio-codec` tags of lossless codecs, in lowercase
+const LOSSLESS_CODECS: &[&str] = &[
+    "flac",
+    "free lossless",
+    "alac",
+    "apple lossless",
+    "pcm",
+    "wavpack",
+    "monkey's audio",
+    "true audio",
+    "uncompressed",
+];
+
+/// Substrings of the `audio-codec` tags of lossy codecs which would otherwise match [`LOSSLESS_CODECS`], in lowercase
+const LOSSY_CODECS: &[&str] = &["adpcm"];
+
+/// Whether the codec named by an `audio-codec` tag is lossless
+pub fn is_lossless_codec(codec: &str) -> bool {
+    let codec = codec.to_lowercase();
+
+    LOSSLESS_CODECS.iter().any(|name| codec.contains(name))
+        && !LOSSY_CODECS.iter().any(|name| codec.contains(name))
+}
+
+/// The decoded audio of the current track
+pub struct SourceAudio {
+    pub caps: gstreamer::Caps,
+    /// False if the audio is uncompressed, so wasn't decoded, e.g. WAV files and CDs
+    pub is_decoded: bool,
+}
+
+/// Describe the current track's format, or `None` if its decoded audio hasn't been negotiated yet
+pub fn describe(
+    codec: Option<&ArcStr>,
+    source_audio: Option<&SourceAudio>,
+    output_caps: Option<&gstreamer::Caps>,
+) -> Option<AudioFormat> {
+    let source_audio = source_audio?;
+    let source_info = gstreamer_audio::AudioInfo::from_caps(&source_audio.caps).ok()?;
+
+    let output_info =
+        output_caps.and_then(|output_caps| gstreamer_audio::AudioInfo::from_caps(output_caps).ok());
+
+    Some(AudioFormat {
+        codec: codec.cloned(),
+        // Audio which wasn't decoded is uncompressed, even if the stream doesn't have a codec tag
+        is_lossless: codec.map_or(!source_audio.is_decoded, |codec| is_lossless_codec(codec)),
+        bit_depth: source_info.depth(),
+        sample_rate: source_info.rate(),
+        is_passthrough: output_info.is_some_and(|output_info| {
+            output_info.format() == source_info.format()
+                && output_info.rate() == source_info.rate()
+                && output_info.channels() == source_info.channels()
+        }),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::is_lossless_codec;
+
+    #[test]
+    fn lossless_codecs_are_recognised() {
+        assert!(is_lossless_codec("Free Lossless Audio Codec (FLAC)"));
+        assert!(is_lossless_codec("Uncompressed 16-bit PCM audio"));
+        assert!(is_lossless_codec("Apple Lossless Audio (ALAC)"));
+        assert!(!is_lossless_codec("MPEG-1 Layer 3 (MP3)"));
+        assert!(!is_lossless_codec("MPEG-4 AAC"));
+        assert!(!is_lossless_codec("IMA ADPCM audio"));
+    }
+}
@@ -1,16 +1,38 @@
-use std::{collections::BTreeMap, convert::TryInto, sync::Arc, time::Duration};
-use tokio::sync::{mpsc, watch};
+use std::{
+    collections::BTreeMap,
+    convert::TryInto,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+use tokio::sync::{broadcast, mpsc, watch};
 
 use rradio_messages::{
-    ArcStr, Command, CurrentStation, LatestError, PingTimes, StationIndex, TrackTags,
+    Activity, ArcStr, AudioFormat, AudioInterruption, AudioOutputs, Command, CurrentStation,
+    ErrorKind, IdleScreen, LatestError, Message, MessageId, PingTimes, Setting, Settings,
+    StationIndex, TrackTags,
 };
 
-use super::playbin::{IgnorePipelineError, PipelineError, PipelineState, Playbin};
+use super::{
+    adaptive_buffering::AdaptiveBuffering,
+    fader::{self, Fade},
+    idle_screen::IdleTracker,
+    loudness_normalisation::{self, LoudnessNormalisation},
+    notification_player::NotificationPlayer,
+    playbin::{IgnorePipelineError, PipelineError, PipelineState, Playbin},
+    title_loop::TitleLoopDetector,
+    track_quarantine::TrackQuarantine,
+    yt_dlp::YtDlp,
+};
 use crate::{
-    config::Config,
-    ports::PartialPortChannels,
+    audit_log::AuditLog,
+    config::{tag_filter::Field as TagField, Config, Notification},
+    play_statistics::PlayStatistics,
+    ports::{PartialPortChannels, ReceivedCommand},
+    saved_position::{self, SavedPosition, SavedTrack},
+    settings,
     station::{PlaylistMetadata, Station, Track},
     stream_select::StreamSelect,
+    system_action::SystemAction,
     tag::Tag,
 };
 
@@ -20,9 +42,45 @@ macro_rules! submodule_path {
     };
 }
 
+/// Log a gstreamer bus message with the name of the element which sent it and its full structure, to help diagnose odd stream behaviour
+fn trace_bus_message(message: &gstreamer::MessageRef) {
+    use gstreamer::prelude::GstObjectExt;
+
+    tracing::debug!(
+        target: submodule_path!(bus_trace),
+        source = message.src().map(GstObjectExt::path_string).as_deref(),
+        structure = message.structure().map(ToString::to_string),
+        "{:?}",
+        message.type_()
+    );
+}
+
 enum Error {
     Station(rradio_messages::StationError),
     Pipeline,
+    /// The command was refused, and playback is unaffected
+    Rejected(Message),
+}
+
+impl Error {
+    fn description(&self) -> ArcStr {
+        self.message().text
+    }
+
+    fn message(&self) -> Message {
+        match self {
+            Error::Station(error) => error.message(),
+            Error::Pipeline => MessageId::PipelineError.into(),
+            Error::Rejected(reason) => reason.clone(),
+        }
+    }
+
+    fn no_track_at_index(index: usize) -> Self {
+        Error::Rejected(Message::new(
+            MessageId::NoTrackAtIndex,
+            vec![index.to_string().into()],
+        ))
+    }
 }
 
 impl From<rradio_messages::StationError> for Error {
@@ -52,7 +110,7 @@ struct PlaylistState {
     tracks: Arc<[Track]>,
     current_track_index: usize,
     playlist_metadata: crate::station::PlaylistMetadata,
-    _playlist_handle: crate::station::PlaylistHandle,
+    playlist_handle: crate::station::PlaylistHandle,
 }
 
 impl PlaylistState {
@@ -78,6 +136,25 @@ impl PlaylistState {
         }
     }
 
+    /// Move in the given direction until a track which isn't quarantined is found
+    fn skip_quarantined_tracks(
+        &mut self,
+        track_quarantine: &TrackQuarantine,
+        goto_track: impl Fn(&mut Self),
+    ) -> Result<(), PipelineError> {
+        for _ in 0..self.tracks.len() {
+            if !track_quarantine.contains(&self.current_track()?.url) {
+                return Ok(());
+            }
+
+            goto_track(self);
+        }
+
+        tracing::error!("All tracks are quarantined");
+
+        Err(PipelineError)
+    }
+
     fn goto_nth_track(&mut self, index: usize) {
         if index < self.tracks.len() {
             self.current_track_index = index;
@@ -85,6 +162,81 @@ impl PlaylistState {
             tracing::error!(%index, length = self.tracks.len(), "Cannot change track");
         }
     }
+
+    /// Where the current track starts and ends in its file.
+    /// Tracks which share a file, such as the tracks of an album split by a cue sheet, end where the next track in the same file starts
+    fn current_track_bounds(&self) -> (Duration, Option<Duration>) {
+        let Some(current_track) = self.tracks.get(self.current_track_index) else {
+            return (Duration::ZERO, None);
+        };
+
+        let end = self
+            .tracks
+            .get(self.current_track_index + 1)
+            .filter(|next_track| next_track.url == current_track.url)
+            .and_then(|next_track| next_track.start_offset);
+
+        (current_track.start_offset.unwrap_or_default(), end)
+    }
+
+    /// Move to the last track which shares a file with the current track, e.g. when the file has finished
+    fn goto_last_track_in_file(&mut self) {
+        while self.current_track_bounds().1.is_some() {
+            self.current_track_index += 1;
+        }
+    }
+
+    /// The index of the track which is played once the current file finishes, skipping quarantined tracks
+    fn next_file_track_index(&mut self, track_quarantine: &TrackQuarantine) -> Option<usize> {
+        let current_track_index = self.current_track_index;
+
+        self.goto_last_track_in_file();
+        self.goto_next_track();
+
+        let next_track_index = self
+            .skip_quarantined_tracks(track_quarantine, Self::goto_next_track)
+            .ok()
+            .map(|()| self.current_track_index);
+
+        self.current_track_index = current_track_index;
+
+        next_track_index
+    }
+
+    /// Remove the track at `index`, which must exist and not be the current track
+    fn remove_track(&mut self, index: usize) {
+        self.tracks = self
+            .tracks
+            .iter()
+            .enumerate()
+            .filter(|&(track_index, _)| track_index != index)
+            .map(|(_, track)| track.clone())
+            .collect();
+
+        if index < self.current_track_index {
+            self.current_track_index -= 1;
+        }
+    }
+
+    /// Move the track at `from` to `to`, which must both exist. The current track index follows the current track
+    fn move_track(&mut self, from: usize, to: usize) {
+        let mut tracks = self.tracks.to_vec();
+        let track = tracks.remove(from);
+        tracks.insert(to, track);
+        self.tracks = tracks.into();
+
+        let current = self.current_track_index;
+
+        self.current_track_index = if current == from {
+            to
+        } else if from < current && current <= to {
+            current - 1
+        } else if to <= current && current < from {
+            current + 1
+        } else {
+            current
+        };
+    }
 }
 
 #[derive(Clone, Debug)]
@@ -101,6 +253,24 @@ pub struct PlayerState {
     pub track_position: Option<Duration>,
     pub ping_times: PingTimes,
     pub latest_error: Arc<Option<LatestError>>,
+    pub settings: Arc<Settings>,
+    pub quarantined_tracks: Arc<Vec<ArcStr>>,
+    /// Incremented whenever `current_station` changes, so that clients can check that track list patches apply to the tracks they have
+    pub tracks_revision: u64,
+    /// Why rradio isn't using its config file, which doesn't change after startup
+    pub safe_mode: Option<ArcStr>,
+    pub idle_screen: Option<IdleScreen>,
+    /// The maximum volume while quiet hours are active
+    pub quiet_hours_maximum_volume: Option<i32>,
+    pub audio_outputs: Option<AudioOutputs>,
+    pub activity: Activity,
+    /// Set while another application has asked the audio server to quieten rradio
+    pub audio_interruption: Option<AudioInterruption>,
+    pub audio_format: Option<AudioFormat>,
+    /// How much of each track is played while intro scanning
+    pub intro_scan: Option<Duration>,
+    /// While set, only privileged clients can change the station
+    pub station_locked: bool,
 }
 
 #[derive(Debug, Clone)]
@@ -110,17 +280,181 @@ struct StationResumeInfo {
     metadata: PlaylistMetadata,
 }
 
+/// Load the tracks of `station` on a blocking thread rather than on the pipeline thread, as walking the directories of a USB stick or fetching the containers of a `UPnP` server can take a long time,
+/// and the pipeline thread might have real-time priority, which would starve other threads. Blocking threads have normal priority
+async fn load_playlist(
+    station: Station,
+    metadata: Option<PlaylistMetadata>,
+    mut progress: crate::station::LoadProgress<'static>,
+    play_statistics: PlayStatistics,
+) -> Result<crate::station::Playlist, Error> {
+    let runtime = tokio::runtime::Handle::current();
+
+    tokio::task::spawn_blocking(move || {
+        runtime.block_on(station.into_playlist(metadata.as_ref(), &mut progress, &play_statistics))
+    })
+    .await
+    .map_err(|err| {
+        tracing::error!("Failed to load station: {err}");
+        Error::Pipeline
+    })?
+    .map_err(Error::Station)
+}
+
+/// The time of day of an alarm, or `None` if `hour` or `minute` is out of range
+fn alarm_time(hour: u8, minute: u8) -> Option<chrono::NaiveTime> {
+    chrono::NaiveTime::from_hms_opt(hour.into(), minute.into(), 0)
+}
+
+/// How far the alarms have been checked, so that each alarm is played once when its time is reached
+struct AlarmCheck {
+    /// When the alarms were last checked, so that jumps of the clock can be detected
+    checked_at: chrono::DateTime<chrono::FixedOffset>,
+    /// The local time up to which the alarms have been checked.
+    /// This doesn't go backwards when the clocks go back for daylight saving time, so that alarms in the repeated hour aren't played twice
+    checked_until: chrono::NaiveDateTime,
+}
+
+impl AlarmCheck {
+    fn new(now: chrono::DateTime<chrono::FixedOffset>) -> Self {
+        Self {
+            checked_at: now,
+            checked_until: now.naive_local(),
+        }
+    }
+
+    /// Check the alarms up to `now`, returning the local times after which and up to which alarms are due, if any are.
+    /// If the clock has jumped, e.g. when it's first synchronised after booting, the alarms which were jumped over aren't played.
+    /// Daylight saving time changes the offset rather than the time, so isn't a jump
+    fn advance(
+        &mut self,
+        now: chrono::DateTime<chrono::FixedOffset>,
+    ) -> Option<(chrono::NaiveDateTime, chrono::NaiveDateTime)> {
+        let checked_at = std::mem::replace(&mut self.checked_at, now);
+        let local_now = now.naive_local();
+
+        if now <= checked_at || now - checked_at > chrono::Duration::minutes(1) {
+            self.checked_until = local_now;
+            return None;
+        }
+
+        if local_now <= self.checked_until {
+            return None;
+        }
+
+        Some((
+            std::mem::replace(&mut self.checked_until, local_now),
+            local_now,
+        ))
+    }
+}
+
+/// Whether an alarm at `time` is due after `after` and up to `until`. The alarms might have been checked just before midnight
+fn alarm_is_due(
+    time: chrono::NaiveTime,
+    (after, until): (chrono::NaiveDateTime, chrono::NaiveDateTime),
+) -> bool {
+    [after.date(), until.date()].iter().any(|date| {
+        let alarm_at = date.and_time(time);
+        after < alarm_at && alarm_at <= until
+    })
+}
+
+fn format_alarm_time(hour: u8, minute: u8) -> ArcStr {
+    rradio_messages::arcstr::format!("{hour:02}:{minute:02}")
+}
+
+/// Progress towards playing the current station's ident
+struct StationIdentProgress {
+    tracks_finished: usize,
+    /// When the station started or the ident was last played
+    since: Instant,
+}
+
+impl StationIdentProgress {
+    fn new() -> Self {
+        Self {
+            tracks_finished: 0,
+            since: Instant::now(),
+        }
+    }
+}
+
+/// A station which is waiting for the server which hosts it to wake up before it's played
+struct WakingServer {
+    /// Distinguishes this station from earlier stations, whose servers might wake up after they've been replaced
+    id: u64,
+    station: Station,
+    task: tokio::task::JoinHandle<()>,
+}
+
 struct Controller {
     config: Config,
     playbin: Playbin,
+    notification_player: NotificationPlayer,
     current_playlist: Option<PlaylistState>,
     published_state: PlayerState,
+    settings: settings::Store,
     station_resume_info: BTreeMap<StationIndex, StationResumeInfo>,
-    new_state_tx: watch::Sender<PlayerState>,
+    new_state_tx: crate::ports::StatePublisher,
+    events_tx: broadcast::Sender<crate::ports::SharedEvent>,
     queued_seek: Option<Duration>,
     error_recovery_attempts_remaining: usize,
+    /// When the latest error occurred. Unlike `published_state.latest_error`, this is not cleared when the error is acknowledged
+    latest_error_time: Option<Instant>,
+    track_quarantine: TrackQuarantine,
+    yt_dlp: YtDlp,
+    /// The url of the first track of the station which is loading, which has been prerolled, paused and muted, so that it starts playing as soon as the station has loaded
+    prerolled_url: Option<ArcStr>,
+    /// The url of the current track which the playbin has loaded, so that changing to a track in the same file is done by seeking
+    loaded_url: Option<ArcStr>,
+    /// Which of the current track's urls is played, where 0 is its `url` and `n` is its `n`th mirror
+    mirror_index: usize,
+    /// The index and url of the track which the playbin has queued, to play without a gap once the current file finishes
+    gapless_track: Option<(usize, ArcStr)>,
+    /// When the current track is loaded after a `NextItem` or `PreviousItem` command, unless another track change is requested first
+    pending_track_change: Option<Instant>,
+    /// The `audio-codec` tag of the loaded file, from which `published_state.audio_format` is described
+    audio_codec: Option<ArcStr>,
+    /// When the sleep timer expires
+    sleep_at: Option<Instant>,
+    /// The fade out after the sleep timer has expired, after which playback stops
+    sleep_fade: Option<Fade>,
+    /// The fade in when playback starts automatically, such as when resuming on startup
+    wake_fade: Option<Fade>,
+    /// How far alarms have been checked, in the configured timezone. Alarms are played once their time has been reached
+    alarm_check: AlarmCheck,
+    idle_tracker: IdleTracker,
+    /// The position saved when rradio last shut down, which is resumed when the saved station is played
+    saved_position: Option<SavedPosition>,
+    station_ident_progress: StationIdentProgress,
+    audit_log: AuditLog,
+    play_statistics: PlayStatistics,
+    adaptive_buffering: AdaptiveBuffering,
+    loudness_normalisation: LoudnessNormalisation,
+    title_loop_detector: TitleLoopDetector,
+    /// If true, every gstreamer bus message is logged. Initially set by config, and changed by `SetBusTrace`
+    bus_trace: bool,
+    /// Shared with the pinger
+    resolver: crate::dns::Resolver,
+    /// Powering off and rebooting are performed by the main task, once rradio has shut down
+    system_actions_tx: mpsc::Sender<SystemAction>,
+    /// Servers are woken up in the background, so that commands and alarms are handled while waiting for them
+    waking_server: Option<WakingServer>,
+    last_waking_server_id: u64,
+    /// The ids of stations whose servers have woken up, or have failed to wake up
+    servers_awake_tx: mpsc::Sender<u64>,
+    pipeline_diagnostics_tx: watch::Sender<crate::ports::PipelineDiagnostics>,
+    /// Seek previews are made in the background, one at a time
+    seek_preview_requests_tx: watch::Sender<Option<super::seek_preview::Request>>,
     #[cfg(feature = "ping")]
     ping_requests_tx: tokio::sync::mpsc::UnboundedSender<Option<ArcStr>>,
+    /// Commands which are sent once background work has finished, such as playing a podcast once its feed has been fetched.
+    /// These commands have already passed the station lock, so are privileged
+    commands_tx: crate::ports::CommandSender,
+    /// Records that the controller and the gstreamer bus are responsive, if the watchdog is running
+    #[cfg(feature = "watchdog")]
+    watchdog: Option<super::watchdog::Liveness>,
 }
 
 impl Controller {
@@ -140,302 +474,2026 @@ impl Controller {
         }
     }
 
-    fn play_pause(&mut self) -> Result<(), PipelineError> {
-        if self.current_playlist.is_some() {
-            match self.playbin.pipeline_state()? {
-                PipelineState::Null | PipelineState::Ready | PipelineState::Paused => {
-                    tracing::debug!("Playing pipeline");
-                    self.playbin.set_pipeline_state(PipelineState::Playing)?;
-                    self.playbin.set_is_muted(false)?;
-                }
-                PipelineState::Playing => {
-                    self.playbin
-                        .set_pipeline_state(if self.playbin.duration().is_some() {
-                            tracing::debug!("Pausing pipeline");
-                            PipelineState::Paused
-                        } else {
-                            tracing::debug!("Stopping pipeline");
-                            PipelineState::Null
-                        })?;
-                }
-            }
-
-            Ok(())
-        } else {
-            tracing::debug!("no current playlist, ignoring play/pause");
-            Ok(())
-        }
-    }
-
-    #[tracing::instrument(skip(self))]
-    async fn play_current_track(&mut self) -> Result<(), PipelineError> {
-        #[cfg(feature = "ping")]
-        self.clear_ping();
-
-        let current_playlist = self.current_playlist.as_ref().ok_or(NoPlaylist)?;
+    /// Start fading out if the sleep timer has expired, and stop playing once the fade out has finished
+    fn update_sleep_timer(&mut self) {
+        let now = Instant::now();
 
-        let track = current_playlist.current_track()?;
-        let pause_before_playing = current_playlist.pause_before_playing;
+        if self.sleep_at.is_some_and(|sleep_at| now >= sleep_at) {
+            tracing::info!("Sleep timer expired");
 
-        #[cfg(feature = "ping")]
-        let track_url = track.url.clone();
+            self.sleep_at = None;
+            self.sleep_fade = Some(Fade::new(
+                self.published_state.volume,
+                rradio_messages::VOLUME_MIN,
+                self.config.fade_config.sleep_fade_out,
+            ));
+        }
 
-        tracing::debug!(?track, "Playing track");
+        let Some(sleep_fade) = &self.sleep_fade else {
+            return;
+        };
 
-        self.playbin.set_url(&track.url)?;
-        self.published_state.current_track_index = current_playlist.current_track_index;
-        self.published_state.current_track_tags = Arc::new(None);
-        if let Some(pause_duration) = pause_before_playing {
-            tracing::info!("Pausing for {}s", pause_duration.as_secs());
-            self.playbin.set_pipeline_state(PipelineState::Paused)?;
-            self.broadcast_state_change();
-            tokio::time::sleep(pause_duration).await;
+        if self.current_playlist.is_some() && !sleep_fade.is_finished(now) {
+            self.playbin
+                .set_volume(self.ducked_volume(sleep_fade.volume_at(now)))
+                .ok();
+            return;
         }
-        self.playbin.set_pipeline_state(PipelineState::Playing)?;
-        self.broadcast_state_change();
 
-        #[cfg(feature = "ping")]
-        self.request_ping(track_url);
+        self.sleep_fade = None;
 
-        Ok(())
-    }
+        self.clear_playlist();
 
-    async fn smart_goto_previous_track(&mut self) -> Result<(), PipelineError> {
-        if let Some(track_position) = self.published_state.track_position {
-            if track_position < self.config.smart_goto_previous_track_duration {
-                self.goto_previous_track().await
-            } else {
-                self.seek_to(Duration::ZERO)
-            }
-        } else {
-            Ok(())
-        }
+        // Restore the volume, so that the next station doesn't start silently
+        self.playbin
+            .set_volume(self.ducked_volume(self.published_state.volume))
+            .ok();
     }
 
-    #[tracing::instrument(skip(self))]
-    async fn goto_previous_track(&mut self) -> Result<(), PipelineError> {
-        self.current_playlist
-            .as_mut()
-            .ok_or(NoPlaylist)?
-            .goto_previous_track();
-        self.play_current_track().await
-    }
+    /// Fade in from silence, e.g. when playback starts automatically
+    fn start_wake_fade(&mut self) {
+        self.wake_fade = Some(Fade::new(
+            rradio_messages::VOLUME_MIN,
+            self.published_state.volume,
+            self.config.fade_config.wake_fade_in,
+        ));
 
-    #[tracing::instrument(skip(self))]
-    async fn goto_next_track(&mut self) -> Result<(), PipelineError> {
-        self.current_playlist
-            .as_mut()
-            .ok_or(NoPlaylist)?
-            .goto_next_track();
-        self.play_current_track().await
+        self.playbin.set_volume(rradio_messages::VOLUME_MIN).ok();
     }
 
-    #[tracing::instrument(skip(self))]
-    async fn goto_nth_track(&mut self, index: usize) -> Result<(), PipelineError> {
-        self.current_playlist
-            .as_mut()
-            .ok_or(NoPlaylist)?
-            .goto_nth_track(index);
-        self.play_current_track().await
-    }
+    fn update_wake_fade(&mut self) {
+        let Some(wake_fade) = &self.wake_fade else {
+            return;
+        };
 
-    fn seek_to(&mut self, position: Duration) -> Result<(), PipelineError> {
-        self.playbin.seek_to(position)
-    }
+        let now = Instant::now();
 
-    fn clear_playlist(&mut self) {
-        #[cfg(feature = "ping")]
-        self.clear_ping();
+        if wake_fade.is_finished(now) {
+            self.wake_fade = None;
+            self.playbin
+                .set_volume(self.ducked_volume(self.published_state.volume))
+                .ok();
+        } else {
+            self.playbin
+                .set_volume(self.ducked_volume(wake_fade.volume_at(now)))
+                .ok();
+        }
+    }
 
-        self.current_playlist = None;
-        self.published_state.current_station = Arc::new(CurrentStation::NoStation);
-        self.published_state.pause_before_playing = None;
-        self.published_state.current_track_index = 0;
-        self.published_state.current_track_tags = Arc::new(None);
+    /// Start or stop quiet hours, reducing the volume when they start if configured to do so
+    fn update_quiet_hours(&mut self) {
+        let maximum_volume = self
+            .config
+            .quiet_hours
+            .as_ref()
+            .filter(|quiet_hours| quiet_hours.contains(self.config.timezone.now().time()))
+            .map(|quiet_hours| quiet_hours.maximum_volume);
 
-        self.set_is_muted(false).ok();
+        if maximum_volume == self.published_state.quiet_hours_maximum_volume {
+            return;
+        }
 
-        self.broadcast_state_change();
+        tracing::info!(?maximum_volume, "Quiet hours changed");
 
-        self.playbin.set_pipeline_state(PipelineState::Null).ok();
-    }
+        self.published_state.quiet_hours_maximum_volume = maximum_volume;
 
-    fn play_error(&mut self, error: Error) {
-        self.clear_playlist();
+        let reduce_volume = self
+            .config
+            .quiet_hours
+            .as_ref()
+            .is_some_and(|quiet_hours| quiet_hours.reduce_volume);
 
-        match error {
-            Error::Station(error) => {
-                self.published_state.current_station =
-                    Arc::new(CurrentStation::FailedToPlayStation { error });
+        if let Some(maximum_volume) = maximum_volume {
+            if reduce_volume && self.published_state.volume > maximum_volume {
+                if let Err(PipelineError) = self.set_volume(maximum_volume) {
+                    tracing::error!("Failed to reduce volume for quiet hours");
+                }
             }
-            Error::Pipeline => (),
         }
 
         self.broadcast_state_change();
-
-        if let Some(url) = &self.config.notifications.error {
-            self.playbin.play_url(url.as_str()).ignore_pipeline_error();
-        }
     }
 
-    fn broadcast_error(&mut self, error: impl AsRef<str>) {
-        self.published_state.latest_error = Arc::new(Some(rradio_messages::LatestError {
-            timestamp: chrono::Utc::now(),
-            error: error.as_ref().into(),
-        }));
+    /// The configured alarms, followed by the alarms set by `SetAlarm`, as the time of day, the station, and how long until playback stops
+    fn alarms(&self) -> impl Iterator<Item = (chrono::NaiveTime, &StationIndex, Option<Duration>)> {
+        let configured_alarms = self
+            .config
+            .alarms
+            .iter()
+            .map(|alarm| (alarm.time.0, &alarm.station, alarm.stop_after));
+
+        let set_alarms = self.settings.settings().alarms.iter().filter_map(|alarm| {
+            Some((
+                alarm_time(alarm.hour, alarm.minute)?,
+                &alarm.station,
+                alarm.stop_after,
+            ))
+        });
 
-        self.broadcast_state_change();
+        configured_alarms.chain(set_alarms)
     }
 
-    fn broadcast_state_change(&mut self) {
-        self.published_state.track_duration = self.playbin.duration();
-        self.published_state.track_position = self.playbin.position();
-        self.published_state.is_muted = self.playbin.is_muted();
-
-        self.new_state_tx.send(self.published_state.clone()).ok();
-    }
+    /// Play the station of an alarm whose time has been reached since the alarms were last checked, fading in the volume
+    async fn update_alarms(&mut self) {
+        let Some(due) = self.alarm_check.advance(self.config.timezone.now()) else {
+            return;
+        };
 
-    fn create_resume_info(
-        &self,
-        new_station_index: &StationIndex,
-    ) -> Option<(StationIndex, StationResumeInfo)> {
-        let CurrentStation::PlayingStation {
-            index: Some(current_station_index),
-            source_type: current_station_source_type,
-            ..
-        } = self.published_state.current_station.as_ref()
+        let Some((station_index, stop_after)) = self
+            .alarms()
+            .find(|&(time, _, _)| alarm_is_due(time, due))
+            .map(|(_, station_index, stop_after)| (station_index.clone(), stop_after))
         else {
-            return None;
+            return;
         };
 
-        if new_station_index == current_station_index {
-            return None;
-        }
-
-        match current_station_source_type {
-            rradio_messages::StationType::UrlList => return None,
-            rradio_messages::StationType::UPnP
-            | rradio_messages::StationType::CD
-            | rradio_messages::StationType::Usb => (),
+        if self.published_state.station_locked {
+            tracing::info!(%station_index, "Ignoring alarm, as the station is locked");
+            return;
         }
 
-        let station_resume_info = StationResumeInfo {
-            track_index: self.published_state.current_track_index,
-            track_position: self.published_state.track_position?,
-            metadata: self.current_playlist.as_ref()?.playlist_metadata.clone(),
-        };
+        // The startup rotation also gives alarms some variety
+        let station_index = self
+            .config
+            .startup_rotation
+            .as_ref()
+            .filter(|startup_rotation| startup_rotation.alarms)
+            .and_then(|startup_rotation| {
+                crate::startup_rotation::next_station(
+                    self.config.state_directory.as_str(),
+                    &startup_rotation.stations,
+                )
+            })
+            .unwrap_or(station_index);
+
+        tracing::info!(%station_index, "Alarm");
+
+        match self
+            .handle_command(Command::SetChannel(station_index), None)
+            .await
+        {
+            Ok(()) => {
+                self.start_wake_fade();
 
-        Some((current_station_index.clone(), station_resume_info))
+                if let Some(stop_after) = stop_after {
+                    self.sleep_at = Some(Instant::now() + stop_after);
+                }
+            }
+            Err(error) => self.play_error(error),
+        }
     }
 
+    /// Add an alarm, replacing the alarm at the same time, if there is one
     #[tracing::instrument(skip(self))]
-    fn save_resume_info(&mut self, new_station_index: &StationIndex) {
-        let Some((current_station_index, station_resume_info)) =
-            self.create_resume_info(new_station_index)
-        else {
-            return;
-        };
+    fn set_alarm(&mut self, alarm: rradio_messages::Alarm) -> Result<(), Error> {
+        if alarm_time(alarm.hour, alarm.minute).is_none() {
+            return Err(Error::Rejected(Message::new(
+                MessageId::BadAlarmTime,
+                vec![format_alarm_time(alarm.hour, alarm.minute)],
+            )));
+        }
 
-        tracing::debug!(
-            "Saving state for {}: {:?}",
-            current_station_index,
-            station_resume_info
-        );
+        tracing::info!("Setting alarm");
 
-        self.station_resume_info
-            .insert(current_station_index, station_resume_info);
+        self.update_settings(|settings| {
+            settings
+                .alarms
+                .retain(|existing| (existing.hour, existing.minute) != (alarm.hour, alarm.minute));
+            settings.alarms.push(alarm);
+            settings
+                .alarms
+                .sort_by_key(|alarm| (alarm.hour, alarm.minute));
+        });
+        self.broadcast_state_change();
+
+        Ok(())
     }
 
+    /// Remove the alarm set by `SetAlarm` at the given time
     #[tracing::instrument(skip(self))]
-    async fn play_station(&mut self, new_station: Station) -> Result<(), Error> {
-        if let Some(index) = new_station.index() {
-            self.save_resume_info(index);
+    fn remove_alarm(&mut self, hour: u8, minute: u8) -> Result<(), Error> {
+        let is_at_time =
+            |alarm: &rradio_messages::Alarm| (alarm.hour, alarm.minute) == (hour, minute);
+
+        if !self.settings.settings().alarms.iter().any(is_at_time) {
+            return Err(Error::Rejected(Message::new(
+                MessageId::NoAlarmAt,
+                vec![format_alarm_time(hour, minute)],
+            )));
         }
 
-        let resume_info = new_station
-            .index()
-            .and_then(|index| self.station_resume_info.remove(index));
+        tracing::info!("Removing alarm");
 
-        self.clear_playlist();
+        self.update_settings(|settings| settings.alarms.retain(|alarm| !is_at_time(alarm)));
+        self.broadcast_state_change();
 
-        self.error_recovery_attempts_remaining = self.config.maximum_error_recovery_attempts;
+        Ok(())
+    }
 
-        self.published_state.current_station =
-            Arc::new(rradio_messages::CurrentStation::PlayingStation {
-                index: new_station.index().cloned(),
-                title: new_station.title().map(ArcStr::from),
-                source_type: new_station.station_type(),
-                tracks: None,
-            });
+    /// Show the next idle screen, or stop showing idle screens if rradio is active. Playing counts as being active
+    fn update_idle_screen(&mut self) {
+        let now = Instant::now();
 
-        self.set_is_muted(false).ok();
+        if self.published_state.pipeline_state == PipelineState::Playing {
+            self.idle_tracker.active(now);
+        }
+
+        if self.idle_tracker.update(now) {
+            self.published_state.idle_screen = self.idle_tracker.screen().cloned();
+            self.broadcast_state_change();
+        }
+    }
+
+    /// Switch to the audio output named `name`, resuming playback from the same position
+    fn set_audio_output(&mut self, name: &str) -> Result<(), Error> {
+        let Some(output) = self
+            .config
+            .audio_output_config
+            .outputs
+            .iter()
+            .find(|output| output.name == name)
+            .cloned()
+        else {
+            return Err(Error::Rejected(Message::new(
+                MessageId::NoAudioOutputNamed,
+                vec![name.into()],
+            )));
+        };
+
+        let Some(mut audio_outputs) = self.published_state.audio_outputs.clone() else {
+            return Err(Error::Rejected(MessageId::NoAudioOutputs.into()));
+        };
+
+        if audio_outputs.selected == output.name {
+            return Ok(());
+        }
+
+        tracing::info!(name = %output.name, audio_sink = %output.audio_sink, "Changing audio output");
+
+        // The audio sink can only be changed while the pipeline is stopped
+        let pipeline_state = self.published_state.pipeline_state;
+        let position = self.track_position();
+
+        self.playbin.set_pipeline_state(PipelineState::Null)?;
+
+        let audio_sink = self
+            .config
+            .pipeline_config
+            .output_sink_description(&output.audio_sink, output.output_trim);
+
+        self.playbin.set_audio_sink(&audio_sink)?;
+        self.notification_player.set_audio_sink(audio_sink.into());
+
+        if self.current_playlist.is_some()
+            && matches!(
+                pipeline_state,
+                PipelineState::Playing | PipelineState::Paused
+            )
+        {
+            // Streams can't be seeked, so resume from the live position
+            if self.published_state.track_duration.is_some() {
+                self.queued_seek = position;
+            }
+
+            self.playbin.set_pipeline_state(pipeline_state)?;
+        }
+
+        audio_outputs.selected = output.name.clone();
+        self.published_state.audio_outputs = Some(audio_outputs);
+
+        match output.maximum_volume {
+            Some(maximum_volume) if self.published_state.volume > maximum_volume => {
+                self.set_volume(maximum_volume)?;
+            }
+            _ => self.broadcast_state_change(),
+        }
+
+        Ok(())
+    }
+
+    /// Select the headphones or the speakers when headphones are plugged in or unplugged
+    fn handle_headphones_connected(&mut self, is_connected: bool) -> Result<(), Error> {
+        tracing::info!(is_connected, "Headphones changed");
+
+        let Some(headphone_detect) = self.config.audio_output_config.headphone_detect.clone()
+        else {
+            return Ok(());
+        };
+
+        if let Some(audio_outputs) = &mut self.published_state.audio_outputs {
+            audio_outputs.headphones_connected = Some(is_connected);
+        }
+
+        self.set_audio_output(if is_connected {
+            &headphone_detect.headphones
+        } else {
+            &headphone_detect.speakers
+        })?;
+
+        self.broadcast_state_change();
+
+        Ok(())
+    }
+
+    /// Record that the controller is responsive, and regularly post a probe to the bus, which records that the bus is responsive once it's received.
+    /// The pipeline flushes its bus when it stops, so while it's stopped there's nothing to probe, and the bus is treated as responsive
+    #[cfg(feature = "watchdog")]
+    fn update_watchdog(&mut self) {
+        let Some(watchdog) = &mut self.watchdog else {
+            return;
+        };
+
+        if !watchdog.controller_is_responsive() {
+            return;
+        }
+
+        if let Ok(PipelineState::Null) | Err(PipelineError) = self.playbin.pipeline_state() {
+            watchdog.bus_is_responsive();
+            return;
+        }
+
+        if self
+            .playbin
+            .post_application_message(super::watchdog::PROBE_NAME)
+            .is_err()
+        {
+            tracing::debug!("Failed to post watchdog probe");
+        }
+    }
+
+    /// Derive the activity from the pipeline state. The time at which the player became idle is kept while it stays idle
+    fn update_activity(&mut self) {
+        let activity = match (
+            self.published_state.pipeline_state,
+            self.published_state.activity,
+        ) {
+            (PipelineState::Playing, _) => Activity::Playing,
+            (PipelineState::Paused, _) => Activity::Paused,
+            (PipelineState::Null | PipelineState::Ready, Activity::Idle { since }) => {
+                Activity::Idle { since }
+            }
+            (PipelineState::Null | PipelineState::Ready, Activity::Playing | Activity::Paused) => {
+                Activity::Idle {
+                    since: chrono::Utc::now(),
+                }
+            }
+        };
+
+        if activity != self.published_state.activity {
+            self.published_state.activity = activity;
+            self.broadcast_state_change();
+        }
+    }
+
+    /// Stop fading, and restore the volume, e.g. if the user changes the volume or station while the volume is fading
+    fn cancel_fades(&mut self) {
+        let sleep_fade = self.sleep_fade.take();
+        let wake_fade = self.wake_fade.take();
+
+        if sleep_fade.is_some() || wake_fade.is_some() {
+            tracing::info!("Fade cancelled");
+            self.playbin
+                .set_volume(self.ducked_volume(self.published_state.volume))
+                .ok();
+        }
+    }
+
+    fn play_pause(&mut self) -> Result<(), PipelineError> {
+        if self.current_playlist.is_some() {
+            match self.playbin.pipeline_state()? {
+                PipelineState::Null | PipelineState::Ready | PipelineState::Paused => {
+                    tracing::debug!("Playing pipeline");
+                    self.playbin.set_pipeline_state(PipelineState::Playing)?;
+                    self.playbin.set_is_muted(false)?;
+
+                    // The user has chosen to play, so playing doesn't resume when the other application finishes
+                    if self.published_state.audio_interruption == Some(AudioInterruption::Paused) {
+                        self.published_state.audio_interruption = None;
+                        self.broadcast_state_change();
+                    }
+                }
+                PipelineState::Playing => {
+                    self.playbin
+                        .set_pipeline_state(if self.playbin.duration().is_some() {
+                            tracing::debug!("Pausing pipeline");
+                            PipelineState::Paused
+                        } else {
+                            tracing::debug!("Stopping pipeline");
+                            PipelineState::Null
+                        })?;
+                }
+            }
+
+            Ok(())
+        } else {
+            tracing::debug!("no current playlist, ignoring play/pause");
+            Ok(())
+        }
+    }
+
+    /// The volume of the playbin while ducked for another application, so that changing the volume or finishing a fade doesn't undo the duck
+    fn ducked_volume(&self, volume: i32) -> i32 {
+        match (&self.config.cork, self.published_state.audio_interruption) {
+            (Some(cork_config), Some(AudioInterruption::Ducked)) => {
+                cork_config.duck_volume.min(volume)
+            }
+            _ => volume,
+        }
+    }
+
+    /// Pause or duck when the audio sink is corked, i.e. when another application, such as a voice call, asks the audio server to quieten rradio, and resume when it's uncorked
+    fn handle_audio_interruption(
+        &mut self,
+        requested_state: gstreamer::State,
+    ) -> Result<(), PipelineError> {
+        let Some(cork_config) = &self.config.cork else {
+            tracing::debug!(?requested_state, "Ignoring audio server request");
+            return Ok(());
+        };
+
+        match (requested_state, self.published_state.audio_interruption) {
+            (gstreamer::State::Paused, None) => {
+                if self.playbin.pipeline_state()? != PipelineState::Playing {
+                    return Ok(());
+                }
+
+                let audio_interruption = match cork_config.action {
+                    crate::config::cork::Action::Pause => {
+                        tracing::info!("Pausing for another application");
+
+                        // Unlike play/pause, streams without a duration are paused rather than stopped, as stopping would remove the audio sink,
+                        // and with it the audio server's stream, so rradio wouldn't be told when the other application finishes
+                        self.playbin.set_pipeline_state(PipelineState::Paused)?;
+
+                        AudioInterruption::Paused
+                    }
+                    crate::config::cork::Action::Duck => {
+                        tracing::info!("Ducking for another application");
+
+                        self.playbin
+                            .set_volume(cork_config.duck_volume.min(self.published_state.volume))?;
+
+                        AudioInterruption::Ducked
+                    }
+                };
+
+                self.published_state.audio_interruption = Some(audio_interruption);
+                self.broadcast_state_change();
+            }
+            (gstreamer::State::Playing, Some(audio_interruption)) => {
+                tracing::info!(?audio_interruption, "Other application finished");
+
+                match audio_interruption {
+                    AudioInterruption::Paused => {
+                        if self.current_playlist.is_some() {
+                            // As with play/pause, streams without a duration are restarted so that they resume live
+                            if self.playbin.duration().is_none() {
+                                self.playbin.set_pipeline_state(PipelineState::Null)?;
+                            }
+
+                            self.playbin.set_pipeline_state(PipelineState::Playing)?;
+                        }
+                    }
+                    AudioInterruption::Ducked => {
+                        self.playbin.set_volume(self.published_state.volume)?;
+                    }
+                }
+
+                self.published_state.audio_interruption = None;
+                self.broadcast_state_change();
+            }
+            _ => (),
+        }
+
+        Ok(())
+    }
+
+    async fn play_current_track(&mut self) -> Result<(), PipelineError> {
+        self.mirror_index = 0;
+        self.load_current_track().await
+    }
+
+    /// Whether the current track has a mirror which hasn't been tried yet
+    fn has_untried_mirror(&self) -> bool {
+        self.current_playlist
+            .as_ref()
+            .and_then(|current_playlist| current_playlist.current_track().ok())
+            .is_some_and(|track| self.mirror_index < track.mirrors.len())
+    }
+
+    /// Play the current track from its next mirror
+    async fn play_next_mirror(&mut self) -> Result<(), PipelineError> {
+        self.mirror_index += 1;
+        self.loaded_url = None;
+
+        self.load_current_track().await
+    }
+
+    /// Play the current track from the url chosen by `mirror_index`
+    #[tracing::instrument(skip(self))]
+    async fn load_current_track(&mut self) -> Result<(), PipelineError> {
+        self.pending_track_change = None;
+
+        #[cfg(feature = "ping")]
+        self.clear_ping();
+
+        let page_url = {
+            let track = self
+                .current_playlist
+                .as_ref()
+                .ok_or(NoPlaylist)?
+                .current_track()?;
+
+            let mirror = self
+                .mirror_index
+                .checked_sub(1)
+                .and_then(|index| track.mirrors.get(index))
+                .cloned();
+
+            self.pipeline_diagnostics_tx
+                .send_if_modified(|diagnostics| {
+                    if diagnostics.track_mirror == mirror {
+                        return false;
+                    }
+
+                    diagnostics.track_mirror = mirror.clone();
+                    true
+                });
+
+            mirror.unwrap_or_else(|| track.url.clone())
+        };
+
+        let url = match self.yt_dlp.resolve(&page_url).await {
+            Ok(url) => url,
+            Err(err) => {
+                tracing::error!("{err:#}");
+                self.broadcast_error(
+                    super::error_kind::from_anyhow_error(&err),
+                    Message::new(
+                        MessageId::TrackResolutionFailed,
+                        vec![rradio_messages::arcstr::format!("{err:#}")],
+                    ),
+                );
+                return Err(PipelineError);
+            }
+        };
+
+        let current_playlist = self.current_playlist.as_ref().ok_or(NoPlaylist)?;
+
+        let track = current_playlist.current_track()?;
+        let pause_before_playing = current_playlist.pause_before_playing;
+
+        tracing::debug!(?track, "Playing track");
+
+        // Fades control the volume while they're running
+        if self.sleep_fade.is_none() && self.wake_fade.is_none() {
+            self.playbin.set_volume(self.track_volume(track)).ok();
+        }
+
+        let (track_start, _) = current_playlist.current_track_bounds();
+
+        let is_loaded = self.loaded_url.as_ref() == Some(&url)
+            && matches!(
+                self.playbin.pipeline_state(),
+                Ok(PipelineState::Playing | PipelineState::Paused)
+            );
+
+        if is_loaded {
+            // Tracks which share a file, such as the tracks of an album split by a cue sheet, are changed by seeking rather than reloading the file
+            self.playbin
+                .seek_to(track_start + self.queued_seek.take().unwrap_or_default())?;
+        } else {
+            // The first track of the station might have been prerolled while the station was loading
+            if self.prerolled_url.take().as_ref() != Some(&url) {
+                self.playbin.set_url(&url)?;
+            }
+
+            self.loaded_url = Some(url.clone());
+
+            // Podcast episodes resume from where they were left, whichever device they were listened to on
+            if self.queued_seek.is_none() {
+                self.queued_seek =
+                    crate::station::podcast::resume_position(&self.config, &track.url);
+            }
+
+            // Play from the start of the track, unless part way through the track is being resumed
+            if track_start > Duration::ZERO {
+                self.queued_seek.get_or_insert(Duration::ZERO);
+            }
+        }
+
+        self.start_track(!is_loaded)?;
+
+        if let Some(pause_duration) = pause_before_playing {
+            tracing::info!("Pausing for {}s", pause_duration.as_secs());
+            self.playbin.set_pipeline_state(PipelineState::Paused)?;
+            self.broadcast_state_change();
+            tokio::time::sleep(pause_duration).await;
+        }
+        self.playbin.set_pipeline_state(PipelineState::Playing)?;
+        self.broadcast_state_change();
+
+        #[cfg(feature = "ping")]
+        self.request_ping(url);
+
+        Ok(())
+    }
+
+    /// Record that the current track has started, either because it was loaded, or because the playbin switched to it without a gap
+    fn start_track(&mut self, is_new_file: bool) -> Result<(), PipelineError> {
+        let current_playlist = self.current_playlist.as_ref().ok_or(NoPlaylist)?;
+        let track = current_playlist.current_track()?;
+
+        // Tracks which are only played while scanning haven't really been played
+        if !track.is_notification
+            && self.published_state.intro_scan.is_none()
+            && self.is_playing_library_station()
+        {
+            self.play_statistics
+                .record_play(crate::play_statistics::track_key(
+                    &track.url,
+                    current_playlist.playlist_handle.mounted_directory(),
+                ));
+        }
+
+        self.published_state.current_track_index = current_playlist.current_track_index;
+        // The tags of a file are only received when it's loaded, and apply to all of its tracks
+        if is_new_file {
+            self.published_state.current_track_tags = Arc::new(None);
+            self.audio_codec = None;
+            self.published_state.audio_format = None;
+        }
+        self.adaptive_buffering.start_track();
+        self.title_loop_detector.start_track();
+
+        Ok(())
+    }
+
+    /// The track which can be played without a gap once the current file finishes, and its url
+    fn next_gapless_track(&mut self) -> Option<(usize, ArcStr)> {
+        // The pending track replaces the current track, so its next track isn't known yet
+        if self.pending_track_change.is_some() {
+            return None;
+        }
+
+        let station_index = match self.published_state.current_station.as_ref() {
+            CurrentStation::PlayingStation { index, .. } => index.as_ref(),
+            CurrentStation::NoStation | CurrentStation::FailedToPlayStation { .. } => None,
+        };
+
+        if !self
+            .config
+            .pipeline_config
+            .is_gapless(station_index.map(StationIndex::as_str))
+        {
+            return None;
+        }
+
+        // Streams have no duration, and are reloaded rather than changing track when they finish
+        self.playbin.duration()?;
+
+        let current_playlist = self.current_playlist.as_mut()?;
+
+        if current_playlist.tracks.len() < 2 || current_playlist.pause_before_playing.is_some() {
+            return None;
+        }
+
+        let index = current_playlist.next_file_track_index(&self.track_quarantine)?;
+        let track = current_playlist.tracks.get(index)?;
+
+        // The playbin can only switch to the start of a file, so tracks which are seeked to once loaded are loaded as usual
+        if track
+            .start_offset
+            .is_some_and(|start_offset| !start_offset.is_zero())
+            || crate::station::podcast::resume_position(&self.config, &track.url).is_some()
+            || self.yt_dlp.resolves(&track.url)
+        {
+            return None;
+        }
+
+        Some((index, track.url.clone()))
+    }
+
+    /// Queue the next track in the playbin, so that it plays without a gap once the current file finishes.
+    /// Called whenever the next track might have changed, and clears the queued track if it can't be played without a gap
+    fn queue_next_track(&mut self) {
+        self.gapless_track = self.next_gapless_track();
+
+        self.playbin
+            .queue_url(self.gapless_track.as_ref().map(|(_, url)| url.as_str()));
+    }
+
+    /// Once the playbin has switched to the queued track, make it the current track
+    fn follow_gapless_track_change(&mut self) -> Result<(), PipelineError> {
+        let Some(switched_url) = self.playbin.take_switched_url() else {
+            return Ok(());
+        };
+
+        let Some((index, url)) = self
+            .gapless_track
+            .take()
+            .filter(|(_, url)| url.as_str() == switched_url)
+        else {
+            tracing::warn!(%switched_url, "Switched to a track which is no longer queued");
+            return Ok(());
+        };
+
+        tracing::debug!(index, "Playing next track without a gap");
+
+        self.play_station_ident_if_due();
+
+        self.current_playlist
+            .as_mut()
+            .ok_or(NoPlaylist)?
+            .current_track_index = index;
+
+        let track = self
+            .current_playlist
+            .as_ref()
+            .ok_or(NoPlaylist)?
+            .current_track()?;
+
+        // Fades control the volume while they're running
+        if self.sleep_fade.is_none() && self.wake_fade.is_none() {
+            self.playbin.set_volume(self.track_volume(track)).ok();
+        }
+
+        self.mirror_index = 0;
+        self.loaded_url = Some(url.clone());
+
+        self.pipeline_diagnostics_tx
+            .send_if_modified(|diagnostics| diagnostics.track_mirror.take().is_some());
+
+        self.start_track(true)?;
+        self.broadcast_state_change();
+
+        self.queue_next_track();
+
+        #[cfg(feature = "ping")]
+        self.request_ping(url);
+
+        Ok(())
+    }
+
+    async fn smart_goto_previous_track(&mut self) -> Result<(), PipelineError> {
+        // The position is of the previous track until the pending track is loaded, so the pending track is treated as just started
+        if self.pending_track_change.is_some() {
+            return self.skip_track(PlaylistState::goto_previous_track).await;
+        }
+
+        if let Some(track_position) = self.published_state.track_position {
+            if track_position < self.config.smart_goto_previous_track_duration {
+                self.skip_track(PlaylistState::goto_previous_track).await
+            } else {
+                self.seek_to(Duration::ZERO)
+            }
+        } else {
+            Ok(())
+        }
+    }
+
+    #[tracing::instrument(skip(self))]
+    async fn goto_next_track(&mut self) -> Result<(), PipelineError> {
+        let current_playlist = self.current_playlist.as_mut().ok_or(NoPlaylist)?;
+        current_playlist.goto_next_track();
+        current_playlist
+            .skip_quarantined_tracks(&self.track_quarantine, PlaylistState::goto_next_track)?;
+
+        // An SDR station has a single track, so after an error or the end of the stream its tuner must be restarted before the track is played again
+        if let Err(err) = current_playlist.playlist_handle.restart_tuner() {
+            tracing::error!("Failed to restart tuner: {err}");
+        }
+
+        self.play_current_track().await
+    }
+
+    /// Change track in response to a command. The new track index is published immediately, but the track is only loaded once no track change has been requested for `track_change_debounce`, so that holding the next button doesn't reload the pipeline for every track
+    #[tracing::instrument(skip(self, goto_track))]
+    async fn skip_track(
+        &mut self,
+        goto_track: fn(&mut PlaylistState),
+    ) -> Result<(), PipelineError> {
+        let current_playlist = self.current_playlist.as_mut().ok_or(NoPlaylist)?;
+        goto_track(current_playlist);
+        current_playlist.skip_quarantined_tracks(&self.track_quarantine, goto_track)?;
+
+        if self.config.track_change_debounce.is_zero() {
+            return self.play_current_track().await;
+        }
+
+        self.published_state.current_track_index = current_playlist.current_track_index;
+        self.pending_track_change = Some(Instant::now() + self.config.track_change_debounce);
+        self.broadcast_state_change();
+
+        // The current file mustn't be followed by the previously queued track
+        self.queue_next_track();
+
+        Ok(())
+    }
+
+    /// Load the track chosen by `skip_track` once track changes have stopped
+    async fn load_pending_track(&mut self) -> Result<(), PipelineError> {
+        if self
+            .pending_track_change
+            .is_some_and(|load_at| Instant::now() >= load_at)
+        {
+            tracing::debug!("Loading track after track changes stopped");
+
+            self.play_current_track().await
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Start playing the first `seconds` of each track in turn, from the current track
+    #[tracing::instrument(skip(self))]
+    fn start_intro_scan(&mut self, seconds: u32) -> Result<(), Error> {
+        if seconds == 0 {
+            return Err(Error::Rejected(MessageId::IntroScanTooShort.into()));
+        }
+
+        if !self
+            .current_playlist
+            .as_ref()
+            .is_some_and(|current_playlist| current_playlist.tracks.len() > 1)
+        {
+            return Err(Error::Rejected(MessageId::NotEnoughTracksToScan.into()));
+        }
+
+        tracing::info!("Starting intro scan");
+
+        self.published_state.intro_scan = Some(Duration::from_secs(seconds.into()));
+        self.broadcast_state_change();
+
+        Ok(())
+    }
+
+    /// Stop intro scanning, keeping the current track playing. Returns true if scanning was in progress
+    fn stop_intro_scan(&mut self) -> bool {
+        if self.published_state.intro_scan.take().is_none() {
+            return false;
+        }
+
+        tracing::info!("Stopping intro scan");
+
+        self.broadcast_state_change();
+
+        true
+    }
+
+    /// While intro scanning, move to the next track once the current track has played for long enough
+    async fn update_intro_scan(&mut self) -> Result<(), PipelineError> {
+        let Some(intro_duration) = self.published_state.intro_scan else {
+            return Ok(());
+        };
+
+        // The position is of the previous track until the pending track is loaded
+        if self.pending_track_change.is_some() {
+            return Ok(());
+        }
+
+        if !self
+            .track_position()
+            .is_some_and(|track_position| track_position >= intro_duration)
+        {
+            return Ok(());
+        }
+
+        tracing::debug!("Scanning next track");
+
+        // Tracks are scanned from their start, even if they would otherwise resume part way through
+        self.queued_seek = Some(Duration::ZERO);
+
+        self.goto_next_track().await
+    }
+
+    /// Record that the current track failed to play, quarantining it if it has failed too many times
+    fn record_track_failure(&mut self) {
+        let Some(url) = self
+            .current_playlist
+            .as_ref()
+            .and_then(|current_playlist| current_playlist.current_track().ok())
+            .map(|track| track.url.clone())
+        else {
+            return;
+        };
+
+        if self.track_quarantine.record_failure(&url) {
+            tracing::warn!(%url, "Quarantining track");
+
+            let mut quarantined_tracks = Vec::clone(&self.published_state.quarantined_tracks);
+            quarantined_tracks.push(url);
+            self.published_state.quarantined_tracks = Arc::new(quarantined_tracks);
+
+            self.broadcast_state_change();
+        }
+    }
+
+    #[tracing::instrument(skip(self))]
+    async fn goto_nth_track(&mut self, index: usize) -> Result<(), PipelineError> {
+        self.current_playlist
+            .as_mut()
+            .ok_or(NoPlaylist)?
+            .goto_nth_track(index);
+        self.play_current_track().await
+    }
+
+    /// Where the current track starts and ends in its file, which is only not the whole file for tracks which share a file
+    fn current_track_bounds(&self) -> (Duration, Option<Duration>) {
+        self.current_playlist
+            .as_ref()
+            .map_or((Duration::ZERO, None), PlaylistState::current_track_bounds)
+    }
+
+    /// The position in the current track, which is relative to the start of the track for tracks which share a file
+    fn track_position(&self) -> Option<Duration> {
+        let (track_start, _) = self.current_track_bounds();
+
+        self.playbin
+            .position()
+            .map(|position| position.saturating_sub(track_start))
+    }
+
+    fn seek_to(&mut self, position: Duration) -> Result<(), PipelineError> {
+        let (track_start, _) = self.current_track_bounds();
+
+        self.playbin.seek_to(track_start + position)
+    }
+
+    /// Tracks which share a file are played by playing the file, so the current track changes when playback reaches the start of the next track
+    fn follow_tracks_within_file(&mut self) {
+        // The position is of the previous track until the pending track is loaded
+        if self.pending_track_change.is_some() {
+            return;
+        }
+
+        let Some(position) = self.playbin.position() else {
+            return;
+        };
+
+        let Some(current_playlist) = self.current_playlist.as_mut() else {
+            return;
+        };
+
+        let (track_start, _) = current_playlist.current_track_bounds();
+
+        // The position is before the start of the track while seeking to the track
+        if position < track_start {
+            return;
+        }
+
+        let mut has_changed = false;
+
+        while let (_, Some(track_end)) = current_playlist.current_track_bounds() {
+            if position < track_end {
+                break;
+            }
+
+            current_playlist.current_track_index += 1;
+            has_changed = true;
+        }
+
+        if has_changed {
+            tracing::debug!(
+                current_playlist.current_track_index,
+                "Playback reached the next track in the file"
+            );
+
+            self.published_state.current_track_index = current_playlist.current_track_index;
+            self.broadcast_state_change();
+        }
+    }
+
+    /// Preview seeking in the current track in the background, and send the result to the client which requested it
+    fn seek_preview(
+        &self,
+        position: Duration,
+        replies_tx: Option<&mpsc::Sender<crate::ports::SharedEvent>>,
+    ) -> Result<(), PipelineError> {
+        let Some(replies_tx) = replies_tx else {
+            tracing::debug!("Ignoring seek preview from a client which doesn't receive events");
+            return Ok(());
+        };
+
+        let url = self
+            .current_playlist
+            .as_ref()
+            .ok_or(NoPlaylist)?
+            .current_track()?
+            .url
+            .clone();
+
+        let (track_start, _) = self.current_track_bounds();
+
+        self.seek_preview_requests_tx
+            .send_replace(Some(super::seek_preview::Request {
+                url,
+                position: track_start + position,
+                replies_tx: replies_tx.clone(),
+            }));
+
+        Ok(())
+    }
+
+    /// Publish the caps negotiated with the audio sink, so that users can check whether the output is bit-perfect
+    fn update_output_caps(&self) {
+        let output_caps = self.playbin.output_caps().map(ArcStr::from);
+
+        self.pipeline_diagnostics_tx
+            .send_if_modified(|diagnostics| {
+                if diagnostics.output_caps == output_caps {
+                    return false;
+                }
+
+                tracing::info!(?output_caps, "Output caps changed");
+                diagnostics.output_caps = output_caps;
+                true
+            });
+    }
+
+    /// Describe the format of the current track once its audio has been decoded, so that clients can show whether it's lossless
+    fn update_audio_format(&mut self) {
+        let audio_format = super::audio_format::describe(
+            self.audio_codec.as_ref(),
+            self.playbin.source_audio().as_ref(),
+            self.playbin.sink_caps().as_ref(),
+        );
+
+        if audio_format.is_some() && audio_format != self.published_state.audio_format {
+            tracing::debug!(?audio_format, "Audio format changed");
+
+            self.published_state.audio_format = audio_format;
+            self.broadcast_state_change();
+        }
+    }
+
+    /// Apply the station's flag overrides, if it has any. The pipeline must be stopped
+    fn set_playbin_flags(&self, station_index: Option<&StationIndex>) {
+        let pipeline_config = &self.config.pipeline_config;
+
+        let station_flags =
+            station_index.and_then(|index| pipeline_config.station_flags.get(index.as_str()));
+
+        let playbin_flags = self
+            .playbin
+            .set_flags(&pipeline_config.flags, station_flags)
+            .ok()
+            .map(ArcStr::from);
+
+        tracing::debug!(?playbin_flags, "Playbin flags");
+
+        self.pipeline_diagnostics_tx
+            .send_modify(|diagnostics| diagnostics.playbin_flags = playbin_flags);
+    }
+
+    /// Use the buffer duration learned for the station, if it has one. The pipeline must be stopped
+    fn set_buffer_duration(&mut self, station_index: Option<&StationIndex>) {
+        self.adaptive_buffering.start_station(station_index);
+
+        let buffer_duration = self.adaptive_buffering.buffer_duration();
+
+        tracing::debug!(?buffer_duration, "Buffer duration");
+
+        self.playbin.set_buffer_duration(buffer_duration).ok();
+
+        self.pipeline_diagnostics_tx
+            .send_modify(|diagnostics| diagnostics.buffer_duration = buffer_duration);
+    }
+
+    /// Start learning the loudness of the station, and apply the gain learned for it, if it has one
+    fn set_normalisation_gain(&mut self, station_index: Option<&StationIndex>) {
+        self.loudness_normalisation.start_station(station_index);
+
+        self.apply_normalisation_gain();
+    }
+
+    /// Apply the gain learned for the current station, and publish the loudness learned for each station
+    fn apply_normalisation_gain(&self) {
+        let loudness_gain = self.loudness_normalisation.gain();
+
+        tracing::debug!(loudness_gain, "Loudness normalisation gain");
+
+        self.playbin.set_normalisation_gain(loudness_gain).ok();
+
+        let station_loudness = self.loudness_normalisation.stations().clone();
+
+        self.pipeline_diagnostics_tx.send_modify(|diagnostics| {
+            diagnostics.loudness_gain = loudness_gain;
+            diagnostics.station_loudness = station_loudness;
+        });
+    }
+
+    /// Forget the loudness learned for a station, or for all stations, which also removes the gain applied to the current station if it's reset
+    fn reset_station_loudness(&mut self, station_index: Option<&StationIndex>) {
+        self.loudness_normalisation.reset(station_index);
+
+        self.apply_normalisation_gain();
+    }
+
+    /// Run the self test in the background, as it plays a test tone and waits for the network, and send the result to clients
+    fn run_self_test(&self) {
+        let config = self.config.clone();
+        let events_tx = self.events_tx.clone();
+
+        tokio::spawn(async move {
+            let result = crate::self_test::run(config).await;
+
+            // There might not be any connected clients, which is not an error
+            events_tx
+                .send(crate::ports::SharedEvent::new(
+                    rradio_messages::Event::SelfTestResult(result),
+                ))
+                .ok();
+        });
+    }
+
+    fn update_config(&self) -> Result<crate::config::update::Config, Error> {
+        if self.config.update_config.manifest_url.is_none() {
+            return Err(Error::Rejected(MessageId::UpdatesDisabled.into()));
+        }
+
+        Ok(self.config.update_config.clone())
+    }
+
+    fn check_for_update(&self, token: &rradio_messages::SystemCommandToken) -> Result<(), Error> {
+        self.check_system_command_token(token)?;
+
+        let update_config = self.update_config()?;
+        let events_tx = self.events_tx.clone();
+
+        tokio::spawn(async move {
+            let progress = crate::updater::check(&update_config).await;
+
+            // There might not be any connected clients, which is not an error
+            events_tx
+                .send(crate::ports::SharedEvent::new(
+                    rradio_messages::Event::UpdateProgress(progress),
+                ))
+                .ok();
+        });
+
+        Ok(())
+    }
+
+    fn apply_update(&self, token: &rradio_messages::SystemCommandToken) -> Result<(), Error> {
+        self.check_system_command_token(token)?;
+
+        let update_config = self.update_config()?;
+        let events_tx = self.events_tx.clone();
+        let system_actions_tx = self.system_actions_tx.clone();
+
+        tokio::spawn(async move {
+            let send_progress = |progress| {
+                // There might not be any connected clients, which is not an error
+                events_tx
+                    .send(crate::ports::SharedEvent::new(
+                        rradio_messages::Event::UpdateProgress(progress),
+                    ))
+                    .ok();
+            };
+
+            match crate::updater::apply(&update_config, &send_progress).await {
+                Ok(true) => {
+                    // If an action has already been requested, rradio is already shutting down
+                    system_actions_tx.try_send(SystemAction::Restart).ok();
+                }
+                Ok(false) => (),
+                Err(err) => {
+                    tracing::error!("Failed to apply update: {err:#}");
+
+                    send_progress(rradio_messages::UpdateProgress::Failed(
+                        rradio_messages::arcstr::format!("{err:#}"),
+                    ));
+                }
+            }
+        });
+
+        Ok(())
+    }
+
+    /// Browse a `UPnP` container in the background, as media servers can be slow to respond, and send the result to clients
+    fn browse_upnp(&self, root_description_url: String, container_id: Option<String>) {
+        let container_id = container_id.unwrap_or_else(|| String::from("0"));
+        let events_tx = self.events_tx.clone();
+
+        tokio::spawn(async move {
+            let result = match url::Url::parse(&root_description_url) {
+                Ok(url) => crate::station::browse_upnp(url, container_id.clone()).await,
+                Err(err) => Err(anyhow::Error::new(err).context("Bad root description url")),
+            };
+
+            let result = result.map_err(|err| {
+                tracing::error!(
+                    %root_description_url,
+                    %container_id,
+                    "Failed to browse UPnP container: {err:#}"
+                );
+                rradio_messages::arcstr::format!("{err:#}")
+            });
+
+            // There might not be any connected clients, which is not an error
+            events_tx
+                .send(crate::ports::SharedEvent::new(
+                    rradio_messages::Event::UPnPBrowseResult(rradio_messages::UPnPBrowseResult {
+                        root_description_url: root_description_url.into(),
+                        container_id: container_id.into(),
+                        result,
+                    }),
+                ))
+                .ok();
+        });
+    }
+
+    /// Search the configured sources in the background, and send the results to the client which searched
+    fn search(
+        &self,
+        query: String,
+        replies_tx: Option<&mpsc::Sender<crate::ports::SharedEvent>>,
+    ) -> Result<(), Error> {
+        if self.config.search.is_none() {
+            return Err(Error::Rejected(MessageId::SearchDisabled.into()));
+        }
+
+        let Some(replies_tx) = replies_tx else {
+            tracing::debug!("Ignoring search from a client which doesn't receive events");
+            return Ok(());
+        };
+
+        crate::search::start(&self.config, &query.into(), replies_tx);
+
+        Ok(())
+    }
+
+    fn export_current_playlist(
+        &self,
+        format: rradio_messages::PlaylistFormat,
+    ) -> Result<(), PipelineError> {
+        let current_playlist = self.current_playlist.as_ref().ok_or(NoPlaylist)?;
+
+        let title = match self.published_state.current_station.as_ref() {
+            CurrentStation::PlayingStation { title, .. } => title.as_deref(),
+            CurrentStation::NoStation | CurrentStation::FailedToPlayStation { .. } => None,
+        };
+
+        let document = crate::station::playlist_document(
+            format,
+            title,
+            &current_playlist.tracks,
+            current_playlist.playlist_handle.mounted_directory(),
+        );
+
+        // There might not be any connected clients, which is not an error
+        self.events_tx
+            .send(crate::ports::SharedEvent::new(
+                rradio_messages::Event::PlaylistDocument(rradio_messages::PlaylistDocument {
+                    format,
+                    document,
+                }),
+            ))
+            .ok();
+
+        Ok(())
+    }
+
+    fn set_current_station(&mut self, current_station: CurrentStation) {
+        self.published_state.current_station = Arc::new(current_station);
+        self.published_state.tracks_revision = self.published_state.tracks_revision.wrapping_add(1);
+    }
+
+    fn clear_playlist(&mut self) {
+        #[cfg(feature = "ping")]
+        self.clear_ping();
+
+        self.stop_waking_server();
+
+        self.current_playlist = None;
+        self.prerolled_url = None;
+        self.loaded_url = None;
+        self.mirror_index = 0;
+        self.gapless_track = None;
+        self.playbin.queue_url(None);
+        self.pending_track_change = None;
+        self.audio_codec = None;
+        self.published_state.audio_format = None;
+        self.set_current_station(CurrentStation::NoStation);
+        self.published_state.pause_before_playing = None;
+        self.published_state.current_track_index = 0;
+        self.published_state.current_track_tags = Arc::new(None);
+        self.published_state.intro_scan = None;
+
+        self.set_is_muted(false).ok();
+
+        self.broadcast_state_change();
+
+        self.playbin.set_pipeline_state(PipelineState::Null).ok();
+    }
+
+    fn play_error(&mut self, error: Error) {
+        self.clear_playlist();
+
+        match error {
+            Error::Station(error) => {
+                self.set_current_station(CurrentStation::FailedToPlayStation {
+                    message: error.message(),
+                    error,
+                });
+            }
+            Error::Pipeline => (),
+            Error::Rejected(reason) => {
+                tracing::warn!("Command rejected: {}", reason.text);
+                return;
+            }
+        }
+
+        self.broadcast_state_change();
+
+        if let Some(notification) = self.config.notifications.error.clone() {
+            self.play_notification(&notification);
+        }
+    }
+
+    /// Play a notification which isn't part of a playlist, using the notification player if the notification is preloaded.
+    /// If the playbin is used, the volume is restored when the next track is played or the notification finishes
+    fn play_notification(&mut self, notification: &Notification) {
+        let volume = notification.volume.apply(self.published_state.volume);
+
+        if self.notification_player.contains(&notification.url) {
+            self.notification_player
+                .play(&notification.url, volume, self.published_state.is_muted)
+                .ignore_pipeline_error();
+        } else {
+            self.loaded_url = None;
+            self.playbin.set_volume(self.ducked_volume(volume)).ok();
+            self.playbin
+                .play_url(&notification.url)
+                .ignore_pipeline_error();
+        }
+    }
+
+    /// Called when a track of a station which isn't a stream has finished. The ident is mixed with the start of the next track
+    fn play_station_ident_if_due(&mut self) {
+        let CurrentStation::PlayingStation {
+            index: Some(station_index),
+            ..
+        } = self.published_state.current_station.as_ref()
+        else {
+            return;
+        };
+
+        let Some(ident) = self.config.station_idents.get(station_index.as_str()) else {
+            return;
+        };
+
+        let progress = &mut self.station_ident_progress;
+        progress.tracks_finished += 1;
+
+        let is_due = ident
+            .every_tracks
+            .is_some_and(|every_tracks| progress.tracks_finished >= every_tracks.get())
+            || ident
+                .every
+                .is_some_and(|every| progress.since.elapsed() >= every);
+
+        if !is_due {
+            return;
+        }
+
+        *progress = StationIdentProgress::new();
+
+        let notification = &ident.notification;
+
+        tracing::debug!(url = %notification.url, "Playing station ident");
+
+        self.notification_player
+            .play(
+                &notification.url,
+                notification.volume.apply(self.published_state.volume),
+                self.published_state.is_muted,
+            )
+            .ignore_pipeline_error();
+    }
+
+    /// The volume to play `track` at, which differs from the current volume if it's a notification with its own volume
+    fn track_volume(&self, track: &Track) -> i32 {
+        let volume = self.ducked_volume(self.published_state.volume);
+
+        if !track.is_notification {
+            return volume;
+        }
+
+        let notifications = &self.config.notifications;
+
+        [
+            &notifications.playlist_prefix,
+            &notifications.playlist_suffix,
+        ]
+        .iter()
+        .copied()
+        .flatten()
+        .find(|notification| notification.url == track.url)
+        .map_or(volume, |notification| notification.volume.apply(volume))
+    }
+
+    /// Act on a stream which plays the same title over and over, as configured by `title_loop`
+    async fn handle_title_loop(&mut self, title: ArcStr) {
+        use crate::config::title_loop::Action;
+
+        let Some(title_loop_config) = self.config.title_loop.clone() else {
+            return;
+        };
+
+        tracing::warn!(%title, "Stream is looping the same title");
+
+        match title_loop_config.action {
+            Action::Log => (),
+            Action::Notify => self.broadcast_error(
+                ErrorKind::Other,
+                Message::new(
+                    MessageId::TitleLoopDetected,
+                    vec![
+                        title,
+                        rradio_messages::arcstr::format!("{}", title_loop_config.repeat_count),
+                    ],
+                ),
+            ),
+            Action::SwitchTo(station_index) => {
+                if let CurrentStation::PlayingStation {
+                    index: Some(current_index),
+                    ..
+                } = self.published_state.current_station.as_ref()
+                {
+                    if current_index == &station_index {
+                        tracing::warn!("Already playing the fallback station");
+                        return;
+                    }
+                }
+
+                let command = Command::SetChannel(station_index);
+                let is_locked = self.published_state.station_locked;
+
+                if check_station_lock(&command, is_locked, false).is_err() {
+                    tracing::warn!("Not switching to fallback station as the station is locked");
+                    return;
+                }
+
+                tracing::info!(%station_index, "Switching to fallback station");
+
+                if let Err(error) = self.handle_command(command, None).await {
+                    self.play_error(error);
+                }
+            }
+        }
+    }
+
+    fn broadcast_error(&mut self, kind: ErrorKind, message: Message) {
+        self.latest_error_time = Some(Instant::now());
+        self.published_state.latest_error = Arc::new(Some(rradio_messages::LatestError {
+            timestamp: chrono::Utc::now(),
+            error: message.text.clone(),
+            kind,
+            message,
+            since_start: crate::clock::since_start(),
+        }));
+
+        self.broadcast_state_change();
+    }
+
+    fn clear_latest_error(&mut self) {
+        self.published_state.latest_error = Arc::new(None);
 
         self.broadcast_state_change();
+    }
+
+    fn clear_expired_latest_error(&mut self) {
+        let Some(latest_error_clear_time) = self.config.latest_error_clear_time else {
+            return;
+        };
+
+        if self
+            .published_state
+            .latest_error
+            .as_ref()
+            .as_ref()
+            .is_some_and(|latest_error| {
+                // The monotonic clock is used so that errors aren't cleared early or late if the wall clock is corrected
+                latest_error.since_start + latest_error_clear_time < crate::clock::since_start()
+            })
+        {
+            tracing::debug!("Clearing expired latest error");
+            self.clear_latest_error();
+        }
+    }
+
+    fn broadcast_state_change(&mut self) {
+        let (track_start, track_end) = self.current_track_bounds();
+
+        self.published_state.track_duration = track_end
+            .or_else(|| self.playbin.duration())
+            .map(|track_end| track_end.saturating_sub(track_start));
+        self.published_state.track_position = self.track_position();
+        self.published_state.is_muted = self.playbin.is_muted();
+
+        self.new_state_tx.publish(self.published_state.clone());
+    }
+
+    fn create_resume_info(
+        &self,
+        new_station_index: &StationIndex,
+    ) -> Option<(StationIndex, StationResumeInfo)> {
+        let CurrentStation::PlayingStation {
+            index: Some(current_station_index),
+            source_type: current_station_source_type,
+            ..
+        } = self.published_state.current_station.as_ref()
+        else {
+            return None;
+        };
+
+        if new_station_index == current_station_index {
+            return None;
+        }
+
+        match current_station_source_type {
+            rradio_messages::StationType::UrlList | rradio_messages::StationType::Sdr => {
+                return None
+            }
+            rradio_messages::StationType::UPnP
+            | rradio_messages::StationType::CD
+            | rradio_messages::StationType::Usb
+            | rradio_messages::StationType::Share => (),
+        }
+
+        let station_resume_info = StationResumeInfo {
+            track_index: self.published_state.current_track_index,
+            track_position: self.published_state.track_position?,
+            metadata: self.current_playlist.as_ref()?.playlist_metadata.clone(),
+        };
+
+        Some((current_station_index.clone(), station_resume_info))
+    }
+
+    #[tracing::instrument(skip(self))]
+    fn save_resume_info(&mut self, new_station_index: &StationIndex) {
+        let Some((current_station_index, station_resume_info)) =
+            self.create_resume_info(new_station_index)
+        else {
+            return;
+        };
+
+        tracing::debug!(
+            "Saving state for {}: {:?}",
+            current_station_index,
+            station_resume_info
+        );
+
+        self.station_resume_info
+            .insert(current_station_index, station_resume_info);
+    }
+
+    /// Save the current station and track, so that playback resumes from the same place after a restart
+    fn save_position(&self) {
+        if !self.config.resume_on_start {
+            return;
+        }
 
-        let playlist = new_station
-            .into_playlist(
-                resume_info
+        let position = match self.published_state.current_station.as_ref() {
+            CurrentStation::PlayingStation {
+                index: Some(station_index),
+                ..
+            } if crate::station::is_temporary_station(station_index)
+                && !self.config.temporary_stations_config.persist =>
+            {
+                // The station won't exist after a restart, so the previous resume point is kept
+                tracing::debug!("Not saving the position of a temporary station");
+                return;
+            }
+            CurrentStation::PlayingStation {
+                index: Some(station_index),
+                source_type,
+                ..
+            } => Some(SavedPosition {
+                station_index: station_index.clone(),
+                track: match source_type {
+                    rradio_messages::StationType::UrlList | rradio_messages::StationType::Sdr => {
+                        None
+                    }
+                    rradio_messages::StationType::UPnP
+                    | rradio_messages::StationType::CD
+                    | rradio_messages::StationType::Usb
+                    | rradio_messages::StationType::Share => self
+                        .current_playlist
+                        .as_ref()
+                        .and_then(|current_playlist| current_playlist.current_track().ok())
+                        .zip(self.published_state.track_position)
+                        .map(|(track, position)| SavedTrack {
+                            url: track.url.clone(),
+                            index: self.published_state.current_track_index,
+                            position,
+                        }),
+                },
+                metadata: self
+                    .current_playlist
                     .as_ref()
-                    .map(|resume_info| &resume_info.metadata),
-            )
-            .await?;
+                    .and_then(|current_playlist| current_playlist.playlist_metadata.to_saved()),
+            }),
+            CurrentStation::PlayingStation { index: None, .. }
+            | CurrentStation::NoStation
+            | CurrentStation::FailedToPlayStation { .. } => None,
+        };
+
+        tracing::debug!(?position, "Saving position");
+
+        if let Err(err) =
+            saved_position::save(self.config.state_directory.as_str(), position.as_ref())
+        {
+            tracing::error!("{err:#}");
+        }
+    }
+
+    /// Start loading the first track of a URL list station, paused and muted, so that it starts playing sooner once the station has loaded, e.g. after the reachability check.
+    /// The previous station is stopped, so that errors while prerolling aren't handled as errors of the previous station
+    fn preroll_first_track(&mut self, station: &Station) {
+        let Station::UrlList { index, tracks, .. } = station else {
+            return;
+        };
+
+        let Some(first_track) = tracks.first() else {
+            return;
+        };
+
+        // The first track to be played is the playlist prefix notification, or is resumed
+        if (tracks.len() > 1 && self.config.notifications.playlist_prefix.is_some())
+            || index
+                .as_ref()
+                .is_some_and(|index| self.station_resume_info.contains_key(index))
+        {
+            return;
+        }
+
+        // The url of the stream isn't known until yt-dlp has run
+        if self.yt_dlp.resolves(&first_track.url) {
+            return;
+        }
+
+        if let Some(index) = index {
+            self.save_resume_info(index);
+        }
+
+        self.clear_playlist();
+
+        self.set_playbin_flags(index.as_ref());
+        self.set_buffer_duration(index.as_ref());
+        self.set_normalisation_gain(index.as_ref());
+
+        self.notification_player.stop();
+
+        tracing::debug!(url = %first_track.url, "Prerolling first track");
+
+        let prerolled = self.playbin.set_is_muted(true).and_then(|()| {
+            self.playbin.set_url(&first_track.url)?;
+            self.playbin.set_pipeline_state(PipelineState::Paused)
+        });
+
+        if prerolled.is_ok() {
+            self.prerolled_url = Some(first_track.url.clone());
+        } else {
+            self.playbin.set_pipeline_state(PipelineState::Null).ok();
+        }
+    }
+
+    /// Reports the progress of loading the station with the given index to clients
+    fn load_progress(&self, index: Option<StationIndex>) -> crate::station::LoadProgress<'static> {
+        let events_tx = self.events_tx.clone();
+
+        crate::station::LoadProgress::new(index, move |progress| {
+            // There might not be any connected clients, which is not an error
+            events_tx
+                .send(crate::ports::SharedEvent::new(
+                    rradio_messages::Event::StationLoadProgress(progress),
+                ))
+                .ok();
+        })
+    }
+
+    /// Wake up the server which hosts `station` in the background, and play `station` once the server is awake, or has failed to wake up.
+    /// Waiting can take a long time, so is reported as station load progress, and commands and alarms are handled in the meantime
+    fn wake_server(&mut self, server: crate::station::WakeOnLanServer, station: Station) {
+        self.stop_waking_server();
+
+        let id = self.last_waking_server_id.wrapping_add(1);
+        self.last_waking_server_id = id;
+
+        let mut progress = self.load_progress(station.index().cloned());
+        let servers_awake_tx = self.servers_awake_tx.clone();
+
+        let task = tokio::spawn(async move {
+            server.wake(|| progress.waking_server(server.host())).await;
+
+            // If the controller has stopped, the station isn't played
+            servers_awake_tx.send(id).await.ok();
+        });
+
+        self.waking_server = Some(WakingServer { id, station, task });
+    }
+
+    /// Playing another station, or stopping, cancels waiting for a server to wake up
+    fn stop_waking_server(&mut self) {
+        if let Some(waking_server) = self.waking_server.take() {
+            waking_server.task.abort();
+        }
+    }
+
+    async fn handle_server_awake(&mut self, id: u64) -> Result<(), Error> {
+        match self.waking_server.take() {
+            Some(waking_server) if waking_server.id == id => {
+                self.start_station(waking_server.station).await
+            }
+            // The server woke up after its station was replaced
+            waking_server => {
+                self.waking_server = waking_server;
+                Ok(())
+            }
+        }
+    }
+
+    /// Check that `station` is reachable, prerolling its first track in the meantime, and play it
+    async fn start_station(&mut self, station: Station) -> Result<(), Error> {
+        self.preroll_first_track(&station);
+
+        if let Some(timeout) = self.config.reachability_check_timeout {
+            station.check_reachability(timeout, &self.resolver).await?;
+        }
+
+        self.play_station(station).await
+    }
+
+    #[tracing::instrument(skip(self))]
+    async fn play_station(&mut self, new_station: Station) -> Result<(), Error> {
+        self.stop_waking_server();
+
+        if let Some(index) = new_station.index() {
+            self.save_resume_info(index);
+        }
+
+        let resume_info = new_station
+            .index()
+            .and_then(|index| self.station_resume_info.remove(index));
+
+        // The position saved on shutdown is only resumed by the first station to be played
+        let mut saved_position = self
+            .saved_position
+            .take()
+            .filter(|saved_position| new_station.index() == Some(&saved_position.station_index));
+
+        let saved_metadata = saved_position
+            .as_mut()
+            .and_then(|saved_position| saved_position.metadata.take())
+            .map(PlaylistMetadata::from_saved);
+
+        // Keep the previous station's device mounted until the new station has loaded, so that the mount can be reused if the new station is on the same device
+        let _previous_playlist_handle = self
+            .current_playlist
+            .take()
+            .map(|playlist| playlist.playlist_handle);
+
+        // If the first track has been prerolled, the previous station has already been cleared
+        if self.prerolled_url.is_none() {
+            self.clear_playlist();
+
+            self.set_playbin_flags(new_station.index());
+            self.set_buffer_duration(new_station.index());
+            self.set_normalisation_gain(new_station.index());
+        }
+
+        self.notification_player.stop();
+
+        self.station_ident_progress = StationIdentProgress::new();
+
+        self.error_recovery_attempts_remaining = self.config.maximum_error_recovery_attempts;
+
+        self.set_current_station(CurrentStation::PlayingStation {
+            index: new_station.index().cloned(),
+            title: new_station.title().map(ArcStr::from),
+            source_type: new_station.station_type(),
+            tracks: None,
+        });
+
+        self.set_is_muted(false).ok();
+
+        self.broadcast_state_change();
+
+        let progress = self.load_progress(new_station.index().cloned());
+
+        let playlist = load_playlist(
+            new_station,
+            resume_info
+                .as_ref()
+                .map(|resume_info| resume_info.metadata.clone())
+                .or(saved_metadata),
+            progress,
+            self.play_statistics.clone(),
+        )
+        .await?;
+
+        tracing::debug!("Station tracks: {:?}", playlist.tracks);
+
+        let playlist_tracks = if playlist.tracks.len() > 1 {
+            let prefix_notification = self
+                .config
+                .notifications
+                .playlist_prefix
+                .clone()
+                .into_iter()
+                .map(|notification| Track::notification(notification.url));
+
+            let suffix_notification = self
+                .config
+                .notifications
+                .playlist_suffix
+                .clone()
+                .into_iter()
+                .map(|notification| Track::notification(notification.url));
+
+            prefix_notification
+                .chain(playlist.tracks)
+                .chain(suffix_notification)
+                .collect()
+        } else {
+            Arc::<[Track]>::from(playlist.tracks)
+        };
+
+        tracing::trace!(
+            "Resume Info for {:?}: {:?}",
+            playlist.station_index,
+            resume_info
+        );
+
+        let saved_track = saved_position
+            .filter(|saved_position| {
+                playlist.station_index.as_ref() == Some(&saved_position.station_index)
+            })
+            .and_then(|saved_position| saved_position.track)
+            .filter(|saved_track| {
+                playlist_tracks
+                    .get(saved_track.index)
+                    .is_some_and(|track| track.url == saved_track.url)
+            });
+
+        let (current_track_index, queued_seek) = match (&resume_info, saved_track) {
+            (Some(resume_info), _) => (resume_info.track_index, Some(resume_info.track_position)),
+            (None, Some(saved_track)) => (saved_track.index, Some(saved_track.position)),
+            (None, None) => (0, None),
+        };
+
+        self.current_playlist = Some(PlaylistState {
+            pause_before_playing: None,
+            tracks: playlist_tracks.clone(),
+            current_track_index,
+            playlist_metadata: playlist.metadata,
+            playlist_handle: playlist.handle,
+        });
+
+        self.set_current_station(CurrentStation::PlayingStation {
+            index: playlist.station_index,
+            title: playlist.station_title.map(ArcStr::from),
+            source_type: playlist.station_type,
+            tracks: Some(playlist_tracks),
+        });
+
+        self.published_state.pause_before_playing = None;
+
+        self.queued_seek = queued_seek;
+
+        self.play_current_track().await?;
+
+        Ok(())
+    }
+
+    /// Add tracks to the current station without interrupting the current track, or play them as a new playlist if nothing is playing
+    #[tracing::instrument(skip(self, tracks))]
+    async fn enqueue_tracks(
+        &mut self,
+        tracks: Vec<Track>,
+        position: rradio_messages::EnqueuePosition,
+    ) -> Result<(), Error> {
+        if tracks.is_empty() {
+            return Err(Error::Rejected(MessageId::NoTracksToEnqueue.into()));
+        }
+
+        let Some(current_playlist) = self.current_playlist.as_mut() else {
+            return self
+                .play_station(Station::UrlList {
+                    index: None,
+                    title: Some(String::from("Queue")),
+                    tracks,
+                })
+                .await;
+        };
+
+        let after_current_track = current_playlist.current_track_index + 1;
+
+        // Tracks are never inserted before the current track, so the current track index is unchanged
+        let insert_index = match position {
+            rradio_messages::EnqueuePosition::Next => after_current_track,
+            rradio_messages::EnqueuePosition::End => {
+                let suffix_length = current_playlist
+                    .tracks
+                    .iter()
+                    .rev()
+                    .take_while(|track| track.is_notification)
+                    .count();
+
+                (current_playlist.tracks.len() - suffix_length).max(after_current_track)
+            }
+        };
+
+        tracing::info!(count = tracks.len(), insert_index, "Enqueueing tracks");
+
+        current_playlist.tracks = current_playlist.tracks[..insert_index]
+            .iter()
+            .cloned()
+            .chain(tracks)
+            .chain(current_playlist.tracks[insert_index..].iter().cloned())
+            .collect();
+
+        self.publish_current_tracks()?;
+
+        Ok(())
+    }
+
+    /// Remove a track from the current station without interrupting the current track
+    #[tracing::instrument(skip(self))]
+    fn remove_track(&mut self, index: usize) -> Result<(), Error> {
+        let current_playlist = self
+            .current_playlist
+            .as_mut()
+            .filter(|current_playlist| index < current_playlist.tracks.len())
+            .ok_or_else(|| Error::no_track_at_index(index))?;
+
+        if index == current_playlist.current_track_index {
+            return Err(Error::Rejected(MessageId::CannotRemoveCurrentTrack.into()));
+        }
+
+        tracing::info!("Removing track");
+
+        current_playlist.remove_track(index);
+
+        self.publish_current_tracks()?;
+
+        Ok(())
+    }
 
-        tracing::debug!("Station tracks: {:?}", playlist.tracks);
+    /// Reorder the tracks of the current station without interrupting the current track
+    #[tracing::instrument(skip(self))]
+    fn move_track(&mut self, from: usize, to: usize) -> Result<(), Error> {
+        let Some(current_playlist) = self.current_playlist.as_mut() else {
+            return Err(Error::no_track_at_index(from));
+        };
 
-        let playlist_tracks = if playlist.tracks.len() > 1 {
-            let prefix_notification = self
-                .config
-                .notifications
-                .playlist_prefix
-                .clone()
-                .into_iter()
-                .map(Track::notification);
+        if let Some(&index) = [from, to]
+            .iter()
+            .find(|&&index| index >= current_playlist.tracks.len())
+        {
+            return Err(Error::no_track_at_index(index));
+        }
 
-            let suffix_notification = self
-                .config
-                .notifications
-                .playlist_suffix
-                .clone()
-                .into_iter()
-                .map(Track::notification);
+        tracing::info!("Moving track");
 
-            prefix_notification
-                .chain(playlist.tracks)
-                .chain(suffix_notification)
-                .collect()
-        } else {
-            Arc::<[Track]>::from(playlist.tracks)
-        };
+        current_playlist.move_track(from, to);
 
-        tracing::trace!(
-            "Resume Info for {:?}: {:?}",
-            playlist.station_index,
-            resume_info
-        );
+        self.publish_current_tracks()?;
 
-        self.current_playlist = Some(PlaylistState {
-            pause_before_playing: None,
-            tracks: playlist_tracks.clone(),
-            current_track_index: resume_info
-                .as_ref()
-                .map_or(0, |resume_info| resume_info.track_index),
-            playlist_metadata: playlist.metadata,
-            _playlist_handle: playlist.handle,
-        });
+        Ok(())
+    }
 
-        self.published_state.current_station =
-            Arc::new(rradio_messages::CurrentStation::PlayingStation {
-                index: playlist.station_index,
-                title: playlist.station_title.map(ArcStr::from),
-                source_type: playlist.station_type,
-                tracks: Some(playlist_tracks),
-            });
+    /// Publish the tracks of the current playlist after they've been edited. Only the tracks have changed, so clients are sent a patch
+    fn publish_current_tracks(&mut self) -> Result<(), PipelineError> {
+        let current_playlist = self.current_playlist.as_ref().ok_or(NoPlaylist)?;
 
-        self.published_state.pause_before_playing = None;
+        let CurrentStation::PlayingStation {
+            index,
+            source_type,
+            title,
+            ..
+        } = self.published_state.current_station.as_ref()
+        else {
+            tracing::error!("Current station is not playing");
+            return Err(PipelineError);
+        };
 
-        self.queued_seek = resume_info.map(|resume_info| resume_info.track_position);
+        let current_station = CurrentStation::PlayingStation {
+            index: index.clone(),
+            source_type: *source_type,
+            title: title.clone(),
+            tracks: Some(current_playlist.tracks.clone()),
+        };
 
-        self.play_current_track().await?;
+        self.published_state.current_track_index = current_playlist.current_track_index;
+        self.set_current_station(current_station);
+        self.broadcast_state_change();
+
+        // The track after the current track might have been edited
+        self.queue_next_track();
 
         Ok(())
     }
@@ -444,16 +2502,74 @@ impl Controller {
     fn set_is_muted(&mut self, is_muted: bool) -> Result<(), PipelineError> {
         self.playbin.set_is_muted(is_muted)?;
         self.published_state.is_muted = is_muted;
+        self.update_settings(|settings| settings.is_muted = is_muted);
         Ok(())
     }
 
     #[tracing::instrument(skip(self))]
     fn set_volume(&mut self, volume: i32) -> Result<(), PipelineError> {
-        self.published_state.volume = self.playbin.set_volume(volume)?;
+        let requested_volume = volume;
+        let previous_volume = self.published_state.volume;
+
+        let volume = match self.published_state.quiet_hours_maximum_volume {
+            Some(maximum_volume) if volume > maximum_volume => {
+                // The volume might already be above the maximum if it isn't reduced when quiet hours start, in which case it can be decreased but not increased
+                let limited_volume = maximum_volume.max(volume.min(self.published_state.volume));
+
+                if limited_volume < volume {
+                    tracing::info!(maximum_volume, "Volume limited by quiet hours");
+                }
+
+                limited_volume
+            }
+            _ => volume,
+        };
+
+        let volume = volume.clamp(rradio_messages::VOLUME_MIN, rradio_messages::VOLUME_MAX);
+        self.playbin.set_volume(self.ducked_volume(volume))?;
+        self.published_state.volume = volume;
+        self.update_settings(|settings| settings.volume = Some(volume));
+
         self.broadcast_state_change();
+
+        // The volume didn't change because it was clamped, so let the user know that the command was ignored
+        if volume == previous_volume && volume != requested_volume {
+            self.events_tx
+                .send(crate::ports::SharedEvent::new(
+                    rradio_messages::Event::VolumeAtLimit,
+                ))
+                .ok();
+
+            self.play_volume_limit_notification();
+        }
+
         Ok(())
     }
 
+    /// Mixed with the current track rather than interrupting it, as the user is still listening
+    fn play_volume_limit_notification(&mut self) {
+        let Some(notification) = &self.config.notifications.volume_limit else {
+            return;
+        };
+
+        tracing::debug!(url = %notification.url, "Volume at limit");
+
+        self.notification_player
+            .play(
+                &notification.url,
+                notification.volume.apply(self.published_state.volume),
+                self.published_state.is_muted,
+            )
+            .ignore_pipeline_error();
+    }
+
+    /// Update and persist the settings. The caller is responsible for broadcasting the state change
+    fn update_settings(&mut self, f: impl FnOnce(&mut Settings)) {
+        if self.settings.update(f) {
+            self.published_state.settings = Arc::new(self.settings.settings().clone());
+        }
+    }
+
     #[tracing::instrument(skip(self))]
     fn change_volume(&mut self, direction: i32) -> Result<(), PipelineError> {
         // First round the current volume to the nearest multiple of the volume offset
@@ -467,81 +2583,458 @@ impl Controller {
         self.set_volume(rounded_volume + direction * self.config.volume_offset)
     }
 
-    #[tracing::instrument(skip(self))]
-    async fn handle_command(&mut self, command: Command) -> Result<(), Error> {
+    /// Handle a command, and record it and its outcome in the audit log
+    async fn handle_received_command(
+        &mut self,
+        ReceivedCommand {
+            command,
+            source,
+            is_privileged,
+            replies_tx,
+        }: ReceivedCommand,
+    ) -> Result<(), Error> {
+        let replies_tx = replies_tx.as_ref();
+
+        let Command::Batch(commands) = command else {
+            return self
+                .handle_audited_command(command, source, is_privileged, replies_tx)
+                .await;
+        };
+
+        let total = commands.len();
+        let mut completed = 0;
+        let mut result = Ok(());
+
+        // Commands are received one at a time, so commands from other clients can't run until the batch has finished
+        for command in commands {
+            result = self
+                .handle_audited_command(command, source.clone(), is_privileged, replies_tx)
+                .await;
+
+            if result.is_err() {
+                break;
+            }
+
+            completed += 1;
+        }
+
+        // There might not be any connected clients, which is not an error
+        self.events_tx
+            .send(crate::ports::SharedEvent::new(
+                rradio_messages::Event::BatchResult(rradio_messages::BatchResult {
+                    completed,
+                    total,
+                    error: result.as_ref().err().map(Error::description),
+                    error_message: result.as_ref().err().map(Error::message),
+                }),
+            ))
+            .ok();
+
+        result
+    }
+
+    async fn handle_audited_command(
+        &mut self,
+        command: Command,
+        source: ArcStr,
+        is_privileged: bool,
+        replies_tx: Option<&mpsc::Sender<crate::ports::SharedEvent>>,
+    ) -> Result<(), Error> {
+        let timestamp = chrono::Utc::now();
+        let description = rradio_messages::arcstr::format!("{command:?}");
+
+        let result = match check_station_lock(
+            &command,
+            self.published_state.station_locked,
+            is_privileged,
+        ) {
+            Ok(()) => self.handle_command(command, replies_tx).await,
+            Err(err) => Err(err),
+        };
+
+        self.audit_log.record(rradio_messages::AuditLogEntry {
+            timestamp,
+            source,
+            command: description,
+            error: result.as_ref().err().map(Error::description),
+        });
+
+        result
+    }
+
+    /// Play statistics are only recorded for stations which play music from a library
+    fn is_playing_library_station(&self) -> bool {
+        matches!(
+            self.published_state.current_station.as_ref(),
+            CurrentStation::PlayingStation {
+                source_type: rradio_messages::StationType::Usb
+                    | rradio_messages::StationType::UPnP
+                    | rradio_messages::StationType::Share,
+                ..
+            }
+        )
+    }
+
+    fn send_play_statistics(&self, count: usize) {
+        let mut statistics = self.play_statistics.most_recently_played(count);
+        crate::redact::play_statistics(&mut statistics);
+
+        // There might not be any connected clients, which is not an error
+        self.events_tx
+            .send(crate::ports::SharedEvent::new(
+                rradio_messages::Event::PlayStatistics(statistics),
+            ))
+            .ok();
+    }
+
+    fn send_audit_log(&self, count: usize) {
+        // There might not be any connected clients, which is not an error
+        self.events_tx
+            .send(crate::ports::SharedEvent::new(
+                rradio_messages::Event::AuditLog(self.audit_log.recent(count)),
+            ))
+            .ok();
+    }
+
+    fn lock_station(&mut self, is_locked: bool) {
+        if self.published_state.station_locked == is_locked {
+            return;
+        }
+
+        tracing::info!(is_locked, "Station lock changed");
+
+        self.published_state.station_locked = is_locked;
+        self.broadcast_state_change();
+    }
+
+    /// System commands, which power off, reboot or update the device, are only allowed with the configured token
+    fn check_system_command_token(
+        &self,
+        token: &rradio_messages::SystemCommandToken,
+    ) -> Result<(), Error> {
+        let Some(expected_token) = &self.config.system_command_token else {
+            return Err(Error::Rejected(MessageId::SystemCommandsDisabled.into()));
+        };
+
+        if !crate::system_action::tokens_match(&token.0, expected_token.as_str()) {
+            return Err(Error::Rejected(MessageId::BadSystemCommandToken.into()));
+        }
+
+        Ok(())
+    }
+
+    fn request_system_action(
+        &self,
+        token: &rradio_messages::SystemCommandToken,
+        action: SystemAction,
+    ) -> Result<(), Error> {
+        self.check_system_command_token(token)?;
+
+        tracing::info!(?action, "System action requested");
+
+        // If an action has already been requested, rradio is already shutting down
+        self.system_actions_tx.try_send(action).ok();
+
+        Ok(())
+    }
+
+    /// Handle a command. Events which are only for the client which sent the command are sent to `replies_tx`, if the client receives events
+    #[tracing::instrument(skip(self, replies_tx))]
+    async fn handle_command(
+        &mut self,
+        command: Command,
+        replies_tx: Option<&mpsc::Sender<crate::ports::SharedEvent>>,
+    ) -> Result<(), Error> {
         tracing::debug!("Processing Command");
+
+        // Commands which only query the player don't show that the user is awake
+        if !matches!(
+            command,
+            Command::DebugPipeline
+                | Command::GetSettings
+                | Command::ClearLatestError
+                | Command::SetEventFormat(_)
+                | Command::SetEventFormatV2(_)
+                | Command::SeekPreview(_)
+                | Command::ExportCurrentPlaylist { .. }
+                | Command::BrowseUPnP { .. }
+                | Command::RequestAuditLog { .. }
+                | Command::RunSelfTest
+                | Command::RequestPlayStatistics { .. }
+                | Command::CheckForUpdate { .. }
+                | Command::SetBusTrace(_)
+        ) {
+            self.cancel_fades();
+            self.idle_tracker.active(Instant::now());
+        }
+
         match command {
             Command::SetChannel(index) => {
-                self.play_station(Station::load(&self.config, index)?)
+                let station = Station::load_by_index_or_alias(&self.config, index)?;
+
+                if let Some(server) = station.wake_on_lan_server(&self.config.wake_on_lan_targets) {
+                    self.wake_server(server, station);
+                    return Ok(());
+                }
+
+                self.start_station(station).await?;
+                Ok(())
+            }
+            Command::PlayPause => {
+                // Pressing play while scanning chooses the current track, which keeps playing
+                if self.stop_intro_scan() {
+                    Ok(())
+                } else {
+                    self.play_pause()
+                }
+            }
+            Command::SmartPreviousItem => self.smart_goto_previous_track().await,
+            Command::PreviousItem => self.skip_track(PlaylistState::goto_previous_track).await,
+            Command::NextItem => self.skip_track(PlaylistState::goto_next_track).await,
+            Command::NthItem(index) => self.goto_nth_track(index).await,
+            Command::SeekTo(position) => self.seek_to(position),
+            Command::SeekBackwards(offset) => self.playbin.position().map_or(Ok(()), |position| {
+                self.seek_to(position.saturating_sub(offset))
+            }),
+            Command::SeekForwards(offset) => self.playbin.position().map_or(Ok(()), |position| {
+                self.seek_to(position.saturating_add(offset))
+            }),
+            Command::SetIsMuted(is_muted) => {
+                self.set_is_muted(is_muted)?;
+                self.broadcast_state_change();
+                Ok(())
+            }
+            Command::ToggleIsMuted => {
+                let is_muted = self.playbin.toggle_is_muted()?;
+                self.published_state.is_muted = is_muted;
+                self.update_settings(|settings| settings.is_muted = is_muted);
+                self.broadcast_state_change();
+                Ok(())
+            }
+            Command::VolumeUp => self.change_volume(1),
+            Command::VolumeDown => self.change_volume(-1),
+            Command::SetVolume(volume) => self.set_volume(volume),
+            Command::SetPlaylist { title, tracks } => {
+                let tracks = tracks.into_iter().map(Track::from).collect::<Vec<_>>();
+
+                // Keep the playlist as a temporary station, so that it can be listed and selected again
+                let index = crate::station::add_temporary_station(
+                    &self.config,
+                    title.clone(),
+                    tracks.clone(),
+                );
+
+                self.play_station(Station::UrlList {
+                    index: Some(index),
+                    title: Some(title),
+                    tracks,
+                })
+                .await?;
+                Ok(())
+            }
+            Command::Eject => {
+                if let CurrentStation::PlayingStation {
+                    source_type: rradio_messages::StationType::CD,
+                    ..
+                } = self.published_state.current_station.as_ref()
+                {
+                    self.clear_playlist();
+                }
+
+                #[cfg(feature = "cd")]
+                {
+                    self.station_resume_info
+                        .remove(self.config.cd_config.station.as_str());
+
+                    if let Err(err) =
+                        crate::station::eject_cd(self.config.cd_config.device.as_str()).await
+                    {
+                        self.broadcast_error(
+                            ErrorKind::Device,
+                            Message::new(
+                                MessageId::CdEjectFailed,
+                                vec![rradio_messages::arcstr::format!("{err}")],
+                            ),
+                        );
+                    }
+
+                    Ok(())
+                }
+
+                #[cfg(not(feature = "cd"))]
+                {
+                    tracing::warn!("Ignoring Eject");
+
+                    Ok(())
+                }
+            }
+            Command::DebugPipeline => {
+                self.playbin.debug_pipeline();
+                Ok(())
+            }
+            Command::GetSettings => {
+                self.settings.reload();
+                self.published_state.settings = Arc::new(self.settings.settings().clone());
+                self.broadcast_state_change();
+                Ok(())
+            }
+            Command::SetSetting(setting) => match setting {
+                Setting::Volume(volume) => self.set_volume(volume),
+                Setting::IsMuted(is_muted) => {
+                    self.set_is_muted(is_muted)?;
+                    self.broadcast_state_change();
+                    Ok(())
+                }
+            },
+            Command::ClearLatestError => {
+                self.clear_latest_error();
+                Ok(())
+            }
+            Command::SetEventFormat(_) | Command::SetEventFormatV2(_) => {
+                tracing::warn!("Event format can only be set on the binary port");
+                Ok(())
+            }
+            Command::SeekPreview(position) => self.seek_preview(position, replies_tx),
+            Command::ExportCurrentPlaylist { format } => self.export_current_playlist(format),
+            Command::PlayPath {
+                source: rradio_messages::PathSource::Usb,
+                path,
+                recursive,
+            } => {
+                #[cfg(feature = "usb")]
+                {
+                    self.play_station(Station::usb_path(&self.config, path, recursive))
+                        .await?;
+                    Ok(())
+                }
+
+                #[cfg(not(feature = "usb"))]
+                {
+                    tracing::warn!(?path, recursive, "Ignoring PlayPath");
+                    return Err(Error::Station(
+                        rradio_messages::MountError::UsbNotEnabled.into(),
+                    ));
+                }
+            }
+            Command::PlayPath {
+                source: rradio_messages::PathSource::Share,
+                path,
+                recursive,
+            } => {
+                #[cfg(feature = "share")]
+                {
+                    self.play_station(Station::share_path(&self.config, path, recursive)?)
+                        .await?;
+                    Ok(())
+                }
+
+                #[cfg(not(feature = "share"))]
+                {
+                    tracing::warn!(?path, recursive, "Ignoring PlayPath");
+                    return Err(Error::Station(
+                        rradio_messages::MountError::ShareNotEnabled.into(),
+                    ));
+                }
+            }
+            Command::SleepTimer(duration) => {
+                tracing::info!(?duration, "Setting sleep timer");
+                self.sleep_at = duration.map(|duration| Instant::now() + duration);
+                Ok(())
+            }
+            Command::BrowseUPnP {
+                root_description_url,
+                container_id,
+            } => {
+                self.browse_upnp(root_description_url, container_id);
+                Ok(())
+            }
+            Command::RequestAuditLog { count } => {
+                self.send_audit_log(count);
+                Ok(())
+            }
+            Command::RunSelfTest => {
+                self.run_self_test();
+                Ok(())
+            }
+            Command::RequestPlayStatistics { count } => {
+                self.send_play_statistics(count);
+                Ok(())
+            }
+            Command::SystemPowerOff { token } => {
+                self.request_system_action(&token, SystemAction::PowerOff)?;
+                Ok(())
+            }
+            Command::SystemReboot { token } => {
+                self.request_system_action(&token, SystemAction::Reboot)?;
+                Ok(())
+            }
+            Command::EnqueueTracks { tracks, position } => {
+                self.enqueue_tracks(tracks.into_iter().map(Track::from).collect(), position)
                     .await?;
                 Ok(())
             }
-            Command::PlayPause => self.play_pause(),
-            Command::SmartPreviousItem => self.smart_goto_previous_track().await,
-            Command::PreviousItem => self.goto_previous_track().await,
-            Command::NextItem => self.goto_next_track().await,
-            Command::NthItem(index) => self.goto_nth_track(index).await,
-            Command::SeekTo(position) => self.seek_to(position),
-            Command::SeekBackwards(offset) => self.playbin.position().map_or(Ok(()), |position| {
-                self.seek_to(position.saturating_sub(offset))
-            }),
-            Command::SeekForwards(offset) => self.playbin.position().map_or(Ok(()), |position| {
-                self.seek_to(position.saturating_add(offset))
-            }),
-            Command::SetIsMuted(is_muted) => {
-                self.set_is_muted(is_muted)?;
-                self.broadcast_state_change();
+            Command::CheckForUpdate { token } => {
+                self.check_for_update(&token)?;
+                Ok(())
+            }
+            Command::ApplyUpdate { token } => {
+                self.apply_update(&token)?;
+                Ok(())
+            }
+            Command::SetAudioOutput(name) => {
+                self.set_audio_output(&name)?;
+                Ok(())
+            }
+            Command::Batch(_) => {
+                return Err(Error::Rejected(MessageId::NestedBatch.into()));
+            }
+            Command::SetBusTrace(bus_trace) => {
+                tracing::info!(bus_trace, "Setting bus trace");
+                self.bus_trace = bus_trace;
+                Ok(())
+            }
+            Command::Search { query } => {
+                self.search(query, replies_tx)?;
+                Ok(())
+            }
+            Command::PlayPodcastFeed { title, feed_url } => {
+                crate::search::play_podcast_feed(
+                    title,
+                    feed_url,
+                    self.commands_tx.with_source("podcast feed"),
+                );
+                Ok(())
+            }
+            Command::RemoveTrack(index) => {
+                self.remove_track(index)?;
+                Ok(())
+            }
+            Command::MoveTrack { from, to } => {
+                self.move_track(from, to)?;
+                Ok(())
+            }
+            Command::StartIntroScan { seconds } => {
+                self.start_intro_scan(seconds)?;
                 Ok(())
             }
-            Command::ToggleIsMuted => {
-                self.published_state.is_muted = self.playbin.toggle_is_muted()?;
-                self.broadcast_state_change();
+            Command::StopIntroScan => {
+                self.stop_intro_scan();
                 Ok(())
             }
-            Command::VolumeUp => self.change_volume(1),
-            Command::VolumeDown => self.change_volume(-1),
-            Command::SetVolume(volume) => self.set_volume(volume),
-            Command::SetPlaylist { title, tracks } => {
-                self.play_station(Station::UrlList {
-                    index: None,
-                    title: Some(title),
-                    tracks: tracks.into_iter().map(Track::from).collect(),
-                })
-                .await?;
+            Command::SetAlarm(alarm) => {
+                self.set_alarm(alarm)?;
                 Ok(())
             }
-            Command::Eject => {
-                if let CurrentStation::PlayingStation {
-                    source_type: rradio_messages::StationType::CD,
-                    ..
-                } = self.published_state.current_station.as_ref()
-                {
-                    self.clear_playlist();
-                }
-
-                #[cfg(feature = "cd")]
-                {
-                    self.station_resume_info
-                        .remove(self.config.cd_config.station.as_str());
-
-                    if let Err(err) =
-                        crate::station::eject_cd(self.config.cd_config.device.as_str()).await
-                    {
-                        self.broadcast_error(format!("{err}"));
-                    }
-
-                    Ok(())
-                }
-
-                #[cfg(not(feature = "cd"))]
-                {
-                    tracing::warn!("Ignoring Eject");
-
-                    Ok(())
-                }
+            Command::RemoveAlarm { hour, minute } => {
+                self.remove_alarm(hour, minute)?;
+                Ok(())
             }
-            Command::DebugPipeline => {
-                self.playbin.debug_pipeline();
+            Command::ResetStationLoudness { station } => {
+                self.reset_station_loudness(station.as_ref());
+                Ok(())
+            }
+            Command::LockStation(is_locked) => {
+                self.lock_station(is_locked);
                 Ok(())
             }
         }
@@ -557,6 +3050,10 @@ impl Controller {
     ) -> Result<(), PipelineError> {
         use gstreamer::MessageView;
 
+        if self.bus_trace {
+            trace_bus_message(message);
+        }
+
         match message.view() {
             MessageView::Buffering(buffering) => {
                 tracing::trace!(
@@ -569,6 +3066,19 @@ impl Controller {
                     Ok(buffering) => {
                         self.published_state.buffering = buffering;
                         self.broadcast_state_change();
+
+                        if self.adaptive_buffering.record_buffering(buffering) {
+                            let station_buffering = self.adaptive_buffering.stations().clone();
+                            let buffer_duration = self.adaptive_buffering.buffer_duration();
+
+                            // A longer buffer takes effect from the next track
+                            self.playbin.set_buffer_duration(buffer_duration).ok();
+
+                            self.pipeline_diagnostics_tx.send_modify(|diagnostics| {
+                                diagnostics.buffer_duration = buffer_duration;
+                                diagnostics.station_buffering = station_buffering;
+                            });
+                        }
                     }
                     Err(_err) => {
                         tracing::warn!("Bad buffering value: {}", buffering.percent());
@@ -577,6 +3087,27 @@ impl Controller {
 
                 Ok(())
             }
+            MessageView::Element(element) => {
+                let Some(loudness) = element
+                    .structure()
+                    .and_then(loudness_normalisation::measured_loudness)
+                else {
+                    return Ok(());
+                };
+
+                tracing::trace!(loudness, "Measured loudness");
+
+                // The learned loudness is published when it's saved, rather than every time it's measured
+                if self.loudness_normalisation.record_loudness(loudness) {
+                    let station_loudness = self.loudness_normalisation.stations().clone();
+
+                    self.pipeline_diagnostics_tx.send_modify(|diagnostics| {
+                        diagnostics.station_loudness = station_loudness;
+                    });
+                }
+
+                Ok(())
+            }
             MessageView::Tag(tag) => {
                 let mut new_tags = self
                     .published_state
@@ -585,20 +3116,55 @@ impl Controller {
                     .clone()
                     .unwrap_or_default();
 
+                let station_index = match self.published_state.current_station.as_ref() {
+                    CurrentStation::PlayingStation { index, .. } => index.as_ref(),
+                    CurrentStation::NoStation | CurrentStation::FailedToPlayStation { .. } => None,
+                };
+
+                let filter = |field: TagField, value| {
+                    crate::tag::filter_value(&self.config.tag_filters, station_index, field, value)
+                };
+
+                let encoding = crate::tag::Encoding::for_station(
+                    &self.config.tag_encoding_config,
+                    station_index,
+                );
+
+                let mut received_title = None;
+                let mut received_audio_codec = None;
+
                 for (i, (name, value)) in tag.tags().as_ref().iter().enumerate() {
-                    let tag = Tag::from_value(name, &value);
+                    let tag = Tag::from_value(name, &value, encoding);
                     tracing::trace!(target: submodule_path!(tag), "{} - {:?}", i, tag);
 
                     match tag {
-                        Ok(Tag::Title(title)) => new_tags.title = Some(title),
+                        Ok(Tag::Title(title)) => {
+                            received_title = Some(title.clone());
+                            new_tags.title = filter(TagField::Title, title);
+                        }
                         Ok(Tag::Organisation(organisation)) => {
-                            new_tags.organisation = Some(organisation);
+                            new_tags.organisation = filter(TagField::Organisation, organisation);
+                        }
+                        Ok(Tag::Artist(artist)) => {
+                            new_tags.artist = filter(TagField::Artist, artist);
+                        }
+                        Ok(Tag::Album(album)) => {
+                            new_tags.album = filter(TagField::Album, album);
+                        }
+                        Ok(Tag::TrackNumber(track_number)) => {
+                            new_tags.track_number = Some(track_number);
+                        }
+                        Ok(Tag::DiscNumber(disc_number)) => {
+                            new_tags.disc_number = Some(disc_number);
+                        }
+                        Ok(Tag::Genre(genre)) => {
+                            new_tags.genre = filter(TagField::Genre, genre);
                         }
-                        Ok(Tag::Artist(artist)) => new_tags.artist = Some(artist),
-                        Ok(Tag::Album(album)) => new_tags.album = Some(album),
-                        Ok(Tag::Genre(genre)) => new_tags.genre = Some(genre),
                         Ok(Tag::Image(image)) => new_tags.image = Some(image),
-                        Ok(Tag::Comment(comment)) => new_tags.comment = Some(comment),
+                        Ok(Tag::Comment(comment)) => {
+                            new_tags.comment = filter(TagField::Comment, comment);
+                        }
+                        Ok(Tag::AudioCodec(codec)) => received_audio_codec = Some(codec),
                         Ok(Tag::Unknown { .. }) => (),
                         Err(err) => tracing::warn!("Failed to decode tag: {err}"),
                     }
@@ -613,6 +3179,19 @@ impl Controller {
                     }
                 }
 
+                if let Some(codec) = received_audio_codec {
+                    if self.audio_codec.as_ref() != Some(&codec) {
+                        self.audio_codec = Some(codec);
+                        self.update_audio_format();
+                    }
+                }
+
+                if let Some(title) = received_title {
+                    if self.title_loop_detector.record_title(&title) {
+                        self.handle_title_loop(title).await;
+                    }
+                }
+
                 Ok(())
             }
             MessageView::StateChanged(state_changed) => {
@@ -631,9 +3210,14 @@ impl Controller {
                     );
 
                     if let gstreamer::State::Playing = new_state {
+                        self.update_output_caps();
+                        self.update_audio_format();
+
                         if let Some(position) = self.queued_seek.take() {
                             self.seek_to(position)?;
                         }
+
+                        self.queue_next_track();
                     }
                 }
                 Ok(())
@@ -641,9 +3225,16 @@ impl Controller {
             MessageView::Eos(..) => {
                 tracing::debug!(target: submodule_path!(end_of_stream), "");
 
-                if let Some(current_playlist) = &self.current_playlist {
+                // The file has finished, so it's reloaded even if the next track is in the same file
+                self.loaded_url = None;
+
+                if let Some(current_playlist) = &mut self.current_playlist {
                     if self.published_state.track_duration.is_some() {
+                        // If the current track wasn't updated before the file finished
+                        current_playlist.goto_last_track_in_file();
+
                         if current_playlist.tracks.len() > 1 {
+                            self.play_station_ident_if_due();
                             self.goto_next_track().await
                         } else {
                             self.clear_playlist();
@@ -673,6 +3264,9 @@ impl Controller {
                         }
                     }
                 } else {
+                    // A notification has finished, and might have been played at a different volume
+                    self.playbin
+                        .set_volume(self.ducked_volume(self.published_state.volume))?;
                     Ok(self.playbin.set_pipeline_state(PipelineState::Null)?)
                 }
             }
@@ -721,14 +3315,35 @@ impl Controller {
                     "gstreamer error"
                 );
 
-                let latest_error_time = self
-                    .published_state
-                    .latest_error
-                    .as_ref()
-                    .as_ref()
-                    .map(|latest_error| latest_error.timestamp);
+                let is_track_error = glib_error.kind::<gstreamer::StreamError>().is_some()
+                    || glib_error.kind::<gstreamer::ResourceError>().is_some();
+
+                // Broadcasters often publish several relays of a stream, so the next relay is tried before the track is treated as failed
+                if is_track_error && self.has_untried_mirror() {
+                    self.playbin.set_pipeline_state(PipelineState::Null)?;
+
+                    // Drain the message queue, thus draining potential other error messages from the failed url
+                    while gstreamer_messages.try_recv().is_ok() {}
+
+                    tracing::warn!(mirror = self.mirror_index + 1, "Playing track from mirror");
+
+                    return self.play_next_mirror().await;
+                }
+
+                let latest_error_time = self.latest_error_time;
+
+                let kind =
+                    super::error_kind::from_gstreamer_error(&glib_error, debug_message.as_deref());
+
+                self.broadcast_error(
+                    kind,
+                    Message::new(
+                        MessageId::PlaybackError,
+                        vec![rradio_messages::arcstr::format!("error={error:?} code={code:?} error_message={error_message:?} debug_message={debug_message:?}")],
+                    ),
+                );
 
-                self.broadcast_error(format!("gstreamer error: error={error:?} code={code:?} error_message={error_message:?} debug_message={debug_message:?}"));
+                self.record_track_failure();
 
                 if self
                     .config
@@ -736,8 +3351,7 @@ impl Controller {
                     .zip(latest_error_time)
                     .is_some_and(
                         |(error_recovery_attempt_count_reset_time, latest_error_time)| {
-                            (latest_error_time + error_recovery_attempt_count_reset_time)
-                                < chrono::Utc::now()
+                            latest_error_time.elapsed() > error_recovery_attempt_count_reset_time
                         },
                     )
                 {
@@ -796,10 +3410,62 @@ impl Controller {
 
                 Ok(())
             }
+            #[cfg(feature = "watchdog")]
+            MessageView::Application(application) => {
+                if let Some(watchdog) = &self.watchdog {
+                    if application
+                        .structure()
+                        .is_some_and(|structure| structure.has_name(super::watchdog::PROBE_NAME))
+                    {
+                        watchdog.bus_is_responsive();
+                    }
+                }
+
+                Ok(())
+            }
+            MessageView::StreamStart(..) => self.follow_gapless_track_change(),
+            // Whether the current track is a stream is only known once its duration is known
+            MessageView::DurationChanged(..) => {
+                self.queue_next_track();
+                Ok(())
+            }
+            MessageView::RequestState(request_state) => {
+                // Audio sinks such as pulsesink request PAUSED when corked and PLAYING when uncorked
+                self.handle_audio_interruption(request_state.requested_state())
+            }
             _ => Ok(()),
         }
     }
 
+    /// Tell clients that a boot milestone has been reached, and play its notification unless a station is playing
+    fn handle_boot_milestone(&mut self, milestone: rradio_messages::BootMilestone) {
+        use rradio_messages::BootMilestone;
+
+        // There might not be any connected clients, which is not an error
+        self.events_tx
+            .send(crate::ports::SharedEvent::new(
+                rradio_messages::Event::BootProgress(milestone),
+            ))
+            .ok();
+
+        if self.current_playlist.is_some() {
+            return;
+        }
+
+        let notifications = &self.config.notifications;
+
+        let notification = match milestone {
+            BootMilestone::ConfigLoaded => &notifications.config_loaded,
+            BootMilestone::NetworkUp => &notifications.network_up,
+            BootMilestone::PortsListening => &notifications.ports_listening,
+            BootMilestone::StationsValidated => &notifications.stations_validated,
+        };
+
+        if let Some(notification) = notification.clone() {
+            self.play_notification(&notification);
+        }
+    }
+
     #[cfg(feature = "ping")]
     fn handle_ping_times(&mut self, ping_times: rradio_messages::PingTimes) {
         self.published_state.ping_times = ping_times;
@@ -808,16 +3474,52 @@ impl Controller {
 }
 
 enum Message {
-    Command(Command),
+    Command(ReceivedCommand),
     FromGStreamer(gstreamer::Message),
+    BootMilestone(rradio_messages::BootMilestone),
     #[cfg(feature = "ping")]
     PingTimes(PingTimes),
+    HeadphonesConnected(bool),
+    ServerAwake(u64),
+}
+
+/// Reject commands which lock the station, or which change the station while it's locked, unless they're privileged.
+/// Alarms, queued tracks and paths would change the station later, so are also rejected while the station is locked
+fn check_station_lock(
+    command: &Command,
+    is_locked: bool,
+    is_privileged: bool,
+) -> Result<(), Error> {
+    if is_privileged {
+        return Ok(());
+    }
+
+    match command {
+        Command::LockStation(_) => Err(Error::Rejected(MessageId::NotPrivileged.into())),
+        Command::SetChannel(_)
+        | Command::SetPlaylist { .. }
+        | Command::Eject
+        | Command::PlayPath { .. }
+        | Command::PlayPodcastFeed { .. }
+        | Command::EnqueueTracks { .. }
+        | Command::SetAlarm(_)
+        | Command::RemoveAlarm { .. }
+            if is_locked =>
+        {
+            Err(Error::Rejected(MessageId::StationLocked.into()))
+        }
+        _ => Ok(()),
+    }
 }
 
-/// Initialise the gstreamer pipeline, and process incoming commands
+/// Initialise the gstreamer pipeline, and process incoming commands until `shutdown_signal` is signalled, at which point the playback position is saved
 #[allow(clippy::too_many_lines)]
 pub fn run(
     config: Config,
+    safe_mode: Option<ArcStr>,
+    port_count: usize,
+    mut shutdown_signal: futures_util::future::Shared<crate::task::ShutdownSignal>,
+    system_actions_tx: mpsc::Sender<SystemAction>,
 ) -> anyhow::Result<(
     impl std::future::Future<Output = ()>,
     PartialPortChannels<crate::ports::NoShutdownSignal>,
@@ -826,11 +3528,55 @@ pub fn run(
     let (playbin, bus_stream) = Playbin::new(&config)
         .map_err(|PipelineError| anyhow::anyhow!("Failed to create playbin"))?;
 
-    if let Some(url) = &config.notifications.ready {
-        playbin.play_url(url).ignore_pipeline_error();
+    let settings = settings::Store::load(config.state_directory.as_str());
+
+    if let Some(volume) = settings.volume() {
+        playbin.set_volume(volume).ok();
     }
 
-    let (commands_tx, commands_rx) = mpsc::unbounded_channel();
+    playbin
+        .set_is_muted(settings.is_muted())
+        .ignore_pipeline_error();
+
+    let mut notification_player = NotificationPlayer::new(&config);
+
+    // The first audio output is selected on startup
+    let audio_outputs = match config.audio_output_config.outputs.first() {
+        Some(output) => {
+            let audio_sink = config
+                .pipeline_config
+                .output_sink_description(&output.audio_sink, output.output_trim);
+
+            playbin
+                .set_audio_sink(&audio_sink)
+                .map_err(|PipelineError| anyhow::anyhow!("Failed to set audio sink"))?;
+            notification_player.set_audio_sink(audio_sink.into());
+
+            Some(AudioOutputs {
+                selected: output.name.clone(),
+                available: config
+                    .audio_output_config
+                    .outputs
+                    .iter()
+                    .map(|output| output.name.clone())
+                    .collect(),
+                headphones_connected: None,
+            })
+        }
+        None => None,
+    };
+
+    let (commands_tx, commands_rx) = mpsc::channel(crate::ports::COMMAND_QUEUE_LENGTH);
+
+    let (servers_awake_tx, servers_awake_rx) = mpsc::channel(1);
+
+    let dropped_messages = Arc::new(crate::ports::DroppedMessages::default());
+
+    let hello_event = crate::ports::SharedEvent::new(rradio_messages::Event::Hello(
+        crate::device_identity::load(&config),
+    ));
+
+    let (boot_milestones, boot_milestones_rx) = crate::boot::Milestones::new(port_count);
 
     let published_state = PlayerState {
         pipeline_state: playbin.pipeline_state().unwrap_or(PipelineState::Null),
@@ -844,33 +3590,164 @@ pub fn run(
         track_duration: None,
         track_position: None,
         ping_times: rradio_messages::PingTimes::None,
-        latest_error: Arc::new(None),
+        // Clients which connect later are told about safe mode by `safe_mode`, as the latest error might be replaced
+        latest_error: Arc::new(safe_mode.clone().map(|reason| {
+            let message = Message::new(MessageId::SafeMode, vec![reason]);
+
+            LatestError {
+                timestamp: chrono::Utc::now(),
+                error: message.text.clone(),
+                kind: ErrorKind::Other,
+                message,
+                since_start: crate::clock::since_start(),
+            }
+        })),
+        settings: Arc::new(settings.settings().clone()),
+        quarantined_tracks: Arc::new(Vec::new()),
+        tracks_revision: 0,
+        safe_mode,
+        idle_screen: None,
+        quiet_hours_maximum_volume: None,
+        audio_outputs,
+        activity: Activity::Idle {
+            since: chrono::Utc::now(),
+        },
+        audio_interruption: None,
+        audio_format: None,
+        intro_scan: None,
+        station_locked: false,
     };
 
-    let (new_state_tx, new_state_rx) = watch::channel(published_state.clone());
+    let (new_state_tx, new_state_rx) = crate::ports::state_channel(published_state.clone());
+
+    let (events_tx, _) = broadcast::channel(16);
+
+    let resolver = crate::dns::Resolver::new(config.dns_config.clone());
+
+    let (seek_preview_requests_tx, seek_preview_task) = super::seek_preview::previewer();
 
     #[cfg(feature = "ping")]
     let (ping_task, ping_requests_tx, ping_times_rx) =
-        super::ping::run(config.ping_config.clone())?;
+        super::ping::run(config.ping_config.clone(), resolver.clone());
+
+    // The watchdog isn't essential, so rradio runs without it if the device can't be opened
+    #[cfg(feature = "watchdog")]
+    let watchdog = match config.watchdog.clone().map(super::watchdog::run) {
+        Some(Ok(watchdog)) => Some(watchdog),
+        Some(Err(err)) => {
+            tracing::error!("{err:#}");
+            None
+        }
+        None => None,
+    };
 
     let error_retries_remaining = config.maximum_error_recovery_attempts;
 
+    let track_quarantine = TrackQuarantine::new(config.maximum_track_failures);
+
+    let yt_dlp = YtDlp::new(config.yt_dlp_config.clone());
+
+    let headphone_detect =
+        super::headphone_detect::watch(config.audio_output_config.headphone_detect.clone());
+
+    let idle_tracker = IdleTracker::new(config.idle_screen_config.clone());
+
+    let saved_position = if config.resume_on_start {
+        saved_position::take(config.state_directory.as_str())
+    } else {
+        None
+    };
+
+    let audit_log = AuditLog::load(config.state_directory.as_str(), config.audit_log_length);
+
+    let play_statistics = PlayStatistics::load(config.state_directory.as_str());
+
+    let adaptive_buffering = AdaptiveBuffering::load(&config);
+
+    let loudness_normalisation = LoudnessNormalisation::load(&config);
+
+    let title_loop_detector = TitleLoopDetector::new(config.title_loop.clone());
+
+    let bus_trace = config.bus_trace;
+
+    let (pipeline_diagnostics_tx, pipeline_diagnostics_rx) =
+        watch::channel(crate::ports::PipelineDiagnostics {
+            output_caps: None,
+            playbin_flags: playbin
+                .set_flags(&config.pipeline_config.flags, None)
+                .ok()
+                .map(ArcStr::from),
+            buffer_duration: config.buffering_duration,
+            station_buffering: adaptive_buffering.stations().clone(),
+            track_mirror: None,
+            loudness_gain: 0.0,
+            station_loudness: loudness_normalisation.stations().clone(),
+        });
+
+    let alarm_check = AlarmCheck::new(config.timezone.now());
+
     let mut controller = Controller {
         config,
         playbin,
+        notification_player,
         current_playlist: None,
         published_state,
+        settings,
         station_resume_info: BTreeMap::new(),
         new_state_tx,
+        events_tx: events_tx.clone(),
         queued_seek: None,
         error_recovery_attempts_remaining: error_retries_remaining,
+        latest_error_time: None,
+        track_quarantine,
+        yt_dlp,
+        prerolled_url: None,
+        loaded_url: None,
+        mirror_index: 0,
+        gapless_track: None,
+        pending_track_change: None,
+        audio_codec: None,
+        sleep_at: None,
+        sleep_fade: None,
+        wake_fade: None,
+        alarm_check,
+        idle_tracker,
+        saved_position,
+        station_ident_progress: StationIdentProgress::new(),
+        audit_log,
+        play_statistics,
+        adaptive_buffering,
+        loudness_normalisation,
+        title_loop_detector,
+        bus_trace,
+        resolver,
+        system_actions_tx,
+        waking_server: None,
+        last_waking_server_id: 0,
+        servers_awake_tx,
+        pipeline_diagnostics_tx,
+        seek_preview_requests_tx,
         #[cfg(feature = "ping")]
         ping_requests_tx,
+        commands_tx: crate::ports::CommandSender::new(
+            commands_tx.clone(),
+            dropped_messages.clone(),
+        )
+        .with_privilege(true),
+        #[cfg(feature = "watchdog")]
+        watchdog,
     };
 
+    if let Some(notification) = controller.config.notifications.ready.clone() {
+        controller.play_notification(&notification);
+    }
+
     let task = async move {
         use futures_util::StreamExt;
 
+        // The previewer stops when the controller is dropped
+        tokio::spawn(seek_preview_task);
+
         #[cfg(feature = "ping")]
         let ping_handle = tokio::spawn(ping_task);
 
@@ -883,6 +3760,20 @@ pub fn run(
 
         let bus_stream = bus_stream.map(Message::FromGStreamer);
 
+        let boot_milestones =
+            futures_util::stream::unfold(boot_milestones_rx, |mut boot_milestones_rx| async {
+                let milestone = boot_milestones_rx.recv().await?;
+                Some((Message::BootMilestone(milestone), boot_milestones_rx))
+            });
+
+        let headphone_detect = headphone_detect.map(Message::HeadphonesConnected);
+
+        let servers_awake =
+            futures_util::stream::unfold(servers_awake_rx, |mut servers_awake_rx| async {
+                let id = servers_awake_rx.recv().await?;
+                Some((Message::ServerAwake(id), servers_awake_rx))
+            });
+
         #[cfg(feature = "ping")]
         let messages = {
             let ping_stream =
@@ -891,39 +3782,134 @@ pub fn run(
                     Some((Message::PingTimes(ping_times), commands_rx))
                 });
 
-            StreamSelect((commands, bus_stream, ping_stream))
+            StreamSelect((
+                commands,
+                bus_stream,
+                boot_milestones,
+                headphone_detect,
+                servers_awake,
+                ping_stream,
+            ))
         };
 
         #[cfg(not(feature = "ping"))]
-        let messages = StreamSelect((commands, bus_stream));
+        let messages = StreamSelect((
+            commands,
+            bus_stream,
+            boot_milestones,
+            headphone_detect,
+            servers_awake,
+        ));
 
         tokio::pin!(messages);
 
-        let timeout = Duration::from_millis(1000 / 3);
+        let startup_station = if let Some(saved_position) = controller.saved_position.as_ref() {
+            tracing::info!("Resuming station {}", saved_position.station_index);
+
+            Some(saved_position.station_index.clone())
+        } else {
+            controller
+                .config
+                .startup_rotation
+                .as_ref()
+                .and_then(|startup_rotation| {
+                    crate::startup_rotation::next_station(
+                        controller.config.state_directory.as_str(),
+                        &startup_rotation.stations,
+                    )
+                })
+                .inspect(|station_index| {
+                    tracing::info!("Playing station {station_index} from the startup rotation");
+                })
+        };
+
+        if let Some(station_index) = startup_station {
+            match controller
+                .handle_command(Command::SetChannel(station_index), None)
+                .await
+            {
+                Ok(()) => controller.start_wake_fade(),
+                Err(error) => controller.play_error(error),
+            }
+        }
 
         loop {
-            match tokio::time::timeout(timeout, messages.next()).await {
+            // Wake up more often while fading, so that the volume changes smoothly
+            let timeout = if controller.sleep_fade.is_some() || controller.wake_fade.is_some() {
+                fader::STEP_INTERVAL
+            } else {
+                Duration::from_millis(1000 / 3)
+            };
+
+            // Wake up in time to load the pending track
+            let timeout = controller.pending_track_change.map_or(timeout, |load_at| {
+                timeout.min(load_at.saturating_duration_since(Instant::now()))
+            });
+
+            let message = tokio::select! {
+                () = &mut shutdown_signal => break,
+                message = tokio::time::timeout(timeout, messages.next()) => message,
+            };
+
+            match message {
                 Ok(None) => break,
                 Ok(Some(message)) => {
                     if let Err(error) = match message {
-                        Message::Command(command) => controller.handle_command(command).await,
+                        Message::Command(command) => {
+                            controller.handle_received_command(command).await
+                        }
                         Message::FromGStreamer(message) => controller
                             .handle_gstreamer_message(&message, &bus_side_stream)
                             .await
                             .map_err(Error::from),
+                        Message::BootMilestone(milestone) => {
+                            controller.handle_boot_milestone(milestone);
+                            Ok(())
+                        }
                         #[cfg(feature = "ping")]
                         Message::PingTimes(ping_times) => {
                             controller.handle_ping_times(ping_times);
                             Ok(())
                         }
+                        Message::HeadphonesConnected(is_connected) => {
+                            controller.handle_headphones_connected(is_connected)
+                        }
+                        Message::ServerAwake(id) => controller.handle_server_awake(id).await,
                     } {
                         controller.play_error(error);
                     }
                 }
-                Err(_) => controller.broadcast_state_change(),
+                Err(_) => {
+                    controller.clear_expired_latest_error();
+                    controller.follow_tracks_within_file();
+                    controller.broadcast_state_change();
+                }
+            }
+
+            if let Err(PipelineError) = controller.load_pending_track().await {
+                controller.play_error(Error::Pipeline);
+            }
+
+            if let Err(PipelineError) = controller.update_intro_scan().await {
+                controller.play_error(Error::Pipeline);
             }
+
+            controller.update_sleep_timer();
+            controller.update_wake_fade();
+            controller.update_idle_screen();
+            controller.update_quiet_hours();
+            controller.update_alarms().await;
+            controller.update_activity();
+            controller.settings.save_if_due();
+
+            #[cfg(feature = "watchdog")]
+            controller.update_watchdog();
         }
 
+        controller.save_position();
+        controller.settings.flush().await;
+        controller.audit_log.close().await;
+
         #[cfg(feature = "ping")]
         {
             drop(controller.ping_requests_tx);
@@ -931,14 +3917,239 @@ pub fn run(
                 tracing::error!("Error with ping routine: {}", err);
             }
         }
+
+        // The watchdog is only stopped once rradio has shut down cleanly
+        #[cfg(feature = "watchdog")]
+        if let Some(watchdog) = controller.watchdog.take() {
+            watchdog.finish();
+        }
     };
 
     Ok((
         task,
         PartialPortChannels {
-            commands_tx,
+            commands_tx: crate::ports::CommandSender::new(commands_tx, dropped_messages.clone()),
             player_state_rx: new_state_rx,
+            events_tx,
+            dropped_messages,
+            pipeline_diagnostics_rx,
+            hello_event,
+            boot_milestones,
             shutdown_signal: crate::ports::NoShutdownSignal,
         },
     ))
 }
+
+#[cfg(test)]
+mod tests {
+    use rradio_messages::{ArcStr, Command, MessageId, StationIndex};
+
+    use super::{alarm_is_due, check_station_lock, AlarmCheck, Error, PlaylistState};
+    use crate::station::{PlaylistHandle, PlaylistMetadata, Track};
+
+    /// A playlist whose tracks have the urls "a" to "e"
+    fn playlist(current_track_index: usize) -> PlaylistState {
+        PlaylistState {
+            pause_before_playing: None,
+            tracks: ["a", "b", "c", "d", "e"]
+                .iter()
+                .map(|&url| Track::url(ArcStr::from(url)))
+                .collect(),
+            current_track_index,
+            playlist_metadata: PlaylistMetadata::default(),
+            playlist_handle: PlaylistHandle::default(),
+        }
+    }
+
+    fn urls(playlist: &PlaylistState) -> String {
+        playlist
+            .tracks
+            .iter()
+            .map(|track| track.url.as_str())
+            .collect()
+    }
+
+    #[test]
+    fn moving_a_track_keeps_the_current_track() {
+        // (from, to, current track index, tracks afterwards, current track index afterwards)
+        let cases = [
+            (0, 4, 2, "bcdea", 1),
+            (4, 0, 2, "eabcd", 3),
+            (2, 4, 2, "abdec", 4),
+            (1, 3, 3, "acdbe", 2),
+            (3, 1, 1, "adbce", 2),
+            (0, 1, 3, "bacde", 3),
+            (4, 3, 1, "abced", 1),
+        ];
+
+        for (from, to, current, tracks, expected_current) in cases {
+            let mut playlist = playlist(current);
+            let current_url = playlist.tracks[current].url.clone();
+
+            playlist.move_track(from, to);
+
+            assert_eq!(urls(&playlist), tracks, "Moving {from} to {to}");
+            assert_eq!(playlist.current_track_index, expected_current);
+            assert_eq!(playlist.tracks[expected_current].url, current_url);
+        }
+    }
+
+    #[test]
+    fn removing_a_track_keeps_the_current_track() {
+        // (index, current track index, tracks afterwards, current track index afterwards)
+        let cases = [
+            (0, 2, "bcde", 1),
+            (4, 2, "abcd", 2),
+            (3, 2, "abce", 2),
+            (1, 4, "acde", 3),
+        ];
+
+        for (index, current, tracks, expected_current) in cases {
+            let mut playlist = playlist(current);
+
+            playlist.remove_track(index);
+
+            assert_eq!(urls(&playlist), tracks, "Removing {index}");
+            assert_eq!(playlist.current_track_index, expected_current);
+        }
+    }
+
+    fn rejection(result: Result<(), Error>) -> Option<MessageId> {
+        match result {
+            Ok(()) => None,
+            Err(Error::Rejected(message)) => Some(message.id),
+            Err(Error::Station(_) | Error::Pipeline) => panic!("Unexpected error"),
+        }
+    }
+
+    #[test]
+    fn locked_station_can_only_be_changed_by_privileged_commands() {
+        let station_changes = [
+            Command::SetChannel(StationIndex::new("05".into())),
+            Command::Eject,
+            Command::PlayPath {
+                source: rradio_messages::PathSource::Usb,
+                path: "Artist/Album".into(),
+                recursive: false,
+            },
+            Command::EnqueueTracks {
+                tracks: Vec::new(),
+                position: rradio_messages::EnqueuePosition::Next,
+            },
+            Command::SetAlarm(rradio_messages::Alarm {
+                hour: 7,
+                minute: 0,
+                station: StationIndex::new("05".into()),
+                stop_after: None,
+            }),
+            Command::RemoveAlarm { hour: 7, minute: 0 },
+        ];
+
+        for command in &station_changes {
+            assert_eq!(rejection(check_station_lock(command, false, false)), None);
+            assert_eq!(
+                rejection(check_station_lock(command, true, false)),
+                Some(MessageId::StationLocked),
+                "{command:?}"
+            );
+            assert_eq!(rejection(check_station_lock(command, true, true)), None);
+        }
+
+        // Other commands are allowed while the station is locked
+        assert_eq!(
+            rejection(check_station_lock(&Command::PlayPause, true, false)),
+            None
+        );
+
+        assert_eq!(
+            rejection(check_station_lock(
+                &Command::LockStation(false),
+                true,
+                false
+            )),
+            Some(MessageId::NotPrivileged)
+        );
+        assert_eq!(
+            rejection(check_station_lock(&Command::LockStation(false), true, true)),
+            None
+        );
+    }
+
+    fn at(time: &str) -> chrono::DateTime<chrono::FixedOffset> {
+        chrono::DateTime::parse_from_rfc3339(time).unwrap()
+    }
+
+    fn alarm(time: &str) -> chrono::NaiveTime {
+        time.parse().unwrap()
+    }
+
+    #[test]
+    fn alarms_are_due_across_midnight() {
+        let mut alarm_check = AlarmCheck::new(at("2024-01-01T23:59:59.9+00:00"));
+
+        let due = alarm_check
+            .advance(at("2024-01-02T00:00:00.2+00:00"))
+            .unwrap();
+
+        assert!(alarm_is_due(alarm("00:00"), due));
+        assert!(!alarm_is_due(alarm("23:59"), due));
+        assert!(!alarm_is_due(alarm("00:01"), due));
+    }
+
+    #[test]
+    fn alarms_are_skipped_when_the_clock_jumps() {
+        let mut alarm_check = AlarmCheck::new(at("2024-01-01T08:00:00+00:00"));
+
+        assert_eq!(alarm_check.advance(at("2024-01-01T10:00:00+00:00")), None);
+        assert_eq!(alarm_check.advance(at("2024-01-01T09:00:00+00:00")), None);
+
+        // Alarms are checked as usual after the jump
+        let due = alarm_check
+            .advance(at("2024-01-01T09:00:00.3+00:00"))
+            .unwrap();
+
+        assert!(!alarm_is_due(alarm("09:00"), due));
+
+        let due = alarm_check
+            .advance(at("2024-01-01T09:01:00+00:00"))
+            .unwrap();
+
+        assert!(alarm_is_due(alarm("09:01"), due));
+    }
+
+    #[test]
+    fn alarms_in_the_hour_skipped_by_daylight_saving_time_are_played() {
+        let mut alarm_check = AlarmCheck::new(at("2024-03-31T00:59:59.9+00:00"));
+
+        let due = alarm_check
+            .advance(at("2024-03-31T02:00:00.2+01:00"))
+            .unwrap();
+
+        assert!(alarm_is_due(alarm("01:30"), due));
+        assert!(alarm_is_due(alarm("02:00"), due));
+        assert!(!alarm_is_due(alarm("02:01"), due));
+    }
+
+    #[test]
+    fn alarms_in_the_hour_repeated_by_daylight_saving_time_are_played_once() {
+        let mut alarm_check = AlarmCheck::new(at("2024-10-27T01:00:00+01:00"));
+
+        let mut due_times = Vec::new();
+
+        // Check every 30 seconds from 01:00 summer time until 02:00 winter time, which is 01:00 winter time repeated
+        for step in 1..=240 {
+            let now = at("2024-10-27T00:00:00+00:00") + chrono::Duration::seconds(30 * step);
+
+            let offset = if step <= 120 { 3600 } else { 0 };
+            let now = now.with_timezone(&chrono::FixedOffset::east_opt(offset).unwrap());
+
+            if let Some(due) = alarm_check.advance(now) {
+                if alarm_is_due(alarm("01:30"), due) {
+                    due_times.push(now);
+                }
+            }
+        }
+
+        assert_eq!(due_times, [at("2024-10-27T01:30:00+01:00")]);
+    }
+}
@@ -0,0 +1,117 @@
+//! Classifying errors, so that clients can react to errors without parsing error messages
+
+use rradio_messages::ErrorKind;
+
+/// Failures to resolve host names are reported by gstreamer as failures to read, so are recognised by their message
+fn is_dns_failure(message: &str) -> bool {
+    let message = message.to_lowercase();
+
+    [
+        "could not resolve",
+        "name or service not known",
+        "no address associated",
+        "temporary failure in name resolution",
+    ]
+    .iter()
+    .any(|pattern| message.contains(pattern))
+}
+
+/// Classify a gstreamer error by its domain and code. `debug_message` is checked for DNS failures
+pub fn from_gstreamer_error(error: &glib::Error, debug_message: Option<&str>) -> ErrorKind {
+    use gstreamer::{CoreError, ResourceError};
+
+    if let Some(resource_error) = error.kind::<ResourceError>() {
+        match resource_error {
+            ResourceError::NotFound => ErrorKind::NotFound,
+            ResourceError::NotAuthorized => ErrorKind::Permission,
+            ResourceError::OpenRead
+            | ResourceError::OpenReadWrite
+            | ResourceError::Read
+            | ResourceError::Seek
+            | ResourceError::Failed => {
+                if is_dns_failure(error.message()) || debug_message.is_some_and(is_dns_failure) {
+                    ErrorKind::Dns
+                } else {
+                    ErrorKind::Network
+                }
+            }
+            // Writing is done by the audio sink
+            _ => ErrorKind::Device,
+        }
+    } else if error.kind::<gstreamer::StreamError>().is_some()
+        || error.kind::<CoreError>() == Some(CoreError::MissingPlugin)
+    {
+        ErrorKind::Codec
+    } else {
+        ErrorKind::Other
+    }
+}
+
+fn from_io_error_kind(kind: std::io::ErrorKind) -> ErrorKind {
+    use std::io::ErrorKind as IoErrorKind;
+
+    match kind {
+        IoErrorKind::NotFound => ErrorKind::NotFound,
+        IoErrorKind::PermissionDenied => ErrorKind::Permission,
+        IoErrorKind::ConnectionRefused
+        | IoErrorKind::ConnectionReset
+        | IoErrorKind::ConnectionAborted
+        | IoErrorKind::NotConnected
+        | IoErrorKind::AddrNotAvailable
+        | IoErrorKind::BrokenPipe
+        | IoErrorKind::TimedOut => ErrorKind::Network,
+        _ => ErrorKind::Other,
+    }
+}
+
+/// Classify an error by the first IO or HTTP error which caused it
+pub fn from_anyhow_error(error: &anyhow::Error) -> ErrorKind {
+    error
+        .chain()
+        .find_map(|cause| {
+            if let Some(io_error) = cause.downcast_ref::<std::io::Error>() {
+                Some(from_io_error_kind(io_error.kind()))
+            } else if let Some(http_error) = cause.downcast_ref::<reqwest::Error>() {
+                Some(match http_error.status() {
+                    Some(reqwest::StatusCode::NOT_FOUND) => ErrorKind::NotFound,
+                    Some(reqwest::StatusCode::UNAUTHORIZED | reqwest::StatusCode::FORBIDDEN) => {
+                        ErrorKind::Permission
+                    }
+                    _ => ErrorKind::Network,
+                })
+            } else {
+                None
+            }
+        })
+        .unwrap_or(ErrorKind::Other)
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::Context;
+    use rradio_messages::ErrorKind;
+
+    #[test]
+    fn errors_are_classified_by_their_cause() {
+        let error = std::fs::read("/does/not/exist")
+            .context("Failed to read track")
+            .unwrap_err();
+
+        assert_eq!(super::from_anyhow_error(&error), ErrorKind::NotFound);
+
+        let error = Err::<(), _>(std::io::Error::from(std::io::ErrorKind::ConnectionRefused))
+            .context("Failed to connect")
+            .unwrap_err();
+
+        assert_eq!(super::from_anyhow_error(&error), ErrorKind::Network);
+
+        assert_eq!(
+            super::from_anyhow_error(&anyhow::anyhow!("yt-dlp failed")),
+            ErrorKind::Other
+        );
+
+        assert!(super::is_dns_failure(
+            "Could not resolve host: example.invalid"
+        ));
+    }
+}
@@ -0,0 +1,70 @@
+//! Gradually change the volume, so that playback doesn't start or stop abruptly
+
+use std::time::{Duration, Instant};
+
+/// How often the volume is changed while fading
+pub const STEP_INTERVAL: Duration = Duration::from_millis(100);
+
+/// A linear change in volume over time. As volumes are in decibels, the change in loudness is smooth
+#[derive(Debug)]
+pub struct Fade {
+    from: i32,
+    to: i32,
+    start: Instant,
+    duration: Duration,
+}
+
+impl Fade {
+    /// Start fading from volume `from` to volume `to`, taking `duration`
+    pub fn new(from: i32, to: i32, duration: Duration) -> Self {
+        Self {
+            from,
+            to,
+            start: Instant::now(),
+            duration,
+        }
+    }
+
+    pub fn is_finished(&self, now: Instant) -> bool {
+        now.saturating_duration_since(self.start) >= self.duration
+    }
+
+    /// The volume at time `now`
+    #[allow(clippy::cast_possible_truncation)]
+    pub fn volume_at(&self, now: Instant) -> i32 {
+        if self.is_finished(now) {
+            return self.to;
+        }
+
+        let progress =
+            now.saturating_duration_since(self.start).as_secs_f64() / self.duration.as_secs_f64();
+
+        // The result is between `from` and `to`, so cannot be truncated
+        (f64::from(self.from) + f64::from(self.to - self.from) * progress).round() as i32
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::Fade;
+
+    #[test]
+    fn volume_changes_linearly() {
+        let fade = Fade::new(100, 0, Duration::from_secs(10));
+
+        assert_eq!(fade.volume_at(fade.start), 100);
+        assert_eq!(fade.volume_at(fade.start + Duration::from_secs(3)), 70);
+        assert_eq!(fade.volume_at(fade.start + Duration::from_secs(10)), 0);
+        assert_eq!(fade.volume_at(fade.start + Duration::from_secs(20)), 0);
+    }
+
+    #[test]
+    fn zero_duration_is_immediately_finished() {
+        let fade = Fade::new(0, 100, Duration::ZERO);
+
+        assert!(fade.is_finished(fade.start));
+        assert_eq!(fade.volume_at(fade.start), 100);
+    }
+}
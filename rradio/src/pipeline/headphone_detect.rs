@@ -0,0 +1,65 @@
+//! Detecting when headphones are plugged in or unplugged, by polling a file such as the value of a headphone detect GPIO
+
+use anyhow::{Context, Result};
+use futures_util::Stream;
+
+use crate::config::audio_output::HeadphoneDetect;
+
+async fn read(config: &HeadphoneDetect) -> Result<bool> {
+    let value = tokio::fs::read_to_string(config.path.as_str())
+        .await
+        .with_context(|| format!("Failed to read {}", config.path))?;
+
+    let is_active = match value.trim() {
+        "0" => false,
+        "1" => true,
+        value => anyhow::bail!("Unexpected value {value:?} in {}", config.path),
+    };
+
+    Ok(is_active != config.active_low)
+}
+
+struct State {
+    config: HeadphoneDetect,
+    is_connected: Option<bool>,
+    /// Errors are only logged when reading first fails, rather than every time the file is polled
+    is_failing: bool,
+}
+
+/// Whether headphones are connected, yielded when it changes. If headphone detection isn't configured, the stream never yields, but doesn't end
+pub fn watch(config: Option<HeadphoneDetect>) -> impl Stream<Item = bool> {
+    futures_util::stream::unfold(
+        config.map(|config| State {
+            config,
+            is_connected: None,
+            is_failing: false,
+        }),
+        |state| async move {
+            let Some(mut state) = state else {
+                return futures_util::future::pending().await;
+            };
+
+            loop {
+                match read(&state.config).await {
+                    Ok(is_connected) => {
+                        state.is_failing = false;
+
+                        if state.is_connected != Some(is_connected) {
+                            state.is_connected = Some(is_connected);
+                            return Some((is_connected, Some(state)));
+                        }
+                    }
+                    Err(err) => {
+                        if !state.is_failing {
+                            tracing::error!("Failed to detect headphones: {err:#}");
+                        }
+
+                        state.is_failing = true;
+                    }
+                }
+
+                tokio::time::sleep(state.config.poll_interval).await;
+            }
+        },
+    )
+}
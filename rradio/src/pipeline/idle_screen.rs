@@ -0,0 +1,181 @@
+//! Showing something useful on displays, such as the time or the IP address of the device, while nothing has played for a while
+
+use std::{
+    net::{IpAddr, Ipv4Addr, Ipv6Addr},
+    time::Instant,
+};
+
+use rradio_messages::{ArcStr, IdleScreen};
+
+use crate::config::idle_screen::{Config, Page};
+
+/// The addresses which the device would use to reach these addresses are its IP addresses. They're reserved for documentation, so aren't used by anything
+const REMOTE_ADDRESSES: [IpAddr; 2] = [
+    IpAddr::V4(Ipv4Addr::new(192, 0, 2, 1)),
+    IpAddr::V6(Ipv6Addr::new(0x2001, 0xdb8, 0, 0, 0, 0, 0, 1)),
+];
+
+/// The IP addresses of the device. Connecting a UDP socket doesn't send anything, but chooses the local address
+fn ip_addresses() -> Vec<ArcStr> {
+    REMOTE_ADDRESSES
+        .iter()
+        .filter_map(|&remote_address| {
+            let local_address = match remote_address {
+                IpAddr::V4(_) => IpAddr::V4(Ipv4Addr::UNSPECIFIED),
+                IpAddr::V6(_) => IpAddr::V6(Ipv6Addr::UNSPECIFIED),
+            };
+
+            let socket = std::net::UdpSocket::bind((local_address, 0)).ok()?;
+            socket.connect((remote_address, 53)).ok()?;
+
+            Some(socket.local_addr().ok()?.ip().to_string().into())
+        })
+        .collect()
+}
+
+/// What `page` shows, or None if it has nothing to show
+fn screen(page: &Page) -> Option<IdleScreen> {
+    match page {
+        Page::Clock => Some(IdleScreen::Clock),
+        Page::IpAddress => {
+            let addresses = ip_addresses();
+
+            if addresses.is_empty() {
+                None
+            } else {
+                Some(IdleScreen::IpAddresses(addresses))
+            }
+        }
+        Page::File(path) => match std::fs::read_to_string(path.as_str()) {
+            Ok(text) if !text.trim().is_empty() => Some(IdleScreen::Text(text.trim().into())),
+            Ok(_) => None,
+            Err(err) => {
+                tracing::debug!("Failed to read {path}: {err}");
+                None
+            }
+        },
+    }
+}
+
+/// Tracks when rradio was last active, and which page is shown while rradio is idle
+pub struct IdleTracker {
+    config: Config,
+    last_active: Instant,
+    /// The index of the page which is shown, and when it was shown, or None if rradio is active
+    page: Option<(usize, Instant)>,
+    screen: Option<IdleScreen>,
+}
+
+impl IdleTracker {
+    pub fn new(config: Config) -> Self {
+        Self {
+            config,
+            last_active: Instant::now(),
+            page: None,
+            screen: None,
+        }
+    }
+
+    /// Record that something is playing, or that the user has done something
+    pub fn active(&mut self, now: Instant) {
+        self.last_active = now;
+    }
+
+    fn is_idle(&self, now: Instant) -> bool {
+        self.config
+            .idle_time
+            .is_some_and(|idle_time| now.saturating_duration_since(self.last_active) >= idle_time)
+    }
+
+    /// What display clients should show, or None if rradio is active
+    pub fn screen(&self) -> Option<&IdleScreen> {
+        self.screen.as_ref()
+    }
+
+    /// Show the next page if the current page has been shown for long enough, or stop showing pages if rradio is active.
+    /// Pages which have nothing to show are skipped. Returns true if the screen has changed
+    pub fn update(&mut self, now: Instant) -> bool {
+        if !self.is_idle(now) {
+            self.page = None;
+            return self.screen.take().is_some();
+        }
+
+        let next_index = match self.page {
+            Some((_, shown_at))
+                if now.saturating_duration_since(shown_at) < self.config.page_duration =>
+            {
+                return false;
+            }
+            Some((index, _)) => index + 1,
+            None => 0,
+        };
+
+        let page_count = self.config.pages.len();
+
+        let (index, screen) = (0..page_count)
+            .map(|offset| (next_index + offset) % page_count)
+            .find_map(|index| Some((index, screen(&self.config.pages[index])?)))
+            .unwrap_or((0, IdleScreen::Clock));
+
+        self.page = Some((index, now));
+
+        let has_changed = self.screen.as_ref() != Some(&screen);
+
+        self.screen = Some(screen);
+
+        has_changed
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant};
+
+    use rradio_messages::{arcstr, IdleScreen};
+
+    use crate::config::idle_screen::{Config, Page};
+
+    use super::IdleTracker;
+
+    #[test]
+    fn pages_are_shown_while_idle() {
+        let directory = tempfile::tempdir().unwrap();
+        let weather_path = directory.path().join("weather");
+
+        let mut idle_tracker = IdleTracker::new(Config {
+            idle_time: Some(Duration::from_secs(60)),
+            page_duration: Duration::from_secs(10),
+            pages: vec![
+                Page::Clock,
+                Page::File(weather_path.to_str().unwrap().into()),
+            ],
+        });
+
+        let start = Instant::now();
+        let after = |seconds| start + Duration::from_secs(seconds);
+
+        assert!(!idle_tracker.update(after(30)));
+        assert_eq!(idle_tracker.screen(), None);
+
+        assert!(idle_tracker.update(after(61)));
+        assert_eq!(idle_tracker.screen(), Some(&IdleScreen::Clock));
+
+        // Pages with nothing to show are skipped
+        assert!(!idle_tracker.update(after(72)));
+        assert_eq!(idle_tracker.screen(), Some(&IdleScreen::Clock));
+
+        std::fs::write(&weather_path, "Sunny, 21°C\n").unwrap();
+
+        assert!(idle_tracker.update(after(83)));
+        assert_eq!(
+            idle_tracker.screen(),
+            Some(&IdleScreen::Text(arcstr::literal!("Sunny, 21°C")))
+        );
+
+        idle_tracker.active(after(85));
+
+        assert!(idle_tracker.update(after(85)));
+        assert_eq!(idle_tracker.screen(), None);
+        assert!(!idle_tracker.update(after(86)));
+    }
+}
@@ -0,0 +1,258 @@
+//! Learning the average loudness of each station, as measured by a `level` element while it plays, and if `loudness_normalisation` is configured,
+//! applying a gain to each station which brings it towards the target loudness. The learned loudness is stored as a JSON file in the state directory
+
+use std::{
+    collections::BTreeMap,
+    path::{Path, PathBuf},
+    time::{Duration, Instant},
+};
+
+use anyhow::Result;
+
+use rradio_messages::StationIndex;
+
+use crate::config::loudness_normalisation::Config;
+
+const STATION_LOUDNESS_FILE_NAME: &str = "station_loudness.json";
+
+/// How often the `level` element measures the loudness
+pub const MEASUREMENT_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Measurements quieter than this, in dB, are silence, such as the gaps between tracks, and aren't learned
+const SILENCE_THRESHOLD: f64 = -60.0;
+
+/// How often the learned loudness is saved while a station is playing
+const SAVE_INTERVAL: Duration = Duration::from_secs(60);
+
+/// The average loudness of a station, and how long it has been measured for
+#[derive(Clone, Debug, Default, PartialEq, serde::Deserialize, serde::Serialize)]
+pub struct StationLoudness {
+    /// The average RMS level, in dB relative to full scale
+    pub loudness: f64,
+    /// Capped at `learning_period`
+    #[serde(with = "humantime_serde")]
+    pub measured_duration: Duration,
+}
+
+impl StationLoudness {
+    fn record(&mut self, loudness: f64, learning_period: Duration) {
+        self.measured_duration =
+            (self.measured_duration + MEASUREMENT_INTERVAL).min(learning_period);
+
+        // Until the learning period has been reached this is the mean of all measurements, after which it's a moving average
+        let weight = MEASUREMENT_INTERVAL.as_secs_f64() / self.measured_duration.as_secs_f64();
+
+        self.loudness += (loudness - self.loudness) * weight.min(1.0);
+    }
+}
+
+pub struct LoudnessNormalisation {
+    config: Option<Config>,
+    path: PathBuf,
+    stations: BTreeMap<StationIndex, StationLoudness>,
+    current_station: Option<StationIndex>,
+    last_saved: Instant,
+    /// Whether any station has been measured since the learned loudness was last saved
+    is_modified: bool,
+}
+
+impl LoudnessNormalisation {
+    pub fn load(config: &crate::config::Config) -> Self {
+        Self::new(
+            config.loudness_normalisation.clone(),
+            config.state_directory.as_str(),
+        )
+    }
+
+    /// The learned loudness is only loaded and saved if `config` is set
+    fn new(config: Option<Config>, state_directory: impl AsRef<Path>) -> Self {
+        let path = state_directory.as_ref().join(STATION_LOUDNESS_FILE_NAME);
+
+        let stations = if config.is_some() {
+            crate::state_file::read_json(&path).unwrap_or_else(|err| {
+                tracing::error!("{err:#}");
+                BTreeMap::new()
+            })
+        } else {
+            BTreeMap::new()
+        };
+
+        Self {
+            config,
+            path,
+            stations,
+            current_station: None,
+            last_saved: Instant::now(),
+            is_modified: false,
+        }
+    }
+
+    pub fn stations(&self) -> &BTreeMap<StationIndex, StationLoudness> {
+        &self.stations
+    }
+
+    /// The gain in dB which is applied to the current station, or `0.0` if it hasn't been measured for long enough
+    pub fn gain(&self) -> f64 {
+        let Some(config) = &self.config else {
+            return 0.0;
+        };
+
+        self.current_station
+            .as_ref()
+            .and_then(|index| self.stations.get(index))
+            .filter(|station| station.measured_duration >= config.minimum_duration)
+            .map_or(0.0, |station| {
+                (config.target_loudness - station.loudness)
+                    .clamp(-config.maximum_gain, config.maximum_gain)
+            })
+    }
+
+    /// Start measuring a new station, saving what was learned about the previous station
+    pub fn start_station(&mut self, index: Option<&StationIndex>) {
+        self.save_if_modified();
+
+        self.current_station = index.cloned();
+    }
+
+    /// Record the loudness, in dB, of the current station. Returns true if the learned loudness has been saved
+    pub fn record_loudness(&mut self, loudness: f64) -> bool {
+        let Some(config) = &self.config else {
+            return false;
+        };
+
+        if loudness < SILENCE_THRESHOLD {
+            return false;
+        }
+
+        let Some(index) = &self.current_station else {
+            return false;
+        };
+
+        self.stations
+            .entry(index.clone())
+            .or_default()
+            .record(loudness, config.learning_period);
+
+        self.is_modified = true;
+
+        if self.last_saved.elapsed() < SAVE_INTERVAL {
+            return false;
+        }
+
+        self.save_if_modified()
+    }
+
+    /// Forget the loudness learned for a station, or for all stations if `index` is `None`
+    pub fn reset(&mut self, index: Option<&StationIndex>) {
+        if let Some(index) = index {
+            tracing::info!(%index, "Resetting station loudness");
+            self.stations.remove(index);
+        } else {
+            tracing::info!("Resetting loudness of all stations");
+            self.stations.clear();
+        }
+
+        self.is_modified = true;
+        self.save_if_modified();
+    }
+
+    /// Returns true if the learned loudness has been saved
+    fn save_if_modified(&mut self) -> bool {
+        if self.config.is_none() || !std::mem::take(&mut self.is_modified) {
+            return false;
+        }
+
+        self.last_saved = Instant::now();
+
+        if let Err(err) = self.save() {
+            tracing::error!("{err:#}");
+        }
+
+        true
+    }
+
+    fn save(&self) -> Result<()> {
+        crate::state_file::write_json(&self.path, &self.stations)
+    }
+}
+
+/// The loudness in dB measured by a `level` element, averaged across channels, if `structure` is a `level` message
+pub fn measured_loudness(structure: &gstreamer::StructureRef) -> Option<f64> {
+    if !structure.has_name("level") {
+        return None;
+    }
+
+    let rms = structure.get::<glib::ValueArray>("rms").ok()?;
+
+    let powers = rms
+        .iter()
+        .filter_map(|value| value.get::<f64>().ok())
+        .map(|rms| 10_f64.powf(rms / 10.0))
+        .collect::<Vec<_>>();
+
+    if powers.is_empty() {
+        return None;
+    }
+
+    #[allow(clippy::cast_precision_loss)]
+    let mean_power = powers.iter().sum::<f64>() / powers.len() as f64;
+
+    Some(10.0 * mean_power.log10())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use rradio_messages::StationIndex;
+
+    use super::LoudnessNormalisation;
+
+    fn record(loudness_normalisation: &mut LoudnessNormalisation, loudness: f64, seconds: u32) {
+        for _ in 0..seconds {
+            loudness_normalisation.record_loudness(loudness);
+        }
+    }
+
+    #[test]
+    fn quiet_and_loud_stations_are_brought_towards_the_target_loudness() {
+        let state_directory = tempfile::tempdir().unwrap();
+
+        let config = crate::config::loudness_normalisation::Config {
+            target_loudness: -20.0,
+            maximum_gain: 6.0,
+            learning_period: Duration::from_secs(60 * 60),
+            minimum_duration: Duration::from_secs(60),
+        };
+
+        let quiet = StationIndex::new("01".into());
+        let loud = StationIndex::new("02".into());
+
+        let mut loudness_normalisation =
+            LoudnessNormalisation::new(Some(config.clone()), state_directory.path());
+
+        loudness_normalisation.start_station(Some(&quiet));
+        record(&mut loudness_normalisation, -24.0, 30);
+
+        // Silence isn't learned
+        record(&mut loudness_normalisation, -90.0, 30);
+        assert!(loudness_normalisation.gain().abs() < f64::EPSILON);
+
+        record(&mut loudness_normalisation, -24.0, 30);
+        assert!((loudness_normalisation.gain() - 4.0).abs() < 1e-9);
+
+        loudness_normalisation.start_station(Some(&loud));
+        record(&mut loudness_normalisation, -8.0, 60);
+        assert!((loudness_normalisation.gain() + 6.0).abs() < 1e-9);
+
+        loudness_normalisation.start_station(None);
+
+        let mut reloaded = LoudnessNormalisation::new(Some(config), state_directory.path());
+        reloaded.start_station(Some(&quiet));
+        assert!((reloaded.gain() - 4.0).abs() < 1e-9);
+
+        reloaded.reset(Some(&quiet));
+        assert!(reloaded.gain().abs() < f64::EPSILON);
+        assert!(reloaded.stations().contains_key(&loud));
+    }
+}
@@ -1,9 +1,31 @@
 //! A task which processes incoming commands and gstreamer messages, and sends commands to the gstreamer pipeline
 
+mod adaptive_buffering;
+mod audio_format;
 mod controller;
+mod error_kind;
+mod fader;
+mod headphone_detect;
+mod idle_screen;
+mod loudness_normalisation;
+mod notification_player;
 mod playbin;
+mod seek_preview;
+mod test_tone;
+mod title_loop;
+mod track_quarantine;
+mod yt_dlp;
 
 #[cfg(feature = "ping")]
 mod ping;
 
+#[cfg(feature = "watchdog")]
+mod watchdog;
+
+pub use adaptive_buffering::StationBuffering;
 pub use controller::{run, PlayerState};
+pub use loudness_normalisation::StationLoudness;
+pub use test_tone::play as play_test_tone;
+
+#[cfg(feature = "ping")]
+pub use ping::ping_once;
@@ -0,0 +1,181 @@
+//! Plays notification sounds which are loaded into memory at startup, using a separate pipeline,
+//! so that they start instantly, and play even if the filesystem is unavailable
+
+use std::{collections::HashMap, sync::Arc, time::Duration};
+
+use glib::{object::ObjectExt, Cast, ToValue};
+use gstreamer::prelude::{ElementExt, GstBinExt};
+use gstreamer_audio::prelude::StreamVolumeExt;
+
+use rradio_messages::ArcStr;
+
+use super::playbin::{PipelineError, PipelineErrorContext};
+
+/// Notifications which take longer than this to play are stopped
+const MAXIMUM_NOTIFICATION_DURATION: Duration = Duration::from_secs(60);
+
+/// Load a notification sound into memory. Only local files are loaded
+fn load_sound(url: &str) -> anyhow::Result<Arc<[u8]>> {
+    use anyhow::Context;
+
+    let path = url::Url::parse(url)
+        .ok()
+        .and_then(|url| url.to_file_path().ok())
+        .context("Only file:// urls can be preloaded")?;
+
+    let sound =
+        std::fs::read(&path).with_context(|| format!("Failed to read {}", path.display()))?;
+
+    Ok(Arc::from(sound))
+}
+
+pub struct NotificationPlayer {
+    audio_sink: ArcStr,
+    sounds: HashMap<ArcStr, Arc<[u8]>>,
+    current_pipeline: Option<gstreamer::Element>,
+}
+
+impl NotificationPlayer {
+    /// Load the notifications which are played outside of a playlist, if preloading is enabled, and the station idents and volume limit notification, which are always preloaded as they're played over playlists
+    pub fn new(config: &crate::config::Config) -> Self {
+        let notifications = &config.notifications;
+
+        let preloaded_notifications = if notifications.preload {
+            vec![
+                &notifications.ready,
+                &notifications.error,
+                &notifications.config_loaded,
+                &notifications.network_up,
+                &notifications.ports_listening,
+                &notifications.stations_validated,
+            ]
+        } else {
+            Vec::new()
+        };
+
+        let sounds = preloaded_notifications
+            .into_iter()
+            .flatten()
+            .chain(&notifications.volume_limit)
+            .chain(
+                config
+                    .station_idents
+                    .values()
+                    .map(|ident| &ident.notification),
+            )
+            .map(|notification| &notification.url)
+            .filter_map(|url| match load_sound(url) {
+                Ok(sound) => {
+                    tracing::debug!(%url, size = sound.len(), "Loaded notification");
+                    Some((url.clone(), sound))
+                }
+                Err(err) => {
+                    tracing::error!(%url, "Failed to load notification: {err:#}");
+                    None
+                }
+            })
+            .collect();
+
+        Self {
+            audio_sink: config.pipeline_config.audio_sink_description().into(),
+            sounds,
+            current_pipeline: None,
+        }
+    }
+
+    /// Play notifications using a different audio sink, e.g. when the audio output is changed
+    pub fn set_audio_sink(&mut self, audio_sink: ArcStr) {
+        self.audio_sink = audio_sink;
+    }
+
+    pub fn contains(&self, url: &str) -> bool {
+        self.sounds.contains_key(url)
+    }
+
+    /// Play a preloaded notification at the given volume, stopping any notification which is already playing
+    pub fn play(&mut self, url: &str, volume: i32, is_muted: bool) -> Result<(), PipelineError> {
+        self.stop();
+
+        let sound = self
+            .sounds
+            .get(url)
+            .with_context(|| format!("{url} is not preloaded"))?
+            .clone();
+
+        let pipeline = gstreamer::parse_launch(&format!(
+            "appsrc name=source ! decodebin ! audioconvert ! audioresample ! volume name=volume ! {}",
+            self.audio_sink
+        ))
+        .context("Failed to create notification pipeline")?;
+
+        let bin = pipeline
+            .downcast_ref::<gstreamer::Bin>()
+            .context("Notification pipeline is not a bin")?;
+
+        let volume_element = bin
+            .by_name("volume")
+            .context("Notification pipeline has no volume")?;
+
+        let stream_volume = volume_element
+            .dynamic_cast_ref::<gstreamer_audio::StreamVolume>()
+            .context("Notification volume is not a stream volume")?;
+
+        stream_volume.set_volume(
+            gstreamer_audio::StreamVolumeFormat::Db,
+            f64::from(volume - rradio_messages::VOLUME_ZERO_DB),
+        );
+        stream_volume.set_mute(is_muted);
+
+        let source = bin
+            .by_name("source")
+            .context("Notification pipeline has no source")?;
+
+        source.emit_by_name_with_values(
+            "push-buffer",
+            &[gstreamer::Buffer::from_slice(sound).to_value()],
+        );
+        source.emit_by_name_with_values("end-of-stream", &[]);
+
+        pipeline
+            .set_state(gstreamer::State::Playing)
+            .context("Failed to play notification")?;
+
+        let bus = pipeline.bus().context("Notification pipeline has no bus")?;
+
+        // Stop the pipeline once the notification has finished, so that the audio device is released
+        std::thread::Builder::new()
+            .name(String::from("notification"))
+            .spawn({
+                let pipeline = pipeline.clone();
+                move || {
+                    let timeout =
+                        gstreamer::ClockTime::from_seconds(MAXIMUM_NOTIFICATION_DURATION.as_secs());
+
+                    if let Some(message) = bus.timed_pop_filtered(
+                        timeout,
+                        &[gstreamer::MessageType::Eos, gstreamer::MessageType::Error],
+                    ) {
+                        if let gstreamer::MessageView::Error(err) = message.view() {
+                            tracing::error!("Notification pipeline error: {}", err.error());
+                        }
+                    }
+
+                    pipeline.set_state(gstreamer::State::Null).ok();
+                }
+            })
+            .context("Failed to start notification thread")?;
+
+        self.current_pipeline = Some(pipeline);
+
+        Ok(())
+    }
+
+    /// Stop the current notification, e.g. so that it doesn't play over a station
+    pub fn stop(&mut self) {
+        if let Some(pipeline) = self.current_pipeline.take() {
+            if let Err(err) = pipeline.set_state(gstreamer::State::Null) {
+                tracing::error!("Failed to stop notification pipeline: {err}");
+            }
+        }
+    }
+}
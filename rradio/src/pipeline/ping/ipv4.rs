@@ -70,8 +70,10 @@ impl Pinger {
         )?;
         Ok(Self { sender, receiver })
     }
+}
 
-    pub fn ping(&mut self, address: Ipv4Addr) -> Result<Duration, PingError> {
+impl super::Ipv4Pinger for Pinger {
+    fn ping(&mut self, address: Ipv4Addr) -> Result<Duration, PingError> {
         tracing::trace!("Pinging {}", address);
 
         let mut packet_iter: IcmpTransportChannelIterator =
@@ -1,5 +1,5 @@
 use std::{
-    net::{Ipv4Addr, SocketAddr},
+    net::{IpAddr, Ipv4Addr, SocketAddr},
     time::Duration,
 };
 
@@ -7,7 +7,14 @@ use tokio::sync::{mpsc, oneshot};
 
 use rradio_messages::{ArcStr, PingError, PingTarget, PingTimes};
 
+use crate::config::ping::Probe;
+
+use probe::ProbeTarget;
+
+#[cfg(feature = "ping-raw-socket")]
 mod ipv4;
+mod probe;
+mod subprocess;
 
 const PING_INTERVAL: Duration = Duration::from_secs(1);
 
@@ -31,15 +38,40 @@ impl From<mpsc::error::SendError<PingTimes>> for PingInterruption {
 #[derive(Clone, Copy, Debug)]
 struct FailedToPing(PingError);
 
+/// A blocking method of sending ICMP echo requests
+trait Ipv4Pinger: Send {
+    fn ping(&mut self, address: Ipv4Addr) -> Result<Duration, PingError>;
+}
+
+/// Use raw sockets if available, otherwise fall back to running the `ping` command
+fn new_ipv4_pinger() -> Box<dyn Ipv4Pinger> {
+    #[cfg(feature = "ping-raw-socket")]
+    match ipv4::Pinger::new() {
+        Ok(pinger) => return Box::new(pinger),
+        Err(err) => tracing::warn!("{err} Falling back to the ping command"),
+    }
+
+    Box::new(subprocess::Pinger)
+}
+
+/// Ping `address` once, blocking the current thread
+pub fn ping_once(address: Ipv4Addr) -> Result<Duration, PingError> {
+    new_ipv4_pinger().ping(address)
+}
+
 struct Ivp4PingRequest {
     address: Ipv4Addr,
     response_tx: oneshot::Sender<Result<Duration, PingError>>,
 }
 
 struct Pinger {
-    gateway_address: Ipv4Addr,
+    probe: Probe,
+    gateway: ProbeTarget,
     ping_count: usize,
-    ipv4_pinger: mpsc::Sender<Ivp4PingRequest>,
+    /// Only used by ICMP probes
+    ipv4_pinger: Option<mpsc::Sender<Ivp4PingRequest>>,
+    http_client: reqwest::Client,
+    resolver: crate::dns::Resolver,
     track_urls: mpsc::UnboundedReceiver<Option<ArcStr>>,
     ping_times: mpsc::UnboundedSender<PingTimes>,
 }
@@ -65,19 +97,18 @@ impl Pinger {
         }
     }
 
-    async fn ping_address(
-        &mut self,
-        name: &str,
+    async fn ping_icmp(
+        &self,
         address: Ipv4Addr,
-        f: impl FnOnce(Result<Duration, PingError>) -> PingTimes,
-    ) -> Result<Result<Duration, FailedToPing>, PingInterruption> {
-        self.check_for_new_track().await?;
-
-        tracing::trace!(%address, "Pinging {name}");
+    ) -> Result<Result<Duration, PingError>, PingInterruption> {
+        let ipv4_pinger = self.ipv4_pinger.as_ref().ok_or_else(|| {
+            tracing::error!("ICMP pinger is not running");
+            PingInterruption::Finished
+        })?;
 
         let (response_tx, response_rx) = oneshot::channel();
 
-        self.ipv4_pinger
+        ipv4_pinger
             .send(Ivp4PingRequest {
                 address,
                 response_tx,
@@ -88,10 +119,27 @@ impl Pinger {
                 PingInterruption::Finished
             })?;
 
-        let ping_time_response = response_rx.await.map_err(|_| {
+        response_rx.await.map_err(|_| {
             tracing::error!("pinger worker has died");
             PingInterruption::Finished
-        })?;
+        })
+    }
+
+    async fn ping_address(
+        &mut self,
+        name: &str,
+        address: ProbeTarget,
+        f: impl FnOnce(Result<Duration, PingError>) -> PingTimes,
+    ) -> Result<Result<Duration, FailedToPing>, PingInterruption> {
+        self.check_for_new_track().await?;
+
+        tracing::trace!(%address, "Pinging {name}");
+
+        let ping_time_response = match &address {
+            ProbeTarget::Icmp(address) => self.ping_icmp(*address).await?,
+            ProbeTarget::TcpConnect(address) => probe::tcp_connect(*address).await,
+            ProbeTarget::HttpHead(url) => probe::http_head(&self.http_client, url).await,
+        };
 
         Ok(match ping_time_response {
             Ok(ping_time) => {
@@ -110,25 +158,31 @@ impl Pinger {
         &mut self,
         f: impl FnOnce(Result<Duration, PingError>) -> PingTimes,
     ) -> Result<Result<Duration, FailedToPing>, PingInterruption> {
-        self.ping_address("gateway", self.gateway_address, f).await
+        self.ping_address("gateway", self.gateway.clone(), f).await
     }
 
     async fn ping_remote(
         &mut self,
-        address: Ipv4Addr,
+        address: ProbeTarget,
         f: impl FnOnce(Result<Duration, PingError>) -> PingTimes,
     ) -> Result<Result<Duration, FailedToPing>, PingInterruption> {
         self.ping_address("remote", address, f).await
     }
 
-    async fn get_remote_address(&mut self, host: &str) -> Result<Ipv4Addr, PingInterruption> {
+    /// Resolve the host of the track, reporting DNS failures, and choose what to probe
+    async fn get_remote_address(
+        &mut self,
+        track_url_str: &ArcStr,
+        track_url: &url::Url,
+        host: &str,
+    ) -> Result<ProbeTarget, PingInterruption> {
         let dns_addresses = loop {
             let gateway_ping = match self.ping_gateway(PingTimes::Gateway).await? {
                 Ok(ping) => ping,
                 Err(FailedToPing(_err)) => continue,
             };
 
-            match std::net::ToSocketAddrs::to_socket_addrs(&(host, 0)) {
+            match self.resolver.lookup(host).await {
                 Ok(addrs) => break addrs,
                 Err(err) => {
                     tracing::error!("Could not resolve DNS ({:?}): {}", host, err);
@@ -142,11 +196,26 @@ impl Pinger {
             }
         };
 
-        for address in dns_addresses {
-            match address {
-                SocketAddr::V4(ipv4_address) => return Ok(*ipv4_address.ip()),
-                SocketAddr::V6(ipv6_address) => {
-                    tracing::debug!("Ignoring ipv6 address ({:?}): {}", host, ipv6_address);
+        match self.probe {
+            Probe::Icmp => {
+                for address in dns_addresses {
+                    match address {
+                        IpAddr::V4(ipv4_address) => return Ok(ProbeTarget::Icmp(ipv4_address)),
+                        IpAddr::V6(ipv6_address) => {
+                            tracing::debug!("Ignoring ipv6 address ({:?}): {}", host, ipv6_address);
+                        }
+                    }
+                }
+            }
+            Probe::TcpConnect => {
+                if let Some(&address) = dns_addresses.first() {
+                    let port = track_url.port_or_known_default().unwrap_or(80);
+                    return Ok(ProbeTarget::TcpConnect(SocketAddr::from((address, port))));
+                }
+            }
+            Probe::HttpHead => {
+                if !dns_addresses.is_empty() {
+                    return Ok(ProbeTarget::HttpHead(track_url_str.clone()));
                 }
             }
         }
@@ -175,12 +244,14 @@ impl Pinger {
             PingInterruption::SuspendUntilNewTrack
         })?;
 
-        let remote_address = self.get_remote_address(host).await?;
+        let remote_address = self
+            .get_remote_address(&track_url_str, &track_url, host)
+            .await?;
 
         let mut maybe_remote_ping = None;
 
         'retry: loop {
-            tracing::trace!(gateway_address=%self.gateway_address, "Checking gateway ping");
+            tracing::trace!(gateway=%self.gateway, "Checking gateway ping");
             let mut gateway_ping = match self
                 .ping_gateway(|gateway_ping| match (gateway_ping, maybe_remote_ping) {
                     (Err(err), _) => PingTimes::Gateway(Err(err)),
@@ -197,7 +268,7 @@ impl Pinger {
                 Err(FailedToPing(_err)) => continue 'retry,
             };
 
-            tracing::trace!(gateway_address=%self.gateway_address, %remote_address, "Pinging gateway and remote");
+            tracing::trace!(gateway=%self.gateway, %remote_address, "Pinging gateway and remote");
 
             let mut remote_pings_remaining = self.ping_count;
 
@@ -207,10 +278,12 @@ impl Pinger {
                 }
 
                 let remote_ping = self
-                    .ping_remote(remote_address, |remote_ping| PingTimes::GatewayAndRemote {
-                        gateway_ping,
-                        remote_ping,
-                        latest: PingTarget::Remote,
+                    .ping_remote(remote_address.clone(), |remote_ping| {
+                        PingTimes::GatewayAndRemote {
+                            gateway_ping,
+                            remote_ping,
+                            latest: PingTarget::Remote,
+                        }
                     })
                     .await?
                     .map_err(|FailedToPing(err)| err);
@@ -294,16 +367,14 @@ impl Pinger {
 
 pub fn run(
     config: crate::config::ping::Config,
-) -> Result<
-    (
-        impl std::future::Future<Output = ()>,
-        mpsc::UnboundedSender<Option<ArcStr>>,
-        mpsc::UnboundedReceiver<PingTimes>,
-    ),
-    ipv4::PermissionsError,
-> {
-    let ipv4_pinger = {
-        let mut ipv4_pinger = ipv4::Pinger::new()?;
+    resolver: crate::dns::Resolver,
+) -> (
+    impl std::future::Future<Output = ()>,
+    mpsc::UnboundedSender<Option<ArcStr>>,
+    mpsc::UnboundedReceiver<PingTimes>,
+) {
+    let ipv4_pinger = (config.probe == Probe::Icmp).then(|| {
+        let mut ipv4_pinger = new_ipv4_pinger();
 
         let (ping_request_tx, mut ping_request_rx) = mpsc::channel(1);
 
@@ -318,6 +389,14 @@ pub fn run(
         });
 
         ping_request_tx
+    });
+
+    let gateway = match config.probe {
+        Probe::Icmp => ProbeTarget::Icmp(config.gateway_address),
+        Probe::TcpConnect | Probe::HttpHead => ProbeTarget::TcpConnect(SocketAddr::from((
+            config.gateway_address,
+            config.gateway_port,
+        ))),
     };
 
     let (track_url_tx, track_url_rx) = mpsc::unbounded_channel::<Option<ArcStr>>();
@@ -326,9 +405,12 @@ pub fn run(
 
     let task = async move {
         Pinger {
-            gateway_address: config.gateway_address,
+            probe: config.probe,
+            gateway,
             ping_count: config.remote_ping_count,
             ipv4_pinger,
+            http_client: probe::http_client(),
+            resolver,
             track_urls: track_url_rx,
             ping_times: ping_time_tx,
         }
@@ -338,5 +420,5 @@ pub fn run(
         tracing::debug!("Shut down");
     };
 
-    Ok((task, track_url_tx, ping_time_rx))
+    (task, track_url_tx, ping_time_rx)
 }
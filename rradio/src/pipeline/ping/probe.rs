@@ -0,0 +1,69 @@
+//! Alternatives to ICMP for measuring latency, for when raw sockets and the `ping` command aren't permitted, or ICMP is blocked
+
+use std::{
+    fmt,
+    net::{Ipv4Addr, SocketAddr},
+    time::{Duration, Instant},
+};
+
+use rradio_messages::{ArcStr, PingError};
+
+/// Matches the timeout of the `ping` command
+const PROBE_TIMEOUT: Duration = Duration::from_secs(4);
+
+/// What is probed to measure the latency to the gateway or the remote host
+#[derive(Clone, Debug)]
+pub enum ProbeTarget {
+    Icmp(Ipv4Addr),
+    TcpConnect(SocketAddr),
+    HttpHead(ArcStr),
+}
+
+impl fmt::Display for ProbeTarget {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Icmp(address) => address.fmt(f),
+            Self::TcpConnect(address) => write!(f, "tcp://{address}"),
+            Self::HttpHead(url) => url.fmt(f),
+        }
+    }
+}
+
+pub fn http_client() -> reqwest::Client {
+    reqwest::Client::builder()
+        .timeout(PROBE_TIMEOUT)
+        // Only the time to the first response is measured
+        .redirect(reqwest::redirect::Policy::none())
+        .build()
+        .unwrap_or_else(|err| {
+            tracing::error!("Failed to create HTTP probe client: {err}");
+            reqwest::Client::new()
+        })
+}
+
+pub async fn tcp_connect(address: SocketAddr) -> Result<Duration, PingError> {
+    let start = Instant::now();
+
+    match tokio::time::timeout(PROBE_TIMEOUT, tokio::net::TcpStream::connect(address)).await {
+        Ok(Ok(_)) => Ok(start.elapsed()),
+        Ok(Err(err)) => {
+            tracing::debug!(%address, "Failed to connect: {err}");
+            Err(PingError::FailedToConnect)
+        }
+        Err(_) => Err(PingError::Timeout),
+    }
+}
+
+/// Any response counts, even an error status, as the server has responded
+pub async fn http_head(client: &reqwest::Client, url: &str) -> Result<Duration, PingError> {
+    let start = Instant::now();
+
+    match client.head(url).send().await {
+        Ok(_) => Ok(start.elapsed()),
+        Err(err) if err.is_timeout() => Err(PingError::Timeout),
+        Err(err) => {
+            tracing::debug!(url, "HEAD request failed: {err}");
+            Err(PingError::FailedToConnect)
+        }
+    }
+}
@@ -0,0 +1,79 @@
+//! A pinger which runs the system `ping` command, for when rradio is not permitted to use raw sockets or was built without them
+
+use std::{net::Ipv4Addr, time::Duration};
+
+use rradio_messages::PingError;
+
+pub struct Pinger;
+
+impl super::Ipv4Pinger for Pinger {
+    fn ping(&mut self, address: Ipv4Addr) -> Result<Duration, PingError> {
+        tracing::trace!("Pinging {}", address);
+
+        let output = std::process::Command::new("ping")
+            .args(&["-n", "-c", "1", "-W", "4"])
+            .arg(address.to_string())
+            .output()
+            .map_err(|io_err| {
+                let err = PingError::FailedToSendICMP;
+                tracing::error!("{} to {}: {}", err, address, io_err);
+                err
+            })?;
+
+        let output = String::from_utf8_lossy(&output.stdout);
+
+        if let Some(ping_time) = parse_ping_time(&output) {
+            tracing::debug!(
+                "Ping time to {:>16}: {:.3}ms",
+                address,
+                ping_time.as_secs_f32() * 1000.0
+            );
+
+            Ok(ping_time)
+        } else if output.contains("Unreachable") {
+            let err = PingError::DestinationUnreachable;
+            tracing::error!("{}: {}", err, address);
+            Err(err)
+        } else {
+            Err(PingError::Timeout)
+        }
+    }
+}
+
+/// Parse the ping time from a line such as "64 bytes from 8.8.8.8: icmp_seq=1 ttl=117 time=12.3 ms"
+fn parse_ping_time(output: &str) -> Option<Duration> {
+    let (_, time) = output.split_once("time=")?;
+
+    let milliseconds = time
+        .split(|c: char| !(c.is_ascii_digit() || c == '.'))
+        .next()?
+        .parse::<f64>()
+        .ok()?;
+
+    Some(Duration::from_secs_f64(milliseconds / 1000.0))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::parse_ping_time;
+
+    #[test]
+    fn ping_time() {
+        assert_eq!(
+            parse_ping_time(
+                "PING 8.8.8.8 (8.8.8.8) 56(84) bytes of data.\n64 bytes from 8.8.8.8: icmp_seq=1 ttl=117 time=12.5 ms\n"
+            ),
+            Some(Duration::from_micros(12500))
+        );
+    }
+
+    #[test]
+    fn no_reply() {
+        assert_eq!(
+            parse_ping_time("PING 8.8.8.8 (8.8.8.8) 56(84) bytes of data.\n\n--- 8.8.8.8 ping statistics ---\n1 packets transmitted, 0 received, 100% packet loss, time 0ms\n"),
+            None
+        );
+    }
+}
@@ -1,13 +1,20 @@
 //! A wrapper around a gstreamer playbin
 
-use std::{convert::TryInto, pin::Pin, time::Duration};
+use std::{
+    convert::TryInto,
+    pin::Pin,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
 
 use glib::{object::ObjectExt, Cast};
-use gstreamer::prelude::{ElementExt, ElementExtManual};
+use gstreamer::prelude::{ElementExt, ElementExtManual, GstBinExt, PadExt};
 use gstreamer_audio::prelude::StreamVolumeExt;
 
 pub use rradio_messages::PipelineState;
 
+use crate::config::pipeline::FlagOverrides;
+
 pub struct PipelineError;
 
 pub trait IgnorePipelineError {
@@ -22,7 +29,7 @@ impl IgnorePipelineError for Result<(), PipelineError> {
     }
 }
 
-trait PipelineErrorContext: Sized {
+pub trait PipelineErrorContext: Sized {
     type Output;
 
     fn with_context<C: std::fmt::Display>(
@@ -87,42 +94,241 @@ pub fn gstreamer_state_to_pipeline_state(
     })
 }
 
-pub struct Playbin(gstreamer::Element);
+fn backend_element_name(backend: crate::config::pipeline::Backend) -> &'static str {
+    use crate::config::pipeline::Backend;
+
+    match backend {
+        Backend::Playbin => "playbin",
+        Backend::Playbin3 => "playbin3",
+    }
+}
+
+/// The url which the playbin switches to when the current url is about to finish, so that there's no gap between tracks
+#[derive(Default)]
+struct Gapless {
+    /// Played once the current url finishes
+    queued_url: Option<String>,
+    /// The queued url, once the playbin has switched to it
+    switched_url: Option<String>,
+}
+
+pub struct Playbin(gstreamer::Element, Arc<Mutex<Gapless>>);
 
 impl Playbin {
     pub fn new(config: &crate::config::Config) -> Result<(Self, BusStream), PipelineError> {
-        let playbin_element = gstreamer::ElementFactory::make("playbin")
+        let element_name = backend_element_name(config.pipeline_config.backend);
+
+        tracing::debug!("Creating {element_name}");
+
+        let playbin_element = gstreamer::ElementFactory::make(element_name)
             .build()
-            .context("Failed to create a playbin")?;
-
-        let flags: glib::Value = playbin_element.property("flags");
-        let flags_class =
-            glib::FlagsClass::with_type(flags.type_()).context("Failed to create a flags class")?;
-        let flags = flags_class
-            .builder_with_value(flags)
-            .unwrap()
-            .unset_by_nick("text")
-            .unset_by_nick("video")
+            .with_context(|| format!("Failed to create a {element_name}"))?;
+
+        let bus = playbin_element.bus().context("Playbin has no bus")?;
+
+        let gapless = Arc::new(Mutex::new(Gapless::default()));
+
+        // Emitted from a streaming thread, and the next url must be set before the handler returns
+        playbin_element.connect("about-to-finish", false, {
+            let gapless = gapless.clone();
+            move |values| {
+                let playbin = values.first()?.get::<gstreamer::Element>().ok()?;
+                let mut gapless = gapless.lock().ok()?;
+                let url = gapless.queued_url.take()?;
+
+                tracing::debug!(%url, "Switching to queued url");
+
+                playbin.set_property("uri", url.as_str());
+                gapless.switched_url = Some(url);
+
+                None
+            }
+        });
+
+        let playbin = Self(playbin_element, gapless);
+
+        playbin.set_buffer_duration(config.buffering_duration)?;
+
+        // The audio sink is always set, rather than letting the playbin choose one, so that the output caps can be queried
+        playbin.set_audio_sink(&config.pipeline_config.audio_sink_description())?;
+
+        if config.loudness_normalisation.is_some() {
+            playbin.set_loudness_filter(super::loudness_normalisation::MEASUREMENT_INTERVAL)?;
+        }
+
+        playbin.set_volume(config.initial_volume)?;
+
+        Ok((playbin, BusStream::new(bus)))
+    }
+
+    /// Set the flags to the playbin's default flags, changed by `overrides` and then by `station_overrides`.
+    /// Returns the chosen flags, e.g. "audio+soft-volume+buffering". Flags should only be changed while the pipeline is stopped
+    pub fn set_flags(
+        &self,
+        overrides: &FlagOverrides,
+        station_overrides: Option<&FlagOverrides>,
+    ) -> Result<String, PipelineError> {
+        let default_flags = self
+            .0
+            .find_property("flags")
+            .context("Playbin has no flags")?
+            .default_value()
+            .clone();
+
+        let flags_class = glib::FlagsClass::with_type(default_flags.type_())
+            .context("Failed to create a flags class")?;
+
+        let mut builder = flags_class
+            .builder_with_value(default_flags)
+            .context("Bad default flags")?;
+
+        for overrides in std::iter::once(overrides).chain(station_overrides) {
+            for nick in &overrides.set {
+                builder = builder.set_by_nick(nick);
+            }
+
+            for nick in &overrides.unset {
+                builder = builder.unset_by_nick(nick);
+            }
+        }
+
+        let flags = builder
             .build()
-            .context("Failed to set flags")?;
-        playbin_element.set_property_from_value("flags", &flags);
+            .with_context(|| format!("Bad flags in {overrides:?} or {station_overrides:?}"))?;
+
+        self.0.set_property_from_value("flags", &flags);
 
-        if let Some(buffering_duration) = config.buffering_duration {
-            let duration_nanos: i64 = buffering_duration
+        Ok(flags_class
+            .values()
+            .iter()
+            .map(glib::FlagsValue::nick)
+            .filter(|nick| flags_class.is_set_by_nick(&flags, nick))
+            .collect::<Vec<_>>()
+            .join("+"))
+    }
+
+    /// Set the duration of audio which is buffered before playing, or use gstreamer's default if `None`. Takes effect from the next track
+    pub fn set_buffer_duration(
+        &self,
+        buffer_duration: Option<std::time::Duration>,
+    ) -> Result<(), PipelineError> {
+        let duration_nanos: i64 = match buffer_duration {
+            Some(buffer_duration) => buffer_duration
                 .as_nanos()
                 .try_into()
-                .context("Bad buffer duration")?;
+                .context("Bad buffer duration")?,
+            None => -1,
+        };
 
-            playbin_element.set_property("buffer-duration", duration_nanos);
-        }
+        self.0.set_property("buffer-duration", duration_nanos);
 
-        let bus = playbin_element.bus().context("Playbin has no bus")?;
+        Ok(())
+    }
 
-        let playbin = Self(playbin_element);
+    /// Set the audio sink from a gstreamer pipeline description. The audio sink should only be changed while the pipeline is stopped
+    pub fn set_audio_sink(&self, description: &str) -> Result<(), PipelineError> {
+        let audio_sink = gstreamer::parse_bin_from_description(description, true)
+            .with_context(|| format!("Failed to create audio sink {description:?}"))?;
 
-        playbin.set_volume(config.initial_volume)?;
+        self.0
+            .set_property("audio-sink", &audio_sink.upcast::<gstreamer::Element>());
 
-        Ok((playbin, BusStream::new(bus)))
+        Ok(())
+    }
+
+    /// Measure the loudness of the decoded audio with a `level` element, which posts a message every `interval`,
+    /// and then apply the gain set by [`Self::set_normalisation_gain`]. The user volume is applied afterwards, so doesn't affect the measured loudness
+    fn set_loudness_filter(&self, interval: Duration) -> Result<(), PipelineError> {
+        let interval_nanos: u64 = interval
+            .as_nanos()
+            .try_into()
+            .context("Bad loudness interval")?;
+
+        let description = format!(
+            "audioconvert ! level name=loudness interval={interval_nanos} ! volume name=normalisation"
+        );
+
+        let audio_filter = gstreamer::parse_bin_from_description(&description, true)
+            .with_context(|| format!("Failed to create audio filter {description:?}"))?;
+
+        self.0
+            .set_property("audio-filter", &audio_filter.upcast::<gstreamer::Element>());
+
+        Ok(())
+    }
+
+    /// Set the gain, in dB, applied by the loudness filter. Does nothing if loudness normalisation isn't configured
+    pub fn set_normalisation_gain(&self, gain: f64) -> Result<(), PipelineError> {
+        let Some(audio_filter) = self
+            .0
+            .property::<Option<gstreamer::Element>>("audio-filter")
+        else {
+            return Ok(());
+        };
+
+        let normalisation = audio_filter
+            .downcast_ref::<gstreamer::Bin>()
+            .and_then(|audio_filter| audio_filter.by_name("normalisation"))
+            .context("Audio filter has no normalisation")?;
+
+        normalisation.set_property("volume", 10_f64.powf(gain / 20.0));
+
+        Ok(())
+    }
+
+    /// The caps negotiated with the audio sink
+    pub fn sink_caps(&self) -> Option<gstreamer::Caps> {
+        let audio_sink = self
+            .0
+            .property::<Option<gstreamer::Element>>("audio-sink")?;
+
+        audio_sink.static_pad("sink")?.current_caps()
+    }
+
+    /// The caps negotiated with the audio sink, so that users can check that output is bit-perfect
+    pub fn output_caps(&self) -> Option<String> {
+        Some(self.sink_caps()?.to_string())
+    }
+
+    /// The decoded audio of the current track, before it's converted for the audio sink.
+    /// This is the output of the audio decoder, or for uncompressed audio, which isn't decoded, of the demuxer or source
+    pub fn source_audio(&self) -> Option<super::audio_format::SourceAudio> {
+        let elements = self
+            .0
+            .downcast_ref::<gstreamer::Bin>()?
+            .iterate_recurse()
+            .into_iter()
+            .filter_map(Result::ok)
+            .collect::<Vec<_>>();
+
+        let raw_audio_caps = |element: &gstreamer::Element| {
+            element.src_pads().into_iter().find_map(|pad| {
+                pad.current_caps().filter(|caps| {
+                    caps.structure(0)
+                        .is_some_and(|structure| structure.name() == "audio/x-raw")
+                })
+            })
+        };
+
+        let has_class = |element: &gstreamer::Element, class: &str| {
+            element
+                .factory()
+                .is_some_and(|factory| factory.klass().contains(class))
+        };
+
+        ["Decoder/Audio", "Demuxer", "Source"]
+            .iter()
+            .find_map(|&class| {
+                elements
+                    .iter()
+                    .filter(|element| has_class(element, class))
+                    .find_map(|element| {
+                        Some(super::audio_format::SourceAudio {
+                            caps: raw_audio_caps(element)?,
+                            is_decoded: class == "Decoder/Audio",
+                        })
+                    })
+            })
     }
 
     #[tracing::instrument(skip(self))]
@@ -150,9 +356,27 @@ impl Playbin {
     pub fn set_url(&self, url: &str) -> Result<(), PipelineError> {
         self.set_pipeline_state(PipelineState::Null)?;
         self.0.set_property("uri", url);
+
+        if let Ok(mut gapless) = self.1.lock() {
+            *gapless = Gapless::default();
+        }
+
         Ok(())
     }
 
+    /// Play `url` without a gap once the current url finishes, or if `None`, stop once the current url finishes. Cleared when a url is set
+    #[tracing::instrument(skip(self))]
+    pub fn queue_url(&self, url: Option<&str>) {
+        if let Ok(mut gapless) = self.1.lock() {
+            gapless.queued_url = url.map(String::from);
+        }
+    }
+
+    /// The queued url, if the playbin has switched to it since this was last called. The playbin switches before the current url has finished playing, so this should be called once the new stream has started
+    pub fn take_switched_url(&self) -> Option<String> {
+        self.1.lock().ok()?.switched_url.take()
+    }
+
     #[tracing::instrument(skip(self))]
     pub fn play_url(&self, url: &str) -> Result<(), PipelineError> {
         self.set_url(url)?;
@@ -257,6 +481,16 @@ impl Playbin {
             .map(Duration::from_nanos)
     }
 
+    /// Post an application message with the given name to the bus, which is received by the controller along with the pipeline's messages
+    #[cfg(feature = "watchdog")]
+    pub fn post_application_message(&self, name: &str) -> Result<(), PipelineError> {
+        self.0
+            .post_message(gstreamer::message::Application::new(
+                gstreamer::Structure::new_empty(name),
+            ))
+            .context("Failed to post application message")
+    }
+
     pub fn debug_pipeline(&self) {
         let debug_pipeline = || {
             let gst_debug_dump_dot_dir = std::env::var("GST_DEBUG_DUMP_DOT_DIR")
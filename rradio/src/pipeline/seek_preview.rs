@@ -0,0 +1,357 @@
+//! Previews of seeking, which use a separate pipeline so that the playing track isn't affected
+
+use std::{
+    convert::TryInto,
+    future::Future,
+    sync::{mpsc, Arc, Mutex},
+    time::Duration,
+};
+
+use glib::{object::ObjectExt, Cast};
+use gstreamer::prelude::{ElementExt, ElementExtManual, GstBinExt};
+
+use rradio_messages::ArcStr;
+
+use super::playbin::{PipelineError, PipelineErrorContext};
+use crate::ports::SharedEvent;
+
+/// How long to wait for the preview pipeline to preroll or seek
+const PIPELINE_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// The sample rate of the audio used to calculate peaks
+const PEAKS_SAMPLE_RATE: usize = 8000;
+
+/// The duration of audio around the requested position used to calculate peaks
+const PEAKS_WINDOW: Duration = Duration::from_secs(2);
+
+/// The number of samples in [`PEAKS_WINDOW`]
+const PEAKS_SAMPLE_COUNT: usize = 2 * PEAKS_SAMPLE_RATE;
+
+/// The number of peaks in a preview
+const PEAKS_COUNT: usize = 64;
+
+/// How long to wait for further requests before making a preview, so that only the final position is previewed while the user drags a seek bar
+const DEBOUNCE_DURATION: Duration = Duration::from_millis(100);
+
+/// How long the preview pipeline is kept open after the last request
+const PIPELINE_IDLE_DURATION: Duration = Duration::from_secs(30);
+
+fn clock_time(duration: Duration) -> Result<gstreamer::ClockTime, PipelineError> {
+    Ok(gstreamer::ClockTime::from_nseconds(
+        duration
+            .as_nanos()
+            .try_into()
+            .context("Failed to cast time")?,
+    ))
+}
+
+/// A pipeline which decodes a track without playing it. It's kept paused between previews of the same track
+struct PreviewPipeline {
+    url: ArcStr,
+    pipeline: gstreamer::Element,
+    sink: gstreamer::Element,
+}
+
+impl PreviewPipeline {
+    /// Create a pipeline for the track at `url`, and wait for it to preroll
+    fn new(url: ArcStr) -> Result<Self, PipelineError> {
+        let pipeline = gstreamer::parse_launch(&format!(
+            "uridecodebin name=source ! audioconvert ! audioresample ! audio/x-raw,format=S16LE,channels=1,rate={PEAKS_SAMPLE_RATE} ! fakesink name=sink sync=false signal-handoffs=true"
+        ))
+        .context("Failed to create seek preview pipeline")?;
+
+        let bin = pipeline
+            .downcast_ref::<gstreamer::Bin>()
+            .context("Seek preview pipeline is not a bin")?;
+
+        bin.by_name("source")
+            .context("Seek preview pipeline has no source")?
+            .set_property("uri", url.as_str());
+
+        let sink = bin
+            .by_name("sink")
+            .context("Seek preview pipeline has no sink")?;
+
+        let preview_pipeline = Self {
+            url,
+            pipeline,
+            sink,
+        };
+
+        preview_pipeline.pause()?;
+
+        Ok(preview_pipeline)
+    }
+
+    fn bus(&self) -> Result<gstreamer::Bus, PipelineError> {
+        self.pipeline
+            .bus()
+            .context("Seek preview pipeline has no bus")
+    }
+
+    /// Discard messages from earlier previews, so that they aren't mistaken for the result of the next state change or seek
+    fn clear_bus(&self) -> Result<(), PipelineError> {
+        let bus = self.bus()?;
+
+        while bus.pop().is_some() {}
+
+        Ok(())
+    }
+
+    /// Wait for the pipeline to finish changing state or seeking
+    fn wait_for_async_done(&self) -> Result<(), PipelineError> {
+        let message = self
+            .bus()?
+            .timed_pop_filtered(
+                clock_time(PIPELINE_TIMEOUT)?,
+                &[
+                    gstreamer::MessageType::AsyncDone,
+                    gstreamer::MessageType::Error,
+                ],
+            )
+            .context("Timeout waiting for seek preview pipeline")?;
+
+        match message.view() {
+            gstreamer::MessageView::Error(err) => {
+                tracing::error!("Seek preview pipeline error: {}", err.error());
+                Err(PipelineError)
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Pause the pipeline, so that it only decodes while making a preview
+    fn pause(&self) -> Result<(), PipelineError> {
+        self.clear_bus()?;
+
+        if self.pipeline.set_state(gstreamer::State::Paused)?
+            == gstreamer::StateChangeSuccess::Async
+        {
+            self.wait_for_async_done()?;
+        }
+
+        Ok(())
+    }
+
+    fn seek(
+        &self,
+        flags: gstreamer::SeekFlags,
+        position: Duration,
+    ) -> Result<Duration, PipelineError> {
+        self.clear_bus()?;
+
+        self.pipeline
+            .seek_simple(gstreamer::SeekFlags::FLUSH | flags, clock_time(position)?)
+            .context("Failed to seek")?;
+
+        self.wait_for_async_done()?;
+
+        self.pipeline
+            .query_position::<gstreamer::ClockTime>()
+            .map(gstreamer::ClockTime::nseconds)
+            .map(Duration::from_nanos)
+            .context("Failed to query position")
+    }
+
+    fn collect_peaks(&self, position: Duration) -> Result<Vec<u8>, PipelineError> {
+        let (samples_tx, samples_rx) = mpsc::sync_channel(1);
+        let samples = Arc::new(Mutex::new(Vec::<i16>::with_capacity(PEAKS_SAMPLE_COUNT)));
+
+        let handoff_handler = self.sink.connect("handoff", false, move |values| {
+            let buffer = values.get(1)?.get::<gstreamer::Buffer>().ok()?;
+            let map = buffer.map_readable().ok()?;
+
+            let mut samples = samples.lock().ok()?;
+
+            if samples.len() < PEAKS_SAMPLE_COUNT {
+                samples.extend(
+                    map.as_slice()
+                        .chunks_exact(2)
+                        .map(|sample| i16::from_le_bytes([sample[0], sample[1]])),
+                );
+
+                if samples.len() >= PEAKS_SAMPLE_COUNT {
+                    samples.truncate(PEAKS_SAMPLE_COUNT);
+                    samples_tx.try_send(std::mem::take(&mut *samples)).ok();
+                }
+            }
+
+            None
+        });
+
+        let collect_samples = || {
+            self.seek(
+                gstreamer::SeekFlags::ACCURATE,
+                position.saturating_sub(PEAKS_WINDOW / 2),
+            )?;
+
+            self.pipeline.set_state(gstreamer::State::Playing)?;
+
+            samples_rx
+                .recv_timeout(PIPELINE_TIMEOUT)
+                .context("Timeout waiting for seek preview samples")
+        };
+
+        let samples = collect_samples();
+
+        // The pipeline is reused for the next preview, which collects its own samples
+        self.sink.disconnect(handoff_handler);
+
+        Ok(calculate_peaks(&samples?))
+    }
+
+    /// Find the keyframe nearest to `position`, and for local files, the peaks of the audio around `position`
+    #[tracing::instrument(skip(self), fields(url = %self.url))]
+    fn preview(&self, position: Duration) -> Result<rradio_messages::SeekPreview, PipelineError> {
+        let keyframe_position = self.seek(
+            gstreamer::SeekFlags::KEY_UNIT | gstreamer::SeekFlags::SNAP_NEAREST,
+            position,
+        )?;
+
+        let peaks = if self.url.starts_with("file://") {
+            let peaks = self.collect_peaks(position).ok();
+
+            // Collecting peaks plays the pipeline, which would otherwise decode the rest of the track
+            self.pause()?;
+
+            peaks
+        } else {
+            None
+        };
+
+        Ok(rradio_messages::SeekPreview {
+            requested_position: position,
+            keyframe_position,
+            peaks,
+        })
+    }
+}
+
+impl Drop for PreviewPipeline {
+    fn drop(&mut self) {
+        if let Err(err) = self.pipeline.set_state(gstreamer::State::Null) {
+            tracing::error!("Failed to stop seek preview pipeline: {err}");
+        }
+    }
+}
+
+/// Reduce the samples to [`PEAKS_COUNT`] peak amplitudes
+fn calculate_peaks(samples: &[i16]) -> Vec<u8> {
+    let chunk_size = (samples.len() / PEAKS_COUNT).max(1);
+
+    samples
+        .chunks(chunk_size)
+        .take(PEAKS_COUNT)
+        .map(|chunk| {
+            let peak = chunk.iter().map(|sample| sample.unsigned_abs()).max();
+            (peak.unwrap_or_default() >> 7)
+                .try_into()
+                .unwrap_or(u8::MAX)
+        })
+        .collect()
+}
+
+/// A request for a preview of seeking in a track
+#[derive(Clone)]
+pub struct Request {
+    pub url: ArcStr,
+    pub position: Duration,
+    /// The preview is only sent to the client which requested it
+    pub replies_tx: tokio::sync::mpsc::Sender<SharedEvent>,
+}
+
+/// Make previews in the background, one at a time. A request replaces any request which is waiting, so that dragging a seek bar doesn't queue up previews.
+/// The preview pipeline is kept between requests for the same track, so that the track isn't reopened for each preview
+pub fn previewer() -> (
+    tokio::sync::watch::Sender<Option<Request>>,
+    impl Future<Output = ()>,
+) {
+    let (requests_tx, mut requests_rx) = tokio::sync::watch::channel(None::<Request>);
+
+    let task = async move {
+        let mut pipeline = None::<PreviewPipeline>;
+
+        loop {
+            let changed = if pipeline.is_some() {
+                let timeout = tokio::time::timeout(PIPELINE_IDLE_DURATION, requests_rx.changed());
+
+                if let Ok(changed) = timeout.await {
+                    changed
+                } else {
+                    // Stopping the pipeline might block
+                    let pipeline = pipeline.take();
+                    tokio::task::spawn_blocking(move || drop(pipeline));
+                    continue;
+                }
+            } else {
+                requests_rx.changed().await
+            };
+
+            if changed.is_err() {
+                break;
+            }
+
+            tokio::time::sleep(DEBOUNCE_DURATION).await;
+
+            let Some(Request {
+                url,
+                position,
+                replies_tx,
+            }) = requests_rx.borrow_and_update().clone()
+            else {
+                continue;
+            };
+
+            let previous_pipeline = pipeline.take();
+
+            let result = tokio::task::spawn_blocking(move || {
+                let pipeline = match previous_pipeline {
+                    Some(pipeline) if pipeline.url == url => pipeline,
+                    _ => PreviewPipeline::new(url)?,
+                };
+
+                let seek_preview = pipeline.preview(position)?;
+
+                Ok::<_, PipelineError>((pipeline, seek_preview))
+            })
+            .await;
+
+            match result {
+                Ok(Ok((preview_pipeline, seek_preview))) => {
+                    pipeline = Some(preview_pipeline);
+
+                    // If the client is too slow to receive replies, it misses the preview
+                    replies_tx
+                        .try_send(SharedEvent::new(rradio_messages::Event::SeekPreview(
+                            seek_preview,
+                        )))
+                        .ok();
+                }
+                Ok(Err(PipelineError)) => (),
+                Err(err) => tracing::error!("Seek preview failed: {err}"),
+            }
+        }
+    };
+
+    (requests_tx, task)
+}
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn peaks() {
+        let samples = (0..6400)
+            .map(|index| if index % 100 == 0 { i16::MIN } else { 256 })
+            .collect::<Vec<_>>();
+
+        let peaks = super::calculate_peaks(&samples);
+
+        assert_eq!(peaks.len(), super::PEAKS_COUNT);
+        assert!(peaks.iter().all(|&peak| peak == u8::MAX));
+
+        assert_eq!(
+            super::calculate_peaks(&[256; 6400]),
+            vec![2; super::PEAKS_COUNT]
+        );
+    }
+}
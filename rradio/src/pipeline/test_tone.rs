@@ -0,0 +1,50 @@
+//! A short tone, played using a separate pipeline, to check that audio output works
+
+use std::time::Duration;
+
+use anyhow::Context;
+use gstreamer::prelude::ElementExt;
+
+const TONE_FREQUENCY_HZ: u32 = 440;
+/// About -20 dBFS, so that the tone isn't startling if the amplifier is turned up
+const TONE_VOLUME: f64 = 0.1;
+const TONE_SAMPLE_RATE: u32 = 44100;
+/// Each buffer is a tenth of a second long
+const TONE_SAMPLES_PER_BUFFER: u32 = TONE_SAMPLE_RATE / 10;
+const TONE_BUFFER_COUNT: u32 = 5;
+
+/// If the tone hasn't finished by then, the audio sink is assumed to be stuck
+const TONE_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Play the tone, blocking until it has finished
+pub fn play(config: &crate::config::Config) -> anyhow::Result<()> {
+    let audio_sink = config.pipeline_config.audio_sink_description();
+
+    let pipeline = gstreamer::parse_launch(&format!(
+        "audiotestsrc freq={TONE_FREQUENCY_HZ} volume={TONE_VOLUME} samplesperbuffer={TONE_SAMPLES_PER_BUFFER} num-buffers={TONE_BUFFER_COUNT} ! audio/x-raw,rate={TONE_SAMPLE_RATE} ! audioconvert ! audioresample ! {audio_sink}"
+    ))
+    .context("Failed to create test tone pipeline")?;
+
+    let bus = pipeline.bus().context("Test tone pipeline has no bus")?;
+
+    pipeline
+        .set_state(gstreamer::State::Playing)
+        .context("Failed to play test tone")?;
+
+    let result = match bus.timed_pop_filtered(
+        gstreamer::ClockTime::from_seconds(TONE_TIMEOUT.as_secs()),
+        &[gstreamer::MessageType::Eos, gstreamer::MessageType::Error],
+    ) {
+        Some(message) => match message.view() {
+            gstreamer::MessageView::Error(err) => {
+                Err(anyhow::anyhow!("Audio output error: {}", err.error()))
+            }
+            _ => Ok(()),
+        },
+        None => Err(anyhow::anyhow!("Test tone did not finish")),
+    };
+
+    pipeline.set_state(gstreamer::State::Null).ok();
+
+    result
+}
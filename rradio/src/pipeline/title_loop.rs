@@ -0,0 +1,140 @@
+//! Detecting streams which play the same title over and over, such as a free stream which loops the same advert break when it's region-blocked
+
+use std::{collections::VecDeque, time::Instant};
+
+use rradio_messages::ArcStr;
+
+use crate::config::title_loop::Config;
+
+pub struct TitleLoopDetector {
+    config: Option<Config>,
+    /// When the current track changed to each title, oldest first
+    titles: VecDeque<(Instant, ArcStr)>,
+    /// Streams often send the same title again with each tag, which doesn't count as the title looping
+    latest_title: Option<ArcStr>,
+    /// A loop is only reported once per track
+    is_detected: bool,
+}
+
+impl TitleLoopDetector {
+    pub fn new(config: Option<Config>) -> Self {
+        Self {
+            config,
+            titles: VecDeque::new(),
+            latest_title: None,
+            is_detected: false,
+        }
+    }
+
+    pub fn start_track(&mut self) {
+        self.titles.clear();
+        self.latest_title = None;
+        self.is_detected = false;
+    }
+
+    /// Record that a title was received. Returns true if the title has just been found to loop
+    pub fn record_title(&mut self, title: &ArcStr) -> bool {
+        self.record_title_at(title, Instant::now())
+    }
+
+    fn record_title_at(&mut self, title: &ArcStr, now: Instant) -> bool {
+        let Some(config) = &self.config else {
+            return false;
+        };
+
+        if self.is_detected {
+            return false;
+        }
+
+        if self.latest_title.as_ref() == Some(title) {
+            return false;
+        }
+
+        self.latest_title = Some(title.clone());
+
+        self.titles.push_back((now, title.clone()));
+
+        let window = config.window;
+        while self
+            .titles
+            .front()
+            .is_some_and(|(received_at, _)| now.duration_since(*received_at) > window)
+        {
+            self.titles.pop_front();
+        }
+
+        let repeat_count = self
+            .titles
+            .iter()
+            .filter(|(_, received_title)| received_title == title)
+            .count();
+
+        self.is_detected = repeat_count >= config.repeat_count.get();
+
+        self.is_detected
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        num::NonZeroUsize,
+        time::{Duration, Instant},
+    };
+
+    use crate::config::title_loop::{Action, Config};
+
+    use super::TitleLoopDetector;
+
+    fn detector() -> TitleLoopDetector {
+        TitleLoopDetector::new(Some(Config {
+            repeat_count: NonZeroUsize::new(3).unwrap(),
+            window: Duration::from_secs(5 * 60),
+            action: Action::Log,
+        }))
+    }
+
+    #[test]
+    fn repeated_titles_are_detected_once() {
+        let mut detector = detector();
+        let start = Instant::now();
+
+        let detections = (0..)
+            .zip([
+                "Advert", "News", "Advert", "News", "Advert", "News", "Advert",
+            ])
+            .map(|(i, title)| {
+                detector.record_title_at(&title.into(), start + Duration::from_secs(30) * i)
+            })
+            .collect::<Vec<_>>();
+
+        assert_eq!(detections, [false, false, false, false, true, false, false]);
+    }
+
+    #[test]
+    fn repeated_tags_with_the_same_title_are_ignored() {
+        let mut detector = detector();
+        let start = Instant::now();
+
+        let detections = (0..10)
+            .map(|i| detector.record_title_at(&"Song".into(), start + Duration::from_secs(10) * i))
+            .collect::<Vec<_>>();
+
+        assert_eq!(detections, [false; 10]);
+    }
+
+    #[test]
+    fn titles_outside_the_window_are_forgotten() {
+        let mut detector = detector();
+        let start = Instant::now();
+
+        let detections = (0..)
+            .zip(["Song", "News", "Song", "News", "Song"])
+            .map(|(i, title)| {
+                detector.record_title_at(&title.into(), start + Duration::from_secs(2 * 60) * i)
+            })
+            .collect::<Vec<_>>();
+
+        assert_eq!(detections, [false, false, false, false, false]);
+    }
+}
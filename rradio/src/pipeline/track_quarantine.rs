@@ -0,0 +1,76 @@
+//! Tracks which consistently fail to play, e.g. corrupt files, are quarantined and skipped for the rest of the session
+
+use std::collections::HashMap;
+
+use rradio_messages::ArcStr;
+
+pub struct TrackQuarantine {
+    maximum_track_failures: Option<usize>,
+    failure_counts: HashMap<ArcStr, usize>,
+}
+
+impl TrackQuarantine {
+    pub fn new(maximum_track_failures: Option<usize>) -> Self {
+        Self {
+            maximum_track_failures,
+            failure_counts: HashMap::new(),
+        }
+    }
+
+    /// Record that the track with the given url failed to play. Returns true if the track has just been quarantined
+    pub fn record_failure(&mut self, url: &ArcStr) -> bool {
+        let Some(maximum_track_failures) = self.maximum_track_failures else {
+            return false;
+        };
+
+        let failure_count = self.failure_counts.entry(url.clone()).or_insert(0);
+        *failure_count += 1;
+
+        *failure_count == maximum_track_failures + 1
+    }
+
+    pub fn contains(&self, url: &str) -> bool {
+        self.maximum_track_failures
+            .zip(self.failure_counts.get(url))
+            .is_some_and(|(maximum_track_failures, &failure_count)| {
+                failure_count > maximum_track_failures
+            })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rradio_messages::arcstr;
+
+    use super::TrackQuarantine;
+
+    #[test]
+    fn tracks_are_quarantined_after_too_many_failures() {
+        let mut quarantine = TrackQuarantine::new(Some(2));
+        let url = arcstr::literal!("file:///corrupt.mp3");
+
+        assert!(!quarantine.record_failure(&url));
+        assert!(!quarantine.record_failure(&url));
+        assert!(!quarantine.contains(&url));
+
+        assert!(quarantine.record_failure(&url));
+        assert!(quarantine.contains(&url));
+        assert!(!quarantine.contains("file:///other.mp3"));
+
+        // The track was already quarantined
+        assert!(!quarantine.record_failure(&url));
+        assert!(quarantine.contains(&url));
+    }
+
+    #[test]
+    fn tracks_are_not_quarantined_if_disabled() {
+        let mut quarantine = TrackQuarantine::new(None);
+        let url = arcstr::literal!("file:///corrupt.mp3");
+
+        for _ in 0..10 {
+            assert!(!quarantine.record_failure(&url));
+        }
+
+        assert!(!quarantine.contains(&url));
+    }
+}
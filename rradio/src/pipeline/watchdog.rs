@@ -0,0 +1,201 @@
+//! Feeding a hardware watchdog, such as the Raspberry Pi's, while the controller and the gstreamer bus are responsive,
+//! so that if the audio stack stops responding the watchdog reboots the device. Linux only
+
+use std::{
+    io::Write,
+    sync::{mpsc, Arc, Mutex, PoisonError},
+    time::{Duration, Instant},
+};
+
+use anyhow::{Context, Result};
+
+use crate::config::watchdog::Config;
+
+/// The name of the application message which is posted to the bus, and which shows that the bus is responsive once the controller receives it
+pub const PROBE_NAME: &str = "rradio-watchdog-probe";
+
+/// How often a probe is posted to the bus
+const PROBE_INTERVAL: Duration = Duration::from_secs(1);
+
+#[derive(Clone, Copy, Debug)]
+struct Heartbeats {
+    /// When the controller last handled a message or timeout
+    controller: Instant,
+    /// When the controller last received a probe from the bus, or last found the pipeline stopped, when the bus is flushed and so can't be probed
+    bus: Instant,
+}
+
+impl Heartbeats {
+    /// What's unresponsive, if anything
+    fn unresponsive(&self, timeout: Duration) -> Option<&'static str> {
+        if self.controller.elapsed() > timeout {
+            Some("controller")
+        } else if self.bus.elapsed() > timeout {
+            Some("gstreamer bus")
+        } else {
+            None
+        }
+    }
+}
+
+/// Held by the controller, which records that it and the bus are responsive
+pub struct Liveness {
+    heartbeats: Arc<Mutex<Heartbeats>>,
+    last_probe: Instant,
+    finish_tx: mpsc::Sender<()>,
+    feeder: std::thread::JoinHandle<()>,
+}
+
+impl Liveness {
+    fn update(&self, update: impl FnOnce(&mut Heartbeats)) {
+        update(
+            &mut self
+                .heartbeats
+                .lock()
+                .unwrap_or_else(PoisonError::into_inner),
+        );
+    }
+
+    /// Record that the controller is responsive. Returns true if a probe should be posted to the bus
+    pub fn controller_is_responsive(&mut self) -> bool {
+        self.update(|heartbeats| heartbeats.controller = Instant::now());
+
+        if self.last_probe.elapsed() < PROBE_INTERVAL {
+            return false;
+        }
+
+        self.last_probe = Instant::now();
+
+        true
+    }
+
+    /// Record that a probe has been received from the bus, or that the pipeline is stopped, so the bus can't be probed
+    pub fn bus_is_responsive(&self) {
+        self.update(|heartbeats| heartbeats.bus = Instant::now());
+    }
+
+    /// Disable the watchdog, as rradio is shutting down cleanly. If the controller panics instead, the watchdog is no longer fed, and reboots the device
+    pub fn finish(self) {
+        self.finish_tx.send(()).ok();
+
+        if self.feeder.join().is_err() {
+            tracing::error!("The watchdog feeder panicked");
+        }
+    }
+}
+
+/// Open the watchdog device, which starts the watchdog, and feed it from a dedicated thread,
+/// so that it's still fed while the controller's thread is busy, e.g. loading a station, until `unresponsive_timeout` passes
+pub fn run(config: Config) -> Result<Liveness> {
+    let device = std::fs::OpenOptions::new()
+        .write(true)
+        .open(config.device.as_str())
+        .with_context(|| format!("Failed to open {}", config.device))?;
+
+    tracing::info!(device = %config.device, "Started watchdog");
+
+    let now = Instant::now();
+
+    let heartbeats = Arc::new(Mutex::new(Heartbeats {
+        controller: now,
+        bus: now,
+    }));
+
+    let (finish_tx, finish_rx) = mpsc::channel();
+
+    let feeder = std::thread::Builder::new()
+        .name(String::from("watchdog"))
+        .spawn({
+            let heartbeats = heartbeats.clone();
+            move || feed(device, &config, &heartbeats, &finish_rx)
+        })
+        .context("Failed to spawn watchdog thread")?;
+
+    Ok(Liveness {
+        heartbeats,
+        last_probe: now,
+        finish_tx,
+        feeder,
+    })
+}
+
+/// Feed the watchdog every `feed_interval` while the controller and the bus are responsive, until the controller finishes
+fn feed(
+    mut device: std::fs::File,
+    config: &Config,
+    heartbeats: &Mutex<Heartbeats>,
+    finish_rx: &mpsc::Receiver<()>,
+) {
+    let mut was_unresponsive = false;
+
+    loop {
+        match finish_rx.recv_timeout(config.feed_interval) {
+            Ok(()) => {
+                // Writing "V" before closing the device disables the watchdog, unless the kernel doesn't allow the watchdog to be disabled
+                match device.write_all(b"V") {
+                    Ok(()) => tracing::info!("Stopped watchdog"),
+                    Err(err) => tracing::error!("Failed to stop watchdog: {err}"),
+                }
+
+                return;
+            }
+            Err(mpsc::RecvTimeoutError::Disconnected) => {
+                // Closing the device without writing "V" doesn't disable the watchdog, so it reboots the device
+                tracing::error!("The controller has stopped. The watchdog is no longer fed");
+                return;
+            }
+            Err(mpsc::RecvTimeoutError::Timeout) => (),
+        }
+
+        let unresponsive = heartbeats
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .unresponsive(config.unresponsive_timeout);
+
+        if let Some(unresponsive) = unresponsive {
+            if !was_unresponsive {
+                tracing::error!(
+                    "The {unresponsive} is unresponsive. The watchdog is no longer fed"
+                );
+                was_unresponsive = true;
+            }
+
+            continue;
+        }
+
+        if was_unresponsive {
+            tracing::warn!("The pipeline is responsive again. The watchdog is fed again");
+            was_unresponsive = false;
+        }
+
+        if let Err(err) = device.write_all(b"\0") {
+            tracing::error!("Failed to feed watchdog: {err}");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant};
+
+    use super::Heartbeats;
+
+    #[test]
+    fn unresponsive_controller_or_bus_is_detected() {
+        let timeout = Duration::from_secs(60);
+        let now = Instant::now();
+        let long_ago = now.checked_sub(Duration::from_secs(120)).unwrap();
+
+        let heartbeats = |controller, bus| Heartbeats { controller, bus };
+
+        assert_eq!(heartbeats(now, now).unresponsive(timeout), None);
+        assert_eq!(
+            heartbeats(long_ago, now).unresponsive(timeout),
+            Some("controller")
+        );
+        assert_eq!(
+            heartbeats(now, long_ago).unresponsive(timeout),
+            Some("gstreamer bus")
+        );
+    }
+}
@@ -0,0 +1,157 @@
+//! Tracks which are pages rather than audio streams, such as `YouTube` videos and live streams, are resolved into the url of their audio stream using yt-dlp just before they're played
+
+use std::{collections::HashMap, time::Instant};
+
+use anyhow::{Context, Result};
+
+use rradio_messages::ArcStr;
+
+use crate::config::yt_dlp::Config;
+
+struct ResolvedUrl {
+    url: ArcStr,
+    resolved_at: Instant,
+}
+
+pub struct YtDlp {
+    config: Config,
+    /// Resolved urls, keyed by page url
+    cache: HashMap<ArcStr, ResolvedUrl>,
+}
+
+impl YtDlp {
+    pub fn new(config: Config) -> Self {
+        Self {
+            config,
+            cache: HashMap::new(),
+        }
+    }
+
+    /// True if yt-dlp is enabled and `url` is on one of the configured hosts, or their subdomains
+    pub fn resolves(&self, url: &str) -> bool {
+        if self.config.path.is_none() {
+            return false;
+        }
+
+        let Some(host) = url::Url::parse(url)
+            .ok()
+            .and_then(|url| url.host_str().map(str::to_owned))
+        else {
+            return false;
+        };
+
+        self.config.hosts.iter().any(|configured_host| {
+            host.strip_suffix(configured_host.as_str())
+                .is_some_and(|prefix| prefix.is_empty() || prefix.ends_with('.'))
+        })
+    }
+
+    fn cached(&self, url: &str) -> Option<ArcStr> {
+        self.cache
+            .get(url)
+            .filter(|resolved_url| resolved_url.resolved_at.elapsed() < self.config.cache_duration)
+            .map(|resolved_url| resolved_url.url.clone())
+    }
+
+    /// The url of the audio stream of `url`, or `url` itself if it isn't resolved by yt-dlp
+    pub async fn resolve(&mut self, url: &ArcStr) -> Result<ArcStr> {
+        if !self.resolves(url) {
+            return Ok(url.clone());
+        }
+
+        if let Some(resolved_url) = self.cached(url) {
+            tracing::debug!(%url, %resolved_url, "Using cached yt-dlp url");
+            return Ok(resolved_url);
+        }
+
+        let resolved_url = self.run(url).await?;
+
+        tracing::debug!(%url, %resolved_url, "Resolved with yt-dlp");
+
+        let cache_duration = self.config.cache_duration;
+        self.cache
+            .retain(|_, resolved_url| resolved_url.resolved_at.elapsed() < cache_duration);
+
+        self.cache.insert(
+            url.clone(),
+            ResolvedUrl {
+                url: resolved_url.clone(),
+                resolved_at: Instant::now(),
+            },
+        );
+
+        Ok(resolved_url)
+    }
+
+    async fn run(&self, url: &str) -> Result<ArcStr> {
+        let path = self.config.path.as_deref().context("yt-dlp is disabled")?;
+
+        let output = tokio::process::Command::new(path)
+            .args([
+                "--no-playlist",
+                "--no-warnings",
+                "--format",
+                self.config.format.as_str(),
+                "--get-url",
+                url,
+            ])
+            .stdin(std::process::Stdio::null())
+            .kill_on_drop(true)
+            .output();
+
+        let output = tokio::time::timeout(self.config.timeout, output)
+            .await
+            .map_err(|_| {
+                anyhow::anyhow!(
+                    "yt-dlp took more than {}s to resolve {url}",
+                    self.config.timeout.as_secs()
+                )
+            })?
+            .with_context(|| format!("Failed to run {path:?}"))?;
+
+        anyhow::ensure!(
+            output.status.success(),
+            "yt-dlp failed to resolve {url}: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+
+        // If the format selects separate video and audio streams, yt-dlp prints one url for each, but the format should select a single stream containing audio
+        String::from_utf8_lossy(&output.stdout)
+            .lines()
+            .map(str::trim)
+            .find(|line| !line.is_empty())
+            .map(ArcStr::from)
+            .with_context(|| format!("yt-dlp didn't find a stream for {url}"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rradio_messages::arcstr;
+
+    use super::YtDlp;
+
+    #[test]
+    fn only_configured_hosts_are_resolved() {
+        let yt_dlp = YtDlp::new(crate::config::yt_dlp::Config {
+            path: Some(arcstr::literal!("yt-dlp")),
+            ..crate::config::yt_dlp::Config::default()
+        });
+
+        for (url, expected) in [
+            ("https://www.youtube.com/watch?v=abc", true),
+            ("https://youtu.be/abc", true),
+            ("https://artist.bandcamp.com/album/name", true),
+            ("https://soundcloud.com/artist/track", true),
+            ("https://notyoutube.com/watch?v=abc", false),
+            ("http://example.com/stream.mp3", false),
+            ("file:///music/track.flac", false),
+        ] {
+            assert_eq!(yt_dlp.resolves(url), expected, "{url}");
+        }
+
+        let disabled = YtDlp::new(crate::config::yt_dlp::Config::default());
+
+        assert!(!disabled.resolves("https://www.youtube.com/watch?v=abc"));
+    }
+}
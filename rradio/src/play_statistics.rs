@@ -0,0 +1,259 @@
+//! How often and how recently each track of a USB or `UPnP` station has been played, stored as JSON lines in the state directory.
+//! Each play is appended to the file, and the file is rewritten with one line per track when the statistics are loaded
+
+use std::{
+    collections::BTreeMap,
+    io::Write,
+    ops::Bound,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{Context, Result};
+use chrono::{DateTime, Utc};
+
+use rradio_messages::{arcstr, ArcStr, TrackPlayStatistics};
+
+const PLAY_STATISTICS_FILE_NAME: &str = "play_statistics.jsonl";
+
+#[derive(Clone, Copy, serde::Deserialize, serde::Serialize)]
+struct Entry {
+    play_count: u64,
+    last_played: DateTime<Utc>,
+}
+
+impl Entry {
+    fn merge(&mut self, other: Self) {
+        self.play_count += other.play_count;
+        self.last_played = self.last_played.max(other.last_played);
+    }
+}
+
+/// A line of the statistics file. A track might have several lines, which are merged
+#[derive(serde::Deserialize, serde::Serialize)]
+struct Line {
+    key: ArcStr,
+    #[serde(flatten)]
+    entry: Entry,
+}
+
+/// The key of a track. Tracks on mounted devices are identified by their path on the device, as the device might be mounted in a different directory each time
+pub fn track_key(url: &str, mounted_directory: Option<&Path>) -> ArcStr {
+    mounted_directory
+        .and_then(|mounted_directory| {
+            url.strip_prefix("file://")?
+                .strip_prefix(mounted_directory.to_str()?)
+        })
+        .map_or_else(
+            || ArcStr::from(url),
+            |path| arcstr::format!("usb:{}", path.trim_start_matches('/')),
+        )
+}
+
+/// The prefix of the keys of tracks in `directory`, which is relative to the root of a mounted device
+pub fn directory_key_prefix(directory: &Path) -> Option<String> {
+    Some(format!("usb:{}/", directory.to_str()?.trim_matches('/')))
+}
+
+#[derive(Clone)]
+pub struct PlayStatistics {
+    path: PathBuf,
+    entries: BTreeMap<ArcStr, Entry>,
+}
+
+impl PlayStatistics {
+    /// Load the statistics from the given state directory, starting afresh if they can't be loaded
+    pub fn load(state_directory: impl AsRef<Path>) -> Self {
+        let path = state_directory.as_ref().join(PLAY_STATISTICS_FILE_NAME);
+
+        let entries = match read_entries(&path) {
+            Ok((entries, line_count)) => {
+                if line_count > entries.len() {
+                    if let Err(err) = compact(&path, &entries) {
+                        tracing::error!("{err:#}");
+                    }
+                }
+
+                entries
+            }
+            Err(err) => {
+                tracing::error!("{err:#}");
+                BTreeMap::new()
+            }
+        };
+
+        Self { path, entries }
+    }
+
+    /// Record that the track with the given key has started playing, and append the play to the statistics file
+    pub fn record_play(&mut self, key: ArcStr) {
+        let entry = Entry {
+            play_count: 1,
+            last_played: Utc::now(),
+        };
+
+        self.entries
+            .entry(key.clone())
+            .and_modify(|existing_entry| existing_entry.merge(entry))
+            .or_insert(entry);
+
+        if let Err(err) = self.append(&Line { key, entry }) {
+            tracing::error!("{err:#}");
+        }
+    }
+
+    /// When the track was last played, or None if it has never been played
+    pub fn last_played(&self, key: &str) -> Option<DateTime<Utc>> {
+        self.entries.get(key).map(|entry| entry.last_played)
+    }
+
+    /// When any track with a key starting with `prefix` was last played, or None if none have been played
+    pub fn last_played_with_prefix(&self, prefix: &str) -> Option<DateTime<Utc>> {
+        self.entries
+            .range::<str, _>((Bound::Included(prefix), Bound::Unbounded))
+            .take_while(|(key, _)| key.starts_with(prefix))
+            .map(|(_, entry)| entry.last_played)
+            .max()
+    }
+
+    /// The statistics of the `count` most recently played tracks, most recently played first
+    pub fn most_recently_played(&self, count: usize) -> Vec<TrackPlayStatistics> {
+        let mut statistics = self
+            .entries
+            .iter()
+            .map(|(key, entry)| TrackPlayStatistics {
+                track: key.clone(),
+                play_count: entry.play_count,
+                last_played: entry.last_played,
+            })
+            .collect::<Vec<_>>();
+
+        statistics.sort_by_key(|statistics| std::cmp::Reverse(statistics.last_played));
+        statistics.truncate(count);
+
+        statistics
+    }
+
+    fn append(&self, line: &Line) -> Result<()> {
+        if let Some(parent) = self.path.parent() {
+            std::fs::create_dir_all(parent)
+                .with_context(|| format!("Failed to create {}", parent.display()))?;
+        }
+
+        let mut line = serde_json::to_vec(line).context("Failed to serialize play statistics")?;
+        line.push(b'\n');
+
+        // The line is written with a single write, so that it's either written completely or, if rradio stops while writing, skipped when loading
+        std::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)
+            .and_then(|mut file| file.write_all(&line))
+            .with_context(|| format!("Failed to write {}", self.path.display()))
+    }
+}
+
+/// Read the statistics, and the number of lines in the file, merging the lines of each track
+fn read_entries(path: &Path) -> Result<(BTreeMap<ArcStr, Entry>, usize)> {
+    let Some(contents) = crate::state_file::read_to_string(path)? else {
+        return Ok((BTreeMap::new(), 0));
+    };
+
+    let mut entries = BTreeMap::<ArcStr, Entry>::new();
+    let mut line_count = 0;
+
+    for line in contents.lines() {
+        line_count += 1;
+
+        // A line might be partially written if rradio stopped while appending to the file, so skip lines which can't be parsed
+        match serde_json::from_str::<Line>(line) {
+            Ok(Line { key, entry }) => {
+                entries
+                    .entry(key)
+                    .and_modify(|existing_entry| existing_entry.merge(entry))
+                    .or_insert(entry);
+            }
+            Err(err) => tracing::warn!("Bad play statistics entry in {}: {err}", path.display()),
+        }
+    }
+
+    Ok((entries, line_count))
+}
+
+/// Rewrite the statistics file with one line per track
+fn compact(path: &Path, entries: &BTreeMap<ArcStr, Entry>) -> Result<()> {
+    let mut contents = Vec::new();
+
+    for (key, &entry) in entries {
+        serde_json::to_writer(
+            &mut contents,
+            &Line {
+                key: key.clone(),
+                entry,
+            },
+        )
+        .context("Failed to serialize play statistics")?;
+
+        contents.push(b'\n');
+    }
+
+    crate::state_file::write(path, contents)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use super::{directory_key_prefix, track_key, PlayStatistics};
+
+    #[test]
+    fn tracks_on_mounted_devices_are_keyed_by_path() {
+        let state_directory = tempfile::tempdir().unwrap();
+
+        let mut play_statistics = PlayStatistics::load(state_directory.path());
+
+        let first_mount = Path::new("/tmp/.tmpAbC123");
+        let second_mount = Path::new("/tmp/.tmpXyZ789");
+
+        let key = track_key(
+            "file:///tmp/.tmpAbC123/Music/Artist/Album/01 Track.mp3",
+            Some(first_mount),
+        );
+
+        assert_eq!(key, "usb:Music/Artist/Album/01 Track.mp3");
+
+        play_statistics.record_play(key);
+        play_statistics.record_play(track_key(
+            "file:///tmp/.tmpXyZ789/Music/Artist/Album/01 Track.mp3",
+            Some(second_mount),
+        ));
+        play_statistics.record_play(track_key("http://example.com/track.mp3", None));
+
+        let play_statistics = PlayStatistics::load(state_directory.path());
+
+        // The file is rewritten with one line per track when it's loaded
+        assert_eq!(
+            std::fs::read_to_string(&play_statistics.path)
+                .unwrap()
+                .lines()
+                .count(),
+            2
+        );
+
+        let statistics = play_statistics.most_recently_played(10);
+
+        assert_eq!(statistics.len(), 2);
+        assert_eq!(statistics[0].track, "http://example.com/track.mp3");
+        assert_eq!(statistics[1].play_count, 2);
+
+        let album_prefix = directory_key_prefix(Path::new("Music/Artist/Album")).unwrap();
+
+        assert!(play_statistics
+            .last_played_with_prefix(&album_prefix)
+            .is_some());
+        assert!(play_statistics
+            .last_played_with_prefix(
+                &directory_key_prefix(Path::new("Music/Artist/Other")).unwrap()
+            )
+            .is_none());
+    }
+}
@@ -0,0 +1,454 @@
+//! Syncing podcast subscriptions and listening progress with gpodder.net, or a Nextcloud server with the `GPodder Sync` app, so that the podcasts followed on a phone are stations on the radio,
+//! and episodes resume where they were left, whichever device they were listened to on.
+//!
+//! At each sync, the subscriptions are fetched, along with the feed of each podcast, which gives its title and episodes.
+//! Listening progress is exchanged as `play` episode actions. The actions since the previous sync are downloaded, and the progress of episodes listened to on the radio since the previous sync is uploaded
+
+use std::time::Duration;
+
+use anyhow::{Context, Result};
+
+use rradio_messages::{ArcStr, CurrentStation, PipelineState, Track};
+
+use crate::{
+    config::podcast_sync::{Config, Service},
+    pipeline::PlayerState,
+    ports::PortChannels,
+    station::podcast::{self, EpisodeProgress, Subscription, UnsentProgress},
+};
+
+/// The format of episode action timestamps, which are in UTC
+const TIMESTAMP_FORMAT: &str = "%Y-%m-%dT%H:%M:%S";
+
+/// Responses from the sync server, and podcast feeds, which are longer than this are rejected
+const MAXIMUM_RESPONSE_LENGTH: usize = 8 * 1024 * 1024;
+
+/// A podcast in the gpodder.net subscription list
+#[derive(Debug, serde::Deserialize)]
+struct GpodderPodcast {
+    url: String,
+}
+
+/// The changes to the subscriptions since a timestamp, which are all of the subscriptions if the timestamp is zero
+#[derive(Debug, serde::Deserialize)]
+struct NextcloudSubscriptions {
+    add: Vec<String>,
+}
+
+#[derive(Debug, PartialEq, serde::Deserialize, serde::Serialize)]
+struct EpisodeAction {
+    /// The feed url of the podcast
+    podcast: String,
+    /// The url of the episode
+    episode: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    device: Option<String>,
+    /// e.g. `play` or `download`
+    action: String,
+    #[serde(default)]
+    timestamp: Option<String>,
+    /// The positions of `play` actions, in seconds
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    started: Option<i64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    position: Option<i64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    total: Option<i64>,
+}
+
+#[derive(Debug, serde::Deserialize)]
+struct EpisodeActions {
+    actions: Vec<EpisodeAction>,
+    /// The `since` of the next download
+    timestamp: i64,
+}
+
+/// The episode which is being listened to, and where listening started
+struct Listening {
+    episode_url: ArcStr,
+    started: Duration,
+}
+
+fn parse_timestamp(timestamp: &str) -> Option<i64> {
+    chrono::NaiveDateTime::parse_from_str(timestamp, TIMESTAMP_FORMAT)
+        .map(|timestamp| timestamp.and_utc().timestamp())
+        .or_else(|_| {
+            chrono::DateTime::parse_from_rfc3339(timestamp).map(|timestamp| timestamp.timestamp())
+        })
+        .ok()
+}
+
+fn format_timestamp(timestamp: i64) -> String {
+    chrono::DateTime::from_timestamp(timestamp, 0)
+        .unwrap_or_default()
+        .format(TIMESTAMP_FORMAT)
+        .to_string()
+}
+
+/// The progress of a `play` action, or `None` if it's another action or doesn't have a position
+fn action_progress(action: &EpisodeAction) -> Option<EpisodeProgress> {
+    if !action.action.eq_ignore_ascii_case("play") {
+        return None;
+    }
+
+    // Unknown positions are sometimes sent as -1
+    let seconds = |seconds: Option<i64>| {
+        seconds
+            .and_then(|seconds| u64::try_from(seconds).ok())
+            .map(Duration::from_secs)
+    };
+
+    Some(EpisodeProgress {
+        started: seconds(action.started).unwrap_or_default(),
+        position: seconds(action.position)?,
+        total: seconds(action.total).unwrap_or_default(),
+        timestamp: parse_timestamp(action.timestamp.as_deref()?)?,
+    })
+}
+
+fn progress_action(config: &Config, unsent_progress: &UnsentProgress) -> EpisodeAction {
+    let seconds = |duration: Duration| i64::try_from(duration.as_secs()).unwrap_or(i64::MAX);
+
+    EpisodeAction {
+        podcast: unsent_progress.feed_url.clone(),
+        episode: unsent_progress.episode_url.clone(),
+        device: Some(config.device_id.to_string()),
+        action: String::from("play"),
+        timestamp: Some(format_timestamp(unsent_progress.progress.timestamp)),
+        started: Some(seconds(unsent_progress.progress.started)),
+        position: Some(seconds(unsent_progress.progress.position)),
+        total: Some(seconds(unsent_progress.progress.total)),
+    }
+}
+
+/// A podcast sync server
+struct Server<'a> {
+    client: &'a reqwest::Client,
+    config: &'a Config,
+}
+
+impl Server<'_> {
+    fn url(&self, path: &[&str]) -> Result<url::Url> {
+        let mut url = self.config.server_url.clone();
+
+        url.path_segments_mut()
+            .map_err(|()| anyhow::anyhow!("Bad server url {}", self.config.server_url))?
+            .pop_if_empty()
+            .extend(path);
+
+        Ok(url)
+    }
+
+    fn episode_actions_url(&self) -> Result<url::Url> {
+        match self.config.service {
+            Service::Gpodder => self.url(&[
+                "api",
+                "2",
+                "episodes",
+                &format!("{}.json", self.config.username),
+            ]),
+            Service::Nextcloud => self.url(&["index.php", "apps", "gpoddersync", "episode_action"]),
+        }
+    }
+
+    async fn send(&self, request: reqwest::RequestBuilder) -> Result<Vec<u8>> {
+        crate::http::send(
+            request.basic_auth(&self.config.username, Some(&self.config.password.0)),
+            MAXIMUM_RESPONSE_LENGTH,
+        )
+        .await
+    }
+
+    async fn get(&self, url: url::Url) -> Result<Vec<u8>> {
+        self.send(self.client.get(url.clone()))
+            .await
+            .with_context(|| format!("Failed to fetch {url}"))
+    }
+
+    /// The feed urls of the subscribed podcasts
+    async fn subscriptions(&self) -> Result<Vec<String>> {
+        match self.config.service {
+            Service::Gpodder => {
+                let url =
+                    self.url(&["subscriptions", &format!("{}.json", self.config.username)])?;
+
+                let podcasts: Vec<GpodderPodcast> = serde_json::from_slice(&self.get(url).await?)
+                    .context("Failed to parse subscriptions")?;
+
+                Ok(podcasts.into_iter().map(|podcast| podcast.url).collect())
+            }
+            Service::Nextcloud => {
+                let mut url = self.url(&["index.php", "apps", "gpoddersync", "subscriptions"])?;
+                url.query_pairs_mut().append_pair("since", "0");
+
+                let NextcloudSubscriptions { add } = serde_json::from_slice(&self.get(url).await?)
+                    .context("Failed to parse subscriptions")?;
+
+                Ok(add)
+            }
+        }
+    }
+
+    async fn episode_actions(&self, since: i64) -> Result<EpisodeActions> {
+        let mut url = self.episode_actions_url()?;
+        url.query_pairs_mut()
+            .append_pair("since", &since.to_string());
+
+        serde_json::from_slice(&self.get(url).await?).context("Failed to parse episode actions")
+    }
+
+    async fn upload_episode_actions(&self, actions: &[EpisodeAction]) -> Result<()> {
+        let mut url = self.episode_actions_url()?;
+
+        if let Service::Nextcloud = self.config.service {
+            url.path_segments_mut()
+                .map_err(|()| anyhow::anyhow!("Bad server url {}", self.config.server_url))?
+                .push("create");
+        }
+
+        let actions = serde_json::to_vec(actions).context("Failed to serialize episode actions")?;
+
+        self.send(
+            self.client
+                .post(url.clone())
+                .header(reqwest::header::CONTENT_TYPE, "application/json")
+                .body(actions),
+        )
+        .await
+        .with_context(|| format!("Failed to upload episode actions to {url}"))?;
+
+        Ok(())
+    }
+}
+
+/// The title of a podcast and its newest episodes
+async fn fetch_feed(
+    client: &reqwest::Client,
+    feed_url: &str,
+    episode_limit: usize,
+) -> Result<(String, Vec<Track>)> {
+    let feed = crate::http::fetch(client, feed_url, MAXIMUM_RESPONSE_LENGTH).await?;
+
+    let (title, episodes) = crate::search::parse_feed(&String::from_utf8_lossy(&feed))?;
+
+    Ok((
+        title.unwrap_or_else(|| String::from(feed_url)),
+        episodes
+            .into_iter()
+            .take(episode_limit)
+            .map(Track::from)
+            .collect(),
+    ))
+}
+
+async fn sync(
+    client: &reqwest::Client,
+    config: &crate::config::Config,
+    podcast_sync_config: &Config,
+) -> Result<()> {
+    let server = Server {
+        client,
+        config: podcast_sync_config,
+    };
+
+    let feed_urls = server.subscriptions().await?;
+
+    // Feeds are fetched concurrently. If a feed can't be fetched, the podcast keeps the episodes from the previous sync
+    let subscriptions =
+        futures_util::future::join_all(feed_urls.into_iter().map(|feed_url| async {
+            let feed = fetch_feed(client, &feed_url, podcast_sync_config.episode_limit)
+                .await
+                .map_err(|err| tracing::warn!(%feed_url, "Failed to fetch podcast: {err:#}"))
+                .ok();
+
+            Subscription { feed_url, feed }
+        }))
+        .await;
+
+    podcast::update_subscriptions(config, subscriptions);
+
+    let EpisodeActions { actions, timestamp } = server
+        .episode_actions(podcast::episode_actions_since(config))
+        .await?;
+
+    podcast::apply_remote_progress(
+        config,
+        actions
+            .into_iter()
+            .filter_map(|action| {
+                let progress = action_progress(&action)?;
+                Some((action.episode, progress))
+            })
+            .collect(),
+        timestamp,
+    );
+
+    let unsent_progress = podcast::unsent_progress(config);
+
+    if !unsent_progress.is_empty() {
+        let actions = unsent_progress
+            .iter()
+            .map(|unsent_progress| progress_action(podcast_sync_config, unsent_progress))
+            .collect::<Vec<_>>();
+
+        server.upload_episode_actions(&actions).await?;
+
+        podcast::mark_progress_as_sent(config, &unsent_progress);
+    }
+
+    podcast::save(config);
+
+    Ok(())
+}
+
+/// Record how far the current episode has been listened to, if a podcast station is playing
+fn record_progress(
+    config: &crate::config::Config,
+    state: &PlayerState,
+    listening: &mut Option<Listening>,
+) {
+    let CurrentStation::PlayingStation {
+        index: Some(index),
+        tracks: Some(tracks),
+        ..
+    } = state.current_station.as_ref()
+    else {
+        *listening = None;
+        return;
+    };
+
+    if !podcast::is_podcast_station(index) {
+        *listening = None;
+        return;
+    }
+
+    let (Some(track), Some(position), Some(total)) = (
+        tracks.get(state.current_track_index),
+        state.track_position,
+        state.track_duration,
+    ) else {
+        return;
+    };
+
+    // The position is zero until a resumed episode has seeked to where it was left
+    if state.pipeline_state != PipelineState::Playing || position.is_zero() {
+        return;
+    }
+
+    let started = match listening {
+        Some(listening) if listening.episode_url == track.url => listening.started,
+        _ => {
+            *listening = Some(Listening {
+                episode_url: track.url.clone(),
+                started: position,
+            });
+            position
+        }
+    };
+
+    podcast::record_progress(
+        config,
+        &track.url,
+        EpisodeProgress {
+            started,
+            position,
+            total,
+            timestamp: chrono::Utc::now().timestamp(),
+        },
+    );
+}
+
+/// Sync podcasts every `interval`, and record the progress of podcast episodes as they're listened to, until rradio shuts down
+pub async fn run(port_channels: PortChannels, config: crate::config::Config) -> Result<()> {
+    let Some(podcast_sync_config) = config.podcast_sync.clone() else {
+        return Ok(());
+    };
+
+    let client = crate::updater::http_client()?;
+
+    let mut player_state_rx = port_channels.player_state_rx.clone();
+
+    let run_syncs = async {
+        let mut listening = None;
+        let mut next_sync = tokio::time::Instant::now();
+
+        loop {
+            tokio::select! {
+                () = tokio::time::sleep_until(next_sync) => {
+                    tracing::debug!("Syncing podcasts");
+
+                    if let Err(err) = sync(&client, &config, &podcast_sync_config).await {
+                        tracing::error!("Failed to sync podcasts: {err:#}");
+                    }
+
+                    next_sync = tokio::time::Instant::now() + podcast_sync_config.interval;
+                }
+                result = player_state_rx.changed() => {
+                    if result.is_err() {
+                        break;
+                    }
+
+                    record_progress(&config, player_state_rx.borrow_and_update().state(), &mut listening);
+                }
+            }
+        }
+    };
+
+    tokio::select! {
+        () = port_channels.shutdown_signal.clone() => (),
+        () = run_syncs => (),
+    }
+
+    // Keep the progress recorded since the previous sync
+    podcast::save(&config);
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use crate::station::podcast::EpisodeProgress;
+
+    use super::EpisodeAction;
+
+    #[test]
+    fn play_actions_are_progress() {
+        let actions: Vec<EpisodeAction> = serde_json::from_str(
+            r#"[
+                {"podcast":"https://example.com/feed.xml","episode":"https://example.com/1.mp3","action":"play","timestamp":"2024-03-01T12:00:00","started":0,"position":120,"total":600},
+                {"podcast":"https://example.com/feed.xml","episode":"https://example.com/2.mp3","action":"PLAY","timestamp":"2024-03-01T12:00:00","started":-1,"position":60,"total":-1},
+                {"podcast":"https://example.com/feed.xml","episode":"https://example.com/3.mp3","action":"download","timestamp":"2024-03-01T12:00:00"}
+            ]"#,
+        )
+        .unwrap();
+
+        assert_eq!(
+            actions
+                .iter()
+                .map(super::action_progress)
+                .collect::<Vec<_>>(),
+            [
+                Some(EpisodeProgress {
+                    started: Duration::ZERO,
+                    position: Duration::from_secs(120),
+                    total: Duration::from_secs(600),
+                    timestamp: 1_709_294_400,
+                }),
+                Some(EpisodeProgress {
+                    started: Duration::ZERO,
+                    position: Duration::from_secs(60),
+                    total: Duration::ZERO,
+                    timestamp: 1_709_294_400,
+                }),
+                None,
+            ]
+        );
+
+        assert_eq!(
+            super::format_timestamp(1_709_294_400),
+            "2024-03-01T12:00:00"
+        );
+    }
+}
@@ -0,0 +1,282 @@
+//! Run commands and call webhooks when the player state changes, e.g. to update an external display or home automation system
+
+use std::sync::Arc;
+
+use anyhow::{Context, Result};
+use tokio::sync::mpsc;
+
+use rradio_messages::{ArcStr, CurrentStation, PlayerStateDiff, StationIndex};
+
+use crate::{
+    config::hook::{Action, Event, Hook},
+    pipeline::PlayerState,
+};
+
+use super::PortChannels;
+
+/// How many triggers can wait for each hook. Further triggers are dropped until the hook catches up
+const TRIGGER_QUEUE_LENGTH: usize = 32;
+
+pub(super) fn event_name(event: Event) -> &'static str {
+    match event {
+        Event::StationChanged => "station_changed",
+        Event::TrackChanged => "track_changed",
+        Event::Error => "error",
+        Event::VolumeChanged => "volume_changed",
+    }
+}
+
+/// The event which triggered a hook, and the player state as JSON
+#[derive(Clone)]
+struct Trigger {
+    event: Event,
+    state: Arc<str>,
+}
+
+/// The entire player state, without the track image, as images can be large, and environment variables have a limited size
+pub(super) fn state_without_image(state: &PlayerState) -> PlayerStateDiff {
+    let mut state = super::player_state_to_diff(state);
+
+    if let Some(Some(tags)) = &mut state.current_track_tags {
+        tags.image = None;
+    }
+
+    state
+}
+
+impl Trigger {
+    fn new(event: Event, state: &PlayerState) -> Result<Self> {
+        Ok(Self {
+            event,
+            state: serde_json::to_string(&state_without_image(state))
+                .context("Failed to encode state as JSON")?
+                .into(),
+        })
+    }
+
+    fn body(&self) -> String {
+        format!(
+            r#"{{"event":"{}","state":{}}}"#,
+            event_name(self.event),
+            self.state
+        )
+    }
+}
+
+fn station_identity(station: &CurrentStation) -> Option<(Option<&StationIndex>, Option<&ArcStr>)> {
+    match station {
+        CurrentStation::PlayingStation { index, title, .. } => {
+            Some((index.as_ref(), title.as_ref()))
+        }
+        CurrentStation::NoStation | CurrentStation::FailedToPlayStation { .. } => None,
+    }
+}
+
+/// The events which describe the change from `previous` to `current`
+pub(super) fn triggered_events(previous: &PlayerState, current: &PlayerState) -> Vec<Event> {
+    let mut events = Vec::new();
+
+    if station_identity(&previous.current_station) != station_identity(&current.current_station) {
+        events.push(Event::StationChanged);
+    }
+
+    let track_identity = |state: &PlayerState| {
+        let tags = state.current_track_tags.as_ref().as_ref();
+        (
+            state.current_track_index,
+            tags.and_then(|tags| tags.title.clone()),
+            tags.and_then(|tags| tags.artist.clone()),
+        )
+    };
+
+    if track_identity(previous) != track_identity(current) {
+        events.push(Event::TrackChanged);
+    }
+
+    let new_error = !Arc::ptr_eq(&previous.latest_error, &current.latest_error)
+        && current.latest_error.is_some();
+
+    let failed_to_play_station = !Arc::ptr_eq(&previous.current_station, &current.current_station)
+        && matches!(
+            current.current_station.as_ref(),
+            CurrentStation::FailedToPlayStation { .. }
+        );
+
+    if new_error || failed_to_play_station {
+        events.push(Event::Error);
+    }
+
+    if previous.volume != current.volume || previous.is_muted != current.is_muted {
+        events.push(Event::VolumeChanged);
+    }
+
+    events
+}
+
+async fn invoke(action: &Action, client: &reqwest::Client, trigger: &Trigger) -> Result<()> {
+    match action {
+        Action::Command { command, args } => {
+            let status = tokio::process::Command::new(command)
+                .args(args)
+                .env("RRADIO_EVENT", event_name(trigger.event))
+                .env("RRADIO_STATE", trigger.state.as_ref())
+                .stdin(std::process::Stdio::null())
+                .kill_on_drop(true)
+                .status()
+                .await
+                .with_context(|| format!("Failed to run {command:?}"))?;
+
+            anyhow::ensure!(status.success(), "{command:?} failed: {status}");
+        }
+        Action::Webhook { url } => {
+            client
+                .post(url.clone())
+                .header(reqwest::header::CONTENT_TYPE, "application/json")
+                .body(trigger.body())
+                .send()
+                .await
+                .and_then(reqwest::Response::error_for_status)
+                .with_context(|| format!("Failed to call {url}"))?;
+        }
+    }
+
+    Ok(())
+}
+
+/// `trigger`, followed by the triggers which are waiting, keeping only the latest trigger of each event, so that a burst of changes doesn't hide a different event
+fn pending_triggers(trigger: Trigger, trigger_rx: &mut mpsc::Receiver<Trigger>) -> Vec<Trigger> {
+    let mut triggers = vec![trigger];
+
+    while let Ok(trigger) = trigger_rx.try_recv() {
+        triggers.retain(|pending_trigger| pending_trigger.event != trigger.event);
+        triggers.push(trigger);
+    }
+
+    triggers
+}
+
+/// Invoke `hook` each time it is triggered. Triggers which occur while the hook is running or during the minimum interval are combined, so only the latest of each event is handled
+async fn run_hook(hook: Hook, client: reqwest::Client, mut trigger_rx: mpsc::Receiver<Trigger>) {
+    while let Some(trigger) = trigger_rx.recv().await {
+        for trigger in pending_triggers(trigger, &mut trigger_rx) {
+            let event = event_name(trigger.event);
+
+            match tokio::time::timeout(hook.timeout, invoke(&hook.action, &client, &trigger)).await
+            {
+                Ok(Ok(())) => tracing::debug!(event, "Hook succeeded"),
+                Ok(Err(err)) => tracing::error!(event, "Hook failed: {err:#}"),
+                Err(_) => tracing::error!(event, "Hook timed out after {:?}", hook.timeout),
+            }
+
+            tokio::time::sleep(hook.minimum_interval).await;
+        }
+    }
+}
+
+pub async fn run(port_channels: PortChannels, hooks: Vec<Hook>) -> Result<()> {
+    if hooks.is_empty() {
+        return Ok(());
+    }
+
+    let client = reqwest::Client::new();
+
+    let mut hook_tasks = Vec::new();
+    let mut triggers = Vec::new();
+
+    for hook in hooks {
+        let (trigger_tx, trigger_rx) = mpsc::channel(TRIGGER_QUEUE_LENGTH);
+        triggers.push((hook.events.clone(), trigger_tx));
+        hook_tasks.push(run_hook(hook, client.clone(), trigger_rx));
+    }
+
+    let mut player_state_rx = port_channels.player_state_rx;
+    let mut shutdown_signal = port_channels.shutdown_signal;
+
+    let watch_state = async move {
+        let mut previous_state = player_state_rx.borrow_and_update().clone();
+
+        loop {
+            tokio::select! {
+                () = &mut shutdown_signal => break,
+                result = player_state_rx.changed() => {
+                    if result.is_err() {
+                        break;
+                    }
+                }
+            }
+
+            let current_state = player_state_rx.borrow_and_update().clone();
+
+            for event in triggered_events(previous_state.state(), current_state.state()) {
+                let trigger = match Trigger::new(event, current_state.state()) {
+                    Ok(trigger) => trigger,
+                    Err(err) => {
+                        tracing::error!("{err:#}");
+                        continue;
+                    }
+                };
+
+                for (events, trigger_tx) in &triggers {
+                    if events.contains(&event) && trigger_tx.try_send(trigger.clone()).is_err() {
+                        tracing::warn!(
+                            event = event_name(event),
+                            "Hook is busy, so the trigger was dropped"
+                        );
+                    }
+                }
+            }
+
+            previous_state = current_state;
+        }
+
+        // Dropping the trigger senders stops the hook tasks once they've handled any pending triggers
+        drop(triggers);
+    };
+
+    futures_util::future::join(watch_state, futures_util::future::join_all(hook_tasks)).await;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::sync::mpsc;
+
+    use crate::config::hook::Event;
+
+    use super::{pending_triggers, Trigger};
+
+    fn trigger(event: Event, state: &str) -> Trigger {
+        Trigger {
+            event,
+            state: state.into(),
+        }
+    }
+
+    #[test]
+    fn only_the_latest_trigger_of_each_event_is_kept() {
+        let (trigger_tx, mut trigger_rx) = mpsc::channel(8);
+
+        for (event, state) in [
+            (Event::TrackChanged, "1"),
+            (Event::VolumeChanged, "2"),
+            (Event::TrackChanged, "3"),
+        ] {
+            trigger_tx.try_send(trigger(event, state)).unwrap();
+        }
+
+        let triggers = pending_triggers(trigger(Event::StationChanged, "0"), &mut trigger_rx)
+            .into_iter()
+            .map(|trigger| (trigger.event, trigger.state.to_string()))
+            .collect::<Vec<_>>();
+
+        assert_eq!(
+            triggers,
+            [
+                (Event::StationChanged, String::from("0")),
+                (Event::VolumeChanged, String::from("2")),
+                (Event::TrackChanged, String::from("3")),
+            ]
+        );
+    }
+}
@@ -0,0 +1,175 @@
+//! Fitting track images within the [`ImageLimits`] which a connection declares in its [`EventFormat`](rradio_messages::EventFormat), so that e.g. embedded displays aren't sent cover art which they can't show.
+//! If the `image-resize` feature is enabled, images which don't fit are downscaled, otherwise, or if a downscaled image still doesn't fit, they are removed
+
+use base64::Engine;
+
+use rradio_messages::{Event, Image, ImageLimits, PlayerStateDiff, TrackTags};
+
+/// Downscaled images are halved in size until they fit within the maximum number of bytes, but no smaller than this
+#[cfg(feature = "image-resize")]
+const MINIMUM_DIMENSION: u32 = 16;
+
+/// The event with its track image fitted within `limits`, or `None` if the event doesn't need to change
+pub fn limit_event(event: &Event, limits: ImageLimits) -> Option<Event> {
+    let Event::PlayerStateChanged(diff) = event else {
+        return None;
+    };
+
+    let Some(Some(tags)) = &diff.current_track_tags else {
+        return None;
+    };
+
+    let tags = limit_tags(tags, limits)?;
+
+    Some(Event::PlayerStateChanged(PlayerStateDiff {
+        current_track_tags: Some(Some(tags)),
+        ..diff.clone()
+    }))
+}
+
+/// The tags with their image fitted within `limits`, or `None` if the tags don't need to change
+fn limit_tags(tags: &TrackTags, limits: ImageLimits) -> Option<TrackTags> {
+    let image = tags.image.as_ref()?;
+
+    let limited_image = limit_image(image, limits);
+
+    (limited_image.as_ref() != Some(image)).then(|| TrackTags {
+        image: limited_image,
+        ..tags.clone()
+    })
+}
+
+/// The data of an image, which is stored as a base64 encoded data url
+fn image_data(image: &Image) -> Option<Vec<u8>> {
+    let (_mime_type, data) = std::str::from_utf8(image.as_ref())
+        .ok()?
+        .strip_prefix("data:")?
+        .split_once(";base64,")?;
+
+    base64::engine::general_purpose::URL_SAFE.decode(data).ok()
+}
+
+fn fits_maximum_bytes(data: &[u8], limits: ImageLimits) -> bool {
+    limits
+        .maximum_bytes
+        .is_none_or(|maximum_bytes| data.len() <= maximum_bytes as usize)
+}
+
+/// The image fitted within `limits`, or `None` if it can't be
+fn limit_image(image: &Image, limits: ImageLimits) -> Option<Image> {
+    let Some(data) = image_data(image) else {
+        tracing::debug!("Removing image which isn't a base64 data url");
+        return None;
+    };
+
+    if limits.maximum_dimension.is_none() && fits_maximum_bytes(&data, limits) {
+        return Some(image.clone());
+    }
+
+    downscale(image, &data, limits)
+}
+
+/// Without decoding the image, its dimensions are unknown, so it's removed rather than risk sending an image which the client can't show
+#[cfg(not(feature = "image-resize"))]
+fn downscale(_image: &Image, _data: &[u8], _limits: ImageLimits) -> Option<Image> {
+    None
+}
+
+#[cfg(feature = "image-resize")]
+fn downscale(image: &Image, data: &[u8], limits: ImageLimits) -> Option<Image> {
+    let decoded_image = image::load_from_memory(data)
+        .map_err(|err| tracing::debug!("Removing image which can't be decoded: {err}"))
+        .ok()?;
+
+    let size = decoded_image.width().max(decoded_image.height());
+
+    let mut dimension = limits.maximum_dimension.unwrap_or(u32::MAX).min(size);
+
+    if dimension == size && fits_maximum_bytes(data, limits) {
+        return Some(image.clone());
+    }
+
+    while dimension >= MINIMUM_DIMENSION {
+        let mut downscaled_data = Vec::new();
+
+        // JPEG doesn't support transparency, so the image is converted to RGB first
+        let downscaled_image = decoded_image.thumbnail(dimension, dimension).into_rgb8();
+
+        if let Err(err) =
+            image::codecs::jpeg::JpegEncoder::new_with_quality(&mut downscaled_data, 80)
+                .encode_image(&downscaled_image)
+        {
+            tracing::warn!("Failed to encode downscaled image: {err}");
+            return None;
+        }
+
+        if fits_maximum_bytes(&downscaled_data, limits) {
+            return Some(Image::new("image/jpeg", &downscaled_data));
+        }
+
+        dimension /= 2;
+    }
+
+    tracing::debug!("Removing image which doesn't fit within the maximum number of bytes");
+
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use rradio_messages::{Image, ImageLimits, TrackTags};
+
+    fn tags_with_image(image: Image) -> TrackTags {
+        TrackTags {
+            image: Some(image),
+            ..TrackTags::default()
+        }
+    }
+
+    #[test]
+    fn images_which_fit_are_unchanged() {
+        let tags = tags_with_image(Image::new("image/png", &[0; 100]));
+
+        let limits = ImageLimits {
+            maximum_dimension: None,
+            maximum_bytes: Some(100),
+        };
+
+        assert_eq!(super::limit_tags(&tags, limits), None);
+    }
+
+    #[test]
+    fn images_which_cannot_fit_are_removed() {
+        let tags = tags_with_image(Image::new("image/png", &[0; 100]));
+
+        let limits = ImageLimits {
+            maximum_dimension: None,
+            maximum_bytes: Some(50),
+        };
+
+        assert_eq!(super::limit_tags(&tags, limits), Some(TrackTags::default()));
+    }
+
+    #[cfg(feature = "image-resize")]
+    #[test]
+    fn large_images_are_downscaled() {
+        let mut data = std::io::Cursor::new(Vec::new());
+
+        image::DynamicImage::new_rgba8(200, 100)
+            .write_to(&mut data, image::ImageOutputFormat::Png)
+            .unwrap();
+
+        let tags = tags_with_image(Image::new("image/png", data.get_ref()));
+
+        let limits = ImageLimits {
+            maximum_dimension: Some(50),
+            maximum_bytes: None,
+        };
+
+        let limited_tags = super::limit_tags(&tags, limits).unwrap();
+        let limited_data = super::image_data(limited_tags.image.as_ref().unwrap()).unwrap();
+        let limited_image = image::load_from_memory(&limited_data).unwrap();
+
+        assert_eq!((limited_image.width(), limited_image.height()), (50, 25));
+    }
+}
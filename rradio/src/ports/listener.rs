@@ -0,0 +1,161 @@
+//! Binding the TCP ports and the web server to IPv4, IPv6, or both, and throttling the connections they accept
+
+use std::{
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
+    time::Duration,
+};
+
+use anyhow::Context;
+use socket2::{Domain, Protocol, Socket, Type};
+use tokio::time::Instant;
+
+use crate::config::tcp::AddressFamily;
+
+const LISTEN_BACKLOG: i32 = 1024;
+
+fn listen(socket_addr: SocketAddr, only_v6: bool) -> anyhow::Result<tokio::net::TcpListener> {
+    let socket = Socket::new(
+        Domain::for_address(socket_addr),
+        Type::STREAM,
+        Some(Protocol::TCP),
+    )?;
+
+    if socket_addr.is_ipv6() {
+        socket.set_only_v6(only_v6)?;
+    }
+
+    // As with `tokio::net::TcpListener::bind`, so that the port can be reused immediately after restarting
+    #[cfg(unix)]
+    socket.set_reuse_address(true)?;
+
+    socket.bind(&socket_addr.into())?;
+    socket.listen(LISTEN_BACKLOG)?;
+    socket.set_nonblocking(true)?;
+
+    Ok(tokio::net::TcpListener::from_std(socket.into())?)
+}
+
+/// Listen on `port`, on all interfaces in production builds, or on loopback otherwise
+pub fn bind(address_family: AddressFamily, port: u16) -> anyhow::Result<tokio::net::TcpListener> {
+    let production = cfg!(feature = "production-server");
+
+    let ipv4_addr = if production {
+        Ipv4Addr::UNSPECIFIED
+    } else {
+        Ipv4Addr::LOCALHOST
+    };
+
+    let ipv6_addr = if production {
+        Ipv6Addr::UNSPECIFIED
+    } else {
+        Ipv6Addr::LOCALHOST
+    };
+
+    let (socket_addr, only_v6) = match address_family {
+        AddressFamily::Ipv4 => (SocketAddr::from((ipv4_addr, port)), false),
+        AddressFamily::Ipv6 => (SocketAddr::from((ipv6_addr, port)), true),
+        // Loopback addresses can't be dual-stack, so development builds listen on IPv4 loopback as before
+        AddressFamily::DualStack if !production => (SocketAddr::from((ipv4_addr, port)), false),
+        AddressFamily::DualStack => {
+            let socket_addr = SocketAddr::from((ipv6_addr, port));
+
+            match listen(socket_addr, false) {
+                Ok(listener) => return Ok(listener),
+                Err(err) => {
+                    tracing::warn!(%socket_addr, "Failed to listen on IPv6, falling back to IPv4: {err:#}");
+                    (SocketAddr::from((ipv4_addr, port)), false)
+                }
+            }
+        }
+    };
+
+    listen(socket_addr, only_v6).with_context(|| format!("Failed to listen to {socket_addr}"))
+}
+
+/// IPv4 clients of dual-stack listeners have IPv4-mapped IPv6 addresses, e.g. `[::ffff:192.168.0.2]:1234`, which are logged as plain IPv4 addresses
+pub fn canonical_remote_address(remote_address: SocketAddr) -> SocketAddr {
+    match remote_address.ip() {
+        IpAddr::V6(ip) => ip.to_ipv4_mapped().map_or(remote_address, |ip| {
+            SocketAddr::from((ip, remote_address.port()))
+        }),
+        IpAddr::V4(_) => remote_address,
+    }
+}
+
+/// The suggested backoff doubles each time this many more connections arrive in quick succession
+const CONNECTIONS_PER_BACKOFF_DOUBLING: u32 = 8;
+
+/// Spaces out accepted connections on a port, and suggests how long clients should wait before reconnecting.
+/// The suggestion grows exponentially while connections arrive in quick succession, such as when many clients reconnect after a restart
+pub struct AcceptThrottle {
+    config: crate::config::tcp::Config,
+    last_accept: Option<Instant>,
+    /// The number of connections since there was a gap of at least `minimum_backoff` between connections
+    burst_length: u32,
+}
+
+impl AcceptThrottle {
+    pub fn new(config: crate::config::tcp::Config) -> Self {
+        Self {
+            config,
+            last_accept: None,
+            burst_length: 0,
+        }
+    }
+
+    /// Wait until the next connection may be accepted. Until then, connections wait in the listen queue
+    pub async fn ready(&self) {
+        if let Some(last_accept) = self.last_accept {
+            tokio::time::sleep_until(last_accept + self.config.accept_interval).await;
+        }
+    }
+
+    /// Record that a connection has been accepted, and return the suggested backoff for that connection
+    pub fn accepted(&mut self) -> Duration {
+        let now = Instant::now();
+
+        let is_in_burst = self
+            .last_accept
+            .is_some_and(|last_accept| now - last_accept < self.config.minimum_backoff);
+
+        self.burst_length = if is_in_burst {
+            self.burst_length.saturating_add(1)
+        } else {
+            0
+        };
+
+        self.last_accept = Some(now);
+
+        let doublings = self.burst_length / CONNECTIONS_PER_BACKOFF_DOUBLING;
+
+        2_u32
+            .checked_pow(doublings)
+            .and_then(|factor| self.config.minimum_backoff.checked_mul(factor))
+            .map_or(self.config.maximum_backoff, |backoff| {
+                backoff.min(self.config.maximum_backoff)
+            })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::SocketAddr;
+
+    use super::canonical_remote_address;
+
+    #[test]
+    fn ipv4_mapped_addresses_are_logged_as_ipv4() {
+        for (remote_address, expected) in [
+            ("[::ffff:192.168.0.2]:1234", "192.168.0.2:1234"),
+            ("[fe80::1]:1234", "[fe80::1]:1234"),
+            ("192.168.0.2:1234", "192.168.0.2:1234"),
+        ] {
+            let remote_address = remote_address.parse::<SocketAddr>().unwrap();
+
+            assert_eq!(
+                canonical_remote_address(remote_address).to_string(),
+                expected
+            );
+        }
+    }
+}
@@ -1,16 +1,34 @@
 //! Ports are the access point for external programs to interact with ``RRadio``.
 //! Through ports a client can listen for [Events](rradio_messages::Event) and submit [Commands](rradio_messages::Command).
 
-use std::sync::Arc;
+use std::{
+    collections::BTreeMap,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
 
 use anyhow::Context;
-use futures_util::{FutureExt, Sink, StreamExt};
-use rradio_messages::PlayerStateDiff;
+use futures_util::{Sink, Stream, StreamExt};
+use rradio_messages::{
+    arcstr, ArcStr, CurrentStation, PlayerStateDiff, StationIndex, TrackListPatch,
+};
 
 use crate::{pipeline::PlayerState, task::ShutdownSignal};
 
+pub use shared_event::{state_channel, PublishedState, SharedEvent, StatePublisher};
+
+mod image_limits;
+mod shared_event;
 mod stream;
 
+pub mod hooks;
+pub mod listener;
+pub mod mpd;
+#[cfg(feature = "lua-plugins")]
+pub mod plugins;
 pub mod tcp;
 pub mod tcp_binary;
 pub mod tcp_text;
@@ -19,7 +37,7 @@ pub mod tcp_text;
 pub mod web;
 
 fn player_state_to_diff(state: &PlayerState) -> PlayerStateDiff {
-    PlayerStateDiff {
+    let mut diff = PlayerStateDiff {
         pipeline_state: Some(state.pipeline_state),
         current_station: Some(state.current_station.as_ref().clone()),
         pause_before_playing: Some(state.pause_before_playing),
@@ -32,14 +50,30 @@ fn player_state_to_diff(state: &PlayerState) -> PlayerStateDiff {
         track_position: Some(state.track_position),
         ping_times: Some(state.ping_times.clone()),
         latest_error: Some(state.latest_error.as_ref().clone()),
-    }
+        settings: Some(state.settings.as_ref().clone()),
+        quarantined_tracks: Some(state.quarantined_tracks.as_ref().clone()),
+        tracks_revision: Some(state.tracks_revision),
+        track_list_patch: None,
+        safe_mode: Some(state.safe_mode.clone()),
+        idle_screen: Some(state.idle_screen.clone()),
+        quiet_hours_maximum_volume: Some(state.quiet_hours_maximum_volume),
+        audio_outputs: Some(state.audio_outputs.clone()),
+        activity: Some(state.activity),
+        audio_interruption: Some(state.audio_interruption),
+        audio_format: Some(state.audio_format.clone()),
+        intro_scan: Some(state.intro_scan),
+        station_locked: Some(state.station_locked),
+    };
+    crate::redact::player_state_diff(&mut diff);
+    diff
 }
 
 fn diff_player_state(a: &PlayerState, b: &PlayerState) -> Option<PlayerStateDiff> {
     let mut any_some = false;
-    let diff = PlayerStateDiff {
+    let (current_station, track_list_patch) = diff_current_station(a, b, &mut any_some);
+    let mut diff = PlayerStateDiff {
         pipeline_state: diff_value(&a.pipeline_state, &b.pipeline_state, &mut any_some),
-        current_station: diff_arc_with_clone(&a.current_station, &b.current_station, &mut any_some),
+        current_station,
         pause_before_playing: diff_value(
             &a.pause_before_playing,
             &b.pause_before_playing,
@@ -62,14 +96,73 @@ fn diff_player_state(a: &PlayerState, b: &PlayerState) -> Option<PlayerStateDiff
         track_position: diff_value(&a.track_position, &b.track_position, &mut any_some),
         ping_times: diff_value(&a.ping_times, &b.ping_times, &mut any_some),
         latest_error: diff_arc_with_clone(&a.latest_error, &b.latest_error, &mut any_some),
+        settings: diff_arc_with_clone(&a.settings, &b.settings, &mut any_some),
+        quarantined_tracks: diff_arc_with_clone(
+            &a.quarantined_tracks,
+            &b.quarantined_tracks,
+            &mut any_some,
+        ),
+        tracks_revision: diff_value(&a.tracks_revision, &b.tracks_revision, &mut any_some),
+        track_list_patch,
+        safe_mode: diff_value(&a.safe_mode, &b.safe_mode, &mut any_some),
+        idle_screen: diff_value(&a.idle_screen, &b.idle_screen, &mut any_some),
+        quiet_hours_maximum_volume: diff_value(
+            &a.quiet_hours_maximum_volume,
+            &b.quiet_hours_maximum_volume,
+            &mut any_some,
+        ),
+        audio_outputs: diff_value(&a.audio_outputs, &b.audio_outputs, &mut any_some),
+        activity: diff_value(&a.activity, &b.activity, &mut any_some),
+        audio_interruption: diff_value(&a.audio_interruption, &b.audio_interruption, &mut any_some),
+        audio_format: diff_value(&a.audio_format, &b.audio_format, &mut any_some),
+        intro_scan: diff_value(&a.intro_scan, &b.intro_scan, &mut any_some),
+        station_locked: diff_value(&a.station_locked, &b.station_locked, &mut any_some),
     };
     if any_some {
+        crate::redact::player_state_diff(&mut diff);
         Some(diff)
     } else {
         None
     }
 }
 
+/// If only the tracks of the current station have changed, and some of them are unchanged, the changes are sent as a patch rather than resending every track
+fn diff_current_station(
+    a: &PlayerState,
+    b: &PlayerState,
+    any_some: &mut bool,
+) -> (Option<CurrentStation>, Option<TrackListPatch>) {
+    if Arc::ptr_eq(&a.current_station, &b.current_station) {
+        return (None, None);
+    }
+
+    *any_some = true;
+
+    if let (
+        CurrentStation::PlayingStation {
+            index: a_index,
+            source_type: a_source_type,
+            title: a_title,
+            tracks: Some(a_tracks),
+        },
+        CurrentStation::PlayingStation {
+            index: b_index,
+            source_type: b_source_type,
+            title: b_title,
+            tracks: Some(b_tracks),
+        },
+    ) = (a.current_station.as_ref(), b.current_station.as_ref())
+    {
+        if (a_index, a_source_type, a_title) == (b_index, b_source_type, b_title) {
+            if let Some(patch) = TrackListPatch::new(a.tracks_revision, a_tracks, b_tracks) {
+                return (None, Some(patch));
+            }
+        }
+    }
+
+    (Some(b.current_station.as_ref().clone()), None)
+}
+
 fn diff_value<T: Clone + std::cmp::PartialEq>(a: &T, b: &T, any_some: &mut bool) -> Option<T> {
     if a == b {
         None
@@ -88,8 +181,178 @@ fn diff_arc_with_clone<T: Clone>(a: &Arc<T>, b: &Arc<T>, any_some: &mut bool) ->
     }
 }
 
+/// The maximum number of commands waiting to be processed by the controller
+pub const COMMAND_QUEUE_LENGTH: usize = 64;
+
+/// Counts of messages which were dropped because a channel was full
+#[derive(Debug, Default)]
+pub struct DroppedMessages {
+    /// Commands which were rejected because the command queue was full
+    commands: AtomicU64,
+    /// Events which weren't sent to a client because the client was too slow to receive them
+    events: AtomicU64,
+}
+
+#[derive(Debug, serde::Serialize)]
+pub struct DroppedMessageCounts {
+    pub commands: u64,
+    pub events: u64,
+}
+
+impl DroppedMessages {
+    pub fn counts(&self) -> DroppedMessageCounts {
+        DroppedMessageCounts {
+            commands: self.commands.load(Ordering::Relaxed),
+            events: self.events.load(Ordering::Relaxed),
+        }
+    }
+}
+
+/// Details of the pipeline, so that users can check how audio is being played
+#[derive(Debug, Clone, Default, PartialEq, serde::Serialize)]
+pub struct PipelineDiagnostics {
+    /// The caps negotiated with the audio sink, once playback has started
+    pub output_caps: Option<ArcStr>,
+    /// The playbin flags chosen for the current station
+    pub playbin_flags: Option<ArcStr>,
+    /// The buffer duration of the current station, or `None` if gstreamer's default is used
+    #[serde(with = "humantime_serde")]
+    pub buffer_duration: Option<Duration>,
+    /// How often each station has rebuffered, and the buffer durations of stations which have been given longer buffers
+    pub station_buffering: BTreeMap<StationIndex, crate::pipeline::StationBuffering>,
+    /// The mirror which the current track is played from, or `None` if it's played from its url
+    pub track_mirror: Option<ArcStr>,
+    /// The gain, in dB, applied to the current station by loudness normalisation
+    pub loudness_gain: f64,
+    /// The average loudness learned for each station by loudness normalisation
+    pub station_loudness: BTreeMap<StationIndex, crate::pipeline::StationLoudness>,
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum CommandSendError {
+    #[error("Too many commands are waiting to be processed")]
+    QueueFull,
+    #[error("The controller is not running")]
+    Closed,
+}
+
+/// A command, and where it came from, which is recorded in the audit log
+#[derive(Debug)]
+pub struct ReceivedCommand {
+    pub command: rradio_messages::Command,
+    pub source: ArcStr,
+    /// Only privileged commands can lock the station, or change the station while it's locked
+    pub is_privileged: bool,
+    /// Events which are only for the client which sent the command, such as seek previews, or `None` if the client doesn't receive events
+    pub replies_tx: Option<tokio::sync::mpsc::Sender<SharedEvent>>,
+}
+
+/// Sends commands to the controller, rejecting commands if too many are waiting to be processed
+#[derive(Debug, Clone)]
+pub struct CommandSender {
+    commands_tx: tokio::sync::mpsc::Sender<ReceivedCommand>,
+    dropped_messages: Arc<DroppedMessages>,
+    source: ArcStr,
+    is_privileged: bool,
+    replies_tx: Option<tokio::sync::mpsc::Sender<SharedEvent>>,
+}
+
+impl CommandSender {
+    pub(crate) fn new(
+        commands_tx: tokio::sync::mpsc::Sender<ReceivedCommand>,
+        dropped_messages: Arc<DroppedMessages>,
+    ) -> Self {
+        Self {
+            commands_tx,
+            dropped_messages,
+            source: arcstr::literal!("unknown"),
+            is_privileged: false,
+            replies_tx: None,
+        }
+    }
+
+    /// A sender which labels its commands with the given source, e.g. a port and the address of the client
+    #[must_use]
+    pub fn with_source(&self, source: impl Into<ArcStr>) -> Self {
+        Self {
+            source: source.into(),
+            ..self.clone()
+        }
+    }
+
+    /// A sender whose commands are privileged, so can lock the station, and change the station while it's locked, or aren't privileged
+    #[must_use]
+    pub fn with_privilege(&self, is_privileged: bool) -> Self {
+        Self {
+            is_privileged,
+            ..self.clone()
+        }
+    }
+
+    /// A sender whose commands are replied to with events sent to `replies_tx`
+    #[must_use]
+    fn with_replies(&self, replies_tx: tokio::sync::mpsc::Sender<SharedEvent>) -> Self {
+        Self {
+            replies_tx: Some(replies_tx),
+            ..self.clone()
+        }
+    }
+
+    /// Queue a command to be processed by the controller
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if too many commands are waiting to be processed, or the controller has stopped
+    pub fn send(&self, command: rradio_messages::Command) -> Result<(), CommandSendError> {
+        use tokio::sync::mpsc::error::TrySendError;
+
+        let command = ReceivedCommand {
+            command,
+            source: self.source.clone(),
+            is_privileged: self.is_privileged,
+            replies_tx: self.replies_tx.clone(),
+        };
+
+        self.commands_tx.try_send(command).map_err(|err| match err {
+            TrySendError::Full(ReceivedCommand {
+                command, source, ..
+            }) => {
+                self.dropped_messages
+                    .commands
+                    .fetch_add(1, Ordering::Relaxed);
+                tracing::warn!(?command, %source, "Command queue is full, rejecting command");
+                CommandSendError::QueueFull
+            }
+            TrySendError::Closed(_) => CommandSendError::Closed,
+        })
+    }
+}
+
+/// The maximum number of replies waiting to be sent to a connection. Further replies are dropped, as the client is too slow to receive them
+const REPLY_QUEUE_LENGTH: usize = 4;
+
+/// Forwards commands from a connection to the controller.
+/// Commands which are rejected because the queue is full don't close the connection, but are reported to the connection as an [`Event::CommandRejected`](rradio_messages::Event::CommandRejected)
 #[derive(Debug, Clone)]
-struct CommandSink(pub tokio::sync::mpsc::UnboundedSender<rradio_messages::Command>);
+struct CommandSink {
+    commands_tx: CommandSender,
+    replies_tx: tokio::sync::mpsc::Sender<SharedEvent>,
+}
+
+impl CommandSink {
+    /// Returns the sink, and the replies, such as rejections, which should be sent to the connection along with the other events
+    fn new(commands_tx: CommandSender) -> (Self, tokio::sync::mpsc::Receiver<SharedEvent>) {
+        let (replies_tx, replies_rx) = tokio::sync::mpsc::channel(REPLY_QUEUE_LENGTH);
+
+        (
+            Self {
+                commands_tx: commands_tx.with_replies(replies_tx.clone()),
+                replies_tx,
+            },
+            replies_rx,
+        )
+    }
+}
 
 impl Sink<rradio_messages::Command> for CommandSink {
     type Error = anyhow::Error;
@@ -105,7 +368,19 @@ impl Sink<rradio_messages::Command> for CommandSink {
         self: std::pin::Pin<&mut Self>,
         item: rradio_messages::Command,
     ) -> Result<(), Self::Error> {
-        self.0.send(item).context("Failed to send command")
+        match self.commands_tx.send(item) {
+            Ok(()) => Ok(()),
+            Err(CommandSendError::QueueFull) => {
+                self.replies_tx
+                    .try_send(SharedEvent::new(rradio_messages::Event::CommandRejected(
+                        rradio_messages::MessageId::CommandQueueFull.into(),
+                    )))
+                    .ok();
+
+                Ok(())
+            }
+            Err(err @ CommandSendError::Closed) => Err(err).context("Failed to send command"),
+        }
     }
 
     fn poll_flush(
@@ -129,8 +404,17 @@ pub struct NoShutdownSignal;
 /// The name "partial" is because `shutdown_signal` is initially of type `()` and is replaced with the actual shutdown signal, of type [`ShutdownSignal`]
 #[derive(Clone)]
 pub struct PartialPortChannels<SS> {
-    pub commands_tx: tokio::sync::mpsc::UnboundedSender<rradio_messages::Command>,
-    pub player_state_rx: tokio::sync::watch::Receiver<PlayerState>,
+    pub commands_tx: CommandSender,
+    /// The player state is a watch channel, so slow clients skip intermediate states rather than queuing them
+    pub player_state_rx: tokio::sync::watch::Receiver<PublishedState>,
+    /// Events which aren't changes to the player state, such as seek previews
+    pub events_tx: tokio::sync::broadcast::Sender<SharedEvent>,
+    pub dropped_messages: Arc<DroppedMessages>,
+    pub pipeline_diagnostics_rx: tokio::sync::watch::Receiver<PipelineDiagnostics>,
+    /// Sent as the first event of each event stream, so that clients know which device they're connected to
+    pub hello_event: SharedEvent,
+    /// Ports report when they are listening, as part of startup
+    pub boot_milestones: crate::boot::Milestones,
     pub shutdown_signal: SS,
 }
 
@@ -138,43 +422,163 @@ pub struct PartialPortChannels<SS> {
 pub type PortChannels = PartialPortChannels<futures_util::future::Shared<ShutdownSignal>>;
 
 impl PartialPortChannels<NoShutdownSignal> {
-    pub fn with_shutdown_signal(self, shutdown_signal: ShutdownSignal) -> PortChannels {
+    pub fn with_shutdown_signal(
+        self,
+        shutdown_signal: futures_util::future::Shared<ShutdownSignal>,
+    ) -> PortChannels {
         PortChannels {
             commands_tx: self.commands_tx,
             player_state_rx: self.player_state_rx,
-            shutdown_signal: shutdown_signal.shared(),
+            events_tx: self.events_tx,
+            dropped_messages: self.dropped_messages,
+            pipeline_diagnostics_rx: self.pipeline_diagnostics_rx,
+            hello_event: self.hello_event,
+            boot_milestones: self.boot_milestones,
+            shutdown_signal,
         }
     }
 }
 
+/// Wait at least `minimum_interval` after each event before polling for the next event.
+/// The player state is a watch channel, so changes to the player state in between are combined into a single diff, and the latest state wins
+fn throttle<S: Stream>(
+    events: S,
+    minimum_interval: Option<Duration>,
+) -> impl Stream<Item = S::Item> {
+    futures_util::stream::unfold(
+        (Box::pin(events), None),
+        move |(mut events, last_sent): (_, Option<tokio::time::Instant>)| async move {
+            if let Some((last_sent, minimum_interval)) = last_sent.zip(minimum_interval) {
+                tokio::time::sleep_until(last_sent + minimum_interval).await;
+            }
+
+            let event = events.next().await?;
+
+            Some((event, (events, Some(tokio::time::Instant::now()))))
+        },
+    )
+}
+
 impl PortChannels {
-    pub fn event_stream(&self) -> impl futures_util::Stream<Item = rradio_messages::Event> {
+    /// If `minimum_interval` is set, events are sent at most once per `minimum_interval`
+    pub fn event_stream(
+        &self,
+        minimum_interval: Option<Duration>,
+    ) -> impl futures_util::Stream<Item = SharedEvent> {
+        self.event_stream_with_full_state(false, minimum_interval)
+    }
+
+    /// If `full_state` is true, each event contains the entire player state rather than a diff.
+    /// If `minimum_interval` is set, events after the initial state are sent at most once per `minimum_interval`, e.g. for displays on slow serial links
+    pub fn event_stream_with_full_state(
+        &self,
+        full_state: bool,
+        minimum_interval: Option<Duration>,
+    ) -> impl futures_util::Stream<Item = SharedEvent> {
         let player_state_rx = self.player_state_rx.clone();
         let current_state = player_state_rx.borrow().clone();
-        futures_util::stream::once(futures_util::future::ready(
-            rradio_messages::Event::PlayerStateChanged(player_state_to_diff(&current_state)),
-        )) // Set the current state as an "everything has changed" diff
-        .chain(
-            // Whenever the player state changed, diff the current state with the new state and if the diff isn't empty, send it
-            futures_util::stream::unfold(
-                (player_state_rx, current_state),
-                |(mut player_state_rx, current_state)| async move {
-                    loop {
-                        player_state_rx.changed().await.ok()?;
-                        let new_state = player_state_rx.borrow().clone();
-                        match diff_player_state(&current_state, &new_state) {
-                            Some(diff) => {
-                                return Some((
-                                    rradio_messages::Event::PlayerStateChanged(diff),
-                                    (player_state_rx, new_state),
-                                ))
-                            }
-                            None => continue,
+
+        // The events channel is bounded, and slow clients skip the oldest events
+        let other_events = futures_util::stream::unfold(
+            (self.events_tx.subscribe(), self.dropped_messages.clone()),
+            |(mut events_rx, dropped_messages)| async move {
+                loop {
+                    match events_rx.recv().await {
+                        Ok(event) => return Some((event, (events_rx, dropped_messages))),
+                        Err(tokio::sync::broadcast::error::RecvError::Lagged(skipped)) => {
+                            dropped_messages
+                                .events
+                                .fetch_add(skipped, Ordering::Relaxed);
+                            tracing::warn!(skipped, "Events skipped");
                         }
+                        Err(tokio::sync::broadcast::error::RecvError::Closed) => return None,
                     }
-                },
+                }
+            },
+        );
+
+        futures_util::stream::iter([
+            self.hello_event.clone(),
+            current_state.full_state_event(), // Set the current state as an "everything has changed" diff
+        ])
+        .chain(throttle(
+            futures_util::stream::select(
+                // Whenever the player state changed, send how it changed, or the entire state if `full_state` is true
+                futures_util::stream::unfold(
+                    (player_state_rx, current_state),
+                    move |(mut player_state_rx, current_state)| async move {
+                        loop {
+                            player_state_rx.changed().await.ok()?;
+                            let new_state = player_state_rx.borrow().clone();
+
+                            let event = if full_state {
+                                Some(new_state.full_state_event())
+                            } else {
+                                new_state.diff_event(&current_state)
+                            };
+
+                            match event {
+                                Some(event) => return Some((event, (player_state_rx, new_state))),
+                                None => continue,
+                            }
+                        }
+                    },
+                ),
+                other_events,
             ),
-        )
+            minimum_interval,
+        ))
         .take_until(self.shutdown_signal.clone())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use futures_util::StreamExt;
+    use rradio_messages::{Command, Event, MessageId};
+
+    #[tokio::test]
+    async fn throttled_events_are_spaced_out() {
+        let minimum_interval = Duration::from_millis(20);
+
+        let start = tokio::time::Instant::now();
+
+        let events = super::throttle(
+            futures_util::stream::iter([1, 2, 3]),
+            Some(minimum_interval),
+        )
+        .collect::<Vec<_>>()
+        .await;
+
+        assert_eq!(events, [1, 2, 3]);
+        assert!(start.elapsed() >= 2 * minimum_interval);
+    }
+
+    #[tokio::test]
+    async fn commands_rejected_by_a_full_queue_are_reported_to_the_connection() {
+        let (commands_tx, mut commands_rx) = tokio::sync::mpsc::channel(1);
+
+        let (commands_tx, mut rejections_rx) = super::CommandSink::new(super::CommandSender::new(
+            commands_tx,
+            std::sync::Arc::default(),
+        ));
+
+        futures_util::stream::iter([Command::PlayPause, Command::Eject])
+            .map(Ok)
+            .forward(commands_tx)
+            .await
+            .unwrap();
+
+        assert!(matches!(
+            commands_rx.recv().await.unwrap().command,
+            Command::PlayPause
+        ));
+
+        assert!(matches!(
+            rejections_rx.recv().await.unwrap().event(),
+            Event::CommandRejected(message) if message.id == MessageId::CommandQueueFull
+        ));
+    }
+}
@@ -0,0 +1,785 @@
+//! A port which speaks a subset of the [Music Player Daemon protocol](https://mpd.readthedocs.io/en/latest/protocol.html), so that existing MPD clients, such as phone apps and hardware controllers, can control rradio.
+//! Stations are listed as MPD stored playlists, and the tracks of the current station are the MPD queue
+
+use std::{fmt::Write, sync::Arc};
+
+use anyhow::Context;
+use futures_util::{StreamExt, TryStreamExt};
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt};
+use tracing::Instrument;
+
+use rradio_messages::{arcstr, Command, CurrentStation, PipelineState, StationIndex, Track};
+
+use crate::{config::Config, pipeline::PlayerState};
+
+/// Sent when a client connects. Clients check the version to decide which commands they can use
+const GREETING: &str = "OK MPD 0.23.0\n";
+
+/// Lines sent by clients are read at most this many bytes at a time, so that clients which never send a newline don't use up memory
+const MAX_LINE_LENGTH: u64 = 4096;
+
+/// The commands which are supported, as listed by `commands`
+const SUPPORTED_COMMANDS: &[&str] = &[
+    "close",
+    "command_list_begin",
+    "command_list_end",
+    "command_list_ok_begin",
+    "commands",
+    "currentsong",
+    "delete",
+    "idle",
+    "listplaylists",
+    "load",
+    "move",
+    "next",
+    "noidle",
+    "notcommands",
+    "outputs",
+    "pause",
+    "ping",
+    "play",
+    "playid",
+    "playlistinfo",
+    "previous",
+    "seekcur",
+    "setvol",
+    "status",
+    "stop",
+    "tagtypes",
+    "volume",
+];
+
+/// The tags which are sent in song entries, as listed by `tagtypes`
+const TAG_TYPES: &[&str] = &["Artist", "Album", "Title", "Track", "Disc", "Genre", "Name"];
+
+/// The error codes of the MPD protocol which rradio uses
+#[derive(Clone, Copy, Debug)]
+enum AckCode {
+    Argument = 2,
+    Unknown = 5,
+    System = 52,
+}
+
+/// An error response, which is sent as `ACK [code@command_list_index] {command} message`
+#[derive(Debug)]
+struct Ack {
+    code: AckCode,
+    message: String,
+}
+
+impl Ack {
+    fn new(code: AckCode, message: impl Into<String>) -> Self {
+        Self {
+            code,
+            message: message.into(),
+        }
+    }
+
+    fn to_line(&self, command_list_index: usize, command: &str) -> String {
+        format!(
+            "ACK [{}@{command_list_index}] {{{command}}} {}\n",
+            self.code as u8, self.message
+        )
+    }
+}
+
+/// Split a command line into the command and its arguments. Arguments containing spaces are quoted, and quotes and backslashes within quoted arguments are escaped with a backslash
+fn parse_arguments(line: &str) -> Result<Vec<String>, Ack> {
+    let mut arguments = Vec::new();
+    let mut chars = line.chars().peekable();
+
+    loop {
+        while chars.next_if(|c| c.is_whitespace()).is_some() {}
+
+        let Some(first) = chars.next() else {
+            return Ok(arguments);
+        };
+
+        let mut argument = String::new();
+
+        if first == '"' {
+            loop {
+                match chars.next() {
+                    Some('"') => break,
+                    Some('\\') => argument.push(
+                        chars
+                            .next()
+                            .ok_or_else(|| Ack::new(AckCode::Argument, "Unterminated escape"))?,
+                    ),
+                    Some(c) => argument.push(c),
+                    None => return Err(Ack::new(AckCode::Argument, "Missing closing '\"'")),
+                }
+            }
+        } else {
+            argument.push(first);
+
+            while let Some(c) = chars.next_if(|c| !c.is_whitespace()) {
+                argument.push(c);
+            }
+        }
+
+        arguments.push(argument);
+    }
+}
+
+fn parse_argument<T: std::str::FromStr>(argument: Option<&String>) -> Result<T, Ack> {
+    let argument = argument.ok_or_else(|| Ack::new(AckCode::Argument, "Missing argument"))?;
+
+    argument
+        .parse()
+        .map_err(|_| Ack::new(AckCode::Argument, format!("Bad argument {argument:?}")))
+}
+
+/// Parse a number of seconds, such as the time of `seekcur`, which must be a non-negative time which fits in a `Duration`
+fn parse_seconds(seconds: &str) -> Option<std::time::Duration> {
+    seconds
+        .parse::<f64>()
+        .ok()
+        .and_then(|seconds| std::time::Duration::try_from_secs_f64(seconds).ok())
+}
+
+/// The volume after the relative change of a `volume` command. The controller clamps the volume, so the sum only needs to not overflow
+fn changed_volume(volume: i32, change: i32) -> i32 {
+    volume.saturating_add(change)
+}
+
+/// The tracks of the current station, which are the MPD queue
+fn current_tracks(state: &PlayerState) -> &[Track] {
+    match state.current_station.as_ref() {
+        CurrentStation::PlayingStation {
+            tracks: Some(tracks),
+            ..
+        } => tracks,
+        CurrentStation::PlayingStation { tracks: None, .. }
+        | CurrentStation::NoStation
+        | CurrentStation::FailedToPlayStation { .. } => &[],
+    }
+}
+
+fn station_title(state: &PlayerState) -> Option<&str> {
+    match state.current_station.as_ref() {
+        CurrentStation::PlayingStation { title, .. } => title.as_deref(),
+        CurrentStation::NoStation | CurrentStation::FailedToPlayStation { .. } => None,
+    }
+}
+
+fn is_stopped(state: &PlayerState) -> bool {
+    !matches!(
+        state.pipeline_state,
+        PipelineState::Playing | PipelineState::Paused
+    )
+}
+
+/// Write the song entry of track `index`. The current track also has the tags received from its stream, and its duration
+fn write_song(response: &mut String, state: &PlayerState, index: usize, track: &Track) {
+    let is_current_track = index == state.current_track_index;
+
+    let tags = state
+        .current_track_tags
+        .as_ref()
+        .as_ref()
+        .filter(|_| is_current_track);
+
+    let title = tags
+        .and_then(|tags| tags.title.as_ref())
+        .or(track.title.as_ref());
+    let artist = tags
+        .and_then(|tags| tags.artist.as_ref())
+        .or(track.artist.as_ref());
+    let album = tags
+        .and_then(|tags| tags.album.as_ref())
+        .or(track.album.as_ref());
+    let track_number = tags
+        .and_then(|tags| tags.track_number)
+        .or(track.track_number);
+    let disc_number = tags.and_then(|tags| tags.disc_number).or(track.disc_number);
+    let genre = tags.and_then(|tags| tags.genre.as_ref());
+
+    writeln!(response, "file: {}", crate::redact::url(&track.url)).ok();
+
+    for (name, value) in [
+        ("Title", title.map(ToString::to_string)),
+        ("Artist", artist.map(ToString::to_string)),
+        ("Album", album.map(ToString::to_string)),
+        ("Track", track_number.map(|number| number.to_string())),
+        ("Disc", disc_number.map(|number| number.to_string())),
+        ("Genre", genre.map(ToString::to_string)),
+        ("Name", station_title(state).map(String::from)),
+    ] {
+        if let Some(value) = value {
+            writeln!(response, "{name}: {value}").ok();
+        }
+    }
+
+    if is_current_track {
+        if let Some(duration) = state.track_duration {
+            writeln!(response, "Time: {}", duration.as_secs()).ok();
+            writeln!(response, "duration: {:.3}", duration.as_secs_f64()).ok();
+        }
+    }
+
+    writeln!(response, "Pos: {index}").ok();
+    writeln!(response, "Id: {index}").ok();
+}
+
+fn write_status(response: &mut String, state: &PlayerState) {
+    let tracks = current_tracks(state);
+
+    let mpd_state = match state.pipeline_state {
+        PipelineState::Playing => "play",
+        PipelineState::Paused => "pause",
+        PipelineState::Null | PipelineState::Ready => "stop",
+    };
+
+    let volume = if state.is_muted {
+        0
+    } else {
+        state.volume.clamp(0, 100)
+    };
+
+    writeln!(response, "volume: {volume}").ok();
+    // The tracks of a station are played in a loop
+    writeln!(response, "repeat: 1").ok();
+    writeln!(response, "random: 0").ok();
+    writeln!(response, "single: 0").ok();
+    writeln!(response, "consume: 0").ok();
+    writeln!(response, "playlist: {}", state.tracks_revision).ok();
+    writeln!(response, "playlistlength: {}", tracks.len()).ok();
+    writeln!(response, "state: {mpd_state}").ok();
+
+    if state.current_track_index < tracks.len() {
+        writeln!(response, "song: {}", state.current_track_index).ok();
+        writeln!(response, "songid: {}", state.current_track_index).ok();
+    }
+
+    if !is_stopped(state) {
+        let position = state.track_position.unwrap_or_default();
+
+        writeln!(response, "elapsed: {:.3}", position.as_secs_f64()).ok();
+
+        if let Some(duration) = state.track_duration {
+            writeln!(
+                response,
+                "time: {}:{}",
+                position.as_secs(),
+                duration.as_secs()
+            )
+            .ok();
+            writeln!(response, "duration: {:.3}", duration.as_secs_f64()).ok();
+        }
+    }
+
+    if let Some(latest_error) = state.latest_error.as_ref() {
+        writeln!(response, "error: {}", latest_error.error).ok();
+    }
+}
+
+/// The subsystems which changed between `a` and `b`, as reported by `idle`
+fn changed_subsystems(a: &PlayerState, b: &PlayerState) -> Vec<&'static str> {
+    let mut subsystems = Vec::new();
+
+    if !Arc::ptr_eq(&a.current_station, &b.current_station) {
+        subsystems.push("playlist");
+    }
+
+    if a.pipeline_state != b.pipeline_state
+        || a.current_track_index != b.current_track_index
+        || !Arc::ptr_eq(&a.current_track_tags, &b.current_track_tags)
+        || !Arc::ptr_eq(&a.current_station, &b.current_station)
+    {
+        subsystems.push("player");
+    }
+
+    if a.volume != b.volume || a.is_muted != b.is_muted {
+        subsystems.push("mixer");
+    }
+
+    if a.audio_outputs != b.audio_outputs {
+        subsystems.push("output");
+    }
+
+    subsystems
+}
+
+/// Reads lines from the client. Partially read lines are kept if reading is cancelled, e.g. while waiting in `idle`
+struct LineReader {
+    stream: tokio::io::BufReader<tokio::net::tcp::OwnedReadHalf>,
+    line: Vec<u8>,
+}
+
+impl LineReader {
+    /// The next line, or `None` if the client has disconnected
+    async fn next_line(&mut self) -> std::io::Result<Option<String>> {
+        loop {
+            if (&mut self.stream)
+                .take(MAX_LINE_LENGTH)
+                .read_until(b'\n', &mut self.line)
+                .await?
+                == 0
+            {
+                return Ok(None);
+            }
+
+            if self.line.ends_with(b"\n") || self.line.len() as u64 >= MAX_LINE_LENGTH {
+                let line = String::from_utf8_lossy(&self.line).trim().to_owned();
+                self.line.clear();
+                return Ok(Some(line));
+            }
+        }
+    }
+}
+
+/// Whether the connection continues after a command
+enum Outcome {
+    Continue,
+    Close,
+}
+
+struct Connection {
+    port_channels: super::PortChannels,
+    config: Arc<Config>,
+    /// The maximum number of bytes of the commands in a command list
+    max_command_list_size: usize,
+}
+
+impl Connection {
+    fn state(&self) -> PlayerState {
+        self.port_channels.player_state_rx.borrow().state().clone()
+    }
+
+    fn send(&self, command: Command) -> Result<(), Ack> {
+        self.port_channels
+            .commands_tx
+            .send(command)
+            .map_err(|err| Ack::new(AckCode::System, err.to_string()))
+    }
+
+    /// Toggle between playing and pausing if the pipeline isn't already in the requested state
+    fn set_is_playing(&self, is_playing: bool) -> Result<(), Ack> {
+        if (self.state().pipeline_state == PipelineState::Playing) == is_playing {
+            Ok(())
+        } else {
+            self.send(Command::PlayPause)
+        }
+    }
+
+    /// Execute a command other than `idle` and the command list commands, writing its response, but not the final `OK`, to `response`
+    #[allow(clippy::too_many_lines)]
+    async fn execute(&self, arguments: &[String], response: &mut String) -> Result<Outcome, Ack> {
+        let command = arguments.first().map_or("", String::as_str);
+        let argument = arguments.get(1);
+
+        match command {
+            "ping" => (),
+            "close" => return Ok(Outcome::Close),
+            "commands" => {
+                for command in SUPPORTED_COMMANDS {
+                    writeln!(response, "command: {command}").ok();
+                }
+            }
+            "notcommands" => (),
+            "tagtypes" => {
+                for tag_type in TAG_TYPES {
+                    writeln!(response, "tagtype: {tag_type}").ok();
+                }
+            }
+            "outputs" => {
+                let state = self.state();
+                let name = state
+                    .audio_outputs
+                    .as_ref()
+                    .map_or("default", |audio_outputs| audio_outputs.selected.as_str());
+
+                writeln!(response, "outputid: 0").ok();
+                writeln!(response, "outputname: {name}").ok();
+                writeln!(response, "outputenabled: 1").ok();
+            }
+            "status" => write_status(response, &self.state()),
+            "currentsong" => {
+                let state = self.state();
+
+                if let Some(track) = current_tracks(&state).get(state.current_track_index) {
+                    write_song(response, &state, state.current_track_index, track);
+                }
+            }
+            "playlistinfo" => {
+                let state = self.state();
+                let tracks = current_tracks(&state);
+
+                match argument {
+                    Some(_) => {
+                        let index = parse_argument::<usize>(argument)?;
+                        let track = tracks
+                            .get(index)
+                            .ok_or_else(|| Ack::new(AckCode::Argument, "Bad song index"))?;
+                        write_song(response, &state, index, track);
+                    }
+                    None => {
+                        for (index, track) in tracks.iter().enumerate() {
+                            write_song(response, &state, index, track);
+                        }
+                    }
+                }
+            }
+            "listplaylists" => {
+                // Listing stations reads the stations directory, which might be slow, e.g. on an SD card
+                let config = self.config.clone();
+                let stations = tokio::task::spawn_blocking(move || crate::station::list(&config))
+                    .await
+                    .map_err(|err| Ack::new(AckCode::System, err.to_string()))?
+                    .map_err(|err| Ack::new(AckCode::System, err.to_string()))?;
+
+                for station in stations {
+                    writeln!(response, "playlist: {} {}", station.index, station.name).ok();
+                }
+            }
+            "load" => {
+                // Playlists are named by `listplaylists` as the station index followed by the station name
+                let index = argument
+                    .and_then(|name| name.split_whitespace().next())
+                    .ok_or_else(|| Ack::new(AckCode::Argument, "Missing playlist name"))?;
+
+                self.send(Command::SetChannel(StationIndex::new(index.into())))?;
+            }
+            "delete" => self.send(Command::RemoveTrack(parse_argument(argument)?))?,
+            "move" => self.send(Command::MoveTrack {
+                from: parse_argument(argument)?,
+                to: parse_argument(arguments.get(2))?,
+            })?,
+            "play" | "playid" => match argument {
+                Some(_) => self.send(Command::NthItem(parse_argument(argument)?))?,
+                None => self.set_is_playing(true)?,
+            },
+            "pause" => match argument.map(String::as_str) {
+                Some("1") => self.set_is_playing(false)?,
+                Some("0") => self.set_is_playing(true)?,
+                Some(_) => return Err(Ack::new(AckCode::Argument, "Bad pause state")),
+                None => self.send(Command::PlayPause)?,
+            },
+            "stop" => self.set_is_playing(false)?,
+            "next" => self.send(Command::NextItem)?,
+            "previous" => self.send(Command::PreviousItem)?,
+            "setvol" => self.send(Command::SetVolume(parse_argument(argument)?))?,
+            "volume" => {
+                let change = parse_argument::<i32>(argument)?;
+                self.send(Command::SetVolume(changed_volume(
+                    self.state().volume,
+                    change,
+                )))?;
+            }
+            "seekcur" => {
+                let time = argument.ok_or_else(|| Ack::new(AckCode::Argument, "Missing time"))?;
+
+                let parse_seconds = |seconds: &str| {
+                    parse_seconds(seconds)
+                        .ok_or_else(|| Ack::new(AckCode::Argument, format!("Bad time {time:?}")))
+                };
+
+                self.send(if let Some(offset) = time.strip_prefix('+') {
+                    Command::SeekForwards(parse_seconds(offset)?)
+                } else if let Some(offset) = time.strip_prefix('-') {
+                    Command::SeekBackwards(parse_seconds(offset)?)
+                } else {
+                    Command::SeekTo(parse_seconds(time)?)
+                })?;
+            }
+            "" => return Err(Ack::new(AckCode::Unknown, "No command given")),
+            _ => {
+                return Err(Ack::new(
+                    AckCode::Unknown,
+                    format!("unknown command {command:?}"),
+                ))
+            }
+        }
+
+        Ok(Outcome::Continue)
+    }
+
+    /// Execute the commands of a command list in turn, stopping at the first command which fails
+    async fn execute_command_list(
+        &self,
+        commands: &[Vec<String>],
+        list_ok: bool,
+        response: &mut String,
+    ) -> Outcome {
+        for (index, arguments) in commands.iter().enumerate() {
+            match self.execute(arguments, response).await {
+                Ok(Outcome::Continue) => (),
+                Ok(Outcome::Close) => return Outcome::Close,
+                Err(ack) => {
+                    let command = arguments.first().map_or("", String::as_str);
+                    response.push_str(&ack.to_line(index, command));
+                    return Outcome::Continue;
+                }
+            }
+
+            if list_ok {
+                response.push_str("list_OK\n");
+            }
+        }
+
+        response.push_str("OK\n");
+
+        Outcome::Continue
+    }
+
+    /// Wait until one of `subsystems`, or any subsystem if empty, changes, or until the client sends `noidle`
+    async fn idle(
+        &mut self,
+        lines: &mut LineReader,
+        subsystems: &[String],
+        response: &mut String,
+    ) -> anyhow::Result<Outcome> {
+        let mut previous_state = self
+            .port_channels
+            .player_state_rx
+            .borrow_and_update()
+            .state()
+            .clone();
+
+        let mut shutdown_signal = self.port_channels.shutdown_signal.clone();
+
+        loop {
+            tokio::select! {
+                () = &mut shutdown_signal => return Ok(Outcome::Close),
+                line = lines.next_line() => match line? {
+                    Some(line) if line == "noidle" => return Ok(Outcome::Continue),
+                    Some(line) => {
+                        tracing::debug!(%line, "Only noidle is allowed while idle");
+                        return Ok(Outcome::Close);
+                    }
+                    None => return Ok(Outcome::Close),
+                },
+                result = self.port_channels.player_state_rx.changed() => {
+                    if result.is_err() {
+                        return Ok(Outcome::Close);
+                    }
+
+                    let state = self.port_channels.player_state_rx.borrow_and_update().state().clone();
+
+                    let changed_subsystems = changed_subsystems(&previous_state, &state)
+                        .into_iter()
+                        .filter(|changed| subsystems.is_empty() || subsystems.iter().any(|subsystem| subsystem == changed))
+                        .collect::<Vec<_>>();
+
+                    previous_state = state;
+
+                    if !changed_subsystems.is_empty() {
+                        for subsystem in changed_subsystems {
+                            writeln!(response, "changed: {subsystem}").ok();
+                        }
+
+                        return Ok(Outcome::Continue);
+                    }
+                }
+            }
+        }
+    }
+
+    #[allow(clippy::too_many_lines)]
+    async fn run(mut self, connection: tokio::net::TcpStream) -> anyhow::Result<()> {
+        let (connection_rx, mut connection_tx) = connection.into_split();
+
+        let mut lines = LineReader {
+            stream: tokio::io::BufReader::new(connection_rx),
+            line: Vec::new(),
+        };
+
+        connection_tx.write_all(GREETING.as_bytes()).await?;
+
+        // The commands of a command list, and whether `list_OK` is sent after each command
+        let mut command_list = None::<(Vec<Vec<String>>, bool)>;
+
+        // The number of bytes of the commands in the command list
+        let mut command_list_size = 0;
+
+        let mut shutdown_signal = self.port_channels.shutdown_signal.clone();
+
+        loop {
+            let line = tokio::select! {
+                () = &mut shutdown_signal => break,
+                line = lines.next_line() => match line.context("Failed to read command")? {
+                    Some(line) => line,
+                    None => break,
+                },
+            };
+
+            tracing::trace!(%line, "Command");
+
+            let arguments = match parse_arguments(&line) {
+                Ok(arguments) => arguments,
+                Err(ack) => {
+                    connection_tx
+                        .write_all(ack.to_line(0, "").as_bytes())
+                        .await?;
+                    continue;
+                }
+            };
+
+            let command = arguments.first().map_or("", String::as_str);
+
+            let mut response = String::new();
+
+            let outcome = match (command, &mut command_list) {
+                ("command_list_begin", None) => {
+                    command_list = Some((Vec::new(), false));
+                    command_list_size = 0;
+                    continue;
+                }
+                ("command_list_ok_begin", None) => {
+                    command_list = Some((Vec::new(), true));
+                    command_list_size = 0;
+                    continue;
+                }
+                ("command_list_end", Some(_)) => {
+                    let (commands, list_ok) = command_list.take().unwrap_or_default();
+
+                    self.execute_command_list(&commands, list_ok, &mut response)
+                        .await
+                }
+                (_, Some((commands, _))) => {
+                    command_list_size += line.len();
+
+                    // As with MPD, the connection is closed, as the rest of the list would otherwise be run as separate commands
+                    if command_list_size > self.max_command_list_size {
+                        let ack = Ack::new(AckCode::Argument, "Command list is too long");
+                        connection_tx
+                            .write_all(ack.to_line(commands.len(), command).as_bytes())
+                            .await?;
+                        break;
+                    }
+
+                    commands.push(arguments);
+                    continue;
+                }
+                ("idle", None) => {
+                    let outcome = self
+                        .idle(&mut lines, &arguments[1..], &mut response)
+                        .await?;
+                    response.push_str("OK\n");
+                    outcome
+                }
+                // `noidle` is ignored when not idle
+                ("noidle", None) => continue,
+                (_, None) => match self.execute(&arguments, &mut response).await {
+                    Ok(outcome) => {
+                        response.push_str("OK\n");
+                        outcome
+                    }
+                    Err(ack) => {
+                        response = ack.to_line(0, command);
+                        Outcome::Continue
+                    }
+                },
+            };
+
+            if let Outcome::Close = outcome {
+                break;
+            }
+
+            connection_tx.write_all(response.as_bytes()).await?;
+        }
+
+        tracing::debug!("Closing connection");
+
+        Ok(())
+    }
+}
+
+pub async fn run(port_channels: super::PortChannels, config: Config) -> anyhow::Result<()> {
+    let Some(mpd_config) = config.mpd.clone() else {
+        return Ok(());
+    };
+
+    async move {
+        let config = Arc::new(config);
+
+        let wait_group = crate::task::WaitGroup::new();
+
+        let listener = super::listener::bind(config.tcp_config.address_family, mpd_config.port)?;
+
+        tracing::info!(socket_addr = %listener.local_addr()?, "Listening");
+
+        let connections = futures_util::stream::try_unfold(listener, |listener| async {
+            let (connection, remote_addr) = listener.accept().await?;
+            anyhow::Ok(Some((
+                (
+                    connection,
+                    super::listener::canonical_remote_address(remote_addr),
+                ),
+                listener,
+            )))
+        })
+        .take_until(port_channels.shutdown_signal.clone());
+
+        tokio::pin!(connections);
+
+        while let Some((connection, remote_addr)) = connections.try_next().await? {
+            tracing::debug!(%remote_addr, "Connection");
+
+            let mut connection_channels = port_channels.clone();
+            connection_channels.commands_tx = port_channels
+                .commands_tx
+                .with_source(arcstr::format!("mpd {remote_addr}"))
+                .with_privilege(config.tcp_config.is_privileged(remote_addr.ip()));
+
+            wait_group.spawn_task(
+                "connection",
+                tracing::error_span!("connection", %remote_addr),
+                Connection {
+                    port_channels: connection_channels,
+                    config: config.clone(),
+                    max_command_list_size: mpd_config.max_command_list_size,
+                }
+                .run(connection),
+            );
+        }
+
+        tracing::debug!("Shutting down");
+
+        wait_group.wait().await;
+
+        tracing::debug!("Shut down");
+
+        Ok(())
+    }
+    .instrument(tracing::error_span!("mpd"))
+    .await
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::{changed_volume, parse_arguments, parse_seconds};
+
+    #[test]
+    fn arguments_are_split() {
+        assert_eq!(
+            parse_arguments(r#"load "05 Radio 4"  now"#).unwrap(),
+            ["load", "05 Radio 4", "now"]
+        );
+        assert_eq!(
+            parse_arguments(r#"find "say \"hi\" \\ there""#).unwrap(),
+            ["find", r#"say "hi" \ there"#]
+        );
+        assert!(parse_arguments("").unwrap().is_empty());
+        assert!(parse_arguments(r#"load "unterminated"#).is_err());
+    }
+
+    #[test]
+    fn seek_times_which_dont_fit_a_duration_are_rejected() {
+        assert_eq!(parse_seconds("1.5"), Some(Duration::from_millis(1500)));
+        assert_eq!(parse_seconds("1e30"), None);
+        assert_eq!(parse_seconds("-1"), None);
+        assert_eq!(parse_seconds("nan"), None);
+        assert_eq!(parse_seconds("inf"), None);
+    }
+
+    #[test]
+    fn relative_volume_changes_dont_overflow() {
+        assert_eq!(changed_volume(50, 10), 60);
+        assert_eq!(changed_volume(50, i32::MAX), i32::MAX);
+        assert_eq!(changed_volume(50, i32::MIN), i32::MIN + 50);
+    }
+}
@@ -0,0 +1,360 @@
+//! Lua plugins, which are notified of changes to the player state and can send commands, so that custom behaviour can be added without changing rradio.
+//!
+//! Each plugin runs in its own Lua state, which only has access to the `table`, `string`, `math` and `utf8` libraries, and to the `rradio` table:
+//! + `rradio.send_command(command)` - Send a command, e.g. `rradio.send_command({SetChannel = "05"})` or `rradio.send_command("PlayPause")`
+//! + `rradio.now()` - The current time in the configured timezone, as a table with `year`, `month`, `day`, `hour`, `minute`, `second` and `weekday` (Monday is 1)
+//! + `rradio.log(message)` - Write a message to the log
+//!
+//! Plugins define global functions which are called with the player state: `on_station_changed`, `on_track_changed`, `on_error`, `on_volume_changed` and `on_tick`.
+//! Loading a plugin, and each call of a plugin function, is stopped if it runs too many instructions, so that a plugin stuck in a loop doesn't stop the other plugins
+
+use std::{
+    path::Path,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        mpsc, Arc,
+    },
+    time::Duration,
+};
+
+use anyhow::{Context, Result};
+use chrono::{Datelike, Timelike};
+use mlua::{Function, HookTriggers, Lua, LuaOptions, LuaSerdeExt, SerializeOptions, StdLib};
+
+use rradio_messages::PlayerStateDiff;
+
+use crate::config::{hook::Event, Config};
+
+use super::{hooks, CommandSender, PortChannels};
+
+/// How often `on_tick` is called, e.g. so that plugins can change station at a particular time
+const TICK_INTERVAL: Duration = Duration::from_secs(30);
+
+/// How many notifications can wait for the plugins. Further notifications are dropped until the plugins catch up
+const NOTIFICATION_QUEUE_LENGTH: usize = 16;
+
+/// How often the instruction limit is checked
+const INSTRUCTIONS_PER_CHECK: u32 = 1000;
+
+fn handler_name(event: Event) -> &'static str {
+    match event {
+        Event::StationChanged => "on_station_changed",
+        Event::TrackChanged => "on_track_changed",
+        Event::Error => "on_error",
+        Event::VolumeChanged => "on_volume_changed",
+    }
+}
+
+/// A call of a plugin function
+struct Notification {
+    handler: &'static str,
+    state: PlayerStateDiff,
+}
+
+struct Plugin {
+    name: String,
+    lua: Lua,
+    instruction_limit: u64,
+    /// Reset to `instruction_limit` before the plugin is loaded, and before each call
+    remaining_instructions: Arc<AtomicU64>,
+}
+
+impl Plugin {
+    fn load(path: &Path, config: &Config, commands_tx: CommandSender) -> Result<Self> {
+        let name = path.file_stem().map_or_else(
+            || path.display().to_string(),
+            |name| name.to_string_lossy().into_owned(),
+        );
+
+        let source = std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read {}", path.display()))?;
+
+        let lua = Lua::new_with(
+            StdLib::TABLE | StdLib::STRING | StdLib::MATH | StdLib::UTF8,
+            LuaOptions::default(),
+        )?;
+
+        lua.set_memory_limit(config.plugins_config.memory_limit)?;
+
+        let instruction_limit = config.plugins_config.instruction_limit;
+        let remaining_instructions = Arc::new(AtomicU64::new(instruction_limit));
+
+        let hook_remaining_instructions = remaining_instructions.clone();
+        lua.set_hook(
+            HookTriggers::new().every_nth_instruction(INSTRUCTIONS_PER_CHECK),
+            move |_, _| {
+                hook_remaining_instructions
+                    .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |remaining| {
+                        remaining.checked_sub(INSTRUCTIONS_PER_CHECK.into())
+                    })
+                    .map(drop)
+                    .map_err(|_| {
+                        mlua::Error::RuntimeError(format!(
+                            "Exceeded the limit of {instruction_limit} instructions"
+                        ))
+                    })
+            },
+        );
+
+        let rradio = lua.create_table()?;
+
+        // Plugins are installed by whoever configures the radio, so are trusted to lock the station
+        let commands_tx = commands_tx
+            .with_source(format!("plugin {name}"))
+            .with_privilege(true);
+
+        rradio.set(
+            "send_command",
+            lua.create_function(move |lua, command: mlua::Value| {
+                commands_tx
+                    .send(lua.from_value(command)?)
+                    .map_err(mlua::Error::external)
+            })?,
+        )?;
+
+        let timezone = config.timezone;
+        rradio.set(
+            "now",
+            lua.create_function(move |lua, ()| {
+                let now = timezone.now();
+
+                let time = lua.create_table()?;
+                time.set("year", now.year())?;
+                time.set("month", now.month())?;
+                time.set("day", now.day())?;
+                time.set("hour", now.hour())?;
+                time.set("minute", now.minute())?;
+                time.set("second", now.second())?;
+                time.set("weekday", now.weekday().number_from_monday())?;
+
+                Ok(time)
+            })?,
+        )?;
+
+        rradio.set("log", {
+            let name = name.clone();
+            lua.create_function(move |_, message: String| {
+                tracing::info!(plugin = %name, "{message}");
+                Ok(())
+            })?
+        })?;
+
+        lua.globals().set("rradio", rradio)?;
+
+        lua.load(source.as_str())
+            .set_name(name.clone())
+            .exec()
+            .with_context(|| format!("Failed to run {}", path.display()))?;
+
+        Ok(Self {
+            name,
+            lua,
+            instruction_limit,
+            remaining_instructions,
+        })
+    }
+
+    /// Call the global function `handler`, if the plugin defines it
+    fn call(&self, handler: &str, state: &PlayerStateDiff) -> mlua::Result<()> {
+        self.remaining_instructions
+            .store(self.instruction_limit, Ordering::Relaxed);
+
+        let Some(function) = self.lua.globals().get::<_, Option<Function>>(handler)? else {
+            return Ok(());
+        };
+
+        let state = self.lua.to_value_with(
+            state,
+            SerializeOptions::new()
+                .serialize_none_to_null(false)
+                .serialize_unit_to_null(false),
+        )?;
+
+        function.call(state)
+    }
+}
+
+/// Load the `.lua` files in the plugins directory, in order of file name
+fn load_plugins(config: &Config, commands_tx: &CommandSender) -> Vec<Plugin> {
+    let directory = Path::new(config.plugins_config.directory.as_str());
+
+    let mut paths = match std::fs::read_dir(directory) {
+        Ok(entries) => entries
+            .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+            .filter(|path| path.extension().is_some_and(|extension| extension == "lua"))
+            .collect::<Vec<_>>(),
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => {
+            tracing::debug!("No plugins directory at {}", directory.display());
+            return Vec::new();
+        }
+        Err(err) => {
+            tracing::error!("Failed to read {}: {err}", directory.display());
+            return Vec::new();
+        }
+    };
+
+    paths.sort();
+
+    paths
+        .iter()
+        .filter_map(
+            |path| match Plugin::load(path, config, commands_tx.clone()) {
+                Ok(plugin) => {
+                    tracing::info!(plugin = %plugin.name, "Loaded plugin");
+                    Some(plugin)
+                }
+                Err(err) => {
+                    tracing::error!("Failed to load plugin: {err:#}");
+                    None
+                }
+            },
+        )
+        .collect()
+}
+
+/// Lua states can't be shared between threads, so plugins are loaded and run on their own thread
+fn run_plugins(
+    config: &Config,
+    commands_tx: &CommandSender,
+    notifications_rx: &mpsc::Receiver<Notification>,
+) {
+    let plugins = load_plugins(config, commands_tx);
+
+    if plugins.is_empty() {
+        return;
+    }
+
+    for Notification { handler, state } in notifications_rx {
+        for plugin in &plugins {
+            if let Err(err) = plugin.call(handler, &state) {
+                tracing::error!(plugin = %plugin.name, "{handler} failed: {err}");
+            }
+        }
+    }
+}
+
+pub async fn run(port_channels: PortChannels, config: Config) -> Result<()> {
+    let (notifications_tx, notifications_rx) = mpsc::sync_channel(NOTIFICATION_QUEUE_LENGTH);
+
+    let commands_tx = port_channels.commands_tx;
+
+    std::thread::Builder::new()
+        .name(String::from("plugins"))
+        .spawn(move || run_plugins(&config, &commands_tx, &notifications_rx))
+        .context("Failed to start plugin thread")?;
+
+    let mut player_state_rx = port_channels.player_state_rx;
+    let mut shutdown_signal = port_channels.shutdown_signal;
+
+    let mut current_state = player_state_rx.borrow_and_update().clone();
+    let mut ticks = tokio::time::interval(TICK_INTERVAL);
+
+    loop {
+        let handlers = tokio::select! {
+            () = &mut shutdown_signal => break,
+            _ = ticks.tick() => vec!["on_tick"],
+            result = player_state_rx.changed() => {
+                if result.is_err() {
+                    break;
+                }
+
+                let previous_state = std::mem::replace(
+                    &mut current_state,
+                    player_state_rx.borrow_and_update().clone(),
+                );
+
+                hooks::triggered_events(previous_state.state(), current_state.state())
+                    .into_iter()
+                    .map(handler_name)
+                    .collect()
+            }
+        };
+
+        for handler in handlers {
+            let notification = Notification {
+                handler,
+                state: hooks::state_without_image(current_state.state()),
+            };
+
+            match notifications_tx.try_send(notification) {
+                Ok(()) => (),
+                Err(mpsc::TrySendError::Full(_)) => {
+                    tracing::warn!("Plugins are busy, so {handler} was dropped");
+                }
+                Err(mpsc::TrySendError::Disconnected(_)) => {
+                    // There are no plugins
+                    return Ok(());
+                }
+            }
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use rradio_messages::PlayerStateDiff;
+
+    use crate::{config::Config, ports::CommandSender};
+
+    use super::{load_plugins, Plugin};
+
+    fn commands_tx() -> CommandSender {
+        let (commands_tx, _) = tokio::sync::mpsc::channel(1);
+        CommandSender::new(commands_tx, std::sync::Arc::default())
+    }
+
+    fn config(directory: &std::path::Path) -> Config {
+        let mut config = Config::default();
+        config.plugins_config.directory = directory.to_str().unwrap().into();
+        config.plugins_config.instruction_limit = 100_000;
+        config
+    }
+
+    fn state() -> PlayerStateDiff {
+        serde_json::from_str("{}").unwrap()
+    }
+
+    #[test]
+    fn plugins_are_loaded_in_order() {
+        let directory = tempfile::tempdir().unwrap();
+
+        for (file_name, source) in [
+            ("b.lua", "function on_tick(state) end"),
+            ("a.lua", "rradio.log('Loaded')"),
+            ("c.txt", "Not a plugin"),
+            ("d.lua", "while true do end"),
+        ] {
+            std::fs::write(directory.path().join(file_name), source).unwrap();
+        }
+
+        let plugins = load_plugins(&config(directory.path()), &commands_tx());
+
+        let names = plugins
+            .iter()
+            .map(|plugin| plugin.name.as_str())
+            .collect::<Vec<_>>();
+
+        assert_eq!(names, ["a", "b"]);
+    }
+
+    #[test]
+    fn runaway_plugins_are_stopped() {
+        let directory = tempfile::tempdir().unwrap();
+        let path = directory.path().join("runaway.lua");
+
+        std::fs::write(
+            &path,
+            "function on_tick(state) while true do end end\nfunction on_error(state) end",
+        )
+        .unwrap();
+
+        let plugin = Plugin::load(&path, &config(directory.path()), commands_tx()).unwrap();
+
+        assert!(plugin.call("on_tick", &state()).is_err());
+
+        // The limit applies to each call
+        assert!(plugin.call("on_error", &state()).is_ok());
+    }
+}
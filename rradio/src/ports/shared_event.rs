@@ -0,0 +1,293 @@
+//! Events which are shared between connections, so that each event is only encoded once per encoding, no matter how many clients are connected
+
+use std::{
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex, OnceLock, PoisonError,
+    },
+    time::Duration,
+};
+
+use tokio::sync::watch;
+
+use rradio_messages::{Event, EventEncoding, EventFormatV2, ImageLimits, SequencedEvent};
+
+use crate::pipeline::PlayerState;
+
+/// An encoded event, or a description of why it couldn't be encoded
+type Frame = Result<Arc<[u8]>, String>;
+
+/// A copy of an event with its track image fitted within some image limits, which is created by the first connection which needs it
+type ImageLimitedEvent = Arc<tokio::sync::OnceCell<Option<SharedEvent>>>;
+
+struct SharedEventInner {
+    event: Event,
+    sequence: u64,
+    since_start: Duration,
+    timestamp: chrono::DateTime<chrono::Utc>,
+    postcard_frame: OnceLock<Frame>,
+    json_frame: OnceLock<Frame>,
+    sequenced_postcard_frame: OnceLock<Frame>,
+    sequenced_json_frame: OnceLock<Frame>,
+    /// Copies of the event with its track image fitted within the image limits of connections, or `None` if the event fits within the limits as it is
+    image_limited_events: Mutex<Vec<(ImageLimits, ImageLimitedEvent)>>,
+}
+
+impl SharedEventInner {
+    fn new(
+        event: Event,
+        sequence: u64,
+        since_start: Duration,
+        timestamp: chrono::DateTime<chrono::Utc>,
+    ) -> Self {
+        Self {
+            event,
+            sequence,
+            since_start,
+            timestamp,
+            postcard_frame: OnceLock::new(),
+            json_frame: OnceLock::new(),
+            sequenced_postcard_frame: OnceLock::new(),
+            sequenced_json_frame: OnceLock::new(),
+            image_limited_events: Mutex::new(Vec::new()),
+        }
+    }
+}
+
+/// An [`Event`] which is encoded when first needed, and whose encoded frames are then shared by all connections
+#[derive(Clone)]
+pub struct SharedEvent(Arc<SharedEventInner>);
+
+impl SharedEvent {
+    pub fn new(event: Event) -> Self {
+        static NEXT_SEQUENCE: AtomicU64 = AtomicU64::new(0);
+
+        Self(Arc::new(SharedEventInner::new(
+            event,
+            NEXT_SEQUENCE.fetch_add(1, Ordering::Relaxed),
+            crate::clock::since_start(),
+            chrono::Utc::now(),
+        )))
+    }
+
+    pub fn event(&self) -> &Event {
+        &self.0.event
+    }
+
+    /// The event encoded as [COBS](https://en.wikipedia.org/wiki/Consistent_Overhead_Byte_Stuffing) encoded postcard, suffixed with a value of `0`
+    pub fn postcard_frame(&self) -> anyhow::Result<Arc<[u8]>> {
+        self.0
+            .postcard_frame
+            .get_or_init(|| {
+                let mut buffer = Vec::new();
+
+                self.0
+                    .event
+                    .encode(&mut buffer)
+                    .map(Arc::from)
+                    .map_err(|err| err.to_string())
+            })
+            .clone()
+            .map_err(anyhow::Error::msg)
+    }
+
+    /// The event encoded as JSON, suffixed with a newline
+    pub fn json_frame(&self) -> anyhow::Result<Arc<[u8]>> {
+        self.0
+            .json_frame
+            .get_or_init(|| {
+                let mut buffer = serde_json::to_vec(&self.0.event)
+                    .map_err(|err| format!("Failed to encode Event as JSON: {err}"))?;
+                buffer.push(b'\n');
+
+                Ok(Arc::from(buffer))
+            })
+            .clone()
+            .map_err(anyhow::Error::msg)
+    }
+
+    fn sequenced_event(&self) -> SequencedEvent {
+        SequencedEvent {
+            sequence: self.0.sequence,
+            since_start: self.0.since_start,
+            timestamp: self.0.timestamp,
+            event: self.0.event.clone(),
+        }
+    }
+
+    /// The event, along with its sequence number and when it was created, encoded as [COBS](https://en.wikipedia.org/wiki/Consistent_Overhead_Byte_Stuffing) encoded postcard, suffixed with a value of `0`
+    pub fn sequenced_postcard_frame(&self) -> anyhow::Result<Arc<[u8]>> {
+        self.0
+            .sequenced_postcard_frame
+            .get_or_init(|| {
+                let mut buffer = Vec::new();
+
+                self.sequenced_event()
+                    .encode(&mut buffer)
+                    .map(Arc::from)
+                    .map_err(|err| err.to_string())
+            })
+            .clone()
+            .map_err(anyhow::Error::msg)
+    }
+
+    /// The event, along with its sequence number and when it was created, encoded as JSON, suffixed with a newline
+    pub fn sequenced_json_frame(&self) -> anyhow::Result<Arc<[u8]>> {
+        self.0
+            .sequenced_json_frame
+            .get_or_init(|| {
+                let mut buffer = serde_json::to_vec(&self.sequenced_event())
+                    .map_err(|err| format!("Failed to encode SequencedEvent as JSON: {err}"))?;
+                buffer.push(b'\n');
+
+                Ok(Arc::from(buffer))
+            })
+            .clone()
+            .map_err(anyhow::Error::msg)
+    }
+
+    /// The event with its track image fitted within `image_limits`, with the same sequence number and creation time, or `None` if the event already fits.
+    /// Images are only downscaled once for each distinct set of image limits, no matter how many connections share them.
+    /// Downscaling happens on a blocking thread, and without holding the lock, so that connections with other image limits aren't held up
+    async fn image_limited(&self, image_limits: ImageLimits) -> Option<SharedEvent> {
+        let image_limited_event = {
+            let mut image_limited_events = self
+                .0
+                .image_limited_events
+                .lock()
+                .unwrap_or_else(PoisonError::into_inner);
+
+            if let Some((_, event)) = image_limited_events
+                .iter()
+                .find(|(limits, _)| *limits == image_limits)
+            {
+                event.clone()
+            } else {
+                let event = ImageLimitedEvent::default();
+                image_limited_events.push((image_limits, event.clone()));
+                event
+            }
+        };
+
+        image_limited_event
+            .get_or_init(|| async {
+                let shared_event = self.clone();
+
+                tokio::task::spawn_blocking(move || {
+                    super::image_limits::limit_event(&shared_event.0.event, image_limits)
+                })
+                .await
+                .unwrap_or_else(|err| {
+                    tracing::error!("Failed to fit image within limits: {err}");
+                    None
+                })
+                .map(|event| {
+                    Self(Arc::new(SharedEventInner::new(
+                        event,
+                        self.0.sequence,
+                        self.0.since_start,
+                        self.0.timestamp,
+                    )))
+                })
+            })
+            .await
+            .clone()
+    }
+
+    pub async fn frame(&self, event_format: EventFormatV2) -> anyhow::Result<Arc<[u8]>> {
+        if let Some(image_limits) = event_format.format.image_limits {
+            if let Some(event) = self.image_limited(image_limits).await {
+                return event.encoded_frame(event_format);
+            }
+        }
+
+        self.encoded_frame(event_format)
+    }
+
+    /// The event encoded as given by `event_format`, ignoring its image limits
+    fn encoded_frame(&self, event_format: EventFormatV2) -> anyhow::Result<Arc<[u8]>> {
+        match (event_format.format.encoding, event_format.sequenced) {
+            (EventEncoding::Postcard, false) => self.postcard_frame(),
+            (EventEncoding::Json, false) => self.json_frame(),
+            (EventEncoding::Postcard, true) => self.sequenced_postcard_frame(),
+            (EventEncoding::Json, true) => self.sequenced_json_frame(),
+        }
+    }
+}
+
+/// A version of the player state, along with the events which describe it
+#[derive(Clone)]
+pub struct PublishedState {
+    version: u64,
+    state: PlayerState,
+    /// The difference from the previous version
+    diff: Option<SharedEvent>,
+    /// An event containing the entire state, which is created when first needed
+    full_state: Arc<OnceLock<SharedEvent>>,
+}
+
+impl PublishedState {
+    fn new(version: u64, state: PlayerState, diff: Option<SharedEvent>) -> Self {
+        Self {
+            version,
+            state,
+            diff,
+            full_state: Arc::new(OnceLock::new()),
+        }
+    }
+
+    pub fn state(&self) -> &PlayerState {
+        &self.state
+    }
+
+    pub fn full_state_event(&self) -> SharedEvent {
+        self.full_state
+            .get_or_init(|| {
+                SharedEvent::new(Event::PlayerStateChanged(super::player_state_to_diff(
+                    &self.state,
+                )))
+            })
+            .clone()
+    }
+
+    /// The event which describes how the state changed since `previous`, or None if nothing changed.
+    /// Clients which have seen every version share the same event, while clients which have skipped versions have their own event
+    pub fn diff_event(&self, previous: &Self) -> Option<SharedEvent> {
+        if self.version == previous.version + 1 {
+            self.diff.clone()
+        } else {
+            super::diff_player_state(&previous.state, &self.state)
+                .map(|diff| SharedEvent::new(Event::PlayerStateChanged(diff)))
+        }
+    }
+}
+
+/// Publishes changes to the player state to all connections
+pub struct StatePublisher(watch::Sender<PublishedState>);
+
+impl StatePublisher {
+    /// Publish the new state, if it's different to the current state
+    pub fn publish(&self, state: PlayerState) {
+        self.0.send_if_modified(|published_state| {
+            let Some(diff) = super::diff_player_state(&published_state.state, &state) else {
+                return false;
+            };
+
+            *published_state = PublishedState::new(
+                published_state.version + 1,
+                state,
+                Some(SharedEvent::new(Event::PlayerStateChanged(diff))),
+            );
+
+            true
+        });
+    }
+}
+
+pub fn state_channel(
+    initial_state: PlayerState,
+) -> (StatePublisher, watch::Receiver<PublishedState>) {
+    let (state_tx, state_rx) = watch::channel(PublishedState::new(0, initial_state, None));
+
+    (StatePublisher(state_tx), state_rx)
+}
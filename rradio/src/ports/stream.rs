@@ -1,8 +1,11 @@
 use anyhow::Result;
-use futures_util::{Sink, Stream, StreamExt};
-use tokio::io::{AsyncRead, AsyncWrite, AsyncWriteExt};
+use futures_util::{Sink, SinkExt, Stream, StreamExt, TryStreamExt};
+use tokio::{
+    io::{AsyncRead, AsyncWrite, AsyncWriteExt},
+    sync::watch,
+};
 
-use rradio_messages::{Command, Event};
+use rradio_messages::{Command, EventFormatV2};
 
 use crate::task::{ShutdownSignal, WaitGroup};
 
@@ -13,47 +16,103 @@ pub trait Splittable {
     fn into_split(self) -> (Self::OwnedReadHalf, Self::OwnedWriteHalf);
 }
 
+/// Forward commands from and events to the connection. `backoff_hint`, if any, is sent after the version header, and events are sent at most once per `minimum_event_interval`
 pub fn handle_connection<S: Splittable, EventsEncoder, Events, CommandsDecoder, Commands>(
     connection: S,
+    backoff_hint: Option<std::time::Duration>,
+    minimum_event_interval: Option<std::time::Duration>,
     port_channels: &super::PortChannels,
     wait_group: &WaitGroup,
     encode_events: EventsEncoder,
     decode_commands: CommandsDecoder,
 ) where
-    EventsEncoder: FnOnce(S::OwnedWriteHalf) -> Events + Send + 'static,
-    Events: Sink<Event, Error = anyhow::Error> + Send + 'static,
+    EventsEncoder:
+        FnOnce(S::OwnedWriteHalf, watch::Receiver<EventFormatV2>) -> Events + Send + 'static,
+    Events: Sink<super::SharedEvent, Error = anyhow::Error> + Send + 'static,
     CommandsDecoder: FnOnce(S::OwnedReadHalf) -> Commands + Send + 'static,
     Commands: Stream<Item = Result<Command>> + Send + 'static,
 {
     let (connection_rx, mut connection_tx) = connection.into_split();
-    let (shutdown_handle, shutdown_signal) = ShutdownSignal::new();
+    let (shutdown_handle, mut shutdown_signal) = ShutdownSignal::new();
+    let (event_format_tx, mut event_format_rx) = watch::channel(EventFormatV2::default());
+    let (commands_tx, mut replies_rx) = super::CommandSink::new(port_channels.commands_tx.clone());
 
-    wait_group.spawn_task(tracing::error_span!("forward_commands"), {
-        let commands_tx = port_channels.commands_tx.clone();
-        let commands_rx = (decode_commands)(connection_rx);
-        async move {
-            commands_rx.forward(super::CommandSink(commands_tx)).await?;
+    wait_group.spawn_task(
+        "forward_commands",
+        tracing::error_span!("forward_commands"),
+        {
+            let commands_rx = (decode_commands)(connection_rx);
+            async move {
+                commands_rx
+                    .try_filter_map(move |command| {
+                        futures_util::future::ready(Ok(match command {
+                            // The event format is a property of the connection, so isn't forwarded
+                            Command::SetEventFormat(event_format) => {
+                                tracing::debug!(?event_format, "Setting event format");
+                                event_format_tx.send_replace(event_format.into());
+                                None
+                            }
+                            Command::SetEventFormatV2(event_format) => {
+                                tracing::debug!(?event_format, "Setting event format");
+                                event_format_tx.send_replace(event_format);
+                                None
+                            }
+                            command => Some(command),
+                        }))
+                    })
+                    .forward(commands_tx)
+                    .await?;
 
-            tracing::debug!("Disconnection");
+                tracing::debug!("Disconnection");
 
-            shutdown_handle.signal_shutdown();
+                shutdown_handle.signal_shutdown();
 
-            Ok(())
-        }
-    });
+                Ok(())
+            }
+        },
+    );
 
-    wait_group.spawn_task(tracing::error_span!("forward_events"), {
-        let events = port_channels.event_stream().take_until(shutdown_signal);
+    wait_group.spawn_task("forward_events", tracing::error_span!("forward_events"), {
+        let port_channels = port_channels.clone();
 
         async move {
             connection_tx
                 .write_all(rradio_messages::API_VERSION_HEADER.as_bytes())
                 .await?;
 
-            events
-                .map(Ok)
-                .forward((encode_events)(connection_tx))
-                .await?;
+            if let Some(backoff_hint) = backoff_hint {
+                connection_tx
+                    .write_all(rradio_messages::encode_backoff_hint(backoff_hint).as_bytes())
+                    .await?;
+            }
+
+            let events_tx = (encode_events)(connection_tx, event_format_rx.clone());
+            tokio::pin!(events_tx);
+
+            // Restart the event stream whenever the event format changes, so that the first event in the new format contains the entire state
+            'events: loop {
+                let full_state = event_format_rx.borrow_and_update().format.full_state;
+                let events =
+                    port_channels.event_stream_with_full_state(full_state, minimum_event_interval);
+                tokio::pin!(events);
+
+                loop {
+                    tokio::select! {
+                        () = &mut shutdown_signal => break 'events,
+                        result = event_format_rx.changed() => match result {
+                            Ok(()) => continue 'events,
+                            Err(_) => break 'events, // No more commands, so the connection is closing
+                        },
+                        event = events.next() => match event {
+                            Some(event) => events_tx.send(event).await?,
+                            None => break 'events,
+                        },
+                        Some(reply) = replies_rx.recv() => events_tx.send(reply).await?,
+                    }
+                }
+            }
+
+            events_tx.close().await?;
 
             tracing::debug!("Closing connection");
             Ok(())
@@ -1,10 +1,12 @@
 //! Common code for TCP ports
 
-use anyhow::{Context, Result};
+use std::time::Duration;
+
+use anyhow::Result;
 use futures_util::{Sink, Stream, StreamExt, TryStreamExt};
 use tokio::net::tcp;
 
-use rradio_messages::{Command, Event};
+use rradio_messages::{arcstr, Command, EventFormatV2};
 use tracing::Instrument;
 
 impl super::stream::Splittable for tokio::net::TcpStream {
@@ -16,49 +18,71 @@ impl super::stream::Splittable for tokio::net::TcpStream {
     }
 }
 
+/// Accept connections on `port`. If `sends_backoff_hint` is true, clients are sent the suggested backoff after the version header
 pub async fn run<EventsEncoder, Events, CommandsDecoder, Commands>(
     port_channels: super::PortChannels,
+    config: crate::config::tcp::Config,
     port: u16,
+    sends_backoff_hint: bool,
+    minimum_event_interval: Option<Duration>,
     encode_events: EventsEncoder,
     decode_commands: CommandsDecoder,
 ) -> anyhow::Result<()>
 where
-    EventsEncoder: FnOnce(tcp::OwnedWriteHalf) -> Events + Send + Clone + 'static,
-    Events: Sink<Event, Error = anyhow::Error> + Send + 'static,
+    EventsEncoder: FnOnce(tcp::OwnedWriteHalf, tokio::sync::watch::Receiver<EventFormatV2>) -> Events
+        + Send
+        + Clone
+        + 'static,
+    Events: Sink<super::SharedEvent, Error = anyhow::Error> + Send + 'static,
     CommandsDecoder: FnOnce(tcp::OwnedReadHalf) -> Commands + Send + Clone + 'static,
     Commands: Stream<Item = Result<Command>> + Send + 'static,
 {
     async move {
-        let addr = if cfg!(feature = "production-server") {
-            std::net::Ipv4Addr::UNSPECIFIED
-        } else {
-            std::net::Ipv4Addr::LOCALHOST
-        };
-
-        let socket_addr = std::net::SocketAddr::from((addr, port));
-
         let wait_group = crate::task::WaitGroup::new();
 
-        let listener = tokio::net::TcpListener::bind(socket_addr)
-            .await
-            .with_context(|| format!("Failed to listen to {socket_addr:?}"))?;
+        let listener = super::listener::bind(config.address_family, port)?;
+
+        let socket_addr = listener.local_addr()?;
 
         tracing::info!(%socket_addr, "Listening");
 
-        let connections = futures_util::stream::try_unfold(listener, |listener| async {
-            anyhow::Ok(Some((listener.accept().await?, listener)))
-        })
+        port_channels.boot_milestones.port_listening();
+
+        let connections = futures_util::stream::try_unfold(
+            (
+                listener,
+                super::listener::AcceptThrottle::new(config.clone()),
+            ),
+            |(listener, mut throttle)| async {
+                throttle.ready().await;
+                let (connection, remote_addr) = listener.accept().await?;
+                let remote_addr = super::listener::canonical_remote_address(remote_addr);
+                let backoff_hint = throttle.accepted();
+                anyhow::Ok(Some((
+                    (connection, remote_addr, backoff_hint),
+                    (listener, throttle),
+                )))
+            },
+        )
         .take_until(port_channels.shutdown_signal.clone());
 
         tokio::pin!(connections);
 
-        while let Some((connection, remote_addr)) = connections.try_next().await? {
+        while let Some((connection, remote_addr, backoff_hint)) = connections.try_next().await? {
             let _span = tracing::error_span!("connection", %remote_addr).entered();
-            tracing::debug!("Connection");
+            tracing::debug!(?backoff_hint, "Connection");
+
+            let mut connection_channels = port_channels.clone();
+            connection_channels.commands_tx = port_channels
+                .commands_tx
+                .with_source(arcstr::format!("tcp:{port} {remote_addr}"))
+                .with_privilege(config.is_privileged(remote_addr.ip()));
 
             super::stream::handle_connection(
                 connection,
-                &port_channels,
+                sends_backoff_hint.then_some(backoff_hint),
+                minimum_event_interval,
+                &connection_channels,
                 &wait_group,
                 encode_events.clone(),
                 decode_commands.clone(),
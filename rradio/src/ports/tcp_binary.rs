@@ -1,10 +1,40 @@
 use futures_util::{SinkExt, TryStreamExt};
+use tokio::{io::AsyncWriteExt, net::tcp::OwnedWriteHalf, sync::watch};
+
+use rradio_messages::EventFormatV2;
+
+use super::SharedEvent;
+
+/// Encode events using the encoding most recently requested by the client
+fn encode_events(
+    stream: OwnedWriteHalf,
+    event_format: watch::Receiver<EventFormatV2>,
+) -> impl futures_util::Sink<SharedEvent, Error = anyhow::Error> {
+    futures_util::sink::unfold(
+        (stream, event_format),
+        |(mut stream, event_format), event: SharedEvent| async move {
+            let format = *event_format.borrow();
+
+            stream.write_all(&event.frame(format).await?).await?;
+
+            Ok((stream, event_format))
+        },
+    )
+}
+
+pub async fn run(
+    port_channels: super::PortChannels,
+    config: crate::config::tcp::Config,
+) -> anyhow::Result<()> {
+    let minimum_event_interval = config.binary_port_event_interval;
 
-pub async fn run(port_channels: super::PortChannels) -> anyhow::Result<()> {
     super::tcp::run(
         port_channels,
+        config,
         rradio_messages::API_PORT,
-        |stream| rradio_messages::Event::encode_to_stream(stream).sink_err_into(),
+        true,
+        minimum_event_interval,
+        encode_events,
         |stream| {
             rradio_messages::Command::decode_from_stream(tokio::io::BufReader::new(stream))
                 .err_into()
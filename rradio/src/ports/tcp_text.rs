@@ -1,9 +1,25 @@
-use std::fmt::{Debug, Display, Formatter};
+//! A port which displays the player state as text, for terminals and simple displays.
+//!
+//! Clients can choose how values are displayed by sending lines such as `duration seconds` or `clock 12h`
+
+use std::{
+    fmt::{Display, Formatter},
+    time::Duration,
+};
 
 use anyhow::{Context, Result};
 
-use rradio_messages::{Command, Event};
-use tokio::io::{AsyncWrite, AsyncWriteExt};
+use rradio_messages::{
+    Activity, AudioInterruption, ClockFormat, Command, DurationFormat, Event, EventFormat,
+    EventFormatV2, IdleScreen, PingTimes, TextFormat,
+};
+use tokio::{
+    io::{AsyncWrite, AsyncWriteExt},
+    sync::watch,
+};
+
+/// Lines sent by clients are read at most this many bytes at a time, so that clients which never send a newline don't use up memory
+const MAX_LINE_LENGTH: u64 = 256;
 
 fn clear_lines(f: &mut Formatter, row: u16, count: u16) -> std::fmt::Result {
     use crossterm::terminal;
@@ -16,120 +32,379 @@ fn clear_lines(f: &mut Formatter, row: u16, count: u16) -> std::fmt::Result {
     Ok(())
 }
 
-fn display_entry(f: &mut Formatter, label: &str, entry: impl Debug) -> std::fmt::Result {
+/// Labels are padded to this width, so that values line up in a column
+const LABEL_WIDTH: usize = 20;
+
+fn display_entry(f: &mut Formatter, label: &str, entry: impl Display) -> std::fmt::Result {
     use crossterm::terminal::{Clear, ClearType};
     write!(
         f,
-        "{}: {:?}{}\r\n",
-        label,
-        entry,
+        "{label:<LABEL_WIDTH$} {entry}{}\r\n",
         Clear(ClearType::UntilNewLine)
     )
 }
 
-struct DisplayDiff<T>(T);
+/// Displays nothing if the value is `None`
+struct Optional<T>(Option<T>);
+
+impl<T: Display> Display for Optional<T> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match &self.0 {
+            Some(value) => Display::fmt(value, f),
+            None => Ok(()),
+        }
+    }
+}
+
+struct DisplayDuration(Duration, DurationFormat);
+
+impl Display for DisplayDuration {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let Self(duration, format) = *self;
+
+        match format {
+            DurationFormat::Clock => {
+                let seconds = duration.as_secs();
+                let (hours, minutes, seconds) = (seconds / 3600, (seconds / 60) % 60, seconds % 60);
+
+                if hours > 0 {
+                    write!(f, "{hours}:{minutes:02}:{seconds:02}")
+                } else {
+                    write!(f, "{minutes}:{seconds:02}")
+                }
+            }
+            DurationFormat::Seconds => write!(f, "{:.1}s", duration.as_secs_f64()),
+        }
+    }
+}
+
+/// Displays a timestamp as the local time of day
+struct DisplayTime(chrono::DateTime<chrono::Utc>, ClockFormat);
+
+impl Display for DisplayTime {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        use chrono::Timelike;
+
+        let time = self.0.with_timezone(&chrono::Local);
+        let (minute, second) = (time.minute(), time.second());
+
+        match self.1 {
+            ClockFormat::TwentyFourHour => write!(f, "{:02}:{minute:02}:{second:02}", time.hour()),
+            ClockFormat::TwelveHour => {
+                let (is_pm, hour) = time.hour12();
+                let suffix = if is_pm { "PM" } else { "AM" };
+                write!(f, "{hour}:{minute:02}:{second:02} {suffix}")
+            }
+        }
+    }
+}
+
+struct DisplayPingTimes<'a>(&'a PingTimes);
+
+impl Display for DisplayPingTimes<'_> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        fn display_ping(
+            f: &mut Formatter,
+            target: &str,
+            ping: &Result<Duration, rradio_messages::PingError>,
+        ) -> std::fmt::Result {
+            match ping {
+                Ok(ping) => write!(f, "{target} {}ms", ping.as_millis()),
+                Err(err) => write!(f, "{target} {err}"),
+            }
+        }
+
+        match self.0 {
+            PingTimes::None => Ok(()),
+            PingTimes::BadUrl => f.write_str("Bad url"),
+            PingTimes::Gateway(gateway_ping) => display_ping(f, "Gateway", gateway_ping),
+            PingTimes::GatewayAndRemote {
+                gateway_ping,
+                remote_ping,
+                ..
+            } => {
+                display_ping(f, "Gateway", &Ok(*gateway_ping))?;
+                f.write_str(", ")?;
+                display_ping(f, "Remote", remote_ping)
+            }
+            PingTimes::FinishedPingingRemote { gateway_ping } => {
+                display_ping(f, "Gateway", &Ok(*gateway_ping))
+            }
+        }
+    }
+}
+
+struct DisplayIdleScreen<'a>(&'a IdleScreen);
 
-impl<'a> Display for DisplayDiff<&'a rradio_messages::PlayerStateDiff> {
+impl Display for DisplayIdleScreen<'_> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self.0 {
+            // The text port is only updated when the state changes, so can't show the time
+            IdleScreen::Clock => f.write_str("Clock"),
+            IdleScreen::IpAddresses(addresses) => f.write_str(&addresses.join(", ")),
+            IdleScreen::Text(text) => Display::fmt(text, f),
+        }
+    }
+}
+
+fn yes_or_no(value: bool) -> &'static str {
+    if value {
+        "Yes"
+    } else {
+        "No"
+    }
+}
+
+struct DisplayDiff<'a> {
+    diff: &'a rradio_messages::PlayerStateDiff,
+    text_format: TextFormat,
+}
+
+impl DisplayDiff<'_> {
+    fn duration(&self, duration: Option<Duration>) -> Optional<DisplayDuration> {
+        Optional(
+            duration.map(|duration| DisplayDuration(duration, self.text_format.duration_format)),
+        )
+    }
+}
+
+impl Display for DisplayDiff<'_> {
+    #[allow(clippy::too_many_lines)]
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
         use crossterm::cursor::MoveTo;
 
+        let diff = self.diff;
+
         let state_row = 0;
         let state_row_count = 1;
-        if let Some(state) = self.0.pipeline_state {
+        if let Some(state) = diff.pipeline_state {
             Display::fmt(&MoveTo(0, state_row), f)?;
             display_entry(f, "Pipeline State", state)?;
         }
 
         let station_row = state_row + state_row_count;
         let station_row_count = 2;
-        if let Some(current_station) = &self.0.current_station {
+        if let Some(current_station) = &diff.current_station {
             match current_station {
                 rradio_messages::CurrentStation::NoStation => {
                     clear_lines(f, station_row, station_row_count)?;
                 }
-                rradio_messages::CurrentStation::FailedToPlayStation { error } => {
+                rradio_messages::CurrentStation::FailedToPlayStation { error, .. } => {
                     clear_lines(f, station_row, station_row_count)?;
-                    display_entry(f, "Failed to play station", error)?;
+                    Display::fmt(&MoveTo(0, station_row), f)?;
+                    display_entry(f, "Failed to play", error)?;
                 }
                 rradio_messages::CurrentStation::PlayingStation { index, title, .. } => {
                     Display::fmt(&MoveTo(0, station_row), f)?;
-                    display_entry(f, "Station Index", index)?;
-                    display_entry(f, "Station Title", title)?;
+                    display_entry(f, "Station Index", Optional(index.as_ref()))?;
+                    display_entry(f, "Station Title", Optional(title.as_ref()))?;
                 }
             }
         }
 
         let pause_before_playing_row = station_row + station_row_count;
         let pause_before_playing_row_count = 1;
-        if let Some(pause_before_playing) = self.0.pause_before_playing {
+        if let Some(pause_before_playing) = diff.pause_before_playing {
             Display::fmt(&MoveTo(0, pause_before_playing_row), f)?;
-            display_entry(f, "Pause Before Playing", pause_before_playing)?;
+            display_entry(
+                f,
+                "Pause Before Playing",
+                self.duration(pause_before_playing),
+            )?;
         }
 
         let current_track_index_row = pause_before_playing_row + pause_before_playing_row_count;
         let current_track_index_row_count = 1;
-        if let Some(track_index) = self.0.current_track_index {
+        if let Some(track_index) = diff.current_track_index {
             Display::fmt(&MoveTo(0, current_track_index_row), f)?;
             display_entry(f, "Current Track", track_index)?;
         }
 
         let tags_row = current_track_index_row + current_track_index_row_count;
         let tags_row_count = 6;
-        if let Some(current_track_tags) = &self.0.current_track_tags {
+        if let Some(current_track_tags) = &diff.current_track_tags {
             match current_track_tags {
                 None => clear_lines(f, tags_row, tags_row_count)?,
                 Some(tags) => {
                     Display::fmt(&MoveTo(0, tags_row), f)?;
-                    display_entry(f, "Title", &tags.title)?;
-                    display_entry(f, "Organisation", &tags.organisation)?;
-                    display_entry(f, "Artist", &tags.artist)?;
-                    display_entry(f, "Album", &tags.album)?;
-                    display_entry(f, "Genre", &tags.genre)?;
-                    display_entry(f, "Comment", &tags.comment)?;
+                    display_entry(f, "Title", Optional(tags.title.as_ref()))?;
+                    display_entry(f, "Organisation", Optional(tags.organisation.as_ref()))?;
+                    display_entry(f, "Artist", Optional(tags.artist.as_ref()))?;
+                    display_entry(f, "Album", Optional(tags.album.as_ref()))?;
+                    display_entry(f, "Genre", Optional(tags.genre.as_ref()))?;
+                    display_entry(f, "Comment", Optional(tags.comment.as_ref()))?;
                 }
             }
         }
 
         let volume_row = tags_row + tags_row_count;
         let volume_row_count = 1;
-        if let Some(volume) = self.0.volume {
+        if let Some(volume) = diff.volume {
             Display::fmt(&MoveTo(0, volume_row), f)?;
             display_entry(f, "Volume", volume)?;
         }
 
         let is_muted_row = volume_row + volume_row_count;
         let is_muted_row_count = 1;
-        if let Some(is_muted) = self.0.is_muted {
+        if let Some(is_muted) = diff.is_muted {
             Display::fmt(&MoveTo(0, is_muted_row), f)?;
-            display_entry(f, "Muted", is_muted)?;
+            display_entry(f, "Muted", yes_or_no(is_muted))?;
         }
 
         let buffering_row = is_muted_row + is_muted_row_count;
         let buffering_row_count = 1;
-        if let Some(buffering) = self.0.buffering {
+        if let Some(buffering) = diff.buffering {
             Display::fmt(&MoveTo(0, buffering_row), f)?;
-            display_entry(f, "Buffering", buffering)?;
+            display_entry(f, "Buffering", format_args!("{buffering}%"))?;
         }
 
         let track_duration_row = buffering_row + buffering_row_count;
         let track_duration_row_count = 1;
-        if let Some(duration) = self.0.track_duration {
+        if let Some(duration) = diff.track_duration {
             Display::fmt(&MoveTo(0, track_duration_row), f)?;
-            display_entry(f, "Duration", duration)?;
+            display_entry(f, "Duration", self.duration(duration))?;
         }
 
         let track_position_row = track_duration_row + track_duration_row_count;
         let track_position_row_count = 1;
-        if let Some(position) = self.0.track_position {
+        if let Some(position) = diff.track_position {
             Display::fmt(&MoveTo(0, track_position_row), f)?;
-            display_entry(f, "Position", position)?;
+            display_entry(f, "Position", self.duration(position))?;
         }
 
         let ping_time_row = track_position_row + track_position_row_count;
-        // let ping_time_row_count = 1;
-        if let Some(ping_times) = &self.0.ping_times {
+        let ping_time_row_count = 1;
+        if let Some(ping_times) = &diff.ping_times {
             Display::fmt(&MoveTo(0, ping_time_row), f)?;
-            display_entry(f, "Ping Time", ping_times)?;
+            display_entry(f, "Ping Time", DisplayPingTimes(ping_times))?;
+        }
+
+        let latest_error_row = ping_time_row + ping_time_row_count;
+        let latest_error_row_count = 1;
+        if let Some(latest_error) = &diff.latest_error {
+            Display::fmt(&MoveTo(0, latest_error_row), f)?;
+            display_entry(
+                f,
+                "Latest Error",
+                Optional(latest_error.as_ref().map(|latest_error| {
+                    format!(
+                        "{} {}",
+                        DisplayTime(latest_error.timestamp, self.text_format.clock_format),
+                        latest_error.error
+                    )
+                })),
+            )?;
+        }
+
+        let idle_screen_row = latest_error_row + latest_error_row_count;
+        let idle_screen_row_count = 1;
+        if let Some(idle_screen) = &diff.idle_screen {
+            Display::fmt(&MoveTo(0, idle_screen_row), f)?;
+            display_entry(
+                f,
+                "Idle Screen",
+                Optional(idle_screen.as_ref().map(DisplayIdleScreen)),
+            )?;
+        }
+
+        let quiet_hours_row = idle_screen_row + idle_screen_row_count;
+        let quiet_hours_row_count = 1;
+        if let Some(maximum_volume) = diff.quiet_hours_maximum_volume {
+            Display::fmt(&MoveTo(0, quiet_hours_row), f)?;
+            display_entry(
+                f,
+                "Quiet Hours",
+                Optional(
+                    maximum_volume.map(|maximum_volume| format!("Maximum volume {maximum_volume}")),
+                ),
+            )?;
+        }
+
+        let audio_output_row = quiet_hours_row + quiet_hours_row_count;
+        let audio_output_row_count = 1;
+        if let Some(audio_outputs) = &diff.audio_outputs {
+            Display::fmt(&MoveTo(0, audio_output_row), f)?;
+            display_entry(
+                f,
+                "Audio Output",
+                Optional(audio_outputs.as_ref().map(|audio_outputs| {
+                    match audio_outputs.headphones_connected {
+                        Some(true) => format!("{} (headphones connected)", audio_outputs.selected),
+                        Some(false) | None => audio_outputs.selected.to_string(),
+                    }
+                })),
+            )?;
+        }
+
+        let activity_row = audio_output_row + audio_output_row_count;
+        let activity_row_count = 1;
+        if let Some(activity) = diff.activity {
+            Display::fmt(&MoveTo(0, activity_row), f)?;
+            match activity {
+                Activity::Playing => display_entry(f, "Activity", "Playing")?,
+                Activity::Paused => display_entry(f, "Activity", "Paused")?,
+                Activity::Idle { since } => display_entry(
+                    f,
+                    "Activity",
+                    format_args!(
+                        "Idle since {}",
+                        DisplayTime(since, self.text_format.clock_format)
+                    ),
+                )?,
+            }
+        }
+
+        let audio_interruption_row = activity_row + activity_row_count;
+        let audio_interruption_row_count = 1;
+        if let Some(audio_interruption) = diff.audio_interruption {
+            Display::fmt(&MoveTo(0, audio_interruption_row), f)?;
+            display_entry(
+                f,
+                "Interrupted",
+                Optional(
+                    audio_interruption.map(|audio_interruption| match audio_interruption {
+                        AudioInterruption::Paused => "Paused for another application",
+                        AudioInterruption::Ducked => "Ducked for another application",
+                    }),
+                ),
+            )?;
+        }
+
+        let audio_format_row = audio_interruption_row + audio_interruption_row_count;
+        let audio_format_row_count = 1;
+        if let Some(audio_format) = &diff.audio_format {
+            Display::fmt(&MoveTo(0, audio_format_row), f)?;
+            display_entry(
+                f,
+                "Format",
+                Optional(audio_format.as_ref().map(|audio_format| {
+                    format!(
+                        "{} {}-bit {}Hz{}",
+                        audio_format.codec.as_deref().unwrap_or("Unknown codec"),
+                        audio_format.bit_depth,
+                        audio_format.sample_rate,
+                        if audio_format.is_lossless {
+                            " (lossless)"
+                        } else {
+                            ""
+                        }
+                    )
+                })),
+            )?;
+        }
+
+        let intro_scan_row = audio_format_row + audio_format_row_count;
+        let intro_scan_row_count = 1;
+        if let Some(intro_scan) = diff.intro_scan {
+            Display::fmt(&MoveTo(0, intro_scan_row), f)?;
+            display_entry(f, "Intro scan", self.duration(intro_scan))?;
+        }
+
+        let station_locked_row = intro_scan_row + intro_scan_row_count;
+        // let station_locked_row_count = 1;
+        if let Some(station_locked) = diff.station_locked {
+            Display::fmt(&MoveTo(0, station_locked_row), f)?;
+            display_entry(f, "Station locked", yes_or_no(station_locked))?;
         }
 
         Ok(())
@@ -138,16 +413,37 @@ impl<'a> Display for DisplayDiff<&'a rradio_messages::PlayerStateDiff> {
 
 pub fn encode_events<S: AsyncWrite + Unpin>(
     stream: S,
-) -> impl futures_util::Sink<Event, Error = anyhow::Error> {
+    event_format: watch::Receiver<EventFormatV2>,
+) -> impl futures_util::Sink<super::SharedEvent, Error = anyhow::Error> {
     use std::io::Write;
 
     futures_util::sink::unfold(
-        (stream, Vec::new()),
-        |(mut stream, mut buffer), event: Event| async move {
+        (stream, event_format, Vec::new()),
+        |(mut stream, event_format, mut buffer), event: super::SharedEvent| async move {
             buffer.clear();
 
-            match event {
-                Event::PlayerStateChanged(diff) => write!(buffer, "{}", DisplayDiff(&diff)),
+            let text_format = event_format.borrow().format.text_format;
+
+            match event.event() {
+                Event::PlayerStateChanged(diff) => {
+                    write!(buffer, "{}", DisplayDiff { diff, text_format })
+                }
+                Event::SeekPreview(_)
+                | Event::PlaylistDocument(_)
+                | Event::StationLoadProgress(_)
+                | Event::BootProgress(_)
+                | Event::UPnPBrowseResult(_)
+                | Event::Hello(_)
+                | Event::AuditLog(_)
+                | Event::PlayStatistics(_)
+                | Event::UpdateProgress(_)
+                | Event::BatchResult(_)
+                | Event::StationsSynced(_)
+                | Event::ShutdownTimedOut(_)
+                | Event::SearchResults(_)
+                | Event::CommandRejected(_)
+                | Event::VolumeAtLimit
+                | Event::SelfTestResult(_) => Ok(()),
             }
             .context("Failed to encode event")?;
 
@@ -156,24 +452,137 @@ pub fn encode_events<S: AsyncWrite + Unpin>(
                 .await
                 .context("Failed to write event")?;
 
-            Ok((stream, buffer))
+            Ok((stream, event_format, buffer))
         },
     )
 }
 
+/// Change `text_format` as requested by `line`, such as `duration seconds` or `clock 12h`
+fn set_text_format_option(text_format: &mut TextFormat, line: &str) -> Result<()> {
+    let mut words = line.split_whitespace();
+
+    match (words.next(), words.next(), words.next()) {
+        (Some("duration"), Some("clock"), None) => {
+            text_format.duration_format = DurationFormat::Clock;
+        }
+        (Some("duration"), Some("seconds"), None) => {
+            text_format.duration_format = DurationFormat::Seconds;
+        }
+        (Some("clock"), Some("24h"), None) => {
+            text_format.clock_format = ClockFormat::TwentyFourHour;
+        }
+        (Some("clock"), Some("12h"), None) => text_format.clock_format = ClockFormat::TwelveHour,
+        _ => anyhow::bail!("Unknown text format option {line:?}"),
+    }
+
+    Ok(())
+}
+
+/// Clients only send text format options, which are sent as [`Command::SetEventFormat`] so that they apply to this connection
 fn decode_commands(
     stream: tokio::net::tcp::OwnedReadHalf,
 ) -> impl futures_util::Stream<Item = Result<Command>> {
-    futures_util::stream::try_unfold(stream, |mut stream| async move {
-        use tokio::io::AsyncReadExt;
+    futures_util::stream::try_unfold(
+        (
+            tokio::io::BufReader::new(stream),
+            TextFormat::default(),
+            Vec::new(),
+        ),
+        |(mut stream, mut text_format, mut line)| async move {
+            use tokio::io::{AsyncBufReadExt, AsyncReadExt};
+
+            loop {
+                line.clear();
+
+                if (&mut stream)
+                    .take(MAX_LINE_LENGTH)
+                    .read_until(b'\n', &mut line)
+                    .await?
+                    == 0
+                {
+                    return Ok(None);
+                }
+
+                let result = {
+                    // Serial terminals might send "\r\n"
+                    let text = String::from_utf8_lossy(&line);
+                    let text = text.trim();
 
-        let mut buffer = [0; 64];
-        while stream.read(&mut buffer).await? > 0 {}
+                    if text.is_empty() {
+                        continue;
+                    }
 
-        Ok(None)
-    })
+                    set_text_format_option(&mut text_format, text)
+                };
+
+                match result {
+                    Ok(()) => {
+                        let command = Command::SetEventFormat(EventFormat {
+                            text_format,
+                            ..EventFormat::default()
+                        });
+
+                        return Ok(Some((command, (stream, text_format, line))));
+                    }
+                    Err(err) => tracing::warn!("{err:#}"),
+                }
+            }
+        },
+    )
 }
 
-pub async fn run(port_channels: super::PortChannels) -> anyhow::Result<()> {
-    super::tcp::run(port_channels, 8001, encode_events, decode_commands).await
+pub async fn run(
+    port_channels: super::PortChannels,
+    config: crate::config::tcp::Config,
+) -> anyhow::Result<()> {
+    let minimum_event_interval = config.text_port_event_interval;
+
+    super::tcp::run(
+        port_channels,
+        config,
+        8001,
+        false, // Serial and LCD bridges would show the backoff hint, so it's only sent on the binary port
+        minimum_event_interval,
+        encode_events,
+        decode_commands,
+    )
+    .await
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use rradio_messages::{ClockFormat, DurationFormat, TextFormat};
+
+    use super::DisplayDuration;
+
+    #[test]
+    fn durations_are_formatted() {
+        for (seconds, format, expected) in [
+            (187.5, DurationFormat::Clock, "3:07"),
+            (3787.0, DurationFormat::Clock, "1:03:07"),
+            (5.0, DurationFormat::Clock, "0:05"),
+            (187.5, DurationFormat::Seconds, "187.5s"),
+        ] {
+            assert_eq!(
+                DisplayDuration(Duration::from_secs_f64(seconds), format).to_string(),
+                expected
+            );
+        }
+    }
+
+    #[test]
+    fn text_format_options() {
+        let mut text_format = TextFormat::default();
+
+        super::set_text_format_option(&mut text_format, "duration seconds").unwrap();
+        super::set_text_format_option(&mut text_format, "clock  12h").unwrap();
+
+        assert_eq!(text_format.duration_format, DurationFormat::Seconds);
+        assert_eq!(text_format.clock_format, ClockFormat::TwelveHour);
+
+        assert!(super::set_text_format_option(&mut text_format, "clock").is_err());
+        assert!(super::set_text_format_option(&mut text_format, "volume 50").is_err());
+    }
 }
@@ -0,0 +1,201 @@
+//! A simple intents API for voice assistants such as Rhasspy or openHAB, which know station names rather than station indices
+//!
+//! Intents are posted as JSON, e.g. `{"intent":"play_station","slots":{"name":"Radio 4"}}`
+
+use std::sync::Arc;
+
+use axum::{extract::State, http::StatusCode};
+
+use rradio_messages::Command;
+
+use crate::{
+    config::Config,
+    station::{normalise_name, StationListing},
+};
+
+#[derive(Debug, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum IntentName {
+    PlayStation,
+    PlayPause,
+    NextTrack,
+    PreviousTrack,
+    VolumeUp,
+    VolumeDown,
+    SetVolume,
+    Mute,
+    Unmute,
+    Stop,
+}
+
+#[derive(Debug, Default, serde::Deserialize)]
+#[serde(default)]
+pub struct Slots {
+    name: Option<String>,
+    volume: Option<i32>,
+}
+
+#[derive(Debug, serde::Deserialize)]
+pub struct Intent {
+    intent: IntentName,
+    #[serde(default)]
+    slots: Slots,
+}
+
+type Response = Result<String, (StatusCode, String)>;
+
+fn missing_slot(name: &str) -> (StatusCode, String) {
+    (StatusCode::BAD_REQUEST, format!("Missing slot {name:?}"))
+}
+
+fn levenshtein_distance(a: &str, b: &str) -> usize {
+    let b = b.chars().collect::<Vec<_>>();
+
+    let mut previous_row = (0..=b.len()).collect::<Vec<_>>();
+    let mut current_row = vec![0; b.len() + 1];
+
+    for (i, a) in a.chars().enumerate() {
+        current_row[0] = i + 1;
+
+        for (j, &b) in b.iter().enumerate() {
+            let substitution_cost = usize::from(a != b);
+
+            current_row[j + 1] = (previous_row[j] + substitution_cost)
+                .min(previous_row[j + 1] + 1)
+                .min(current_row[j] + 1);
+        }
+
+        std::mem::swap(&mut previous_row, &mut current_row);
+    }
+
+    previous_row[b.len()]
+}
+
+/// Find the station whose name best matches `query`.
+///
+/// Exact matches are preferred, then stations whose name contains the query, then the closest match by edit distance,
+/// as long as fewer than half of the characters need changing.
+fn find_station<'a>(stations: &'a [StationListing], query: &str) -> Option<&'a StationListing> {
+    let query = normalise_name(query);
+
+    if query.is_empty() {
+        return None;
+    }
+
+    stations
+        .iter()
+        .filter_map(|station| {
+            let name = normalise_name(&station.name);
+
+            let rank = if name == query {
+                0
+            } else if name.contains(&query) {
+                1
+            } else {
+                2
+            };
+
+            let distance = levenshtein_distance(&query, &name);
+
+            (rank < 2 || 2 * distance < query.chars().count().max(name.chars().count()))
+                .then_some(((rank, distance), station))
+        })
+        .min_by_key(|&(key, _)| key)
+        .map(|(_, station)| station)
+}
+
+pub async fn handle_intent(
+    State(port_channels): State<super::super::PortChannels>,
+    State(config): State<Arc<Config>>,
+    axum::Json(Intent { intent, slots }): axum::Json<Intent>,
+) -> Response {
+    tracing::debug!(?intent, ?slots, "Intent");
+
+    let (command, response) = match intent {
+        IntentName::PlayStation => {
+            let name = slots.name.ok_or_else(|| missing_slot("name"))?;
+
+            let stations = super::list_stations(config.clone()).await?;
+
+            // Configured aliases are checked before station names
+            let station = crate::station::find_alias(&config.station_aliases, &[], &name)
+                .ok()
+                .flatten()
+                .and_then(|index| stations.iter().find(|station| station.index == index))
+                .or_else(|| find_station(&stations, &name))
+                .ok_or_else(|| {
+                    (
+                        StatusCode::NOT_FOUND,
+                        format!("No station matches {name:?}"),
+                    )
+                })?;
+
+            (
+                Command::SetChannel(station.index.clone()),
+                format!("Playing {}", station.name),
+            )
+        }
+        IntentName::PlayPause => (Command::PlayPause, String::from("Play/Pause")),
+        IntentName::NextTrack => (Command::NextItem, String::from("Next track")),
+        IntentName::PreviousTrack => (Command::PreviousItem, String::from("Previous track")),
+        IntentName::VolumeUp => (Command::VolumeUp, String::from("Volume up")),
+        IntentName::VolumeDown => (Command::VolumeDown, String::from("Volume down")),
+        IntentName::SetVolume => {
+            let volume = slots.volume.ok_or_else(|| missing_slot("volume"))?;
+
+            (Command::SetVolume(volume), format!("Volume {volume}"))
+        }
+        IntentName::Mute => (Command::SetIsMuted(true), String::from("Muted")),
+        IntentName::Unmute => (Command::SetIsMuted(false), String::from("Unmuted")),
+        IntentName::Stop => (Command::Eject, String::from("Stopped")),
+    };
+
+    port_channels
+        .commands_tx
+        .send(command)
+        .map_err(|err| (super::command_send_error_status(&err), err.to_string()))?;
+
+    Ok(response)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn stations(names: &[&str]) -> Vec<StationListing> {
+        names
+            .iter()
+            .enumerate()
+            .map(|(index, name)| StationListing {
+                index: rradio_messages::StationIndex::new(format!("{index:02}").into()),
+                name: String::from(*name),
+                is_temporary: false,
+            })
+            .collect()
+    }
+
+    fn find<'a>(stations: &'a [StationListing], query: &str) -> Option<&'a str> {
+        find_station(stations, query).map(|station| station.name.as_str())
+    }
+
+    #[test]
+    fn station_matching() {
+        let stations = stations(&[
+            "BBC Radio 4",
+            "BBC Radio 4 Extra",
+            "Classic FM",
+            "Jazz",
+            "Ретро",
+        ]);
+
+        assert_eq!(find(&stations, "bbc radio-4"), Some("BBC Radio 4"));
+        assert_eq!(find(&stations, "radio 4 extra"), Some("BBC Radio 4 Extra"));
+        assert_eq!(find(&stations, "classical fm"), Some("Classic FM"));
+        assert_eq!(find(&stations, "jaz"), Some("Jazz"));
+        assert_eq!(find(&stations, "ретра"), Some("Ретро"));
+        // Three of the five characters need changing, which is too many, even though it's fewer than half of the bytes
+        assert_eq!(find(&stations, "радио"), None);
+        assert_eq!(find(&stations, "heavy metal"), None);
+        assert_eq!(find(&stations, ""), None);
+    }
+}
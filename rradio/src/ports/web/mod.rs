@@ -0,0 +1,546 @@
+use std::sync::Arc;
+
+use anyhow::Context;
+use axum::{
+    extract::{FromRef, State},
+    response::IntoResponse,
+    routing::{get, get_service, post},
+};
+use futures_util::{SinkExt, StreamExt, TryStreamExt};
+use tower::ServiceExt;
+
+use rradio_messages::arcstr;
+
+use crate::{
+    config::Config,
+    task::{FailableFuture, ShutdownSignal, WaitGroupHandle},
+};
+
+mod intents;
+mod public_status;
+#[cfg(feature = "web-tls")]
+mod tls;
+
+fn websocket_protocol() -> &'static str {
+    rradio_messages::API_VERSION_HEADER.trim()
+}
+
+enum WebSocketUpgradeRejection {
+    BadRequest(axum::extract::ws::rejection::WebSocketUpgradeRejection),
+    NoProtocol,
+    BadProtocol(axum::http::HeaderValue),
+}
+
+impl axum::response::IntoResponse for WebSocketUpgradeRejection {
+    fn into_response(self) -> axum::response::Response {
+        let code = axum::http::StatusCode::BAD_REQUEST;
+        match self {
+            WebSocketUpgradeRejection::BadRequest(rejection) => rejection.into_response(),
+            WebSocketUpgradeRejection::NoProtocol => {
+                (code, "websocket protocol not specified").into_response()
+            }
+            WebSocketUpgradeRejection::BadProtocol(protocol) => (
+                code,
+                format!(
+                    "expected protocol {:?}, got protocol {:?}",
+                    websocket_protocol(),
+                    protocol
+                ),
+            )
+                .into_response(),
+        }
+    }
+}
+
+struct WebSocketUpgrade(axum::extract::WebSocketUpgrade);
+
+impl WebSocketUpgrade {
+    fn on_upgrade<F, Fut>(self, callback: F) -> axum::response::Response
+    where
+        F: FnOnce(axum::extract::ws::WebSocket) -> Fut + Send + 'static,
+        Fut: std::future::Future<Output = ()> + Send + 'static,
+    {
+        self.0.on_upgrade(callback)
+    }
+}
+
+#[axum::async_trait]
+impl<S: Send + Sync> axum::extract::FromRequest<S> for WebSocketUpgrade {
+    type Rejection = WebSocketUpgradeRejection;
+
+    async fn from_request(
+        req: axum::http::Request<axum::body::Body>,
+        state: &S,
+    ) -> Result<Self, Self::Rejection> {
+        let protocol = req
+            .headers()
+            .get(axum::http::header::SEC_WEBSOCKET_PROTOCOL)
+            .ok_or(WebSocketUpgradeRejection::NoProtocol)?
+            .clone();
+
+        let protocol_str = String::from(
+            protocol
+                .to_str()
+                .map_err(|_| WebSocketUpgradeRejection::BadProtocol(protocol.clone()))?,
+        );
+
+        if protocol_str == websocket_protocol() {
+            Ok(Self(
+                axum::extract::WebSocketUpgrade::from_request(req, state)
+                    .await
+                    .map_err(WebSocketUpgradeRejection::BadRequest)?
+                    .protocols([websocket_protocol()]),
+            ))
+        } else {
+            return Err(WebSocketUpgradeRejection::BadProtocol(protocol));
+        }
+    }
+}
+
+#[allow(clippy::unused_async)]
+async fn handle_websocket_connection(
+    port_channels: super::PortChannels,
+    wait_handle: crate::task::WaitGroupHandle,
+    event_interval: Option<std::time::Duration>,
+    websocket: axum::extract::ws::WebSocket,
+) -> anyhow::Result<()> {
+    tracing::debug!("Connection Upgraded");
+
+    let (websocket_tx, websocket_rx) = websocket.split();
+
+    // Convert the websocket sink (i.e. of websocket [axum::extract::ws::Message]) into a sink of [`super::SharedEvent`]
+    let websocket_tx = websocket_tx
+        .sink_map_err(|err| anyhow::Error::msg(err).context("Failed to send websocket message"))
+        .with(|event: super::SharedEvent| async move {
+            let frame = event.postcard_frame()?;
+
+            Ok::<_, anyhow::Error>(axum::extract::ws::Message::Binary(frame.to_vec()))
+        });
+
+    let websocket_rx = websocket_rx
+        .map_err(|err| anyhow::Error::msg(err).context("Failed to recieve websocket message"));
+
+    let (shutdown_handle, shutdown_signal) = ShutdownSignal::new();
+
+    let (commands_tx, replies_rx) = super::CommandSink::new(port_channels.commands_tx.clone());
+
+    // Replies, such as rejections, are only sent to this connection
+    let events_rx = futures_util::stream::select(
+        port_channels.event_stream(event_interval),
+        futures_util::stream::unfold(replies_rx, |mut replies_rx| async move {
+            let reply = replies_rx.recv().await?;
+            Some((reply, replies_rx))
+        }),
+    );
+
+    // Handle incoming websocket messages
+    wait_handle.spawn_task(
+        "forward_commands",
+        tracing::error_span!("forward_commands"),
+        async move {
+            websocket_rx
+                .try_filter_map(|message| async move {
+                    anyhow::Ok(match message {
+                        axum::extract::ws::Message::Text(text) => {
+                            tracing::debug!("Ignoring text message: {:?}", text);
+                            None
+                        }
+                        axum::extract::ws::Message::Binary(mut buffer) => {
+                            Some(rradio_messages::Command::decode(&mut buffer)?)
+                        }
+                        axum::extract::ws::Message::Ping(_) => {
+                            tracing::debug!("Ignoring ping messages");
+                            None
+                        }
+                        axum::extract::ws::Message::Pong(_) => {
+                            tracing::debug!("Ignoring pong messages");
+                            None
+                        }
+                        axum::extract::ws::Message::Close(_) => {
+                            tracing::debug!("Close message received");
+                            None
+                        }
+                    })
+                })
+                .forward(commands_tx)
+                .await?;
+
+            tracing::debug!("Shutting down");
+
+            shutdown_handle.signal_shutdown();
+
+            Ok(())
+        },
+    );
+
+    wait_handle.spawn_task(
+        "forward_events",
+        tracing::error_span!("forward_events"),
+        async move {
+            events_rx
+                .map(Ok)
+                .take_until(shutdown_signal) // Stop when the websocket is closed
+                .forward(websocket_tx) // Send each event to the websocket
+                .await?;
+
+            tracing::debug!("Closing connection");
+
+            Ok(())
+        },
+    );
+
+    Ok(())
+}
+
+#[derive(Clone, FromRef)]
+struct AppState {
+    span: tracing::Span,
+    port_channels: super::PortChannels,
+    wait_handle: WaitGroupHandle,
+    config: Arc<Config>,
+}
+
+fn command_send_error_status(error: &super::CommandSendError) -> axum::http::StatusCode {
+    match error {
+        super::CommandSendError::QueueFull => axum::http::StatusCode::SERVICE_UNAVAILABLE,
+        super::CommandSendError::Closed => axum::http::StatusCode::INTERNAL_SERVER_ERROR,
+    }
+}
+
+/// List the stations on a blocking thread, as every station file is read
+async fn list_stations(
+    config: Arc<Config>,
+) -> Result<Vec<crate::station::StationListing>, (axum::http::StatusCode, String)> {
+    let internal_server_error = |err: String| (axum::http::StatusCode::INTERNAL_SERVER_ERROR, err);
+
+    tokio::task::spawn_blocking(move || crate::station::list(&config))
+        .await
+        .map_err(|err| internal_server_error(err.to_string()))?
+        .map_err(|err| internal_server_error(err.to_string()))
+}
+
+async fn handle_post_command(
+    port_channels: State<super::PortChannels>,
+    axum::Json(command): axum::Json<rradio_messages::Command>,
+) -> impl IntoResponse {
+    port_channels
+        .commands_tx
+        .send(command)
+        .map_err(|err| (command_send_error_status(&err), err.to_string()))
+}
+
+#[derive(serde::Serialize)]
+struct Diagnostics {
+    #[serde(flatten)]
+    dropped_messages: super::DroppedMessageCounts,
+    #[serde(flatten)]
+    pipeline: super::PipelineDiagnostics,
+}
+
+async fn handle_get_diagnostics(
+    port_channels: State<super::PortChannels>,
+) -> axum::Json<Diagnostics> {
+    axum::Json(Diagnostics {
+        dropped_messages: port_channels.dropped_messages.counts(),
+        pipeline: port_channels.pipeline_diagnostics_rx.borrow().clone(),
+    })
+}
+
+/// The entire player state, in the same format as a full `PlayerStateChanged` event, so that clients which poll don't need to keep a websocket open
+async fn handle_get_state(
+    State(port_channels): State<super::PortChannels>,
+) -> axum::Json<rradio_messages::PlayerStateDiff> {
+    axum::Json(super::player_state_to_diff(
+        port_channels.player_state_rx.borrow().state(),
+    ))
+}
+
+#[derive(serde::Serialize)]
+struct StationSummary {
+    index: rradio_messages::StationIndex,
+    name: String,
+    /// Whether the station was created from a playlist pushed by a client. Temporary stations aren't checked
+    is_temporary: bool,
+    /// Whether the station was reachable when it was last checked, or `None` if it hasn't been checked
+    last_known_good: Option<bool>,
+    last_checked: Option<chrono::DateTime<chrono::Utc>>,
+    /// When the station was last reachable
+    last_good: Option<chrono::DateTime<chrono::Utc>>,
+    /// Why the station failed the latest check
+    error: Option<rradio_messages::ArcStr>,
+}
+
+async fn handle_get_stations(
+    State(config): State<Arc<Config>>,
+) -> Result<axum::Json<Vec<StationSummary>>, (axum::http::StatusCode, String)> {
+    let state_directory = config.state_directory.clone();
+
+    let stations = list_stations(config).await?;
+
+    let mut checks =
+        tokio::task::spawn_blocking(move || crate::station_check::load(state_directory.as_str()))
+            .await
+            .map_err(|err| {
+                (
+                    axum::http::StatusCode::INTERNAL_SERVER_ERROR,
+                    err.to_string(),
+                )
+            })?;
+
+    Ok(axum::Json(
+        stations
+            .into_iter()
+            .map(|station| {
+                let check = checks.remove(&station.index);
+
+                StationSummary {
+                    index: station.index,
+                    name: station.name,
+                    is_temporary: station.is_temporary,
+                    last_known_good: check
+                        .as_ref()
+                        .map(crate::station_check::StationCheck::is_good),
+                    last_checked: check.as_ref().map(|check| check.checked_at),
+                    last_good: check.as_ref().and_then(|check| check.last_good),
+                    error: check.and_then(|check| check.error),
+                }
+            })
+            .collect(),
+    ))
+}
+
+#[derive(serde::Serialize)]
+struct MessageCatalogueEntry {
+    id: rradio_messages::MessageId,
+    /// The text of the message in English, with the parameters in braces
+    english: &'static str,
+    parameters: &'static [&'static str],
+}
+
+/// The message catalogue, so that clients can translate the messages in events
+async fn handle_get_messages() -> axum::Json<Vec<MessageCatalogueEntry>> {
+    axum::Json(
+        rradio_messages::MessageId::ALL
+            .iter()
+            .map(|&id| MessageCatalogueEntry {
+                id,
+                english: id.english_template(),
+                parameters: id.parameter_names(),
+            })
+            .collect(),
+    )
+}
+
+async fn handle_api(
+    State(span): State<tracing::Span>,
+    State(port_channels): State<super::PortChannels>,
+    State(wait_handle): State<WaitGroupHandle>,
+    State(config): State<Arc<Config>>,
+    upgrade: WebSocketUpgrade,
+) -> impl IntoResponse {
+    let event_interval = config.web_config.event_interval;
+
+    upgrade.on_upgrade(move |websocket| {
+        handle_websocket_connection(port_channels, wait_handle, event_interval, websocket)
+            .log_error(tracing::error_span!(parent: &span, "websocket_connection"))
+    })
+}
+
+enum Never {}
+
+/// Serve HTTP over `socket`, which is either a TCP stream or, if TLS is configured, a TLS stream, until the client disconnects or rradio shuts down
+async fn serve_connection<S>(
+    socket: S,
+    app: axum::Router,
+    shutdown_signal: futures_util::future::Shared<ShutdownSignal>,
+) -> anyhow::Result<()>
+where
+    S: tokio::io::AsyncRead + tokio::io::AsyncWrite + Unpin + Send + 'static,
+{
+    match futures_util::future::select(
+        shutdown_signal,
+        hyper::server::conn::http1::Builder::new()
+            .serve_connection(
+                hyper_util::rt::TokioIo::new(socket),
+                hyper::service::service_fn(move |request| app.clone().oneshot(request)),
+            )
+            .with_upgrades(),
+    )
+    .await
+    {
+        futures_util::future::Either::Left(((), mut connection)) => {
+            tracing::debug!("Shutting down");
+
+            // Gracefully shutdown
+            std::pin::Pin::new(&mut connection).graceful_shutdown();
+
+            // Wait for shutdown to finish
+            connection.await
+        }
+        futures_util::future::Either::Right((result, _)) => result,
+    }
+    .map_err(|err| anyhow::anyhow!("Failed to serve connection: {err}"))
+}
+
+async fn do_run(
+    port_channels: super::PortChannels,
+    config: Arc<Config>,
+    wait_group: &crate::task::WaitGroup,
+) -> anyhow::Result<Never> {
+    let shutdown_signal = port_channels.shutdown_signal.clone();
+
+    let port = if cfg!(feature = "production-server") {
+        80
+    } else {
+        8000
+    };
+
+    let app = axum::Router::new()
+        .fallback_service(get_service(
+            tower_http::services::ServeDir::new(config.web_config.web_app_path.as_str())
+                .not_found_service(axum::routing::any(|uri: axum::http::Uri| async move {
+                    format!("{uri} not found")
+                })),
+        ))
+        .route("/command", post(handle_post_command))
+        .route("/intent", post(intents::handle_intent))
+        .route("/diagnostics", get(handle_get_diagnostics))
+        .route("/state", get(handle_get_state))
+        .route("/stations", get(handle_get_stations))
+        .route("/messages", get(handle_get_messages))
+        .route("/api", get(handle_api));
+
+    let app = if config.web_config.public_status_fields.is_empty() {
+        app
+    } else {
+        app.route("/status", get(public_status::handle_get_status_page))
+            .route("/status.json", get(public_status::handle_get_status))
+    };
+
+    #[cfg(feature = "web-tls")]
+    let tls_acceptor = config
+        .web_config
+        .tls
+        .as_ref()
+        .map(tls::acceptor)
+        .transpose()
+        .context("Failed to load TLS certificate")?;
+
+    #[cfg(not(feature = "web-tls"))]
+    if config.web_config.tls.is_some() {
+        anyhow::bail!("Serving the web interface over TLS requires the \"web-tls\" feature");
+    }
+
+    let listener = super::listener::bind(config.tcp_config.address_family, port)?;
+
+    let server_addr = listener.local_addr()?;
+
+    tracing::info!(%server_addr, "Listening");
+
+    port_channels.boot_milestones.port_listening();
+
+    let mut throttle = super::listener::AcceptThrottle::new(config.tcp_config.clone());
+
+    loop {
+        throttle.ready().await;
+
+        let (socket, remote_address) = listener
+            .accept()
+            .await
+            .context("Failed to accept connection")?;
+
+        // Web clients aren't sent a backoff hint, but the throttle still spaces out connections
+        throttle.accepted();
+
+        let remote_address = super::listener::canonical_remote_address(remote_address);
+
+        let shutdown_signal = shutdown_signal.clone();
+
+        let mut port_channels = port_channels.clone();
+        port_channels.commands_tx = port_channels
+            .commands_tx
+            .with_source(arcstr::format!("web {remote_address}"))
+            .with_privilege(config.tcp_config.is_privileged(remote_address.ip()));
+        let wait_handle = wait_group.clone_handle();
+        let config = config.clone();
+
+        let app = app.clone();
+
+        #[cfg(feature = "web-tls")]
+        let tls_acceptor = tls_acceptor.clone();
+
+        wait_group.spawn_task(
+            "connection",
+            tracing::error_span!("connection", %remote_address),
+            async move {
+                tracing::debug!("Connection");
+
+                let app = app.with_state(AppState {
+                    span: tracing::Span::current(),
+                    port_channels,
+                    wait_handle,
+                    config,
+                });
+
+                #[cfg(feature = "web-tls")]
+                if let Some(tls_acceptor) = tls_acceptor {
+                    let socket = match futures_util::future::select(
+                        shutdown_signal.clone(),
+                        std::pin::pin!(tokio::time::timeout(
+                            tls::HANDSHAKE_TIMEOUT,
+                            tls_acceptor.accept(socket),
+                        )),
+                    )
+                    .await
+                    {
+                        futures_util::future::Either::Left(((), _)) => return Ok(()),
+                        futures_util::future::Either::Right((Ok(Ok(socket)), _)) => socket,
+                        futures_util::future::Either::Right((Ok(Err(err)), _)) => {
+                            // Clients which don't speak TLS, such as port scanners, aren't worth an error
+                            tracing::debug!("TLS handshake failed: {err}");
+                            return Ok(());
+                        }
+                        futures_util::future::Either::Right((Err(_), _)) => {
+                            tracing::debug!("TLS handshake timed out");
+                            return Ok(());
+                        }
+                    };
+
+                    serve_connection(socket, app, shutdown_signal).await?;
+
+                    tracing::debug!("Disconnection");
+
+                    return Ok(());
+                }
+
+                serve_connection(socket, app, shutdown_signal).await?;
+
+                tracing::debug!("Disconnection");
+
+                Ok(())
+            },
+        );
+    }
+}
+
+pub async fn run(port_channels: super::PortChannels, config: Config) -> anyhow::Result<()> {
+    let wait_group = crate::task::WaitGroup::new();
+
+    match futures_util::future::select(
+        port_channels.shutdown_signal.clone(),
+        std::pin::pin!(do_run(port_channels, Arc::new(config), &wait_group)),
+    )
+    .await
+    {
+        futures_util::future::Either::Left(((), _)) => (),
+        futures_util::future::Either::Right((result, _)) => match result? {},
+    }
+
+    tracing::debug!("Shutting down");
+
+    wait_group.wait().await;
+
+    tracing::debug!("Shut down");
+
+    Ok(())
+}
@@ -0,0 +1,167 @@
+//! A read-only status page, e.g. for embedding in a family dashboard, which only shows the fields of the player state selected in the config.
+//! Track lists, errors, and file paths are never shown
+
+use std::{fmt::Write, sync::Arc};
+
+use axum::extract::State;
+
+use rradio_messages::{ArcStr, CurrentStation};
+
+use crate::{config::web::StatusField, pipeline::PlayerState};
+
+/// The fields which aren't selected are omitted. Fields which are selected but unknown, such as the title of a track without tags, are `null`
+#[derive(Debug, Default, serde::Serialize)]
+pub struct PublicStatus {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    title: Option<Option<ArcStr>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    artist: Option<Option<ArcStr>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    station: Option<Option<ArcStr>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    volume: Option<i32>,
+}
+
+impl PublicStatus {
+    fn new(state: &PlayerState, fields: &[StatusField]) -> Self {
+        let current_track = match state.current_station.as_ref() {
+            CurrentStation::PlayingStation {
+                tracks: Some(tracks),
+                ..
+            } => tracks.get(state.current_track_index),
+            CurrentStation::NoStation
+            | CurrentStation::FailedToPlayStation { .. }
+            | CurrentStation::PlayingStation { tracks: None, .. } => None,
+        };
+
+        let tags = state.current_track_tags.as_ref().as_ref();
+
+        let mut status = Self::default();
+
+        for field in fields {
+            match field {
+                StatusField::Title => {
+                    status.title = Some(
+                        tags.and_then(|tags| tags.title.clone())
+                            .or_else(|| current_track.and_then(|track| track.title.clone())),
+                    );
+                }
+                StatusField::Artist => {
+                    status.artist = Some(
+                        tags.and_then(|tags| tags.artist.clone())
+                            .or_else(|| current_track.and_then(|track| track.artist.clone())),
+                    );
+                }
+                StatusField::Station => {
+                    status.station = Some(match state.current_station.as_ref() {
+                        CurrentStation::PlayingStation { index, title, .. } => title
+                            .clone()
+                            .or_else(|| index.as_ref().map(|index| index.as_str().into())),
+                        CurrentStation::NoStation | CurrentStation::FailedToPlayStation { .. } => {
+                            None
+                        }
+                    });
+                }
+                StatusField::Volume => status.volume = Some(state.volume),
+            }
+        }
+
+        status
+    }
+
+    fn html(&self) -> String {
+        let mut rows = String::new();
+
+        let text_rows = [
+            ("Title", &self.title),
+            ("Artist", &self.artist),
+            ("Station", &self.station),
+        ];
+
+        for (name, value) in text_rows {
+            if let Some(value) = value {
+                let value = value.as_ref().map_or("", ArcStr::as_str);
+                writeln!(
+                    rows,
+                    "<tr><th>{name}</th><td>{}</td></tr>",
+                    escape_html(value)
+                )
+                .ok();
+            }
+        }
+
+        if let Some(volume) = self.volume {
+            writeln!(rows, "<tr><th>Volume</th><td>{volume}</td></tr>").ok();
+        }
+
+        format!(
+            r#"<!DOCTYPE html>
+<html>
+<head>
+<meta charset="utf-8">
+<meta http-equiv="refresh" content="10">
+<title>rradio</title>
+</head>
+<body>
+<table>
+{rows}</table>
+</body>
+</html>
+"#
+        )
+    }
+}
+
+fn escape_html(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            c => escaped.push(c),
+        }
+    }
+
+    escaped
+}
+
+fn current_status(
+    port_channels: &super::super::PortChannels,
+    config: &crate::config::Config,
+) -> PublicStatus {
+    PublicStatus::new(
+        port_channels.player_state_rx.borrow().state(),
+        &config.web_config.public_status_fields,
+    )
+}
+
+pub async fn handle_get_status(
+    State(port_channels): State<super::super::PortChannels>,
+    State(config): State<Arc<crate::config::Config>>,
+) -> axum::Json<PublicStatus> {
+    axum::Json(current_status(&port_channels, &config))
+}
+
+pub async fn handle_get_status_page(
+    State(port_channels): State<super::super::PortChannels>,
+    State(config): State<Arc<crate::config::Config>>,
+) -> axum::response::Html<String> {
+    axum::response::Html(current_status(&port_channels, &config).html())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::escape_html;
+
+    #[test]
+    fn html_is_escaped() {
+        assert_eq!(
+            escape_html(r#"Tom & Jerry's <"Greatest"> Hits"#),
+            "Tom &amp; Jerry&#39;s &lt;&quot;Greatest&quot;&gt; Hits"
+        );
+    }
+}
@@ -0,0 +1,64 @@
+//! Serving the web interface over HTTPS, so that it can be exposed beyond the LAN without a reverse proxy
+
+use std::{fs::File, io::BufReader, sync::Arc, time::Duration};
+
+use anyhow::{Context, Result};
+use tokio_rustls::{rustls, TlsAcceptor};
+
+use crate::config::web::Tls;
+
+/// How long clients have to complete the TLS handshake, so that clients which connect and never finish the handshake don't hold a connection until shutdown
+pub const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+fn open(path: &str) -> Result<BufReader<File>> {
+    Ok(BufReader::new(
+        File::open(path).with_context(|| format!("Failed to open {path}"))?,
+    ))
+}
+
+/// The certificate chain, starting with the server's certificate
+fn load_certificates(path: &str) -> Result<Vec<rustls::Certificate>> {
+    let certificates = rustls_pemfile::certs(&mut open(path)?)
+        .with_context(|| format!("Failed to read certificates from {path}"))?;
+
+    anyhow::ensure!(!certificates.is_empty(), "No certificates in {path}");
+
+    Ok(certificates.into_iter().map(rustls::Certificate).collect())
+}
+
+/// The first private key in the file, which may also contain certificates
+fn load_private_key(path: &str) -> Result<rustls::PrivateKey> {
+    let mut reader = open(path)?;
+
+    loop {
+        match rustls_pemfile::read_one(&mut reader)
+            .with_context(|| format!("Failed to read private key from {path}"))?
+        {
+            Some(
+                rustls_pemfile::Item::PKCS8Key(key)
+                | rustls_pemfile::Item::RSAKey(key)
+                | rustls_pemfile::Item::ECKey(key),
+            ) => return Ok(rustls::PrivateKey(key)),
+            Some(_) => (),
+            None => anyhow::bail!("No private key in {path}"),
+        }
+    }
+}
+
+/// Accepts TLS connections using the configured certificate and private key.
+/// They're only loaded when the web port starts, so rradio must be restarted once the certificate is renewed
+pub fn acceptor(config: &Tls) -> Result<TlsAcceptor> {
+    let mut server_config = rustls::ServerConfig::builder()
+        .with_safe_defaults()
+        .with_no_client_auth()
+        .with_single_cert(
+            load_certificates(&config.certificate_path)?,
+            load_private_key(&config.private_key_path)?,
+        )
+        .context("Certificate doesn't match private key")?;
+
+    // Connections are served using HTTP/1.1, including websockets, which are upgraded HTTP/1.1 connections
+    server_config.alpn_protocols = vec![b"http/1.1".to_vec()];
+
+    Ok(TlsAcceptor::from(Arc::new(server_config)))
+}
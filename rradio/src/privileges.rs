@@ -0,0 +1,225 @@
+//! Switch from root to an unprivileged user after startup, keeping only the capabilities which rradio still needs
+
+use std::ffi::{CStr, CString};
+
+use anyhow::{Context, Result};
+
+// Capability numbers, from linux/capability.h
+#[cfg(feature = "production-server")]
+const CAP_NET_BIND_SERVICE: u32 = 10;
+#[cfg(feature = "mount")]
+const CAP_SYS_ADMIN: u32 = 21;
+#[cfg(feature = "thread-priority")]
+const CAP_SYS_NICE: u32 = 23;
+
+const LINUX_CAPABILITY_VERSION_3: u32 = 0x2008_0522;
+
+#[repr(C)]
+struct CapUserHeader {
+    version: u32,
+    pid: libc::c_int,
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, Default)]
+struct CapUserData {
+    effective: u32,
+    permitted: u32,
+    inheritable: u32,
+}
+
+/// The capabilities needed after startup
+fn required_capabilities() -> u32 {
+    #[allow(unused_mut)]
+    let mut capabilities = 0;
+
+    // The web server binds to port 80 after privileges are dropped
+    #[cfg(feature = "production-server")]
+    {
+        capabilities |= 1 << CAP_NET_BIND_SERVICE;
+    }
+
+    // Devices are mounted when stations are selected
+    #[cfg(feature = "mount")]
+    {
+        capabilities |= 1 << CAP_SYS_ADMIN;
+    }
+
+    // The pipeline thread's priority is raised after privileges are dropped
+    #[cfg(feature = "thread-priority")]
+    {
+        capabilities |= 1 << CAP_SYS_NICE;
+    }
+
+    capabilities
+}
+
+fn check(result: impl Into<i64>) -> std::io::Result<()> {
+    if result.into() < 0 {
+        Err(std::io::Error::last_os_error())
+    } else {
+        Ok(())
+    }
+}
+
+fn lookup_user(user: &CStr) -> Result<(libc::uid_t, libc::gid_t)> {
+    let mut passwd = std::mem::MaybeUninit::<libc::passwd>::uninit();
+    let mut buffer: Vec<libc::c_char> = vec![0; 4096];
+    let mut result = std::ptr::null_mut();
+
+    // SAFETY: `user` is a valid C string, and `passwd`, `buffer` and `result` outlive the call, with `buffer.len()` being the length of `buffer`
+    let code = unsafe {
+        libc::getpwnam_r(
+            user.as_ptr(),
+            passwd.as_mut_ptr(),
+            buffer.as_mut_ptr(),
+            buffer.len(),
+            std::ptr::addr_of_mut!(result),
+        )
+    };
+
+    if code != 0 {
+        return Err(std::io::Error::from_raw_os_error(code))
+            .with_context(|| format!("Failed to look up user {user:?}"));
+    }
+
+    if result.is_null() {
+        anyhow::bail!("User {user:?} not found");
+    }
+
+    // SAFETY: `getpwnam_r` succeeded and found the user, so it initialised `passwd`
+    let passwd = unsafe { passwd.assume_init() };
+
+    Ok((passwd.pw_uid, passwd.pw_gid))
+}
+
+/// Check that every thread of the process has switched to `uid` and `gid`, and has no more than `capabilities`.
+///
+/// glibc switches the user and group of every thread, but capabilities are set per thread
+fn check_all_threads(uid: libc::uid_t, gid: libc::gid_t, capabilities: u32) -> Result<()> {
+    fn ids_match(value: &str, expected: u32) -> bool {
+        value
+            .split_whitespace()
+            .all(|id| id.parse::<u32>().ok() == Some(expected))
+    }
+
+    for entry in std::fs::read_dir("/proc/self/task").context("Failed to list threads")? {
+        let path = entry
+            .context("Failed to list threads")?
+            .path()
+            .join("status");
+
+        let status = match std::fs::read_to_string(&path) {
+            Ok(status) => status,
+            // The thread has since exited
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => continue,
+            Err(err) => {
+                return Err(err).with_context(|| format!("Failed to read {}", path.display()))
+            }
+        };
+
+        for line in status.lines() {
+            let Some((key, value)) = line.split_once(':') else {
+                continue;
+            };
+
+            let is_dropped = match key {
+                "Uid" => ids_match(value, uid),
+                "Gid" => ids_match(value, gid),
+                "CapPrm" | "CapEff" => {
+                    u64::from_str_radix(value.trim(), 16).is_ok_and(|thread_capabilities| {
+                        thread_capabilities & !u64::from(capabilities) == 0
+                    })
+                }
+                _ => true,
+            };
+
+            if !is_dropped {
+                anyhow::bail!("{} has {key} {}", path.display(), value.trim());
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// If running as root and a user is configured, switch to that user, keeping the capabilities which rradio needs.
+///
+/// Capabilities are only kept by the calling thread, which must be the thread which runs the async runtime, so that threads spawned later inherit them.
+/// Threads which are already running lose all of their capabilities when the user is switched, which is checked before returning
+pub fn drop_privileges(config: &crate::config::privileges::Config) -> Result<()> {
+    let Some(user) = &config.user else {
+        return Ok(());
+    };
+
+    // SAFETY: `geteuid` has no preconditions
+    if unsafe { libc::geteuid() } != 0 {
+        tracing::info!("Not running as root, so not dropping privileges");
+        return Ok(());
+    }
+
+    let user = CString::new(user.as_str()).context("Bad user name")?;
+
+    let (uid, gid) = lookup_user(&user)?;
+
+    // Only applies to the calling thread
+    let keep_capabilities: libc::c_ulong = 1;
+    // SAFETY: `PR_SET_KEEPCAPS` takes a single integer argument
+    check(unsafe { libc::prctl(libc::PR_SET_KEEPCAPS, keep_capabilities) })
+        .context("Failed to keep capabilities")?;
+
+    // SAFETY: `user` is a valid C string. glibc applies the change to every thread
+    check(unsafe { libc::initgroups(user.as_ptr(), gid) })
+        .context("Failed to set supplementary groups")?;
+
+    // SAFETY: `setgid` has no preconditions. glibc applies the change to every thread
+    check(unsafe { libc::setgid(gid) }).context("Failed to set group")?;
+
+    // SAFETY: `setuid` has no preconditions. glibc applies the change to every thread
+    check(unsafe { libc::setuid(uid) }).context("Failed to set user")?;
+
+    // Threads spawned later shouldn't keep capabilities if they switch user
+    let keep_capabilities: libc::c_ulong = 0;
+    // SAFETY: As above
+    check(unsafe { libc::prctl(libc::PR_SET_KEEPCAPS, keep_capabilities) })
+        .context("Failed to stop keeping capabilities")?;
+
+    let capabilities = required_capabilities();
+
+    let mut header = CapUserHeader {
+        version: LINUX_CAPABILITY_VERSION_3,
+        pid: 0,
+    };
+
+    let data = [
+        CapUserData {
+            effective: capabilities,
+            permitted: capabilities,
+            inheritable: 0,
+        },
+        CapUserData::default(),
+    ];
+
+    // SAFETY: `header` and `data` outlive the call, and version 3 capabilities take two `CapUserData`s, matching the kernel's layout
+    check(unsafe {
+        libc::syscall(
+            libc::SYS_capset,
+            std::ptr::addr_of_mut!(header),
+            data.as_ptr(),
+        )
+    })
+    .context("Failed to set capabilities")?;
+
+    check_all_threads(uid, gid, capabilities)
+        .context("Failed to drop privileges of all threads")?;
+
+    tracing::info!(
+        ?user,
+        uid,
+        gid,
+        capabilities = format_args!("{capabilities:#x}"),
+        "Dropped privileges"
+    );
+
+    Ok(())
+}
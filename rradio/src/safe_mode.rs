@@ -0,0 +1,384 @@
+//! Booting with a known good config after repeated startup failures, so that a bad config change doesn't stop the radio from booting.
+//!
+//! Each startup is counted, and the count is reset once rradio has been running for [`STABLE_DURATION`] or shuts down cleanly.
+//! Once startup has succeeded, the config file and the stations directory are copied into the `last_known_good` directory in the state directory.
+//! If the config file can't be loaded, or after [`ROLLBACK_BOOT_ATTEMPTS`] consecutive failed startups, the last known good config is used instead.
+//! After [`SAFE_MODE_BOOT_ATTEMPTS`] consecutive failed startups, the default config is used, with the last known good stations.
+
+use std::{
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use anyhow::{Context, Result};
+
+use rradio_messages::{arcstr, ArcStr};
+
+use crate::config::Config;
+
+const LAST_KNOWN_GOOD_DIRECTORY_NAME: &str = "last_known_good";
+const BOOT_ATTEMPTS_FILE_NAME: &str = "boot_attempts";
+const CONFIG_FILE_NAME: &str = "config.toml";
+const STATIONS_DIRECTORY_NAME: &str = "stations";
+
+/// After this many consecutive failed startups, the last known good config is used
+const ROLLBACK_BOOT_ATTEMPTS: u32 = 3;
+
+/// After this many consecutive failed startups, the default config is used
+const SAFE_MODE_BOOT_ATTEMPTS: u32 = 6;
+
+/// Startup is considered to have succeeded once rradio has been running for this long
+pub const STABLE_DURATION: Duration = Duration::from_secs(60);
+
+/// The config which rradio started with
+pub struct Startup {
+    pub config: Config,
+    /// True if the config was loaded from the config file
+    pub config_loaded: bool,
+    /// Why rradio isn't using the config file, or None if it is
+    pub safe_mode: Option<ArcStr>,
+}
+
+impl Startup {
+    /// Load the config file, using the default config if it can't be loaded, without counting the startup or falling back to the last known good config
+    pub fn from_file(config_path: impl AsRef<Path>) -> Self {
+        let loaded_config = Config::from_file(config_path.as_ref());
+
+        Self {
+            config_loaded: loaded_config.is_some(),
+            config: loaded_config.unwrap_or_default(), // See config::Config::default() for default config
+            safe_mode: None,
+        }
+    }
+}
+
+/// Records startups, and the last known good config
+#[derive(Clone)]
+pub struct BootRecord {
+    config_path: PathBuf,
+    directory: PathBuf,
+    /// The last known good config is only updated if rradio started with the config file
+    config_loaded: bool,
+}
+
+impl BootRecord {
+    pub fn new(config_path: impl AsRef<Path>) -> Self {
+        let config_path = config_path.as_ref().to_owned();
+
+        let directory = state_directory(&config_path).join(LAST_KNOWN_GOOD_DIRECTORY_NAME);
+
+        Self {
+            config_path,
+            directory,
+            config_loaded: false,
+        }
+    }
+
+    fn boot_attempts_path(&self) -> PathBuf {
+        self.directory.join(BOOT_ATTEMPTS_FILE_NAME)
+    }
+
+    fn stations_directory(&self) -> PathBuf {
+        self.directory.join(STATIONS_DIRECTORY_NAME)
+    }
+
+    fn read_boot_attempts(&self) -> u32 {
+        let path = self.boot_attempts_path();
+
+        match crate::state_file::read_to_string(&path) {
+            Ok(Some(boot_attempts)) => boot_attempts.trim().parse().unwrap_or_else(|err| {
+                tracing::warn!("Bad boot attempts in {}: {err}", path.display());
+                0
+            }),
+            Ok(None) => 0,
+            Err(err) => {
+                tracing::error!("{err:#}");
+                0
+            }
+        }
+    }
+
+    fn write_boot_attempts(&self, boot_attempts: u32) -> Result<()> {
+        crate::state_file::write(&self.boot_attempts_path(), boot_attempts.to_string())
+    }
+
+    /// Use the last known good stations, if they have been saved
+    fn with_last_known_good_stations(&self, mut config: Config) -> Config {
+        let stations_directory = self.stations_directory();
+
+        if stations_directory.is_dir() {
+            config.stations_directory = ArcStr::from(stations_directory.to_string_lossy());
+        }
+
+        config
+    }
+
+    fn last_known_good_config(&self) -> Option<Config> {
+        let path = self.directory.join(CONFIG_FILE_NAME);
+
+        if !path.exists() {
+            return None;
+        }
+
+        Config::from_file(&path).map(|config| self.with_last_known_good_stations(config))
+    }
+
+    /// Count this startup, and load the config, falling back to the last known good config or the default config if the config file can't be loaded or rradio has failed to start too many times
+    pub fn start(&mut self) -> Startup {
+        let boot_attempts = self.read_boot_attempts() + 1;
+
+        if let Err(err) = self.write_boot_attempts(boot_attempts) {
+            tracing::error!("{err:#}");
+        }
+
+        let failed_boot_attempts = boot_attempts - 1;
+
+        let (config, safe_mode) = if failed_boot_attempts >= SAFE_MODE_BOOT_ATTEMPTS {
+            (
+                self.with_last_known_good_stations(Config::default()),
+                arcstr::format!(
+                    "rradio failed to start {failed_boot_attempts} times, so is using the default config"
+                ),
+            )
+        } else if failed_boot_attempts >= ROLLBACK_BOOT_ATTEMPTS {
+            match self.last_known_good_config() {
+                Some(config) => (
+                    config,
+                    arcstr::format!("rradio failed to start {failed_boot_attempts} times, so is using the last known good config"),
+                ),
+                None => (
+                    self.with_last_known_good_stations(Config::default()),
+                    arcstr::format!("rradio failed to start {failed_boot_attempts} times, and there is no last known good config, so is using the default config"),
+                ),
+            }
+        } else if let Some(config) = Config::from_file(&self.config_path) {
+            self.config_loaded = true;
+
+            return Startup {
+                config,
+                config_loaded: true,
+                safe_mode: None,
+            };
+        } else {
+            match self.last_known_good_config() {
+                Some(config) => (
+                    config,
+                    arcstr::format!(
+                        "Failed to load {}, so using the last known good config",
+                        self.config_path.display()
+                    ),
+                ),
+                // As before safe mode was added, as there might not be a config file during development
+                None => {
+                    return Startup {
+                        config: Config::default(),
+                        config_loaded: false,
+                        safe_mode: None,
+                    }
+                }
+            }
+        };
+
+        tracing::error!("Safe mode: {safe_mode}");
+
+        Startup {
+            config,
+            config_loaded: false,
+            safe_mode: Some(safe_mode),
+        }
+    }
+
+    /// Reset the count of failed startups, e.g. when rradio shuts down cleanly
+    pub fn reset_boot_attempts(&self) {
+        let path = self.boot_attempts_path();
+
+        match std::fs::remove_file(&path) {
+            Err(err) if err.kind() != std::io::ErrorKind::NotFound => {
+                tracing::error!("Failed to remove {}: {err}", path.display());
+            }
+            _ => (),
+        }
+    }
+
+    /// Record that startup has succeeded, and if rradio started with the config file, save it and the stations directory as the last known good config
+    pub fn startup_succeeded(&self, config: &Config) -> Result<()> {
+        self.reset_boot_attempts();
+
+        if !self.config_loaded {
+            return Ok(());
+        }
+
+        let config_file = std::fs::read(&self.config_path)
+            .with_context(|| format!("Failed to read {}", self.config_path.display()))?;
+
+        crate::state_file::write(&self.directory.join(CONFIG_FILE_NAME), config_file)?;
+
+        // Copy to a temporary directory and then rename it so that the stations are never partially copied
+        let stations_directory = self.stations_directory();
+        let temporary_directory = stations_directory.with_extension("new");
+
+        remove_directory(&temporary_directory)?;
+
+        copy_directory(
+            Path::new(config.stations_directory.as_str()),
+            &temporary_directory,
+        )?;
+
+        remove_directory(&stations_directory)?;
+
+        std::fs::rename(&temporary_directory, &stations_directory)
+            .with_context(|| format!("Failed to replace {}", stations_directory.display()))?;
+
+        tracing::info!("Saved last known good config");
+
+        Ok(())
+    }
+}
+
+/// The state directory set by the config file, or the default state directory.
+/// The config file might not parse, e.g. after a bad edit, so the `state_directory` line, which must come before any tables, is parsed on its own
+fn state_directory(config_path: &Path) -> PathBuf {
+    std::fs::read_to_string(config_path)
+        .ok()
+        .and_then(|config| {
+            config
+                .lines()
+                .take_while(|line| !line.trim_start().starts_with('['))
+                .filter(|line| line.trim_start().starts_with("state_directory"))
+                .find_map(|line| {
+                    line.parse::<toml::Table>()
+                        .ok()?
+                        .get("state_directory")?
+                        .as_str()
+                        .map(PathBuf::from)
+                })
+        })
+        .unwrap_or_else(|| PathBuf::from(Config::default().state_directory.as_str()))
+}
+
+fn remove_directory(path: &Path) -> Result<()> {
+    match std::fs::remove_dir_all(path) {
+        Err(err) if err.kind() != std::io::ErrorKind::NotFound => {
+            Err(err).with_context(|| format!("Failed to remove {}", path.display()))
+        }
+        _ => Ok(()),
+    }
+}
+
+fn copy_directory(from: &Path, to: &Path) -> Result<()> {
+    std::fs::create_dir_all(to).with_context(|| format!("Failed to create {}", to.display()))?;
+
+    for entry in
+        std::fs::read_dir(from).with_context(|| format!("Failed to read {}", from.display()))?
+    {
+        let entry = entry.with_context(|| format!("Failed to read {}", from.display()))?;
+        let path = entry.path();
+        let destination = to.join(entry.file_name());
+
+        if path.is_dir() {
+            copy_directory(&path, &destination)?;
+        } else {
+            std::fs::copy(&path, &destination)
+                .with_context(|| format!("Failed to copy {}", path.display()))?;
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{BootRecord, ROLLBACK_BOOT_ATTEMPTS, SAFE_MODE_BOOT_ATTEMPTS};
+
+    #[test]
+    fn falls_back_after_failed_startups() {
+        let directory = tempfile::tempdir().unwrap();
+
+        let config_path = directory.path().join("config.toml");
+        let stations_directory = directory.path().join("stations");
+        let state_directory = directory.path().join("state");
+        let last_known_good_stations_directory =
+            state_directory.join("last_known_good").join("stations");
+
+        // Each config file starts with the state directory
+        let write_config = |config: &str| {
+            std::fs::write(
+                &config_path,
+                format!(
+                    "state_directory = {:?}\n{config}",
+                    state_directory.to_str().unwrap()
+                ),
+            )
+            .unwrap();
+        };
+
+        std::fs::create_dir(&stations_directory).unwrap();
+        std::fs::write(stations_directory.join("01.m3u"), "http://example.com/").unwrap();
+
+        write_config(&format!(
+            "stations_directory = {:?}\ninitial_volume = 50\n",
+            stations_directory.to_str().unwrap()
+        ));
+
+        let mut boot_record = BootRecord::new(&config_path);
+        let startup = boot_record.start();
+
+        assert!(startup.config_loaded);
+        assert!(startup.safe_mode.is_none());
+
+        boot_record.startup_succeeded(&startup.config).unwrap();
+
+        assert!(last_known_good_stations_directory.join("01.m3u").exists());
+
+        // A config file which can't be parsed is rolled back immediately
+        write_config("initial_volume = ");
+
+        let startup = BootRecord::new(&config_path).start();
+
+        assert!(!startup.config_loaded);
+        assert!(startup.safe_mode.is_some());
+        assert_eq!(startup.config.initial_volume, 50);
+        assert_eq!(
+            startup.config.stations_directory,
+            last_known_good_stations_directory.to_str().unwrap()
+        );
+
+        // A config file which parses but fails to start is rolled back after several attempts
+        write_config("initial_volume = 60\n");
+
+        for _ in 1..ROLLBACK_BOOT_ATTEMPTS {
+            let startup = BootRecord::new(&config_path).start();
+
+            assert!(startup.safe_mode.is_none());
+            assert_eq!(startup.config.initial_volume, 60);
+        }
+
+        for _ in ROLLBACK_BOOT_ATTEMPTS..SAFE_MODE_BOOT_ATTEMPTS {
+            let startup = BootRecord::new(&config_path).start();
+
+            assert!(startup.safe_mode.is_some());
+            assert_eq!(startup.config.initial_volume, 50);
+        }
+
+        let mut boot_record = BootRecord::new(&config_path);
+        let startup = boot_record.start();
+
+        assert!(startup.safe_mode.is_some());
+        assert_eq!(
+            startup.config.initial_volume,
+            crate::config::Config::default().initial_volume
+        );
+        assert_eq!(
+            startup.config.stations_directory,
+            last_known_good_stations_directory.to_str().unwrap()
+        );
+
+        // The safe mode config isn't saved as the last known good config
+        boot_record.startup_succeeded(&startup.config).unwrap();
+
+        let startup = BootRecord::new(&config_path).start();
+
+        assert!(startup.safe_mode.is_none());
+        assert_eq!(startup.config.initial_volume, 60);
+    }
+}
@@ -0,0 +1,127 @@
+//! The station and track which were playing when rradio shut down, stored as a TOML file in the state directory, so that playback can resume after a restart
+
+use std::{
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use anyhow::{Context, Result};
+
+use rradio_messages::{ArcStr, StationIndex};
+
+const SAVED_POSITION_FILE_NAME: &str = "saved_position.toml";
+
+#[derive(Debug, serde::Deserialize, serde::Serialize)]
+pub struct SavedTrack {
+    /// The url of the track, so that the position isn't used if the station's tracks have changed, e.g. if a different random album is chosen
+    pub url: ArcStr,
+    pub index: usize,
+    #[serde(with = "humantime_serde")]
+    pub position: Duration,
+}
+
+#[derive(Debug, serde::Deserialize, serde::Serialize)]
+pub struct SavedPosition {
+    pub station_index: StationIndex,
+    /// None if the station always starts from the beginning, such as internet radio
+    pub track: Option<SavedTrack>,
+    /// The directories or tracks chosen by the station, so that the same tracks are loaded when it's resumed
+    pub metadata: Option<crate::station::SavedPlaylistMetadata>,
+}
+
+fn saved_position_path(state_directory: impl AsRef<Path>) -> PathBuf {
+    state_directory.as_ref().join(SAVED_POSITION_FILE_NAME)
+}
+
+/// Save the position, or remove the saved position if nothing is playing
+pub fn save(state_directory: impl AsRef<Path>, position: Option<&SavedPosition>) -> Result<()> {
+    let path = saved_position_path(state_directory);
+
+    let Some(position) = position else {
+        return match std::fs::remove_file(&path) {
+            Err(err) if err.kind() != std::io::ErrorKind::NotFound => {
+                Err(err).with_context(|| format!("Failed to remove {}", path.display()))
+            }
+            _ => Ok(()),
+        };
+    };
+
+    let position = toml::to_string(position).context("Failed to serialize saved position")?;
+
+    crate::state_file::write(&path, position)
+}
+
+/// Load and remove the saved position, so that a position which causes rradio to crash isn't resumed repeatedly
+pub fn take(state_directory: impl AsRef<Path>) -> Option<SavedPosition> {
+    let path = saved_position_path(state_directory);
+
+    let position = match crate::state_file::read_to_string(&path) {
+        Ok(Some(position)) => position,
+        Ok(None) => {
+            tracing::debug!("{} not found, nothing to resume", path.display());
+            return None;
+        }
+        Err(err) => {
+            tracing::error!("{err:#}");
+            return None;
+        }
+    };
+
+    if let Err(err) = std::fs::remove_file(&path) {
+        tracing::error!("Failed to remove {}: {err}", path.display());
+    }
+
+    toml::from_str(&position)
+        .map_err(|err| tracing::error!("Failed to parse {}: {err}", path.display()))
+        .ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use crate::station::SavedPlaylistMetadata;
+
+    use super::{SavedPosition, SavedTrack};
+
+    #[test]
+    fn save_and_take() {
+        let state_directory = tempfile::tempdir().unwrap();
+
+        let index = rradio_messages::StationIndex::new("05".into());
+
+        super::save(
+            state_directory.path(),
+            Some(&SavedPosition {
+                station_index: index.clone(),
+                track: Some(SavedTrack {
+                    url: rradio_messages::arcstr::literal!("file:///music/01 Track.mp3"),
+                    index: 3,
+                    position: Duration::from_millis(83_500),
+                }),
+                metadata: Some(SavedPlaylistMetadata::Directories {
+                    artist: "Artist".into(),
+                    album: "Album".into(),
+                }),
+            }),
+        )
+        .unwrap();
+
+        let position = super::take(state_directory.path()).unwrap();
+
+        assert_eq!(position.station_index, index);
+
+        let track = position.track.unwrap();
+        assert_eq!(track.index, 3);
+        assert_eq!(track.position, Duration::from_millis(83_500));
+
+        let Some(SavedPlaylistMetadata::Directories { artist, album }) = position.metadata else {
+            panic!("Directories not saved");
+        };
+        assert_eq!(artist, "Artist");
+        assert_eq!(album, "Album");
+
+        // The position is only resumed once
+        assert!(super::take(state_directory.path()).is_none());
+    }
+}
@@ -0,0 +1,466 @@
+//! Searching the configured sources for `Search` commands.
+//! Each source is searched on its own task, and its results are sent as soon as they're found, so that a slow source doesn't delay the others
+
+use anyhow::{Context, Result};
+use tokio::sync::mpsc;
+
+use rradio_messages::{
+    arcstr, ArcStr, Command, Event, SearchResult, SearchResults, SetPlaylistTrack,
+};
+
+use crate::{
+    config::search::Source,
+    ports::{CommandSender, SharedEvent},
+};
+
+#[derive(serde::Deserialize)]
+struct RadioBrowserStation {
+    name: String,
+    url_resolved: String,
+    #[serde(default)]
+    country: String,
+}
+
+#[derive(serde::Deserialize)]
+struct PodcastDirectoryResults {
+    results: Vec<Podcast>,
+}
+
+#[derive(serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Podcast {
+    collection_name: String,
+    #[serde(default)]
+    artist_name: Option<String>,
+    #[serde(default)]
+    feed_url: Option<String>,
+}
+
+#[derive(serde::Deserialize)]
+struct Rss {
+    channel: Channel,
+}
+
+struct Channel {
+    title: Option<String>,
+    episodes: Vec<Episode>,
+}
+
+struct Episode {
+    title: Option<String>,
+    url: Option<String>,
+}
+
+#[derive(serde::Deserialize)]
+struct Enclosure {
+    #[serde(rename = "@url")]
+    url: String,
+}
+
+/// Channels are deserialized by hand for the same reason as [`Episode`], e.g. `itunes:title` is a second `title`
+impl<'de> serde::Deserialize<'de> for Channel {
+    fn deserialize<D>(deserializer: D) -> std::result::Result<Self, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        struct ChannelVisitor;
+
+        impl<'de> serde::de::Visitor<'de> for ChannelVisitor {
+            type Value = Channel;
+
+            fn expecting(&self, formatter: &mut std::fmt::Formatter) -> std::fmt::Result {
+                formatter.write_str("a podcast channel")
+            }
+
+            fn visit_map<A>(self, mut map: A) -> std::result::Result<Channel, A::Error>
+            where
+                A: serde::de::MapAccess<'de>,
+            {
+                let mut channel = Channel {
+                    title: None,
+                    episodes: Vec::new(),
+                };
+
+                while let Some(key) = map.next_key::<String>()? {
+                    match key.as_str() {
+                        "title" if channel.title.is_none() => {
+                            channel.title = Some(map.next_value()?);
+                        }
+                        "item" => channel.episodes.push(map.next_value()?),
+                        _ => {
+                            map.next_value::<serde::de::IgnoredAny>()?;
+                        }
+                    }
+                }
+
+                Ok(channel)
+            }
+        }
+
+        deserializer.deserialize_map(ChannelVisitor)
+    }
+}
+
+/// Episodes are deserialized by hand because namespaces are ignored, so e.g. `itunes:title` is a second `title`, which a derived implementation rejects
+impl<'de> serde::Deserialize<'de> for Episode {
+    fn deserialize<D>(deserializer: D) -> std::result::Result<Self, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        struct EpisodeVisitor;
+
+        impl<'de> serde::de::Visitor<'de> for EpisodeVisitor {
+            type Value = Episode;
+
+            fn expecting(&self, formatter: &mut std::fmt::Formatter) -> std::fmt::Result {
+                formatter.write_str("a podcast episode")
+            }
+
+            fn visit_map<A>(self, mut map: A) -> std::result::Result<Episode, A::Error>
+            where
+                A: serde::de::MapAccess<'de>,
+            {
+                let mut episode = Episode {
+                    title: None,
+                    url: None,
+                };
+
+                while let Some(key) = map.next_key::<String>()? {
+                    match key.as_str() {
+                        "title" if episode.title.is_none() => {
+                            episode.title = Some(map.next_value()?);
+                        }
+                        "enclosure" if episode.url.is_none() => {
+                            episode.url = Some(map.next_value::<Enclosure>()?.url);
+                        }
+                        _ => {
+                            map.next_value::<serde::de::IgnoredAny>()?;
+                        }
+                    }
+                }
+
+                Ok(episode)
+            }
+        }
+
+        deserializer.deserialize_map(EpisodeVisitor)
+    }
+}
+
+fn source_name(source: &Source) -> ArcStr {
+    match source {
+        Source::UPnP {
+            root_description_url,
+        } => arcstr::format!(
+            "upnp {}",
+            root_description_url.host_str().unwrap_or_default()
+        ),
+        #[cfg(feature = "usb")]
+        Source::UsbIndex => arcstr::literal!("usb"),
+        Source::RadioBrowser { .. } => arcstr::literal!("radio-browser"),
+        Source::PodcastDirectory { .. } => arcstr::literal!("podcasts"),
+    }
+}
+
+/// The command which plays a single stream, such as a station or a track
+fn play_url(title: &str, url: &str) -> Command {
+    Command::SetPlaylist {
+        title: title.into(),
+        tracks: vec![SetPlaylistTrack {
+            title: title.into(),
+            url: url.into(),
+        }],
+    }
+}
+
+/// Search results and podcast feeds which are longer than this are rejected
+const MAXIMUM_RESPONSE_LENGTH: usize = 8 * 1024 * 1024;
+
+async fn fetch(client: &reqwest::Client, url: &str) -> Result<Vec<u8>> {
+    crate::http::fetch(client, url, MAXIMUM_RESPONSE_LENGTH).await
+}
+
+async fn search_upnp(
+    root_description_url: url::Url,
+    query: &str,
+    limit: usize,
+) -> Result<Vec<SearchResult>> {
+    let tracks = crate::station::search_upnp(root_description_url, query, limit).await?;
+
+    Ok(tracks
+        .into_iter()
+        .map(|track| {
+            let title = track.title.clone().unwrap_or_else(|| track.url.clone());
+
+            let subtitle = match (track.artist, track.album) {
+                (Some(artist), Some(album)) => Some(arcstr::format!("{artist} - {album}")),
+                (Some(detail), None) | (None, Some(detail)) => Some(detail),
+                (None, None) => None,
+            };
+
+            SearchResult {
+                play: play_url(&title, &track.url),
+                title,
+                subtitle,
+            }
+        })
+        .collect())
+}
+
+#[cfg(feature = "usb")]
+async fn search_usb_index(
+    config: &crate::config::Config,
+    query: &str,
+    limit: usize,
+) -> Result<Vec<SearchResult>> {
+    let config = config.clone();
+    let query = String::from(query);
+
+    let albums =
+        tokio::task::spawn_blocking(move || crate::station::search_usb_index(&config, &query))
+            .await
+            .context("USB index search task failed")??;
+
+    Ok(albums
+        .into_iter()
+        .take(limit)
+        .map(|(artist, album)| SearchResult {
+            play: Command::PlayPath {
+                source: rradio_messages::PathSource::Usb,
+                path: format!("{artist}/{album}"),
+                recursive: false,
+            },
+            title: album.into(),
+            subtitle: Some(artist.into()),
+        })
+        .collect())
+}
+
+async fn search_radio_browser(
+    client: &reqwest::Client,
+    server_url: &str,
+    query: &str,
+    limit: usize,
+) -> Result<Vec<SearchResult>> {
+    let mut url = url::Url::parse(server_url)
+        .and_then(|server_url| server_url.join("json/stations/search"))
+        .with_context(|| format!("Bad radio-browser url {server_url:?}"))?;
+
+    url.query_pairs_mut()
+        .append_pair("name", query)
+        .append_pair("limit", &limit.to_string())
+        .append_pair("hidebroken", "true")
+        .append_pair("order", "clickcount")
+        .append_pair("reverse", "true");
+
+    let stations: Vec<RadioBrowserStation> =
+        serde_json::from_slice(&fetch(client, url.as_str()).await?)
+            .context("Failed to parse radio-browser stations")?;
+
+    Ok(stations
+        .into_iter()
+        .take(limit)
+        .map(|station| SearchResult {
+            play: play_url(&station.name, &station.url_resolved),
+            title: station.name.into(),
+            subtitle: (!station.country.is_empty()).then(|| station.country.into()),
+        })
+        .collect())
+}
+
+/// The title of a podcast feed, and its episodes which have audio, in the order of the feed, which is usually newest first
+pub fn parse_feed(feed: &str) -> Result<(Option<String>, Vec<SetPlaylistTrack>)> {
+    let Rss { channel } = quick_xml::de::from_str(feed).context("Failed to parse feed")?;
+
+    let episodes = channel
+        .episodes
+        .into_iter()
+        .filter_map(|Episode { title, url }| {
+            let url = url?;
+
+            Some(SetPlaylistTrack {
+                title: title.unwrap_or_else(|| url.clone()),
+                url,
+            })
+        })
+        .collect();
+
+    Ok((channel.title, episodes))
+}
+
+async fn search_podcast_directory(
+    client: &reqwest::Client,
+    search_url: &str,
+    query: &str,
+    limit: usize,
+) -> Result<Vec<SearchResult>> {
+    let mut url = url::Url::parse(search_url)
+        .with_context(|| format!("Bad podcast directory url {search_url:?}"))?;
+
+    url.query_pairs_mut()
+        .append_pair("media", "podcast")
+        .append_pair("limit", &limit.to_string())
+        .append_pair("term", query);
+
+    let PodcastDirectoryResults { results } =
+        serde_json::from_slice(&fetch(client, url.as_str()).await?)
+            .context("Failed to parse podcast directory results")?;
+
+    // The episodes of each podcast are fetched when it's played, so that searching doesn't fetch every feed found
+    Ok(results
+        .into_iter()
+        .filter_map(|podcast| {
+            let feed_url = podcast.feed_url?;
+
+            Some(SearchResult {
+                play: Command::PlayPodcastFeed {
+                    title: podcast.collection_name.clone(),
+                    feed_url,
+                },
+                title: podcast.collection_name.into(),
+                subtitle: podcast.artist_name.map(ArcStr::from),
+            })
+        })
+        .take(limit)
+        .collect())
+}
+
+#[cfg_attr(not(feature = "usb"), allow(unused_variables))]
+async fn search_source(
+    config: &crate::config::Config,
+    source: &Source,
+    query: &str,
+    limit: usize,
+) -> Result<Vec<SearchResult>> {
+    match source {
+        Source::UPnP {
+            root_description_url,
+        } => search_upnp(root_description_url.clone(), query, limit).await,
+        #[cfg(feature = "usb")]
+        Source::UsbIndex => search_usb_index(config, query, limit).await,
+        Source::RadioBrowser { server_url } => {
+            let client = crate::updater::http_client()?;
+            search_radio_browser(&client, server_url, query, limit).await
+        }
+        Source::PodcastDirectory { search_url } => {
+            let client = crate::updater::http_client()?;
+            search_podcast_directory(&client, search_url, query, limit).await
+        }
+    }
+}
+
+/// Search each of the configured sources on its own task, sending the results of each source as an event to the client which searched
+pub fn start(
+    config: &crate::config::Config,
+    query: &ArcStr,
+    replies_tx: &mpsc::Sender<SharedEvent>,
+) {
+    let Some(search_config) = &config.search else {
+        return;
+    };
+
+    for source in &search_config.sources {
+        let config = config.clone();
+        let source = source.clone();
+        let query = query.clone();
+        let replies_tx = replies_tx.clone();
+        let limit = search_config.result_limit.get();
+        let timeout = search_config.timeout;
+
+        tokio::spawn(async move {
+            let name = source_name(&source);
+
+            let result =
+                tokio::time::timeout(timeout, search_source(&config, &source, &query, limit))
+                    .await
+                    .context("Timed out")
+                    .and_then(std::convert::identity)
+                    .map_err(|err| {
+                        tracing::error!(source = %name, %query, "Failed to search: {err:#}");
+                        arcstr::format!("{err:#}")
+                    });
+
+            // The client might have disconnected, which is not an error
+            replies_tx
+                .send(SharedEvent::new(Event::SearchResults(SearchResults {
+                    query,
+                    source: name,
+                    result,
+                })))
+                .await
+                .ok();
+        });
+    }
+}
+
+/// The episodes of a podcast feed, failing if there aren't any, as there's nothing to play
+async fn fetch_podcast_episodes(feed_url: &str) -> Result<Vec<SetPlaylistTrack>> {
+    let client = crate::updater::http_client()?;
+    let feed = fetch(&client, feed_url).await?;
+    let (_, episodes) = parse_feed(&String::from_utf8_lossy(&feed))?;
+
+    anyhow::ensure!(!episodes.is_empty(), "Podcast has no episodes");
+
+    Ok(episodes)
+}
+
+/// Fetch a podcast feed in the background, and play its episodes by sending a [`Command::SetPlaylist`] with `commands_tx`
+pub fn play_podcast_feed(title: String, feed_url: String, commands_tx: CommandSender) {
+    tokio::spawn(async move {
+        let result = fetch_podcast_episodes(&feed_url)
+            .await
+            .and_then(|tracks| Ok(commands_tx.send(Command::SetPlaylist { title, tracks })?));
+
+        if let Err(err) = result {
+            tracing::error!(%feed_url, "Failed to play podcast: {err:#}");
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn podcast_episodes_with_audio_are_found() {
+        let feed = r#"<?xml version="1.0" encoding="UTF-8"?>
+            <rss version="2.0" xmlns:itunes="http://www.itunes.com/dtds/podcast-1.0.dtd">
+                <channel>
+                    <title>A Podcast</title>
+                    <itunes:title>The Podcast</itunes:title>
+                    <item>
+                        <title>Episode 2</title>
+                        <enclosure url="https://example.com/2.mp3" type="audio/mpeg" length="1"/>
+                        <itunes:title>Second Episode</itunes:title>
+                    </item>
+                    <item>
+                        <title>Announcement</title>
+                    </item>
+                    <item>
+                        <enclosure url="https://example.com/1.mp3" type="audio/mpeg" length="1"/>
+                    </item>
+                </channel>
+            </rss>"#;
+
+        let (title, episodes) = super::parse_feed(feed).unwrap();
+
+        assert_eq!(title.as_deref(), Some("A Podcast"));
+
+        let episodes = episodes
+            .into_iter()
+            .map(|episode| (episode.title, episode.url))
+            .collect::<Vec<_>>();
+
+        assert_eq!(
+            episodes,
+            [
+                (
+                    String::from("Episode 2"),
+                    String::from("https://example.com/2.mp3")
+                ),
+                (
+                    String::from("https://example.com/1.mp3"),
+                    String::from("https://example.com/1.mp3")
+                ),
+            ]
+        );
+    }
+}
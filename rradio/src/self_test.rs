@@ -0,0 +1,122 @@
+//! Checks of the hardware and configuration, run by the `RunSelfTest` command or the `--self-test` flag,
+//! e.g. on the production line or after hardware changes
+
+use std::sync::Arc;
+
+use rradio_messages::{arcstr, SelfTestOutcome, SelfTestResult};
+
+use crate::config::Config;
+
+fn outcome<E: std::fmt::Display>(result: Result<(), E>) -> SelfTestOutcome {
+    match result {
+        Ok(()) => SelfTestOutcome::Passed,
+        Err(err) => SelfTestOutcome::Failed(arcstr::format!("{err:#}")),
+    }
+}
+
+fn check_audio_output(config: &Config) -> SelfTestOutcome {
+    outcome(crate::pipeline::play_test_tone(config))
+}
+
+#[cfg_attr(not(feature = "cd"), allow(unused_variables))]
+fn check_cd_drive(config: &Config) -> SelfTestOutcome {
+    #[cfg(feature = "cd")]
+    {
+        outcome(crate::station::check_cd_drive(&config.cd_config.device))
+    }
+
+    #[cfg(not(feature = "cd"))]
+    {
+        SelfTestOutcome::Skipped(arcstr::literal!("CD support is not enabled"))
+    }
+}
+
+#[cfg_attr(not(feature = "usb"), allow(unused_variables))]
+fn check_usb_device(config: &Config) -> SelfTestOutcome {
+    #[cfg(feature = "usb")]
+    {
+        let device = &config.usb_config.device;
+
+        if std::path::Path::new(device.as_str()).exists() {
+            SelfTestOutcome::Passed
+        } else {
+            SelfTestOutcome::Failed(arcstr::format!("{device} not found"))
+        }
+    }
+
+    #[cfg(not(feature = "usb"))]
+    {
+        SelfTestOutcome::Skipped(arcstr::literal!("USB support is not enabled"))
+    }
+}
+
+fn check_network(_: &Config) -> SelfTestOutcome {
+    if crate::boot::has_route_to_internet() {
+        SelfTestOutcome::Passed
+    } else {
+        SelfTestOutcome::Failed(arcstr::literal!("No route to the internet"))
+    }
+}
+
+#[cfg_attr(not(feature = "ping"), allow(unused_variables))]
+fn check_gateway(config: &Config) -> SelfTestOutcome {
+    #[cfg(feature = "ping")]
+    {
+        outcome(crate::pipeline::ping_once(config.ping_config.gateway_address).map(|_| ()))
+    }
+
+    #[cfg(not(feature = "ping"))]
+    {
+        SelfTestOutcome::Skipped(arcstr::literal!("Ping support is not enabled"))
+    }
+}
+
+fn check_stations_directory(config: &Config) -> SelfTestOutcome {
+    match crate::station::validate_all(config) {
+        Ok(0) => SelfTestOutcome::Passed,
+        Ok(failure_count) => {
+            SelfTestOutcome::Failed(arcstr::format!("{failure_count} stations failed to load"))
+        }
+        Err(err) => SelfTestOutcome::Failed(arcstr::format!("{err}")),
+    }
+}
+
+/// Run all checks concurrently, as some of them wait for hardware or the network
+pub async fn run(config: Config) -> SelfTestResult {
+    let config = Arc::new(config);
+
+    let check = |check: fn(&Config) -> SelfTestOutcome| {
+        let config = config.clone();
+        async move {
+            tokio::task::spawn_blocking(move || check(&config))
+                .await
+                .unwrap_or_else(|err| SelfTestOutcome::Failed(arcstr::format!("{err}")))
+        }
+    };
+
+    let (audio_output, cd_drive, usb_device, network, gateway, stations_directory) = tokio::join!(
+        check(check_audio_output),
+        check(check_cd_drive),
+        check(check_usb_device),
+        check(check_network),
+        check(check_gateway),
+        check(check_stations_directory),
+    );
+
+    let result = SelfTestResult {
+        audio_output,
+        cd_drive,
+        usb_device,
+        network,
+        gateway,
+        stations_directory,
+    };
+
+    if result.passed() {
+        tracing::info!(?result, "Self-test passed");
+    } else {
+        tracing::warn!(?result, "Self-test failed");
+    }
+
+    result
+}
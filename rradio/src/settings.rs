@@ -0,0 +1,153 @@
+//! Settings which persist between restarts, stored as a TOML file in the state directory
+
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result};
+use tokio::time::{Duration, Instant};
+
+use rradio_messages::Settings;
+
+const SETTINGS_FILE_NAME: &str = "settings.toml";
+
+/// How long to wait after a change before saving the settings, so that a burst of changes, such as holding down a volume button, is written once
+const SAVE_DELAY: Duration = Duration::from_secs(5);
+
+/// The persistent settings store. Changes are written to disk on a blocking thread shortly after they are made
+pub struct Store {
+    path: PathBuf,
+    settings: Settings,
+    /// When the unsaved changes should be written, or `None` if there are no unsaved changes
+    save_at: Option<Instant>,
+    /// The write in progress, if any. Only one write runs at a time, so that an older write can't replace a newer one
+    save_task: Option<tokio::task::JoinHandle<()>>,
+}
+
+impl Store {
+    /// Load the settings from the given state directory, using the default settings if they cannot be loaded
+    pub fn load(state_directory: impl AsRef<Path>) -> Self {
+        let path = state_directory.as_ref().join(SETTINGS_FILE_NAME);
+
+        let settings = read_settings(&path).unwrap_or_else(|err| {
+            tracing::error!("{err:#}");
+            Settings::default()
+        });
+
+        Self {
+            path,
+            settings,
+            save_at: None,
+            save_task: None,
+        }
+    }
+
+    /// Reload the settings from disk, in case they have been edited externally.
+    /// Unsaved changes are newer than the file, so the file isn't read until they have been saved
+    pub fn reload(&mut self) {
+        if self.save_at.is_some() || self.save_task.is_some() {
+            return;
+        }
+
+        match read_settings(&self.path) {
+            Ok(settings) => self.settings = settings,
+            Err(err) => tracing::error!("{err:#}"),
+        }
+    }
+
+    pub fn settings(&self) -> &Settings {
+        &self.settings
+    }
+
+    pub fn volume(&self) -> Option<i32> {
+        self.settings.volume
+    }
+
+    pub fn is_muted(&self) -> bool {
+        self.settings.is_muted
+    }
+
+    /// Modify the settings, scheduling a save if they have changed. Returns true if the settings have changed
+    pub fn update(&mut self, f: impl FnOnce(&mut Settings)) -> bool {
+        let mut settings = self.settings.clone();
+
+        f(&mut settings);
+
+        if settings == self.settings {
+            return false;
+        }
+
+        self.settings = settings;
+
+        self.save_at
+            .get_or_insert_with(|| Instant::now() + SAVE_DELAY);
+
+        true
+    }
+
+    /// Start saving the settings if they were changed more than [`SAVE_DELAY`] ago and no other save is in progress
+    pub fn save_if_due(&mut self) {
+        if self
+            .save_task
+            .as_ref()
+            .is_some_and(tokio::task::JoinHandle::is_finished)
+        {
+            self.save_task = None;
+        }
+
+        let is_due = self
+            .save_at
+            .is_some_and(|save_at| save_at <= Instant::now());
+
+        if self.save_task.is_some() || !is_due {
+            return;
+        }
+
+        self.save_at = None;
+
+        let path = self.path.clone();
+
+        let settings = match toml::to_string(&self.settings).context("Failed to serialize settings")
+        {
+            Ok(settings) => settings,
+            Err(err) => {
+                tracing::error!("{err:#}");
+                return;
+            }
+        };
+
+        self.save_task = Some(tokio::task::spawn_blocking(move || {
+            if let Err(err) = crate::state_file::write(&path, settings) {
+                tracing::error!("{err:#}");
+            }
+        }));
+    }
+
+    /// Save any unsaved changes immediately, and wait for all saves to finish. Called on shutdown
+    pub async fn flush(&mut self) {
+        if self.save_at.is_some() {
+            self.save_at = Some(Instant::now());
+        }
+
+        loop {
+            if let Some(save_task) = self.save_task.take() {
+                if let Err(err) = save_task.await {
+                    tracing::error!("Failed to save settings: {err}");
+                }
+            }
+
+            if self.save_at.is_none() {
+                break;
+            }
+
+            self.save_if_due();
+        }
+    }
+}
+
+fn read_settings(path: &Path) -> Result<Settings> {
+    let Some(settings) = crate::state_file::read_to_string(path)? else {
+        tracing::debug!("{} not found, using default settings", path.display());
+        return Ok(Settings::default());
+    };
+
+    toml::from_str(&settings).with_context(|| format!("Failed to parse {}", path.display()))
+}
@@ -0,0 +1,82 @@
+//! A task which sends the configured commands when rradio receives `SIGUSR1` or `SIGUSR2`, so that GPIO daemons, cron jobs and scripts can control playback without connecting to a port
+
+use anyhow::Result;
+
+use crate::{config::signal_commands::Config, ports::PortChannels};
+
+#[cfg(unix)]
+pub async fn run(port_channels: PortChannels, config: Config) -> Result<()> {
+    use anyhow::Context;
+    use futures_util::StreamExt;
+    use tokio::signal::unix::SignalKind;
+
+    use crate::ports::CommandSendError;
+
+    let signals = [
+        ("SIGUSR1", SignalKind::user_defined1(), config.sigusr1),
+        ("SIGUSR2", SignalKind::user_defined2(), config.sigusr2),
+    ];
+
+    let mut signal_streams = Vec::new();
+
+    for (name, kind, command) in signals {
+        let Some(command) = command else {
+            continue;
+        };
+
+        let signal = tokio::signal::unix::signal(kind)
+            .with_context(|| format!("Failed to listen for {name}"))?;
+
+        let commands_tx = port_channels
+            .commands_tx
+            .with_source(format!("signal {name}"))
+            .with_privilege(true);
+
+        tracing::debug!(?command, "Listening for {name}");
+
+        signal_streams.push(
+            futures_util::stream::unfold(signal, |mut signal| async move {
+                signal.recv().await?;
+                Some(((), signal))
+            })
+            .map(move |()| (name, commands_tx.clone(), command.clone()))
+            .boxed(),
+        );
+    }
+
+    if signal_streams.is_empty() {
+        return Ok(());
+    }
+
+    let mut received_signals = futures_util::stream::select_all(signal_streams)
+        .take_until(port_channels.shutdown_signal.clone());
+
+    while let Some((name, commands_tx, command)) = received_signals.next().await {
+        tracing::debug!(?command, "Received {name}");
+
+        match commands_tx.send(command) {
+            Ok(()) => (),
+            // A signal can't be replied to, so the sender can only find out from the log
+            Err(CommandSendError::QueueFull) => {
+                tracing::error!(
+                    "Too many commands are waiting to be processed, so {name} was ignored"
+                );
+            }
+            Err(err @ CommandSendError::Closed) => return Err(err.into()),
+        }
+    }
+
+    tracing::debug!("Shut down");
+
+    Ok(())
+}
+
+#[cfg(not(unix))]
+#[allow(clippy::unused_async)]
+pub async fn run(_port_channels: PortChannels, config: Config) -> Result<()> {
+    if config.sigusr1.is_some() || config.sigusr2.is_some() {
+        tracing::warn!("Signal commands are only supported on unix");
+    }
+
+    Ok(())
+}
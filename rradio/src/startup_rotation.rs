@@ -0,0 +1,110 @@
+//! Choosing the next station of the startup rotation. The most recently chosen station is stored as a TOML file in the state directory,
+//! so that the rotation continues where it left off after a restart
+
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result};
+
+use rradio_messages::StationIndex;
+
+const STARTUP_ROTATION_FILE_NAME: &str = "startup_rotation.toml";
+
+#[derive(Debug, serde::Deserialize, serde::Serialize)]
+struct RotationPosition {
+    /// The station rather than its position in the rotation is stored, so that the rotation continues from the same station if stations are added or removed
+    last_station: StationIndex,
+}
+
+fn path(state_directory: impl AsRef<Path>) -> PathBuf {
+    state_directory.as_ref().join(STARTUP_ROTATION_FILE_NAME)
+}
+
+fn load(state_directory: impl AsRef<Path>) -> Option<StationIndex> {
+    let path = path(state_directory);
+
+    let position = crate::state_file::read_to_string(&path)
+        .map_err(|err| tracing::error!("{err:#}"))
+        .ok()??;
+
+    toml::from_str::<RotationPosition>(&position)
+        .map(|position| position.last_station)
+        .map_err(|err| tracing::error!("Failed to parse {}: {err}", path.display()))
+        .ok()
+}
+
+fn save(state_directory: impl AsRef<Path>, last_station: &StationIndex) -> Result<()> {
+    let position = toml::to_string(&RotationPosition {
+        last_station: last_station.clone(),
+    })
+    .context("Failed to serialize startup rotation position")?;
+
+    crate::state_file::write(&path(state_directory), position)
+}
+
+/// The station after `last_station` in `stations`, or the first station if `last_station` isn't in the rotation
+fn following<'a>(
+    stations: &'a [StationIndex],
+    last_station: Option<&StationIndex>,
+) -> Option<&'a StationIndex> {
+    let next_position = last_station
+        .and_then(|last_station| stations.iter().position(|station| station == last_station))
+        .map_or(0, |position| (position + 1) % stations.len());
+
+    stations.get(next_position)
+}
+
+/// Choose the next station of the rotation, and remember it so that the following call chooses the station after it.
+/// Returns `None` if the rotation is empty
+pub fn next_station(
+    state_directory: impl AsRef<Path>,
+    stations: &[StationIndex],
+) -> Option<StationIndex> {
+    let state_directory = state_directory.as_ref();
+
+    let station = following(stations, load(state_directory).as_ref())?.clone();
+
+    if let Err(err) = save(state_directory, &station) {
+        tracing::error!("{err:#}");
+    }
+
+    Some(station)
+}
+
+#[cfg(test)]
+mod tests {
+    use rradio_messages::StationIndex;
+
+    fn stations(indices: &[&str]) -> Vec<StationIndex> {
+        indices
+            .iter()
+            .map(|&index| StationIndex::new(index.into()))
+            .collect()
+    }
+
+    #[test]
+    fn stations_are_chosen_in_turn() {
+        let state_directory = tempfile::tempdir().unwrap();
+
+        let rotation = stations(&["01", "05", "07"]);
+
+        let chosen = (0..4)
+            .map(|_| super::next_station(state_directory.path(), &rotation).unwrap())
+            .collect::<Vec<_>>();
+
+        assert_eq!(chosen, stations(&["01", "05", "07", "01"]));
+    }
+
+    #[test]
+    fn rotation_restarts_if_the_last_station_is_removed() {
+        let rotation = stations(&["01", "05"]);
+
+        let removed_station = StationIndex::new("03".into());
+
+        assert_eq!(
+            super::following(&rotation, Some(&removed_station)),
+            rotation.first()
+        );
+
+        assert_eq!(super::following(&[], None), None);
+    }
+}
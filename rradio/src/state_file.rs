@@ -0,0 +1,150 @@
+//! Reading and replacing the files which rradio keeps, such as those in the state directory.
+//! Files are replaced by writing a temporary file alongside them and renaming it over them, so that they're never partially written
+
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result};
+
+/// The contents of the file at `path`, or `None` if it doesn't exist
+pub fn read(path: &Path) -> Result<Option<Vec<u8>>> {
+    match std::fs::read(path) {
+        Ok(contents) => Ok(Some(contents)),
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(None),
+        Err(err) => Err(err).with_context(|| format!("Failed to read {}", path.display())),
+    }
+}
+
+/// The contents of the file at `path` as text, or `None` if it doesn't exist
+pub fn read_to_string(path: &Path) -> Result<Option<String>> {
+    match std::fs::read_to_string(path) {
+        Ok(contents) => Ok(Some(contents)),
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(None),
+        Err(err) => Err(err).with_context(|| format!("Failed to read {}", path.display())),
+    }
+}
+
+/// Parse the JSON file at `path`, or return the default value if it doesn't exist
+pub fn read_json<T: serde::de::DeserializeOwned + Default>(path: &Path) -> Result<T> {
+    match read(path)? {
+        Some(contents) => serde_json::from_slice(&contents)
+            .with_context(|| format!("Failed to parse {}", path.display())),
+        None => Ok(T::default()),
+    }
+}
+
+/// Replace the file at `path` with `contents`, creating its directory if it doesn't exist
+pub fn write(path: &Path, contents: impl AsRef<[u8]>) -> Result<()> {
+    replace(path, contents.as_ref(), |_| Ok(()))
+}
+
+/// Replace the file at `path` with `value` as JSON
+pub fn write_json<T: serde::Serialize + ?Sized>(path: &Path, value: &T) -> Result<()> {
+    let contents = serde_json::to_vec(value)
+        .with_context(|| format!("Failed to serialize {}", path.display()))?;
+
+    write(path, contents)
+}
+
+/// Replace the file at `path` with `contents`, such as a new release of rradio.
+/// The file is made executable before it replaces the existing file, so that there's never a file which can't be run
+pub fn write_executable(path: &Path, contents: impl AsRef<[u8]>) -> Result<()> {
+    replace(path, contents.as_ref(), |temporary_path| {
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+
+            std::fs::set_permissions(temporary_path, std::fs::Permissions::from_mode(0o755))
+                .with_context(|| {
+                    format!("Failed to make {} executable", temporary_path.display())
+                })?;
+        }
+
+        #[cfg(not(unix))]
+        let _ = temporary_path;
+
+        Ok(())
+    })
+}
+
+/// The file which is written and then renamed to `path`, e.g. `settings.toml.tmp` for `settings.toml`
+fn temporary_path(path: &Path) -> PathBuf {
+    let mut temporary_path = path.as_os_str().to_owned();
+    temporary_path.push(".tmp");
+    temporary_path.into()
+}
+
+/// Write `contents` to a temporary file, call `prepare` with its path, and then rename it to `path`
+fn replace(path: &Path, contents: &[u8], prepare: impl FnOnce(&Path) -> Result<()>) -> Result<()> {
+    if let Some(parent) = path
+        .parent()
+        .filter(|parent| !parent.as_os_str().is_empty())
+    {
+        std::fs::create_dir_all(parent)
+            .with_context(|| format!("Failed to create {}", parent.display()))?;
+    }
+
+    let temporary_path = temporary_path(path);
+
+    std::fs::write(&temporary_path, contents)
+        .with_context(|| format!("Failed to write {}", temporary_path.display()))?;
+
+    prepare(&temporary_path)?;
+
+    std::fs::rename(&temporary_path, path)
+        .with_context(|| format!("Failed to replace {}", path.display()))
+}
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn missing_files_are_none() {
+        let directory = tempfile::tempdir().unwrap();
+        let path = directory.path().join("missing.json");
+
+        assert!(super::read(&path).unwrap().is_none());
+        assert!(super::read_to_string(&path).unwrap().is_none());
+        assert_eq!(
+            super::read_json::<Vec<u32>>(&path).unwrap(),
+            Vec::<u32>::new()
+        );
+    }
+
+    #[test]
+    fn files_are_replaced_without_leaving_temporary_files() {
+        let directory = tempfile::tempdir().unwrap();
+        let path = directory.path().join("state").join("numbers.json");
+
+        super::write_json(&path, &[1, 2]).unwrap();
+        super::write_json(&path, &[3]).unwrap();
+
+        assert_eq!(super::read_json::<Vec<u32>>(&path).unwrap(), [3]);
+        assert_eq!(
+            std::fs::read_dir(path.parent().unwrap()).unwrap().count(),
+            1
+        );
+    }
+
+    #[test]
+    fn bad_json_is_an_error() {
+        let directory = tempfile::tempdir().unwrap();
+        let path = directory.path().join("bad.json");
+
+        super::write(&path, "not json").unwrap();
+
+        assert!(super::read_json::<Vec<u32>>(&path).is_err());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn executables_can_be_run() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let directory = tempfile::tempdir().unwrap();
+        let path = directory.path().join("rradio");
+
+        super::write_executable(&path, "#!/bin/sh\n").unwrap();
+
+        let mode = std::fs::metadata(&path).unwrap().permissions().mode();
+        assert_eq!(mode & 0o111, 0o111);
+    }
+}
@@ -0,0 +1,130 @@
+//! Friendly names for stations, so that stations can be selected by e.g. "jazz" or "radio 4" as well as by index
+
+use std::collections::{BTreeMap, BTreeSet};
+
+use rradio_messages::{ArcStr, StationIndex};
+
+use super::{Error, StationListing};
+
+/// Lowercase, with punctuation removed and whitespace collapsed, so that "BBC Radio 4" matches "bbc radio-4"
+pub fn normalise(name: &str) -> String {
+    name.split(|c: char| !c.is_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .map(str::to_lowercase)
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// Aliases match if they're the same once normalised, ignoring spaces, so that "bbc4" matches "BBC 4"
+fn matches(name: &str, alias: &str) -> bool {
+    normalise(name).replace(' ', "") == alias
+}
+
+/// The only index in `indices`, or an error if there are several
+fn only_index(alias: &str, indices: BTreeSet<StationIndex>) -> Result<Option<StationIndex>, Error> {
+    if indices.len() > 1 {
+        return Err(Error::AmbiguousStationAlias {
+            alias: alias.into(),
+            indices: indices.into_iter().collect(),
+        });
+    }
+
+    Ok(indices.into_iter().next())
+}
+
+/// Find the station with the given alias. Configured aliases are checked first, then station names
+pub fn find(
+    aliases: &BTreeMap<ArcStr, StationIndex>,
+    stations: &[StationListing],
+    alias: &str,
+) -> Result<Option<StationIndex>, Error> {
+    let normalised_alias = normalise(alias).replace(' ', "");
+
+    if normalised_alias.is_empty() {
+        return Ok(None);
+    }
+
+    let configured = aliases
+        .iter()
+        .filter(|(name, _)| matches(name, &normalised_alias))
+        .map(|(_, index)| index.clone())
+        .collect();
+
+    if let Some(index) = only_index(alias, configured)? {
+        return Ok(Some(index));
+    }
+
+    let named = stations
+        .iter()
+        .filter(|station| matches(&station.name, &normalised_alias))
+        .map(|station| station.index.clone())
+        .collect();
+
+    only_index(alias, named)
+}
+
+/// Find the station with the given alias, listing the stations in the stations directory if there isn't a configured alias
+pub fn resolve(config: &crate::config::Config, alias: &str) -> Result<Option<StationIndex>, Error> {
+    if let Some(index) = find(&config.station_aliases, &[], alias)? {
+        return Ok(Some(index));
+    }
+
+    find(&BTreeMap::new(), &super::list(config)?, alias)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use rradio_messages::{arcstr, StationIndex};
+
+    use super::{find, Error, StationListing};
+
+    fn index(index: &str) -> StationIndex {
+        StationIndex::new(index.into())
+    }
+
+    #[test]
+    fn aliases() {
+        let aliases = vec![
+            (arcstr::literal!("bbc4"), index("04")),
+            (arcstr::literal!("Jazz"), index("12")),
+        ]
+        .into_iter()
+        .collect::<BTreeMap<_, _>>();
+
+        let stations = [("04", "BBC Radio 4"), ("05", "Jazz FM"), ("06", "Radio 4")]
+            .iter()
+            .map(|&(station_index, name)| StationListing {
+                index: index(station_index),
+                name: String::from(name),
+                is_temporary: false,
+            })
+            .collect::<Vec<_>>();
+
+        let find_alias = |alias| find(&aliases, &stations, alias);
+
+        assert_eq!(find_alias("BBC 4").unwrap(), Some(index("04")));
+        assert_eq!(find_alias("jazz").unwrap(), Some(index("12")));
+        assert_eq!(find_alias("bbc radio-4").unwrap(), Some(index("04")));
+        assert_eq!(find_alias("jazz fm").unwrap(), Some(index("05")));
+        assert_eq!(find_alias("heavy metal").unwrap(), None);
+        assert_eq!(find_alias("").unwrap(), None);
+
+        let stations = [("07", "Radio 4"), ("08", "radio-4")]
+            .iter()
+            .map(|&(station_index, name)| StationListing {
+                index: index(station_index),
+                name: String::from(name),
+                is_temporary: false,
+            })
+            .collect::<Vec<_>>();
+
+        match find(&BTreeMap::new(), &stations, "radio 4") {
+            Err(Error::AmbiguousStationAlias { indices, .. }) => {
+                assert_eq!(indices, vec![index("07"), index("08")]);
+            }
+            result => panic!("Unexpected result: {result:?}"),
+        }
+    }
+}
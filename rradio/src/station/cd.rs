@@ -329,6 +329,25 @@ pub fn tracks(device: &str) -> Result<Vec<Track>> {
         .collect()
 }
 
+/// Check that there's a CD drive at `device`, whether or not it contains a disc
+pub fn check_drive(device: &str) -> Result<()> {
+    use std::os::unix::fs::OpenOptionsExt;
+
+    let mut device = std::fs::OpenOptions::new()
+        .custom_flags(libc::O_NONBLOCK)
+        .read(true)
+        .open(device)
+        .map_err(|err| CdError::FailedToOpenDevice {
+            code: err.raw_os_error(),
+            message: arcstr::format!("{err}"),
+        })?;
+
+    match device.ioctl(CDROM_DRIVE_STATUS).map_err(ioctl_error)? {
+        0..=4 => Ok(()), // CDS_NO_INFO to CDS_DISC_OK
+        n => Err(CdError::UnknownDriveStatus(n as isize)),
+    }
+}
+
 fn cd_track(device: &mut std::fs::File, track_index: u8, track_count: u8) -> Result<Option<Track>> {
     let mut toc_entry = CdTocEntry {
         cdte_track: track_index,
@@ -354,8 +373,12 @@ fn cd_track(device: &mut std::fs::File, track_index: u8, track_count: u8) -> Res
             )),
             album: None,
             artist: None,
+            track_number: Some(track_index.into()),
+            disc_number: None,
             url: rradio_messages::arcstr::format!("cdda://{}", track_index),
             is_notification: false,
+            start_offset: None,
+            mirrors: Vec::new(),
         }))
     }
 }
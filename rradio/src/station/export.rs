@@ -0,0 +1,122 @@
+//! Export a list of tracks as a playlist document
+
+use std::{fmt::Write, path::Path};
+
+use rradio_messages::{PlaylistFormat, Track};
+
+/// The location of a track in the document. Local files within `base_directory` are relative to `base_directory`
+fn track_location(track: &Track, base_directory: Option<&Path>) -> String {
+    base_directory
+        .and_then(|base_directory| {
+            let path = url::Url::parse(&track.url).ok()?.to_file_path().ok()?;
+            let relative_path = path.strip_prefix(base_directory).ok()?;
+
+            relative_path.to_str().map(String::from)
+        })
+        .unwrap_or_else(|| track.url.to_string())
+}
+
+fn track_title(track: &Track) -> Option<String> {
+    let title = track.title.as_deref()?;
+
+    Some(match &track.artist {
+        Some(artist) => format!("{artist} - {title}"),
+        None => String::from(title),
+    })
+}
+
+/// Write `tracks` as a playlist document in the given format, skipping notifications
+pub fn playlist_document(
+    format: PlaylistFormat,
+    title: Option<&str>,
+    tracks: &[Track],
+    base_directory: Option<&Path>,
+) -> String {
+    let tracks = tracks.iter().filter(|track| !track.is_notification);
+
+    let mut document = String::new();
+
+    // Writing to a String cannot fail
+    match format {
+        PlaylistFormat::M3u8 => {
+            writeln!(document, "#EXTM3U").unwrap();
+
+            if let Some(title) = title {
+                writeln!(document, "#PLAYLIST:{title}").unwrap();
+            }
+
+            for track in tracks {
+                if let Some(title) = track_title(track) {
+                    writeln!(document, "#EXTINF:-1,{title}").unwrap();
+                }
+
+                writeln!(document, "{}", track_location(track, base_directory)).unwrap();
+            }
+        }
+        PlaylistFormat::Pls => {
+            writeln!(document, "[playlist]").unwrap();
+
+            let mut track_count = 0;
+
+            for (track, number) in tracks.zip(1..) {
+                writeln!(
+                    document,
+                    "File{number}={}",
+                    track_location(track, base_directory)
+                )
+                .unwrap();
+
+                if let Some(title) = track_title(track) {
+                    writeln!(document, "Title{number}={title}").unwrap();
+                }
+
+                writeln!(document, "Length{number}=-1").unwrap();
+
+                track_count = number;
+            }
+
+            writeln!(document, "NumberOfEntries={track_count}").unwrap();
+            writeln!(document, "Version=2").unwrap();
+        }
+    }
+
+    document
+}
+
+#[cfg(test)]
+mod tests {
+    use rradio_messages::{PlaylistFormat, Track};
+
+    fn tracks() -> Vec<Track> {
+        vec![
+            Track {
+                title: Some("A".into()),
+                artist: Some("X".into()),
+                ..Track::url("file:///tmp/usb/music/a.mp3".into())
+            },
+            Track::notification("file:///usr/share/sounds/ready.ogg".into()),
+            Track::url("http://example.com/b.mp3".into()),
+        ]
+    }
+
+    #[test]
+    fn m3u8() {
+        assert_eq!(
+            super::playlist_document(
+                PlaylistFormat::M3u8,
+                Some("P"),
+                &tracks(),
+                Some(std::path::Path::new("/tmp/usb"))
+            ),
+            "#EXTM3U\n#PLAYLIST:P\n#EXTINF:-1,X - A\nmusic/a.mp3\nhttp://example.com/b.mp3\n"
+        );
+    }
+
+    #[test]
+    fn pls() {
+        assert_eq!(
+            super::playlist_document(PlaylistFormat::Pls, Some("P"), &tracks(), None),
+            "[playlist]\nFile1=file:///tmp/usb/music/a.mp3\nTitle1=X - A\nLength1=-1\nFile2=http://example.com/b.mp3\nLength2=-1\nNumberOfEntries=2\nVersion=2\n"
+        );
+    }
+}
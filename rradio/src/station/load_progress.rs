@@ -0,0 +1,71 @@
+//! Progress reports while loading stations whose tracks take a long time to find
+
+use std::time::{Duration, Instant};
+
+use rradio_messages::{ArcStr, StationIndex, StationLoadProgress};
+
+/// The minimum time between reports, so that clients aren't flooded with events
+const REPORT_INTERVAL: Duration = Duration::from_millis(250);
+
+/// Counts the containers scanned and tracks found while loading a station, and periodically reports them
+pub struct LoadProgress<'a> {
+    index: Option<StationIndex>,
+    start: Instant,
+    last_report: Option<Instant>,
+    containers_scanned: usize,
+    report: Box<dyn FnMut(StationLoadProgress) + Send + 'a>,
+}
+
+impl<'a> LoadProgress<'a> {
+    pub fn new(
+        index: Option<StationIndex>,
+        report: impl FnMut(StationLoadProgress) + Send + 'a,
+    ) -> Self {
+        Self {
+            index,
+            start: Instant::now(),
+            last_report: None,
+            containers_scanned: 0,
+            report: Box::new(report),
+        }
+    }
+
+    /// Report that the server which hosts the station is still being woken up
+    pub fn waking_server(&mut self, host: &ArcStr) {
+        let now = Instant::now();
+
+        self.last_report = Some(now);
+
+        (self.report)(StationLoadProgress {
+            index: self.index.clone(),
+            waking_server: Some(host.clone()),
+            containers_scanned: 0,
+            tracks_found: 0,
+            elapsed: now - self.start,
+        });
+    }
+
+    /// Record that another container has been scanned, and that `tracks_found` tracks have been found so far
+    pub fn container_scanned(&mut self, tracks_found: usize) {
+        self.containers_scanned += 1;
+
+        let now = Instant::now();
+
+        if self
+            .last_report
+            .is_some_and(|last_report| now - last_report < REPORT_INTERVAL)
+        {
+            return;
+        }
+
+        self.last_report = Some(now);
+
+        (self.report)(StationLoadProgress {
+            index: self.index.clone(),
+            waking_server: None,
+            containers_scanned: self.containers_scanned,
+            tracks_found,
+            elapsed: now - self.start,
+        });
+    }
+}
@@ -1,12 +1,22 @@
 //! A radio station in rradio
-use std::{any::Any, fmt, sync::Arc};
+use std::{any::Any, ffi::OsString, fmt, sync::Arc};
 
+pub use parse_upnp::{browse as browse_upnp, search as search_upnp};
 use rradio_messages::{arcstr, ArcStr, StationIndex, StationType};
 pub use rradio_messages::{StationError as Error, Track};
 
+mod alias;
+mod export;
+mod load_progress;
+mod parse_custom;
 mod parse_m3u;
 mod parse_pls;
 mod parse_upnp;
+pub mod podcast;
+mod reachability;
+mod temporary;
+mod url_template;
+mod wake_on_lan;
 
 #[cfg(feature = "mount")]
 mod mount;
@@ -14,13 +24,24 @@ mod mount;
 #[cfg(feature = "cd")]
 mod cd;
 
+#[cfg(feature = "sdr")]
+mod sdr;
+
 #[cfg(feature = "cd")]
-pub use cd::eject as eject_cd;
+pub use cd::{check_drive as check_cd_drive, eject as eject_cd};
+
+#[cfg(feature = "web")]
+pub use alias::{find as find_alias, normalise as normalise_name};
+pub use export::playlist_document;
+pub use load_progress::LoadProgress;
+pub use temporary::{add as add_temporary_station, is_temporary as is_temporary_station};
+pub use wake_on_lan::Server as WakeOnLanServer;
 
-#[derive(Debug, PartialEq)]
+/// The credentials of a network share. The password is hidden when debug formatted, so that it isn't logged
+#[derive(Debug)]
 pub struct Credentials {
     username: String,
-    password: String,
+    password: crate::config::Secret,
 }
 
 #[derive(Clone)]
@@ -30,6 +51,49 @@ impl PlaylistMetadata {
     fn new(metadata: impl Any + Send + Sync + 'static) -> Self {
         Self(Arc::new(metadata))
     }
+
+    /// The metadata in a form which can be saved, or `None` if the playlist has no metadata
+    pub fn to_saved(&self) -> Option<SavedPlaylistMetadata> {
+        #[cfg(feature = "mount")]
+        if let Some(mount::SelectedDirectories { artist, album }) = self.0.downcast_ref() {
+            return Some(SavedPlaylistMetadata::Directories {
+                artist: artist.clone(),
+                album: album.clone(),
+            });
+        }
+
+        self.0.downcast_ref().map(
+            |parse_upnp::Metadata {
+                 station_index,
+                 station_title,
+                 tracks,
+             }| SavedPlaylistMetadata::UPnP {
+                station_index: station_index.clone(),
+                station_title: station_title.clone(),
+                tracks: tracks.clone(),
+            },
+        )
+    }
+
+    pub fn from_saved(metadata: SavedPlaylistMetadata) -> Self {
+        match metadata {
+            #[cfg(feature = "mount")]
+            SavedPlaylistMetadata::Directories { artist, album } => {
+                Self::new(mount::SelectedDirectories::new(artist, album))
+            }
+            #[cfg(not(feature = "mount"))]
+            SavedPlaylistMetadata::Directories { .. } => Self::default(),
+            SavedPlaylistMetadata::UPnP {
+                station_index,
+                station_title,
+                tracks,
+            } => Self::new(parse_upnp::Metadata {
+                station_index,
+                station_title,
+                tracks,
+            }),
+        }
+    }
 }
 
 impl fmt::Debug for PlaylistMetadata {
@@ -46,13 +110,56 @@ impl Default for PlaylistMetadata {
     }
 }
 
+/// The metadata of a playlist in a form which can be saved with the playback position, so that a station resumed after a restart chooses the same tracks
+#[derive(Debug, serde::Deserialize, serde::Serialize)]
+pub enum SavedPlaylistMetadata {
+    /// The artist and album directories chosen by a USB station
+    Directories { artist: OsString, album: OsString },
+    /// The tracks found by a `UPnP` station
+    UPnP {
+        station_index: Option<StationIndex>,
+        station_title: Option<String>,
+        tracks: Vec<Track>,
+    },
+}
+
 pub struct PlaylistHandle(Box<dyn Any + Send + Sync>);
 
 impl PlaylistHandle {
-    #[cfg(feature = "mount")]
+    #[cfg(any(feature = "mount", feature = "sdr"))]
     fn new(handle: impl Any + Send + Sync + 'static) -> Self {
         Self(Box::new(handle))
     }
+
+    /// The directory where the playlist's device is mounted, if the playlist is on a mounted device
+    #[cfg(feature = "mount")]
+    pub fn mounted_directory(&self) -> Option<&std::path::Path> {
+        self.0
+            .downcast_ref::<Arc<dyn mount::Handle>>()
+            .map(|handle| mount::Handle::mounted_directory(handle.as_ref()))
+    }
+
+    /// The directory where the playlist's device is mounted, if the playlist is on a mounted device
+    #[cfg(not(feature = "mount"))]
+    #[allow(clippy::unused_self)]
+    pub fn mounted_directory(&self) -> Option<&std::path::Path> {
+        None
+    }
+
+    /// Restart the tuner, if the playlist is received with an SDR dongle, as the tuner's output can't be played again once it's been read
+    #[cfg(feature = "sdr")]
+    pub fn restart_tuner(&mut self) -> Result<(), Error> {
+        self.0
+            .downcast_mut::<sdr::Tuner>()
+            .map_or(Ok(()), sdr::Tuner::restart)
+    }
+
+    /// Restart the tuner, if the playlist is received with an SDR dongle, as the tuner's output can't be played again once it's been read
+    #[cfg(not(feature = "sdr"))]
+    #[allow(clippy::unused_self, clippy::unnecessary_wraps)]
+    pub fn restart_tuner(&mut self) -> Result<(), Error> {
+        Ok(())
+    }
 }
 
 impl fmt::Debug for PlaylistHandle {
@@ -95,10 +202,36 @@ pub enum Station {
     #[cfg(feature = "usb")]
     Usb {
         index: StationIndex,
+        mount_backend: crate::config::mount::Backend,
         device: String,
         path: std::path::PathBuf,
+        /// Where to store indices of USB sticks, if indexing is enabled
+        index_directory: Option<std::path::PathBuf>,
+        favour_least_recently_played: bool,
+    },
+    /// A file or directory on the USB device, played without a station file
+    #[cfg(feature = "usb")]
+    UsbPath {
+        mount_backend: crate::config::mount::Backend,
+        device: String,
+        path: std::path::PathBuf,
+        requested_path: std::path::PathBuf,
+        recursive: bool,
+    },
+    /// A file or directory on a network share, either played without a station file, or from a legacy `.txt` station
+    #[cfg(feature = "share")]
+    SharePath {
+        /// `None` unless loaded from a station file
+        index: Option<StationIndex>,
+        device: String,
+        credentials: Credentials,
+        path: std::path::PathBuf,
+        requested_path: std::path::PathBuf,
+        recursive: bool,
     },
     UPnP(parse_upnp::Station),
+    #[cfg(feature = "sdr")]
+    Sdr(sdr::Station),
 }
 
 /// Convert an [`std::io::Error`] into a [`rradio_messages::StationError::StationsDirectoryIoError`]
@@ -117,11 +250,184 @@ fn playlist_error<T>(result: anyhow::Result<T>) -> Result<T, Error> {
     result.map_err(|err| rradio_messages::StationError::BadStationFile(format!("{err:#}").into()))
 }
 
+/// The index and file stem of each file in the stations directory whose name starts with an index
+fn station_files(config: &crate::config::Config) -> Result<Vec<(StationIndex, String)>, Error> {
+    let directory = &config.stations_directory;
+
+    let mut files = Vec::new();
+
+    for entry in stations_directory_io_error(directory, std::fs::read_dir(directory.as_str()))? {
+        let path = stations_directory_io_error(directory, entry)?.path();
+
+        let Some(file_stem) = path
+            .file_stem()
+            .map(|file_stem| file_stem.to_string_lossy())
+        else {
+            continue;
+        };
+
+        let Some(index) = file_stem
+            .get(..2)
+            .filter(|index| index.chars().all(|c| c.is_ascii_digit()))
+        else {
+            continue;
+        };
+
+        files.push((StationIndex::new(index.into()), file_stem.into_owned()));
+    }
+
+    Ok(files)
+}
+
+/// A station in the stations directory
+#[derive(Debug)]
+pub struct StationListing {
+    pub index: StationIndex,
+    /// The station title if it has one, otherwise the file name without the index and extension
+    pub name: String,
+    /// Whether the station was created from a playlist pushed by a client, rather than loaded from the stations directory
+    pub is_temporary: bool,
+}
+
+/// List the stations in the stations directory, followed by the temporary stations and the podcast stations. Stations which fail to load are skipped
+pub fn list(config: &crate::config::Config) -> Result<Vec<StationListing>, Error> {
+    let mut stations = Vec::new();
+
+    for (index, file_stem) in station_files(config)? {
+        let station = match Station::load(config, index.clone()) {
+            Ok(station) => station,
+            Err(err) => {
+                tracing::debug!(%index, "Skipping station: {err}");
+                continue;
+            }
+        };
+
+        let name = station.title().map_or_else(
+            || {
+                file_stem[2..]
+                    .trim_start_matches(|c: char| c == '-' || c == '_' || c.is_whitespace())
+                    .to_owned()
+            },
+            String::from,
+        );
+
+        stations.push(StationListing {
+            index,
+            name,
+            is_temporary: false,
+        });
+    }
+
+    stations.sort_by(|a, b| a.index.cmp(&b.index));
+
+    stations.extend(temporary::list(config));
+    stations.extend(podcast::list(config));
+
+    Ok(stations)
+}
+
+/// Load every station in the stations directory, including those which fail to load
+pub fn load_all(
+    config: &crate::config::Config,
+) -> Result<Vec<(StationIndex, Result<Station, Error>)>, Error> {
+    Ok(station_files(config)?
+        .into_iter()
+        .map(|(index, _)| {
+            let station = Station::load(config, index.clone());
+            (index, station)
+        })
+        .collect())
+}
+
+/// Load every station in the stations directory, logging those which fail to load, and return the number of failures
+pub fn validate_all(config: &crate::config::Config) -> Result<usize, Error> {
+    let mut failure_count = 0;
+
+    for (index, station) in load_all(config)? {
+        if let Err(err) = station {
+            tracing::error!(%index, "Station failed to load: {err}");
+            failure_count += 1;
+        }
+    }
+
+    Ok(failure_count)
+}
+
+/// Where the indices of USB sticks are stored
+#[cfg(feature = "usb")]
+fn usb_index_directory(config: &crate::config::Config) -> std::path::PathBuf {
+    std::path::Path::new(config.state_directory.as_str()).join("usb_index")
+}
+
+/// Search the index of the USB stick for albums whose artist or album directory contain `query`, as `(artist, album)` pairs
+#[cfg(feature = "usb")]
+pub fn search_usb_index(
+    config: &crate::config::Config,
+    query: &str,
+) -> anyhow::Result<Vec<(String, String)>> {
+    anyhow::ensure!(config.usb_config.index, "USB sticks are not indexed");
+
+    mount::search_usb_index(
+        &usb_index_directory(config),
+        &config.usb_config.device,
+        query,
+    )
+}
+
 impl Station {
+    /// A music file or directory on the configured USB device
+    #[cfg(feature = "usb")]
+    pub fn usb_path(
+        config: &crate::config::Config,
+        requested_path: String,
+        recursive: bool,
+    ) -> Self {
+        Self::UsbPath {
+            mount_backend: config.mount_config.backend,
+            device: config.usb_config.device.to_string(),
+            path: config.usb_config.path.clone(),
+            requested_path: requested_path.into(),
+            recursive,
+        }
+    }
+
+    /// A music file or directory on the configured network share
+    #[cfg(feature = "share")]
+    pub fn share_path(
+        config: &crate::config::Config,
+        requested_path: String,
+        recursive: bool,
+    ) -> Result<Self, Error> {
+        let share = config
+            .share_config
+            .as_ref()
+            .ok_or(rradio_messages::MountError::ShareNotConfigured)?;
+
+        Ok(Self::SharePath {
+            index: None,
+            device: share.device.to_string(),
+            credentials: Credentials {
+                username: share.username.to_string(),
+                password: share.password.clone(),
+            },
+            path: share.path.clone(),
+            requested_path: requested_path.into(),
+            recursive,
+        })
+    }
+
     /// Load the station with the given index from the given directory, if the index exists
     pub fn load(config: &crate::config::Config, index: StationIndex) -> Result<Self, Error> {
         let directory = &config.stations_directory;
 
+        if let Some(station) = temporary::load(config, &index) {
+            return Ok(station);
+        }
+
+        if let Some(station) = podcast::load(config, &index) {
+            return Ok(station);
+        }
+
         #[cfg(feature = "cd")]
         if index.as_str() == config.cd_config.station {
             return Ok(Self::CD {
@@ -134,8 +440,11 @@ impl Station {
         if index.as_str() == config.usb_config.station {
             return Ok(Self::Usb {
                 index,
+                mount_backend: config.mount_config.backend,
                 device: config.usb_config.device.to_string(),
                 path: config.usb_config.path.clone(),
+                index_directory: config.usb_config.index.then(|| usb_index_directory(config)),
+                favour_least_recently_played: config.usb_config.favour_least_recently_played,
             });
         }
 
@@ -153,9 +462,19 @@ impl Station {
                     .to_string_lossy()
                     .as_ref()
                 {
-                    "m3u" => playlist_error(parse_m3u::from_file(&path, index)),
-                    "pls" => playlist_error(parse_pls::from_file(&path, index)),
+                    "m3u" => playlist_error(parse_m3u::from_file(&path, index))
+                        .map(|station| station.expand_url_templates(config.timezone)),
+                    "pls" => playlist_error(parse_pls::from_file(&path, index))
+                        .map(|station| station.expand_url_templates(config.timezone)),
                     "upnp" => playlist_error(parse_upnp::from_file(&path, index)),
+                    "txt" => playlist_error(parse_custom::from_file(&path, index, config))
+                        .map(|station| station.expand_url_templates(config.timezone)),
+                    #[cfg(feature = "sdr")]
+                    "sdr" => playlist_error(sdr::from_file(&path, index, config)),
+                    #[cfg(not(feature = "sdr"))]
+                    "sdr" => Err(Error::BadStationFile(arcstr::literal!(
+                        "SDR stations require the \"sdr\" feature"
+                    ))),
                     extension => Err(Error::BadStationFile(
                         format!("Unsupported format: \"{extension}\"").into(),
                     )),
@@ -169,6 +488,60 @@ impl Station {
         })
     }
 
+    /// Load the station with the given index, or if there isn't one, the station with the given alias
+    pub fn load_by_index_or_alias(
+        config: &crate::config::Config,
+        index: StationIndex,
+    ) -> Result<Self, Error> {
+        match Self::load(config, index) {
+            Err(Error::StationNotFound { index, directory }) => {
+                match alias::resolve(config, index.as_str())? {
+                    Some(aliased_index) => {
+                        tracing::debug!(alias = %index, index = %aliased_index, "Resolved alias");
+                        Self::load(config, aliased_index)
+                    }
+                    None => Err(Error::StationNotFound { index, directory }),
+                }
+            }
+            result => result,
+        }
+    }
+
+    /// Substitute the current date and time into the urls of URL list stations
+    fn expand_url_templates(self, timezone: crate::config::Timezone) -> Self {
+        match self {
+            Station::UrlList {
+                index,
+                title,
+                tracks,
+            } => {
+                let now = timezone.now();
+
+                let expand = |url: ArcStr| {
+                    if url.contains('{') {
+                        url_template::expand(&url, now).into()
+                    } else {
+                        url
+                    }
+                };
+
+                Station::UrlList {
+                    index,
+                    title,
+                    tracks: tracks
+                        .into_iter()
+                        .map(|track| Track {
+                            url: expand(track.url),
+                            mirrors: track.mirrors.into_iter().map(expand).collect(),
+                            ..track
+                        })
+                        .collect(),
+                }
+            }
+            station => station,
+        }
+    }
+
     pub fn index(&self) -> Option<&StationIndex> {
         match self {
             Station::UrlList { index, .. } => index.as_ref(),
@@ -176,7 +549,13 @@ impl Station {
             Station::CD { index, .. } => Some(index),
             #[cfg(feature = "usb")]
             Station::Usb { index, .. } => Some(index),
+            #[cfg(feature = "usb")]
+            Station::UsbPath { .. } => None,
+            #[cfg(feature = "share")]
+            Station::SharePath { index, .. } => index.as_ref(),
             Station::UPnP(station) => Some(station.index()),
+            #[cfg(feature = "sdr")]
+            Station::Sdr(station) => Some(station.index()),
         }
     }
 
@@ -187,7 +566,17 @@ impl Station {
             Station::CD { .. } => None,
             #[cfg(feature = "usb")]
             Station::Usb { .. } => None,
+            #[cfg(feature = "usb")]
+            Station::UsbPath { requested_path, .. } => {
+                requested_path.file_name().and_then(std::ffi::OsStr::to_str)
+            }
+            #[cfg(feature = "share")]
+            Station::SharePath { requested_path, .. } => {
+                requested_path.file_name().and_then(std::ffi::OsStr::to_str)
+            }
             Station::UPnP(station) => station.title(),
+            #[cfg(feature = "sdr")]
+            Station::Sdr(station) => station.title(),
         }
     }
 
@@ -197,15 +586,69 @@ impl Station {
             #[cfg(feature = "cd")]
             Station::CD { .. } => StationType::CD,
             #[cfg(feature = "usb")]
-            Station::Usb { .. } => StationType::Usb,
+            Station::Usb { .. } | Station::UsbPath { .. } => StationType::Usb,
+            #[cfg(feature = "share")]
+            Station::SharePath { .. } => StationType::Share,
             Station::UPnP(..) => StationType::UPnP,
+            #[cfg(feature = "sdr")]
+            Station::Sdr(..) => StationType::Sdr,
         }
     }
 
+    /// The url of the server which the station is loaded from, if any
+    fn server_url(&self) -> Option<&str> {
+        match self {
+            Station::UrlList { tracks, .. } => tracks.first().map(|track| track.url.as_str()),
+            #[cfg(feature = "cd")]
+            Station::CD { .. } => None,
+            #[cfg(feature = "usb")]
+            Station::Usb { .. } | Station::UsbPath { .. } => None,
+            #[cfg(feature = "share")]
+            Station::SharePath { .. } => None,
+            Station::UPnP(station) => Some(station.root_description_url().as_str()),
+            #[cfg(feature = "sdr")]
+            Station::Sdr(..) => None,
+        }
+    }
+
+    /// The server which hosts the station, if it's configured as a Wake-on-LAN target, so might need waking up before the station is loaded
+    pub fn wake_on_lan_server(
+        &self,
+        targets: &[crate::config::wake_on_lan::Target],
+    ) -> Option<WakeOnLanServer> {
+        WakeOnLanServer::find(targets, self.server_url()?)
+    }
+
+    /// Check that the first track of a URL list station is reachable. Other station types are not checked
+    pub async fn check_reachability(
+        &self,
+        timeout: std::time::Duration,
+        resolver: &crate::dns::Resolver,
+    ) -> Result<(), Error> {
+        match self {
+            Station::UrlList { tracks, .. } => match tracks.first() {
+                Some(track) => reachability::check(&track.url, timeout, resolver).await,
+                None => Ok(()),
+            },
+            #[cfg(feature = "cd")]
+            Station::CD { .. } => Ok(()),
+            #[cfg(feature = "usb")]
+            Station::Usb { .. } | Station::UsbPath { .. } => Ok(()),
+            #[cfg(feature = "share")]
+            Station::SharePath { .. } => Ok(()),
+            Station::UPnP(..) => Ok(()),
+            #[cfg(feature = "sdr")]
+            Station::Sdr(..) => Ok(()),
+        }
+    }
+
+    /// Load the tracks of the station. `play_statistics` are used by stations which favour tracks which haven't been played recently
     #[allow(clippy::unnecessary_wraps)]
     pub async fn into_playlist(
         self,
         metadata: Option<&PlaylistMetadata>,
+        progress: &mut LoadProgress<'_>,
+        play_statistics: &crate::play_statistics::PlayStatistics,
     ) -> Result<Playlist, Error> {
         match self {
             Station::UrlList {
@@ -232,10 +675,21 @@ impl Station {
             #[cfg(feature = "usb")]
             Station::Usb {
                 index,
+                mount_backend,
                 device,
                 path,
+                index_directory,
+                favour_least_recently_played,
             } => {
-                let (tracks, metadata, handle) = mount::usb(&device, &path, metadata)?;
+                let (tracks, metadata, handle) = mount::usb(
+                    mount_backend,
+                    &device,
+                    &path,
+                    index_directory.as_deref(),
+                    metadata,
+                    favour_least_recently_played.then_some(play_statistics),
+                    progress,
+                )?;
                 Ok(Playlist {
                     station_index: Some(index),
                     station_title: None,
@@ -245,9 +699,97 @@ impl Station {
                     handle,
                 })
             }
-            Station::UPnP(station) => station.into_playlist(metadata).await.map_err(|err| {
-                rradio_messages::StationError::UPnPError(arcstr::format!("{err:#}"))
-            }),
+            #[cfg(feature = "usb")]
+            Station::UsbPath {
+                mount_backend,
+                device,
+                path,
+                requested_path,
+                recursive,
+            } => {
+                let (tracks, handle) = mount::usb_path(
+                    mount_backend,
+                    &device,
+                    &path,
+                    &requested_path,
+                    recursive,
+                    progress,
+                )?;
+                Ok(Playlist {
+                    station_index: None,
+                    station_title: requested_path
+                        .file_name()
+                        .map(|name| name.to_string_lossy().into_owned()),
+                    station_type: StationType::Usb,
+                    tracks,
+                    metadata: PlaylistMetadata::default(),
+                    handle,
+                })
+            }
+            #[cfg(feature = "share")]
+            Station::SharePath {
+                index,
+                device,
+                credentials,
+                path,
+                requested_path,
+                recursive,
+            } => {
+                let (tracks, handle) = mount::share_path(
+                    &device,
+                    &credentials,
+                    &path,
+                    &requested_path,
+                    recursive,
+                    progress,
+                )?;
+                Ok(Playlist {
+                    station_index: index,
+                    station_title: requested_path
+                        .file_name()
+                        .map(|name| name.to_string_lossy().into_owned()),
+                    station_type: StationType::Share,
+                    tracks,
+                    metadata: PlaylistMetadata::default(),
+                    handle,
+                })
+            }
+            Station::UPnP(station) => station
+                .into_playlist(metadata, progress, play_statistics)
+                .await
+                .map_err(|err| {
+                    rradio_messages::StationError::UPnPError(arcstr::format!("{err:#}"))
+                }),
+            #[cfg(feature = "sdr")]
+            Station::Sdr(station) => station.into_playlist(),
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use rradio_messages::arcstr;
+
+    #[test]
+    fn stations_which_fail_to_load_are_counted() {
+        let directory = tempfile::tempdir().unwrap();
+        let stations_directory = directory.path().join("stations");
+        std::fs::create_dir(&stations_directory).unwrap();
+
+        std::fs::write(
+            stations_directory.join("01 Good.m3u"),
+            "http://example.com/stream\n",
+        )
+        .unwrap();
+        std::fs::write(stations_directory.join("02 Bad.xyz"), "").unwrap();
+        std::fs::write(stations_directory.join("README.md"), "Not a station").unwrap();
+
+        let config = crate::config::Config {
+            stations_directory: arcstr::format!("{}", stations_directory.display()),
+            state_directory: arcstr::format!("{}", directory.path().join("state").display()),
+            ..crate::config::Config::default()
+        };
+
+        assert_eq!(super::validate_all(&config).unwrap(), 1);
+    }
+}
@@ -0,0 +1,207 @@
+//! Parsing [cue sheets](https://en.wikipedia.org/wiki/Cue_sheet_(computing)), which split a single music file, such as an album rip, into tracks
+
+use std::time::Duration;
+
+/// Cue sheet times are in minutes, seconds and frames, of which there are 75 per second
+const FRAMES_PER_SECOND: u32 = 75;
+
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct CueSheet {
+    pub title: Option<String>,
+    pub performer: Option<String>,
+    pub tracks: Vec<CueTrack>,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub struct CueTrack {
+    /// The name of the music file, relative to the cue sheet
+    pub file: String,
+    pub number: u32,
+    pub title: Option<String>,
+    pub performer: Option<String>,
+    /// Where the track starts in the music file
+    pub start: Duration,
+}
+
+/// Read a cue sheet, which is usually UTF-8, but is often Windows-1252 if it was written by older ripping software
+pub fn from_file(path: &std::path::Path) -> std::io::Result<CueSheet> {
+    let cue_sheet = std::fs::read(path)?;
+
+    let cue_sheet = match String::from_utf8(cue_sheet) {
+        Ok(cue_sheet) => cue_sheet,
+        Err(err) => encoding_rs::WINDOWS_1252
+            .decode(err.as_bytes())
+            .0
+            .into_owned(),
+    };
+
+    Ok(from_str(&cue_sheet))
+}
+
+/// The value of a command, which is quoted if it contains spaces
+fn unquote(value: &str) -> String {
+    let value = value.trim();
+
+    String::from(
+        value
+            .strip_prefix('"')
+            .and_then(|value| value.rsplit_once('"'))
+            .map_or(value, |(value, _)| value),
+    )
+}
+
+/// Parse a time in the format `mm:ss:ff`
+fn parse_time(time: &str) -> Option<Duration> {
+    let mut parts = time.trim().split(':').map(str::parse::<u64>);
+
+    let minutes = parts.next()?.ok()?;
+    let seconds = parts.next()?.ok()?;
+    let frames = parts.next()?.ok()?;
+
+    if parts.next().is_some() {
+        return None;
+    }
+
+    Some(
+        Duration::from_secs(minutes * 60 + seconds)
+            + Duration::from_secs(frames) / FRAMES_PER_SECOND,
+    )
+}
+
+/// Add `track` to `sheet` if its start is known
+fn finish_track(sheet: &mut CueSheet, track: Option<CueTrack>, start: Option<Duration>) {
+    let Some(track) = track else {
+        return;
+    };
+
+    if let Some(start) = start {
+        sheet.tracks.push(CueTrack { start, ..track });
+    } else {
+        tracing::debug!(track.number, "Cue sheet track has no start");
+    }
+}
+
+/// Parse a cue sheet. Unknown commands and badly formatted lines are ignored, and tracks without an `INDEX 01` are skipped
+pub fn from_str(cue_sheet: &str) -> CueSheet {
+    let mut sheet = CueSheet::default();
+
+    let mut file = None::<String>;
+    let mut track = None::<CueTrack>;
+    let mut track_start = None::<Duration>;
+
+    for line in cue_sheet.trim_start_matches('\u{feff}').lines() {
+        let (command, value) = line
+            .trim()
+            .split_once(char::is_whitespace)
+            .unwrap_or((line.trim(), ""));
+
+        match (command.to_ascii_uppercase().as_str(), &mut track) {
+            ("FILE", _) => {
+                finish_track(&mut sheet, track.take(), track_start.take());
+
+                // The file type follows the file name, e.g. `FILE "Album.flac" WAVE`
+                let name = match value.trim().strip_prefix('"') {
+                    Some(value) => value.split_once('"').map_or(value, |(name, _)| name),
+                    None => value.split_whitespace().next().unwrap_or_default(),
+                };
+
+                file = Some(String::from(name));
+            }
+            ("TRACK", _) => {
+                finish_track(&mut sheet, track.take(), track_start.take());
+
+                let Some(file) = &file else {
+                    tracing::debug!("Cue sheet track has no file");
+                    continue;
+                };
+
+                let Some(number) = value
+                    .split_whitespace()
+                    .next()
+                    .and_then(|number| number.parse().ok())
+                else {
+                    tracing::debug!(line, "Bad cue sheet track number");
+                    continue;
+                };
+
+                track = Some(CueTrack {
+                    file: file.clone(),
+                    number,
+                    title: None,
+                    performer: None,
+                    start: Duration::ZERO,
+                });
+            }
+            ("TITLE", None) => sheet.title = Some(unquote(value)),
+            ("TITLE", Some(track)) => track.title = Some(unquote(value)),
+            ("PERFORMER", None) => sheet.performer = Some(unquote(value)),
+            ("PERFORMER", Some(track)) => track.performer = Some(unquote(value)),
+            ("INDEX", Some(_)) => {
+                // Index 0 is the pregap, which is played at the end of the previous track
+                if let Some(("01" | "1", time)) = value.trim().split_once(char::is_whitespace) {
+                    track_start = parse_time(time);
+
+                    if track_start.is_none() {
+                        tracing::debug!(line, "Bad cue sheet index");
+                    }
+                }
+            }
+            _ => (),
+        }
+    }
+
+    finish_track(&mut sheet, track, track_start);
+
+    sheet
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::{CueSheet, CueTrack};
+
+    #[test]
+    fn tracks_are_parsed() {
+        let cue_sheet = r#"
+            REM GENRE Rock
+            PERFORMER "The Band"
+            TITLE "The Album"
+            FILE "The Band - The Album.flac" WAVE
+              TRACK 01 AUDIO
+                TITLE "First Song"
+                INDEX 01 00:00:00
+              TRACK 02 AUDIO
+                TITLE "Second Song"
+                PERFORMER "The Band feat. Someone"
+                INDEX 00 03:58:40
+                INDEX 01 04:01:15
+              TRACK 03 AUDIO
+                TITLE "Hidden Track"
+        "#;
+
+        assert_eq!(
+            super::from_str(cue_sheet),
+            CueSheet {
+                title: Some("The Album".into()),
+                performer: Some("The Band".into()),
+                tracks: vec![
+                    CueTrack {
+                        file: "The Band - The Album.flac".into(),
+                        number: 1,
+                        title: Some("First Song".into()),
+                        performer: None,
+                        start: Duration::ZERO,
+                    },
+                    CueTrack {
+                        file: "The Band - The Album.flac".into(),
+                        number: 2,
+                        title: Some("Second Song".into()),
+                        performer: Some("The Band feat. Someone".into()),
+                        start: Duration::from_millis(241_200),
+                    },
+                ],
+            }
+        );
+    }
+}
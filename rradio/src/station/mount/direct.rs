@@ -1,7 +1,10 @@
+//! Mount devices directly using the `mount` syscall, which requires `CAP_SYS_ADMIN`
+
 use sys_mount::Unmount;
 
+use std::path::Path;
+
 use super::super::Credentials;
-use super::Handle;
 
 use rradio_messages::{arcstr, MountError};
 
@@ -21,6 +24,17 @@ impl<'a> MountBuilderExt<'a> for sys_mount::MountBuilder<'a> {
     }
 }
 
+pub(super) struct Handle {
+    _mount: sys_mount::UnmountDrop<sys_mount::Mount>,
+    mounted_directory: tempfile::TempDir,
+}
+
+impl super::Handle for Handle {
+    fn mounted_directory(&self) -> &Path {
+        self.mounted_directory.path()
+    }
+}
+
 pub(super) fn mount(
     device: &str,
     file_system_type: &str,
@@ -37,7 +51,7 @@ pub(super) fn mount(
         .maybe_data(&credentials.map(|credentials| {
             format!(
                 "user={},pass={},vers=3.0",
-                credentials.username, credentials.password
+                credentials.username, credentials.password.0
             )
         }))
         .mount(device, &mounted_directory)
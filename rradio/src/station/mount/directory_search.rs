@@ -6,12 +6,44 @@ use std::{
 
 use rand::{seq::SliceRandom, Rng};
 
-use rradio_messages::Track;
+use rradio_messages::{arcstr, Track};
+
+use crate::station::LoadProgress;
 
 #[derive(Clone)]
 pub struct SelectedDirectories {
-    artist: OsString,
-    album: OsString,
+    pub(in crate::station) artist: OsString,
+    pub(in crate::station) album: OsString,
+}
+
+impl SelectedDirectories {
+    pub fn new(artist: OsString, album: OsString) -> Self {
+        Self { artist, album }
+    }
+}
+
+const HANDLED_EXTENSIONS: [&str; 6] = ["mp3", "wma", "aac", "ogg", "wav", "flac"];
+
+const CUE_SHEET_EXTENSION: &str = "cue";
+
+fn is_music_file(file_path: &Path) -> bool {
+    file_path.extension().is_some_and(|extension| {
+        HANDLED_EXTENSIONS
+            .iter()
+            .any(|handled_extension| handled_extension == &extension)
+    })
+}
+
+/// Returns true if `directory_path` directly contains at least one music file
+pub fn contains_music(directory_path: &Path) -> Result<bool> {
+    for item in std::fs::read_dir(directory_path)? {
+        let item = item?;
+        if item.file_type()?.is_file() && is_music_file(&item.path()) {
+            return Ok(true);
+        }
+    }
+
+    Ok(false)
 }
 
 fn filter_directory(item: Result<std::fs::DirEntry>) -> Result<Option<std::fs::DirEntry>> {
@@ -39,6 +71,7 @@ fn random_subdirectories(
 pub fn random_music_directory(
     directory_path: &Path,
     selected_directories: Option<&SelectedDirectories>,
+    progress: &mut LoadProgress<'_>,
 ) -> Result<Option<(Vec<Track>, SelectedDirectories)>> {
     match selected_directories {
         Some(selected_directories) => {
@@ -51,24 +84,29 @@ pub fn random_music_directory(
             let artist = artist.to_string_lossy();
             let album = album.to_string_lossy();
 
-            album_directory(&directory_path, &artist, &album)
+            album_directory(&directory_path, &artist, &album, progress)
                 .map(|tracks| tracks.map(|tracks| (tracks, selected_directories.clone())))
         }
-        None => random_artist_directory(directory_path, &mut rand::thread_rng()),
+        None => random_artist_directory(directory_path, &mut rand::thread_rng(), progress),
     }
 }
 
 fn random_artist_directory(
     directory_path: &Path,
     rng: &mut impl Rng,
+    progress: &mut LoadProgress<'_>,
 ) -> Result<Option<(Vec<Track>, SelectedDirectories)>> {
     tracing::debug!("Searching {}", directory_path.display());
     for directory in random_subdirectories(directory_path, rng)? {
         let artist_directory_name = directory.file_name();
         let artist = artist_directory_name.to_string_lossy().into_owned();
-        if let Some(playlist) =
-            random_album_directory(&directory.path(), artist_directory_name, &artist, rng)?
-        {
+        if let Some(playlist) = random_album_directory(
+            &directory.path(),
+            artist_directory_name,
+            &artist,
+            rng,
+            progress,
+        )? {
             return Ok(Some(playlist));
         }
     }
@@ -81,12 +119,13 @@ fn random_album_directory(
     artist_directory_name: OsString,
     artist: &str,
     rng: &mut impl Rng,
+    progress: &mut LoadProgress<'_>,
 ) -> Result<Option<(Vec<Track>, SelectedDirectories)>> {
     tracing::debug!("Searching {}", directory_path.display());
     for directory in random_subdirectories(directory_path, rng)? {
         let album_directory_name = directory.file_name();
         let album = album_directory_name.to_string_lossy();
-        if let Some(playlist) = album_directory(&directory.path(), artist, &album)? {
+        if let Some(playlist) = album_directory(&directory.path(), artist, &album, progress)? {
             return Ok(Some((
                 playlist,
                 SelectedDirectories {
@@ -100,42 +139,339 @@ fn random_album_directory(
     Ok(None)
 }
 
-fn album_directory(directory_path: &Path, artist: &str, album: &str) -> Result<Option<Vec<Track>>> {
-    tracing::debug!("Creating playlist from {}", directory_path.display());
-    let handled_extensions = ["mp3", "wma", "aac", "ogg", "wav"];
+/// The track of a music file, or None if `file_path` isn't a music file
+fn music_file_track(file_path: &Path, artist: Option<&str>, album: Option<&str>) -> Option<Track> {
+    let name = file_path.file_stem()?;
 
-    let mut tracks = Vec::new();
+    if !is_music_file(file_path) {
+        return None;
+    }
+
+    let title = name.to_string_lossy();
+    tracing::debug!("Track: {}", title);
+
+    let (disc_number, track_number) = parse_track_number(&title);
+
+    Some(Track {
+        title: Some(title.into()),
+        album: album.map(Into::into),
+        artist: artist.map(Into::into),
+        track_number,
+        disc_number,
+        url: arcstr::format!("file://{}", file_path.to_string_lossy()),
+        is_notification: false,
+        start_offset: None,
+        mirrors: Vec::new(),
+    })
+}
+
+/// The tracks of a cue sheet whose music files exist, along with the paths of those music files
+fn cue_sheet_tracks(
+    cue_sheet_path: &Path,
+    artist: Option<&str>,
+    album: Option<&str>,
+) -> Result<(Vec<Track>, Vec<PathBuf>)> {
+    let super::cue_sheet::CueSheet {
+        title: cue_sheet_title,
+        performer: cue_sheet_performer,
+        tracks: cue_tracks,
+    } = super::cue_sheet::from_file(cue_sheet_path)?;
+
+    let directory_path = cue_sheet_path.parent().unwrap_or(Path::new(""));
+
+    let mut file_paths = Vec::<PathBuf>::new();
+
+    let tracks = cue_tracks
+        .into_iter()
+        .filter_map(|cue_track| {
+            let super::cue_sheet::CueTrack {
+                file,
+                number,
+                title,
+                performer,
+                start,
+            } = cue_track;
+
+            let file_path = directory_path.join(file);
+
+            if !is_music_file(&file_path) || !file_path.is_file() {
+                tracing::debug!(file = %file_path.display(), "Skipping cue sheet track");
+                return None;
+            }
+
+            tracing::debug!(?title, "Cue sheet track");
+
+            let track = Track {
+                title: Some(title.map_or_else(|| arcstr::format!("Track {number}"), Into::into)),
+                album: cue_sheet_title.as_deref().or(album).map(Into::into),
+                artist: performer
+                    .as_deref()
+                    .or(cue_sheet_performer.as_deref())
+                    .or(artist)
+                    .map(Into::into),
+                track_number: Some(number),
+                disc_number: None,
+                url: arcstr::format!("file://{}", file_path.to_string_lossy()),
+                is_notification: false,
+                start_offset: Some(start),
+                mirrors: Vec::new(),
+            };
+
+            if !file_paths.contains(&file_path) {
+                file_paths.push(file_path);
+            }
+
+            Some(track)
+        })
+        .collect();
+
+    Ok((tracks, file_paths))
+}
+
+/// The tracks of the music files directly inside `directory_path`.
+/// Music files which are split by a cue sheet are replaced by the tracks of the cue sheet, which come first, in order of cue sheet name.
+/// Directory entries are returned in an unspecified order, so the other tracks are sorted by disc and track number, falling back to the file path
+fn directory_tracks(
+    directory_path: &Path,
+    artist: Option<&str>,
+    album: Option<&str>,
+) -> Result<Vec<Track>> {
+    let mut file_paths = Vec::new();
+    let mut cue_sheet_paths = Vec::new();
 
     for item in std::fs::read_dir(directory_path)? {
         let item = item?;
         if item.file_type()?.is_file() {
-            let file_path = item.path();
-            if let Some((name, extension)) = file_path.file_stem().zip(file_path.extension()) {
-                if handled_extensions
-                    .iter()
-                    .any(|handled_extension| handled_extension == &extension)
-                {
-                    let title = name.to_string_lossy();
-                    tracing::debug!("Track: {}", title);
-
-                    tracks.push(Track {
-                        title: Some(title.into()),
-                        album: Some(album.into()),
-                        artist: Some(artist.into()),
-                        url: rradio_messages::arcstr::format!(
-                            "file://{}",
-                            file_path.to_string_lossy()
-                        ),
-                        is_notification: false,
-                    });
-                }
+            let path = item.path();
+
+            if path
+                .extension()
+                .is_some_and(|extension| extension.eq_ignore_ascii_case(CUE_SHEET_EXTENSION))
+            {
+                cue_sheet_paths.push(path);
+            } else {
+                file_paths.push(path);
             }
         }
     }
 
+    cue_sheet_paths.sort();
+
+    let mut tracks = Vec::new();
+
+    for cue_sheet_path in cue_sheet_paths {
+        match cue_sheet_tracks(&cue_sheet_path, artist, album) {
+            Ok((mut cue_sheet_tracks, split_file_paths)) => {
+                file_paths.retain(|file_path| !split_file_paths.contains(file_path));
+                tracks.append(&mut cue_sheet_tracks);
+            }
+            Err(err) => {
+                tracing::warn!("Failed to read {}: {err}", cue_sheet_path.display());
+            }
+        }
+    }
+
+    let mut file_tracks = file_paths
+        .iter()
+        .filter_map(|file_path| music_file_track(file_path, artist, album))
+        .collect::<Vec<_>>();
+
+    file_tracks.sort_by(|a, b| {
+        (a.disc_number, a.track_number)
+            .cmp(&(b.disc_number, b.track_number))
+            .then_with(|| a.url.cmp(&b.url))
+    });
+
+    tracks.append(&mut file_tracks);
+
+    Ok(tracks)
+}
+
+/// The tracks of a music file, or of the music files in a directory.
+/// If `recursive` is true, the music files in subdirectories follow, in order of subdirectory name
+pub fn path_tracks(
+    path: &Path,
+    recursive: bool,
+    progress: &mut LoadProgress<'_>,
+) -> Result<Vec<Track>> {
+    if !path.is_dir() {
+        return Ok(music_file_track(path, None, None).into_iter().collect());
+    }
+
+    let mut tracks = Vec::new();
+
+    append_directory_tracks(path, recursive, &mut tracks, progress)?;
+
+    Ok(tracks)
+}
+
+/// Append the tracks of the music files in `path`, and if `recursive` is true, in its subdirectories, to `tracks`
+fn append_directory_tracks(
+    path: &Path,
+    recursive: bool,
+    tracks: &mut Vec<Track>,
+    progress: &mut LoadProgress<'_>,
+) -> Result<()> {
+    tracing::debug!("Creating playlist from {}", path.display());
+
+    let album = path.file_name().map(|name| name.to_string_lossy());
+
+    tracks.append(&mut directory_tracks(path, None, album.as_deref())?);
+
+    progress.container_scanned(tracks.len());
+
+    if recursive {
+        let mut subdirectories = std::fs::read_dir(path)?
+            .filter_map(|item| filter_directory(item).transpose())
+            .map(|item| item.map(|item| item.path()))
+            .collect::<Result<Vec<_>>>()?;
+
+        subdirectories.sort();
+
+        for subdirectory in subdirectories {
+            append_directory_tracks(&subdirectory, true, tracks, progress)?;
+        }
+    }
+
+    Ok(())
+}
+
+fn album_directory(
+    directory_path: &Path,
+    artist: &str,
+    album: &str,
+    progress: &mut LoadProgress<'_>,
+) -> Result<Option<Vec<Track>>> {
+    tracing::debug!("Creating playlist from {}", directory_path.display());
+
+    let tracks = directory_tracks(directory_path, Some(artist), Some(album))?;
+
+    progress.container_scanned(tracks.len());
+
     Ok(if tracks.is_empty() {
         None
     } else {
         Some(tracks)
     })
 }
+
+/// Parse the disc and track number from the start of a file name, e.g. "03 Title" or "1-03 Title"
+fn parse_track_number(name: &str) -> (Option<u32>, Option<u32>) {
+    fn split_number(s: &str) -> Option<(u32, &str)> {
+        let end = s.find(|c: char| !c.is_ascii_digit()).unwrap_or(s.len());
+        Some((s[..end].parse().ok()?, &s[end..]))
+    }
+
+    let Some((first_number, rest)) = split_number(name) else {
+        return (None, None);
+    };
+
+    match rest.strip_prefix('-').and_then(split_number) {
+        Some((second_number, _)) => (Some(first_number), Some(second_number)),
+        None => (None, Some(first_number)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::parse_track_number;
+
+    fn no_progress() -> crate::station::LoadProgress<'static> {
+        crate::station::LoadProgress::new(None, |_| ())
+    }
+
+    #[test]
+    fn no_track_number() {
+        assert_eq!(parse_track_number("Title"), (None, None));
+    }
+
+    #[test]
+    fn track_number() {
+        assert_eq!(parse_track_number("03 Title"), (None, Some(3)));
+        assert_eq!(parse_track_number("03-Title"), (None, Some(3)));
+        assert_eq!(parse_track_number("03 - Title"), (None, Some(3)));
+    }
+
+    #[test]
+    fn disc_and_track_number() {
+        assert_eq!(parse_track_number("1-03 Title"), (Some(1), Some(3)));
+    }
+
+    #[test]
+    fn path_tracks() {
+        let directory = tempfile::tempdir().unwrap();
+
+        for path in [
+            "Album/02 Second.mp3",
+            "Album/01 First.mp3",
+            "Album/cover.jpg",
+            "Album/Disc 2/01 Third.ogg",
+        ] {
+            let path = directory.path().join(path);
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            std::fs::write(path, "").unwrap();
+        }
+
+        let titles = |recursive| {
+            super::path_tracks(
+                &directory.path().join("Album"),
+                recursive,
+                &mut no_progress(),
+            )
+            .unwrap()
+            .into_iter()
+            .map(|track| track.title.unwrap().to_string())
+            .collect::<Vec<_>>()
+        };
+
+        assert_eq!(titles(false), ["01 First", "02 Second"]);
+        assert_eq!(titles(true), ["01 First", "02 Second", "01 Third"]);
+
+        assert_eq!(
+            super::path_tracks(
+                &directory.path().join("Album/02 Second.mp3"),
+                false,
+                &mut no_progress()
+            )
+            .unwrap()
+            .len(),
+            1
+        );
+    }
+
+    #[test]
+    fn cue_sheets_split_music_files() {
+        let directory = tempfile::tempdir().unwrap();
+
+        for (path, contents) in [
+            ("Album/Album.flac", ""),
+            ("Album/Bonus.mp3", ""),
+            (
+                "Album/Album.cue",
+                "FILE \"Album.flac\" WAVE\n  TRACK 01 AUDIO\n    TITLE \"First\"\n    INDEX 01 00:00:00\n  TRACK 02 AUDIO\n    TITLE \"Second\"\n    INDEX 01 03:00:00\n",
+            ),
+        ] {
+            let path = directory.path().join(path);
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            std::fs::write(path, contents).unwrap();
+        }
+
+        let tracks = super::path_tracks(&directory.path().join("Album"), false, &mut no_progress())
+            .unwrap()
+            .into_iter()
+            .map(|track| (track.title.unwrap().to_string(), track.start_offset))
+            .collect::<Vec<_>>();
+
+        assert_eq!(
+            tracks,
+            [
+                (String::from("First"), Some(std::time::Duration::ZERO)),
+                (
+                    String::from("Second"),
+                    Some(std::time::Duration::from_secs(3 * 60))
+                ),
+                (String::from("Bonus"), None),
+            ]
+        );
+    }
+}
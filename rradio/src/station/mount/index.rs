@@ -0,0 +1,240 @@
+//! An index of the album directories on a USB stick, so that choosing a random album doesn't require walking the stick.
+//!
+//! Indices are stored in the state directory, keyed by the filesystem UUID of the stick
+
+use std::{
+    ffi::OsString,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+};
+
+use anyhow::{Context, Result};
+use rand::seq::SliceRandom;
+
+use crate::play_statistics::{self, PlayStatistics};
+
+use super::directory_search::{self, SelectedDirectories};
+
+/// Only one index is built at a time
+static IS_INDEXING: AtomicBool = AtomicBool::new(false);
+
+/// When favouring albums which haven't been played recently, the least recently played of this many random albums is chosen
+const LEAST_RECENTLY_PLAYED_CANDIDATE_COUNT: usize = 8;
+
+#[derive(serde::Deserialize, serde::Serialize)]
+struct Album {
+    artist: String,
+    album: String,
+}
+
+#[derive(serde::Deserialize, serde::Serialize)]
+struct Index {
+    albums: Vec<Album>,
+}
+
+/// Find the filesystem UUID of `device` by searching `/dev/disk/by-uuid`
+fn filesystem_uuid(device: &str) -> Option<String> {
+    let device = std::fs::canonicalize(device).ok()?;
+
+    std::fs::read_dir("/dev/disk/by-uuid")
+        .ok()?
+        .filter_map(std::result::Result::ok)
+        .find(|entry| std::fs::canonicalize(entry.path()).ok().as_ref() == Some(&device))
+        .and_then(|entry| entry.file_name().into_string().ok())
+}
+
+/// Walk `directory`, finding all `artist/album` directories which contain music
+fn build_index(directory: &Path) -> std::io::Result<Index> {
+    let mut albums = Vec::new();
+
+    for artist_directory in std::fs::read_dir(directory)? {
+        let artist_directory = artist_directory?;
+        if !artist_directory.file_type()?.is_dir() {
+            continue;
+        }
+
+        for album_directory in std::fs::read_dir(artist_directory.path())? {
+            let album_directory = album_directory?;
+            if !album_directory.file_type()?.is_dir()
+                || !directory_search::contains_music(&album_directory.path())?
+            {
+                continue;
+            }
+
+            // Directories with names which aren't valid UTF-8 can't be stored in the index, but can still be found by walking the stick
+            if let (Ok(artist), Ok(album)) = (
+                artist_directory.file_name().into_string(),
+                album_directory.file_name().into_string(),
+            ) {
+                albums.push(Album { artist, album });
+            }
+        }
+    }
+
+    Ok(Index { albums })
+}
+
+/// The index of a particular USB stick
+pub struct UsbIndex {
+    path: PathBuf,
+}
+
+impl UsbIndex {
+    /// The index of the stick `device`, stored in `index_directory`, or None if the stick's filesystem UUID can't be found
+    pub fn for_device(index_directory: &Path, device: &str) -> Option<Self> {
+        let Some(uuid) = filesystem_uuid(device) else {
+            tracing::debug!("Filesystem UUID of {device} not found, so it cannot be indexed");
+            return None;
+        };
+
+        Some(Self {
+            path: index_directory.join(format!("{uuid}.json")),
+        })
+    }
+
+    fn load(&self) -> Result<Option<Index>> {
+        crate::state_file::read(&self.path)?
+            .map(|index| serde_json::from_slice(&index))
+            .transpose()
+            .with_context(|| format!("Failed to parse {}", self.path.display()))
+    }
+
+    fn save(&self, index: &Index) -> Result<()> {
+        crate::state_file::write_json(&self.path, index)
+    }
+
+    /// The albums in the index whose artist or album directory contain `query`, ignoring case, as `(artist, album)` pairs.
+    /// Returns an empty list if the stick hasn't been indexed
+    pub fn search(&self, query: &str) -> Result<Vec<(String, String)>> {
+        let Some(index) = self.load()? else {
+            return Ok(Vec::new());
+        };
+
+        let query = query.to_lowercase();
+
+        Ok(index
+            .albums
+            .into_iter()
+            .filter(|Album { artist, album }| {
+                artist.to_lowercase().contains(&query) || album.to_lowercase().contains(&query)
+            })
+            .map(|Album { artist, album }| (artist, album))
+            .collect())
+    }
+
+    /// Choose a random album from the index, so that each album, rather than each artist, is equally likely to be chosen.
+    /// If `play_statistics` are given, the least recently played of several random albums is chosen.
+    /// `path` is the path of `directory` on the stick.
+    /// Returns None if there is no index, or if the chosen album no longer exists, in which case the index is discarded
+    pub fn random_album(
+        &self,
+        directory: &Path,
+        path: &Path,
+        play_statistics: Option<&PlayStatistics>,
+        progress: &mut crate::station::LoadProgress<'_>,
+    ) -> Option<(Vec<rradio_messages::Track>, SelectedDirectories)> {
+        let index = self.load().unwrap_or_else(|err| {
+            tracing::error!("{err:#}");
+            None
+        })?;
+
+        let mut rng = rand::thread_rng();
+
+        let Album { artist, album } = match play_statistics {
+            Some(play_statistics) => index
+                .albums
+                .choose_multiple(&mut rng, LEAST_RECENTLY_PLAYED_CANDIDATE_COUNT)
+                .min_by_key(|Album { artist, album }| {
+                    // Albums which have never been played have no time, which is less than any time
+                    play_statistics::directory_key_prefix(&path.join(artist).join(album))
+                        .and_then(|prefix| play_statistics.last_played_with_prefix(&prefix))
+                })?,
+            None => index.albums.choose(&mut rng)?,
+        };
+
+        let selected_directories =
+            SelectedDirectories::new(OsString::from(artist), OsString::from(album));
+
+        if let Ok(Some(playlist)) = directory_search::random_music_directory(
+            directory,
+            Some(&selected_directories),
+            progress,
+        ) {
+            return Some(playlist);
+        }
+
+        tracing::info!("{artist}/{album} no longer contains music, discarding index");
+
+        if let Err(err) = std::fs::remove_file(&self.path) {
+            tracing::error!("Failed to remove {}: {err}", self.path.display());
+        }
+
+        None
+    }
+
+    /// Walk `directory` on a background thread and save the index. `handle` keeps the stick mounted until the index is built
+    pub fn build_in_background(self, handle: Arc<dyn super::Handle>, directory: PathBuf) {
+        if IS_INDEXING.swap(true, Ordering::AcqRel) {
+            tracing::debug!("Already indexing");
+            return;
+        }
+
+        let spawn_result = std::thread::Builder::new()
+            .name(String::from("usb-index"))
+            .spawn(move || {
+                let _handle = handle;
+
+                tracing::info!("Indexing {}", directory.display());
+
+                match build_index(&directory)
+                    .with_context(|| format!("Failed to index {}", directory.display()))
+                    .and_then(|index| {
+                        tracing::info!("Found {} albums", index.albums.len());
+                        self.save(&index)
+                    }) {
+                    Ok(()) => tracing::info!("Saved index to {}", self.path.display()),
+                    Err(err) => tracing::error!("{err:#}"),
+                }
+
+                IS_INDEXING.store(false, Ordering::Release);
+            });
+
+        if let Err(err) = spawn_result {
+            tracing::error!("Failed to start indexing: {err}");
+            IS_INDEXING.store(false, Ordering::Release);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn build_index() {
+        let directory = tempfile::tempdir().unwrap();
+
+        for (path, contents) in [
+            ("Artist A/Album 1/01 Track.mp3", "a"),
+            ("Artist A/Album 2/cover.jpg", "b"),
+            ("Artist B/Album 3/01 Track.ogg", "c"),
+            ("Artist B/notes.txt", "d"),
+        ] {
+            let path = directory.path().join(path);
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            std::fs::write(path, contents).unwrap();
+        }
+
+        let mut albums = super::build_index(directory.path())
+            .unwrap()
+            .albums
+            .into_iter()
+            .map(|album| format!("{}/{}", album.artist, album.album))
+            .collect::<Vec<_>>();
+
+        albums.sort();
+
+        assert_eq!(albums, ["Artist A/Album 1", "Artist B/Album 3"]);
+    }
+}
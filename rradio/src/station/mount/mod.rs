@@ -1,48 +1,110 @@
-use std::{any::Any, path::Path};
+use std::{
+    any::Any,
+    path::Path,
+    sync::{Arc, Mutex, PoisonError, Weak},
+};
 
 use rradio_messages::{arcstr, Track};
 
-mod directory_search;
+use crate::config::mount::Backend;
 
-use directory_search::SelectedDirectories;
+mod cue_sheet;
+mod directory_search;
 
 #[cfg(feature = "usb")]
-mod usb;
+mod index;
+
+pub(super) use directory_search::SelectedDirectories;
+
+mod direct;
+mod udisks2;
 
 type Result<T> = std::result::Result<T, rradio_messages::MountError>;
 
-struct Handle {
-    _mount: sys_mount::UnmountDrop<sys_mount::Mount>,
-    mounted_directory: tempfile::TempDir,
+/// A mounted device, which is unmounted when the handle is dropped
+pub trait Handle: Send + Sync + 'static {
+    fn mounted_directory(&self) -> &Path;
+}
+
+/// Mount `device`, or reuse an existing mount of `device` if it's still mounted
+fn mount(
+    backend: Backend,
+    device: &str,
+    file_system_type: &str,
+    credentials: Option<&super::Credentials>,
+) -> Result<Arc<dyn Handle>> {
+    static MOUNTS: Mutex<Vec<(String, Weak<dyn Handle>)>> = Mutex::new(Vec::new());
+
+    let mut mounts = MOUNTS.lock().unwrap_or_else(PoisonError::into_inner);
+
+    mounts.retain(|(_, handle)| handle.strong_count() > 0);
+
+    if let Some(handle) = mounts
+        .iter()
+        .filter(|(mounted_device, _)| mounted_device == device)
+        .find_map(|(_, handle)| handle.upgrade())
+    {
+        tracing::debug!("Reusing mount of {device}");
+        return Ok(handle);
+    }
+
+    let handle: Arc<dyn Handle> = match backend {
+        Backend::Direct => Arc::new(direct::mount(device, file_system_type, credentials)?),
+        Backend::Udisks2 => Arc::new(udisks2::mount(device)?),
+    };
+
+    mounts.push((String::from(device), Arc::downgrade(&handle)));
+
+    Ok(handle)
 }
 
 #[cfg(feature = "usb")]
 pub fn usb(
+    backend: Backend,
     device: &str,
     path: &Path,
+    index_directory: Option<&Path>,
     metadata: Option<&super::PlaylistMetadata>,
+    play_statistics: Option<&crate::play_statistics::PlayStatistics>,
+    progress: &mut super::LoadProgress<'_>,
 ) -> Result<(Vec<Track>, super::PlaylistMetadata, super::PlaylistHandle)> {
-    let handle = usb::mount(device, "vfat", None)?;
+    let handle = mount(backend, device, "vfat", None)?;
 
-    let mut directory = std::path::PathBuf::from(handle.mounted_directory.path());
+    let mut directory = std::path::PathBuf::from(handle.mounted_directory());
     directory.push(path);
 
-    let (tracks, selected_directories) = random_music_directory(
-        &directory,
-        metadata.and_then(|super::PlaylistMetadata(metadata)| {
-            metadata
-                .as_ref()
-                .downcast_ref::<SelectedDirectories>()
-                .or_else(|| {
-                    tracing::error!(
-                        "Metadata is not SelectedDirectories, but is {:?}",
-                        metadata.type_id()
-                    );
-
-                    None
-                })
-        }),
-    )?;
+    let selected_directories = metadata.and_then(|super::PlaylistMetadata(metadata)| {
+        metadata
+            .as_ref()
+            .downcast_ref::<SelectedDirectories>()
+            .or_else(|| {
+                tracing::error!(
+                    "Metadata is not SelectedDirectories, but is {:?}",
+                    metadata.type_id()
+                );
+
+                None
+            })
+    });
+
+    let usb_index = index_directory
+        .filter(|_| selected_directories.is_none())
+        .and_then(|index_directory| index::UsbIndex::for_device(index_directory, device));
+
+    let (tracks, selected_directories) = match usb_index {
+        Some(usb_index) => {
+            match usb_index.random_album(&directory, path, play_statistics, progress) {
+                Some(playlist) => playlist,
+                None => {
+                    let playlist = random_music_directory(&directory, None, progress)?;
+                    usb_index.build_in_background(handle.clone(), directory);
+                    playlist
+                }
+            }
+        }
+        None => random_music_directory(&directory, selected_directories, progress)?,
+    };
+
     Ok((
         tracks,
         super::PlaylistMetadata::new(selected_directories),
@@ -50,11 +112,98 @@ pub fn usb(
     ))
 }
 
+/// Search the index of the USB stick `device` for albums whose artist or album directory contain `query`, as `(artist, album)` pairs
+#[cfg(feature = "usb")]
+pub fn search_usb_index(
+    index_directory: &Path,
+    device: &str,
+    query: &str,
+) -> anyhow::Result<Vec<(String, String)>> {
+    index::UsbIndex::for_device(index_directory, device)
+        .map_or_else(|| Ok(Vec::new()), |usb_index| usb_index.search(query))
+}
+
+/// Play a music file or directory at `requested_path`, which is relative to `path` on the USB device
+#[cfg(feature = "usb")]
+pub fn usb_path(
+    backend: Backend,
+    device: &str,
+    path: &Path,
+    requested_path: &Path,
+    recursive: bool,
+    progress: &mut super::LoadProgress<'_>,
+) -> Result<(Vec<Track>, super::PlaylistHandle)> {
+    check_requested_path(requested_path)?;
+
+    let handle = mount(backend, device, "vfat", None)?;
+
+    mounted_path_tracks(handle, path, requested_path, recursive, progress)
+}
+
+/// Play a music file or directory at `requested_path`, which is relative to `path` on the network share `device`.
+/// Network shares are always mounted directly, as udisks2 doesn't mount them
+#[cfg(feature = "share")]
+pub fn share_path(
+    device: &str,
+    credentials: &super::Credentials,
+    path: &Path,
+    requested_path: &Path,
+    recursive: bool,
+    progress: &mut super::LoadProgress<'_>,
+) -> Result<(Vec<Track>, super::PlaylistHandle)> {
+    check_requested_path(requested_path)?;
+
+    let handle = mount(Backend::Direct, device, "cifs", Some(credentials))?;
+
+    mounted_path_tracks(handle, path, requested_path, recursive, progress)
+}
+
+/// Don't allow paths to escape the music directory
+#[cfg(any(feature = "usb", feature = "share"))]
+fn check_requested_path(requested_path: &Path) -> Result<()> {
+    if requested_path.components().all(|component| {
+        matches!(
+            component,
+            std::path::Component::Normal(_) | std::path::Component::CurDir
+        )
+    }) {
+        Ok(())
+    } else {
+        Err(rradio_messages::MountError::InvalidPath(
+            requested_path.to_string_lossy().into(),
+        ))
+    }
+}
+
+/// The tracks of the music file or directory at `requested_path`, which is relative to `path` in the mounted directory of `handle`
+#[cfg(any(feature = "usb", feature = "share"))]
+fn mounted_path_tracks(
+    handle: Arc<dyn Handle>,
+    path: &Path,
+    requested_path: &Path,
+    recursive: bool,
+    progress: &mut super::LoadProgress<'_>,
+) -> Result<(Vec<Track>, super::PlaylistHandle)> {
+    let mut full_path = std::path::PathBuf::from(handle.mounted_directory());
+    full_path.push(path);
+    full_path.push(requested_path);
+
+    let tracks = directory_search::path_tracks(&full_path, recursive, progress)
+        .map_err(|err| rradio_messages::MountError::ErrorFindingTracks(arcstr::format!("{err}")))?;
+
+    if tracks.is_empty() {
+        return Err(rradio_messages::MountError::TracksNotFound);
+    }
+
+    Ok((tracks, super::PlaylistHandle::new(handle)))
+}
+
 fn random_music_directory(
     directory_path: &Path,
     selected_directories: Option<&SelectedDirectories>,
+    progress: &mut super::LoadProgress<'_>,
 ) -> Result<(Vec<Track>, SelectedDirectories)> {
-    directory_search::random_music_directory(directory_path, selected_directories)
+    directory_search::random_music_directory(directory_path, selected_directories, progress)
         .map_err(|err| rradio_messages::MountError::ErrorFindingTracks(arcstr::format!("{err}")))?
         .ok_or(rradio_messages::MountError::TracksNotFound)
 }
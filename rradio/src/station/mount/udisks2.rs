@@ -0,0 +1,101 @@
+//! Mount devices using udisks2, which doesn't require root, and uses the system's file system configuration.
+//!
+//! `udisksctl` is used to talk to the udisks2 daemon over D-Bus.
+
+use std::{
+    path::{Path, PathBuf},
+    process::Command,
+};
+
+use rradio_messages::{arcstr, MountError};
+
+type Result<T> = std::result::Result<T, rradio_messages::MountError>;
+
+pub(super) struct Handle {
+    device: String,
+    mounted_directory: PathBuf,
+}
+
+impl super::Handle for Handle {
+    fn mounted_directory(&self) -> &Path {
+        &self.mounted_directory
+    }
+}
+
+impl Drop for Handle {
+    fn drop(&mut self) {
+        if let Err(err) = udisksctl("unmount", &self.device, &[]) {
+            tracing::error!("Failed to unmount {}: {err}", self.device);
+        }
+    }
+}
+
+fn udisksctl(command: &str, device: &str, args: &[&str]) -> std::result::Result<String, String> {
+    let output = Command::new("udisksctl")
+        .arg(command)
+        .arg("--no-user-interaction")
+        .arg("--block-device")
+        .arg(device)
+        .args(args)
+        .output()
+        .map_err(|err| format!("Failed to run udisksctl: {err}"))?;
+
+    if output.status.success() {
+        Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+    } else {
+        Err(String::from_utf8_lossy(&output.stderr).trim().to_owned())
+    }
+}
+
+/// Parse the mount point from the output of `udisksctl mount`, e.g. "Mounted /dev/sda1 at /media/rradio/USB."
+fn parse_mount_point(output: &str) -> Option<PathBuf> {
+    let (_, mount_point) = output.trim().split_once(" at ")?;
+    let mount_point = mount_point.strip_suffix('.').unwrap_or(mount_point);
+
+    Some(PathBuf::from(mount_point))
+}
+
+pub(super) fn mount(device: &str) -> Result<Handle> {
+    if !Path::new(device).exists() {
+        return Err(MountError::NotFound);
+    }
+
+    let could_not_mount_device = |err: &str| MountError::CouldNotMountDevice {
+        device: device.into(),
+        err: arcstr::format!("{err}"),
+    };
+
+    let output = udisksctl("mount", device, &["--options", "ro,noatime"])
+        .map_err(|err| could_not_mount_device(&err))?;
+
+    let mounted_directory = parse_mount_point(&output).ok_or_else(|| {
+        could_not_mount_device(&format!("Unexpected udisksctl output: {output:?}"))
+    })?;
+
+    tracing::debug!("Mounted {} at {}", device, mounted_directory.display());
+
+    Ok(Handle {
+        device: device.into(),
+        mounted_directory,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn mount_point() {
+        assert_eq!(
+            parse_mount_point("Mounted /dev/sda1 at /media/rradio/MY USB.\n"),
+            Some(PathBuf::from("/media/rradio/MY USB"))
+        );
+
+        assert_eq!(
+            parse_mount_point("Mounted /dev/sda1 at /run/media/rradio/USB"),
+            Some(PathBuf::from("/run/media/rradio/USB"))
+        );
+
+        assert_eq!(parse_mount_point(""), None);
+    }
+}
@@ -0,0 +1,241 @@
+//! The `.txt` station format of earlier versions of rradio, which is still loaded so that existing station collections keep working.
+//! Lines starting with `#` are the station title, `cd:<device>` is a CD station, a device starting with `/dev/` is a USB station,
+//! `//server/share` is a network share station, using the credentials on the `username=` and `password=` lines, and any other lines are track urls
+
+use anyhow::{Context, Result};
+
+use rradio_messages::StationIndex;
+
+use super::{Station, Track};
+
+/// Parse a legacy `.txt` station
+pub fn from_file(
+    path: &std::path::Path,
+    index: StationIndex,
+    config: &crate::config::Config,
+) -> Result<Station> {
+    let station_text = std::fs::read_to_string(path)
+        .with_context(|| format!("Failed to read {}", path.display()))?;
+
+    from_str(&station_text, index, config)
+}
+
+#[cfg(feature = "cd")]
+#[allow(clippy::unnecessary_wraps)]
+fn cd_station(index: StationIndex, device: &str) -> Result<Station> {
+    Ok(Station::CD {
+        index,
+        device: String::from(device),
+    })
+}
+
+#[cfg(not(feature = "cd"))]
+fn cd_station(_index: StationIndex, device: &str) -> Result<Station> {
+    anyhow::bail!("CD station {device:?} requires the \"cd\" feature")
+}
+
+#[cfg(feature = "usb")]
+#[allow(clippy::unnecessary_wraps)]
+fn usb_station(
+    index: StationIndex,
+    device: &str,
+    config: &crate::config::Config,
+) -> Result<Station> {
+    Ok(Station::Usb {
+        index,
+        mount_backend: config.mount_config.backend,
+        device: String::from(device),
+        path: config.usb_config.path.clone(),
+        index_directory: config
+            .usb_config
+            .index
+            .then(|| super::usb_index_directory(config)),
+        favour_least_recently_played: config.usb_config.favour_least_recently_played,
+    })
+}
+
+#[cfg(not(feature = "usb"))]
+fn usb_station(
+    _index: StationIndex,
+    device: &str,
+    _config: &crate::config::Config,
+) -> Result<Station> {
+    anyhow::bail!("USB station {device:?} requires the \"usb\" feature")
+}
+
+/// The credentials of a network share station, which might be given before or after the share
+#[derive(Default)]
+struct ShareCredentials<'a> {
+    username: Option<&'a str>,
+    password: Option<&'a str>,
+}
+
+#[cfg(feature = "share")]
+fn share_station(
+    index: StationIndex,
+    share: &str,
+    ShareCredentials { username, password }: ShareCredentials,
+) -> Result<Station> {
+    // `//server/share/directory` is the directory `directory` in the share `//server/share`
+    let mut components = share.trim_start_matches('/').splitn(3, '/');
+
+    let (server, share_name) = match (components.next(), components.next()) {
+        (Some(server), Some(share_name)) if !server.is_empty() && !share_name.is_empty() => {
+            (server, share_name)
+        }
+        _ => anyhow::bail!("Network share {share:?} is not of the form //server/share"),
+    };
+
+    Ok(Station::SharePath {
+        index: Some(index),
+        device: format!("//{server}/{share_name}"),
+        credentials: super::Credentials {
+            username: username.unwrap_or_default().into(),
+            password: crate::config::Secret(password.unwrap_or_default().into()),
+        },
+        path: components.next().unwrap_or_default().into(),
+        requested_path: std::path::PathBuf::new(),
+        recursive: true,
+    })
+}
+
+#[cfg(not(feature = "share"))]
+fn share_station(
+    _index: StationIndex,
+    share: &str,
+    _credentials: ShareCredentials,
+) -> Result<Station> {
+    anyhow::bail!("Network share station {share:?} requires the \"share\" feature")
+}
+
+fn from_str(src: &str, index: StationIndex, config: &crate::config::Config) -> Result<Station> {
+    let mut title = None;
+    let mut tracks = Vec::new();
+    let mut share = None;
+    let mut credentials = ShareCredentials::default();
+
+    for line in src.lines().map(str::trim).filter(|line| !line.is_empty()) {
+        if let Some(station_title) = line.strip_prefix('#') {
+            title.get_or_insert_with(|| String::from(station_title.trim()));
+            continue;
+        }
+
+        if let Some(device) = line.strip_prefix("cd:") {
+            return cd_station(index, device.trim());
+        }
+
+        if line.starts_with("/dev/") {
+            return usb_station(index, line, config);
+        }
+
+        if line.starts_with("//") {
+            share.get_or_insert(line);
+            continue;
+        }
+
+        if let Some(username) = line.strip_prefix("username=") {
+            credentials.username = Some(username);
+            continue;
+        }
+
+        if let Some(password) = line.strip_prefix("password=") {
+            credentials.password = Some(password);
+            continue;
+        }
+
+        tracks.push(Track {
+            title: None,
+            album: None,
+            artist: None,
+            track_number: None,
+            disc_number: None,
+            url: line.into(),
+            is_notification: false,
+            start_offset: None,
+            mirrors: Vec::new(),
+        });
+    }
+
+    if let Some(share) = share {
+        return share_station(index, share, credentials);
+    }
+
+    anyhow::ensure!(
+        credentials.username.is_none() && credentials.password.is_none(),
+        "Credentials are only used by network share stations"
+    );
+
+    Ok(Station::UrlList {
+        index: Some(index),
+        title,
+        tracks,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use rradio_messages::StationIndex;
+
+    use super::{from_str, Station};
+
+    fn parse(src: &str) -> anyhow::Result<Station> {
+        from_str(
+            src,
+            StationIndex::new("42".into()),
+            &crate::config::Config::default(),
+        )
+    }
+
+    #[test]
+    fn url_list() {
+        let Station::UrlList { title, tracks, .. } =
+            parse("# Radio 4\nhttp://example.com/a\n\nhttp://example.com/b\n").unwrap()
+        else {
+            panic!("Expected UrlList");
+        };
+
+        assert_eq!(title.as_deref(), Some("Radio 4"));
+        assert_eq!(
+            tracks
+                .iter()
+                .map(|track| track.url.as_str())
+                .collect::<Vec<_>>(),
+            ["http://example.com/a", "http://example.com/b"]
+        );
+    }
+
+    #[cfg(feature = "cd")]
+    #[test]
+    fn cd_station() {
+        assert!(matches!(
+            parse("# CD\ncd:/dev/sr0\n").unwrap(),
+            Station::CD { device, .. } if device == "/dev/sr0"
+        ));
+    }
+
+    #[cfg(feature = "share")]
+    #[test]
+    fn network_share() {
+        let Station::SharePath {
+            index,
+            device,
+            credentials,
+            path,
+            ..
+        } = parse("# Music\nusername=user\n//server/music/Albums\npassword=pass\n").unwrap()
+        else {
+            panic!("Expected SharePath");
+        };
+
+        assert_eq!(index, Some(StationIndex::new("42".into())));
+        assert_eq!(device, "//server/music");
+        assert_eq!(credentials.username, "user");
+        assert_eq!(credentials.password.0.as_str(), "pass");
+        assert_eq!(path, std::path::Path::new("Albums"));
+    }
+
+    #[test]
+    fn credentials_without_a_share_are_rejected() {
+        assert!(parse("http://example.com/a\nusername=user\n").is_err());
+    }
+}
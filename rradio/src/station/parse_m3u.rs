@@ -19,6 +19,7 @@ fn from_str(src: &str, index: StationIndex) -> Result<Station> {
         let mut lines = lines.enumerate();
 
         let mut title = None;
+        let mut mirrors = Vec::new();
 
         let tracks = std::iter::from_fn(|| loop {
             let (line_num, line) = lines.next()?;
@@ -28,6 +29,12 @@ fn from_str(src: &str, index: StationIndex) -> Result<Station> {
                 continue;
             }
 
+            // Mirrors apply to the next track, as with EXTINF
+            if let Some(mirror) = line.strip_prefix("#MIRROR:") {
+                mirrors.push(mirror.trim().into());
+                continue;
+            }
+
             if let Some(extra_info) = line.strip_prefix("#EXTINF:") {
                 let title = match extra_info
                     .split_once(',')
@@ -37,20 +44,30 @@ fn from_str(src: &str, index: StationIndex) -> Result<Station> {
                     Err(err) => return Some(Err(err)),
                 };
 
-                let url = match lines
-                    .find(|(_, line)| !line.starts_with('#'))
-                    .with_context(|| format!("No url after EXTINF on line {line_num}"))
-                {
-                    Ok((_, url)) => url.trim().into(),
-                    Err(err) => return Some(Err(err)),
+                let url = loop {
+                    let Some((_, line)) = lines.next() else {
+                        return Some(Err(anyhow::anyhow!(
+                            "No url after EXTINF on line {line_num}"
+                        )));
+                    };
+
+                    if let Some(mirror) = line.strip_prefix("#MIRROR:") {
+                        mirrors.push(mirror.trim().into());
+                    } else if !line.starts_with('#') {
+                        break line.into();
+                    }
                 };
 
                 return Some(Ok(Track {
                     title,
                     album: None,
                     artist: None,
+                    track_number: None,
+                    disc_number: None,
                     url,
                     is_notification: false,
+                    start_offset: None,
+                    mirrors: std::mem::take(&mut mirrors),
                 }));
             }
 
@@ -59,8 +76,12 @@ fn from_str(src: &str, index: StationIndex) -> Result<Station> {
                     title: None,
                     album: None,
                     artist: None,
+                    track_number: None,
+                    disc_number: None,
                     url: line.into(),
                     is_notification: false,
+                    start_offset: None,
+                    mirrors: std::mem::take(&mut mirrors),
                 }));
             }
         })
@@ -78,8 +99,12 @@ fn from_str(src: &str, index: StationIndex) -> Result<Station> {
                 title: None,
                 album: None,
                 artist: None,
+                track_number: None,
+                disc_number: None,
                 url: url.into(),
                 is_notification: false,
+                start_offset: None,
+                mirrors: Vec::new(),
             })
             .collect();
 
@@ -173,6 +198,32 @@ mod tests {
         );
     }
 
+    #[test]
+    fn extm3u_file_mirrors() {
+        verify_station(
+            from_str(
+                "#EXTM3U\n#EXTINF:-1, A\n#MIRROR: a2\n#MIRROR: a3\na\n#MIRROR:b2\nb\nc\n",
+                StationIndex::new(INDEX.into()),
+            )
+            .unwrap(),
+            None,
+            [
+                |track| {
+                    verify_track(Some("A"), "a", track);
+                    assert_eq!(track.mirrors, ["a2", "a3"]);
+                },
+                |track| {
+                    verify_track(None, "b", track);
+                    assert_eq!(track.mirrors, ["b2"]);
+                },
+                |track| {
+                    verify_track(None, "c", track);
+                    assert!(track.mirrors.is_empty());
+                },
+            ],
+        );
+    }
+
     #[test]
     fn extm3u_file_extinf_missing() {
         verify_station(
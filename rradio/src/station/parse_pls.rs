@@ -15,8 +15,12 @@ pub fn from_file(path: &std::path::Path, index: StationIndex) -> Result<Station>
                     title: entry.title.map(ArcStr::from),
                     album: None,
                     artist: None,
+                    track_number: None,
+                    disc_number: None,
                     url: entry.path.into(),
                     is_notification: false,
+                    start_offset: None,
+                    mirrors: Vec::new(),
                 })
                 .collect()
         })
@@ -23,6 +23,19 @@ struct BrowseResponse {
     result: BrowseResponseResult,
 }
 
+#[derive(serde::Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct SearchSoapEnvelope {
+    body: SearchSoapBody,
+}
+
+#[derive(serde::Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct SearchSoapBody {
+    /// Has the same structure as the response to `Browse`
+    search_response: BrowseResponse,
+}
+
 #[derive(serde::Deserialize)]
 struct BrowseResponseResult {
     #[serde(rename = "$value")]
@@ -50,10 +63,13 @@ struct ItemDerive {
     #[serde(rename = "class")]
     upnp_class: String,
     #[serde(rename = "originalTrackNumber")]
-    track_number: usize,
+    track_number: Option<u32>,
+    #[serde(rename = "originalDiscNumber")]
+    disc_number: Option<u32>,
     title: Vec<String>,
     album: Vec<String>,
     artist: Vec<String>,
+    date: Vec<String>,
     #[serde(rename = "res")]
     urls: Vec<String>,
 }
@@ -61,11 +77,16 @@ struct ItemDerive {
 #[derive(Debug)]
 pub struct Item {
     pub upnp_class: String,
-    pub track_number: usize,
+    pub track_number: Option<u32>,
+    pub disc_number: Option<u32>,
     pub title: Option<ArcStr>,
     pub album: Option<ArcStr>,
     pub artist: Option<ArcStr>,
+    /// The date of the item, usually in ISO 8601 format, e.g. "2003-01-01"
+    pub date: Option<ArcStr>,
     pub url: ArcStr,
+    /// The path of the container holding this item, relative to the station's container
+    pub container_path: ArcStr,
 }
 
 impl<'de> serde::Deserialize<'de> for Item {
@@ -78,23 +99,28 @@ impl<'de> serde::Deserialize<'de> for Item {
         let ItemDerive {
             upnp_class,
             track_number,
+            disc_number,
             title,
             album,
             artist,
+            date,
             urls,
         } = ItemDerive::deserialize(deserializer)?;
 
         Ok(Self {
             upnp_class,
             track_number,
+            disc_number,
             title: map_into(title.into_iter().next()),
             album: map_into(album.into_iter().next()),
             artist: map_into(artist.into_iter().next()),
+            date: map_into(date.into_iter().next()),
             url: urls
                 .into_iter()
                 .next()
                 .ok_or_else(|| D::Error::missing_field("res"))?
                 .into(),
+            container_path: ArcStr::new(),
         })
     }
 }
@@ -103,19 +129,26 @@ impl From<Item> for rradio_messages::Track {
     fn from(item: Item) -> Self {
         let Item {
             upnp_class: _,
-            track_number: _,
+            track_number,
+            disc_number,
             title,
             album,
             artist,
+            date: _,
             url,
+            container_path: _,
         } = item;
 
         Self {
             title,
             album,
             artist,
+            track_number,
+            disc_number,
             url,
             is_notification: false,
+            start_offset: None,
+            mirrors: Vec::new(),
         }
     }
 }
@@ -132,30 +165,47 @@ struct Request<'a> {
     object_id: &'a str,
 }
 
-pub async fn fetch(
+#[derive(askama::Template)]
+#[template(path = "content_directory_search_request.xml")]
+struct SearchRequest<'a> {
+    search_criteria: &'a str,
+    requested_count: usize,
+}
+
+/// Call the `ContentDirectory` `action`, returning the Soap Envelope of the response
+async fn call_action(
     client: &reqwest::Client,
     control_url: &str,
-    Reference { id, title }: Reference,
-) -> Result<Container> {
-    tracing::trace!("Fetching {} - {}", id, title);
-
-    let body = Request { object_id: &id }.to_string();
-
-    let http_response = client
+    action: &str,
+    body: String,
+) -> Result<String> {
+    client
         .post(control_url)
         .header(reqwest::header::CONTENT_TYPE, "text/xml;charset=utf-8")
         .header(
             "Soapaction",
-            "urn:schemas-upnp-org:service:ContentDirectory:1#Browse",
+            format!("urn:schemas-upnp-org:service:ContentDirectory:1#{action}"),
         )
         .body(body)
         .send()
         .await
         .and_then(reqwest::Response::error_for_status)
-        .context("Failed to fetch container")?
+        .with_context(|| format!("Failed to call {action}"))?
         .text()
         .await
-        .context("Failed to fetch text")?;
+        .context("Failed to fetch text")
+}
+
+pub async fn fetch(
+    client: &reqwest::Client,
+    control_url: &str,
+    Reference { id, title }: Reference,
+) -> Result<Container> {
+    tracing::trace!("Fetching {} - {}", id, title);
+
+    let body = Request { object_id: &id }.to_string();
+
+    let http_response = call_action(client, control_url, "Browse", body).await?;
 
     tracing::trace!("Parsing XML");
 
@@ -177,3 +227,56 @@ pub async fn fetch(
         items,
     })
 }
+
+/// The `UPnP` search criteria which match audio items whose title, artist or album contain `query`
+fn search_criteria(query: &str) -> String {
+    // Quotes and backslashes within search criteria strings are escaped with a backslash
+    let query = query.replace('\\', "\\\\").replace('"', "\\\"");
+
+    format!(
+        r#"upnp:class derivedfrom "object.item.audioItem" and (dc:title contains "{query}" or upnp:artist contains "{query}" or upnp:album contains "{query}")"#
+    )
+}
+
+/// Search the whole content directory for audio items matching `query`
+pub async fn search(
+    client: &reqwest::Client,
+    control_url: &str,
+    query: &str,
+    requested_count: usize,
+) -> Result<Vec<Item>> {
+    tracing::trace!("Searching for {query:?}");
+
+    let body = SearchRequest {
+        search_criteria: &search_criteria(query),
+        requested_count,
+    }
+    .to_string();
+
+    let http_response = call_action(client, control_url, "Search", body).await?;
+
+    let search_result = quick_xml::de::from_str::<SearchSoapEnvelope>(&http_response)
+        .context("Failed to parse Soap Envelope")?
+        .body
+        .search_response
+        .result
+        .body;
+
+    tracing::trace!("{search_result}");
+
+    let DidlRoot { items, .. } =
+        quick_xml::de::from_str(&search_result).context("Failed to parse Soap Payload")?;
+
+    Ok(items)
+}
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn search_queries_are_escaped() {
+        assert_eq!(
+            super::search_criteria(r#"say "hi" \ bye"#),
+            r#"upnp:class derivedfrom "object.item.audioItem" and (dc:title contains "say \"hi\" \\ bye" or upnp:artist contains "say \"hi\" \\ bye" or upnp:album contains "say \"hi\" \\ bye")"#
+        );
+    }
+}
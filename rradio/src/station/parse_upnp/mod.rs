@@ -1,29 +1,19 @@
 use std::path::{Path, PathBuf};
 
-use rradio_messages::StationIndex;
+use rradio_messages::{arcstr, ArcStr, StationIndex};
 
 use anyhow::{Context, Result};
-use rand::{prelude::SliceRandom, Rng};
+use rand::Rng;
 use url::Url;
 
-use super::Track;
+use crate::play_statistics::PlayStatistics;
+
+use super::{LoadProgress, Track};
 
 mod container;
+mod natural_order;
 mod root_description;
-
-#[derive(Debug, Clone, Copy, serde::Deserialize)]
-#[serde(rename_all = "snake_case")]
-enum SortBy {
-    None,
-    TrackNumber,
-    Random,
-}
-
-impl Default for SortBy {
-    fn default() -> Self {
-        Self::None
-    }
-}
+mod sort;
 
 #[derive(Debug, serde::Deserialize)]
 struct Container {
@@ -32,7 +22,7 @@ struct Container {
     root_description_url: Url,
     container: PathBuf,
     #[serde(default)]
-    sort_by: SortBy,
+    sort_by: sort::SortBy,
     #[serde(default)]
     limit_track_count: Option<usize>,
     #[serde(default)]
@@ -82,12 +72,16 @@ struct RootContainerBuilder {
     current_container: container::Container,
 }
 
+fn http_client() -> Result<reqwest::Client> {
+    reqwest::Client::builder()
+        .user_agent("rradio")
+        .build()
+        .context("Failed to create http client")
+}
+
 impl RootContainerBuilder {
-    async fn new(root_description_url: Url) -> Result<Self> {
-        let client = reqwest::Client::builder()
-            .user_agent("rradio")
-            .build()
-            .context("Failed to create http client")?;
+    async fn new(root_description_url: Url, progress: &mut LoadProgress<'_>) -> Result<Self> {
+        let client = http_client()?;
 
         let root_device =
             root_description::get_content_directory_control_path(&client, root_description_url)
@@ -103,6 +97,8 @@ impl RootContainerBuilder {
         )
         .await?;
 
+        progress.container_scanned(0);
+
         Ok(Self {
             client,
             root_device,
@@ -110,7 +106,11 @@ impl RootContainerBuilder {
         })
     }
 
-    async fn with_container_path(mut self, container_path: &Path) -> Result<Self> {
+    async fn with_container_path(
+        mut self,
+        container_path: &Path,
+        progress: &mut LoadProgress<'_>,
+    ) -> Result<Self> {
         for section in container_path {
             let section = section.to_str().context("Bad path")?;
 
@@ -129,12 +129,14 @@ impl RootContainerBuilder {
                 reference,
             )
             .await?;
+
+            progress.container_scanned(0);
         }
 
         Ok(self)
     }
 
-    async fn random_subcontainer(self) -> Result<TracksBuilder> {
+    async fn random_subcontainer(self, progress: &mut LoadProgress<'_>) -> Result<TracksBuilder> {
         let mut current_container = self.current_container;
         let items = loop {
             if !current_container.items.is_empty() {
@@ -155,37 +157,64 @@ impl RootContainerBuilder {
                 reference,
             )
             .await?;
+
+            progress.container_scanned(current_container.items.len());
         };
 
         Ok(TracksBuilder { items })
     }
 
-    async fn flatten_container(self) -> Result<TracksBuilder> {
+    async fn flatten_container(self, progress: &mut LoadProgress<'_>) -> Result<TracksBuilder> {
         let mut items = self.current_container.items;
-        let mut containers = self.current_container.containers;
-
-        while let Some(container) = containers.pop() {
-            let mut new_container = container::fetch(
+        let mut containers = self
+            .current_container
+            .containers
+            .into_iter()
+            .map(|container| (ArcStr::new(), container))
+            .collect::<Vec<_>>();
+
+        while let Some((parent_path, container)) = containers.pop() {
+            let container_path = if parent_path.is_empty() {
+                ArcStr::from(container.title.as_str())
+            } else {
+                arcstr::format!("{parent_path}/{}", container.title)
+            };
+
+            let new_container = container::fetch(
                 &self.client,
                 &self.root_device.content_directory_control_url,
                 container,
             )
             .await?;
 
-            items.append(&mut new_container.items);
-            containers.append(&mut new_container.containers);
+            items.extend(new_container.items.into_iter().map(|item| container::Item {
+                container_path: container_path.clone(),
+                ..item
+            }));
+            containers.extend(
+                new_container
+                    .containers
+                    .into_iter()
+                    .map(|container| (container_path.clone(), container)),
+            );
+
+            progress.container_scanned(items.len());
         }
 
         Ok(TracksBuilder { items })
     }
 
-    async fn tracks(self, envelope: &Envelope) -> Result<TracksBuilder> {
+    async fn tracks(
+        self,
+        envelope: &Envelope,
+        progress: &mut LoadProgress<'_>,
+    ) -> Result<TracksBuilder> {
         match envelope {
             Envelope::Single(_) => Ok(TracksBuilder {
                 items: self.current_container.items,
             }),
-            Envelope::Random(_) => self.random_subcontainer().await,
-            Envelope::Flattened(_) => self.flatten_container().await,
+            Envelope::Random(_) => self.random_subcontainer(progress).await,
+            Envelope::Flattened(_) => self.flatten_container(progress).await,
         }
     }
 }
@@ -206,12 +235,8 @@ impl TracksBuilder {
         self
     }
 
-    fn sort_tracks(mut self, sort_by: SortBy) -> Self {
-        match sort_by {
-            SortBy::None => (),
-            SortBy::TrackNumber => self.items.sort_by_key(|item| item.track_number),
-            SortBy::Random => self.items.shuffle(&mut rand::thread_rng()),
-        }
+    fn sort_tracks(mut self, sort_by: &sort::SortBy, play_statistics: &PlayStatistics) -> Self {
+        sort::sort(&mut self.items, sort_by, play_statistics);
         self
     }
 
@@ -229,10 +254,10 @@ impl TracksBuilder {
 }
 
 #[derive(Clone)]
-struct Metadata {
-    station_index: Option<StationIndex>,
-    station_title: Option<String>,
-    tracks: Vec<Track>,
+pub(super) struct Metadata {
+    pub(super) station_index: Option<StationIndex>,
+    pub(super) station_title: Option<String>,
+    pub(super) tracks: Vec<Track>,
 }
 
 impl Metadata {
@@ -281,9 +306,15 @@ impl Station {
         self.envelope.container().station_title.as_deref()
     }
 
+    pub fn root_description_url(&self) -> &Url {
+        &self.envelope.container().root_description_url
+    }
+
     pub async fn into_playlist(
         self,
         metadata: Option<&super::PlaylistMetadata>,
+        progress: &mut LoadProgress<'_>,
+        play_statistics: &PlayStatistics,
     ) -> Result<super::Playlist> {
         if let Some(metadata) = metadata
             .and_then(|super::PlaylistMetadata(metadata)| metadata.downcast_ref::<Metadata>())
@@ -294,17 +325,19 @@ impl Station {
 
         let station_index = Some(self.index);
         let station_title = self.envelope.container().station_title.clone();
-        let tracks =
-            RootContainerBuilder::new(self.envelope.container().root_description_url.clone())
-                .await?
-                .with_container_path(&self.envelope.container().container)
-                .await?
-                .tracks(&self.envelope)
-                .await?
-                .filter_upnp_class(self.envelope.container().filter_upnp_class.as_deref())
-                .sort_tracks(self.envelope.container().sort_by)
-                .limit_track_count(self.envelope.container().limit_track_count)
-                .tracks();
+        let tracks = RootContainerBuilder::new(
+            self.envelope.container().root_description_url.clone(),
+            progress,
+        )
+        .await?
+        .with_container_path(&self.envelope.container().container, progress)
+        .await?
+        .tracks(&self.envelope, progress)
+        .await?
+        .filter_upnp_class(self.envelope.container().filter_upnp_class.as_deref())
+        .sort_tracks(&self.envelope.container().sort_by, play_statistics)
+        .limit_track_count(self.envelope.container().limit_track_count)
+        .tracks();
 
         let metadata = Metadata {
             station_index: station_index.clone(),
@@ -323,6 +356,59 @@ impl Station {
     }
 }
 
+/// Fetch the containers and items in the container with the given id, so that clients can browse a media server
+pub async fn browse(
+    root_description_url: Url,
+    container_id: String,
+) -> Result<rradio_messages::UPnPContainer> {
+    let client = http_client()?;
+
+    let root_device =
+        root_description::get_content_directory_control_path(&client, root_description_url).await?;
+
+    let container = container::fetch(
+        &client,
+        &root_device.content_directory_control_url,
+        container::Reference {
+            title: container_id.clone(),
+            id: container_id,
+        },
+    )
+    .await?;
+
+    Ok(rradio_messages::UPnPContainer {
+        containers: container
+            .containers
+            .into_iter()
+            .map(
+                |container::Reference { id, title }| rradio_messages::UPnPContainerReference {
+                    id: id.into(),
+                    title: title.into(),
+                },
+            )
+            .collect(),
+        items: container.items.into_iter().map(Track::from).collect(),
+    })
+}
+
+/// Search the media server for at most `limit` audio items whose title, artist or album contain `query`
+pub async fn search(root_description_url: Url, query: &str, limit: usize) -> Result<Vec<Track>> {
+    let client = http_client()?;
+
+    let root_device =
+        root_description::get_content_directory_control_path(&client, root_description_url).await?;
+
+    let items = container::search(
+        &client,
+        &root_device.content_directory_control_url,
+        query,
+        limit,
+    )
+    .await?;
+
+    Ok(items.into_iter().take(limit).map(Track::from).collect())
+}
+
 /// Parse a `UPnP` Station
 pub fn from_file(path: &std::path::Path, index: StationIndex) -> Result<super::Station> {
     Station::from_file(path, index).map(super::Station::UPnP)
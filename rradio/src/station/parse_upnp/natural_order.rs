@@ -0,0 +1,80 @@
+//! Compare strings so that numbers within them are ordered by value, e.g. "Track 2" before "Track 10"
+
+use std::cmp::Ordering;
+
+/// Split off the leading run of digits, or the leading run of non-digits
+fn split_chunk(s: &str) -> (&str, &str) {
+    let is_digit = s.starts_with(|c: char| c.is_ascii_digit());
+    let end = s
+        .find(|c: char| c.is_ascii_digit() != is_digit)
+        .unwrap_or(s.len());
+
+    s.split_at(end)
+}
+
+/// Compare two runs of digits by value, without parsing them, so that arbitrarily long numbers don't overflow
+fn compare_numbers(a: &str, b: &str) -> Ordering {
+    let a = a.trim_start_matches('0');
+    let b = b.trim_start_matches('0');
+
+    a.len().cmp(&b.len()).then_with(|| a.cmp(b))
+}
+
+fn compare_text(a: &str, b: &str) -> Ordering {
+    a.chars()
+        .flat_map(char::to_lowercase)
+        .cmp(b.chars().flat_map(char::to_lowercase))
+}
+
+/// Compare `a` and `b` case-insensitively, treating runs of digits as numbers
+pub fn compare(mut a: &str, mut b: &str) -> Ordering {
+    loop {
+        if a.is_empty() || b.is_empty() {
+            return a.len().cmp(&b.len());
+        }
+
+        let (a_chunk, a_rest) = split_chunk(a);
+        let (b_chunk, b_rest) = split_chunk(b);
+
+        let a_is_number = a_chunk.starts_with(|c: char| c.is_ascii_digit());
+        let b_is_number = b_chunk.starts_with(|c: char| c.is_ascii_digit());
+
+        let ordering = if a_is_number && b_is_number {
+            compare_numbers(a_chunk, b_chunk)
+        } else {
+            compare_text(a_chunk, b_chunk)
+        };
+
+        if ordering != Ordering::Equal {
+            return ordering;
+        }
+
+        a = a_rest;
+        b = b_rest;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cmp::Ordering;
+
+    use super::compare;
+
+    #[test]
+    fn numbers_are_ordered_by_value() {
+        assert_eq!(compare("Track 2", "Track 10"), Ordering::Less);
+        assert_eq!(compare("Track 02", "Track 2"), Ordering::Equal);
+        assert_eq!(compare("1-10 Title", "2-01 Title"), Ordering::Less);
+    }
+
+    #[test]
+    fn text_is_case_insensitive() {
+        assert_eq!(compare("abc", "ABD"), Ordering::Less);
+        assert_eq!(compare("Disc 1/b", "disc 1/A"), Ordering::Greater);
+    }
+
+    #[test]
+    fn prefixes_are_ordered_first() {
+        assert_eq!(compare("Album", "Album 2"), Ordering::Less);
+    }
+}
@@ -0,0 +1,261 @@
+//! How the tracks of a `UPnP` station are sorted, either by a single key, or by several keys, each of which can be descending
+
+use std::cmp::Ordering;
+
+use rand::prelude::SliceRandom;
+use rradio_messages::ArcStr;
+
+use crate::play_statistics::PlayStatistics;
+
+use super::{container::Item, natural_order};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SortKey {
+    None,
+    TrackNumber,
+    Random,
+    /// By title, with numbers ordered by value, e.g. "Track 2" before "Track 10"
+    #[serde(alias = "title")]
+    Natural,
+    /// Grouped by container, and by track number then title within each container
+    Container,
+    /// By container path then title, with numbers ordered by value
+    Path,
+    Artist,
+    Album,
+    Date,
+    /// Tracks which have never been played first, then the tracks which were played longest ago
+    LeastRecentlyPlayed,
+}
+
+/// A sort key, and whether it's in descending order
+#[derive(Debug, Clone, Copy, serde::Deserialize)]
+#[serde(untagged)]
+pub enum OrderedKey {
+    Ascending(SortKey),
+    WithOrder {
+        key: SortKey,
+        #[serde(default)]
+        descending: bool,
+    },
+}
+
+impl OrderedKey {
+    fn key(self) -> SortKey {
+        match self {
+            Self::Ascending(key) | Self::WithOrder { key, .. } => key,
+        }
+    }
+
+    fn compare(self, a: &Item, b: &Item, play_statistics: &PlayStatistics) -> Ordering {
+        match self {
+            Self::Ascending(key)
+            | Self::WithOrder {
+                key,
+                descending: false,
+            } => key.compare(a, b, play_statistics),
+            Self::WithOrder {
+                key,
+                descending: true,
+            } => key.compare(a, b, play_statistics).reverse(),
+        }
+    }
+}
+
+/// Either a single key, e.g. `"track_number"`, or a list of keys, which are compared in order,
+/// e.g. `["album", "track_number"]` or `[{ key = "date", descending = true }, "title"]`
+#[derive(Debug, Clone, serde::Deserialize)]
+#[serde(untagged)]
+pub enum SortBy {
+    Single(OrderedKey),
+    Multiple(Vec<OrderedKey>),
+}
+
+impl Default for SortBy {
+    fn default() -> Self {
+        Self::Single(OrderedKey::Ascending(SortKey::None))
+    }
+}
+
+/// Compare the titles of items, falling back to their urls if they have no title
+fn compare_titles(a: &Item, b: &Item) -> Ordering {
+    natural_order::compare(
+        a.title.as_deref().unwrap_or(&a.url),
+        b.title.as_deref().unwrap_or(&b.url),
+    )
+}
+
+/// Compare optional metadata, with items which are missing the metadata last
+fn compare_metadata(a: Option<&ArcStr>, b: Option<&ArcStr>) -> Ordering {
+    match (a, b) {
+        (Some(a), Some(b)) => natural_order::compare(a, b),
+        (Some(_), None) => Ordering::Less,
+        (None, Some(_)) => Ordering::Greater,
+        (None, None) => Ordering::Equal,
+    }
+}
+
+impl SortKey {
+    /// `None` and `Random` compare all items as equal, as they don't sort by the items' metadata
+    fn compare(self, a: &Item, b: &Item, play_statistics: &PlayStatistics) -> Ordering {
+        let compare_track_numbers =
+            || (a.disc_number, a.track_number).cmp(&(b.disc_number, b.track_number));
+
+        match self {
+            Self::None | Self::Random => Ordering::Equal,
+            Self::TrackNumber => compare_track_numbers(),
+            Self::Natural => compare_titles(a, b),
+            Self::Container => natural_order::compare(&a.container_path, &b.container_path)
+                .then_with(compare_track_numbers)
+                .then_with(|| compare_titles(a, b)),
+            Self::Path => natural_order::compare(&a.container_path, &b.container_path)
+                .then_with(|| compare_titles(a, b)),
+            Self::Artist => compare_metadata(a.artist.as_ref(), b.artist.as_ref()),
+            Self::Album => compare_metadata(a.album.as_ref(), b.album.as_ref()),
+            Self::Date => compare_metadata(a.date.as_ref(), b.date.as_ref()),
+            // Tracks which have never been played have no time, which is less than any time
+            Self::LeastRecentlyPlayed => play_statistics
+                .last_played(&a.url)
+                .cmp(&play_statistics.last_played(&b.url)),
+        }
+    }
+}
+
+/// Sort the items. If any key is `random`, the items are shuffled first, so that the other keys are applied to the shuffled items
+pub fn sort(items: &mut [Item], sort_by: &SortBy, play_statistics: &PlayStatistics) {
+    let keys = match sort_by {
+        SortBy::Single(key) => std::slice::from_ref(key),
+        SortBy::Multiple(keys) => keys.as_slice(),
+    };
+
+    if keys.iter().any(|key| key.key() == SortKey::Random) {
+        items.shuffle(&mut rand::thread_rng());
+    }
+
+    if keys
+        .iter()
+        .all(|key| matches!(key.key(), SortKey::None | SortKey::Random))
+    {
+        return;
+    }
+
+    // Sorting is stable, so items which are equal keep the order of the Content Directory
+    items.sort_by(|a, b| {
+        keys.iter().fold(Ordering::Equal, |ordering, key| {
+            ordering.then_with(|| key.compare(a, b, play_statistics))
+        })
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use rradio_messages::ArcStr;
+
+    use crate::play_statistics::PlayStatistics;
+
+    use super::{Item, SortBy};
+
+    fn item(title: &str, album: &str, track_number: u32, date: &str) -> Item {
+        Item {
+            upnp_class: String::new(),
+            track_number: Some(track_number),
+            disc_number: None,
+            title: Some(title.into()),
+            album: Some(album.into()),
+            artist: None,
+            date: Some(date.into()),
+            url: ArcStr::from(title),
+            container_path: ArcStr::new(),
+        }
+    }
+
+    fn titles(items: &[Item]) -> Vec<&str> {
+        items
+            .iter()
+            .map(|item| item.title.as_deref().unwrap())
+            .collect()
+    }
+
+    fn sort_by(sort_by: &str) -> SortBy {
+        #[derive(serde::Deserialize)]
+        struct Station {
+            sort_by: SortBy,
+        }
+
+        toml::from_str::<Station>(&format!("sort_by = {sort_by}"))
+            .unwrap()
+            .sort_by
+    }
+
+    fn items() -> Vec<Item> {
+        vec![
+            item("B2", "B", 2, "2001"),
+            item("A1", "A", 1, "2005"),
+            item("B1", "B", 1, "2001"),
+            item("A2", "A", 2, "2005"),
+        ]
+    }
+
+    fn play_statistics() -> (tempfile::TempDir, PlayStatistics) {
+        let state_directory = tempfile::tempdir().unwrap();
+        let play_statistics = PlayStatistics::load(state_directory.path());
+        (state_directory, play_statistics)
+    }
+
+    #[test]
+    fn album_then_track_number() {
+        let (_state_directory, play_statistics) = play_statistics();
+
+        let mut items = items();
+
+        super::sort(
+            &mut items,
+            &sort_by(r#"["album", "track_number"]"#),
+            &play_statistics,
+        );
+
+        assert_eq!(titles(&items), ["A1", "A2", "B1", "B2"]);
+    }
+
+    #[test]
+    fn descending_date_then_title() {
+        let (_state_directory, play_statistics) = play_statistics();
+
+        let mut items = items();
+
+        super::sort(
+            &mut items,
+            &sort_by(r#"[{ key = "date", descending = true }, "title"]"#),
+            &play_statistics,
+        );
+
+        assert_eq!(titles(&items), ["A1", "A2", "B1", "B2"]);
+
+        super::sort(
+            &mut items,
+            &sort_by(r#"{ key = "title", descending = true }"#),
+            &play_statistics,
+        );
+
+        assert_eq!(titles(&items), ["B2", "B1", "A2", "A1"]);
+    }
+
+    #[test]
+    fn least_recently_played_first() {
+        let (_state_directory, mut play_statistics) = play_statistics();
+
+        play_statistics.record_play(ArcStr::from("A1"));
+        play_statistics.record_play(ArcStr::from("B2"));
+
+        let mut items = items();
+
+        super::sort(
+            &mut items,
+            &sort_by(r#"["least_recently_played", "title"]"#),
+            &play_statistics,
+        );
+
+        assert_eq!(titles(&items), ["A2", "B1", "A1", "B2"]);
+    }
+}
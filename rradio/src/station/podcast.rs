@@ -0,0 +1,397 @@
+//! Podcast stations, which are the podcasts subscribed to on gpodder.net or a Nextcloud server, and which are kept up to date by [`crate::podcast_sync`].
+//! They have their own index namespace, e.g. `P1`, and are stored in the state directory as a JSON file, along with how far each episode has been listened to
+
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    path::{Path, PathBuf},
+    sync::{Mutex, PoisonError},
+    time::Duration,
+};
+
+use rradio_messages::{StationIndex, Track};
+
+use super::{Station, StationListing};
+
+const PODCASTS_FILE_NAME: &str = "podcasts.json";
+
+/// The prefix of the indices of podcast stations. Station files start with a digit and temporary stations start with `T`, so the indices never clash
+const INDEX_PREFIX: &str = "P";
+
+/// Episodes which were stopped this close to the end have been listened to, so are played from the start
+const FINISHED_MARGIN: Duration = Duration::from_secs(30);
+
+#[derive(Clone, Debug, PartialEq, serde::Deserialize, serde::Serialize)]
+struct PodcastStation {
+    index: StationIndex,
+    feed_url: String,
+    title: String,
+    tracks: Vec<Track>,
+}
+
+/// How far an episode has been listened to, on this radio or on another device
+#[derive(Clone, Copy, Debug, PartialEq, Eq, serde::Deserialize, serde::Serialize)]
+pub struct EpisodeProgress {
+    /// Where listening started
+    #[serde(with = "humantime_serde")]
+    pub started: Duration,
+    /// Where listening stopped, or has reached
+    #[serde(with = "humantime_serde")]
+    pub position: Duration,
+    /// The duration of the episode, or zero if it isn't known
+    #[serde(with = "humantime_serde")]
+    pub total: Duration,
+    /// When the episode was listened to, in seconds since the Unix epoch
+    pub timestamp: i64,
+}
+
+/// A podcast which is subscribed to
+pub struct Subscription {
+    pub feed_url: String,
+    /// The title and episodes of the podcast, or `None` if its feed couldn't be fetched, in which case the episodes from the previous sync are kept
+    pub feed: Option<(String, Vec<Track>)>,
+}
+
+/// Progress on this radio which hasn't been uploaded to the sync server
+pub struct UnsentProgress {
+    pub feed_url: String,
+    pub episode_url: String,
+    pub progress: EpisodeProgress,
+}
+
+#[derive(Debug, Default, serde::Deserialize, serde::Serialize)]
+struct Podcasts {
+    /// In the order of the subscriptions
+    stations: Vec<PodcastStation>,
+    /// Indices aren't reused, so that a client never selects a different station to the one it listed
+    last_number: u64,
+    /// Keyed by episode url
+    progress: BTreeMap<String, EpisodeProgress>,
+    /// The episodes whose progress on this radio hasn't been uploaded yet
+    unsent_progress: BTreeSet<String>,
+    /// The sync server's timestamp of the latest episode actions which have been downloaded
+    episode_actions_since: i64,
+}
+
+impl Podcasts {
+    /// Replace the stations with the subscriptions. Podcasts which are still subscribed to keep their index
+    fn update_subscriptions(&mut self, subscriptions: Vec<Subscription>) {
+        let mut previous_stations = std::mem::take(&mut self.stations);
+
+        for Subscription { feed_url, feed } in subscriptions {
+            let previous_station = previous_stations
+                .iter()
+                .position(|station| station.feed_url == feed_url)
+                .map(|position| previous_stations.remove(position));
+
+            let station = match (previous_station, feed) {
+                (Some(station), Some((title, tracks))) => PodcastStation {
+                    title,
+                    tracks,
+                    ..station
+                },
+                (Some(station), None) => station,
+                (None, Some((title, tracks))) => {
+                    self.last_number += 1;
+
+                    PodcastStation {
+                        index: StationIndex::new(
+                            format!("{INDEX_PREFIX}{}", self.last_number).into(),
+                        ),
+                        feed_url,
+                        title,
+                        tracks,
+                    }
+                }
+                (None, None) => continue,
+            };
+
+            self.stations.push(station);
+        }
+
+        // Forget the progress of episodes which are no longer in any podcast
+        let episode_urls = self
+            .stations
+            .iter()
+            .flat_map(|station| &station.tracks)
+            .map(|track| track.url.as_str())
+            .collect::<BTreeSet<_>>();
+
+        self.progress
+            .retain(|url, _| episode_urls.contains(url.as_str()));
+        self.unsent_progress
+            .retain(|url| episode_urls.contains(url.as_str()));
+    }
+
+    fn record_progress(&mut self, episode_url: &str, progress: EpisodeProgress) {
+        self.unsent_progress.insert(String::from(episode_url));
+        self.progress.insert(String::from(episode_url), progress);
+    }
+
+    /// Apply progress from another device, unless the episode has been listened to more recently on this radio
+    fn apply_remote_progress(&mut self, episode_url: String, progress: EpisodeProgress) {
+        let is_newer = self
+            .progress
+            .get(&episode_url)
+            .is_none_or(|local_progress| local_progress.timestamp < progress.timestamp);
+
+        if is_newer {
+            self.unsent_progress.remove(&episode_url);
+            self.progress.insert(episode_url, progress);
+        }
+    }
+
+    fn resume_position(&self, episode_url: &str) -> Option<Duration> {
+        let progress = self.progress.get(episode_url)?;
+
+        let is_finished =
+            !progress.total.is_zero() && progress.position + FINISHED_MARGIN >= progress.total;
+
+        (!progress.position.is_zero() && !is_finished).then_some(progress.position)
+    }
+
+    fn get(&self, index: &StationIndex) -> Option<&PodcastStation> {
+        self.stations.iter().find(|station| &station.index == index)
+    }
+}
+
+fn path(config: &crate::config::Config) -> PathBuf {
+    Path::new(config.state_directory.as_str()).join(PODCASTS_FILE_NAME)
+}
+
+/// Access the podcasts, loading them from the state directory the first time
+fn with_podcasts<T>(config: &crate::config::Config, f: impl FnOnce(&mut Podcasts) -> T) -> T {
+    static PODCASTS: Mutex<Option<Podcasts>> = Mutex::new(None);
+
+    let mut podcasts = PODCASTS.lock().unwrap_or_else(PoisonError::into_inner);
+
+    let podcasts = podcasts.get_or_insert_with(|| {
+        crate::state_file::read_json(&path(config)).unwrap_or_else(|err| {
+            tracing::error!("{err:#}");
+            Podcasts::default()
+        })
+    });
+
+    f(podcasts)
+}
+
+/// Whether the station with the given index is a podcast
+pub fn is_podcast_station(index: &StationIndex) -> bool {
+    index.as_str().starts_with(INDEX_PREFIX)
+}
+
+/// Write the podcasts, and the progress of their episodes, to the state directory
+pub fn save(config: &crate::config::Config) {
+    with_podcasts(config, |podcasts| {
+        if let Err(err) = crate::state_file::write_json(&path(config), &*podcasts) {
+            tracing::error!("{err:#}");
+        }
+    });
+}
+
+/// Replace the podcast stations with the subscriptions
+pub fn update_subscriptions(config: &crate::config::Config, subscriptions: Vec<Subscription>) {
+    with_podcasts(config, |podcasts| {
+        podcasts.update_subscriptions(subscriptions);
+    });
+}
+
+/// The sync server's timestamp of the latest episode actions which have been downloaded
+pub fn episode_actions_since(config: &crate::config::Config) -> i64 {
+    with_podcasts(config, |podcasts| podcasts.episode_actions_since)
+}
+
+/// Apply the progress downloaded from the sync server, and the sync server's timestamp of the download
+pub fn apply_remote_progress(
+    config: &crate::config::Config,
+    progress: Vec<(String, EpisodeProgress)>,
+    episode_actions_since: i64,
+) {
+    with_podcasts(config, |podcasts| {
+        for (episode_url, progress) in progress {
+            podcasts.apply_remote_progress(episode_url, progress);
+        }
+
+        podcasts.episode_actions_since = episode_actions_since;
+    });
+}
+
+/// Record how far an episode has been listened to on this radio, so that it's uploaded at the next sync
+pub fn record_progress(
+    config: &crate::config::Config,
+    episode_url: &str,
+    progress: EpisodeProgress,
+) {
+    with_podcasts(config, |podcasts| {
+        podcasts.record_progress(episode_url, progress);
+    });
+}
+
+/// The progress on this radio which hasn't been uploaded yet
+pub fn unsent_progress(config: &crate::config::Config) -> Vec<UnsentProgress> {
+    with_podcasts(config, |podcasts| {
+        podcasts
+            .stations
+            .iter()
+            .flat_map(|station| {
+                station
+                    .tracks
+                    .iter()
+                    .map(move |track| (&station.feed_url, track.url.as_str()))
+            })
+            .filter(|(_, episode_url)| podcasts.unsent_progress.contains(*episode_url))
+            .filter_map(|(feed_url, episode_url)| {
+                Some(UnsentProgress {
+                    feed_url: feed_url.clone(),
+                    episode_url: String::from(episode_url),
+                    progress: *podcasts.progress.get(episode_url)?,
+                })
+            })
+            .collect()
+    })
+}
+
+/// Mark progress as uploaded, unless it has changed since it was uploaded
+pub fn mark_progress_as_sent(config: &crate::config::Config, sent_progress: &[UnsentProgress]) {
+    with_podcasts(config, |podcasts| {
+        for sent_progress in sent_progress {
+            if podcasts.progress.get(&sent_progress.episode_url) == Some(&sent_progress.progress) {
+                podcasts.unsent_progress.remove(&sent_progress.episode_url);
+            }
+        }
+    });
+}
+
+/// Where to resume an episode, or `None` if it should be played from the start, because it hasn't been started or has been listened to the end
+pub fn resume_position(config: &crate::config::Config, episode_url: &str) -> Option<Duration> {
+    // Episodes are only resumed while podcasts are synced
+    config.podcast_sync.as_ref()?;
+
+    with_podcasts(config, |podcasts| podcasts.resume_position(episode_url))
+}
+
+/// The podcast station with the given index, if there is one
+pub fn load(config: &crate::config::Config, index: &StationIndex) -> Option<Station> {
+    if config.podcast_sync.is_none() || !is_podcast_station(index) {
+        return None;
+    }
+
+    with_podcasts(config, |podcasts| {
+        podcasts.get(index).map(|station| Station::UrlList {
+            index: Some(station.index.clone()),
+            title: Some(station.title.clone()),
+            tracks: station.tracks.clone(),
+        })
+    })
+}
+
+/// The podcast stations, in the order of the subscriptions
+pub fn list(config: &crate::config::Config) -> Vec<StationListing> {
+    if config.podcast_sync.is_none() {
+        return Vec::new();
+    }
+
+    with_podcasts(config, |podcasts| {
+        podcasts
+            .stations
+            .iter()
+            .map(|station| StationListing {
+                index: station.index.clone(),
+                name: station.title.clone(),
+                is_temporary: false,
+            })
+            .collect()
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use rradio_messages::Track;
+
+    use super::{EpisodeProgress, Podcasts, Subscription};
+
+    fn subscription(feed_url: &str, episode_url: Option<&str>) -> Subscription {
+        Subscription {
+            feed_url: feed_url.into(),
+            feed: episode_url
+                .map(|episode_url| (feed_url.into(), vec![Track::url(episode_url.into())])),
+        }
+    }
+
+    fn progress(position: u64, timestamp: i64) -> EpisodeProgress {
+        EpisodeProgress {
+            started: Duration::ZERO,
+            position: Duration::from_secs(position),
+            total: Duration::from_secs(600),
+            timestamp,
+        }
+    }
+
+    #[test]
+    fn subscribed_podcasts_keep_their_index() {
+        let mut podcasts = Podcasts::default();
+
+        podcasts.update_subscriptions(vec![
+            subscription(
+                "https://example.com/a.xml",
+                Some("https://example.com/a1.mp3"),
+            ),
+            subscription(
+                "https://example.com/b.xml",
+                Some("https://example.com/b1.mp3"),
+            ),
+        ]);
+
+        podcasts.record_progress("https://example.com/a1.mp3", progress(60, 1));
+
+        // The feed of b couldn't be fetched, so its previous episodes are kept
+        podcasts.update_subscriptions(vec![
+            subscription("https://example.com/b.xml", None),
+            subscription(
+                "https://example.com/c.xml",
+                Some("https://example.com/c1.mp3"),
+            ),
+        ]);
+
+        assert_eq!(
+            podcasts
+                .stations
+                .iter()
+                .map(|station| (station.index.as_str(), station.tracks[0].url.as_str()))
+                .collect::<Vec<_>>(),
+            [
+                ("P2", "https://example.com/b1.mp3"),
+                ("P3", "https://example.com/c1.mp3")
+            ]
+        );
+
+        // The episodes of a are no longer in any podcast
+        assert!(podcasts.progress.is_empty());
+        assert!(podcasts.unsent_progress.is_empty());
+    }
+
+    #[test]
+    fn newer_progress_wins() {
+        let mut podcasts = Podcasts::default();
+
+        podcasts.record_progress("https://example.com/1.mp3", progress(60, 10));
+
+        podcasts.apply_remote_progress("https://example.com/1.mp3".into(), progress(30, 5));
+        assert_eq!(
+            podcasts.resume_position("https://example.com/1.mp3"),
+            Some(Duration::from_secs(60))
+        );
+
+        podcasts.apply_remote_progress("https://example.com/1.mp3".into(), progress(120, 20));
+        assert_eq!(
+            podcasts.resume_position("https://example.com/1.mp3"),
+            Some(Duration::from_secs(120))
+        );
+        assert!(podcasts.unsent_progress.is_empty());
+
+        // Finished episodes are played from the start
+        podcasts.apply_remote_progress("https://example.com/1.mp3".into(), progress(590, 30));
+        assert_eq!(podcasts.resume_position("https://example.com/1.mp3"), None);
+    }
+}
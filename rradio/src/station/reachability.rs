@@ -0,0 +1,72 @@
+//! A quick check that a stream can be reached, as gstreamer can take a long time to report that a stream is unreachable
+
+use std::time::Duration;
+
+use rradio_messages::{arcstr, ReachabilityError};
+
+use super::Error;
+
+/// Check that a connection can be made to the host of `track_url` within `timeout`
+pub async fn check(
+    track_url: &str,
+    timeout: Duration,
+    resolver: &crate::dns::Resolver,
+) -> Result<(), Error> {
+    tracing::debug!(track_url, "Checking reachability");
+
+    let result = tokio::time::timeout(timeout, try_connect(track_url, resolver))
+        .await
+        .unwrap_or(Err(ReachabilityError::Timeout));
+
+    result.map_err(|err| {
+        tracing::warn!(track_url, "Station is unreachable: {err}");
+
+        Error::Unreachable {
+            url: track_url.into(),
+            err,
+        }
+    })
+}
+
+async fn try_connect(
+    track_url: &str,
+    resolver: &crate::dns::Resolver,
+) -> Result<(), ReachabilityError> {
+    let track_url = url::Url::parse(track_url)
+        .map_err(|err| ReachabilityError::BadUrl(arcstr::format!("{err}")))?;
+
+    if !matches!(track_url.scheme(), "http" | "https") {
+        tracing::trace!("Not checking {} url", track_url.scheme());
+        return Ok(());
+    }
+
+    let host = track_url
+        .host_str()
+        .ok_or_else(|| ReachabilityError::BadUrl(arcstr::literal!("No host")))?;
+
+    let port = track_url.port_or_known_default().unwrap_or(80);
+
+    let addresses = resolver
+        .lookup(host)
+        .await
+        .map_err(|err| ReachabilityError::Dns(arcstr::format!("{err}")))?;
+
+    let mut error = ReachabilityError::NoAddresses;
+
+    for address in addresses {
+        let address = std::net::SocketAddr::from((address, port));
+
+        match tokio::net::TcpStream::connect(address).await {
+            Ok(_) => {
+                tracing::debug!(%address, "Station is reachable");
+                return Ok(());
+            }
+            Err(err) => {
+                tracing::debug!(%address, "Failed to connect: {err}");
+                error = ReachabilityError::Connect(arcstr::format!("{err}"));
+            }
+        }
+    }
+
+    Err(error)
+}
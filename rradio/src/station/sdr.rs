@@ -0,0 +1,351 @@
+//! FM and DAB stations received with an RTL-SDR dongle. `.sdr` station files are TOML, with an optional `title`, and either `fm`, the frequency in MHz,
+//! or `dab`, a table of the `channel` of the multiplex and the name of the `service`, e.g. `dab = { channel = "12B", service = "BBC Radio 4" }`.
+//! The station is tuned by running the configured command, whose standard output is played, and which is stopped when the station changes
+
+use std::{
+    os::unix::{io::AsRawFd, process::CommandExt},
+    process::Stdio,
+};
+
+use anyhow::{Context, Result};
+
+use rradio_messages::{arcstr, ArcStr, StationIndex};
+
+use super::Track;
+
+/// How a station is received
+#[derive(Clone, Debug, PartialEq, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Tuning {
+    /// The frequency in MHz
+    Fm(f64),
+    Dab {
+        channel: ArcStr,
+        service: ArcStr,
+    },
+}
+
+#[derive(Debug, serde::Deserialize)]
+struct StationFile {
+    title: Option<String>,
+    #[serde(flatten)]
+    tuning: Tuning,
+}
+
+#[derive(Debug)]
+pub struct Station {
+    index: StationIndex,
+    title: Option<String>,
+    /// The command which receives the station, with its placeholders replaced
+    command: Vec<String>,
+}
+
+/// The running tuner command, which is stopped when the playlist is dropped
+#[derive(Debug)]
+pub(super) struct Tuner {
+    command: Vec<String>,
+    /// `None` once the command has been stopped
+    child: Option<std::process::Child>,
+    /// Kept open so that the playbin can read from it
+    stdout: std::process::ChildStdout,
+}
+
+/// The command is run in its own process group, so that when it's a shell pipeline, every program in the pipeline can be stopped, releasing the dongle
+fn start(
+    command: &[String],
+) -> Result<(std::process::Child, std::process::ChildStdout), rradio_messages::StationError> {
+    let Some((program, arguments)) = command.split_first() else {
+        return Err(rradio_messages::StationError::SdrError(arcstr::literal!(
+            "No SDR command is configured"
+        )));
+    };
+
+    tracing::debug!(?command, "Starting tuner");
+
+    let mut child = std::process::Command::new(program)
+        .args(arguments)
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::inherit())
+        .process_group(0)
+        .spawn()
+        .map_err(|err| {
+            rradio_messages::StationError::SdrError(arcstr::format!(
+                "Failed to run {program:?}: {err}"
+            ))
+        })?;
+
+    let Some(stdout) = child.stdout.take() else {
+        child.kill().ok();
+        child.wait().ok();
+
+        return Err(rradio_messages::StationError::SdrError(arcstr::literal!(
+            "Tuner has no output"
+        )));
+    };
+
+    Ok((child, stdout))
+}
+
+impl Tuner {
+    fn spawn(command: Vec<String>) -> Result<Self, rradio_messages::StationError> {
+        let (child, stdout) = start(&command)?;
+
+        Ok(Self {
+            command,
+            child: Some(child),
+            stdout,
+        })
+    }
+
+    fn url(&self) -> ArcStr {
+        arcstr::format!("fd://{}", self.stdout.as_raw_fd())
+    }
+
+    /// Stop every program in the command's process group
+    fn stop(&mut self) {
+        let Some(mut child) = self.child.take() else {
+            return;
+        };
+
+        tracing::debug!(pid = child.id(), "Stopping tuner");
+
+        // The process group id is the id of the command, as it leads the group
+        #[allow(clippy::cast_possible_wrap)]
+        let process_group = child.id() as libc::pid_t;
+
+        // SAFETY: `killpg` has no memory safety preconditions. The leader of the process group hasn't been reaped yet, as it's only waited for below, so its id can't have been reused
+        if unsafe { libc::killpg(process_group, libc::SIGKILL) } < 0 {
+            tracing::warn!("Failed to stop tuner: {}", std::io::Error::last_os_error());
+        }
+
+        child.wait().ok();
+    }
+
+    /// Restart the command, as the output of the previous command can't be played again once it's been read.
+    /// The new output replaces the previous output on the same file descriptor, so the track's url plays the new output
+    pub(super) fn restart(&mut self) -> Result<(), rradio_messages::StationError> {
+        // The dongle can only be used by one command at a time
+        self.stop();
+
+        let (child, stdout) = start(&self.command)?;
+
+        // The child is kept before checking for errors, so that it's stopped when the tuner is dropped
+        self.child = Some(child);
+
+        // SAFETY: Both file descriptors are open, as they're owned by `stdout` and `self.stdout`.
+        // `dup2` closes the previous output, and `self.stdout` keeps ownership of its file descriptor, which now refers to the new output
+        if unsafe { libc::dup2(stdout.as_raw_fd(), self.stdout.as_raw_fd()) } < 0 {
+            return Err(rradio_messages::StationError::SdrError(arcstr::format!(
+                "Failed to replace tuner output: {}",
+                std::io::Error::last_os_error()
+            )));
+        }
+
+        Ok(())
+    }
+}
+
+impl Drop for Tuner {
+    fn drop(&mut self) {
+        self.stop();
+    }
+}
+
+/// Replace the placeholders in each argument of the command
+fn command(config: &crate::config::sdr::Config, tuning: &Tuning) -> Vec<String> {
+    match tuning {
+        Tuning::Fm(frequency) => {
+            #[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+            let frequency = (frequency * 1_000_000.0).round() as u64;
+
+            config
+                .fm_command
+                .iter()
+                .map(|argument| argument.replace("{frequency}", &frequency.to_string()))
+                .collect()
+        }
+        Tuning::Dab { channel, service } => config
+            .dab_command
+            .iter()
+            .map(|argument| {
+                argument
+                    .replace("{channel}", channel)
+                    .replace("{service}", service)
+            })
+            .collect(),
+    }
+}
+
+fn from_str(
+    src: &str,
+    index: StationIndex,
+    config: &crate::config::sdr::Config,
+) -> Result<Station> {
+    let StationFile { title, tuning } = toml::from_str(src)?;
+
+    if let Tuning::Fm(frequency) = tuning {
+        anyhow::ensure!(
+            frequency.is_finite() && frequency > 0.0,
+            "Bad FM frequency {frequency}"
+        );
+    }
+
+    let command = command(config, &tuning);
+
+    anyhow::ensure!(!command.is_empty(), "No SDR command is configured");
+
+    Ok(Station {
+        index,
+        title,
+        command,
+    })
+}
+
+pub fn from_file(
+    path: &std::path::Path,
+    index: StationIndex,
+    config: &crate::config::Config,
+) -> Result<super::Station> {
+    let file = std::fs::read_to_string(path)
+        .with_context(|| format!("Failed to read {}", path.display()))?;
+
+    from_str(&file, index, &config.sdr_config)
+        .with_context(|| format!("Failed to parse {}", path.display()))
+        .map(super::Station::Sdr)
+}
+
+impl Station {
+    pub fn index(&self) -> &StationIndex {
+        &self.index
+    }
+
+    pub fn title(&self) -> Option<&str> {
+        self.title.as_deref()
+    }
+
+    /// Start the tuner command. The playlist has a single track, which plays the command's standard output for as long as the playlist's handle is kept
+    pub fn into_playlist(self) -> Result<super::Playlist, rradio_messages::StationError> {
+        let tuner = Tuner::spawn(self.command)?;
+
+        let url = tuner.url();
+
+        let track = Track {
+            title: self.title.as_deref().map(ArcStr::from),
+            ..Track::url(url)
+        };
+
+        Ok(super::Playlist {
+            station_index: Some(self.index),
+            station_title: self.title,
+            station_type: rradio_messages::StationType::Sdr,
+            tracks: vec![track],
+            metadata: super::PlaylistMetadata::default(),
+            handle: super::PlaylistHandle::new(tuner),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        io::Read,
+        time::{Duration, Instant},
+    };
+
+    use rradio_messages::StationIndex;
+
+    use super::{from_str, Tuner};
+
+    fn command(src: &str) -> Vec<String> {
+        let config = crate::config::sdr::Config {
+            fm_command: vec!["rtl_fm".into(), "-f".into(), "{frequency}".into()],
+            dab_command: vec![
+                "dab".into(),
+                "{channel}".into(),
+                "--service={service}".into(),
+            ],
+        };
+
+        from_str(src, StationIndex::new("42".into()), &config)
+            .unwrap()
+            .command
+    }
+
+    #[test]
+    fn placeholders_are_replaced() {
+        assert_eq!(command("fm = 100.9"), ["rtl_fm", "-f", "100900000"]);
+
+        assert_eq!(
+            command("title = \"Radio 4\"\ndab = { channel = \"12B\", service = \"BBC Radio 4\" }"),
+            ["dab", "12B", "--service=BBC Radio 4"]
+        );
+    }
+
+    /// The ids of the running processes in a process group, ignoring zombies which have exited but not yet been reaped
+    fn running_processes(process_group: u32) -> Vec<u32> {
+        std::fs::read_dir("/proc")
+            .unwrap()
+            .filter_map(|entry| {
+                let pid = entry.ok()?.file_name().to_str()?.parse::<u32>().ok()?;
+                let stat = std::fs::read_to_string(format!("/proc/{pid}/stat")).ok()?;
+
+                // The command name, in parentheses, might contain spaces, so the fields are counted from after it
+                let mut fields = stat.rsplit_once(')')?.1.split_whitespace();
+                let state = fields.next()?;
+                let _parent = fields.next()?;
+                let group = fields.next()?.parse::<u32>().ok()?;
+
+                (group == process_group && state != "Z").then_some(pid)
+            })
+            .collect()
+    }
+
+    fn wait_until(condition: impl Fn() -> bool) -> bool {
+        let deadline = Instant::now() + Duration::from_secs(5);
+
+        while Instant::now() < deadline {
+            if condition() {
+                return true;
+            }
+
+            std::thread::sleep(Duration::from_millis(10));
+        }
+
+        condition()
+    }
+
+    #[test]
+    fn dropped_tuner_leaves_no_processes() {
+        let tuner =
+            Tuner::spawn(vec!["sh".into(), "-c".into(), "sleep 60 | sleep 60".into()]).unwrap();
+
+        let process_group = tuner.child.as_ref().unwrap().id();
+
+        // The shell and both programs of the pipeline
+        assert!(wait_until(|| running_processes(process_group).len() >= 3));
+
+        drop(tuner);
+
+        assert!(
+            wait_until(|| running_processes(process_group).is_empty()),
+            "{:?}",
+            running_processes(process_group)
+        );
+    }
+
+    #[test]
+    fn restarted_tuner_replaces_output() {
+        let mut tuner = Tuner::spawn(vec!["echo".into(), "tuned".into()]).unwrap();
+        let url = tuner.url();
+
+        for _ in 0..2 {
+            let mut output = String::new();
+            tuner.stdout.read_to_string(&mut output).unwrap();
+            assert_eq!(output, "tuned\n");
+
+            tuner.restart().unwrap();
+            assert_eq!(tuner.url(), url);
+        }
+    }
+}
@@ -0,0 +1,236 @@
+//! Temporary stations, which are created from playlists pushed by clients with `SetPlaylist`.
+//! They have their own index namespace, e.g. `T1`, so that they can be listed and selected again like the stations in the stations directory.
+//! If `persist` is configured, they are stored as a JSON file in the state directory, otherwise they are lost when rradio restarts
+
+use std::{
+    path::{Path, PathBuf},
+    sync::{Mutex, PoisonError},
+};
+
+use anyhow::Context;
+
+use rradio_messages::{StationIndex, Track};
+
+use super::{Station, StationListing};
+
+const TEMPORARY_STATIONS_FILE_NAME: &str = "temporary_stations.json";
+
+/// The prefix of the indices of temporary stations. Station files start with a digit, so the indices never clash
+const INDEX_PREFIX: &str = "T";
+
+#[derive(Clone, Debug, PartialEq, serde::Deserialize, serde::Serialize)]
+struct TemporaryStation {
+    index: StationIndex,
+    title: String,
+    tracks: Vec<Track>,
+}
+
+#[derive(Debug, Default, serde::Deserialize, serde::Serialize)]
+struct TemporaryStations {
+    /// Oldest first
+    stations: Vec<TemporaryStation>,
+    /// Indices aren't reused, so that a client never selects a different station to the one it listed
+    last_number: u64,
+    /// Incremented whenever the stations are changed. Not persisted
+    #[serde(skip)]
+    revision: u64,
+}
+
+impl TemporaryStations {
+    /// Add a station, replacing the station with the same title if there is one, so that a client pushing an updated playlist doesn't create a new station.
+    /// The oldest stations are removed so that there are at most `maximum_count` stations
+    fn add(&mut self, title: String, tracks: Vec<Track>, maximum_count: usize) -> StationIndex {
+        let index = if let Some(position) = self
+            .stations
+            .iter()
+            .position(|station| station.title == title)
+        {
+            self.stations.remove(position).index
+        } else {
+            self.last_number += 1;
+            StationIndex::new(format!("{INDEX_PREFIX}{}", self.last_number).into())
+        };
+
+        self.stations.push(TemporaryStation {
+            index: index.clone(),
+            title,
+            tracks,
+        });
+
+        let excess_count = self.stations.len().saturating_sub(maximum_count.max(1));
+        self.stations.drain(..excess_count);
+
+        index
+    }
+
+    fn get(&self, index: &StationIndex) -> Option<&TemporaryStation> {
+        self.stations.iter().find(|station| &station.index == index)
+    }
+}
+
+fn path(config: &crate::config::Config) -> PathBuf {
+    Path::new(config.state_directory.as_str()).join(TEMPORARY_STATIONS_FILE_NAME)
+}
+
+/// Access the temporary stations, loading them from the state directory the first time if they are persisted
+fn with_stations<T>(
+    config: &crate::config::Config,
+    f: impl FnOnce(&mut TemporaryStations) -> T,
+) -> T {
+    static STATIONS: Mutex<Option<TemporaryStations>> = Mutex::new(None);
+
+    let mut stations = STATIONS.lock().unwrap_or_else(PoisonError::into_inner);
+
+    let stations = stations.get_or_insert_with(|| {
+        if !config.temporary_stations_config.persist {
+            return TemporaryStations::default();
+        }
+
+        crate::state_file::read_json(&path(config)).unwrap_or_else(|err| {
+            tracing::error!("{err:#}");
+            TemporaryStations::default()
+        })
+    });
+
+    f(stations)
+}
+
+/// Keep a playlist pushed by a client as a temporary station, returning its index
+pub fn add(config: &crate::config::Config, title: String, tracks: Vec<Track>) -> StationIndex {
+    /// The revision of the stations which was last written, so that a slow write doesn't replace a newer one
+    static WRITTEN_REVISION: Mutex<u64> = Mutex::new(0);
+
+    let temporary_stations_config = &config.temporary_stations_config;
+
+    // The stations are serialized while they're locked, but written afterwards, so that listing and loading stations isn't blocked by the write
+    let (index, serialized_stations) = with_stations(config, |stations| {
+        let index = stations.add(title, tracks, temporary_stations_config.maximum_count);
+
+        let serialized_stations = temporary_stations_config.persist.then(|| {
+            stations.revision += 1;
+            (stations.revision, serde_json::to_vec(&*stations))
+        });
+
+        (index, serialized_stations)
+    });
+
+    if let Some((revision, serialized_stations)) = serialized_stations {
+        let mut written_revision = WRITTEN_REVISION
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+
+        if revision > *written_revision {
+            *written_revision = revision;
+
+            if let Err(err) = serialized_stations
+                .context("Failed to serialize temporary stations")
+                .and_then(|serialized_stations| {
+                    crate::state_file::write(&path(config), serialized_stations)
+                })
+            {
+                tracing::error!("{err:#}");
+            }
+        }
+    }
+
+    index
+}
+
+/// Whether `index` is the index of a temporary station, rather than a station in the stations directory
+pub fn is_temporary(index: &StationIndex) -> bool {
+    index.as_str().starts_with(INDEX_PREFIX)
+}
+
+/// The temporary station with the given index, if there is one
+pub fn load(config: &crate::config::Config, index: &StationIndex) -> Option<Station> {
+    if !is_temporary(index) {
+        return None;
+    }
+
+    with_stations(config, |stations| {
+        stations.get(index).map(|station| Station::UrlList {
+            index: Some(station.index.clone()),
+            title: Some(station.title.clone()),
+            tracks: station.tracks.clone(),
+        })
+    })
+}
+
+/// The temporary stations, oldest first
+pub fn list(config: &crate::config::Config) -> Vec<StationListing> {
+    with_stations(config, |stations| {
+        stations
+            .stations
+            .iter()
+            .map(|station| StationListing {
+                index: station.index.clone(),
+                name: station.title.clone(),
+                is_temporary: true,
+            })
+            .collect()
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use rradio_messages::{StationIndex, Track};
+
+    use super::TemporaryStations;
+
+    fn tracks(url: &str) -> Vec<Track> {
+        vec![Track::url(url.into())]
+    }
+
+    fn indices(stations: &TemporaryStations) -> Vec<&str> {
+        stations
+            .stations
+            .iter()
+            .map(|station| station.index.as_str())
+            .collect()
+    }
+
+    #[test]
+    fn oldest_stations_are_removed() {
+        let mut stations = TemporaryStations::default();
+
+        for title in ["A", "B", "C"] {
+            stations.add(title.into(), tracks("http://example.com/a"), 2);
+        }
+
+        assert_eq!(indices(&stations), ["T2", "T3"]);
+        assert!(stations.get(&StationIndex::new("T1".into())).is_none());
+    }
+
+    #[test]
+    fn playlists_with_the_same_title_replace_the_station() {
+        let mut stations = TemporaryStations::default();
+
+        let first_index = stations.add("A".into(), tracks("http://example.com/a"), 10);
+        stations.add("B".into(), tracks("http://example.com/b"), 10);
+        let second_index = stations.add("A".into(), tracks("http://example.com/c"), 10);
+
+        assert_eq!(first_index, second_index);
+        assert_eq!(indices(&stations), ["T2", "T1"]);
+        assert_eq!(
+            stations.get(&first_index).unwrap().tracks,
+            tracks("http://example.com/c")
+        );
+    }
+
+    #[test]
+    fn stations_are_persisted() {
+        let state_directory = tempfile::tempdir().unwrap();
+        let path = state_directory
+            .path()
+            .join(super::TEMPORARY_STATIONS_FILE_NAME);
+
+        let mut stations = TemporaryStations::default();
+        stations.add("A".into(), tracks("http://example.com/a"), 10);
+        crate::state_file::write_json(&path, &stations).unwrap();
+
+        let loaded: TemporaryStations = crate::state_file::read_json(&path).unwrap();
+
+        assert_eq!(loaded.stations, stations.stations);
+        assert_eq!(loaded.last_number, 1);
+    }
+}
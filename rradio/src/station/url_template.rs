@@ -0,0 +1,167 @@
+//! Substitution of the current date and time into station urls, for broadcasters whose archive urls contain the date or hour
+//!
+//! Placeholders are of the form `{field}` or `{field±offset}`, e.g. `{yyyy}`, `{hour-1}`, or `{dd-1d}`.
+//! The offset shifts the time before the field is formatted, and is in units of the field unless a unit (`h` or `d`) is given.
+//! Unrecognised placeholders are left unchanged.
+
+use chrono::{DateTime, Datelike, FixedOffset, Months, Timelike};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Field {
+    Year,
+    ShortYear,
+    Month,
+    Day,
+    Hour,
+}
+
+impl Field {
+    fn parse(name: &str) -> Option<Self> {
+        Some(match name {
+            "yyyy" => Self::Year,
+            "yy" => Self::ShortYear,
+            "mm" => Self::Month,
+            "dd" => Self::Day,
+            "hour" | "hh" => Self::Hour,
+            _ => return None,
+        })
+    }
+
+    fn format(self, time: &DateTime<FixedOffset>) -> String {
+        match self {
+            Self::Year => format!("{:04}", time.year()),
+            Self::ShortYear => format!("{:02}", time.year().rem_euclid(100)),
+            Self::Month => format!("{:02}", time.month()),
+            Self::Day => format!("{:02}", time.day()),
+            Self::Hour => format!("{:02}", time.hour()),
+        }
+    }
+
+    fn default_unit(self) -> Unit {
+        match self {
+            Self::Year | Self::ShortYear => Unit::Years,
+            Self::Month => Unit::Months,
+            Self::Day => Unit::Days,
+            Self::Hour => Unit::Hours,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Unit {
+    Years,
+    Months,
+    Days,
+    Hours,
+}
+
+fn shift(time: DateTime<FixedOffset>, offset: i32, unit: Unit) -> Option<DateTime<FixedOffset>> {
+    match unit {
+        Unit::Years => shift_months(time, offset.checked_mul(12)?),
+        Unit::Months => shift_months(time, offset),
+        Unit::Days => time.checked_add_signed(chrono::Duration::days(offset.into())),
+        Unit::Hours => time.checked_add_signed(chrono::Duration::hours(offset.into())),
+    }
+}
+
+fn shift_months(time: DateTime<FixedOffset>, offset: i32) -> Option<DateTime<FixedOffset>> {
+    let months = Months::new(offset.unsigned_abs());
+
+    if offset < 0 {
+        time.checked_sub_months(months)
+    } else {
+        time.checked_add_months(months)
+    }
+}
+
+/// Expand a single placeholder, i.e. the text between the braces
+fn expand_placeholder(placeholder: &str, now: DateTime<FixedOffset>) -> Option<String> {
+    let (name, offset) = match placeholder.find(['+', '-']) {
+        Some(sign_index) => placeholder.split_at(sign_index),
+        None => (placeholder, ""),
+    };
+
+    let field = Field::parse(name)?;
+
+    if offset.is_empty() {
+        return Some(field.format(&now));
+    }
+
+    let (offset, unit) = match offset.strip_suffix('h') {
+        Some(offset) => (offset, Unit::Hours),
+        None => match offset.strip_suffix('d') {
+            Some(offset) => (offset, Unit::Days),
+            None => (offset, field.default_unit()),
+        },
+    };
+
+    let offset = offset.strip_prefix('+').unwrap_or(offset).parse().ok()?;
+
+    Some(field.format(&shift(now, offset, unit)?))
+}
+
+/// Substitute the placeholders in `url` using the time `now`
+pub fn expand(url: &str, now: DateTime<FixedOffset>) -> String {
+    let mut expanded = String::with_capacity(url.len());
+    let mut remaining = url;
+
+    while let Some(start) = remaining.find('{') {
+        let (before, placeholder_start) = remaining.split_at(start);
+        expanded.push_str(before);
+
+        let Some(end) = placeholder_start.find('}') else {
+            remaining = placeholder_start;
+            break;
+        };
+
+        let placeholder = &placeholder_start[1..end];
+
+        if let Some(value) = expand_placeholder(placeholder, now) {
+            expanded.push_str(&value);
+        } else {
+            tracing::warn!(url, placeholder, "Unrecognised url placeholder");
+            expanded.push_str(&placeholder_start[..=end]);
+        }
+
+        remaining = &placeholder_start[(end + 1)..];
+    }
+
+    expanded.push_str(remaining);
+
+    expanded
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn now() -> DateTime<FixedOffset> {
+        DateTime::parse_from_rfc3339("2024-03-01T00:30:00+01:00").unwrap()
+    }
+
+    #[test]
+    fn fields() {
+        assert_eq!(
+            expand("http://example.com/{yyyy}/{mm}/{dd}/{hour}.mp3", now()),
+            "http://example.com/2024/03/01/00.mp3"
+        );
+
+        assert_eq!(expand("{yy}{hh}", now()), "2400");
+    }
+
+    #[test]
+    fn offsets() {
+        assert_eq!(expand("{hour-1}", now()), "23");
+        assert_eq!(expand("{dd-1}", now()), "29");
+        assert_eq!(expand("{yyyy-1d}{mm-1d}{dd-1d}", now()), "20240229");
+        assert_eq!(expand("{yyyy-1h}{mm-1h}{dd-1h}", now()), "20240229");
+        assert_eq!(expand("{mm+10}", now()), "01");
+        assert_eq!(expand("{yyyy+1}", now()), "2025");
+    }
+
+    #[test]
+    fn unrecognised_placeholders() {
+        assert_eq!(expand("{name}/{yyyy", now()), "{name}/{yyyy");
+        assert_eq!(expand("{dd-x}", now()), "{dd-x}");
+    }
+}
@@ -0,0 +1,106 @@
+//! Wake up servers which host stations, such as a NAS, which are often asleep when a station is selected
+
+use std::{net::Ipv4Addr, time::Duration};
+
+use rradio_messages::ArcStr;
+
+use crate::config::wake_on_lan::{MacAddress, Target};
+
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(1);
+const RETRY_INTERVAL: Duration = Duration::from_secs(1);
+
+/// A magic packet is 6 bytes of `0xFF` followed by 16 repetitions of the target's MAC address
+fn magic_packet(MacAddress(mac_address): MacAddress) -> Vec<u8> {
+    let mut packet = vec![0xFF; 6];
+
+    for _ in 0..16 {
+        packet.extend_from_slice(&mac_address);
+    }
+
+    packet
+}
+
+async fn send_magic_packet(target: &Target) -> std::io::Result<()> {
+    let socket = tokio::net::UdpSocket::bind((Ipv4Addr::UNSPECIFIED, 0)).await?;
+    socket.set_broadcast(true)?;
+    socket
+        .send_to(&magic_packet(target.mac_address), target.broadcast_address)
+        .await?;
+
+    Ok(())
+}
+
+async fn is_awake(host: &str, port: u16) -> bool {
+    matches!(
+        tokio::time::timeout(
+            CONNECT_TIMEOUT,
+            tokio::net::TcpStream::connect((host, port))
+        )
+        .await,
+        Ok(Ok(_))
+    )
+}
+
+/// A server which hosts a station, and which is a Wake-on-LAN target
+pub struct Server {
+    target: Target,
+    port: u16,
+}
+
+impl Server {
+    /// The Wake-on-LAN target which is the host of `url`, if any
+    pub fn find(targets: &[Target], url: &str) -> Option<Self> {
+        let url = url::Url::parse(url).ok()?;
+        let host = url.host_str()?;
+
+        let target = targets.iter().find(|target| target.host == host)?;
+
+        Some(Self {
+            target: target.clone(),
+            port: url.port_or_known_default().unwrap_or(80),
+        })
+    }
+
+    pub fn host(&self) -> &ArcStr {
+        &self.target.host
+    }
+
+    /// Wake the server up, if it's asleep, and wait until it accepts connections. `waiting` is called each time the server is still asleep.
+    ///
+    /// Failures are logged rather than returned, as loading the station will report a more relevant error
+    pub async fn wake(&self, mut waiting: impl FnMut()) {
+        let host = self.target.host.as_str();
+        let port = self.port;
+
+        if is_awake(host, port).await {
+            tracing::debug!(host, "Server is awake");
+            return;
+        }
+
+        tracing::info!(host, mac_address = %self.target.mac_address, "Waking server");
+
+        if let Err(err) = send_magic_packet(&self.target).await {
+            tracing::error!(host, "Failed to send Wake-on-LAN packet: {err}");
+            return;
+        }
+
+        let deadline = tokio::time::Instant::now() + self.target.wake_timeout;
+
+        while tokio::time::Instant::now() < deadline {
+            waiting();
+
+            if is_awake(host, port).await {
+                tracing::info!(host, "Server has woken up");
+                return;
+            }
+
+            tokio::time::sleep(RETRY_INTERVAL).await;
+        }
+
+        tracing::warn!(
+            host,
+            "Server did not wake up within {:?}",
+            self.target.wake_timeout
+        );
+    }
+}
@@ -0,0 +1,241 @@
+//! Checking every night that the first track of each URL list station is reachable, so that users can see which stations are dead before selecting them.
+//! The results are stored as a JSON file in the state directory
+
+use std::{
+    collections::BTreeMap,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use anyhow::{Context, Result};
+use chrono::{DateTime, NaiveDateTime, NaiveTime, Utc};
+
+use rradio_messages::{arcstr, ArcStr, StationIndex, StationType};
+
+use crate::{config::Config, ports::PortChannels};
+
+const STATION_CHECKS_FILE_NAME: &str = "station_checks.json";
+
+/// The result of the latest check of a station
+#[derive(Clone, Debug, serde::Deserialize, serde::Serialize)]
+pub struct StationCheck {
+    pub checked_at: DateTime<Utc>,
+    /// Why the station failed the check, or `None` if it was reachable
+    pub error: Option<ArcStr>,
+    /// When the station was last reachable, or `None` if it has never been reachable
+    pub last_good: Option<DateTime<Utc>>,
+}
+
+impl StationCheck {
+    /// True if the station was reachable when it was last checked
+    pub fn is_good(&self) -> bool {
+        self.error.is_none()
+    }
+}
+
+fn path(state_directory: impl AsRef<Path>) -> PathBuf {
+    state_directory.as_ref().join(STATION_CHECKS_FILE_NAME)
+}
+
+/// Load the results of the latest check of each station. Stations which haven't been checked are missing
+pub fn load(state_directory: impl AsRef<Path>) -> BTreeMap<StationIndex, StationCheck> {
+    crate::state_file::read_json(&path(state_directory)).unwrap_or_else(|err| {
+        tracing::error!("{err:#}");
+        BTreeMap::new()
+    })
+}
+
+fn save(
+    state_directory: impl AsRef<Path>,
+    checks: &BTreeMap<StationIndex, StationCheck>,
+) -> Result<()> {
+    crate::state_file::write_json(&path(state_directory), checks)
+}
+
+/// How often the clock is read, to see whether it's time to check the stations.
+/// The clock is read repeatedly rather than sleeping until the check is due, so that the check happens at the right time of day when daylight saving time starts or ends
+const CLOCK_POLL_INTERVAL: Duration = Duration::from_secs(30);
+
+/// True if the clock showed `time` after `after`, up to and including `until`
+fn is_due(time: NaiveTime, (after, until): (NaiveDateTime, NaiveDateTime)) -> bool {
+    [after.date(), until.date()].iter().any(|date| {
+        let check_at = date.and_time(time);
+        after < check_at && check_at <= until
+    })
+}
+
+/// Check the first track of each URL list station, replacing the results of the previous check. Stations which fail to load fail the check
+async fn check_all(
+    config: &Config,
+    timeout: Duration,
+    resolver: &crate::dns::Resolver,
+    previous_checks: &BTreeMap<StationIndex, StationCheck>,
+) -> Result<BTreeMap<StationIndex, StationCheck>> {
+    let stations_config = config.clone();
+
+    let stations = tokio::task::spawn_blocking(move || crate::station::load_all(&stations_config))
+        .await
+        .context("Station loading task failed")?
+        .context("Failed to load stations")?;
+
+    let mut checks = BTreeMap::new();
+
+    for (index, station) in stations {
+        let result = match station {
+            Ok(station) if station.station_type() != StationType::UrlList => continue,
+            Ok(station) => station.check_reachability(timeout, resolver).await,
+            Err(err) => Err(err),
+        };
+
+        let checked_at = Utc::now();
+
+        let previous_last_good = previous_checks
+            .get(&index)
+            .and_then(|check| check.last_good);
+
+        let check = match result {
+            Ok(()) => StationCheck {
+                checked_at,
+                error: None,
+                last_good: Some(checked_at),
+            },
+            Err(err) => {
+                tracing::warn!(%index, "Station check failed: {err}");
+
+                StationCheck {
+                    checked_at,
+                    error: Some(arcstr::format!("{err}")),
+                    last_good: previous_last_good,
+                }
+            }
+        };
+
+        checks.insert(index, check);
+    }
+
+    Ok(checks)
+}
+
+/// Check the stations every day at the configured time, until rradio shuts down
+pub async fn run(port_channels: PortChannels, config: Config) -> Result<()> {
+    let Some(station_check_config) = config.station_check.clone() else {
+        return Ok(());
+    };
+
+    let resolver = crate::dns::Resolver::new(config.dns_config.clone());
+
+    let state_directory = PathBuf::from(config.state_directory.as_str());
+
+    let run_checks = async {
+        // The local time up to which the schedule has been checked.
+        // This doesn't go backwards when the clocks go back for daylight saving time, so that the stations aren't checked twice
+        let mut checked_until = config.timezone.now().naive_local();
+
+        loop {
+            tokio::time::sleep(CLOCK_POLL_INTERVAL).await;
+
+            let now = config.timezone.now().naive_local();
+
+            if now <= checked_until {
+                continue;
+            }
+
+            let check_is_due = is_due(station_check_config.time.0, (checked_until, now));
+
+            checked_until = now;
+
+            if !check_is_due {
+                continue;
+            }
+
+            tracing::info!("Checking stations");
+
+            let previous_checks = {
+                let state_directory = state_directory.clone();
+
+                match tokio::task::spawn_blocking(move || load(state_directory)).await {
+                    Ok(previous_checks) => previous_checks,
+                    Err(err) => {
+                        tracing::error!("Failed to load previous station checks: {err}");
+                        continue;
+                    }
+                }
+            };
+
+            match check_all(
+                &config,
+                station_check_config.timeout,
+                &resolver,
+                &previous_checks,
+            )
+            .await
+            {
+                Ok(checks) => {
+                    let failure_count = checks.values().filter(|check| !check.is_good()).count();
+
+                    tracing::info!(
+                        station_count = checks.len(),
+                        failure_count,
+                        "Checked stations"
+                    );
+
+                    let state_directory = state_directory.clone();
+
+                    match tokio::task::spawn_blocking(move || save(state_directory, &checks)).await
+                    {
+                        Ok(Ok(())) => (),
+                        Ok(Err(err)) => tracing::error!("{err:#}"),
+                        Err(err) => tracing::error!("Failed to save station checks: {err}"),
+                    }
+                }
+                Err(err) => tracing::error!("{err:#}"),
+            }
+        }
+    };
+
+    tokio::select! {
+        () = port_channels.shutdown_signal.clone() => (),
+        () = run_checks => (),
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::{NaiveDate, NaiveDateTime, NaiveTime};
+
+    use super::is_due;
+
+    fn at(day: u32, hour: u32, minute: u32) -> NaiveDateTime {
+        NaiveDate::from_ymd_opt(2026, 3, day)
+            .unwrap()
+            .and_hms_opt(hour, minute, 0)
+            .unwrap()
+    }
+
+    #[test]
+    fn checks_are_due_once_the_time_has_passed() {
+        let time = NaiveTime::from_hms_opt(3, 30, 0).unwrap();
+
+        assert!(!is_due(time, (at(1, 3, 0), at(1, 3, 29))));
+        assert!(is_due(time, (at(1, 3, 29), at(1, 3, 30))));
+        assert!(!is_due(time, (at(1, 3, 30), at(1, 3, 31))));
+    }
+
+    #[test]
+    fn checks_are_due_across_midnight() {
+        let time = NaiveTime::from_hms_opt(0, 0, 0).unwrap();
+
+        assert!(is_due(time, (at(1, 23, 59), at(2, 0, 0))));
+        assert!(!is_due(time, (at(2, 0, 0), at(2, 0, 1))));
+    }
+
+    #[test]
+    fn checks_skipped_by_daylight_saving_time_are_due() {
+        // The clocks go forward from 01:00 to 02:00, so 01:30 never happens
+        let time = NaiveTime::from_hms_opt(1, 30, 0).unwrap();
+
+        assert!(is_due(time, (at(29, 0, 59), at(29, 2, 0))));
+    }
+}
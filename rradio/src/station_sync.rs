@@ -0,0 +1,396 @@
+//! Periodically syncing the stations directory from a station manifest, so that a family's radios can share one centrally managed list of stations.
+//!
+//! The manifest is a JSON document such as `{"files": {"05.m3u": "...", "07.upnp": "..."}}`, mapping the name of each station file to the hex encoded SHA-256 hash of its contents.
+//! Station files are found relative to the manifest url. If a public key is configured, the manifest must be signed,
+//! and the base64 encoded Ed25519 signature of the manifest is found at the manifest url followed by `.sig`.
+//!
+//! The new stations directory is written alongside the stations directory and then atomically exchanged with it, so that stations are never partially synced.
+//! Only station files are synced. Subdirectories, hidden files, and files whose names aren't UTF-8 are kept as they are
+
+use std::{
+    collections::BTreeMap,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{Context, Result};
+
+use rradio_messages::{arcstr, Event, StationSyncChanges};
+
+/// Manifests and station files which are longer than this are rejected
+const MAXIMUM_FILE_LENGTH: usize = 1024 * 1024;
+
+use crate::{
+    config::station_sync::Config,
+    ports::{PortChannels, SharedEvent},
+};
+
+#[derive(Debug, serde::Deserialize)]
+struct Manifest {
+    /// The hex encoded SHA-256 hash of each station file, keyed by file name
+    files: BTreeMap<String, String>,
+}
+
+/// Station files must be in the stations directory, not in a subdirectory or elsewhere
+fn is_valid_file_name(name: &str) -> bool {
+    !name.is_empty() && !name.starts_with('.') && !name.contains(['/', '\\'])
+}
+
+/// The files which differ between the hashes of the local files and the hashes of the files in the manifest
+fn diff(local: &BTreeMap<String, String>, remote: &BTreeMap<String, String>) -> StationSyncChanges {
+    let mut changes = StationSyncChanges::default();
+
+    for (name, hash) in remote {
+        match local.get(name) {
+            None => changes.added.push(name.as_str().into()),
+            Some(local_hash) if local_hash != hash => changes.changed.push(name.as_str().into()),
+            Some(_) => (),
+        }
+    }
+
+    for name in local.keys() {
+        if !remote.contains_key(name) {
+            changes.removed.push(name.as_str().into());
+        }
+    }
+
+    changes
+}
+
+fn is_empty(changes: &StationSyncChanges) -> bool {
+    changes.added.is_empty() && changes.changed.is_empty() && changes.removed.is_empty()
+}
+
+/// The name of `entry` if it's a station file which is synced, rather than a subdirectory, a hidden file, or a file whose name isn't UTF-8
+fn synced_file_name(entry: &std::fs::DirEntry) -> Option<String> {
+    let name = entry.file_name().into_string().ok()?;
+
+    (entry.path().is_file() && is_valid_file_name(&name)).then_some(name)
+}
+
+/// The contents of each synced file in the stations directory
+fn read_local_files(directory: &Path) -> Result<BTreeMap<String, Vec<u8>>> {
+    let mut files = BTreeMap::new();
+
+    let entries = match std::fs::read_dir(directory) {
+        Ok(entries) => entries,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(files),
+        Err(err) => {
+            return Err(err).with_context(|| format!("Failed to read {}", directory.display()))
+        }
+    };
+
+    for entry in entries {
+        let entry = entry.with_context(|| format!("Failed to read {}", directory.display()))?;
+
+        let Some(name) = synced_file_name(&entry) else {
+            continue;
+        };
+
+        let path = entry.path();
+
+        let contents =
+            std::fs::read(&path).with_context(|| format!("Failed to read {}", path.display()))?;
+
+        files.insert(name, contents);
+    }
+
+    Ok(files)
+}
+
+fn sibling_path(directory: &Path, suffix: &str) -> PathBuf {
+    let mut path = directory.as_os_str().to_owned();
+    path.push(suffix);
+    PathBuf::from(path)
+}
+
+/// Atomically exchange two directories
+fn exchange(a: &Path, b: &Path) -> std::io::Result<()> {
+    use std::{ffi::CString, os::unix::ffi::OsStrExt};
+
+    let a = CString::new(a.as_os_str().as_bytes())?;
+    let b = CString::new(b.as_os_str().as_bytes())?;
+
+    // SAFETY: Both paths are valid C strings which outlive the call
+    let result = unsafe {
+        libc::renameat2(
+            libc::AT_FDCWD,
+            a.as_ptr(),
+            libc::AT_FDCWD,
+            b.as_ptr(),
+            libc::RENAME_EXCHANGE,
+        )
+    };
+
+    if result == 0 {
+        Ok(())
+    } else {
+        Err(std::io::Error::last_os_error())
+    }
+}
+
+/// Move the entries of `from` which aren't synced station files into `to`
+fn move_unsynced_entries(from: &Path, to: &Path) -> Result<()> {
+    for entry in
+        std::fs::read_dir(from).with_context(|| format!("Failed to read {}", from.display()))?
+    {
+        let entry = entry.with_context(|| format!("Failed to read {}", from.display()))?;
+
+        if synced_file_name(&entry).is_none() {
+            let path = entry.path();
+
+            std::fs::rename(&path, to.join(entry.file_name()))
+                .with_context(|| format!("Failed to move {}", path.display()))?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Write `files` to a new directory alongside `directory`, and then exchange it with `directory`, keeping the entries of `directory` which aren't synced
+fn swap_in(directory: &Path, files: &BTreeMap<String, Vec<u8>>) -> Result<()> {
+    let new_directory = sibling_path(directory, ".sync-new");
+
+    if new_directory.exists() {
+        std::fs::remove_dir_all(&new_directory)
+            .with_context(|| format!("Failed to remove {}", new_directory.display()))?;
+    }
+
+    std::fs::create_dir_all(&new_directory)
+        .with_context(|| format!("Failed to create {}", new_directory.display()))?;
+
+    for (name, contents) in files {
+        let path = new_directory.join(name);
+
+        std::fs::write(&path, contents)
+            .with_context(|| format!("Failed to write {}", path.display()))?;
+    }
+
+    if !directory.exists() {
+        return std::fs::rename(&new_directory, directory)
+            .with_context(|| format!("Failed to create {}", directory.display()));
+    }
+
+    move_unsynced_entries(directory, &new_directory)?;
+
+    if let Err(err) = exchange(&new_directory, directory) {
+        // Put the entries which aren't synced back
+        move_unsynced_entries(&new_directory, directory).ok();
+
+        return Err(err).with_context(|| format!("Failed to replace {}", directory.display()));
+    }
+
+    // The previous station files are now in the new directory
+    std::fs::remove_dir_all(&new_directory)
+        .with_context(|| format!("Failed to remove {}", new_directory.display()))
+}
+
+async fn fetch(client: &reqwest::Client, url: &str) -> Result<Vec<u8>> {
+    crate::http::fetch(client, url, MAXIMUM_FILE_LENGTH).await
+}
+
+/// Sync the stations directory from the manifest, returning what changed
+async fn sync(
+    client: &reqwest::Client,
+    config: &Config,
+    stations_directory: &Path,
+) -> Result<StationSyncChanges> {
+    let manifest_url = config.manifest_url.as_str();
+
+    let manifest = fetch(client, manifest_url).await?;
+
+    if let Some(public_key) = &config.public_key {
+        let signature = fetch(client, &format!("{manifest_url}.sig")).await?;
+        let signature = String::from_utf8_lossy(&signature);
+
+        crate::updater::verify_ed25519_signature(public_key, &manifest, signature.trim())
+            .context("Failed to verify station manifest")?;
+    }
+
+    let Manifest {
+        files: remote_hashes,
+    } = serde_json::from_slice(&manifest)
+        .with_context(|| format!("Failed to parse {manifest_url}"))?;
+
+    if let Some(name) = remote_hashes.keys().find(|name| !is_valid_file_name(name)) {
+        anyhow::bail!("Bad station file name {name:?}");
+    }
+
+    let remote_hashes = remote_hashes
+        .into_iter()
+        .map(|(name, hash)| (name, hash.to_ascii_lowercase()))
+        .collect();
+
+    let directory = stations_directory.to_owned();
+    let mut files = tokio::task::spawn_blocking(move || read_local_files(&directory))
+        .await
+        .context("Station reading task failed")??;
+
+    let local_hashes = files
+        .iter()
+        .map(|(name, contents)| (name.clone(), crate::updater::sha256(contents)))
+        .collect();
+
+    let changes = diff(&local_hashes, &remote_hashes);
+
+    if is_empty(&changes) {
+        return Ok(changes);
+    }
+
+    let base_url = url::Url::parse(manifest_url)
+        .with_context(|| format!("Bad manifest url {manifest_url:?}"))?;
+
+    for name in changes.added.iter().chain(&changes.changed) {
+        // Station files are in the same directory as the manifest
+        let mut file_url = base_url.clone();
+        file_url
+            .path_segments_mut()
+            .map_err(|()| anyhow::anyhow!("Bad manifest url {manifest_url:?}"))?
+            .pop()
+            .push(name);
+
+        let contents = fetch(client, file_url.as_str()).await?;
+
+        if Some(&crate::updater::sha256(&contents)) != remote_hashes.get(name.as_str()) {
+            anyhow::bail!("{file_url} does not match its hash");
+        }
+
+        files.insert(name.to_string(), contents);
+    }
+
+    for name in &changes.removed {
+        files.remove(name.as_str());
+    }
+
+    let directory = stations_directory.to_owned();
+    tokio::task::spawn_blocking(move || swap_in(&directory, &files))
+        .await
+        .context("Station writing task failed")??;
+
+    Ok(changes)
+}
+
+/// Sync the stations directory every `interval`, until rradio shuts down
+pub async fn run(port_channels: PortChannels, config: crate::config::Config) -> Result<()> {
+    let Some(station_sync_config) = config.station_sync.clone() else {
+        return Ok(());
+    };
+
+    let client = crate::updater::http_client()?;
+
+    let stations_directory = PathBuf::from(config.stations_directory.as_str());
+
+    let run_syncs = async {
+        loop {
+            tracing::debug!("Syncing stations");
+
+            let result = match sync(&client, &station_sync_config, &stations_directory).await {
+                Ok(changes) if is_empty(&changes) => {
+                    tracing::debug!("Stations are up to date");
+                    None
+                }
+                Ok(changes) => {
+                    tracing::info!(?changes, "Synced stations");
+                    Some(Ok(changes))
+                }
+                Err(err) => {
+                    tracing::error!("Failed to sync stations: {err:#}");
+                    Some(Err(arcstr::format!("{err:#}")))
+                }
+            };
+
+            if let Some(result) = result {
+                // There might not be any connected clients, which is not an error
+                port_channels
+                    .events_tx
+                    .send(SharedEvent::new(Event::StationsSynced(result)))
+                    .ok();
+            }
+
+            tokio::time::sleep(station_sync_config.interval).await;
+        }
+    };
+
+    tokio::select! {
+        () = port_channels.shutdown_signal.clone() => (),
+        () = run_syncs => (),
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use rradio_messages::{ArcStr, StationSyncChanges};
+
+    fn hashes(files: &[(&str, &str)]) -> BTreeMap<String, String> {
+        files
+            .iter()
+            .map(|&(name, hash)| (String::from(name), String::from(hash)))
+            .collect()
+    }
+
+    fn names(names: &[&str]) -> Vec<ArcStr> {
+        names.iter().map(|&name| name.into()).collect()
+    }
+
+    #[test]
+    fn changes_are_found_by_hash() {
+        let local = hashes(&[("01.m3u", "a"), ("02.m3u", "b"), ("03.m3u", "c")]);
+        let remote = hashes(&[("01.m3u", "a"), ("02.m3u", "x"), ("04.m3u", "d")]);
+
+        assert_eq!(
+            super::diff(&local, &remote),
+            StationSyncChanges {
+                added: names(&["04.m3u"]),
+                changed: names(&["02.m3u"]),
+                removed: names(&["03.m3u"]),
+            }
+        );
+
+        assert!(super::is_empty(&super::diff(&local, &local)));
+    }
+
+    #[test]
+    fn station_files_are_not_written_outside_the_stations_directory() {
+        assert!(super::is_valid_file_name("05.m3u"));
+        assert!(!super::is_valid_file_name("../config.toml"));
+        assert!(!super::is_valid_file_name("sub/05.m3u"));
+        assert!(!super::is_valid_file_name(".hidden"));
+        assert!(!super::is_valid_file_name(""));
+    }
+
+    #[test]
+    fn stations_directory_is_replaced() {
+        let parent = tempfile::tempdir().unwrap();
+        let directory = parent.path().join("stations");
+
+        std::fs::create_dir_all(directory.join("podcasts")).unwrap();
+        std::fs::write(directory.join("01.m3u"), "old").unwrap();
+        std::fs::write(directory.join(".hidden"), "kept").unwrap();
+        std::fs::write(
+            directory
+                .join(<std::ffi::OsStr as std::os::unix::ffi::OsStrExt>::from_bytes(b"\xff.m3u")),
+            "kept",
+        )
+        .unwrap();
+        std::fs::write(directory.join("podcasts").join("01.m3u"), "kept").unwrap();
+
+        let files = IntoIterator::into_iter([(String::from("02.m3u"), b"new".to_vec())]).collect();
+
+        super::swap_in(&directory, &files).unwrap();
+
+        assert_eq!(super::read_local_files(&directory).unwrap(), files);
+        assert_eq!(
+            std::fs::read(directory.join(".hidden")).unwrap(),
+            b"kept".to_vec()
+        );
+        assert_eq!(
+            std::fs::read(directory.join("podcasts").join("01.m3u")).unwrap(),
+            b"kept".to_vec()
+        );
+        assert_eq!(std::fs::read_dir(&directory).unwrap().count(), 4);
+        assert_eq!(std::fs::read_dir(parent.path()).unwrap().count(), 1);
+    }
+}
@@ -39,4 +39,6 @@ impl_stream_select!(
     S1;
     S1 S2;
     S1 S2 S3;
+    S1 S2 S3 S4;
+    S1 S2 S3 S4 S5;
 );
@@ -0,0 +1,109 @@
+//! Powering off and rebooting the device once rradio has shut down cleanly, so that the SD card isn't corrupted by pulling the plug.
+//!
+//! `busctl` is used to ask systemd-logind over D-Bus, so rradio doesn't need to be root as long as the logind policy allows it.
+//!
+//! After an update has been installed, rradio restarts by exiting with [`RESTART_EXIT_STATUS`], so that systemd restarts the service with the new binary.
+//! The service is then started with the same user, capabilities and environment as before, which wouldn't be the case if rradio ran the new binary itself.
+
+use anyhow::{Context, Result};
+
+/// The exit status which asks systemd to restart rradio, which needs `RestartForceExitStatus=75` in the service unit. 75 is `EX_TEMPFAIL`
+pub const RESTART_EXIT_STATUS: i32 = 75;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SystemAction {
+    PowerOff,
+    Reboot,
+    /// Exit with [`RESTART_EXIT_STATUS`], so that the service manager starts rradio again
+    Restart,
+}
+
+impl SystemAction {
+    /// Power off, reboot, or restart
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if logind refuses to power off or reboot
+    pub fn perform(self) -> Result<()> {
+        match self {
+            Self::PowerOff => call_logind("PowerOff"),
+            Self::Reboot => call_logind("Reboot"),
+            Self::Restart => {
+                tracing::info!("Exiting with status {RESTART_EXIT_STATUS} to be restarted");
+
+                std::process::exit(RESTART_EXIT_STATUS)
+            }
+        }
+    }
+}
+
+fn call_logind(method: &str) -> Result<()> {
+    tracing::info!("Asking logind to {method}");
+
+    let output = std::process::Command::new("busctl")
+        .args([
+            "call",
+            "org.freedesktop.login1",
+            "/org/freedesktop/login1",
+            "org.freedesktop.login1.Manager",
+            method,
+            "b",
+            "false", // Not interactive, i.e. fail rather than asking for a password
+        ])
+        .output()
+        .context("Failed to run busctl")?;
+
+    if output.status.success() {
+        Ok(())
+    } else {
+        Err(anyhow::anyhow!(
+            "Failed to {method}: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        ))
+    }
+}
+
+/// Compare tokens without returning early, so that the time taken doesn't reveal how much of the token is correct
+pub fn tokens_match(token: &str, expected_token: &str) -> bool {
+    token.len() == expected_token.len()
+        && token
+            .bytes()
+            .zip(expected_token.bytes())
+            .fold(0, |difference, (a, b)| difference | (a ^ b))
+            == 0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::tokens_match;
+
+    #[test]
+    fn only_identical_tokens_match() {
+        assert!(tokens_match("correct horse", "correct horse"));
+
+        assert!(!tokens_match("correct hors", "correct horse"));
+        assert!(!tokens_match("correct horse!", "correct horse"));
+        assert!(!tokens_match("correct house", "correct horse"));
+        assert!(!tokens_match("", "correct horse"));
+    }
+
+    #[test]
+    fn empty_tokens_are_rejected() {
+        for token in ["", " "] {
+            let config = format!("system_command_token = {token:?}");
+
+            assert!(toml::from_str::<crate::config::Config>(&config).is_err());
+        }
+
+        let config: crate::config::Config =
+            toml::from_str("system_command_token = \"correct horse\"").unwrap();
+
+        assert_eq!(
+            config
+                .system_command_token
+                .as_ref()
+                .map(crate::config::SystemCommandToken::as_str),
+            Some("correct horse")
+        );
+    }
+}
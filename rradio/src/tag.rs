@@ -1,9 +1,84 @@
 //! Tracks have tags attached to them.
 
+use std::convert::TryFrom;
+
 use anyhow::{Context, Result};
 use glib::value::SendValue;
 
-use rradio_messages::{ArcStr, Image};
+use rradio_messages::{ArcStr, Image, StationIndex};
+
+use crate::config::{
+    tag_encoding,
+    tag_filter::{Field, Filter},
+};
+
+/// How tags which aren't UTF-8 are decoded.
+///
+/// gstreamer decodes tags which aren't valid UTF-8 as Latin-1, so tags in legacy encodings such as Windows-1251 or GBK, as found in the ID3 tags of old MP3s, are garbled.
+/// Such tags are converted back into their original bytes, which are then decoded using the configured or detected encoding, unless they look like Latin-1
+#[derive(Clone, Copy, Debug)]
+pub enum Encoding {
+    /// Tags are used as decoded by gstreamer
+    AsDecoded,
+    Detect,
+    Fixed(&'static encoding_rs::Encoding),
+}
+
+impl Encoding {
+    /// The encoding of the tags of station `station_index`
+    pub fn for_station(
+        config: &tag_encoding::Config,
+        station_index: Option<&StationIndex>,
+    ) -> Self {
+        let station_encoding =
+            station_index.and_then(|station_index| config.stations.get(station_index.as_str()));
+
+        match station_encoding.or(config.encoding.as_ref()) {
+            Some(&tag_encoding::Encoding(encoding)) => Self::Fixed(encoding),
+            None if config.detect => Self::Detect,
+            None => Self::AsDecoded,
+        }
+    }
+
+    /// Decode `value` again, returning None if it doesn't need to be decoded again, or can't be
+    fn redecode(self, value: &str) -> Option<String> {
+        let fixed_encoding = match self {
+            Self::AsDecoded => return None,
+            Self::Detect => None,
+            Self::Fixed(encoding) => Some(encoding),
+        };
+
+        // Only text decoded as Latin-1 can be converted back into the original bytes
+        let bytes = value
+            .chars()
+            .map(|c| u8::try_from(u32::from(c)).ok())
+            .collect::<Option<Vec<u8>>>()?;
+
+        if bytes.is_ascii() {
+            return None;
+        }
+
+        let mut detector = chardetng::EncodingDetector::new();
+        detector.feed(&bytes, true);
+        let detected_encoding = detector.guess(None, true);
+
+        // Text which looks like Latin-1, such as "Café", was decoded correctly, so is only decoded again if it doesn't
+        if detected_encoding == encoding_rs::WINDOWS_1252 {
+            return None;
+        }
+
+        let encoding = fixed_encoding.unwrap_or(detected_encoding);
+
+        let decoded = encoding.decode_without_bom_handling_and_without_replacement(&bytes)?;
+
+        if decoded == value {
+            None
+        } else {
+            tracing::debug!(%value, %decoded, encoding = encoding.name(), "Decoded tag");
+            Some(decoded.into_owned())
+        }
+    }
+}
 
 /// A tag attached to a track
 #[derive(Debug)]
@@ -12,20 +87,27 @@ pub enum Tag {
     Organisation(ArcStr),
     Artist(ArcStr),
     Album(ArcStr),
+    TrackNumber(u32),
+    DiscNumber(u32),
     Genre(ArcStr),
     Image(Image),
     Comment(ArcStr),
+    AudioCodec(ArcStr),
     Unknown { name: ArcStr, value: ArcStr },
 }
 
 impl Tag {
-    pub fn from_value(name: &str, value: &SendValue) -> Result<Self> {
+    pub fn from_value(name: &str, value: &SendValue, encoding: Encoding) -> Result<Self> {
         match name {
-            "title" => get_atomic_string(value, Self::Title),
-            "organisation" | "organization" => get_atomic_string(value, Self::Organisation),
-            "artist" => get_atomic_string(value, Self::Artist),
-            "album" => get_atomic_string(value, Self::Album),
-            "genre" => get_atomic_string(value, Self::Genre),
+            "title" => get_atomic_string(value, encoding, Self::Title),
+            "organisation" | "organization" => {
+                get_atomic_string(value, encoding, Self::Organisation)
+            }
+            "artist" => get_atomic_string(value, encoding, Self::Artist),
+            "album" => get_atomic_string(value, encoding, Self::Album),
+            "track-number" => get_value(value, Self::TrackNumber),
+            "album-disc-number" => get_value(value, Self::DiscNumber),
+            "genre" => get_atomic_string(value, encoding, Self::Genre),
             "image" => {
                 let image = value.get::<gstreamer::Sample>().context("No Value")?;
 
@@ -41,7 +123,8 @@ impl Tag {
 
                 Ok(Self::Image(Image::new(mime_type, readable_mem.as_slice())))
             }
-            "comment" => get_atomic_string(value, Self::Comment),
+            "comment" => get_atomic_string(value, encoding, Self::Comment),
+            "audio-codec" => get_value(value, |codec: &str| Self::AudioCodec(codec.into())),
             _ => Ok(Self::Unknown {
                 name: name.into(),
                 value: value_to_string(value)?.into(),
@@ -59,8 +142,18 @@ where
     value.get().context("No Value").map(builder)
 }
 
-fn get_atomic_string<F: FnOnce(ArcStr) -> Tag>(value: &SendValue, builder: F) -> Result<Tag> {
-    get_value(value, |str: &str| builder(ArcStr::from(str)))
+fn get_atomic_string<F: FnOnce(ArcStr) -> Tag>(
+    value: &SendValue,
+    encoding: Encoding,
+    builder: F,
+) -> Result<Tag> {
+    get_value(value, |str: &str| {
+        builder(
+            encoding
+                .redecode(str)
+                .map_or_else(|| ArcStr::from(str), ArcStr::from),
+        )
+    })
 }
 
 pub fn value_to_string(value: &glib::value::Value) -> Result<String> {
@@ -81,3 +174,224 @@ pub fn value_to_string(value: &glib::value::Value) -> Result<String> {
         format!("Value of unhandled type {value_type}: {value:?}")
     })
 }
+
+/// Apply the filters which apply to `field` of the tags of station `station_index` to `value`.
+/// Returns None if the filtered value is empty
+pub fn filter_value(
+    filters: &[Filter],
+    station_index: Option<&StationIndex>,
+    field: Field,
+    value: ArcStr,
+) -> Option<ArcStr> {
+    let filters = filters.iter().filter(|filter| {
+        filter.fields.contains(&field)
+            && (filter.stations.is_empty()
+                || station_index.is_some_and(|station_index| {
+                    filter
+                        .stations
+                        .iter()
+                        .any(|station| station.as_str() == station_index.as_str())
+                }))
+    });
+
+    let mut filtered_value = std::borrow::Cow::Borrowed(value.as_str());
+
+    for filter in filters {
+        // Only replace the value if the filter matched, to avoid needlessly allocating
+        let new_value = match filter
+            .pattern
+            .0
+            .replace_all(&filtered_value, filter.replacement.as_str())
+        {
+            std::borrow::Cow::Borrowed(_) => None,
+            std::borrow::Cow::Owned(new_value) => Some(new_value),
+        };
+
+        if let Some(new_value) = new_value {
+            filtered_value = std::borrow::Cow::Owned(new_value);
+        }
+    }
+
+    match filtered_value {
+        std::borrow::Cow::Borrowed(_) => Some(value),
+        std::borrow::Cow::Owned(filtered_value) => {
+            let filtered_value = filtered_value.trim();
+
+            if filtered_value.is_empty() {
+                None
+            } else {
+                Some(filtered_value.into())
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::convert::TryFrom;
+
+    use rradio_messages::{arcstr, ArcStr, StationIndex};
+
+    use crate::config::{
+        tag_encoding,
+        tag_filter::{Field, Filter, Pattern},
+    };
+
+    use super::Encoding;
+
+    /// Encode `text` with `encoding`, and then decode it as Latin-1, as gstreamer does
+    fn garble(text: &str, encoding: &'static encoding_rs::Encoding) -> String {
+        encoding
+            .encode(text)
+            .0
+            .iter()
+            .map(|&b| char::from(b))
+            .collect()
+    }
+
+    #[test]
+    fn legacy_encodings_are_decoded() {
+        let cyrillic = garble("Кино - Группа крови", encoding_rs::WINDOWS_1251);
+        let chinese = garble("周杰伦 - 晴天", encoding_rs::GBK);
+
+        assert_eq!(
+            Encoding::Fixed(encoding_rs::WINDOWS_1251)
+                .redecode(&cyrillic)
+                .as_deref(),
+            Some("Кино - Группа крови")
+        );
+
+        assert_eq!(
+            Encoding::Detect.redecode(&cyrillic).as_deref(),
+            Some("Кино - Группа крови")
+        );
+
+        assert_eq!(
+            Encoding::Detect.redecode(&chinese).as_deref(),
+            Some("周杰伦 - 晴天")
+        );
+
+        // Tags which are already correct are unchanged
+        assert_eq!(Encoding::AsDecoded.redecode(&cyrillic), None);
+        assert_eq!(Encoding::Detect.redecode("Кино"), None);
+        assert_eq!(Encoding::Detect.redecode("Title"), None);
+        assert_eq!(Encoding::Detect.redecode("Café del Mar"), None);
+    }
+
+    #[test]
+    fn fixed_encodings_only_decode_garbled_tags() {
+        for (text, encoding) in [
+            ("Кино - Группа крови", encoding_rs::WINDOWS_1251),
+            ("Мумий Тролль", encoding_rs::KOI8_R),
+            ("周杰伦 - 晴天", encoding_rs::GBK),
+        ] {
+            assert_eq!(
+                Encoding::Fixed(encoding)
+                    .redecode(&garble(text, encoding))
+                    .as_deref(),
+                Some(text)
+            );
+        }
+
+        for text in ["Title", "Café del Mar", "Björk - Jóga", "Кино"] {
+            assert_eq!(
+                Encoding::Fixed(encoding_rs::WINDOWS_1251).redecode(text),
+                None
+            );
+        }
+    }
+
+    #[test]
+    fn station_encodings_override_the_default_encoding() {
+        let config = tag_encoding::Config {
+            detect: true,
+            encoding: Some(tag_encoding::Encoding(encoding_rs::WINDOWS_1251)),
+            stations: vec![(
+                arcstr::literal!("05"),
+                tag_encoding::Encoding(encoding_rs::GBK),
+            )]
+            .into_iter()
+            .collect(),
+        };
+
+        assert!(matches!(
+            Encoding::for_station(&config, Some(&StationIndex::new("05".into()))),
+            Encoding::Fixed(encoding) if encoding == encoding_rs::GBK
+        ));
+
+        assert!(matches!(
+            Encoding::for_station(&config, None),
+            Encoding::Fixed(encoding) if encoding == encoding_rs::WINDOWS_1251
+        ));
+
+        assert!(matches!(
+            Encoding::for_station(&tag_encoding::Config::default(), None),
+            Encoding::AsDecoded
+        ));
+    }
+
+    fn advert_filter(stations: Vec<ArcStr>) -> Filter {
+        Filter {
+            fields: vec![Field::Title],
+            pattern: Pattern::try_from(String::from(r"^\*\*\* Now on AwesomeFM: (.*) \*\*\*$"))
+                .unwrap(),
+            replacement: String::from("$1"),
+            stations,
+        }
+    }
+
+    #[test]
+    fn adverts_are_removed() {
+        assert_eq!(
+            super::filter_value(
+                &[advert_filter(Vec::new())],
+                None,
+                Field::Title,
+                arcstr::literal!("*** Now on AwesomeFM: Song ***"),
+            )
+            .as_deref(),
+            Some("Song")
+        );
+    }
+
+    #[test]
+    fn other_fields_and_stations_are_unchanged() {
+        let filters = [advert_filter(vec![arcstr::literal!("05")])];
+        let value = arcstr::literal!("*** Now on AwesomeFM: Song ***");
+
+        assert_eq!(
+            super::filter_value(&filters, None, Field::Title, value.clone()),
+            Some(value.clone())
+        );
+
+        assert_eq!(
+            super::filter_value(
+                &filters,
+                Some(&StationIndex::new("05".into())),
+                Field::Artist,
+                value.clone()
+            ),
+            Some(value)
+        );
+    }
+
+    #[test]
+    fn empty_values_are_removed() {
+        let filter = Filter {
+            fields: vec![Field::Comment],
+            pattern: Pattern::try_from(String::from("advert")).unwrap(),
+            replacement: String::new(),
+            stations: Vec::new(),
+        };
+
+        assert_eq!(
+            super::filter_value(
+                &[filter],
+                None,
+                Field::Comment,
+                arcstr::literal!(" advert ")
+            ),
+            None
+        );
+    }
+}
@@ -5,5 +5,5 @@ mod shutdown;
 mod wait_group;
 
 pub use log_error::FailableFuture;
-pub use shutdown::Signal as ShutdownSignal;
+pub use shutdown::{Handle as ShutdownHandle, Signal as ShutdownSignal};
 pub use wait_group::{Handle as WaitGroupHandle, WaitGroup};
@@ -1,34 +1,47 @@
 //! Wait for all tasks to complete
 
+use std::collections::BTreeMap;
 use std::future::Future;
 use std::sync::Arc;
+use std::time::Duration;
 
+use tokio::sync::watch;
 use tracing::Instrument;
 
 use super::shutdown;
 
-struct TaskIsActive(Handle);
+/// The names of the tasks which haven't terminated, keyed by task id
+type ActiveTasks = BTreeMap<usize, &'static str>;
 
 struct LogDroppedTask {
-    is_active: Option<TaskIsActive>,
+    handle: Handle,
+    task_id: usize,
+    is_active: bool,
 }
 
 impl LogDroppedTask {
     fn shutdown(mut self) {
-        self.is_active.take();
+        self.is_active = false;
     }
 }
 
 impl Drop for LogDroppedTask {
     fn drop(&mut self) {
-        if let Some(TaskIsActive(handle)) = self.is_active.take() {
-            tracing::warn!(id = handle.id(), "Task has been aborted");
+        let task_id = self.task_id;
+
+        self.handle.0.active_tasks.send_modify(|active_tasks| {
+            active_tasks.remove(&task_id);
+        });
+
+        if self.is_active {
+            tracing::warn!(id = self.handle.id(), "Task has been aborted");
         }
     }
 }
 
 struct HandleCore {
     id: usize,
+    active_tasks: watch::Sender<ActiveTasks>,
     #[allow(dead_code)]
     handle: shutdown::Handle,
 }
@@ -42,18 +55,30 @@ impl Handle {
         self.0.id
     }
 
-    /// Spawn a new task using the same wait group as this handle
+    /// Spawn a new task using the same wait group as this handle. `name` is used to look up the task's shutdown grace period, and to report it if it doesn't stop in time
     pub fn spawn_task(
         &self,
+        name: &'static str,
         span: tracing::Span,
         task: impl Future<Output = anyhow::Result<()>> + Send + 'static,
     ) {
-        let handle = self.clone();
+        static TASK_ID: std::sync::atomic::AtomicUsize = std::sync::atomic::AtomicUsize::new(0);
+
+        let task_id = TASK_ID.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+
+        self.0.active_tasks.send_modify(|active_tasks| {
+            active_tasks.insert(task_id, name);
+        });
+
+        // Created before the task is spawned, so that the task is removed from the active tasks even if it is dropped before it is first polled
+        let log_dropped_task = LogDroppedTask {
+            handle: self.clone(),
+            task_id,
+            is_active: true,
+        };
+
         tokio::spawn(
             async move {
-                let log_dropped_task = LogDroppedTask {
-                    is_active: Some(TaskIsActive(handle)),
-                };
                 let result = task.await;
                 log_dropped_task.shutdown();
 
@@ -77,6 +102,7 @@ impl Drop for Handle {
 /// A `WaitGroup` allows a task to wait for multiple other tasks to terminate
 pub struct WaitGroup {
     handle: Handle,
+    active_tasks: watch::Receiver<ActiveTasks>,
     complete: shutdown::Signal,
 }
 
@@ -86,11 +112,15 @@ impl WaitGroup {
 
         let (handle, complete) = shutdown::Signal::new();
 
+        let (active_tasks_tx, active_tasks) = watch::channel(ActiveTasks::new());
+
         Self {
             handle: Handle(Arc::new(HandleCore {
                 id: ID.fetch_add(1, std::sync::atomic::Ordering::SeqCst),
+                active_tasks: active_tasks_tx,
                 handle,
             })),
+            active_tasks,
             complete,
         }
     }
@@ -103,10 +133,11 @@ impl WaitGroup {
     /// Spawn a task which the group will wait for
     pub fn spawn_task(
         &self,
+        name: &'static str,
         span: tracing::Span,
         task: impl Future<Output = anyhow::Result<()>> + Send + 'static,
     ) {
-        self.handle.spawn_task(span, task);
+        self.handle.spawn_task(name, span, task);
     }
 
     /// Wait for all spawned tasks to terminate
@@ -125,4 +156,65 @@ impl WaitGroup {
 
         tracing::trace!(?id, "Success");
     }
+
+    /// Wait for all spawned tasks to terminate, giving each task the grace period returned by `grace_period` when called with the name of the task.
+    /// Returns the names of the tasks which are still running after their grace period
+    pub async fn wait_with_grace_periods(
+        self,
+        grace_period: impl Fn(&str) -> Duration,
+    ) -> Vec<&'static str> {
+        let start = tokio::time::Instant::now();
+
+        let mut active_tasks = self.active_tasks.clone();
+
+        let complete = self.wait();
+        tokio::pin!(complete);
+
+        loop {
+            // The longest grace period of the remaining tasks, which shortens as tasks terminate
+            let deadline = start
+                + active_tasks
+                    .borrow_and_update()
+                    .values()
+                    .map(|&name| grace_period(name))
+                    .max()
+                    .unwrap_or_default();
+
+            tokio::select! {
+                biased;
+
+                () = &mut complete => return Vec::new(),
+                () = tokio::time::sleep_until(deadline) => {
+                    return active_tasks.borrow().values().copied().collect();
+                }
+                // The sender is owned by the wait group handle, so is only dropped once all tasks have terminated
+                _ = active_tasks.changed() => (),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    #[tokio::test]
+    async fn tasks_which_outlive_their_grace_period_are_reported() {
+        let wait_group = super::WaitGroup::new();
+
+        wait_group.spawn_task("quick", tracing::Span::none(), async { Ok(()) });
+        wait_group.spawn_task("stuck", tracing::Span::none(), std::future::pending());
+        wait_group.spawn_task("slow", tracing::Span::none(), async {
+            tokio::time::sleep(Duration::from_millis(40)).await;
+            Ok(())
+        });
+
+        let timed_out_tasks = wait_group
+            .wait_with_grace_periods(|task| {
+                Duration::from_millis(if task == "slow" { 200 } else { 20 })
+            })
+            .await;
+
+        assert_eq!(timed_out_tasks, ["stuck"]);
+    }
 }
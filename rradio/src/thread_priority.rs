@@ -0,0 +1,48 @@
+//! Raising the scheduling priority of a thread, so that audio isn't interrupted when the device is busy. Linux only
+
+use crate::config::runtime::Priority;
+
+/// Set the scheduling policy of the calling thread. Unlike most functions, `pthread_setschedparam` returns the error rather than setting `errno`
+fn set_scheduler(policy: libc::c_int, priority: libc::c_int) -> std::io::Result<()> {
+    let param = libc::sched_param {
+        sched_priority: priority,
+    };
+
+    // SAFETY: `pthread_self` is always a valid thread, and `param` outlives the call
+    match unsafe {
+        libc::pthread_setschedparam(libc::pthread_self(), policy, std::ptr::addr_of!(param))
+    } {
+        0 => Ok(()),
+        code => Err(std::io::Error::from_raw_os_error(code)),
+    }
+}
+
+/// Set the scheduling priority of the calling thread. Raising the priority requires `CAP_SYS_NICE`, or a suitable `RLIMIT_RTPRIO` or `RLIMIT_NICE`
+pub fn set_current_thread(priority: Priority) -> std::io::Result<()> {
+    match priority {
+        Priority::Fifo(priority) => set_scheduler(libc::SCHED_FIFO, priority),
+        Priority::Nice(nice) => {
+            // On Linux, the nice value is per-thread, so the thread id is used as the process id
+            // SAFETY: `SYS_gettid` takes no arguments
+            let thread_id = unsafe { libc::syscall(libc::SYS_gettid) };
+
+            #[allow(clippy::cast_sign_loss, clippy::cast_possible_truncation)]
+            let thread_id = thread_id as libc::id_t;
+
+            // SAFETY: `setpriority` has no memory safety preconditions
+            if unsafe { libc::setpriority(libc::PRIO_PROCESS, thread_id, nice) } < 0 {
+                Err(std::io::Error::last_os_error())
+            } else {
+                Ok(())
+            }
+        }
+    }
+}
+
+/// Return the calling thread to normal scheduling.
+/// Threads inherit the scheduling policy of the thread which spawns them, so threads spawned by a real-time thread to do slow work, such as loading stations, must lower their priority
+pub fn reset_current_thread() {
+    if let Err(err) = set_scheduler(libc::SCHED_OTHER, 0) {
+        tracing::error!("Failed to reset thread priority: {err}");
+    }
+}
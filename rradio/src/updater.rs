@@ -0,0 +1,320 @@
+//! Updating rradio from a release manifest, so that radios don't need to be updated by hand.
+//!
+//! The manifest is a JSON document such as `{"version": "0.53.0", "url": "https://example.com/rradio", "sha256": "...", "signature": "..."}`,
+//! where `sha256` is the hex encoded SHA-256 hash of the binary at `url`, and `signature` is the base64 encoded Ed25519 signature of the version and the hash, separated by a newline.
+//! The version is signed along with the binary, so that an older release can't be offered as a newer version.
+//! Manifests are only trusted if the signature matches the configured public key, and releases are only installed if they match the hash.
+
+use std::{
+    convert::TryFrom,
+    sync::atomic::{AtomicBool, Ordering},
+    time::{Duration, Instant},
+};
+
+use anyhow::{Context, Result};
+use base64::Engine;
+use sha2::{Digest, Sha256};
+
+use rradio_messages::{arcstr, ArcStr, UpdateProgress};
+
+use crate::config::update::Config;
+
+const CURRENT_VERSION: &str = env!("CARGO_PKG_VERSION");
+
+/// Download progress is reported at most this often
+const DOWNLOAD_PROGRESS_INTERVAL: Duration = Duration::from_millis(250);
+
+/// Only one update is applied at a time
+static IS_UPDATING: AtomicBool = AtomicBool::new(false);
+
+#[derive(Debug, serde::Deserialize)]
+struct Manifest {
+    version: ArcStr,
+    url: ArcStr,
+    /// The hex encoded SHA-256 hash of the binary at `url`
+    sha256: ArcStr,
+    /// The signature of the version and the hash
+    signature: ArcStr,
+}
+
+impl Manifest {
+    /// The version and the hash, separated by a newline, e.g. as produced by `printf '%s\n%s' "$version" "$hash"`
+    fn signed_message(&self) -> String {
+        format!("{}\n{}", self.version, self.sha256.to_ascii_lowercase())
+    }
+}
+
+/// The hex encoded SHA-256 hash of `contents`
+pub fn sha256(contents: &[u8]) -> String {
+    use std::fmt::Write;
+
+    Sha256::digest(contents)
+        .iter()
+        .fold(String::new(), |mut hash, byte| {
+            write!(hash, "{byte:02x}").ok();
+            hash
+        })
+}
+
+/// Parse a version such as `0.52.7` into its numeric components, ignoring any pre-release or build suffix
+fn parse_version(version: &str) -> Option<Vec<u64>> {
+    version
+        .split(['-', '+'])
+        .next()?
+        .split('.')
+        .map(|component| component.parse().ok())
+        .collect()
+}
+
+fn is_newer(version: &str, current_version: &str) -> Result<bool> {
+    let version =
+        parse_version(version).with_context(|| format!("Bad release version {version:?}"))?;
+    let current_version = parse_version(current_version)
+        .with_context(|| format!("Bad current version {current_version:?}"))?;
+
+    Ok(version > current_version)
+}
+
+pub fn http_client() -> Result<reqwest::Client> {
+    reqwest::Client::builder()
+        .user_agent("rradio")
+        .build()
+        .context("Failed to create http client")
+}
+
+async fn fetch_manifest(client: &reqwest::Client, config: &Config) -> Result<Manifest> {
+    let manifest_url = config
+        .manifest_url
+        .as_deref()
+        .context("No manifest url configured")?;
+
+    let manifest = client
+        .get(manifest_url)
+        .send()
+        .await
+        .and_then(reqwest::Response::error_for_status)
+        .with_context(|| format!("Failed to fetch {manifest_url}"))?
+        .bytes()
+        .await
+        .with_context(|| format!("Failed to fetch {manifest_url}"))?;
+
+    let manifest = serde_json::from_slice(&manifest)
+        .with_context(|| format!("Failed to parse {manifest_url}"))?;
+
+    // The version isn't trusted until the signature has been checked
+    verify_manifest(config, &manifest)?;
+
+    Ok(manifest)
+}
+
+fn verify_manifest(config: &Config, manifest: &Manifest) -> Result<()> {
+    let public_key = config
+        .public_key
+        .as_deref()
+        .context("No public key configured")?;
+
+    verify_ed25519_signature(
+        public_key,
+        manifest.signed_message().as_bytes(),
+        &manifest.signature,
+    )
+    .context("Failed to verify release manifest signature")
+}
+
+/// Check that the downloaded release is the release which was signed
+fn verify_release(manifest: &Manifest, release: &[u8]) -> Result<()> {
+    anyhow::ensure!(
+        sha256(release).eq_ignore_ascii_case(&manifest.sha256),
+        "Release does not match the hash in the manifest"
+    );
+
+    Ok(())
+}
+
+/// Check that `signature` is the base64 encoded Ed25519 signature of `message`, signed by the base64 encoded `public_key`
+pub fn verify_ed25519_signature(public_key: &str, message: &[u8], signature: &str) -> Result<()> {
+    let base64 = base64::engine::general_purpose::STANDARD;
+
+    let public_key = base64
+        .decode(public_key)
+        .ok()
+        .and_then(|public_key| <[u8; 32]>::try_from(public_key).ok())
+        .context("Public key is not a base64 encoded Ed25519 key")?;
+
+    let public_key = ed25519_dalek::VerifyingKey::from_bytes(&public_key)
+        .context("Public key is not a valid Ed25519 key")?;
+
+    let signature = base64
+        .decode(signature)
+        .ok()
+        .and_then(|signature| ed25519_dalek::Signature::from_slice(&signature).ok())
+        .context("Signature is not a base64 encoded Ed25519 signature")?;
+
+    public_key
+        .verify_strict(message, &signature)
+        .context("Signature does not match")
+}
+
+/// Check whether there is a newer release
+pub async fn check(config: &Config) -> UpdateProgress {
+    let result = async {
+        let manifest = fetch_manifest(&http_client()?, config).await?;
+
+        anyhow::Ok(if is_newer(&manifest.version, CURRENT_VERSION)? {
+            UpdateProgress::Available {
+                current_version: arcstr::literal!(CURRENT_VERSION),
+                version: manifest.version,
+            }
+        } else {
+            UpdateProgress::UpToDate {
+                current_version: arcstr::literal!(CURRENT_VERSION),
+            }
+        })
+    }
+    .await;
+
+    result.unwrap_or_else(|err| {
+        tracing::error!("Failed to check for update: {err:#}");
+        UpdateProgress::Failed(arcstr::format!("{err:#}"))
+    })
+}
+
+/// Download, verify and install a newer release, if there is one, reporting progress with `progress`.
+/// Returns true if a newer release was installed, in which case rradio should restart once it has shut down
+pub async fn apply(config: &Config, mut progress: impl FnMut(UpdateProgress)) -> Result<bool> {
+    if IS_UPDATING.swap(true, Ordering::AcqRel) {
+        anyhow::bail!("Already updating");
+    }
+
+    let result = async {
+        // Find the path before it's replaced, as afterwards the path of the running binary is reported as deleted
+        let path = std::env::current_exe().context("Failed to find the rradio binary")?;
+
+        let client = http_client()?;
+
+        let manifest = fetch_manifest(&client, config).await?;
+
+        if !is_newer(&manifest.version, CURRENT_VERSION)? {
+            progress(UpdateProgress::UpToDate {
+                current_version: arcstr::literal!(CURRENT_VERSION),
+            });
+            return anyhow::Ok(false);
+        }
+
+        tracing::info!(version = %manifest.version, url = %manifest.url, "Downloading update");
+
+        let mut response = client
+            .get(manifest.url.as_str())
+            .send()
+            .await
+            .and_then(reqwest::Response::error_for_status)
+            .with_context(|| format!("Failed to download {}", manifest.url))?;
+
+        let total = response.content_length();
+        let mut release = Vec::new();
+        let mut last_progress_time = Instant::now();
+
+        progress(UpdateProgress::Downloading {
+            downloaded: 0,
+            total,
+        });
+
+        while let Some(chunk) = response
+            .chunk()
+            .await
+            .with_context(|| format!("Failed to download {}", manifest.url))?
+        {
+            release.extend_from_slice(&chunk);
+
+            if last_progress_time.elapsed() >= DOWNLOAD_PROGRESS_INTERVAL {
+                last_progress_time = Instant::now();
+                progress(UpdateProgress::Downloading {
+                    downloaded: release.len() as u64,
+                    total,
+                });
+            }
+        }
+
+        progress(UpdateProgress::Verifying);
+
+        verify_release(&manifest, &release)?;
+
+        crate::state_file::write_executable(&path, &release)?;
+
+        tracing::info!(version = %manifest.version, "Update installed");
+
+        progress(UpdateProgress::Installed {
+            version: manifest.version,
+        });
+
+        anyhow::Ok(true)
+    }
+    .await;
+
+    IS_UPDATING.store(false, Ordering::Release);
+
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use base64::Engine;
+    use ed25519_dalek::Signer;
+
+    use super::{verify_manifest, verify_release, Manifest};
+
+    #[test]
+    fn manifest_version_and_release_must_be_signed() {
+        let base64 = base64::engine::general_purpose::STANDARD;
+        let signing_key = ed25519_dalek::SigningKey::from_bytes(&[7; 32]);
+
+        let config = crate::config::update::Config {
+            manifest_url: None,
+            public_key: Some(base64.encode(signing_key.verifying_key().as_bytes()).into()),
+        };
+
+        let release = b"rradio 0.53.0";
+
+        let mut manifest = Manifest {
+            version: "0.53.0".into(),
+            url: "https://example.com/rradio".into(),
+            sha256: super::sha256(release).into(),
+            signature: "".into(),
+        };
+
+        manifest.signature = base64
+            .encode(
+                signing_key
+                    .sign(manifest.signed_message().as_bytes())
+                    .to_bytes(),
+            )
+            .into();
+
+        verify_manifest(&config, &manifest).unwrap();
+        verify_release(&manifest, release).unwrap();
+        assert!(verify_release(&manifest, b"rradio 0.52.0").is_err());
+
+        // An older release can't be offered as a newer version
+        manifest.version = "0.54.0".into();
+        assert!(verify_manifest(&config, &manifest).is_err());
+    }
+
+    #[test]
+    fn newer_versions() {
+        for (version, current_version, expected) in [
+            ("0.53.0", "0.52.7", true),
+            ("0.52.10", "0.52.9", true),
+            ("1.0.0-rc1", "0.52.7", true),
+            ("0.52.7", "0.52.7", false),
+            ("0.52.6", "0.52.7", false),
+        ] {
+            assert_eq!(
+                super::is_newer(version, current_version).unwrap(),
+                expected,
+                "{version} > {current_version}"
+            );
+        }
+
+        assert!(super::is_newer("latest", "0.52.7").is_err());
+    }
+}